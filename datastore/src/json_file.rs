@@ -27,7 +27,7 @@ use futures::stream::{Stream, iter_ok};
 use query::{Query, naive_apply_query};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
-use serde_json::{Map, from_value, to_value, from_reader, to_writer};
+use serde_json::{Map, from_value, to_value, from_reader, to_writer_pretty};
 use serde_json::value::Value;
 use std::borrow::Cow;
 use std::fs;
@@ -122,8 +122,9 @@ impl<T> JsonFileDatastore<T>
 		))?;
 		let mut temporary_file = NamedTempFile::new_in(self_path_parent)?;
 
+		// The file is pretty-printed so that it can easily be inspected or modified by hand.
 		let content = self.content.clone().into_iter();
-		to_writer(
+		to_writer_pretty(
 			&mut temporary_file,
 			&content.map(|(k, v)| (k, to_value(v).unwrap())).collect::<Map<_, _>>(),
 		)?;
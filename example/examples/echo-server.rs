@@ -70,7 +70,7 @@ fn main() {
         })
 
         // On top of plaintext or secio, we will use the multiplex protocol.
-        .with_upgrade(multiplex::MultiplexConfig)
+        .with_upgrade(multiplex::MultiplexConfig::new())
         // The object returned by the call to `with_upgrade(MultiplexConfig)` can't be used as a
         // `Transport` because the output of the upgrade is not a stream but a controller for
         // muxing. We have to explicitly call `into_connection_reuse()` in order to turn this into
@@ -110,20 +110,20 @@ fn main() {
                         println!("Received a message from {}: {:?}\n => Sending back \
                                 identical message to remote", client_addr, msg);
                         Box::new(rest.send(msg.freeze()).map(|m| Loop::Continue(m)))
-                            as Box<Future<Item = _, Error = _>>
+                            as Box<Future<Item = _, Error = _> + Send>
                     } else {
                         // End of stream. Connection closed. Breaking the loop.
                         println!("Received EOF from {}\n => Dropping connection",
                                 client_addr);
                         Box::new(Ok(Loop::Break(())).into_future())
-                            as Box<Future<Item = _, Error = _>>
+                            as Box<Future<Item = _, Error = _> + Send>
                     }
                 })
         })
     });
 
     // We now use the controller to listen on the address.
-    let address = swarm_controller
+    let (_listener_id, address) = swarm_controller
         .listen_on(listen_addr.parse().expect("invalid multiaddr"))
         // If the multiaddr protocol exists but is not supported, then we get an error containing
         // the original multiaddress.
@@ -22,9 +22,8 @@ extern crate libp2p_peerstore;
 extern crate libp2p_swarm;
 extern crate multiaddr;
 
-use libp2p_peerstore::{PeerId, PeerAccess, Peerstore};
+use libp2p_peerstore::{ttl, PeerId, PeerAccess, Peerstore};
 use multiaddr::Multiaddr;
-use std::time::Duration;
 
 /// Stores initial addresses on the given peer store. Uses a very large timeout.
 pub fn ipfs_bootstrap<P>(peer_store: P)
@@ -43,8 +42,6 @@ where
         "/dns4/wss1.bootstrap.libp2p.io/tcp/443/wss/ipfs/Qmbut9Ywz9YEDrz8ySBSgWyJk41Uvm2QJPhwDJzJyGFsD6"
     ];
 
-	let ttl = Duration::from_secs(100 * 365 * 24 * 3600);
-
 	for address in ADDRESSES.iter() {
 		let mut multiaddr = address
 			.parse::<Multiaddr>()
@@ -59,6 +56,6 @@ where
 		peer_store
 			.clone()
 			.peer_or_create(&PeerId::from_bytes(public_key).unwrap())
-			.add_addr(multiaddr, ttl.clone());
+			.add_addr(multiaddr, ttl::permanent());
 	}
 }
@@ -0,0 +1,533 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+// TODO: use this once stable ; for now we just copy-paste the content of the README.md
+//#![doc(include = "../README.md")]
+
+//! Implementation of the libp2p `Transport` trait that resolves the DNS names of the addresses.
+//! Must be put on top of another `Transport`.
+//!
+//! Lists of bootstrap nodes are usually published as DNS names, which the other transports don't
+//! understand. When dialing, the `DnsConfig` replaces the names with IP addresses and passes the
+//! result to the underlying transport.
+//!
+//! # Usage
+//!
+//! ```
+//! extern crate libp2p_dns;
+//! extern crate libp2p_tcp_transport;
+//! extern crate tokio_core;
+//!
+//! use libp2p_dns::DnsConfig;
+//! use libp2p_tcp_transport::TcpConfig;
+//! use tokio_core::reactor::Core;
+//!
+//! # fn main() {
+//! let mut core = Core::new().unwrap();
+//! let transport = DnsConfig::new(TcpConfig::new(core.handle()));
+//! # }
+//! ```
+//!
+//! The `DnsConfig` struct implements the `Transport` trait of the `swarm` library. See the
+//! documentation of `swarm` and of libp2p in general to learn how to use the `Transport` trait.
+//!
+//! # Resolution
+//!
+//! A `/dns4/<name>` or `/dns6/<name>` component is replaced with the IPv4 or IPv6 addresses of
+//! `name`. If there are several addresses, they are dialed one after the other until one of them
+//! succeeds.
+//!
+//! A `/dnsaddr/<name>` component is replaced with the multiaddresses found in the `TXT` records
+//! of `_dnsaddr.<name>`, which have the form `dnsaddr=<multiaddr>`. These multiaddresses can
+//! contain DNS names as well, which are resolved in turn. If the original address continues after
+//! `/dnsaddr/<name>`, for example with `/p2p/<peer id>`, only the records that end with the same
+//! components are used.
+//!
+//! The lookups are performed by a `Resolver`. By default, the `SystemResolver` resolves the names
+//! on a thread pool, as the resolver of the operating system is blocking, and queries the `TXT`
+//! records from the name servers listed in `/etc/resolv.conf`. `DnsConfig::with_executor()`
+//! spawns these lookups on an `Executor` of the `swarm` library instead of a dedicated pool.
+//! Use `DnsConfig::with_resolver()` in order to control the caching, the timeouts or the privacy
+//! of the lookups.
+//!
+//! Listening on an address that contains a DNS name isn't supported.
+
+extern crate futures;
+extern crate libp2p_swarm as swarm;
+extern crate multiaddr;
+extern crate rand;
+
+#[cfg(test)]
+extern crate libp2p_tcp_transport as tcp;
+#[cfg(test)]
+extern crate tokio_core;
+#[cfg(test)]
+extern crate tokio_io;
+
+mod resolver;
+mod txt;
+
+pub use self::resolver::{Resolver, SystemResolver};
+
+use futures::{Async, Future, IntoFuture, Poll};
+use multiaddr::{AddrComponent, Multiaddr};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::mem;
+use std::net::IpAddr;
+use swarm::{Executor, Transport};
+
+// Maximum number of `dnsaddr` lookups when resolving an address, including the recursive ones.
+const MAX_DNSADDR_LOOKUPS: usize = 32;
+
+/// Represents the configuration for a DNS transport capability for libp2p. Must be put on top of
+/// another `Transport`.
+///
+/// The DNS names of the addresses passed to `dial()` are resolved with a `Resolver`, then the
+/// resulting addresses are passed to the underlying `Transport`. Addresses without a DNS name are
+/// passed as they are.
+#[derive(Clone)]
+pub struct DnsConfig<T, R = SystemResolver> {
+	inner: T,
+	resolver: R,
+}
+
+impl<T> DnsConfig<T> {
+	/// Creates a new configuration object for DNS, with one thread for resolving the names.
+	#[inline]
+	pub fn new(inner: T) -> DnsConfig<T> {
+		DnsConfig::with_resolve_threads(inner, 1)
+	}
+
+	/// Same as `new()`, but allows choosing the number of threads used for resolving the names.
+	///
+	/// # Panic
+	///
+	/// Panics if `num_threads` is 0.
+	#[inline]
+	pub fn with_resolve_threads(inner: T, num_threads: usize) -> DnsConfig<T> {
+		DnsConfig::with_resolver(inner, SystemResolver::new(num_threads))
+	}
+
+	/// Same as `new()`, but resolves the names by spawning the blocking lookups on `executor`.
+	#[inline]
+	pub fn with_executor<E>(inner: T, executor: E) -> DnsConfig<T>
+		where E: Executor + 'static
+	{
+		DnsConfig::with_resolver(inner, SystemResolver::with_executor(executor))
+	}
+}
+
+impl<T, R> DnsConfig<T, R> {
+	/// Creates a new configuration object for DNS, which resolves the names with `resolver`.
+	#[inline]
+	pub fn with_resolver(inner: T, resolver: R) -> DnsConfig<T, R> {
+		DnsConfig {
+			inner: inner,
+			resolver: resolver,
+		}
+	}
+}
+
+impl<T, R> fmt::Debug for DnsConfig<T, R>
+	where T: fmt::Debug,
+		  R: fmt::Debug
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		f.debug_tuple("DnsConfig").field(&self.inner).field(&self.resolver).finish()
+	}
+}
+
+impl<T, R> Transport for DnsConfig<T, R>
+	where T: Transport + Clone + Send + 'static,
+		  <T::Dial as IntoFuture>::Future: Send,
+		  R: Resolver + Send + 'static,
+		  R::IpsFuture: Send,
+		  R::TxtFuture: Send,
+{
+	type RawConn = T::RawConn;
+	type Listener = T::Listener;
+	type ListenerUpgrade = T::ListenerUpgrade;
+	type Dial = Box<Future<Item = Self::RawConn, Error = IoError> + Send>;
+
+	fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+		if contains_dns_name(&addr) {
+			return Err((self, addr));
+		}
+
+		let resolver = self.resolver;
+		self.inner.listen_on(addr).map_err(|(inner, addr)| {
+			(DnsConfig { inner: inner, resolver: resolver }, addr)
+		})
+	}
+
+	fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+		if !contains_dns_name(&addr) {
+			let resolver = self.resolver;
+			return match self.inner.dial(addr) {
+				Ok(dial) => Ok(Box::new(dial.into_future()) as Box<_>),
+				Err((inner, addr)) => Err((DnsConfig { inner: inner, resolver: resolver }, addr)),
+			};
+		}
+
+		let inner = self.inner;
+		let future = resolve_addr(self.resolver, addr)
+			.and_then(move |addrs| {
+				swarm::dial_any(addrs, 1, move |addr| {
+					inner.clone().dial(addr).map_err(|(_, addr)| addr)
+				})
+			})
+			.map(|(conn, _)| conn);
+
+		Ok(Box::new(future) as Box<_>)
+	}
+
+	#[inline]
+	fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+		self.inner.nat_traversal(server, observed)
+	}
+
+	#[inline]
+	fn expand_listen_addr(&self, addr: &Multiaddr) -> Vec<Multiaddr> {
+		self.inner.expand_listen_addr(addr)
+	}
+}
+
+#[inline]
+fn is_dns_name(component: &AddrComponent) -> bool {
+	match *component {
+		AddrComponent::DNS4(_) | AddrComponent::DNS6(_) | AddrComponent::DNSADDR(_) => true,
+		_ => false,
+	}
+}
+
+#[inline]
+fn contains_dns_name(addr: &Multiaddr) -> bool {
+	addr.iter().any(|component| is_dns_name(&component))
+}
+
+// Builds a future that replaces the DNS names of `addr` with what they resolve to.
+//
+// A failure to resolve one of the names only produces an error if no address at all could be
+// resolved.
+fn resolve_addr<R>(resolver: R, addr: Multiaddr) -> ResolveFuture<R>
+	where R: Resolver
+{
+	let mut pending = VecDeque::new();
+	pending.push_back(addr.clone());
+
+	ResolveFuture {
+		resolver: resolver,
+		original: addr,
+		pending: pending,
+		resolved: Vec::new(),
+		lookup: None,
+		dnsaddr_lookups: 0,
+		last_error: None,
+	}
+}
+
+// Future that resolves the DNS names of an address. See `resolve_addr()`.
+struct ResolveFuture<R>
+	where R: Resolver
+{
+	resolver: R,
+	original: Multiaddr,
+	// Addresses that may still contain a DNS name.
+	pending: VecDeque<Multiaddr>,
+	// Addresses without any DNS name.
+	resolved: Vec<Multiaddr>,
+	// Lookup in progress, and the components before and after the name being resolved.
+	lookup: Option<(Lookup<R>, Vec<AddrComponent>, Vec<AddrComponent>)>,
+	dnsaddr_lookups: usize,
+	last_error: Option<IoError>,
+}
+
+enum Lookup<R>
+	where R: Resolver
+{
+	Ipv4(R::IpsFuture),
+	Ipv6(R::IpsFuture),
+	DnsAddr(R::TxtFuture),
+}
+
+impl<R> Future for ResolveFuture<R>
+	where R: Resolver
+{
+	type Item = Vec<Multiaddr>;
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		loop {
+			if let Some((mut lookup, prefix, suffix)) = self.lookup.take() {
+				let result = match lookup {
+					Lookup::Ipv4(ref mut future) | Lookup::Ipv6(ref mut future) => {
+						future.poll().map(|ready| ready.map(LookupResult::Ips))
+					}
+					Lookup::DnsAddr(ref mut future) => {
+						future.poll().map(|ready| ready.map(LookupResult::Txt))
+					}
+				};
+
+				match result {
+					Ok(Async::Ready(LookupResult::Ips(ips))) => {
+						let want_ipv4 = match lookup {
+							Lookup::Ipv4(_) => true,
+							_ => false,
+						};
+						self.pending.extend(expand_ips(&prefix, &suffix, ips, want_ipv4));
+					}
+					Ok(Async::Ready(LookupResult::Txt(records))) => {
+						self.pending.extend(expand_dnsaddr(&prefix, &suffix, records));
+					}
+					Ok(Async::NotReady) => {
+						self.lookup = Some((lookup, prefix, suffix));
+						return Ok(Async::NotReady);
+					}
+					Err(err) => self.last_error = Some(err),
+				}
+			}
+
+			let current = match self.pending.pop_front() {
+				Some(addr) => addr,
+				None => break,
+			};
+
+			let mut components = current.iter().collect::<Vec<_>>();
+			let pos = match components.iter().position(is_dns_name) {
+				Some(pos) => pos,
+				None => {
+					self.resolved.push(current);
+					continue;
+				}
+			};
+
+			let suffix = components.split_off(pos + 1);
+			let name = components.pop().expect("the DNS component is at pos");
+			let lookup = match name {
+				AddrComponent::DNS4(ref name) => Lookup::Ipv4(self.resolver.lookup_ips(name)),
+				AddrComponent::DNS6(ref name) => Lookup::Ipv6(self.resolver.lookup_ips(name)),
+				AddrComponent::DNSADDR(ref name) => {
+					self.dnsaddr_lookups += 1;
+					if self.dnsaddr_lookups > MAX_DNSADDR_LOOKUPS {
+						let msg = "too many dnsaddr lookups";
+						self.last_error = Some(IoError::new(IoErrorKind::Other, msg));
+						break;
+					}
+					let txt_name = format!("_dnsaddr.{}", name);
+					Lookup::DnsAddr(self.resolver.lookup_txt(&txt_name))
+				}
+				_ => unreachable!("is_dns_name() only matches DNS components"),
+			};
+			self.lookup = Some((lookup, components, suffix));
+		}
+
+		if self.resolved.is_empty() {
+			let original = &self.original;
+			Err(self.last_error.take().unwrap_or_else(|| {
+				IoError::new(IoErrorKind::NotFound, format!("no address found for {}", original))
+			}))
+		} else {
+			Ok(Async::Ready(mem::replace(&mut self.resolved, Vec::new())))
+		}
+	}
+}
+
+enum LookupResult {
+	Ips(Vec<IpAddr>),
+	Txt(Vec<String>),
+}
+
+// Builds the addresses where the name between `prefix` and `suffix` is replaced with `ips`.
+fn expand_ips(prefix: &[AddrComponent], suffix: &[AddrComponent], ips: Vec<IpAddr>,
+			  want_ipv4: bool) -> Vec<Multiaddr>
+{
+	ips.into_iter()
+		.filter_map(|ip| match ip {
+			IpAddr::V4(ip) if want_ipv4 => Some(AddrComponent::IP4(ip)),
+			IpAddr::V6(ip) if !want_ipv4 => Some(AddrComponent::IP6(ip)),
+			_ => None,
+		})
+		.map(|ip| {
+			prefix.iter().cloned().chain(Some(ip)).chain(suffix.iter().cloned()).collect()
+		})
+		.collect()
+}
+
+// Builds the addresses where the `dnsaddr` between `prefix` and `suffix` is replaced with the
+// multiaddresses of the `TXT` records. Only the records that end with `suffix` are used.
+fn expand_dnsaddr(prefix: &[AddrComponent], suffix: &[AddrComponent], records: Vec<String>)
+				  -> Vec<Multiaddr>
+{
+	records.into_iter()
+		.filter_map(|record| {
+			if !record.starts_with("dnsaddr=") {
+				return None;
+			}
+			record["dnsaddr=".len() ..].parse::<Multiaddr>().ok()
+		})
+		.map(|record| record.iter().collect::<Vec<_>>())
+		.filter(|record| record.ends_with(suffix))
+		.map(|record| prefix.iter().cloned().chain(record).collect())
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{resolve_addr, DnsConfig, Resolver, SystemResolver};
+	use futures::{future, Future, Stream};
+	use multiaddr::{AddrComponent, Multiaddr};
+	use std::collections::HashMap;
+	use std::io::Error as IoError;
+	use std::net::IpAddr;
+	use std::thread;
+	use swarm::Transport;
+	use tcp::TcpConfig;
+	use tokio_core::reactor::Core;
+	use tokio_io;
+
+	// Resolver that returns hard-coded answers.
+	#[derive(Debug, Clone, Default)]
+	struct MockResolver {
+		ips: HashMap<String, Vec<IpAddr>>,
+		txt: HashMap<String, Vec<String>>,
+	}
+
+	impl Resolver for MockResolver {
+		type IpsFuture = future::FutureResult<Vec<IpAddr>, IoError>;
+		type TxtFuture = future::FutureResult<Vec<String>, IoError>;
+
+		fn lookup_ips(&self, name: &str) -> Self::IpsFuture {
+			future::ok(self.ips.get(name).cloned().unwrap_or_default())
+		}
+
+		fn lookup_txt(&self, name: &str) -> Self::TxtFuture {
+			future::ok(self.txt.get(name).cloned().unwrap_or_default())
+		}
+	}
+
+	fn resolve(resolver: &MockResolver, addr: &str) -> Result<Vec<Multiaddr>, IoError> {
+		resolve_addr(resolver.clone(), addr.parse().unwrap()).wait()
+	}
+
+	#[test]
+	fn resolve_without_dns_name() {
+		let addr = "/ip4/1.2.3.4/tcp/5".parse::<Multiaddr>().unwrap();
+		let resolved = resolve_addr(MockResolver::default(), addr.clone()).wait().unwrap();
+		assert_eq!(resolved, vec![addr]);
+	}
+
+	#[test]
+	fn resolve_localhost() {
+		let addr = "/dns4/localhost/tcp/5".parse::<Multiaddr>().unwrap();
+		let expected = "/ip4/127.0.0.1/tcp/5".parse::<Multiaddr>().unwrap();
+		let resolved = resolve_addr(SystemResolver::new(1), addr).wait().unwrap();
+		assert!(resolved.contains(&expected));
+	}
+
+	#[test]
+	fn resolve_on_executor() {
+		let executor = |lookup: Box<Future<Item = (), Error = ()> + Send>| {
+			thread::spawn(move || { let _ = lookup.wait(); });
+		};
+
+		let addr = "/dns4/localhost/tcp/5".parse::<Multiaddr>().unwrap();
+		let expected = "/ip4/127.0.0.1/tcp/5".parse::<Multiaddr>().unwrap();
+		let resolver = SystemResolver::with_executor(executor);
+		let resolved = resolve_addr(resolver, addr).wait().unwrap();
+		assert!(resolved.contains(&expected));
+	}
+
+	#[test]
+	fn resolve_dns4_and_dns6() {
+		let mut resolver = MockResolver::default();
+		let ips = vec!["1.2.3.4".parse().unwrap(), "::1".parse().unwrap()];
+		resolver.ips.insert("example.com".to_owned(), ips);
+
+		let resolved = resolve(&resolver, "/dns4/example.com/tcp/5").unwrap();
+		assert_eq!(resolved, vec!["/ip4/1.2.3.4/tcp/5".parse::<Multiaddr>().unwrap()]);
+		let resolved = resolve(&resolver, "/dns6/example.com/tcp/5").unwrap();
+		assert_eq!(resolved, vec!["/ip6/::1/tcp/5".parse::<Multiaddr>().unwrap()]);
+		assert!(resolve(&resolver, "/dns4/unknown.com/tcp/5").is_err());
+	}
+
+	#[test]
+	fn resolve_dnsaddr_recursively() {
+		let peer_a = "/ipfs/QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC";
+		let peer_b = "/ipfs/QmSoLPppuBtQSGwKDZT2M73ULpjvfd3aZ6ha4oFGL1KrGM";
+
+		let mut resolver = MockResolver::default();
+		resolver.txt.insert("_dnsaddr.bootstrap.example".to_owned(), vec![
+			format!("dnsaddr=/dnsaddr/sjc.example{}", peer_a),
+			format!("dnsaddr=/ip4/5.6.7.8/tcp/4001{}", peer_b),
+			"unrelated record".to_owned(),
+		]);
+		resolver.txt.insert("_dnsaddr.sjc.example".to_owned(), vec![
+			format!("dnsaddr=/dns4/node.example/tcp/4001{}", peer_a),
+		]);
+		resolver.ips.insert("node.example".to_owned(), vec!["1.2.3.4".parse().unwrap()]);
+
+		let resolved = resolve(&resolver, &format!("/dnsaddr/bootstrap.example{}", peer_a));
+		let expected = format!("/ip4/1.2.3.4/tcp/4001{}", peer_a).parse::<Multiaddr>().unwrap();
+		assert_eq!(resolved.unwrap(), vec![expected]);
+	}
+
+	#[test]
+	fn dnsaddr_loop() {
+		let mut resolver = MockResolver::default();
+		let record = "dnsaddr=/dnsaddr/loop.example".to_owned();
+		resolver.txt.insert("_dnsaddr.loop.example".to_owned(), vec![record]);
+		assert!(resolve(&resolver, "/dnsaddr/loop.example").is_err());
+	}
+
+	#[test]
+	fn listen_on_dns_name_unsupported() {
+		let core = Core::new().unwrap();
+		let dns = DnsConfig::new(TcpConfig::new(core.handle()));
+		assert!(dns.listen_on("/dns4/localhost/tcp/0".parse().unwrap()).is_err());
+	}
+
+	#[test]
+	fn dial_localhost() {
+		let mut core = Core::new().unwrap();
+		let dns = DnsConfig::new(TcpConfig::new(core.handle()));
+
+		let (listener, addr) = dns.clone()
+			.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+			.unwrap();
+		let port = match addr.iter().last() {
+			Some(AddrComponent::TCP(port)) => port,
+			_ => panic!("unexpected listen address {}", addr),
+		};
+
+		let listener = listener
+			.into_future()
+			.map_err(|(err, _)| err)
+			.and_then(|(conn, _)| conn.unwrap().0)
+			.and_then(|conn| tokio_io::io::read_exact(conn, [0; 3]))
+			.map(|(_, buf)| assert_eq!(buf, [1, 2, 3]));
+
+		let dialer = dns.dial(format!("/dns4/localhost/tcp/{}", port).parse().unwrap())
+			.unwrap()
+			.and_then(|conn| tokio_io::io::write_all(conn, [1, 2, 3]));
+
+		core.run(listener.join(dialer)).unwrap();
+	}
+}
@@ -0,0 +1,126 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+//! Contains the `Resolver` trait, which performs the DNS lookups of a `DnsConfig`, and the
+//! `SystemResolver`, which is the default implementation.
+
+use futures::{future, Future};
+use futures::sync::oneshot;
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::Arc;
+use swarm::{Executor, ThreadPoolExecutor};
+use txt;
+
+/// Performs DNS lookups.
+///
+/// Implementing this trait allows controlling how the names are resolved: caching, timeouts,
+/// which servers are queried and over which protocol (for example DNS-over-HTTPS), or returning
+/// hard-coded answers in tests.
+pub trait Resolver {
+	/// Future that produces the IP addresses of a name.
+	type IpsFuture: Future<Item = Vec<IpAddr>, Error = IoError>;
+	/// Future that produces the `TXT` records of a name.
+	type TxtFuture: Future<Item = Vec<String>, Error = IoError>;
+
+	/// Looks up the IPv4 and IPv6 addresses of `name`.
+	fn lookup_ips(&self, name: &str) -> Self::IpsFuture;
+
+	/// Looks up the `TXT` records of `name`. Each record must be returned as the concatenation
+	/// of its character strings.
+	///
+	/// If the name doesn't exist, the future should produce an empty list rather than an error.
+	fn lookup_txt(&self, name: &str) -> Self::TxtFuture;
+}
+
+/// Resolves the IP addresses with the resolver of the operating system, and the `TXT` records
+/// by querying the name servers listed in `/etc/resolv.conf`.
+///
+/// The lookups are blocking, and therefore run on an `Executor` whose tasks are allowed to
+/// block, by default a thread pool.
+#[derive(Clone)]
+pub struct SystemResolver {
+	executor: Arc<Executor>,
+}
+
+impl SystemResolver {
+	/// Creates a resolver that performs the lookups on `num_threads` threads.
+	///
+	/// # Panic
+	///
+	/// Panics if `num_threads` is 0.
+	#[inline]
+	pub fn new(num_threads: usize) -> SystemResolver {
+		SystemResolver::with_executor(ThreadPoolExecutor::new(num_threads))
+	}
+
+	/// Creates a resolver that performs the lookups on `executor`.
+	///
+	/// Each lookup blocks the thread that runs it, so the executor shouldn't be the one that
+	/// drives the connections, otherwise they stall during the lookups.
+	#[inline]
+	pub fn with_executor<E>(executor: E) -> SystemResolver
+		where E: Executor + 'static
+	{
+		SystemResolver {
+			executor: Arc::new(executor),
+		}
+	}
+
+	// Runs `lookup` on the executor and returns a future to its result.
+	fn spawn<T, F>(&self, lookup: F) -> Box<Future<Item = T, Error = IoError> + Send>
+		where F: FnOnce() -> Result<T, IoError> + Send + 'static,
+			  T: Send + 'static
+	{
+		let (tx, rx) = oneshot::channel();
+		self.executor.spawn(Box::new(future::lazy(move || {
+			let _ = tx.send(lookup());
+			Ok(())
+		})));
+
+		Box::new(rx.then(|result| match result {
+			Ok(result) => result,
+			Err(_) => Err(IoError::new(IoErrorKind::Other, "the lookup has been dropped")),
+		}))
+	}
+}
+
+impl fmt::Debug for SystemResolver {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		f.debug_struct("SystemResolver").finish()
+	}
+}
+
+impl Resolver for SystemResolver {
+	type IpsFuture = Box<Future<Item = Vec<IpAddr>, Error = IoError> + Send>;
+	type TxtFuture = Box<Future<Item = Vec<String>, Error = IoError> + Send>;
+
+	fn lookup_ips(&self, name: &str) -> Self::IpsFuture {
+		let name = name.to_owned();
+		self.spawn(move || {
+			Ok((name.as_str(), 0).to_socket_addrs()?.map(|addr| addr.ip()).collect())
+		})
+	}
+
+	fn lookup_txt(&self, name: &str) -> Self::TxtFuture {
+		let name = name.to_owned();
+		self.spawn(move || txt::lookup_txt(&name))
+	}
+}
@@ -0,0 +1,309 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+//! Minimal DNS client that looks up the `TXT` records of a name.
+//!
+//! The system resolver only gives access to the IP addresses of a name, so we send the queries
+//! ourselves to the name servers listed in `/etc/resolv.conf`.
+
+use rand;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+// Time to wait for the answer of a name server.
+const QUERY_TIMEOUT_SECS: u64 = 5;
+// Number of queries sent to a name server before trying the next one.
+const ATTEMPTS: usize = 2;
+
+const TYPE_TXT: u16 = 16;
+const CLASS_IN: u16 = 1;
+
+/// Looks up the `TXT` records of `name`. Blocks the current thread until the answer arrives.
+///
+/// Each record is returned as the concatenation of its character strings. Records that aren't
+/// valid UTF-8 are ignored. Returns an empty list if the name doesn't exist.
+pub fn lookup_txt(name: &str) -> Result<Vec<String>, IoError> {
+	let query = encode_query(rand::random(), name)?;
+
+	let mut last_error = None;
+	for server in name_servers() {
+		for _ in 0 .. ATTEMPTS {
+			match send_query(server, &query) {
+				Ok(records) => return Ok(records),
+				Err(err) => last_error = Some(err),
+			}
+		}
+	}
+
+	Err(last_error.unwrap_or_else(|| IoError::new(IoErrorKind::NotFound, "no name server")))
+}
+
+// Reads the name servers from `/etc/resolv.conf`. Like the C library, falls back to a name
+// server on the local machine if there is none.
+fn name_servers() -> Vec<SocketAddr> {
+	let mut servers = Vec::new();
+
+	if let Ok(file) = File::open("/etc/resolv.conf") {
+		for line in BufReader::new(file).lines() {
+			let line = match line {
+				Ok(line) => line,
+				Err(_) => break,
+			};
+
+			let mut words = line.split_whitespace();
+			if words.next() != Some("nameserver") {
+				continue;
+			}
+
+			// Link-local IPv6 addresses with a `%scope` suffix fail to parse and are skipped.
+			if let Some(Ok(ip)) = words.next().map(|ip| ip.parse::<IpAddr>()) {
+				servers.push(SocketAddr::new(ip, 53));
+			}
+		}
+	}
+
+	if servers.is_empty() {
+		servers.push(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 53));
+	}
+
+	servers
+}
+
+// Sends `query` to `server` over UDP, and retries over TCP if the answer is truncated.
+fn send_query(server: SocketAddr, query: &[u8]) -> Result<Vec<String>, IoError> {
+	let timeout = Some(Duration::from_secs(QUERY_TIMEOUT_SECS));
+
+	let bind_addr = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+	let socket = UdpSocket::bind(bind_addr)?;
+	socket.set_read_timeout(timeout)?;
+	socket.connect(server)?;
+	socket.send(query)?;
+
+	let mut buf = [0; 4096];
+	loop {
+		let len = socket.recv(&mut buf)?;
+		// Ignore the late answers to our previous attempts.
+		if len < 2 || buf[.. 2] != query[.. 2] {
+			continue;
+		}
+
+		match decode_response(&query[.. 2], &buf[.. len])? {
+			Some(records) => return Ok(records),
+			None => break,
+		}
+	}
+
+	let mut stream = TcpStream::connect(server)?;
+	stream.set_read_timeout(timeout)?;
+	stream.set_write_timeout(timeout)?;
+
+	let mut framed = Vec::with_capacity(query.len() + 2);
+	framed.push((query.len() >> 8) as u8);
+	framed.push(query.len() as u8);
+	framed.extend_from_slice(query);
+	stream.write_all(&framed)?;
+
+	let mut len = [0; 2];
+	stream.read_exact(&mut len)?;
+	let mut response = vec![0; ((len[0] as usize) << 8) | len[1] as usize];
+	stream.read_exact(&mut response)?;
+
+	match decode_response(&query[.. 2], &response)? {
+		Some(records) => Ok(records),
+		None => Err(IoError::new(IoErrorKind::InvalidData, "truncated DNS response over TCP")),
+	}
+}
+
+// Builds a recursive query for the `TXT` records of `name`.
+fn encode_query(id: u16, name: &str) -> Result<Vec<u8>, IoError> {
+	let invalid_name = || IoError::new(IoErrorKind::InvalidInput, "invalid DNS name");
+
+	let mut out = Vec::with_capacity(name.len() + 18);
+	out.extend_from_slice(&[(id >> 8) as u8, id as u8]);
+	// Standard query, with recursion desired.
+	out.extend_from_slice(&[0x01, 0x00]);
+	// One question, and no answer, authority or additional record.
+	out.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+
+	let name = if name.ends_with('.') { &name[.. name.len() - 1] } else { name };
+	for label in name.split('.') {
+		if label.is_empty() || label.len() > 63 {
+			return Err(invalid_name());
+		}
+		out.push(label.len() as u8);
+		out.extend_from_slice(label.as_bytes());
+	}
+	out.push(0);
+	if out.len() - 12 > 255 {
+		return Err(invalid_name());
+	}
+
+	out.extend_from_slice(&[(TYPE_TXT >> 8) as u8, TYPE_TXT as u8]);
+	out.extend_from_slice(&[(CLASS_IN >> 8) as u8, CLASS_IN as u8]);
+	Ok(out)
+}
+
+// Decodes the answer to the query whose identifier is `id`. Returns `None` if the answer is
+// truncated.
+fn decode_response(id: &[u8], data: &[u8]) -> Result<Option<Vec<String>>, IoError> {
+	let invalid = || IoError::new(IoErrorKind::InvalidData, "invalid DNS response");
+
+	if data.len() < 12 || data[.. 2] != id[.. 2] || data[2] & 0x80 == 0 {
+		return Err(invalid());
+	}
+	if data[2] & 0x02 != 0 {
+		return Ok(None);
+	}
+	match data[3] & 0x0f {
+		0 => (),
+		// The name doesn't exist.
+		3 => return Ok(Some(Vec::new())),
+		code => {
+			let msg = format!("DNS server answered with error code {}", code);
+			return Err(IoError::new(IoErrorKind::Other, msg));
+		}
+	}
+
+	let num_questions = read_u16(data, 4);
+	let num_answers = read_u16(data, 6);
+	let mut pos = 12;
+
+	for _ in 0 .. num_questions {
+		// Skip the name, the type and the class.
+		pos = skip_name(data, pos).ok_or_else(invalid)? + 4;
+	}
+
+	let mut records = Vec::new();
+	for _ in 0 .. num_answers {
+		pos = skip_name(data, pos).ok_or_else(invalid)?;
+		if pos + 10 > data.len() {
+			return Err(invalid());
+		}
+		let ty = read_u16(data, pos);
+		let class = read_u16(data, pos + 2);
+		let rdata_len = read_u16(data, pos + 8) as usize;
+		pos += 10;
+		if pos + rdata_len > data.len() {
+			return Err(invalid());
+		}
+
+		// The answer can also contain the `CNAME` records that the server followed.
+		if ty == TYPE_TXT && class == CLASS_IN {
+			let record = decode_txt(&data[pos .. pos + rdata_len]).ok_or_else(invalid)?;
+			if let Ok(record) = String::from_utf8(record) {
+				records.push(record);
+			}
+		}
+		pos += rdata_len;
+	}
+
+	Ok(Some(records))
+}
+
+// Reads a big-endian `u16`. The caller must check that `data` is large enough.
+#[inline]
+fn read_u16(data: &[u8], pos: usize) -> u16 {
+	((data[pos] as u16) << 8) | data[pos + 1] as u16
+}
+
+// Returns the position after the name that starts at `pos`, or `None` if it is malformed.
+fn skip_name(data: &[u8], mut pos: usize) -> Option<usize> {
+	loop {
+		let len = match data.get(pos) {
+			Some(&len) => len as usize,
+			None => return None,
+		};
+
+		match len & 0xc0 {
+			0x00 if len == 0 => return Some(pos + 1),
+			0x00 => pos += 1 + len,
+			// A compression pointer always ends the name.
+			0xc0 if pos + 1 < data.len() => return Some(pos + 2),
+			_ => return None,
+		}
+	}
+}
+
+// Concatenates the character strings of the data of a `TXT` record.
+fn decode_txt(rdata: &[u8]) -> Option<Vec<u8>> {
+	let mut out = Vec::with_capacity(rdata.len());
+	let mut pos = 0;
+	while pos < rdata.len() {
+		let len = rdata[pos] as usize;
+		match rdata.get(pos + 1 .. pos + 1 + len) {
+			Some(string) => out.extend_from_slice(string),
+			None => return None,
+		}
+		pos += 1 + len;
+	}
+	Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{decode_response, encode_query};
+
+	#[test]
+	fn query_encoding() {
+		let query = encode_query(0x1234, "_dnsaddr.example.com.").unwrap();
+		let mut expected = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+		expected.extend_from_slice(b"\x08_dnsaddr\x07example\x03com\x00");
+		expected.extend_from_slice(&[0, 16, 0, 1]);
+		assert_eq!(query, expected);
+
+		assert!(encode_query(0, "foo..com").is_err());
+		assert!(encode_query(0, &"a".repeat(64)).is_err());
+	}
+
+	#[test]
+	fn response_decoding() {
+		let query = encode_query(0xabcd, "_dnsaddr.example.com").unwrap();
+
+		let mut response = query[.. 12].to_vec();
+		// Response, recursion available, two answers.
+		response[2] = 0x81;
+		response[3] = 0x80;
+		response[7] = 2;
+		response.extend_from_slice(&query[12 ..]);
+		// `CNAME` record pointing the name of the question to `other.example.com`.
+		response.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 8]);
+		response.extend_from_slice(b"\x05other\xc0\x15");
+		// `TXT` record made of two character strings.
+		response.extend_from_slice(b"\x05other\xc0\x15");
+		response.extend_from_slice(&[0, 16, 0, 1, 0, 0, 0, 60, 0, 14]);
+		response.extend_from_slice(b"\x09dnsaddr=/\x03ip4");
+
+		let records = decode_response(&query[.. 2], &response).unwrap().unwrap();
+		assert_eq!(records, vec!["dnsaddr=/ip4".to_owned()]);
+
+		// Wrong identifier.
+		assert!(decode_response(&[0, 0], &response).is_err());
+
+		// Truncated.
+		response[2] |= 0x02;
+		assert!(decode_response(&query[.. 2], &response).unwrap().is_none());
+
+		// The name doesn't exist.
+		response[2] = 0x81;
+		response[3] = 0x83;
+		assert!(decode_response(&query[.. 2], &response).unwrap().unwrap().is_empty());
+	}
+}
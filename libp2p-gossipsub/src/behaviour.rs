@@ -0,0 +1,1941 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `Gossipsub` struct, which maintains the meshes of the topics and routes the
+//! messages between the handlers of the connections.
+
+use futures::{future, Async, Future, IntoFuture};
+use handler::{GossipsubHandler, GossipsubHandlerEvent};
+use libp2p_keys::{Signer, SigningError};
+use libp2p_swarm::{ClockSkew, PeerId};
+use libp2p_swarm::time::{self, Instant};
+use mcache::MessageCache;
+use protocol::{GossipsubControlAction, GossipsubMessage, GossipsubRpc, GossipsubSubscription};
+use protocol::{GossipsubSubscriptionAction, MessageId, Topic};
+use rand::{self, ChaChaRng, Rng};
+use score::{PeerScore, PeerScoreParams, PeerScoreThresholds};
+use signing::{self, SignaturePolicy};
+use std::cmp::{self, Ordering};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_core::reactor::{Handle, Timeout};
+
+/// Configuration of `Gossipsub`.
+///
+/// The default values are the ones of the other implementations of libp2p. The mesh degree
+/// parameters trade bandwidth for latency and robustness: a larger mesh delivers the messages
+/// faster and survives more failures, but each message is received more times.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GossipsubConfig {
+	/// Number of peers in the mesh of a topic, to which the messages of the topic are sent. Also
+	/// known as D. Defaults to 6.
+	pub mesh_n: usize,
+	/// Minimum number of peers in the mesh of a topic, also known as D_low. Below this, peers
+	/// are added to the mesh at the next heartbeat. Defaults to 4.
+	pub mesh_n_low: usize,
+	/// Maximum number of peers in the mesh of a topic, also known as D_high. Above this, peers
+	/// are removed from the mesh at the next heartbeat. Defaults to 12.
+	pub mesh_n_high: usize,
+	/// Number of peers with the best score that are kept when a mesh has too many peers, also
+	/// known as D_score. The rest of the mesh is chosen at random. Values greater than `mesh_n`
+	/// are treated as `mesh_n`. Defaults to 4.
+	pub mesh_n_score: usize,
+	/// Number of peers outside of the mesh to which the gossip of a topic is sent at each
+	/// heartbeat. Also known as D_lazy. Defaults to 6.
+	pub gossip_lazy: usize,
+	/// Number of heartbeats during which the messages are kept in order to answer the `IWant`
+	/// requests. Defaults to 5.
+	pub history_length: usize,
+	/// Number of heartbeats whose messages are advertised in the gossip. Defaults to 3.
+	pub history_gossip: usize,
+	/// Interval between two heartbeats. Defaults to 1 second.
+	pub heartbeat_interval: Duration,
+	/// Duration after which the peers that the messages of a topic we aren't subscribed to are
+	/// sent to are forgotten, if we haven't published on the topic since. Defaults to 60 seconds.
+	pub fanout_ttl: Duration,
+	/// Duration during which the identifiers of the messages are remembered, in order to ignore
+	/// the duplicates. Defaults to 120 seconds.
+	pub duplicate_cache_time: Duration,
+	/// Duration during which a peer removed from a mesh must not join it again. Sent to the peer
+	/// alongside the `Prune`. Defaults to 60 seconds.
+	pub prune_backoff: Duration,
+	/// Whether other peers subscribed to the topic are sent alongside a `Prune`, so that the
+	/// pruned peer can connect to them. Also known as peer exchange, or PX. Defaults to false.
+	pub do_px: bool,
+	/// Maximum number of peers sent in a peer exchange, or connected to after receiving one.
+	/// Defaults to 16.
+	pub prune_peers: usize,
+	/// Number of heartbeats between two opportunistic grafts. Defaults to 60.
+	pub opportunistic_graft_ticks: u64,
+	/// Number of peers added to a mesh by an opportunistic graft. Defaults to 2.
+	pub opportunistic_graft_peers: usize,
+	/// Duration during which a peer must deliver the messages that we asked for with an `IWant`,
+	/// otherwise it receives a behavioural penalty. Defaults to 3 seconds.
+	pub iwant_followup_time: Duration,
+	/// How the signatures of the received messages are checked. Defaults to
+	/// `SignaturePolicy::Strict`, which rejects the unsigned messages.
+	pub signature_policy: SignaturePolicy,
+	/// Maximum number of received messages that are waiting for the validators of their topics.
+	/// The messages received while this number is reached are ignored without being validated.
+	/// Defaults to 1024.
+	pub max_pending_validations: usize,
+	/// Duration after which a message whose validation isn't over is ignored. Checked whenever
+	/// `poll` is called, which happens at least at each heartbeat. Defaults to 10 seconds.
+	pub validation_timeout: Duration,
+}
+
+impl Default for GossipsubConfig {
+	#[inline]
+	fn default() -> GossipsubConfig {
+		GossipsubConfig {
+			mesh_n: 6,
+			mesh_n_low: 4,
+			mesh_n_high: 12,
+			mesh_n_score: 4,
+			gossip_lazy: 6,
+			history_length: 5,
+			history_gossip: 3,
+			heartbeat_interval: Duration::from_secs(1),
+			fanout_ttl: Duration::from_secs(60),
+			duplicate_cache_time: Duration::from_secs(120),
+			prune_backoff: Duration::from_secs(60),
+			do_px: false,
+			prune_peers: 16,
+			opportunistic_graft_ticks: 60,
+			opportunistic_graft_peers: 2,
+			iwant_followup_time: Duration::from_secs(3),
+			signature_policy: SignaturePolicy::Strict,
+			max_pending_validations: 1024,
+			validation_timeout: Duration::from_secs(10),
+		}
+	}
+}
+
+/// Action that the user must perform on behalf of `Gossipsub`.
+#[derive(Debug, Clone)]
+pub enum GossipsubAction {
+	/// A connection to the peer should be opened, because a peer exchange suggested it. Once it
+	/// is open, call `inject_connected`.
+	Dial {
+		/// The peer to connect to.
+		peer_id: PeerId,
+	},
+	/// The RPC must be injected in the `GossipsubHandler` of the connection to the peer.
+	SendEvent {
+		/// The peer whose handler must receive the RPC.
+		peer_id: PeerId,
+		/// The RPC to inject.
+		event: GossipsubRpc,
+	},
+	/// An event for the user.
+	GenerateEvent(GossipsubEvent),
+}
+
+/// Event produced by `Gossipsub` for the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GossipsubEvent {
+	/// A message has been received on a topic we are subscribed to. Each message is only
+	/// reported once, even if it is received from several peers.
+	Message {
+		/// The peer that sent us the message, which isn't necessarily its source.
+		propagation_source: PeerId,
+		/// The message.
+		message: GossipsubMessage,
+	},
+	/// A remote has subscribed to a topic.
+	Subscribed {
+		/// The remote.
+		peer_id: PeerId,
+		/// The topic.
+		topic: Topic,
+	},
+	/// A remote has unsubscribed from a topic.
+	Unsubscribed {
+		/// The remote.
+		peer_id: PeerId,
+		/// The topic.
+		topic: Topic,
+	},
+	/// A message we published couldn't be signed, and hasn't been sent.
+	PublishFailed {
+		/// The identifier returned by `publish`.
+		message_id: MessageId,
+		/// The error of the signer.
+		error: SigningError,
+	},
+}
+
+/// Result of the validation of a received message by a topic validator.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ValidationResult {
+	/// The message is valid. It is reported to the user and forwarded.
+	Accept,
+	/// The message is invalid. It is dropped, and the peer that sent it is penalized.
+	Reject,
+	/// The message is dropped without penalizing the peer that sent it, for example because it
+	/// is valid but no longer relevant.
+	Ignore,
+}
+
+// Validator of the messages of a topic, with the peer that sent the message.
+type TopicValidator = Box<FnMut(&PeerId, &GossipsubMessage) -> ValidationFuture>;
+// Future that produces the result of a validation.
+type ValidationFuture = Box<Future<Item = ValidationResult, Error = ()>>;
+// Future that produces a message we published, once signed.
+type SignatureFuture = Box<Future<Item = GossipsubMessage, Error = SigningError> + Send>;
+
+/// State of the gossipsub router of the local node.
+///
+/// For each topic we are subscribed to, `Gossipsub` keeps a mesh of `mesh_n` peers subscribed
+/// to the same topic. The messages are only sent to the peers of the mesh, and the identifiers
+/// of the recent messages are sent as gossip to a few other peers at each heartbeat, so that
+/// they can ask for the messages they missed.
+///
+/// Like `Kademlia`, `Gossipsub` doesn't open any connection by itself. `poll` produces
+/// `GossipsubAction`s that ask the user to pass an RPC to the `GossipsubHandler` of a
+/// connection, and the user must report the connections and the RPCs received by the handlers
+/// with the `inject_*` methods.
+pub struct Gossipsub {
+	config: GossipsubConfig,
+	local_peer_id: PeerId,
+	// Topics we are subscribed to.
+	subscriptions: HashSet<Topic>,
+	// Peers we are connected to, with the topics they are subscribed to.
+	peer_topics: HashMap<PeerId, HashSet<Topic>>,
+	// Peers the messages of each topic we are subscribed to are exchanged with.
+	mesh: HashMap<Topic, HashSet<PeerId>>,
+	// Peers the messages of each topic we publish on without being subscribed are sent to.
+	fanout: HashMap<Topic, HashSet<PeerId>>,
+	// Last time we published on each topic of `fanout`.
+	fanout_last_published: HashMap<Topic, Instant>,
+	mcache: MessageCache,
+	// Identifiers of the messages received recently, in order to ignore the duplicates.
+	seen: HashSet<MessageId>,
+	// Same as `seen`, with the time of reception, the oldest first.
+	seen_order: VecDeque<(Instant, MessageId)>,
+	// Minimum sequence number of the next message we publish.
+	next_sequence_number: u64,
+	// Signer of the messages we publish, if any.
+	signer: Option<Arc<Signer>>,
+	// Messages we published that are being signed.
+	pending_signatures: Vec<(MessageId, SignatureFuture)>,
+	// Scores of the peers, if the peer scoring is enabled.
+	peer_score: Option<PeerScore>,
+	thresholds: PeerScoreThresholds,
+	// Measures the offsets of the clocks of the authors of the messages, if enabled.
+	clock_skew: Option<ClockSkew<PeerId>>,
+	// Validators of the messages of each topic.
+	validators: HashMap<Topic, TopicValidator>,
+	// Received messages that are waiting for the validators of their topics, with the peer
+	// that sent them and the time after which they are ignored.
+	pending_validations: Vec<(PeerId, GossipsubMessage, ValidationFuture, Instant)>,
+	// Time until which each peer removed from the mesh of a topic must not join it again.
+	backoffs: HashMap<(Topic, PeerId), Instant>,
+	// Messages we asked for with an `IWant`, with the peer that advertised them and the time
+	// before which it must deliver them.
+	promises: HashMap<MessageId, (PeerId, Instant)>,
+	// Number of heartbeats so far.
+	heartbeat_ticks: u64,
+	// Actions to produce from `poll`.
+	queued_actions: VecDeque<GossipsubAction>,
+	handle: Handle,
+	// Fires when the next heartbeat must happen.
+	heartbeat_timer: Option<Timeout>,
+	// Chooses the peers of the meshes, of the fanout and of the gossip. In a `RefCell` because
+	// the peers are chosen with filters that borrow `self`.
+	rng: RefCell<Box<Rng + Send>>,
+}
+
+impl Gossipsub {
+	/// Builds a new `Gossipsub` that isn't subscribed to any topic. `handle` is used for the
+	/// heartbeat.
+	///
+	/// # Panic
+	///
+	/// Panics if `mesh_n` isn't between `mesh_n_low` and `mesh_n_high`, if `history_gossip` is
+	/// greater than `history_length`, or if `history_length` or `opportunistic_graft_ticks` is 0.
+	pub fn new(local_peer_id: PeerId, handle: Handle, config: GossipsubConfig) -> Gossipsub {
+		assert!(config.mesh_n_low <= config.mesh_n && config.mesh_n <= config.mesh_n_high,
+				"mesh_n must be between mesh_n_low and mesh_n_high");
+		assert!(config.history_gossip <= config.history_length,
+				"history_gossip can't be greater than history_length");
+		assert!(config.history_length > 0, "history_length must be at least 1");
+		assert!(config.opportunistic_graft_ticks > 0,
+				"opportunistic_graft_ticks must be at least 1");
+
+		let rng = Box::new(rand::thread_rng().gen::<ChaChaRng>()) as Box<Rng + Send>;
+
+		Gossipsub {
+			config: config,
+			local_peer_id: local_peer_id,
+			subscriptions: HashSet::new(),
+			peer_topics: HashMap::new(),
+			mesh: HashMap::new(),
+			fanout: HashMap::new(),
+			fanout_last_published: HashMap::new(),
+			mcache: MessageCache::new(config.history_gossip, config.history_length),
+			seen: HashSet::new(),
+			seen_order: VecDeque::new(),
+			next_sequence_number: 0,
+			signer: None,
+			pending_signatures: Vec::new(),
+			peer_score: None,
+			thresholds: PeerScoreThresholds::default(),
+			clock_skew: None,
+			validators: HashMap::new(),
+			pending_validations: Vec::new(),
+			backoffs: HashMap::new(),
+			promises: HashMap::new(),
+			heartbeat_ticks: 0,
+			queued_actions: VecDeque::new(),
+			handle: handle,
+			heartbeat_timer: None,
+			rng: RefCell::new(rng),
+		}
+	}
+
+	/// Sets the random number generator that chooses the peers of the meshes, of the fanout and
+	/// of the gossip. By default, it is seeded from the random number generator of the operating
+	/// system.
+	///
+	/// With a generator built from a fixed seed, the same connections and subscriptions produce
+	/// the same meshes from one run to another, which is useful for simulations and tests.
+	pub fn with_rng<R>(mut self, rng: R) -> Gossipsub
+		where R: Rng + Send + 'static
+	{
+		self.rng = RefCell::new(Box::new(rng));
+		self
+	}
+
+	/// Sets the signer of the messages we publish. It must sign with the identity key of the
+	/// local node. Without it, the messages are published unsigned, and are rejected by the
+	/// nodes that use the `Strict` signature policy.
+	///
+	/// # Panic
+	///
+	/// Panics if the peer ID of the public key of the signer isn't the local peer ID.
+	pub fn with_signer(mut self, signer: Arc<Signer>) -> Gossipsub {
+		let peer_id = PeerId::from_public_key(&signer.public().into_protobuf_encoding());
+		assert!(peer_id == self.local_peer_id, "the signer must use the key of the local node");
+		self.signer = Some(signer);
+		self
+	}
+
+	/// Measures the offsets of the clocks of the authors of the messages in `clock_skew`.
+	///
+	/// The sequence numbers of the messages are the moments when they were published, in
+	/// nanoseconds since the UNIX epoch. Only the messages received directly from their author
+	/// are measured, so that the delays of the intermediate hops don't add up. The remotes whose
+	/// sequence numbers aren't timestamps give measurements that `ClockSkew` ignores.
+	pub fn with_clock_skew(mut self, clock_skew: ClockSkew<PeerId>) -> Gossipsub {
+		self.clock_skew = Some(clock_skew);
+		self
+	}
+
+	/// Enables the peer scoring of gossipsub v1.1. Without it, all the peers have a score of 0.
+	///
+	/// # Panic
+	///
+	/// Panics if the parameters are invalid (see `PeerScore::new`), if `gossip_threshold` is
+	/// positive, if the thresholds aren't ordered as `graylist_threshold <= publish_threshold
+	/// <= gossip_threshold`, or if `accept_px_threshold` or `opportunistic_graft_threshold` is
+	/// negative.
+	pub fn with_peer_score(mut self, params: PeerScoreParams, thresholds: PeerScoreThresholds)
+						   -> Gossipsub
+	{
+		assert!(thresholds.gossip_threshold <= 0.0, "gossip_threshold must be negative or 0");
+		assert!(thresholds.publish_threshold <= thresholds.gossip_threshold &&
+				thresholds.graylist_threshold <= thresholds.publish_threshold,
+				"the thresholds must be ordered");
+		assert!(thresholds.accept_px_threshold >= 0.0 &&
+				thresholds.opportunistic_graft_threshold >= 0.0,
+				"accept_px_threshold and opportunistic_graft_threshold must be positive or 0");
+
+		let mut peer_score = PeerScore::new(params);
+		for peer_id in self.peer_topics.keys() {
+			peer_score.add_peer(peer_id);
+		}
+		self.peer_score = Some(peer_score);
+		self.thresholds = thresholds;
+		self
+	}
+
+	/// Returns the configuration.
+	#[inline]
+	pub fn config(&self) -> &GossipsubConfig {
+		&self.config
+	}
+
+	/// Returns the score of a peer, or `None` if the peer scoring isn't enabled.
+	#[inline]
+	pub fn peer_score(&self, peer_id: &PeerId) -> Option<f64> {
+		self.peer_score.as_ref().map(|peer_score| peer_score.score(peer_id))
+	}
+
+	/// Builds the handler to use for a new connection.
+	#[inline]
+	pub fn new_handler<S>(&self) -> GossipsubHandler<S> {
+		GossipsubHandler::new()
+	}
+
+	/// Subscribes to a topic, and adds peers subscribed to it to its mesh. Returns false if we
+	/// were already subscribed.
+	pub fn subscribe(&mut self, topic: Topic) -> bool {
+		if !self.subscriptions.insert(topic.clone()) {
+			return false;
+		}
+
+		self.announce_subscription(&topic, GossipsubSubscriptionAction::Subscribe);
+
+		// The peers we were already sending our messages to are the first members of the mesh.
+		self.fanout_last_published.remove(&topic);
+		let mut peers = self.fanout.remove(&topic).unwrap_or_default();
+		peers.retain(|peer_id| self.can_graft(&topic, peer_id));
+		let missing = self.config.mesh_n.saturating_sub(peers.len());
+		let added = self.random_peers(&topic, missing, |peer_id| {
+			!peers.contains(peer_id) && self.can_graft(&topic, peer_id)
+		});
+		peers.extend(added);
+		for peer_id in &peers {
+			let graft = self.graft_peer(peer_id, &topic);
+			self.send_control(peer_id.clone(), vec![graft]);
+		}
+		self.mesh.insert(topic, peers);
+		true
+	}
+
+	/// Unsubscribes from a topic, and leaves its mesh. Returns false if we weren't subscribed.
+	pub fn unsubscribe(&mut self, topic: &Topic) -> bool {
+		if !self.subscriptions.remove(topic) {
+			return false;
+		}
+
+		self.announce_subscription(topic, GossipsubSubscriptionAction::Unsubscribe);
+		for peer_id in self.mesh.remove(topic).unwrap_or_default() {
+			let prune = self.prune_peer(&peer_id, topic, true);
+			self.send_control(peer_id, vec![prune]);
+		}
+		true
+	}
+
+	/// Returns true if we are subscribed to `topic`.
+	#[inline]
+	pub fn is_subscribed(&self, topic: &Topic) -> bool {
+		self.subscriptions.contains(topic)
+	}
+
+	/// Sets the validator of the messages received on `topic`, replacing the previous one if
+	/// any. The validator is called with the peer that sent the message and the message, and
+	/// returns a future that produces a `ValidationResult`. A synchronous validator can simply
+	/// return a `Result<ValidationResult, ()>`.
+	///
+	/// The received messages are neither reported nor forwarded before all the validators of
+	/// their topics have accepted them. A validator that fails is treated as `Ignore`, and so
+	/// are the validations that exceed `max_pending_validations` or `validation_timeout`. The
+	/// messages we publish aren't validated.
+	pub fn set_topic_validator<F, T>(&mut self, topic: Topic, mut validator: F)
+		where F: FnMut(&PeerId, &GossipsubMessage) -> T + 'static,
+			  T: IntoFuture<Item = ValidationResult, Error = ()>,
+			  T::Future: 'static
+	{
+		let validator = move |peer_id: &PeerId, message: &GossipsubMessage| {
+			Box::new(validator(peer_id, message).into_future()) as ValidationFuture
+		};
+		self.validators.insert(topic, Box::new(validator));
+	}
+
+	/// Removes the validator of `topic`. Returns true if there was one. The messages that are
+	/// being validated are still validated by it.
+	#[inline]
+	pub fn remove_topic_validator(&mut self, topic: &Topic) -> bool {
+		self.validators.remove(topic).is_some()
+	}
+
+	/// Returns the peers of the mesh of `topic`. Empty if we aren't subscribed to the topic.
+	pub fn mesh_peers(&self, topic: &Topic) -> Vec<PeerId> {
+		self.mesh.get(topic)
+			.map(|peers| peers.iter().cloned().collect())
+			.unwrap_or_default()
+	}
+
+	/// Publishes a message on a topic, and returns its identifier.
+	///
+	/// If a signer has been set with `with_signer`, the message is sent once it is signed, from
+	/// `poll`. If the signing fails, `poll` produces a `GossipsubEvent::PublishFailed`.
+	///
+	/// If we are subscribed to the topic, the message is sent to the peers of its mesh.
+	/// Otherwise, it is sent to `mesh_n` peers subscribed to the topic, which are kept for the
+	/// next messages until `fanout_ttl` elapses without publishing. Peers whose score is below
+	/// the `publish_threshold` aren't chosen.
+	pub fn publish(&mut self, topic: Topic, data: Vec<u8>) -> MessageId {
+		let message = GossipsubMessage {
+			source: self.local_peer_id.clone(),
+			data: data,
+			sequence_number: self.next_sequence_number(),
+			topics: vec![topic.clone()],
+			signature: None,
+			key: None,
+		};
+		let id = message.id();
+		self.mark_seen(id.clone());
+
+		let signature = self.signer.as_ref()
+			.map(|signer| signing::sign_message(&**signer, message.clone()));
+		match signature {
+			Some(future) => self.pending_signatures.push((id.clone(), future)),
+			None => self.send_published(message),
+		}
+		id
+	}
+
+	// Sends a message we published to the mesh or to the fanout of its topic.
+	fn send_published(&mut self, message: GossipsubMessage) {
+		self.mcache.put(message.clone());
+
+		let topic = message.topics[0].clone();
+		let mesh = self.mesh.get(&topic).cloned();
+		let peers = match mesh {
+			Some(peers) => peers,
+			None => {
+				if !self.fanout.contains_key(&topic) {
+					let publish_threshold = self.thresholds.publish_threshold;
+					let peers = self.random_peers(&topic, self.config.mesh_n, |peer_id| {
+						self.score(peer_id) >= publish_threshold
+					});
+					self.fanout.insert(topic.clone(), peers.into_iter().collect());
+				}
+				self.fanout_last_published.insert(topic.clone(), Instant::now());
+				self.fanout[&topic].clone()
+			},
+		};
+
+		for peer_id in peers {
+			self.send_rpc(peer_id, GossipsubRpc {
+				messages: vec![message.clone()],
+				.. GossipsubRpc::default()
+			});
+		}
+	}
+
+	/// Indicates that a connection to `peer_id` has been opened. Sends it our subscriptions.
+	pub fn inject_connected(&mut self, peer_id: &PeerId) {
+		self.peer_topics.insert(peer_id.clone(), HashSet::new());
+		if let Some(ref mut peer_score) = self.peer_score {
+			peer_score.add_peer(peer_id);
+		}
+
+		if !self.subscriptions.is_empty() {
+			let subscriptions = self.subscriptions.iter()
+				.map(|topic| GossipsubSubscription {
+					action: GossipsubSubscriptionAction::Subscribe,
+					topic: topic.clone(),
+				})
+				.collect();
+			self.send_rpc(peer_id.clone(), GossipsubRpc {
+				subscriptions: subscriptions,
+				.. GossipsubRpc::default()
+			});
+		}
+	}
+
+	/// Indicates that the connection to `peer_id` has been closed.
+	pub fn inject_disconnected(&mut self, peer_id: &PeerId) {
+		self.peer_topics.remove(peer_id);
+		for peers in self.mesh.values_mut().chain(self.fanout.values_mut()) {
+			peers.remove(peer_id);
+		}
+		self.promises.retain(|_, &mut (ref promised_by, _)| promised_by != peer_id);
+		if let Some(ref mut peer_score) = self.peer_score {
+			peer_score.remove_peer(peer_id);
+		}
+	}
+
+	/// Injects an event produced by the `GossipsubHandler` of the connection to `peer_id`.
+	///
+	/// The RPCs of the peers whose score is below the `graylist_threshold` are ignored. A
+	/// `ProtocolViolation` counts as a misbehaviour in the behavioural penalty of the peer.
+	pub fn inject_node_event(&mut self, peer_id: &PeerId, event: GossipsubHandlerEvent) {
+		match event {
+			GossipsubHandlerEvent::Rpc(rpc) => self.inject_rpc(peer_id, rpc),
+			GossipsubHandlerEvent::ProtocolViolation(_) => {
+				if let Some(ref mut peer_score) = self.peer_score {
+					peer_score.add_penalty(peer_id, 1);
+				}
+			},
+		}
+	}
+
+	// Processes an RPC received from `peer_id`.
+	fn inject_rpc(&mut self, peer_id: &PeerId, rpc: GossipsubRpc) {
+		if !self.peer_topics.contains_key(peer_id) {
+			return;
+		}
+		if self.score(peer_id) < self.thresholds.graylist_threshold {
+			return;
+		}
+
+		for subscription in rpc.subscriptions {
+			self.inject_subscription(peer_id, subscription);
+		}
+
+		for message in rpc.messages {
+			self.inject_message(peer_id, message);
+		}
+
+		// No gossip is exchanged with the peers whose score is too low.
+		let accepts_gossip = self.score(peer_id) >= self.thresholds.gossip_threshold;
+		let mut wanted = Vec::new();
+		let mut messages = Vec::new();
+		let mut control = Vec::new();
+		let mut exchanged_peers = Vec::new();
+		for action in rpc.control {
+			match action {
+				GossipsubControlAction::IHave { topic, message_ids } => {
+					if !accepts_gossip || !self.mesh.contains_key(&topic) {
+						continue;
+					}
+					for id in message_ids {
+						if !self.seen.contains(&id) && !wanted.contains(&id) {
+							wanted.push(id);
+						}
+					}
+				},
+				GossipsubControlAction::IWant { message_ids } => {
+					if !accepts_gossip {
+						continue;
+					}
+					for id in message_ids {
+						if let Some(message) = self.mcache.get(&id) {
+							messages.push(message.clone());
+						}
+					}
+				},
+				GossipsubControlAction::Graft { topic } => {
+					if let Some(action) = self.inject_graft(peer_id, topic) {
+						control.push(action);
+					}
+				},
+				GossipsubControlAction::Prune { topic, peers, backoff } => {
+					if let Some(mesh) = self.mesh.get_mut(&topic) {
+						mesh.remove(peer_id);
+					}
+					if let Some(ref mut peer_score) = self.peer_score {
+						peer_score.prune(peer_id, &topic);
+					}
+					let backoff = backoff.unwrap_or(self.config.prune_backoff);
+					self.backoffs.insert((topic, peer_id.clone()), Instant::now() + backoff);
+
+					let accept_px = match self.peer_score {
+						Some(ref peer_score) => {
+							peer_score.score(peer_id) >= self.thresholds.accept_px_threshold
+						},
+						None => true,
+					};
+					if accept_px {
+						exchanged_peers.extend(peers);
+					}
+				},
+			}
+		}
+
+		if !wanted.is_empty() {
+			let deadline = Instant::now() + self.config.iwant_followup_time;
+			for id in &wanted {
+				self.promises.entry(id.clone()).or_insert_with(|| (peer_id.clone(), deadline));
+			}
+			control.push(GossipsubControlAction::IWant { message_ids: wanted });
+		}
+		self.send_rpc(peer_id.clone(), GossipsubRpc {
+			subscriptions: Vec::new(),
+			messages: messages,
+			control: control,
+		});
+
+		let mut dialed = 0;
+		for exchanged in exchanged_peers {
+			if dialed >= self.config.prune_peers {
+				break;
+			}
+			if exchanged == self.local_peer_id || self.peer_topics.contains_key(&exchanged) {
+				continue;
+			}
+			dialed += 1;
+			self.queued_actions.push_back(GossipsubAction::Dial { peer_id: exchanged });
+		}
+	}
+
+	/// Returns the next action to perform.
+	pub fn poll(&mut self) -> Async<GossipsubAction> {
+		loop {
+			if self.heartbeat_timer.is_none() {
+				let interval = self.config.heartbeat_interval;
+				self.heartbeat_timer = Timeout::new(interval, &self.handle).ok();
+			}
+			let polled = self.heartbeat_timer.as_mut().map(|timer| timer.poll());
+			match polled {
+				Some(Ok(Async::Ready(()))) => self.heartbeat_timer = None,
+				_ => break,
+			}
+
+			self.heartbeat();
+		}
+
+		// Sends the published messages whose signature is over.
+		let mut n = 0;
+		while n < self.pending_signatures.len() {
+			let result = match self.pending_signatures[n].1.poll() {
+				Ok(Async::Ready(message)) => Ok(message),
+				Ok(Async::NotReady) => {
+					n += 1;
+					continue;
+				},
+				Err(err) => Err(err),
+			};
+
+			let (message_id, _) = self.pending_signatures.remove(n);
+			match result {
+				Ok(message) => self.send_published(message),
+				Err(err) => {
+					let event = GossipsubEvent::PublishFailed {
+						message_id: message_id,
+						error: err,
+					};
+					self.queued_actions.push_back(GossipsubAction::GenerateEvent(event));
+				},
+			}
+		}
+
+		// Handles the messages whose validation is over.
+		let now = Instant::now();
+		let mut n = 0;
+		while n < self.pending_validations.len() {
+			let result = match self.pending_validations[n].2.poll() {
+				Ok(Async::Ready(result)) => result,
+				Ok(Async::NotReady) if self.pending_validations[n].3 <= now => {
+					ValidationResult::Ignore
+				},
+				Ok(Async::NotReady) => {
+					n += 1;
+					continue;
+				},
+				Err(()) => ValidationResult::Ignore,
+			};
+
+			let (propagation_source, message, _, _) = self.pending_validations.remove(n);
+			match result {
+				ValidationResult::Accept => self.accept_message(&propagation_source, message),
+				ValidationResult::Reject => {
+					if let Some(ref mut peer_score) = self.peer_score {
+						peer_score.invalid_delivery(&propagation_source, &message.topics);
+					}
+				},
+				ValidationResult::Ignore => (),
+			}
+		}
+
+		match self.queued_actions.pop_front() {
+			Some(action) => Async::Ready(action),
+			None => Async::NotReady,
+		}
+	}
+
+	// Handles a subscription or an unsubscription of a remote.
+	fn inject_subscription(&mut self, peer_id: &PeerId, subscription: GossipsubSubscription) {
+		let GossipsubSubscription { action, topic } = subscription;
+		let changed = match self.peer_topics.get_mut(peer_id) {
+			Some(topics) => match action {
+				GossipsubSubscriptionAction::Subscribe => topics.insert(topic.clone()),
+				GossipsubSubscriptionAction::Unsubscribe => topics.remove(&topic),
+			},
+			None => return,
+		};
+		if !changed {
+			return;
+		}
+
+		let event = match action {
+			GossipsubSubscriptionAction::Subscribe => GossipsubEvent::Subscribed {
+				peer_id: peer_id.clone(),
+				topic: topic,
+			},
+			GossipsubSubscriptionAction::Unsubscribe => {
+				if let Some(peers) = self.mesh.get_mut(&topic) {
+					peers.remove(peer_id);
+				}
+				if let Some(peers) = self.fanout.get_mut(&topic) {
+					peers.remove(peer_id);
+				}
+				GossipsubEvent::Unsubscribed {
+					peer_id: peer_id.clone(),
+					topic: topic,
+				}
+			},
+		};
+		self.queued_actions.push_back(GossipsubAction::GenerateEvent(event));
+	}
+
+	// Handles a message sent by `propagation_source`. Once the validators of its topics have
+	// accepted it, the message is reported to the user if we are subscribed to one of its topics,
+	// and forwarded to the meshes of its topics.
+	fn inject_message(&mut self, propagation_source: &PeerId, message: GossipsubMessage) {
+		let id = message.id();
+		if self.seen.contains(&id) {
+			if let Some(ref mut peer_score) = self.peer_score {
+				peer_score.duplicate_delivery(propagation_source, &id, &message.topics);
+			}
+			return;
+		}
+
+		// The invalid messages aren't marked as seen, so that a valid message with the same
+		// identifier can still be received from another peer.
+		if !signing::verify_message(&message, self.config.signature_policy) {
+			if let Some(ref mut peer_score) = self.peer_score {
+				peer_score.invalid_delivery(propagation_source, &message.topics);
+			}
+			return;
+		}
+
+		// The message is marked as seen during its validation, so that its duplicates are
+		// ignored and it is validated only once.
+		self.mark_seen(id.clone());
+		self.promises.remove(&id);
+
+		if let Some(ref clock_skew) = self.clock_skew {
+			if message.source == *propagation_source {
+				if let Some(published) = sequence_number_time(&message.sequence_number) {
+					clock_skew.record_now(message.source.clone(), published);
+				}
+			}
+		}
+
+		if !message.topics.iter().any(|topic| self.validators.contains_key(topic)) {
+			self.accept_message(propagation_source, message);
+			return;
+		}
+
+		// Too many messages are being validated. The message is ignored.
+		if self.pending_validations.len() >= self.config.max_pending_validations {
+			return;
+		}
+
+		let mut validations = Vec::new();
+		for topic in &message.topics {
+			if let Some(validator) = self.validators.get_mut(topic) {
+				validations.push(validator(propagation_source, &message));
+			}
+		}
+
+		// A rejection by any validator prevails over an `Ignore`.
+		let validation = future::join_all(validations).map(|results| {
+			if results.contains(&ValidationResult::Reject) {
+				ValidationResult::Reject
+			} else if results.contains(&ValidationResult::Ignore) {
+				ValidationResult::Ignore
+			} else {
+				ValidationResult::Accept
+			}
+		});
+		let validation = Box::new(validation) as ValidationFuture;
+		let deadline = Instant::now() + self.config.validation_timeout;
+		self.pending_validations.push((propagation_source.clone(), message, validation, deadline));
+	}
+
+	// Handles a valid message sent by `propagation_source`.
+	fn accept_message(&mut self, propagation_source: &PeerId, message: GossipsubMessage) {
+		let id = message.id();
+		if let Some(ref mut peer_score) = self.peer_score {
+			peer_score.first_delivery(propagation_source, &id, &message.topics);
+		}
+		self.mcache.put(message.clone());
+
+		let mut recipients = HashSet::new();
+		for topic in &message.topics {
+			if let Some(peers) = self.mesh.get(topic) {
+				recipients.extend(peers.iter().cloned());
+			}
+		}
+		recipients.remove(propagation_source);
+		recipients.remove(&message.source);
+		for peer_id in recipients {
+			self.send_rpc(peer_id, GossipsubRpc {
+				messages: vec![message.clone()],
+				.. GossipsubRpc::default()
+			});
+		}
+
+		if message.topics.iter().any(|topic| self.subscriptions.contains(topic)) {
+			let event = GossipsubEvent::Message {
+				propagation_source: propagation_source.clone(),
+				message: message,
+			};
+			self.queued_actions.push_back(GossipsubAction::GenerateEvent(event));
+		}
+	}
+
+	// Handles a request of a remote to join the mesh of a topic. Returns the `Prune` to send
+	// back if the request is refused.
+	fn inject_graft(&mut self, peer_id: &PeerId, topic: Topic) -> Option<GossipsubControlAction> {
+		let in_mesh = self.mesh.get(&topic).map(|peers| peers.contains(peer_id));
+		match in_mesh {
+			Some(true) => return None,
+			Some(false) => (),
+			// We aren't subscribed to the topic.
+			None => return Some(self.prune_peer(peer_id, &topic, false)),
+		}
+
+		// Asking again before the end of the backoff is a misbehaviour.
+		if self.is_backing_off(&topic, peer_id) {
+			if let Some(ref mut peer_score) = self.peer_score {
+				peer_score.add_penalty(peer_id, 1);
+			}
+			return Some(self.prune_peer(peer_id, &topic, false));
+		}
+		if self.score(peer_id) < 0.0 {
+			return Some(self.prune_peer(peer_id, &topic, false));
+		}
+
+		self.graft_peer(peer_id, &topic);
+		if let Some(peers) = self.mesh.get_mut(&topic) {
+			peers.insert(peer_id.clone());
+		}
+		None
+	}
+
+	// Maintains the meshes and the fanout, and sends the gossip.
+	fn heartbeat(&mut self) {
+		self.heartbeat_ticks = self.heartbeat_ticks.wrapping_add(1);
+		let now = Instant::now();
+		loop {
+			let expired = match self.seen_order.front() {
+				Some(&(time, _)) => time + self.config.duplicate_cache_time <= now,
+				None => false,
+			};
+			if !expired {
+				break;
+			}
+			if let Some((_, id)) = self.seen_order.pop_front() {
+				self.seen.remove(&id);
+			}
+		}
+
+		self.backoffs.retain(|_, &mut expires| expires > now);
+
+		// The peers that didn't deliver the messages they advertised are penalized.
+		let broken_promises = self.promises.iter()
+			.filter(|&(_, &(_, deadline))| deadline <= now)
+			.map(|(id, _)| id.clone())
+			.collect::<Vec<_>>();
+		for id in broken_promises {
+			if let Some((peer_id, _)) = self.promises.remove(&id) {
+				if let Some(ref mut peer_score) = self.peer_score {
+					peer_score.add_penalty(&peer_id, 1);
+				}
+			}
+		}
+
+		if let Some(ref mut peer_score) = self.peer_score {
+			peer_score.refresh();
+		}
+
+		// Control messages to send, grouped by peer so that each peer receives a single RPC.
+		let mut control = HashMap::new();
+
+		// The topics are sorted so that the random number generator is used in the same order
+		// from one run to another.
+		let mut topics = self.mesh.keys().cloned().collect::<Vec<_>>();
+		topics.sort();
+		for topic in topics {
+			let mut peers = self.mesh.remove(&topic).unwrap_or_default();
+			let mut grafted = Vec::new();
+			let mut pruned = Vec::new();
+
+			// The peers with a negative score are removed, without peer exchange.
+			let negative = peers.iter()
+				.filter(|peer_id| self.score(peer_id) < 0.0)
+				.cloned()
+				.collect::<Vec<_>>();
+			for peer_id in negative {
+				peers.remove(&peer_id);
+				pruned.push((peer_id, false));
+			}
+
+			if peers.len() < self.config.mesh_n_low {
+				let missing = self.config.mesh_n - peers.len();
+				grafted = self.random_peers(&topic, missing, |peer_id| {
+					!peers.contains(peer_id) && self.can_graft(&topic, peer_id)
+				});
+			} else if peers.len() > self.config.mesh_n_high {
+				// The peers with the best score are kept, and the rest is chosen at random.
+				let mut kept = peers.iter().cloned().collect::<Vec<_>>();
+				kept.sort();
+				self.rng.borrow_mut().shuffle(&mut kept);
+				kept.sort_by(|a, b| {
+					self.score(b).partial_cmp(&self.score(a)).unwrap_or(Ordering::Equal)
+				});
+				let mesh_n_score = cmp::min(self.config.mesh_n_score, self.config.mesh_n);
+				self.rng.borrow_mut().shuffle(&mut kept[mesh_n_score ..]);
+				for peer_id in kept.split_off(self.config.mesh_n) {
+					peers.remove(&peer_id);
+					pruned.push((peer_id, true));
+				}
+			}
+
+			// If the peers of the mesh have a low score, better peers are added to it.
+			let opportunistic_graft = self.peer_score.is_some() && peers.len() > 1 &&
+				self.heartbeat_ticks % self.config.opportunistic_graft_ticks == 0;
+			if opportunistic_graft {
+				let mut scores = peers.iter()
+					.map(|peer_id| self.score(peer_id))
+					.collect::<Vec<_>>();
+				scores.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+				let median = scores[scores.len() / 2];
+				if median < self.thresholds.opportunistic_graft_threshold {
+					let count = self.config.opportunistic_graft_peers;
+					let added = self.random_peers(&topic, count, |peer_id| {
+						!peers.contains(peer_id) && !grafted.contains(peer_id) &&
+							self.can_graft(&topic, peer_id) && self.score(peer_id) > median
+					});
+					grafted.extend(added);
+				}
+			}
+
+			for peer_id in grafted {
+				let graft = self.graft_peer(&peer_id, &topic);
+				control.entry(peer_id.clone()).or_insert_with(Vec::new).push(graft);
+				peers.insert(peer_id);
+			}
+			self.mesh.insert(topic.clone(), peers);
+			for (peer_id, px) in pruned {
+				let prune = self.prune_peer(&peer_id, &topic, px);
+				control.entry(peer_id).or_insert_with(Vec::new).push(prune);
+			}
+		}
+
+		let fanout_ttl = self.config.fanout_ttl;
+		let expired = self.fanout_last_published.iter()
+			.filter(|&(_, &last)| last + fanout_ttl <= now)
+			.map(|(topic, _)| topic.clone())
+			.collect::<Vec<_>>();
+		for topic in expired {
+			self.fanout.remove(&topic);
+			self.fanout_last_published.remove(&topic);
+		}
+
+		let publish_threshold = self.thresholds.publish_threshold;
+		let mut topics = self.fanout.keys().cloned().collect::<Vec<_>>();
+		topics.sort();
+		for topic in topics {
+			let mut peers = self.fanout.remove(&topic).unwrap_or_default();
+			peers.retain(|peer_id| self.score(peer_id) >= publish_threshold);
+			let missing = self.config.mesh_n.saturating_sub(peers.len());
+			let added = self.random_peers(&topic, missing, |peer_id| {
+				!peers.contains(peer_id) && self.score(peer_id) >= publish_threshold
+			});
+			peers.extend(added);
+			self.fanout.insert(topic, peers);
+		}
+
+		let gossip_threshold = self.thresholds.gossip_threshold;
+		let mut topics = self.mesh.keys().chain(self.fanout.keys()).cloned().collect::<Vec<_>>();
+		topics.sort();
+		for topic in topics {
+			let message_ids = self.mcache.get_gossip_ids(&topic);
+			if message_ids.is_empty() {
+				continue;
+			}
+
+			let exclude = match self.mesh.get(&topic).or_else(|| self.fanout.get(&topic)) {
+				Some(peers) => peers.clone(),
+				None => HashSet::new(),
+			};
+			let peers = self.random_peers(&topic, self.config.gossip_lazy, |peer_id| {
+				!exclude.contains(peer_id) && self.score(peer_id) >= gossip_threshold
+			});
+			for peer_id in peers {
+				control.entry(peer_id)
+					.or_insert_with(Vec::new)
+					.push(GossipsubControlAction::IHave {
+						topic: topic.clone(),
+						message_ids: message_ids.clone(),
+					});
+			}
+		}
+
+		self.mcache.shift();
+
+		for (peer_id, actions) in control {
+			self.send_control(peer_id, actions);
+		}
+	}
+
+	// Records that a peer joins the mesh of a topic, and returns the `Graft` to send to it.
+	fn graft_peer(&mut self, peer_id: &PeerId, topic: &Topic) -> GossipsubControlAction {
+		if let Some(ref mut peer_score) = self.peer_score {
+			peer_score.graft(peer_id, topic);
+		}
+		GossipsubControlAction::Graft { topic: topic.clone() }
+	}
+
+	// Records that a peer leaves the mesh of a topic, and returns the `Prune` to send to it. If
+	// `px` is true and `do_px` is enabled, other peers of the topic are sent alongside.
+	fn prune_peer(&mut self, peer_id: &PeerId, topic: &Topic, px: bool)
+				  -> GossipsubControlAction
+	{
+		let backoff = self.config.prune_backoff;
+		self.backoffs.insert((topic.clone(), peer_id.clone()), Instant::now() + backoff);
+		if let Some(ref mut peer_score) = self.peer_score {
+			peer_score.prune(peer_id, topic);
+		}
+
+		let peers = if px && self.config.do_px {
+			self.random_peers(topic, self.config.prune_peers, |other| {
+				other != peer_id && self.score(other) >= 0.0
+			})
+		} else {
+			Vec::new()
+		};
+
+		GossipsubControlAction::Prune {
+			topic: topic.clone(),
+			peers: peers,
+			backoff: Some(backoff),
+		}
+	}
+
+	// Sends a subscription or an unsubscription to all the connected peers.
+	fn announce_subscription(&mut self, topic: &Topic, action: GossipsubSubscriptionAction) {
+		let peers = self.peer_topics.keys().cloned().collect::<Vec<_>>();
+		for peer_id in peers {
+			self.send_rpc(peer_id, GossipsubRpc {
+				subscriptions: vec![GossipsubSubscription {
+					action: action,
+					topic: topic.clone(),
+				}],
+				.. GossipsubRpc::default()
+			});
+		}
+	}
+
+	// Returns up to `count` random peers subscribed to `topic` for which `filter` returns true.
+	fn random_peers<F>(&self, topic: &Topic, count: usize, mut filter: F) -> Vec<PeerId>
+		where F: FnMut(&PeerId) -> bool
+	{
+		let mut peers = self.peer_topics.iter()
+			.filter(|&(peer_id, topics)| topics.contains(topic) && filter(peer_id))
+			.map(|(peer_id, _)| peer_id.clone())
+			.collect::<Vec<_>>();
+		// Sorted first, as the order of a `HashMap` differs from one run to another.
+		peers.sort();
+		self.rng.borrow_mut().shuffle(&mut peers);
+		peers.truncate(count);
+		peers
+	}
+
+	// Returns the score of a peer, or 0 if the peer scoring isn't enabled.
+	#[inline]
+	fn score(&self, peer_id: &PeerId) -> f64 {
+		self.peer_score.as_ref().map(|peer_score| peer_score.score(peer_id)).unwrap_or(0.0)
+	}
+
+	// Returns true if a peer was removed from the mesh of a topic less than `prune_backoff` ago.
+	fn is_backing_off(&self, topic: &Topic, peer_id: &PeerId) -> bool {
+		self.backoffs.get(&(topic.clone(), peer_id.clone()))
+			.map(|&expires| expires > Instant::now())
+			.unwrap_or(false)
+	}
+
+	// Returns true if a peer can be added to the mesh of a topic.
+	#[inline]
+	fn can_graft(&self, topic: &Topic, peer_id: &PeerId) -> bool {
+		!self.is_backing_off(topic, peer_id) && self.score(peer_id) >= 0.0
+	}
+
+	#[inline]
+	fn send_control(&mut self, peer_id: PeerId, control: Vec<GossipsubControlAction>) {
+		self.send_rpc(peer_id, GossipsubRpc {
+			control: control,
+			.. GossipsubRpc::default()
+		});
+	}
+
+	fn send_rpc(&mut self, peer_id: PeerId, rpc: GossipsubRpc) {
+		if rpc.is_empty() {
+			return;
+		}
+
+		self.queued_actions.push_back(GossipsubAction::SendEvent {
+			peer_id: peer_id,
+			event: rpc,
+		});
+	}
+
+	fn mark_seen(&mut self, id: MessageId) {
+		self.seen.insert(id.clone());
+		self.seen_order.push_back((Instant::now(), id));
+	}
+
+	// Returns the next sequence number, as 8 big-endian bytes. It is the current time in
+	// nanoseconds since the UNIX epoch, or one more than the previous one if the clock didn't move
+	// forward.
+	fn next_sequence_number(&mut self) -> Vec<u8> {
+		let now = time::system_now().duration_since(UNIX_EPOCH).unwrap_or(Duration::new(0, 0));
+		let now = now.as_secs() * 1_000_000_000 + u64::from(now.subsec_nanos());
+		let sequence_number = cmp::max(now, self.next_sequence_number);
+		self.next_sequence_number = sequence_number.wrapping_add(1);
+		(0 .. 8).rev().map(|n| (sequence_number >> (8 * n)) as u8).collect()
+	}
+}
+
+// Converts a sequence number that is a number of nanoseconds since the UNIX epoch to the moment
+// when the message was published. Returns `None` if it isn't 8 bytes long.
+fn sequence_number_time(sequence_number: &[u8]) -> Option<SystemTime> {
+	if sequence_number.len() != 8 {
+		return None;
+	}
+
+	let nanos = sequence_number.iter().fold(0u64, |acc, &byte| (acc << 8) | u64::from(byte));
+	Some(UNIX_EPOCH + Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Gossipsub, GossipsubAction, GossipsubConfig, GossipsubEvent, ValidationResult};
+	use super::sequence_number_time;
+	use handler::GossipsubHandlerEvent::{self, Rpc};
+	use futures::sync::oneshot;
+	use futures::{future, Async, Future};
+	use libp2p_keys::Keypair;
+	use libp2p_swarm::{ClockSkew, ConnectionUpgrade, Endpoint, NodeHandlerEndpoint, PeerId};
+	use libp2p_swarm::{ProtocolsHandler, ProtocolsHandlerEvent};
+	use std::io::Cursor;
+	use std::sync::Arc;
+	use protocol::{GossipsubControlAction, GossipsubMessage, GossipsubRpc, MessageId, Topic};
+	use protocol::{GossipsubSubscription, GossipsubSubscriptionAction};
+	use rand::{SeedableRng, XorShiftRng};
+	use score::{PeerScoreParams, PeerScoreThresholds, TopicScoreParams};
+	use signing::SignaturePolicy;
+	use std::cell::RefCell;
+	use std::rc::Rc;
+	use std::thread;
+	use std::time::{Duration, SystemTime, UNIX_EPOCH};
+	use tokio_core::reactor::{Core, Handle};
+
+	// Nodes that are all connected to each other, and that sign their messages. The actions of the
+	// nodes are delivered to each other as if they were connected through handlers.
+	struct Network {
+		peers: Vec<PeerId>,
+		nodes: Vec<Gossipsub>,
+		// Connections opened after a `Dial`, as the indices of the dialer and of the dialed node.
+		dials: Vec<(usize, usize)>,
+	}
+
+	impl Network {
+		fn new(handle: &Handle, size: u8, config: GossipsubConfig) -> Network {
+			let keypairs = (0 .. size)
+				.map(|_| Keypair::generate_ed25519().unwrap())
+				.collect::<Vec<_>>();
+			let peers = keypairs.iter()
+				.map(|keypair| PeerId::from_public_key(&keypair.public().into_protobuf_encoding()))
+				.collect::<Vec<_>>();
+			let mut nodes = peers.iter()
+				.zip(keypairs.into_iter())
+				.map(|(peer_id, keypair)| {
+					Gossipsub::new(peer_id.clone(), handle.clone(), config)
+						.with_signer(Arc::new(keypair))
+				})
+				.collect::<Vec<_>>();
+			for n in 0 .. peers.len() {
+				for other in 0 .. peers.len() {
+					if n != other {
+						nodes[n].inject_connected(&peers[other]);
+					}
+				}
+			}
+
+			Network {
+				peers: peers,
+				nodes: nodes,
+				dials: Vec::new(),
+			}
+		}
+
+		// Delivers the actions until nothing happens anymore, and returns the events of the
+		// nodes. Must be called within a task, because of the heartbeat timers.
+		fn run(&mut self) -> Vec<(usize, GossipsubEvent)> {
+			let mut events = Vec::new();
+			loop {
+				let mut progress = false;
+				for n in 0 .. self.nodes.len() {
+					let action = match self.nodes[n].poll() {
+						Async::Ready(action) => action,
+						Async::NotReady => continue,
+					};
+					progress = true;
+
+					match action {
+						GossipsubAction::Dial { peer_id } => {
+							let remote = self.peers.iter().position(|p| *p == peer_id).unwrap();
+							let local = self.peers[n].clone();
+							self.nodes[n].inject_connected(&peer_id);
+							self.nodes[remote].inject_connected(&local);
+							self.dials.push((n, remote));
+						},
+						GossipsubAction::SendEvent { peer_id, event } => {
+							let remote = self.peers.iter().position(|p| *p == peer_id).unwrap();
+							let local = self.peers[n].clone();
+							self.nodes[remote].inject_node_event(&local, Rpc(event));
+						},
+						GossipsubAction::GenerateEvent(event) => events.push((n, event)),
+					}
+				}
+
+				if !progress {
+					return events;
+				}
+			}
+		}
+
+		fn heartbeat(&mut self) {
+			for node in &mut self.nodes {
+				node.heartbeat();
+			}
+		}
+	}
+
+	// Scoring on a single topic, where the peers are only penalized for their misbehaviours.
+	fn score_params(topic: &Topic) -> PeerScoreParams {
+		let mut params = PeerScoreParams::default();
+		params.topics.insert(topic.clone(), TopicScoreParams {
+			time_in_mesh_weight: 0.0,
+			first_message_deliveries_weight: 0.0,
+			mesh_message_deliveries_weight: 0.0,
+			mesh_failure_penalty_weight: 0.0,
+			.. TopicScoreParams::default()
+		});
+		params
+	}
+
+	// Returns the control messages sent by a node to a peer.
+	fn control_sent(node: &mut Gossipsub, peer_id: &PeerId) -> Vec<GossipsubControlAction> {
+		let mut control = Vec::new();
+		while let Async::Ready(action) = node.poll() {
+			if let GossipsubAction::SendEvent { peer_id: ref to, ref event } = action {
+				if to == peer_id {
+					control.extend(event.control.iter().cloned());
+				}
+			}
+		}
+		control
+	}
+
+	// Returns an RPC that contains an unsigned message of `source` on `topic`.
+	fn unsigned_rpc(source: &PeerId, topic: &Topic, sequence_number: u8, data: &[u8])
+					-> GossipsubRpc
+	{
+		GossipsubRpc {
+			messages: vec![GossipsubMessage {
+				source: source.clone(),
+				data: data.to_vec(),
+				sequence_number: vec![sequence_number],
+				topics: vec![topic.clone()],
+				signature: None,
+				key: None,
+			}],
+			.. GossipsubRpc::default()
+		}
+	}
+
+	// Returns the data of the messages reported by a node.
+	fn reported(node: &mut Gossipsub) -> Vec<Vec<u8>> {
+		let mut reported = Vec::new();
+		while let Async::Ready(action) = node.poll() {
+			if let GossipsubAction::GenerateEvent(event) = action {
+				if let GossipsubEvent::Message { message, .. } = event {
+					reported.push(message.data);
+				}
+			}
+		}
+		reported
+	}
+
+	// Returns the nodes that received a message, with its data.
+	fn received(events: &[(usize, GossipsubEvent)]) -> Vec<(usize, Vec<u8>)> {
+		events.iter()
+			.filter_map(|&(n, ref event)| match *event {
+				GossipsubEvent::Message { ref message, .. } => Some((n, message.data.clone())),
+				_ => None,
+			})
+			.collect()
+	}
+
+	#[test]
+	fn publish_through_mesh() {
+		let mut core = Core::new().unwrap();
+		let mut network = Network::new(&core.handle(), 10, GossipsubConfig::default());
+		let topic = Topic::new("topic");
+
+		for node in &mut network.nodes {
+			assert!(node.subscribe(topic.clone()));
+		}
+		let events = core.run(future::lazy(|| {
+			let events = network.run();
+			network.heartbeat();
+			Ok::<_, ()>(events.into_iter().chain(network.run()).collect::<Vec<_>>())
+		})).unwrap();
+		let subscribed = events.iter()
+			.filter(|&&(_, ref event)| match *event {
+				GossipsubEvent::Subscribed { .. } => true,
+				_ => false,
+			})
+			.count();
+		assert_eq!(subscribed, 10 * 9);
+
+		let config = GossipsubConfig::default();
+		for node in &network.nodes {
+			let mesh_len = node.mesh_peers(&topic).len();
+			assert!(mesh_len >= config.mesh_n_low && mesh_len <= config.mesh_n_high);
+		}
+
+		network.nodes[0].publish(topic.clone(), b"hello".to_vec());
+		let events = core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		let expected = (1 .. 10).map(|n| (n, b"hello".to_vec())).collect::<Vec<_>>();
+		let mut received = received(&events);
+		received.sort();
+		assert_eq!(received, expected);
+	}
+
+	#[test]
+	fn same_seed_same_mesh() {
+		let core = Core::new().unwrap();
+		let topic = Topic::new("topic");
+		let peers = (0 .. 20)
+			.map(|_| {
+				let keypair = Keypair::generate_ed25519().unwrap();
+				PeerId::from_public_key(&keypair.public().into_protobuf_encoding())
+			})
+			.collect::<Vec<_>>();
+
+		let meshes = (0 .. 2)
+			.map(|_| {
+				let rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+				let config = GossipsubConfig::default();
+				let mut node = Gossipsub::new(peers[0].clone(), core.handle(), config)
+					.with_rng(rng);
+				for peer_id in &peers[1 ..] {
+					node.inject_connected(peer_id);
+					node.inject_node_event(peer_id, Rpc(GossipsubRpc {
+						subscriptions: vec![GossipsubSubscription {
+							action: GossipsubSubscriptionAction::Subscribe,
+							topic: topic.clone(),
+						}],
+						.. GossipsubRpc::default()
+					}));
+				}
+				node.subscribe(topic.clone());
+				let mut mesh = node.mesh_peers(&topic);
+				mesh.sort();
+				mesh
+			})
+			.collect::<Vec<_>>();
+
+		assert_eq!(meshes[0].len(), GossipsubConfig::default().mesh_n);
+		assert_eq!(meshes[0], meshes[1]);
+	}
+
+	#[test]
+	fn publish_through_fanout() {
+		let mut core = Core::new().unwrap();
+		let mut network = Network::new(&core.handle(), 10, GossipsubConfig::default());
+		let topic = Topic::new("topic");
+
+		// All the nodes except the last one are subscribed.
+		for node in &mut network.nodes[.. 9] {
+			node.subscribe(topic.clone());
+		}
+		core.run(future::lazy(|| {
+			network.run();
+			network.heartbeat();
+			Ok::<_, ()>(network.run())
+		})).unwrap();
+
+		network.nodes[9].publish(topic.clone(), b"hello".to_vec());
+		let events = core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		assert_eq!(network.nodes[9].fanout[&topic].len(), GossipsubConfig::default().mesh_n);
+		let expected = (0 .. 9).map(|n| (n, b"hello".to_vec())).collect::<Vec<_>>();
+		let mut received = received(&events);
+		received.sort();
+		assert_eq!(received, expected);
+	}
+
+	#[test]
+	fn gossip() {
+		let mut core = Core::new().unwrap();
+		// Without any mesh, the messages are only propagated through the gossip.
+		let config = GossipsubConfig { mesh_n_low: 0, mesh_n: 0, .. GossipsubConfig::default() };
+		let mut network = Network::new(&core.handle(), 2, config);
+		let topic = Topic::new("topic");
+
+		network.nodes[0].subscribe(topic.clone());
+		network.nodes[1].subscribe(topic.clone());
+		network.nodes[0].publish(topic.clone(), b"hello".to_vec());
+		let events = core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		assert!(received(&events).is_empty());
+
+		// The heartbeat sends `IHave`, which is answered with `IWant`.
+		network.nodes[0].heartbeat();
+		let events = core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		assert_eq!(received(&events), vec![(1, b"hello".to_vec())]);
+
+		// The message is reported only once.
+		network.nodes[0].heartbeat();
+		let events = core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		assert!(received(&events).is_empty());
+	}
+
+	#[test]
+	fn mesh_pruned_above_high() {
+		let mut core = Core::new().unwrap();
+		let config = GossipsubConfig {
+			mesh_n_low: 1,
+			mesh_n: 2,
+			mesh_n_high: 3,
+			.. GossipsubConfig::default()
+		};
+		let mut network = Network::new(&core.handle(), 8, config);
+		let topic = Topic::new("topic");
+
+		network.nodes[0].subscribe(topic.clone());
+		let graft = GossipsubRpc {
+			control: vec![GossipsubControlAction::Graft { topic: topic.clone() }],
+			.. GossipsubRpc::default()
+		};
+		for n in 1 .. 8 {
+			let peer_id = network.peers[n].clone();
+			network.nodes[0].inject_node_event(&peer_id, Rpc(graft.clone()));
+		}
+		assert_eq!(network.nodes[0].mesh_peers(&topic).len(), 7);
+
+		network.nodes[0].heartbeat();
+		core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		assert_eq!(network.nodes[0].mesh_peers(&topic).len(), 2);
+	}
+
+	#[test]
+	#[should_panic]
+	fn invalid_mesh_degree() {
+		let core = Core::new().unwrap();
+		let config = GossipsubConfig { mesh_n: 20, .. GossipsubConfig::default() };
+		Gossipsub::new(PeerId::from_public_key(&[0]), core.handle(), config);
+	}
+
+	#[test]
+	fn graft_during_backoff() {
+		let core = Core::new().unwrap();
+		let topic = Topic::new("topic");
+		let local_peer_id = PeerId::from_public_key(&[0]);
+		let remote = PeerId::from_public_key(&[1]);
+		let mut gossipsub = Gossipsub::new(local_peer_id, core.handle(), Default::default())
+			.with_peer_score(score_params(&topic), PeerScoreThresholds::default());
+		gossipsub.inject_connected(&remote);
+		gossipsub.subscribe(topic.clone());
+
+		let graft = GossipsubRpc {
+			control: vec![GossipsubControlAction::Graft { topic: topic.clone() }],
+			.. GossipsubRpc::default()
+		};
+		gossipsub.inject_node_event(&remote, Rpc(graft.clone()));
+		assert_eq!(gossipsub.mesh_peers(&topic), vec![remote.clone()]);
+
+		let prune = GossipsubRpc {
+			control: vec![GossipsubControlAction::Prune {
+				topic: topic.clone(),
+				peers: Vec::new(),
+				backoff: None,
+			}],
+			.. GossipsubRpc::default()
+		};
+		gossipsub.inject_node_event(&remote, Rpc(prune));
+		assert!(gossipsub.mesh_peers(&topic).is_empty());
+		assert_eq!(gossipsub.peer_score(&remote), Some(0.0));
+
+		// Grafting again before the end of the backoff is penalized, and refused.
+		core.run(future::lazy(|| {
+			control_sent(&mut gossipsub, &remote);
+			gossipsub.inject_node_event(&remote, Rpc(graft));
+			let control = control_sent(&mut gossipsub, &remote);
+			assert_eq!(control.len(), 1);
+			match control[0] {
+				GossipsubControlAction::Prune { ref peers, .. } => assert!(peers.is_empty()),
+				_ => panic!("expected a prune"),
+			}
+			Ok::<_, ()>(())
+		})).unwrap();
+		assert!(gossipsub.mesh_peers(&topic).is_empty());
+		assert!(gossipsub.peer_score(&remote).unwrap() < 0.0);
+	}
+
+	#[test]
+	fn broken_promise_pruned() {
+		let core = Core::new().unwrap();
+		let topic = Topic::new("topic");
+		let local_peer_id = PeerId::from_public_key(&[0]);
+		let remote = PeerId::from_public_key(&[1]);
+		let config = GossipsubConfig {
+			iwant_followup_time: Duration::new(0, 0),
+			.. GossipsubConfig::default()
+		};
+		let mut gossipsub = Gossipsub::new(local_peer_id, core.handle(), config)
+			.with_peer_score(score_params(&topic), PeerScoreThresholds::default());
+		gossipsub.inject_connected(&remote);
+		gossipsub.subscribe(topic.clone());
+		gossipsub.inject_node_event(&remote, Rpc(GossipsubRpc {
+			control: vec![GossipsubControlAction::Graft { topic: topic.clone() }],
+			.. GossipsubRpc::default()
+		}));
+
+		// The remote advertises a message, but doesn't deliver it.
+		gossipsub.inject_node_event(&remote, Rpc(GossipsubRpc {
+			control: vec![GossipsubControlAction::IHave {
+				topic: topic.clone(),
+				message_ids: vec![MessageId(vec![1, 2, 3])],
+			}],
+			.. GossipsubRpc::default()
+		}));
+		gossipsub.heartbeat();
+		assert!(gossipsub.peer_score(&remote).unwrap() < 0.0);
+		assert!(gossipsub.mesh_peers(&topic).is_empty());
+	}
+
+	#[test]
+	fn protocol_violation_is_penalized() {
+		let core = Core::new().unwrap();
+		let topic = Topic::new("topic");
+		let local_peer_id = PeerId::from_public_key(&[0]);
+		let remote = PeerId::from_public_key(&[1]);
+		let mut gossipsub = Gossipsub::new(local_peer_id, core.handle(), GossipsubConfig::default())
+			.with_peer_score(score_params(&topic), PeerScoreThresholds::default());
+		gossipsub.inject_connected(&remote);
+
+		// The remote negotiates gossipsub, then speaks HTTP.
+		let addr = "/ip4/127.0.0.1/tcp/10000".parse().unwrap();
+		let mut handler = gossipsub.new_handler();
+		let substream = Cursor::new(b"GET / HTTP/1.1\r\n\r\n".to_vec());
+		let upgrade = handler.listen_protocol();
+		let substream = upgrade.upgrade(substream, (), Endpoint::Listener, &addr);
+		handler.inject_fully_negotiated(substream.wait().unwrap(), NodeHandlerEndpoint::Listener);
+		let event = match future::lazy(|| handler.poll()).wait() {
+			Ok(Async::Ready(Some(ProtocolsHandlerEvent::Custom(event)))) => event,
+			_ => panic!("expected an event"),
+		};
+		match event {
+			GossipsubHandlerEvent::ProtocolViolation(_) => (),
+			_ => panic!("expected a protocol violation"),
+		}
+
+		gossipsub.inject_node_event(&remote, event);
+		assert!(gossipsub.peer_score(&remote).unwrap() < 0.0);
+	}
+
+	#[test]
+	fn peer_exchange() {
+		let mut core = Core::new().unwrap();
+		let config = GossipsubConfig { do_px: true, .. GossipsubConfig::default() };
+		let mut network = Network::new(&core.handle(), 3, config);
+		let topic = Topic::new("topic");
+
+		// The nodes 1 and 2 only know the node 0.
+		let (peer1, peer2) = (network.peers[1].clone(), network.peers[2].clone());
+		network.nodes[1].inject_disconnected(&peer2);
+		network.nodes[2].inject_disconnected(&peer1);
+
+		network.nodes[1].subscribe(topic.clone());
+		network.nodes[2].subscribe(topic.clone());
+		core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		network.nodes[0].subscribe(topic.clone());
+		core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		assert_eq!(network.nodes[0].mesh_peers(&topic).len(), 2);
+
+		// When leaving the mesh, the node 0 tells each peer about the other one. Once one of them
+		// has dialed the other, they are connected and the second dial doesn't happen.
+		network.nodes[0].unsubscribe(&topic);
+		core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		assert!(network.dials == vec![(1, 2)] || network.dials == vec![(2, 1)]);
+	}
+
+	#[test]
+	fn unsigned_message() {
+		let mut core = Core::new().unwrap();
+		let topic = Topic::new("topic");
+		let remote = PeerId::from_public_key(&[1]);
+		let rpc = unsigned_rpc(&remote, &topic, 1, b"hello");
+
+		for &policy in &[SignaturePolicy::Strict, SignaturePolicy::Permissive] {
+			let config = GossipsubConfig {
+				signature_policy: policy,
+				.. GossipsubConfig::default()
+			};
+			let mut gossipsub = Gossipsub::new(PeerId::from_public_key(&[0]), core.handle(), config)
+				.with_peer_score(score_params(&topic), PeerScoreThresholds::default());
+			gossipsub.inject_connected(&remote);
+			gossipsub.subscribe(topic.clone());
+			gossipsub.inject_node_event(&remote, Rpc(rpc.clone()));
+
+			let messages = core.run(future::lazy(|| Ok::<_, ()>(reported(&mut gossipsub))))
+				.unwrap();
+
+			// The unsigned message is only accepted by the permissive policy. Otherwise, it counts
+			// as an invalid delivery.
+			if policy == SignaturePolicy::Strict {
+				assert!(messages.is_empty());
+				assert!(gossipsub.peer_score(&remote).unwrap() < 0.0);
+			} else {
+				assert_eq!(messages, vec![b"hello".to_vec()]);
+				assert_eq!(gossipsub.peer_score(&remote), Some(0.0));
+			}
+		}
+	}
+
+	#[test]
+	fn clock_skew_of_the_authors() {
+		let core = Core::new().unwrap();
+		let topic = Topic::new("topic");
+		let remote = PeerId::from_public_key(&[1]);
+		let author = PeerId::from_public_key(&[2]);
+		let config = GossipsubConfig {
+			signature_policy: SignaturePolicy::Permissive,
+			.. GossipsubConfig::default()
+		};
+		let skew = ClockSkew::new(4);
+		let mut gossipsub = Gossipsub::new(PeerId::from_public_key(&[0]), core.handle(), config)
+			.with_clock_skew(skew.clone());
+		gossipsub.inject_connected(&remote);
+		gossipsub.subscribe(topic.clone());
+
+		// Both messages have been published 5 seconds in our future.
+		let published = SystemTime::now() + Duration::from_secs(5);
+		let published = published.duration_since(UNIX_EPOCH).unwrap();
+		let nanos = published.as_secs() * 1_000_000_000 + u64::from(published.subsec_nanos());
+		let sequence_number = (0 .. 8).rev().map(|n| (nanos >> (8 * n)) as u8).collect::<Vec<_>>();
+		let mut rpc = unsigned_rpc(&remote, &topic, 0, b"hello");
+		rpc.messages[0].sequence_number = sequence_number.clone();
+		let mut relayed = unsigned_rpc(&author, &topic, 0, b"relayed");
+		relayed.messages[0].sequence_number = sequence_number;
+		gossipsub.inject_node_event(&remote, Rpc(rpc));
+		gossipsub.inject_node_event(&remote, Rpc(relayed));
+
+		// Only the message received directly from its author is measured.
+		let offset = skew.remote_offset(&remote).unwrap();
+		assert!(offset > 4000 && offset <= 5000);
+		assert_eq!(skew.remote_offset(&author), None);
+
+		// Our own sequence numbers are timestamps too.
+		let ours = sequence_number_time(&gossipsub.next_sequence_number()).unwrap();
+		assert!(SystemTime::now().duration_since(ours).unwrap() < Duration::from_secs(1));
+	}
+
+	#[test]
+	fn duplicates_are_penalized() {
+		let mut core = Core::new().unwrap();
+		let topic = Topic::new("topic");
+		let remote = PeerId::from_public_key(&[1]);
+		let config = GossipsubConfig {
+			signature_policy: SignaturePolicy::Permissive,
+			.. GossipsubConfig::default()
+		};
+		let params = PeerScoreParams {
+			duplicate_deliveries_threshold: 1.0,
+			.. score_params(&topic)
+		};
+		let mut gossipsub = Gossipsub::new(PeerId::from_public_key(&[0]), core.handle(), config)
+			.with_peer_score(params, PeerScoreThresholds::default());
+		gossipsub.inject_connected(&remote);
+		gossipsub.subscribe(topic.clone());
+
+		let rpc = unsigned_rpc(&remote, &topic, 1, b"hello");
+		gossipsub.inject_node_event(&remote, Rpc(rpc.clone()));
+		gossipsub.inject_node_event(&remote, Rpc(rpc.clone()));
+		assert_eq!(gossipsub.peer_score(&remote), Some(0.0));
+
+		// Above the threshold, the duplicates lower the score.
+		gossipsub.inject_node_event(&remote, Rpc(rpc));
+		assert!(gossipsub.peer_score(&remote).unwrap() < 0.0);
+		let messages = core.run(future::lazy(|| Ok::<_, ()>(reported(&mut gossipsub)))).unwrap();
+		assert_eq!(messages, vec![b"hello".to_vec()]);
+	}
+
+	#[test]
+	fn validator_rejects() {
+		let mut core = Core::new().unwrap();
+		let mut network = Network::new(&core.handle(), 3, GossipsubConfig::default());
+		let topic = Topic::new("topic");
+
+		for node in &mut network.nodes {
+			node.subscribe(topic.clone());
+			node.set_topic_validator(topic.clone(), |_: &PeerId, message: &GossipsubMessage| {
+				let result = if message.data == b"valid" {
+					ValidationResult::Accept
+				} else {
+					ValidationResult::Reject
+				};
+				Ok::<_, ()>(result)
+			});
+		}
+		core.run(future::lazy(|| {
+			network.run();
+			network.heartbeat();
+			Ok::<_, ()>(network.run())
+		})).unwrap();
+
+		network.nodes[0].publish(topic.clone(), b"invalid".to_vec());
+		let events = core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		assert!(received(&events).is_empty());
+
+		network.nodes[0].publish(topic.clone(), b"valid".to_vec());
+		let events = core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		let mut received = received(&events);
+		received.sort();
+		assert_eq!(received, vec![(1, b"valid".to_vec()), (2, b"valid".to_vec())]);
+	}
+
+	#[test]
+	fn async_validation() {
+		let mut core = Core::new().unwrap();
+		let topic = Topic::new("topic");
+		let remote = PeerId::from_public_key(&[1]);
+		let config = GossipsubConfig {
+			signature_policy: SignaturePolicy::Permissive,
+			.. GossipsubConfig::default()
+		};
+		let mut gossipsub = Gossipsub::new(PeerId::from_public_key(&[0]), core.handle(), config)
+			.with_peer_score(score_params(&topic), PeerScoreThresholds::default());
+		gossipsub.inject_connected(&remote);
+		gossipsub.subscribe(topic.clone());
+
+		// The validator answers once the test sends the result.
+		let pending = Rc::new(RefCell::new(Vec::new()));
+		let validator_pending = pending.clone();
+		gossipsub.set_topic_validator(topic.clone(), move |_: &PeerId, _: &GossipsubMessage| {
+			let (tx, rx) = oneshot::channel();
+			validator_pending.borrow_mut().push(tx);
+			rx.map_err(|_| ())
+		});
+
+		gossipsub.inject_node_event(&remote, Rpc(unsigned_rpc(&remote, &topic, 1, b"first")));
+		gossipsub.inject_node_event(&remote, Rpc(unsigned_rpc(&remote, &topic, 2, b"second")));
+		// The duplicates aren't validated again.
+		gossipsub.inject_node_event(&remote, Rpc(unsigned_rpc(&remote, &topic, 1, b"first")));
+		assert_eq!(pending.borrow().len(), 2);
+
+		core.run(future::lazy(|| {
+			assert!(reported(&mut gossipsub).is_empty());
+
+			let mut senders = pending.borrow_mut().drain(..).collect::<Vec<_>>().into_iter();
+			senders.next().unwrap().send(ValidationResult::Accept).unwrap();
+			senders.next().unwrap().send(ValidationResult::Reject).unwrap();
+			assert_eq!(reported(&mut gossipsub), vec![b"first".to_vec()]);
+			Ok::<_, ()>(())
+		})).unwrap();
+		assert!(gossipsub.peer_score(&remote).unwrap() < 0.0);
+	}
+
+	// Builds a node connected to `remote` whose validator of `topic` answers once the test sends
+	// the result through one of the returned senders.
+	fn node_with_async_validator(handle: Handle, config: GossipsubConfig, remote: &PeerId,
+								 topic: &Topic)
+								 -> (Gossipsub, Rc<RefCell<Vec<oneshot::Sender<ValidationResult>>>>)
+	{
+		let config = GossipsubConfig {
+			signature_policy: SignaturePolicy::Permissive,
+			.. config
+		};
+		let mut gossipsub = Gossipsub::new(PeerId::from_public_key(&[0]), handle, config)
+			.with_peer_score(score_params(topic), PeerScoreThresholds::default());
+		gossipsub.inject_connected(remote);
+		gossipsub.subscribe(topic.clone());
+
+		let pending = Rc::new(RefCell::new(Vec::new()));
+		let validator_pending = pending.clone();
+		gossipsub.set_topic_validator(topic.clone(), move |_: &PeerId, _: &GossipsubMessage| {
+			let (tx, rx) = oneshot::channel();
+			validator_pending.borrow_mut().push(tx);
+			rx.map_err(|_| ())
+		});
+
+		(gossipsub, pending)
+	}
+
+	#[test]
+	fn validation_queue_full() {
+		let mut core = Core::new().unwrap();
+		let topic = Topic::new("topic");
+		let remote = PeerId::from_public_key(&[1]);
+		let config = GossipsubConfig {
+			max_pending_validations: 1,
+			.. GossipsubConfig::default()
+		};
+		let (mut gossipsub, pending) =
+			node_with_async_validator(core.handle(), config, &remote, &topic);
+
+		// The second message isn't validated, because the first one is still being validated.
+		gossipsub.inject_node_event(&remote, Rpc(unsigned_rpc(&remote, &topic, 1, b"first")));
+		gossipsub.inject_node_event(&remote, Rpc(unsigned_rpc(&remote, &topic, 2, b"second")));
+		assert_eq!(pending.borrow().len(), 1);
+
+		core.run(future::lazy(|| {
+			pending.borrow_mut().remove(0).send(ValidationResult::Accept).unwrap();
+			assert_eq!(reported(&mut gossipsub), vec![b"first".to_vec()]);
+			Ok::<_, ()>(())
+		})).unwrap();
+
+		// Like with an `Ignore`, the peer isn't penalized and the message isn't validated again.
+		assert_eq!(gossipsub.peer_score(&remote), Some(0.0));
+		gossipsub.inject_node_event(&remote, Rpc(unsigned_rpc(&remote, &topic, 2, b"second")));
+		assert!(pending.borrow().is_empty());
+	}
+
+	#[test]
+	fn validation_timeout() {
+		let mut core = Core::new().unwrap();
+		let topic = Topic::new("topic");
+		let remote = PeerId::from_public_key(&[1]);
+		let config = GossipsubConfig {
+			validation_timeout: Duration::from_millis(10),
+			.. GossipsubConfig::default()
+		};
+		let (mut gossipsub, pending) =
+			node_with_async_validator(core.handle(), config, &remote, &topic);
+
+		gossipsub.inject_node_event(&remote, Rpc(unsigned_rpc(&remote, &topic, 1, b"slow")));
+		assert_eq!(pending.borrow().len(), 1);
+		thread::sleep(Duration::from_millis(50));
+
+		core.run(future::lazy(|| {
+			assert!(reported(&mut gossipsub).is_empty());
+			// The validation has been dropped, therefore the result can't be sent anymore.
+			let sender = pending.borrow_mut().remove(0);
+			assert!(sender.send(ValidationResult::Accept).is_err());
+			assert!(reported(&mut gossipsub).is_empty());
+			Ok::<_, ()>(())
+		})).unwrap();
+		assert_eq!(gossipsub.peer_score(&remote), Some(0.0));
+	}
+}
@@ -0,0 +1,381 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains `GossipsubHandler`, which handles the gossipsub substreams of a single connection.
+
+use futures::{Async, AsyncSink, Poll, Sink, Stream};
+use libp2p_swarm::{FairScheduler, NodeHandlerEndpoint, ProtocolsHandler, ProtocolsHandlerEvent};
+use libp2p_swarm::{ProtocolMismatch, SniffGuard, SniffedSocket};
+use protocol::{GossipsubProtocolConfig, GossipsubRpc, GossipsubSubstream};
+use std::collections::VecDeque;
+use std::io::Error as IoError;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+// Maximum number of RPCs that a single inbound substream can deliver before the other ones get
+// a chance to be read.
+const INBOUND_QUOTA: usize = 16;
+
+// Number of bytes sent by the remote that are checked by `looks_like_rpc`.
+const SNIFF_LEN: usize = 2;
+
+/// Event produced by a `GossipsubHandler`, to pass to `Gossipsub::inject_node_event`.
+#[derive(Debug, Clone)]
+pub enum GossipsubHandlerEvent {
+	/// The remote sent an RPC.
+	Rpc(GossipsubRpc),
+	/// The remote opened a gossipsub substream, but the first bytes it sent on it aren't an
+	/// RPC. The substream has been closed.
+	ProtocolViolation(ProtocolMismatch),
+}
+
+/// Implementation of `ProtocolsHandler` for the gossipsub protocol.
+///
+/// Sends the RPCs injected with `inject_event` on a single outbound substream, which is opened
+/// when the first RPC is injected, and reports the RPCs that the remote sends on the substreams
+/// it opened. RPCs that can't be decoded are ignored.
+///
+/// The upgrade of the substreams is wrapped in a `SniffGuard`. A remote that negotiates
+/// gossipsub and then sends something that doesn't start like an RPC is reported with a
+/// `GossipsubHandlerEvent::ProtocolViolation`.
+///
+/// The inbound substreams are read in turns with a `FairScheduler`, so that a remote that floods
+/// RPCs on one substream doesn't delay the RPCs of its other substreams.
+///
+/// > **Note**: If the outbound substream can't be opened, the pending RPCs and all the RPCs
+/// >           injected afterwards are discarded. Gossipsub tolerates lost RPCs, as the messages
+/// >           are also propagated through the gossip.
+pub struct GossipsubHandler<S> {
+	// Substream on which we send our RPCs.
+	outbound: OutboundState<SniffedSocket<S>>,
+	// RPCs waiting to be sent on the outbound substream.
+	pending_rpcs: VecDeque<GossipsubRpc>,
+	// Substreams opened by the remote, on which it sends its RPCs.
+	inbound: FairScheduler<(), GossipsubSubstream<SniffedSocket<S>>>,
+	shutting_down: bool,
+}
+
+// State of the outbound substream of a `GossipsubHandler`.
+enum OutboundState<S> {
+	// No substream has been requested yet, or the previous one has been closed.
+	Closed,
+	// The substream has been requested and is being negotiated.
+	Requested,
+	// The substream is open.
+	Open(GossipsubSubstream<S>),
+	// Negotiating the substream failed. The RPCs are discarded.
+	Failed,
+}
+
+impl<S> GossipsubHandler<S> {
+	/// Builds a new `GossipsubHandler`.
+	#[inline]
+	pub fn new() -> GossipsubHandler<S> {
+		GossipsubHandler {
+			outbound: OutboundState::Closed,
+			pending_rpcs: VecDeque::new(),
+			inbound: FairScheduler::new(INBOUND_QUOTA),
+			shutting_down: false,
+		}
+	}
+}
+
+impl<S> Default for GossipsubHandler<S> {
+	#[inline]
+	fn default() -> GossipsubHandler<S> {
+		GossipsubHandler::new()
+	}
+}
+
+impl<S> ProtocolsHandler for GossipsubHandler<S>
+	where S: AsyncRead + AsyncWrite + 'static
+{
+	type InEvent = GossipsubRpc;
+	type OutEvent = GossipsubHandlerEvent;
+	type Substream = S;
+	type Protocol = SniffGuard<GossipsubProtocolConfig>;
+	type OutboundOpenInfo = ();
+
+	#[inline]
+	fn listen_protocol(&self) -> SniffGuard<GossipsubProtocolConfig> {
+		SniffGuard::new(GossipsubProtocolConfig, SNIFF_LEN, looks_like_rpc)
+	}
+
+	fn inject_fully_negotiated(&mut self, substream: GossipsubSubstream<SniffedSocket<S>>,
+							   endpoint: NodeHandlerEndpoint<()>)
+	{
+		match endpoint {
+			NodeHandlerEndpoint::Dialer(()) => self.outbound = OutboundState::Open(substream),
+			NodeHandlerEndpoint::Listener => self.inbound.push((), substream),
+		}
+	}
+
+	fn inject_event(&mut self, rpc: GossipsubRpc) {
+		if let OutboundState::Failed = self.outbound {
+			return;
+		}
+
+		if !rpc.is_empty() {
+			self.pending_rpcs.push_back(rpc);
+		}
+	}
+
+	#[inline]
+	fn inject_dial_upgrade_error(&mut self, _: (), _: &IoError) {
+		self.outbound = OutboundState::Failed;
+		self.pending_rpcs.clear();
+	}
+
+	#[inline]
+	fn shutdown(&mut self) {
+		self.shutting_down = true;
+	}
+
+	fn poll(&mut self) -> Poll<
+		Option<ProtocolsHandlerEvent<SniffGuard<GossipsubProtocolConfig>, (),
+									 GossipsubHandlerEvent>>,
+		IoError
+	> {
+		if self.shutting_down {
+			return Ok(Async::Ready(None));
+		}
+
+		let mut close_outbound = false;
+		match self.outbound {
+			OutboundState::Closed => {
+				if !self.pending_rpcs.is_empty() {
+					self.outbound = OutboundState::Requested;
+					return Ok(Async::Ready(Some(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+						upgrade: self.listen_protocol(),
+						info: (),
+					})));
+				}
+			},
+			OutboundState::Open(ref mut substream) => {
+				while let Some(rpc) = self.pending_rpcs.pop_front() {
+					match substream.start_send(rpc.to_bytes()) {
+						Ok(AsyncSink::Ready) => (),
+						Ok(AsyncSink::NotReady(_)) => {
+							self.pending_rpcs.push_front(rpc);
+							break;
+						},
+						Err(_) => {
+							close_outbound = true;
+							break;
+						},
+					}
+				}
+
+				if !close_outbound && substream.poll_complete().is_err() {
+					close_outbound = true;
+				}
+			},
+			OutboundState::Requested | OutboundState::Failed => (),
+		}
+
+		// The substream is reopened at the next call to `poll` if there are RPCs left to send.
+		if close_outbound {
+			self.outbound = OutboundState::Closed;
+			return self.poll();
+		}
+
+		// The scheduler drops the substreams that the remote closed or that produced an error.
+		while !self.inbound.is_empty() {
+			match self.inbound.poll() {
+				Ok(Async::Ready(Some(((), frame)))) => {
+					if let Ok(rpc) = GossipsubRpc::from_bytes(&frame) {
+						let event = GossipsubHandlerEvent::Rpc(rpc);
+						return Ok(Async::Ready(Some(ProtocolsHandlerEvent::Custom(event))));
+					}
+				},
+				Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+				Err(((), err)) => {
+					if let Some(mismatch) = ProtocolMismatch::from_io_error(&err) {
+						let event = GossipsubHandlerEvent::ProtocolViolation(mismatch.clone());
+						return Ok(Async::Ready(Some(ProtocolsHandlerEvent::Custom(event))));
+					}
+				},
+			}
+		}
+
+		Ok(Async::NotReady)
+	}
+}
+
+// Returns true if the first bytes sent by the remote on a substream look like the beginning of
+// an RPC: a varint length, followed by the tag of one of the fields of `RPC`. Only the tag that
+// follows a single-byte length is checked.
+fn looks_like_rpc(bytes: &[u8]) -> bool {
+	if bytes.len() < 2 || bytes[0] == 0 || bytes[0] >= 0x80 {
+		return true;
+	}
+
+	[0x0a, 0x12, 0x1a].contains(&bytes[1])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{GossipsubHandler, GossipsubHandlerEvent};
+	use futures::{future, Async, Future, Stream};
+	use std::io::Cursor;
+	use libp2p_swarm::{ConnectionUpgrade, Endpoint, NodeHandlerEndpoint, PeerId};
+	use libp2p_swarm::{ProtocolsHandler, ProtocolsHandlerEvent};
+	use protocol::{GossipsubMessage, GossipsubRpc, Topic};
+	use tokio_core::net::{TcpListener, TcpStream};
+	use tokio_core::reactor::Core;
+
+	fn rpc(data: &[u8]) -> GossipsubRpc {
+		GossipsubRpc {
+			messages: vec![GossipsubMessage {
+				source: PeerId::from_public_key(&[1, 2, 3]),
+				data: data.to_owned(),
+				sequence_number: vec![1],
+				topics: vec![Topic::new("topic")],
+				signature: None,
+				key: None,
+			}],
+			.. GossipsubRpc::default()
+		}
+	}
+
+	#[test]
+	fn send_rpcs() {
+		let mut core = Core::new().unwrap();
+		let addr = "/ip4/127.0.0.1/tcp/10000".parse().unwrap();
+
+		let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+		let listener_addr = listener.local_addr().unwrap();
+		let incoming = listener.incoming().into_future().map_err(|(err, _)| err);
+		let dial = TcpStream::connect(&listener_addr, &core.handle());
+		let (incoming, dialed) = core.run(incoming.join(dial)).unwrap();
+		let incoming = incoming.0.unwrap().0;
+
+		let mut dialer = GossipsubHandler::new();
+		let mut listener = GossipsubHandler::new();
+
+		// Empty RPCs aren't sent.
+		dialer.inject_event(GossipsubRpc::default());
+		assert!(dialer.poll().unwrap().is_not_ready());
+
+		dialer.inject_event(rpc(b"first"));
+		match dialer.poll().unwrap() {
+			Async::Ready(Some(ProtocolsHandlerEvent::OutboundSubstreamRequest { .. })) => (),
+			_ => panic!("expected a substream request"),
+		}
+		dialer.inject_event(rpc(b"second"));
+		// Only one substream is requested.
+		assert!(dialer.poll().unwrap().is_not_ready());
+
+		let substream = dialer.listen_protocol().upgrade(dialed, (), Endpoint::Dialer, &addr);
+		dialer.inject_fully_negotiated(core.run(substream).unwrap(),
+									   NodeHandlerEndpoint::Dialer(()));
+		let upgrade = listener.listen_protocol();
+		let substream = upgrade.upgrade(incoming, (), Endpoint::Listener, &addr);
+		listener.inject_fully_negotiated(core.run(substream).unwrap(),
+										 NodeHandlerEndpoint::Listener);
+
+		for expected in &[&b"first"[..], &b"second"[..]] {
+			let received = core.run(future::poll_fn(|| {
+				assert!(dialer.poll().unwrap().is_not_ready());
+				listener.poll()
+			})).unwrap();
+			match received {
+				Some(ProtocolsHandlerEvent::Custom(GossipsubHandlerEvent::Rpc(ref received))) => {
+					assert_eq!(*received, rpc(expected));
+				},
+				_ => panic!("expected an RPC"),
+			}
+		}
+	}
+
+	// Builds a substream on which the remote has sent `rpcs`.
+	fn sent_rpcs(rpcs: &[GossipsubRpc]) -> Cursor<Vec<u8>> {
+		let mut data = Vec::new();
+		for rpc in rpcs {
+			let mut bytes = rpc.to_bytes();
+			let mut len = bytes.len();
+			while len >= 0x80 {
+				data.push((len as u8) | 0x80);
+				len >>= 7;
+			}
+			data.push(len as u8);
+			data.append(&mut bytes);
+		}
+		Cursor::new(data)
+	}
+
+	#[test]
+	fn busy_substream_doesnt_starve_others() {
+		let addr = "/ip4/127.0.0.1/tcp/10000".parse().unwrap();
+		let mut listener = GossipsubHandler::new();
+
+		let busy = (0 .. 100).map(|_| rpc(b"busy")).collect::<Vec<_>>();
+		let quiet = vec![rpc(b"quiet")];
+		for rpcs in &[busy, quiet] {
+			let upgrade = listener.listen_protocol();
+			let substream = upgrade.upgrade(sent_rpcs(rpcs), (), Endpoint::Listener, &addr);
+			listener.inject_fully_negotiated(substream.wait().unwrap(),
+											 NodeHandlerEndpoint::Listener);
+		}
+
+		// The RPC of the quiet substream is reported right after the first one of the busy
+		// substream, instead of after all of them.
+		let received = future::lazy(|| {
+			let mut received = Vec::new();
+			for _ in 0 .. 3 {
+				match listener.poll() {
+					Ok(Async::Ready(Some(ProtocolsHandlerEvent::Custom(event)))) => match event {
+						GossipsubHandlerEvent::Rpc(rpc) => {
+							received.push(rpc.messages[0].data.clone())
+						},
+						_ => panic!("expected an RPC"),
+					},
+					_ => panic!("expected an RPC"),
+				}
+			}
+			Ok::<_, ()>(received)
+		}).wait().unwrap();
+		assert_eq!(received, vec![b"busy".to_vec(), b"quiet".to_vec(), b"busy".to_vec()]);
+	}
+
+	#[test]
+	fn other_protocol_is_reported() {
+		let addr = "/ip4/127.0.0.1/tcp/10000".parse().unwrap();
+		let mut listener = GossipsubHandler::new();
+
+		let substream = Cursor::new(b"GET / HTTP/1.1\r\n\r\n".to_vec());
+		let upgrade = listener.listen_protocol();
+		let substream = upgrade.upgrade(substream, (), Endpoint::Listener, &addr);
+		listener.inject_fully_negotiated(substream.wait().unwrap(), NodeHandlerEndpoint::Listener);
+
+		let event = future::lazy(|| Ok::<_, ()>(listener.poll())).wait().unwrap();
+		match event {
+			Ok(Async::Ready(Some(ProtocolsHandlerEvent::Custom(event)))) => match event {
+				GossipsubHandlerEvent::ProtocolViolation(mismatch) => {
+					assert_eq!(mismatch.received(), b"GE")
+				},
+				_ => panic!("expected a protocol violation"),
+			},
+			_ => panic!("expected an event"),
+		}
+		// The substream has been dropped.
+		let event = future::lazy(|| Ok::<_, ()>(listener.poll())).wait().unwrap();
+		assert!(event.unwrap().is_not_ready());
+	}
+}
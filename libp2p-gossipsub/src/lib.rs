@@ -0,0 +1,212 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+// TODO: use this once stable ; for now we just copy-paste the content of the README.md
+//#![doc(include = "../README.md")]
+
+//! Implementation of gossipsub, the publish-subscribe protocol of libp2p, compatible with the
+//! `/meshsub/1.1.0` and `/meshsub/1.0.0` protocols of the other implementations.
+//!
+//! Nodes subscribe to topics, and the messages published on a topic are delivered to all the
+//! nodes subscribed to it. Instead of flooding each message to every peer, each node only sends
+//! the messages of a topic to a small set of peers subscribed to the same topic, called its
+//! *mesh*. Since the meshes of the nodes overlap, the messages still reach the whole network,
+//! but each node receives each message only a few times. The identifiers of the recent messages
+//! are additionally sent as *gossip* to a few peers outside of the mesh, which ask for the
+//! messages they missed.
+//!
+//! # Usage
+//!
+//! This crate is made of several layers:
+//!
+//! - `GossipsubProtocolConfig` is the upgrade for the protocol, and `GossipsubRpc` is the
+//!   message that the nodes exchange.
+//! - `GossipsubHandler` implements the `ProtocolsHandler` trait of `libp2p-swarm`. It sends the
+//!   RPCs to the remote of a connection, and reports the RPCs of the remote as
+//!   `GossipsubHandlerEvent`s.
+//! - `PeerScore` computes the score of the peers from their behaviour. It doesn't perform any
+//!   I/O and can be used on its own.
+//! - `Gossipsub` contains the subscriptions and the meshes, routes the messages, and produces
+//!   `GossipsubAction`s that tell the user which RPCs to pass to the handlers and which peers
+//!   to dial.
+//!
+//! The user is responsible for the connections: driving the `GossipsubHandler` built by
+//! `Gossipsub::new_handler` on each connection, reporting the connections with
+//! `inject_connected` and `inject_disconnected`, and passing the events reported by the handlers
+//! to `Gossipsub::inject_node_event`.
+//!
+//! ```
+//! extern crate libp2p_gossipsub;
+//! extern crate libp2p_keys;
+//! extern crate libp2p_swarm;
+//! extern crate tokio_core;
+//!
+//! use libp2p_gossipsub::{Gossipsub, GossipsubConfig, Topic};
+//! use libp2p_keys::Keypair;
+//! use libp2p_swarm::PeerId;
+//! use std::sync::Arc;
+//!
+//! # fn main() {
+//! let core = tokio_core::reactor::Core::new().unwrap();
+//! let keypair = Keypair::generate_ed25519().unwrap();
+//! let local_peer_id = PeerId::from_public_key(&keypair.public().into_protobuf_encoding());
+//! let mut gossipsub = Gossipsub::new(local_peer_id, core.handle(), GossipsubConfig::default())
+//!     .with_signer(Arc::new(keypair));
+//!
+//! // The messages received on the topic are produced by `poll()` as
+//! // `GossipsubEvent::Message`s.
+//! let topic = Topic::new("chat");
+//! gossipsub.subscribe(topic.clone());
+//! gossipsub.publish(topic, b"hello".to_vec());
+//! # }
+//! ```
+//!
+//! # Mesh maintenance
+//!
+//! At each heartbeat, which happens every `GossipsubConfig::heartbeat_interval`, peers are
+//! added to the meshes that have fewer than `mesh_n_low` peers with a `GRAFT` control message,
+//! and removed from the meshes that have more than `mesh_n_high` peers with a `PRUNE`, so that
+//! each mesh goes back to `mesh_n` peers. A larger mesh delivers the messages faster and
+//! survives more failures, at the cost of more bandwidth.
+//!
+//! The messages published on a topic we aren't subscribed to are sent to `mesh_n` peers
+//! subscribed to it, the *fanout*, which are kept for the next messages on the topic until
+//! `fanout_ttl` elapses without publishing.
+//!
+//! The peers are chosen at random. For simulations and tests, `Gossipsub::with_rng` sets a
+//! random number generator built from a fixed seed, so that the meshes are the same from one run
+//! to another.
+//!
+//! # Gossip
+//!
+//! The messages are kept for `history_length` heartbeats. At each heartbeat, the identifiers of
+//! the messages of the last `history_gossip` heartbeats are sent in an `IHAVE` control message to
+//! `gossip_lazy` peers of each topic that aren't in the mesh. The peers answer with an `IWANT`
+//! for the messages they haven't seen, which are then sent to them.
+//!
+//! # Message signing
+//!
+//! The messages published by a `Gossipsub` built with `with_signer` are signed with the
+//! identity key of the local node, and carry its public key. The signer is the `Signer` of
+//! libp2p-keys, which can keep the key out of the process, and a message is only sent once its
+//! signature is produced. By default, the received messages that aren't signed by
+//! their source are rejected, like in go-libp2p. With
+//! `SignaturePolicy::Permissive`, the unsigned messages are accepted, but the messages with an
+//! invalid signature are still rejected. The rejected messages are neither reported nor
+//! forwarded, and count as invalid deliveries in the score of the peer that sent them.
+//!
+//! > **Note**: The nodes that don't sign their messages can only communicate with nodes that
+//! > use `SignaturePolicy::Permissive`.
+//!
+//! The sequence number of a message is the moment when it was published, in nanoseconds since the
+//! UNIX epoch. A `Gossipsub` built with `with_clock_skew` measures the offsets of the clocks of the
+//! authors of the messages it receives directly from them, in a `ClockSkew` of libp2p-swarm.
+//!
+//! # Message validation
+//!
+//! The application can check the content of the messages received on a topic by setting a
+//! validator with `Gossipsub::set_topic_validator`. The validator returns a future, so that the
+//! validation can be asynchronous, which produces a `ValidationResult`. The messages are only
+//! reported and forwarded once the validators of all their topics have accepted them, so that
+//! the invalid messages don't propagate. The messages rejected by a validator count as invalid
+//! deliveries in the score of the peer that sent them, while the ignored messages are dropped
+//! without any penalty. So are the messages whose validation takes longer than the
+//! `validation_timeout` of the configuration, or that arrive while `max_pending_validations`
+//! messages are already being validated.
+//!
+//! ```
+//! extern crate libp2p_gossipsub;
+//! extern crate libp2p_swarm;
+//! extern crate tokio_core;
+//!
+//! use libp2p_gossipsub::{Gossipsub, GossipsubConfig, GossipsubMessage, Topic};
+//! use libp2p_gossipsub::ValidationResult;
+//! use libp2p_swarm::PeerId;
+//! use std::str;
+//!
+//! # fn main() {
+//! let core = tokio_core::reactor::Core::new().unwrap();
+//! let local_peer_id = PeerId::from_public_key(&[1, 2, 3, 4]);
+//! let mut gossipsub = Gossipsub::new(local_peer_id, core.handle(), GossipsubConfig::default());
+//!
+//! // Only the messages that contain UTF-8 text are accepted.
+//! gossipsub.set_topic_validator(Topic::new("chat"), |_: &PeerId, message: &GossipsubMessage| {
+//!     let result = match str::from_utf8(&message.data) {
+//!         Ok(_) => ValidationResult::Accept,
+//!         Err(_) => ValidationResult::Reject,
+//!     };
+//!     Ok::<_, ()>(result)
+//! });
+//! # }
+//! ```
+//!
+//! # Peer scoring
+//!
+//! Gossipsub v1.1 lets the nodes protect their meshes against peers that misbehave, for
+//! example to isolate a node by surrounding it with sybils. Once enabled with
+//! `Gossipsub::with_peer_score`, each peer gets a score computed from its behaviour on each of
+//! the topics configured in `PeerScoreParams`, weighted by the `topic_weight` of the topic:
+//! the time it has spent in the mesh, the messages it was the first to deliver, the messages it
+//! failed to deliver while in the mesh, and the invalid messages it delivered. Misbehaviours
+//! such as asking to join a mesh during the backoff that followed a `PRUNE`, or not delivering
+//! the messages advertised in an `IHAVE`, add a behavioural penalty. So does a remote that
+//! negotiates gossipsub on a substream and then sends something else, which the handler detects
+//! with a `SniffGuard` and reports as a `GossipsubHandlerEvent::ProtocolViolation`. A large
+//! number of duplicate messages adds a small penalty as well.
+//!
+//! The score is used as follows:
+//!
+//! - Peers with a negative score are removed from the meshes and aren't added to them.
+//! - When a mesh has too many peers, the `mesh_n_score` peers with the best score are kept.
+//! - Below the thresholds of `PeerScoreThresholds`, no gossip is exchanged with a peer, the
+//!   messages we publish aren't sent to it, and finally all its RPCs are ignored.
+//! - Periodically, if the median score of a mesh is low, peers with a better score are added to
+//!   it. This is called opportunistic grafting.
+//!
+//! With `GossipsubConfig::do_px`, a node that removes a peer from a mesh sends it other peers
+//! of the topic, so that the peer can find another mesh. This is called peer exchange, or PX.
+//! The peers suggested by a remote whose score is above the `accept_px_threshold` are reported
+//! with a `GossipsubAction::Dial`.
+
+extern crate bytes;
+extern crate futures;
+extern crate libp2p_keys;
+extern crate libp2p_swarm;
+extern crate multiaddr;
+extern crate rand;
+extern crate tokio_core;
+extern crate tokio_io;
+extern crate varint;
+
+pub use self::behaviour::{Gossipsub, GossipsubAction, GossipsubConfig, GossipsubEvent};
+pub use self::behaviour::ValidationResult;
+pub use self::handler::{GossipsubHandler, GossipsubHandlerEvent};
+pub use self::protocol::{GossipsubControlAction, GossipsubMessage, GossipsubProtocolConfig};
+pub use self::protocol::{GossipsubRpc, GossipsubSubscription, GossipsubSubscriptionAction};
+pub use self::protocol::{GossipsubSubstream, MessageId, Topic};
+pub use self::score::{PeerScore, PeerScoreParams, PeerScoreThresholds, TopicScoreParams};
+pub use self::signing::{sign_message, verify_message, SignaturePolicy};
+
+mod behaviour;
+mod handler;
+mod mcache;
+mod protocol;
+mod score;
+mod signing;
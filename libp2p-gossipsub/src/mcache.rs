@@ -0,0 +1,150 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains `MessageCache`, which keeps the recent messages in order to answer the `IWant`
+//! requests and to build the gossip.
+
+use protocol::{GossipsubMessage, MessageId, Topic};
+use std::collections::{HashMap, VecDeque};
+
+/// Cache of the messages received or published during the last few heartbeats.
+///
+/// The cache is split into windows, one per heartbeat. Messages are added to the most recent
+/// window, and `shift` drops the oldest window and starts a new one.
+#[derive(Debug, Clone)]
+pub struct MessageCache {
+	// The messages, indexed by their identifier.
+	messages: HashMap<MessageId, GossipsubMessage>,
+	// Identifiers and topics of the messages of each window, the most recent window first.
+	history: VecDeque<Vec<(MessageId, Vec<Topic>)>>,
+	// Number of windows whose messages are advertised in the gossip.
+	gossip: usize,
+}
+
+impl MessageCache {
+	/// Builds an empty cache that keeps `history` windows, the `gossip` most recent of which are
+	/// advertised in the gossip.
+	///
+	/// # Panic
+	///
+	/// Panics if `gossip` is greater than `history`, or if `history` is 0.
+	pub fn new(gossip: usize, history: usize) -> MessageCache {
+		assert!(history != 0, "the message cache must keep at least one window");
+		assert!(gossip <= history, "can't gossip more windows than the cache keeps");
+		let mut windows = VecDeque::with_capacity(history);
+		for _ in 0 .. history {
+			windows.push_back(Vec::new());
+		}
+
+		MessageCache {
+			messages: HashMap::new(),
+			history: windows,
+			gossip: gossip,
+		}
+	}
+
+	/// Adds a message to the most recent window. Does nothing if the message is already cached.
+	pub fn put(&mut self, message: GossipsubMessage) {
+		let id = message.id();
+		if self.messages.contains_key(&id) {
+			return;
+		}
+
+		self.history[0].push((id.clone(), message.topics.clone()));
+		self.messages.insert(id, message);
+	}
+
+	/// Returns a cached message.
+	#[inline]
+	pub fn get(&self, id: &MessageId) -> Option<&GossipsubMessage> {
+		self.messages.get(id)
+	}
+
+	/// Returns the identifiers of the messages of `topic` in the windows advertised in the
+	/// gossip.
+	pub fn get_gossip_ids(&self, topic: &Topic) -> Vec<MessageId> {
+		self.history
+			.iter()
+			.take(self.gossip)
+			.flat_map(|window| window.iter())
+			.filter(|&&(_, ref topics)| topics.contains(topic))
+			.map(|&(ref id, _)| id.clone())
+			.collect()
+	}
+
+	/// Drops the messages of the oldest window, and starts a new window.
+	pub fn shift(&mut self) {
+		if let Some(window) = self.history.pop_back() {
+			for (id, _) in window {
+				self.messages.remove(&id);
+			}
+		}
+		self.history.push_front(Vec::new());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::MessageCache;
+	use libp2p_swarm::PeerId;
+	use protocol::{GossipsubMessage, Topic};
+
+	fn message(seqno: u8, topic: &str) -> GossipsubMessage {
+		GossipsubMessage {
+			source: PeerId::from_public_key(&[1, 2, 3]),
+			data: vec![seqno],
+			sequence_number: vec![seqno],
+			topics: vec![Topic::new(topic)],
+			signature: None,
+			key: None,
+		}
+	}
+
+	#[test]
+	fn gossip_and_expiry() {
+		let mut cache = MessageCache::new(2, 3);
+		let topic = Topic::new("a");
+
+		cache.put(message(1, "a"));
+		cache.put(message(2, "b"));
+		assert_eq!(cache.get_gossip_ids(&topic), vec![message(1, "a").id()]);
+
+		cache.shift();
+		cache.put(message(3, "a"));
+		assert_eq!(cache.get_gossip_ids(&topic),
+				   vec![message(3, "a").id(), message(1, "a").id()]);
+
+		// The first message is still cached, but no longer advertised.
+		cache.shift();
+		assert_eq!(cache.get_gossip_ids(&topic), vec![message(3, "a").id()]);
+		assert!(cache.get(&message(1, "a").id()).is_some());
+
+		cache.shift();
+		assert!(cache.get(&message(1, "a").id()).is_none());
+		assert!(cache.get(&message(2, "b").id()).is_none());
+		assert_eq!(cache.get(&message(3, "a").id()), Some(&message(3, "a")));
+	}
+
+	#[test]
+	#[should_panic]
+	fn gossip_greater_than_history() {
+		MessageCache::new(4, 3);
+	}
+}
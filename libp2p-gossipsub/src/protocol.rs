@@ -0,0 +1,626 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `GossipsubProtocolConfig` upgrade, and the messages of the `/meshsub/1.1.0`
+//! protocol and of its predecessor `/meshsub/1.0.0`.
+//!
+//! Unlike most protocols, each side opens a single substream and keeps it open for as long as
+//! the connection is alive. The node sends all its RPCs on the substream that it opened, and
+//! receives the RPCs of the remote on the substream that the remote opened. RPCs are `RPC`
+//! protobuf messages prefixed with their length as a varint:
+//!
+//! ```text
+//! message RPC {
+//!     message SubOpts {
+//!         bool subscribe = 1;
+//!         string topicid = 2;
+//!     }
+//!     repeated SubOpts subscriptions = 1;
+//!     repeated Message publish = 2;
+//!     ControlMessage control = 3;
+//! }
+//! message Message {
+//!     bytes from = 1;
+//!     bytes data = 2;
+//!     bytes seqno = 3;
+//!     repeated string topicIDs = 4;
+//!     bytes signature = 5;
+//!     bytes key = 6;
+//! }
+//! message ControlMessage {
+//!     repeated ControlIHave ihave = 1;
+//!     repeated ControlIWant iwant = 2;
+//!     repeated ControlGraft graft = 3;
+//!     repeated ControlPrune prune = 4;
+//! }
+//! message ControlIHave {
+//!     string topicID = 1;
+//!     repeated bytes messageIDs = 2;
+//! }
+//! message ControlIWant { repeated bytes messageIDs = 1; }
+//! message ControlGraft { string topicID = 1; }
+//! message ControlPrune {
+//!     string topicID = 1;
+//!     repeated PeerInfo peers = 2;
+//!     uint64 backoff = 3;
+//! }
+//! message PeerInfo {
+//!     bytes peerID = 1;
+//!     bytes signedPeerRecord = 2;
+//! }
+//! ```
+//!
+//! The `peers` and `backoff` fields of `ControlPrune` were added by version 1.1, and are ignored
+//! by the nodes that only support version 1.0. The signed peer records aren't supported.
+
+use bytes::Bytes;
+use futures::future;
+use libp2p_swarm::{ConnectionUpgrade, Endpoint, PeerId};
+use multiaddr::Multiaddr;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::time::Duration;
+use std::vec;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::codec::Framed;
+use varint::VarintCodec;
+
+/// Implementation of the `ConnectionUpgrade` trait for the `/meshsub/1.1.0` protocol, with a
+/// fallback to `/meshsub/1.0.0`.
+///
+/// The output is the substream with the framing of the protocol. The RPCs are exchanged on it by
+/// the `GossipsubHandler`.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct GossipsubProtocolConfig;
+
+/// Substream on which the `/meshsub/1.0.0` protocol has been negotiated.
+pub type GossipsubSubstream<C> = Framed<C, VarintCodec<Vec<u8>>>;
+
+impl<C> ConnectionUpgrade<C> for GossipsubProtocolConfig
+	where C: AsyncRead + AsyncWrite + 'static
+{
+	type NamesIter = vec::IntoIter<(Bytes, ())>;
+	type UpgradeIdentifier = ();
+	type Output = GossipsubSubstream<C>;
+	type Future = future::FutureResult<Self::Output, IoError>;
+
+	#[inline]
+	fn protocol_names(&self) -> Self::NamesIter {
+		vec![
+			(Bytes::from("/meshsub/1.1.0"), ()),
+			(Bytes::from("/meshsub/1.0.0"), ()),
+		].into_iter()
+	}
+
+	#[inline]
+	fn upgrade(self, socket: C, _: (), _: Endpoint, _: &Multiaddr) -> Self::Future {
+		future::ok(socket.framed(VarintCodec::default()))
+	}
+}
+
+/// Topic that the nodes subscribe to and publish messages on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Topic(String);
+
+impl Topic {
+	/// Builds a topic from its name.
+	#[inline]
+	pub fn new<S>(name: S) -> Topic
+		where S: Into<String>
+	{
+		Topic(name.into())
+	}
+
+	/// Returns the name of the topic.
+	#[inline]
+	pub fn as_str(&self) -> &str {
+		&self.0
+	}
+}
+
+/// Identifier of a message, made of the ID of its source followed by its sequence number.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MessageId(pub(crate) Vec<u8>);
+
+impl MessageId {
+	/// Returns the bytes of the identifier.
+	#[inline]
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.0
+	}
+}
+
+/// Message published on one or more topics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GossipsubMessage {
+	/// The node that published the message.
+	pub source: PeerId,
+	/// Content of the message.
+	pub data: Vec<u8>,
+	/// Number chosen by the source, which makes the message unique.
+	pub sequence_number: Vec<u8>,
+	/// Topics the message is published on.
+	pub topics: Vec<Topic>,
+	/// Signature of `signed_data()` by the source, if the message is signed.
+	pub signature: Option<Vec<u8>>,
+	/// Public key of the source, in the `PublicKey` protobuf format, if the message is signed.
+	pub key: Option<Vec<u8>>,
+}
+
+impl GossipsubMessage {
+	/// Returns the identifier of the message.
+	pub fn id(&self) -> MessageId {
+		let mut id = self.source.as_bytes().to_owned();
+		id.extend_from_slice(&self.sequence_number);
+		MessageId(id)
+	}
+
+	/// Returns the data signed by the source of the message, which is the `Message` protobuf
+	/// message without the `signature` and `key` fields, prefixed with `libp2p-pubsub:`.
+	pub fn signed_data(&self) -> Vec<u8> {
+		let mut out = b"libp2p-pubsub:".to_vec();
+		write_message_fields(&mut out, self);
+		out
+	}
+}
+
+/// Subscription or unsubscription of the sender of an RPC to a topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GossipsubSubscription {
+	/// Whether the sender subscribes or unsubscribes.
+	pub action: GossipsubSubscriptionAction,
+	/// The topic.
+	pub topic: Topic,
+}
+
+/// Action of a `GossipsubSubscription`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GossipsubSubscriptionAction {
+	/// The sender subscribes to the topic.
+	Subscribe,
+	/// The sender unsubscribes from the topic.
+	Unsubscribe,
+}
+
+/// Control message used to maintain the meshes and to propagate the gossip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GossipsubControlAction {
+	/// The sender has recently seen these messages.
+	IHave {
+		/// Topic of the messages.
+		topic: Topic,
+		/// Identifiers of the messages.
+		message_ids: Vec<MessageId>,
+	},
+	/// The sender asks for these messages, after an `IHave`.
+	IWant {
+		/// Identifiers of the messages.
+		message_ids: Vec<MessageId>,
+	},
+	/// The sender has added the receiver to its mesh for the topic.
+	Graft {
+		/// The topic.
+		topic: Topic,
+	},
+	/// The sender has removed the receiver from its mesh for the topic.
+	Prune {
+		/// The topic.
+		topic: Topic,
+		/// Other peers subscribed to the topic, which the receiver may connect to instead. Also
+		/// known as peer exchange, or PX.
+		peers: Vec<PeerId>,
+		/// Duration during which the receiver must not ask to join the mesh of the sender again.
+		backoff: Option<Duration>,
+	},
+}
+
+/// RPC exchanged between two nodes.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct GossipsubRpc {
+	/// Changes in the subscriptions of the sender.
+	pub subscriptions: Vec<GossipsubSubscription>,
+	/// Messages published or forwarded by the sender.
+	pub messages: Vec<GossipsubMessage>,
+	/// Control messages.
+	pub control: Vec<GossipsubControlAction>,
+}
+
+impl GossipsubRpc {
+	/// Returns true if the RPC doesn't contain anything.
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.subscriptions.is_empty() && self.messages.is_empty() && self.control.is_empty()
+	}
+
+	/// Parses the protobuf message of an RPC.
+	pub fn from_bytes(bytes: &[u8]) -> Result<GossipsubRpc, IoError> {
+		let mut rpc = GossipsubRpc::default();
+		decode_fields(bytes, |field, value| {
+			match (field, value) {
+				(1, FieldValue::Bytes(sub)) => rpc.subscriptions.push(decode_subscription(sub)?),
+				(2, FieldValue::Bytes(message)) => rpc.messages.push(decode_message(message)?),
+				(3, FieldValue::Bytes(control)) => decode_control(control, &mut rpc.control)?,
+				_ => (),
+			}
+			Ok(())
+		})?;
+		Ok(rpc)
+	}
+
+	/// Serializes the RPC into its protobuf message.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut out = Vec::new();
+
+		for subscription in &self.subscriptions {
+			let mut sub = Vec::new();
+			let subscribe = subscription.action == GossipsubSubscriptionAction::Subscribe;
+			write_varint_field(&mut sub, 1, subscribe as u64);
+			write_bytes_field(&mut sub, 2, subscription.topic.as_str().as_bytes());
+			write_bytes_field(&mut out, 1, &sub);
+		}
+
+		for message in &self.messages {
+			let mut msg = Vec::new();
+			write_message_fields(&mut msg, message);
+			if let Some(ref signature) = message.signature {
+				write_bytes_field(&mut msg, 5, signature);
+			}
+			if let Some(ref key) = message.key {
+				write_bytes_field(&mut msg, 6, key);
+			}
+			write_bytes_field(&mut out, 2, &msg);
+		}
+
+		if !self.control.is_empty() {
+			let mut control = Vec::new();
+			for action in &self.control {
+				let mut inner = Vec::new();
+				let field = match *action {
+					GossipsubControlAction::IHave { ref topic, ref message_ids } => {
+						write_bytes_field(&mut inner, 1, topic.as_str().as_bytes());
+						for id in message_ids {
+							write_bytes_field(&mut inner, 2, id.as_bytes());
+						}
+						1
+					},
+					GossipsubControlAction::IWant { ref message_ids } => {
+						for id in message_ids {
+							write_bytes_field(&mut inner, 1, id.as_bytes());
+						}
+						2
+					},
+					GossipsubControlAction::Graft { ref topic } => {
+						write_bytes_field(&mut inner, 1, topic.as_str().as_bytes());
+						3
+					},
+					GossipsubControlAction::Prune { ref topic, ref peers, backoff } => {
+						write_bytes_field(&mut inner, 1, topic.as_str().as_bytes());
+						for peer_id in peers {
+							let mut peer_info = Vec::new();
+							write_bytes_field(&mut peer_info, 1, peer_id.as_bytes());
+							write_bytes_field(&mut inner, 2, &peer_info);
+						}
+						if let Some(backoff) = backoff {
+							write_varint_field(&mut inner, 3, backoff.as_secs());
+						}
+						4
+					},
+				};
+				write_bytes_field(&mut control, field, &inner);
+			}
+			write_bytes_field(&mut out, 3, &control);
+		}
+
+		out
+	}
+}
+
+// Writes the fields of a `Message` protobuf message, except `signature` and `key`.
+fn write_message_fields(out: &mut Vec<u8>, message: &GossipsubMessage) {
+	write_bytes_field(out, 1, message.source.as_bytes());
+	write_bytes_field(out, 2, &message.data);
+	write_bytes_field(out, 3, &message.sequence_number);
+	for topic in &message.topics {
+		write_bytes_field(out, 4, topic.as_str().as_bytes());
+	}
+}
+
+// Parses a `SubOpts` protobuf message.
+fn decode_subscription(bytes: &[u8]) -> Result<GossipsubSubscription, IoError> {
+	let mut subscribe = false;
+	let mut topic = None;
+	decode_fields(bytes, |field, value| {
+		match (field, value) {
+			(1, FieldValue::Varint(value)) => subscribe = value != 0,
+			(2, FieldValue::Bytes(name)) => topic = Some(decode_topic(name)?),
+			_ => (),
+		}
+		Ok(())
+	})?;
+
+	Ok(GossipsubSubscription {
+		action: if subscribe {
+			GossipsubSubscriptionAction::Subscribe
+		} else {
+			GossipsubSubscriptionAction::Unsubscribe
+		},
+		topic: topic.ok_or_else(|| invalid_message("missing topic"))?,
+	})
+}
+
+// Parses a `Message` protobuf message.
+fn decode_message(bytes: &[u8]) -> Result<GossipsubMessage, IoError> {
+	let mut source = None;
+	let mut data = Vec::new();
+	let mut sequence_number = Vec::new();
+	let mut topics = Vec::new();
+	let mut signature = None;
+	let mut key = None;
+	decode_fields(bytes, |field, value| {
+		match (field, value) {
+			(1, FieldValue::Bytes(from)) => {
+				let peer_id = PeerId::from_bytes(from.to_owned())
+					.map_err(|_| invalid_message("invalid peer ID"))?;
+				source = Some(peer_id);
+			},
+			(2, FieldValue::Bytes(bytes)) => data = bytes.to_owned(),
+			(3, FieldValue::Bytes(seqno)) => sequence_number = seqno.to_owned(),
+			(4, FieldValue::Bytes(name)) => topics.push(decode_topic(name)?),
+			(5, FieldValue::Bytes(bytes)) => signature = Some(bytes.to_owned()),
+			(6, FieldValue::Bytes(bytes)) => key = Some(bytes.to_owned()),
+			_ => (),
+		}
+		Ok(())
+	})?;
+
+	Ok(GossipsubMessage {
+		source: source.ok_or_else(|| invalid_message("missing source"))?,
+		data: data,
+		sequence_number: sequence_number,
+		topics: topics,
+		signature: signature,
+		key: key,
+	})
+}
+
+// Parses a `ControlMessage` protobuf message, and appends its actions to `out`.
+fn decode_control(bytes: &[u8], out: &mut Vec<GossipsubControlAction>) -> Result<(), IoError> {
+	decode_fields(bytes, |field, value| {
+		let inner = match value {
+			FieldValue::Bytes(inner) => inner,
+			FieldValue::Varint(_) => return Ok(()),
+		};
+
+		let mut topic = None;
+		let mut message_ids = Vec::new();
+		let mut peers = Vec::new();
+		let mut backoff = None;
+		decode_fields(inner, |inner_field, value| {
+			match (field, inner_field, value) {
+				(1, 1, FieldValue::Bytes(name)) |
+				(3, 1, FieldValue::Bytes(name)) |
+				(4, 1, FieldValue::Bytes(name)) => topic = Some(decode_topic(name)?),
+				(1, 2, FieldValue::Bytes(id)) |
+				(2, 1, FieldValue::Bytes(id)) => message_ids.push(MessageId(id.to_owned())),
+				(4, 2, FieldValue::Bytes(peer_info)) => peers.push(decode_peer_info(peer_info)?),
+				(4, 3, FieldValue::Varint(secs)) => backoff = Some(Duration::from_secs(secs)),
+				_ => (),
+			}
+			Ok(())
+		})?;
+
+		let missing_topic = || invalid_message("missing topic");
+		let action = match field {
+			1 => GossipsubControlAction::IHave {
+				topic: topic.ok_or_else(missing_topic)?,
+				message_ids: message_ids,
+			},
+			2 => GossipsubControlAction::IWant { message_ids: message_ids },
+			3 => GossipsubControlAction::Graft { topic: topic.ok_or_else(missing_topic)? },
+			4 => GossipsubControlAction::Prune {
+				topic: topic.ok_or_else(missing_topic)?,
+				peers: peers,
+				backoff: backoff,
+			},
+			_ => return Ok(()),
+		};
+		out.push(action);
+		Ok(())
+	})
+}
+
+// Parses a `PeerInfo` protobuf message.
+fn decode_peer_info(bytes: &[u8]) -> Result<PeerId, IoError> {
+	let mut peer_id = None;
+	decode_fields(bytes, |field, value| {
+		if let (1, FieldValue::Bytes(id)) = (field, value) {
+			let id = PeerId::from_bytes(id.to_owned())
+				.map_err(|_| invalid_message("invalid peer ID"))?;
+			peer_id = Some(id);
+		}
+		Ok(())
+	})?;
+
+	peer_id.ok_or_else(|| invalid_message("missing peer ID"))
+}
+
+#[inline]
+fn decode_topic(name: &[u8]) -> Result<Topic, IoError> {
+	String::from_utf8(name.to_owned())
+		.map(Topic)
+		.map_err(|_| invalid_message("the topic isn't valid UTF-8"))
+}
+
+// Value of a field of a protobuf message.
+enum FieldValue<'a> {
+	Varint(u64),
+	Bytes(&'a [u8]),
+}
+
+// Calls `f` for each field of a protobuf message. The fixed-size fields are skipped, as the
+// protocol doesn't use them.
+fn decode_fields<'a, F>(mut data: &'a [u8], mut f: F) -> Result<(), IoError>
+	where F: FnMut(u64, FieldValue<'a>) -> Result<(), IoError>
+{
+	while !data.is_empty() {
+		let key = read_varint(&mut data)?;
+		let value = match key & 0x7 {
+			0 => Some(FieldValue::Varint(read_varint(&mut data)?)),
+			1 | 5 => {
+				let len = if key & 0x7 == 1 { 8 } else { 4 };
+				if len > data.len() {
+					return Err(invalid_message("unexpected end of message"));
+				}
+				data = &data[len ..];
+				None
+			},
+			2 => {
+				let len = read_varint(&mut data)?;
+				if len > data.len() as u64 {
+					return Err(invalid_message("unexpected end of message"));
+				}
+				let (value, rest) = data.split_at(len as usize);
+				data = rest;
+				Some(FieldValue::Bytes(value))
+			},
+			_ => return Err(invalid_message("invalid wire type")),
+		};
+
+		if let Some(value) = value {
+			f(key >> 3, value)?;
+		}
+	}
+
+	Ok(())
+}
+
+fn write_varint_field(out: &mut Vec<u8>, field: u64, value: u64) {
+	write_varint(out, field << 3);
+	write_varint(out, value);
+}
+
+fn write_bytes_field(out: &mut Vec<u8>, field: u64, value: &[u8]) {
+	write_varint(out, (field << 3) | 2);
+	write_varint(out, value.len() as u64);
+	out.extend_from_slice(value);
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+	while value >= 0x80 {
+		out.push((value as u8) | 0x80);
+		value >>= 7;
+	}
+	out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64, IoError> {
+	let mut value = 0u64;
+	for shift in 0 .. 10 {
+		let remaining: &[u8] = *data;
+		let byte = match remaining.first() {
+			Some(&byte) => byte,
+			None => return Err(invalid_message("unexpected end of message")),
+		};
+		*data = &remaining[1 ..];
+		value |= u64::from(byte & 0x7f) << (7 * shift);
+		if byte & 0x80 == 0 {
+			return Ok(value);
+		}
+	}
+
+	Err(invalid_message("invalid varint"))
+}
+
+#[inline]
+fn invalid_message(msg: &str) -> IoError {
+	IoError::new(IoErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{GossipsubControlAction, GossipsubMessage, GossipsubRpc, GossipsubSubscription};
+	use super::{GossipsubSubscriptionAction, MessageId, Topic};
+	use libp2p_swarm::PeerId;
+	use std::time::Duration;
+
+	#[test]
+	fn rpc_roundtrip() {
+		let message = GossipsubMessage {
+			source: PeerId::from_public_key(&[1, 2, 3]),
+			data: b"hello".to_vec(),
+			sequence_number: vec![0, 0, 0, 0, 0, 0, 0, 1],
+			topics: vec![Topic::new("a"), Topic::new("b")],
+			signature: Some(vec![7, 8, 9]),
+			key: Some(vec![10, 11]),
+		};
+		let rpc = GossipsubRpc {
+			subscriptions: vec![
+				GossipsubSubscription {
+					action: GossipsubSubscriptionAction::Subscribe,
+					topic: Topic::new("a"),
+				},
+				GossipsubSubscription {
+					action: GossipsubSubscriptionAction::Unsubscribe,
+					topic: Topic::new("b"),
+				},
+			],
+			messages: vec![message.clone()],
+			control: vec![
+				GossipsubControlAction::IHave {
+					topic: Topic::new("a"),
+					message_ids: vec![message.id()],
+				},
+				GossipsubControlAction::IWant { message_ids: vec![message.id()] },
+				GossipsubControlAction::Graft { topic: Topic::new("a") },
+				GossipsubControlAction::Prune {
+					topic: Topic::new("b"),
+					peers: vec![PeerId::from_public_key(&[4, 5, 6])],
+					backoff: Some(Duration::from_secs(60)),
+				},
+			],
+		};
+
+		assert_eq!(GossipsubRpc::from_bytes(&rpc.to_bytes()).unwrap(), rpc);
+		assert!(GossipsubRpc::default().is_empty());
+		assert!(GossipsubRpc::from_bytes(&[]).unwrap().is_empty());
+	}
+
+	#[test]
+	fn message_id() {
+		let source = PeerId::from_public_key(&[1, 2, 3]);
+		let message = GossipsubMessage {
+			source: source.clone(),
+			data: Vec::new(),
+			sequence_number: vec![4, 5],
+			topics: Vec::new(),
+			signature: None,
+			key: None,
+		};
+		let mut expected = source.as_bytes().to_owned();
+		expected.extend_from_slice(&[4, 5]);
+		assert_eq!(message.id(), MessageId(expected));
+	}
+
+	#[test]
+	fn invalid_topic() {
+		// `SubOpts` whose topic isn't valid UTF-8.
+		let rpc = [0x0a, 0x05, 0x08, 0x01, 0x12, 0x01, 0xff];
+		assert!(GossipsubRpc::from_bytes(&rpc).is_err());
+	}
+}
@@ -0,0 +1,730 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the peer scoring of gossipsub v1.1, which lets the nodes detect and ignore the peers
+//! that misbehave.
+//!
+//! The score of a peer is the sum of the scores of the topics, each weighted by the
+//! `topic_weight` of the topic, plus the behavioural penalty. The score of a topic is made of:
+//!
+//! - P₁, the time spent in the mesh of the topic (positive).
+//! - P₂, the number of messages that the peer was the first to deliver (positive).
+//! - P₃, the square of the deficit of messages delivered by the peer while in the mesh, compared
+//!   to `mesh_message_deliveries_threshold` (negative).
+//! - P₃b, the deficit that the peer had when it was removed from the mesh (negative).
+//! - P₄, the square of the number of invalid messages delivered by the peer (negative).
+//!
+//! The behavioural penalty, P₇, is the square of the number of misbehaviours above
+//! `behaviour_penalty_threshold`, such as asking to join a mesh too soon after being removed, or
+//! not delivering the messages advertised in the gossip.
+//!
+//! The duplicate penalty is the number of messages delivered by the peer that had already been
+//! delivered, above `duplicate_deliveries_threshold`. It is small compared to the other
+//! penalties, as the mesh peers normally deliver some duplicates.
+//!
+//! All the counters decay at each `decay_interval`, so that old deliveries and misbehaviours
+//! are eventually forgotten.
+
+use libp2p_swarm::PeerId;
+use libp2p_swarm::time::Instant;
+use protocol::{MessageId, Topic};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+/// Duration in seconds during which the first delivery of a message is remembered, in order to
+/// credit the mesh peers that deliver it shortly afterwards.
+const DELIVERY_RECORD_TTL_SECS: u64 = 2 * 60;
+
+/// Parameters of the peer scoring.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerScoreParams {
+	/// Parameters of each topic. The topics not in this map don't count in the score.
+	/// Empty by default.
+	pub topics: HashMap<Topic, TopicScoreParams>,
+	/// Maximum of the sum of the weighted scores of the topics, so that a peer can't make up for
+	/// misbehaviours by being very good on one topic. 0 for no maximum. Defaults to 3600.
+	pub topic_score_cap: f64,
+	/// Weight of the behavioural penalty. Must be negative or 0. Defaults to -10.
+	pub behaviour_penalty_weight: f64,
+	/// Number of misbehaviours that are tolerated before the penalty applies. Defaults to 0.
+	pub behaviour_penalty_threshold: f64,
+	/// Decay of the behavioural penalty at each `decay_interval`. Defaults to 0.2.
+	pub behaviour_penalty_decay: f64,
+	/// Weight of the duplicate penalty. Must be negative or 0. Defaults to -0.01.
+	pub duplicate_deliveries_weight: f64,
+	/// Number of duplicates that are tolerated before the penalty applies. Defaults to 100.
+	pub duplicate_deliveries_threshold: f64,
+	/// Decay of the duplicate penalty at each `decay_interval`. Defaults to 0.5.
+	pub duplicate_deliveries_decay: f64,
+	/// Interval at which the counters decay. Defaults to 1 second.
+	pub decay_interval: Duration,
+	/// Value below which a decayed counter is reset to 0. Defaults to 0.1.
+	pub decay_to_zero: f64,
+	/// Duration during which the score of a disconnected peer is remembered, so that a peer
+	/// can't reset a negative score by reconnecting. Defaults to 1 hour.
+	pub retain_score: Duration,
+}
+
+impl Default for PeerScoreParams {
+	#[inline]
+	fn default() -> PeerScoreParams {
+		PeerScoreParams {
+			topics: HashMap::new(),
+			topic_score_cap: 3600.0,
+			behaviour_penalty_weight: -10.0,
+			behaviour_penalty_threshold: 0.0,
+			behaviour_penalty_decay: 0.2,
+			duplicate_deliveries_weight: -0.01,
+			duplicate_deliveries_threshold: 100.0,
+			duplicate_deliveries_decay: 0.5,
+			decay_interval: Duration::from_secs(1),
+			decay_to_zero: 0.1,
+			retain_score: Duration::from_secs(3600),
+		}
+	}
+}
+
+/// Parameters of the peer scoring for a single topic.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TopicScoreParams {
+	/// Weight of the score of the topic in the score of the peer. Defaults to 0.5.
+	pub topic_weight: f64,
+
+	/// Weight of P₁, the time spent in the mesh. Defaults to 1.
+	pub time_in_mesh_weight: f64,
+	/// Duration worth one unit of P₁. Defaults to 1 second.
+	pub time_in_mesh_quantum: Duration,
+	/// Maximum of P₁. Defaults to 3600.
+	pub time_in_mesh_cap: f64,
+
+	/// Weight of P₂, the messages first delivered by the peer. Defaults to 1.
+	pub first_message_deliveries_weight: f64,
+	/// Decay of P₂ at each `decay_interval`. Defaults to 0.5.
+	pub first_message_deliveries_decay: f64,
+	/// Maximum of P₂. Defaults to 2000.
+	pub first_message_deliveries_cap: f64,
+
+	/// Weight of P₃, the deficit of messages delivered while in the mesh. Must be negative or 0.
+	/// Defaults to -1.
+	pub mesh_message_deliveries_weight: f64,
+	/// Decay of the messages delivered while in the mesh, at each `decay_interval`. Defaults to
+	/// 0.5.
+	pub mesh_message_deliveries_decay: f64,
+	/// Maximum of the messages delivered while in the mesh. Defaults to 100.
+	pub mesh_message_deliveries_cap: f64,
+	/// Number of messages below which a mesh peer is penalized. Defaults to 20.
+	pub mesh_message_deliveries_threshold: f64,
+	/// Duration after the first delivery of a message during which the mesh peers that deliver
+	/// it too are credited. Defaults to 10 milliseconds.
+	pub mesh_message_deliveries_window: Duration,
+	/// Duration after joining the mesh before P₃ applies. Defaults to 5 seconds.
+	pub mesh_message_deliveries_activation: Duration,
+
+	/// Weight of P₃b, the deficit when the peer left the mesh. Must be negative or 0.
+	/// Defaults to -1.
+	pub mesh_failure_penalty_weight: f64,
+	/// Decay of P₃b at each `decay_interval`. Defaults to 0.5.
+	pub mesh_failure_penalty_decay: f64,
+
+	/// Weight of P₄, the invalid messages delivered by the peer. Must be negative or 0.
+	/// Defaults to -1.
+	pub invalid_message_deliveries_weight: f64,
+	/// Decay of P₄ at each `decay_interval`. Defaults to 0.3.
+	pub invalid_message_deliveries_decay: f64,
+}
+
+impl Default for TopicScoreParams {
+	#[inline]
+	fn default() -> TopicScoreParams {
+		TopicScoreParams {
+			topic_weight: 0.5,
+			time_in_mesh_weight: 1.0,
+			time_in_mesh_quantum: Duration::from_secs(1),
+			time_in_mesh_cap: 3600.0,
+			first_message_deliveries_weight: 1.0,
+			first_message_deliveries_decay: 0.5,
+			first_message_deliveries_cap: 2000.0,
+			mesh_message_deliveries_weight: -1.0,
+			mesh_message_deliveries_decay: 0.5,
+			mesh_message_deliveries_cap: 100.0,
+			mesh_message_deliveries_threshold: 20.0,
+			mesh_message_deliveries_window: Duration::from_millis(10),
+			mesh_message_deliveries_activation: Duration::from_secs(5),
+			mesh_failure_penalty_weight: -1.0,
+			mesh_failure_penalty_decay: 0.5,
+			invalid_message_deliveries_weight: -1.0,
+			invalid_message_deliveries_decay: 0.3,
+		}
+	}
+}
+
+/// Scores below or above which `Gossipsub` treats the peers differently.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PeerScoreThresholds {
+	/// Below this score, no gossip is exchanged with the peer. Must be negative or 0.
+	/// Defaults to -10.
+	pub gossip_threshold: f64,
+	/// Below this score, the messages we publish aren't sent to the peer. Must be lower than or
+	/// equal to `gossip_threshold`. Defaults to -50.
+	pub publish_threshold: f64,
+	/// Below this score, all the RPCs of the peer are ignored. Must be lower than or equal to
+	/// `publish_threshold`. Defaults to -80.
+	pub graylist_threshold: f64,
+	/// Minimum score of a peer whose peer exchange is followed. Must be positive or 0.
+	/// Defaults to 10.
+	pub accept_px_threshold: f64,
+	/// Median score of a mesh below which peers with a better score are grafted opportunistically.
+	/// Must be positive or 0. Defaults to 20.
+	pub opportunistic_graft_threshold: f64,
+}
+
+impl Default for PeerScoreThresholds {
+	#[inline]
+	fn default() -> PeerScoreThresholds {
+		PeerScoreThresholds {
+			gossip_threshold: -10.0,
+			publish_threshold: -50.0,
+			graylist_threshold: -80.0,
+			accept_px_threshold: 10.0,
+			opportunistic_graft_threshold: 20.0,
+		}
+	}
+}
+
+/// Keeps the statistics of the peers and computes their score.
+pub struct PeerScore {
+	params: PeerScoreParams,
+	peers: HashMap<PeerId, PeerStats>,
+	// Peers that delivered each recent message, with the time of the first delivery.
+	deliveries: HashMap<MessageId, (Instant, HashSet<PeerId>)>,
+	// Same as the keys of `deliveries`, the oldest first.
+	deliveries_order: VecDeque<(Instant, MessageId)>,
+	last_decay: Instant,
+}
+
+// Statistics of a peer.
+#[derive(Default)]
+struct PeerStats {
+	// If the peer is disconnected, the time after which its statistics are forgotten.
+	expires: Option<Instant>,
+	topics: HashMap<Topic, TopicStats>,
+	behaviour_penalty: f64,
+	duplicate_deliveries: f64,
+}
+
+// Statistics of a peer for a topic.
+#[derive(Default)]
+struct TopicStats {
+	// Time at which the peer joined the mesh, if it's in it.
+	mesh_since: Option<Instant>,
+	first_message_deliveries: f64,
+	mesh_message_deliveries: f64,
+	mesh_failure_penalty: f64,
+	invalid_message_deliveries: f64,
+}
+
+impl PeerScore {
+	/// Builds a `PeerScore` that doesn't know any peer.
+	///
+	/// # Panic
+	///
+	/// Panics if a weight that must be negative is positive, if a decay isn't between 0 and 1,
+	/// or if `decay_interval` is 0.
+	pub fn new(params: PeerScoreParams) -> PeerScore {
+		assert!(params.decay_interval > Duration::new(0, 0), "decay_interval can't be 0");
+		assert!(params.behaviour_penalty_weight <= 0.0,
+				"behaviour_penalty_weight must be negative or 0");
+		assert!(is_decay(params.behaviour_penalty_decay),
+				"behaviour_penalty_decay must be between 0 and 1");
+		assert!(params.duplicate_deliveries_weight <= 0.0,
+				"duplicate_deliveries_weight must be negative or 0");
+		assert!(is_decay(params.duplicate_deliveries_decay),
+				"duplicate_deliveries_decay must be between 0 and 1");
+		for topic in params.topics.values() {
+			assert!(topic.mesh_message_deliveries_weight <= 0.0 &&
+					topic.mesh_failure_penalty_weight <= 0.0 &&
+					topic.invalid_message_deliveries_weight <= 0.0,
+					"the weights of the penalties must be negative or 0");
+			assert!(is_decay(topic.first_message_deliveries_decay) &&
+					is_decay(topic.mesh_message_deliveries_decay) &&
+					is_decay(topic.mesh_failure_penalty_decay) &&
+					is_decay(topic.invalid_message_deliveries_decay),
+					"the decays must be between 0 and 1");
+		}
+
+		PeerScore {
+			params: params,
+			peers: HashMap::new(),
+			deliveries: HashMap::new(),
+			deliveries_order: VecDeque::new(),
+			last_decay: Instant::now(),
+		}
+	}
+
+	/// Returns the score of a peer. Unknown peers have a score of 0.
+	pub fn score(&self, peer_id: &PeerId) -> f64 {
+		let stats = match self.peers.get(peer_id) {
+			Some(stats) => stats,
+			None => return 0.0,
+		};
+
+		let now = Instant::now();
+		let mut score = 0.0;
+		for (topic, topic_stats) in &stats.topics {
+			let params = match self.params.topics.get(topic) {
+				Some(params) => params,
+				None => continue,
+			};
+
+			let mut topic_score = 0.0;
+			if let Some(since) = topic_stats.mesh_since {
+				let quanta = secs(now - since) / secs(params.time_in_mesh_quantum);
+				topic_score += quanta.min(params.time_in_mesh_cap) * params.time_in_mesh_weight;
+
+				if now - since >= params.mesh_message_deliveries_activation {
+					let deficit = params.mesh_message_deliveries_threshold -
+						topic_stats.mesh_message_deliveries;
+					if deficit > 0.0 {
+						topic_score += deficit * deficit * params.mesh_message_deliveries_weight;
+					}
+				}
+			}
+			topic_score += topic_stats.first_message_deliveries *
+				params.first_message_deliveries_weight;
+			topic_score += topic_stats.mesh_failure_penalty * params.mesh_failure_penalty_weight;
+			let invalid = topic_stats.invalid_message_deliveries;
+			topic_score += invalid * invalid * params.invalid_message_deliveries_weight;
+
+			score += topic_score * params.topic_weight;
+		}
+
+		if self.params.topic_score_cap > 0.0 && score > self.params.topic_score_cap {
+			score = self.params.topic_score_cap;
+		}
+
+		let excess = stats.behaviour_penalty - self.params.behaviour_penalty_threshold;
+		if excess > 0.0 {
+			score += excess * excess * self.params.behaviour_penalty_weight;
+		}
+
+		let excess = stats.duplicate_deliveries - self.params.duplicate_deliveries_threshold;
+		if excess > 0.0 {
+			score += excess * self.params.duplicate_deliveries_weight;
+		}
+
+		score
+	}
+
+	/// Indicates that a peer has connected. The statistics of a peer that reconnects before
+	/// `retain_score` elapses are kept.
+	pub fn add_peer(&mut self, peer_id: &PeerId) {
+		self.peers.entry(peer_id.clone()).or_insert_with(PeerStats::default).expires = None;
+	}
+
+	/// Indicates that a peer has disconnected. The peer leaves all the meshes, and its
+	/// statistics are kept for `retain_score`, except if its score is positive.
+	pub fn remove_peer(&mut self, peer_id: &PeerId) {
+		if self.score(peer_id) > 0.0 {
+			self.peers.remove(peer_id);
+			return;
+		}
+
+		let topics = match self.peers.get(peer_id) {
+			Some(stats) => stats.topics.keys().cloned().collect::<Vec<_>>(),
+			None => return,
+		};
+		for topic in topics {
+			self.prune(peer_id, &topic);
+		}
+		if let Some(stats) = self.peers.get_mut(peer_id) {
+			stats.expires = Some(Instant::now() + self.params.retain_score);
+		}
+	}
+
+	/// Indicates that a peer has joined the mesh of a topic.
+	pub fn graft(&mut self, peer_id: &PeerId, topic: &Topic) {
+		if let Some(stats) = self.topic_stats(peer_id, topic) {
+			stats.mesh_since = Some(Instant::now());
+			stats.mesh_message_deliveries = 0.0;
+		}
+	}
+
+	/// Indicates that a peer has left the mesh of a topic. If the peer didn't deliver enough
+	/// messages while in the mesh, the deficit is remembered as P₃b.
+	pub fn prune(&mut self, peer_id: &PeerId, topic: &Topic) {
+		let params = match self.params.topics.get(topic) {
+			Some(params) => *params,
+			None => return,
+		};
+		if let Some(stats) = self.topic_stats(peer_id, topic) {
+			let since = match stats.mesh_since.take() {
+				Some(since) => since,
+				None => return,
+			};
+			let deficit = params.mesh_message_deliveries_threshold -
+				stats.mesh_message_deliveries;
+			if since.elapsed() >= params.mesh_message_deliveries_activation && deficit > 0.0 {
+				stats.mesh_failure_penalty += deficit * deficit;
+			}
+		}
+	}
+
+	/// Indicates that a peer was the first to deliver a valid message.
+	pub fn first_delivery(&mut self, peer_id: &PeerId, id: &MessageId, topics: &[Topic]) {
+		let now = Instant::now();
+		let mut delivered_by = HashSet::new();
+		delivered_by.insert(peer_id.clone());
+		self.deliveries.insert(id.clone(), (now, delivered_by));
+		self.deliveries_order.push_back((now, id.clone()));
+
+		for topic in topics {
+			let params = match self.params.topics.get(topic) {
+				Some(params) => *params,
+				None => continue,
+			};
+			if let Some(stats) = self.topic_stats(peer_id, topic) {
+				stats.first_message_deliveries = (stats.first_message_deliveries + 1.0)
+					.min(params.first_message_deliveries_cap);
+				if stats.mesh_since.is_some() {
+					stats.mesh_message_deliveries = (stats.mesh_message_deliveries + 1.0)
+						.min(params.mesh_message_deliveries_cap);
+				}
+			}
+		}
+	}
+
+	/// Indicates that a peer delivered a message that had already been delivered. The duplicate
+	/// counts in the duplicate penalty of the peer, but the mesh peers that deliver it shortly
+	/// after the first delivery are credited as well.
+	pub fn duplicate_delivery(&mut self, peer_id: &PeerId, id: &MessageId, topics: &[Topic]) {
+		if let Some(stats) = self.peers.get_mut(peer_id) {
+			stats.duplicate_deliveries += 1.0;
+		}
+
+		let first = match self.deliveries.get_mut(id) {
+			Some(&mut (first, ref mut delivered_by)) => {
+				if !delivered_by.insert(peer_id.clone()) {
+					return;
+				}
+				first
+			},
+			None => return,
+		};
+
+		for topic in topics {
+			let params = match self.params.topics.get(topic) {
+				Some(params) => *params,
+				None => continue,
+			};
+			if first.elapsed() > params.mesh_message_deliveries_window {
+				continue;
+			}
+			if let Some(stats) = self.topic_stats(peer_id, topic) {
+				if stats.mesh_since.is_some() {
+					stats.mesh_message_deliveries = (stats.mesh_message_deliveries + 1.0)
+						.min(params.mesh_message_deliveries_cap);
+				}
+			}
+		}
+	}
+
+	/// Indicates that a peer delivered an invalid message.
+	pub fn invalid_delivery(&mut self, peer_id: &PeerId, topics: &[Topic]) {
+		for topic in topics {
+			if let Some(stats) = self.topic_stats(peer_id, topic) {
+				stats.invalid_message_deliveries += 1.0;
+			}
+		}
+	}
+
+	/// Adds `count` misbehaviours to the behavioural penalty of a peer.
+	pub fn add_penalty(&mut self, peer_id: &PeerId, count: usize) {
+		if let Some(stats) = self.peers.get_mut(peer_id) {
+			stats.behaviour_penalty += count as f64;
+		}
+	}
+
+	/// Decays the counters if `decay_interval` has elapsed, and forgets the expired peers and
+	/// deliveries. Must be called regularly.
+	pub fn refresh(&mut self) {
+		let now = Instant::now();
+
+		self.peers.retain(|_, stats| stats.expires.map(|expires| expires > now).unwrap_or(true));
+
+		let delivery_ttl = Duration::from_secs(DELIVERY_RECORD_TTL_SECS);
+		loop {
+			let expired = match self.deliveries_order.front() {
+				Some(&(time, _)) => time + delivery_ttl <= now,
+				None => false,
+			};
+			if !expired {
+				break;
+			}
+			if let Some((_, id)) = self.deliveries_order.pop_front() {
+				self.deliveries.remove(&id);
+			}
+		}
+
+		while self.last_decay + self.params.decay_interval <= now {
+			self.last_decay += self.params.decay_interval;
+			self.decay();
+		}
+	}
+
+	// Multiplies all the counters by their decay.
+	fn decay(&mut self) {
+		let params = &self.params;
+		let decay = |value: f64, factor: f64| {
+			let value = value * factor;
+			if value < params.decay_to_zero { 0.0 } else { value }
+		};
+
+		for stats in self.peers.values_mut() {
+			stats.behaviour_penalty = decay(stats.behaviour_penalty,
+											params.behaviour_penalty_decay);
+			stats.duplicate_deliveries = decay(stats.duplicate_deliveries,
+											   params.duplicate_deliveries_decay);
+			for (topic, topic_stats) in &mut stats.topics {
+				let topic_params = match params.topics.get(topic) {
+					Some(topic_params) => topic_params,
+					None => continue,
+				};
+				topic_stats.first_message_deliveries = decay(
+					topic_stats.first_message_deliveries,
+					topic_params.first_message_deliveries_decay);
+				topic_stats.mesh_message_deliveries = decay(
+					topic_stats.mesh_message_deliveries,
+					topic_params.mesh_message_deliveries_decay);
+				topic_stats.mesh_failure_penalty = decay(
+					topic_stats.mesh_failure_penalty,
+					topic_params.mesh_failure_penalty_decay);
+				topic_stats.invalid_message_deliveries = decay(
+					topic_stats.invalid_message_deliveries,
+					topic_params.invalid_message_deliveries_decay);
+			}
+		}
+	}
+
+	// Returns the statistics of a connected peer for a topic that has parameters.
+	fn topic_stats(&mut self, peer_id: &PeerId, topic: &Topic) -> Option<&mut TopicStats> {
+		if !self.params.topics.contains_key(topic) {
+			return None;
+		}
+
+		self.peers.get_mut(peer_id)
+			.map(|stats| stats.topics.entry(topic.clone()).or_insert_with(TopicStats::default))
+	}
+}
+
+#[inline]
+fn is_decay(decay: f64) -> bool {
+	decay > 0.0 && decay < 1.0
+}
+
+#[inline]
+fn secs(duration: Duration) -> f64 {
+	duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{PeerScore, PeerScoreParams, TopicScoreParams};
+	use libp2p_swarm::PeerId;
+	use protocol::{MessageId, Topic};
+	use std::time::Duration;
+
+	// Scoring on a single topic, where only the parameter being tested has a weight.
+	fn params(topic_params: TopicScoreParams) -> PeerScoreParams {
+		let mut params = PeerScoreParams {
+			decay_interval: Duration::from_secs(3600),
+			.. PeerScoreParams::default()
+		};
+		params.topics.insert(Topic::new("topic"), TopicScoreParams {
+			topic_weight: 1.0,
+			.. topic_params
+		});
+		params
+	}
+
+	fn no_weights() -> TopicScoreParams {
+		TopicScoreParams {
+			time_in_mesh_weight: 0.0,
+			first_message_deliveries_weight: 0.0,
+			mesh_message_deliveries_weight: 0.0,
+			mesh_failure_penalty_weight: 0.0,
+			invalid_message_deliveries_weight: 0.0,
+			.. TopicScoreParams::default()
+		}
+	}
+
+	#[test]
+	fn first_message_deliveries() {
+		let mut score = PeerScore::new(params(TopicScoreParams {
+			first_message_deliveries_weight: 2.0,
+			first_message_deliveries_cap: 3.0,
+			.. no_weights()
+		}));
+		let peer_id = PeerId::from_public_key(&[1]);
+		let topics = [Topic::new("topic")];
+
+		// Unknown peers aren't tracked.
+		score.first_delivery(&peer_id, &MessageId(vec![0]), &topics);
+		assert_eq!(score.score(&peer_id), 0.0);
+
+		score.add_peer(&peer_id);
+		for n in 1 .. 5 {
+			score.first_delivery(&peer_id, &MessageId(vec![n]), &topics);
+		}
+		assert_eq!(score.score(&peer_id), 6.0);
+
+		// Only the topics with parameters count.
+		let other = PeerId::from_public_key(&[2]);
+		score.add_peer(&other);
+		score.first_delivery(&other, &MessageId(vec![9]), &[Topic::new("other")]);
+		assert_eq!(score.score(&other), 0.0);
+	}
+
+	#[test]
+	fn mesh_message_deliveries_deficit() {
+		let mut score = PeerScore::new(params(TopicScoreParams {
+			mesh_message_deliveries_weight: -1.0,
+			mesh_message_deliveries_threshold: 4.0,
+			mesh_message_deliveries_activation: Duration::new(0, 0),
+			mesh_failure_penalty_weight: -2.0,
+			.. no_weights()
+		}));
+		let peer_id = PeerId::from_public_key(&[1]);
+		let topic = Topic::new("topic");
+		score.add_peer(&peer_id);
+
+		// Outside of the mesh, the deficit doesn't count.
+		assert_eq!(score.score(&peer_id), 0.0);
+
+		score.graft(&peer_id, &topic);
+		assert_eq!(score.score(&peer_id), -16.0);
+		score.first_delivery(&peer_id, &MessageId(vec![1]), &[topic.clone()]);
+		assert_eq!(score.score(&peer_id), -9.0);
+
+		// The deficit is remembered when the peer leaves the mesh.
+		score.prune(&peer_id, &topic);
+		assert_eq!(score.score(&peer_id), -18.0);
+	}
+
+	#[test]
+	fn duplicate_deliveries() {
+		let mut score = PeerScore::new(params(TopicScoreParams {
+			mesh_message_deliveries_weight: -1.0,
+			mesh_message_deliveries_threshold: 2.0,
+			mesh_message_deliveries_activation: Duration::new(0, 0),
+			mesh_message_deliveries_window: Duration::from_secs(60),
+			.. no_weights()
+		}));
+		let first = PeerId::from_public_key(&[1]);
+		let second = PeerId::from_public_key(&[2]);
+		let topics = [Topic::new("topic")];
+		for peer_id in &[&first, &second] {
+			score.add_peer(peer_id);
+			score.graft(peer_id, &topics[0]);
+		}
+
+		let id = MessageId(vec![1]);
+		score.first_delivery(&first, &id, &topics);
+		score.duplicate_delivery(&second, &id, &topics);
+		// Delivering the same message twice is only credited once.
+		score.duplicate_delivery(&second, &id, &topics);
+		assert_eq!(score.score(&first), -1.0);
+		assert_eq!(score.score(&second), -1.0);
+	}
+
+	#[test]
+	fn duplicate_penalty() {
+		let mut score = PeerScore::new(PeerScoreParams {
+			duplicate_deliveries_weight: -0.5,
+			duplicate_deliveries_threshold: 2.0,
+			.. params(no_weights())
+		});
+		let first = PeerId::from_public_key(&[1]);
+		let second = PeerId::from_public_key(&[2]);
+		let topics = [Topic::new("topic")];
+		score.add_peer(&first);
+		score.add_peer(&second);
+
+		for n in 0 .. 5 {
+			let id = MessageId(vec![n]);
+			score.first_delivery(&first, &id, &topics);
+			score.duplicate_delivery(&second, &id, &topics);
+			// Delivering the same message several times counts each time.
+			score.duplicate_delivery(&second, &id, &topics);
+		}
+
+		// The first two duplicates are tolerated.
+		assert_eq!(score.score(&first), 0.0);
+		assert_eq!(score.score(&second), -4.0);
+	}
+
+	#[test]
+	fn invalid_deliveries_and_penalties() {
+		let mut score = PeerScore::new(params(TopicScoreParams {
+			invalid_message_deliveries_weight: -1.0,
+			.. no_weights()
+		}));
+		let peer_id = PeerId::from_public_key(&[1]);
+		score.add_peer(&peer_id);
+
+		score.invalid_delivery(&peer_id, &[Topic::new("topic")]);
+		score.invalid_delivery(&peer_id, &[Topic::new("topic")]);
+		assert_eq!(score.score(&peer_id), -4.0);
+
+		score.add_penalty(&peer_id, 2);
+		assert_eq!(score.score(&peer_id), -4.0 - 4.0 * 10.0);
+	}
+
+	#[test]
+	fn decay_and_retention() {
+		let mut score = PeerScore::new(PeerScoreParams {
+			decay_interval: Duration::from_millis(1),
+			.. params(TopicScoreParams {
+				invalid_message_deliveries_weight: -1.0,
+				.. no_weights()
+			})
+		});
+		let peer_id = PeerId::from_public_key(&[1]);
+		score.add_peer(&peer_id);
+		score.invalid_delivery(&peer_id, &[Topic::new("topic")]);
+
+		// A negative score is kept after disconnecting.
+		score.remove_peer(&peer_id);
+		score.add_peer(&peer_id);
+		assert_eq!(score.score(&peer_id), -1.0);
+
+		::std::thread::sleep(Duration::from_millis(20));
+		score.refresh();
+		assert_eq!(score.score(&peer_id), 0.0);
+	}
+
+	#[test]
+	#[should_panic]
+	fn positive_penalty_weight() {
+		PeerScore::new(PeerScoreParams {
+			behaviour_penalty_weight: 1.0,
+			.. PeerScoreParams::default()
+		});
+	}
+}
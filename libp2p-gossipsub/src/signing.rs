@@ -0,0 +1,132 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the signing of the messages with the identity key of their source, and the
+//! verification of the signatures.
+//!
+//! The source signs the `signed_data()` of the message, and adds the signature and its public
+//! key to the message. Since the peer IDs are hashes of the public keys, the public key is
+//! always included, and the receivers check that it matches the peer ID of the source.
+
+use futures::Future;
+use libp2p_keys::{PublicKey, Signer, SigningError};
+use libp2p_swarm::PeerId;
+use protocol::GossipsubMessage;
+
+/// How the signatures of the received messages are checked.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SignaturePolicy {
+	/// The messages without a valid signature are rejected. This is the default, like in
+	/// go-libp2p.
+	Strict,
+	/// The unsigned messages are accepted. The messages with an invalid signature are still
+	/// rejected.
+	Permissive,
+}
+
+impl Default for SignaturePolicy {
+	#[inline]
+	fn default() -> SignaturePolicy {
+		SignaturePolicy::Strict
+	}
+}
+
+/// Signs a message with the identity key of its source, and produces the signed message.
+pub fn sign_message(signer: &Signer, mut message: GossipsubMessage)
+					-> Box<Future<Item = GossipsubMessage, Error = SigningError> + Send>
+{
+	let key = signer.public().into_protobuf_encoding();
+	let future = signer.sign(&message.signed_data()).map(move |signature| {
+		message.signature = Some(signature);
+		message.key = Some(key);
+		message
+	});
+	Box::new(future)
+}
+
+/// Returns true if the signature of a message is acceptable according to `policy`.
+pub fn verify_message(message: &GossipsubMessage, policy: SignaturePolicy) -> bool {
+	let signature = match message.signature {
+		Some(ref signature) => signature,
+		None => return policy == SignaturePolicy::Permissive,
+	};
+	let key = match message.key {
+		Some(ref key) => key,
+		None => return false,
+	};
+
+	if PeerId::from_public_key(key) != message.source {
+		return false;
+	}
+
+	match PublicKey::from_protobuf_encoding(key) {
+		Ok(public_key) => public_key.verify(&message.signed_data(), signature),
+		Err(_) => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{sign_message, verify_message, SignaturePolicy};
+	use futures::Future;
+	use libp2p_keys::Keypair;
+	use libp2p_swarm::PeerId;
+	use protocol::{GossipsubMessage, Topic};
+
+	fn message(keypair: &Keypair) -> GossipsubMessage {
+		GossipsubMessage {
+			source: PeerId::from_public_key(&keypair.public().into_protobuf_encoding()),
+			data: b"hello".to_vec(),
+			sequence_number: vec![1],
+			topics: vec![Topic::new("topic")],
+			signature: None,
+			key: None,
+		}
+	}
+
+	#[test]
+	fn sign_and_verify() {
+		let keypair = Keypair::generate_ed25519().unwrap();
+		let mut message = sign_message(&keypair, message(&keypair)).wait().unwrap();
+		assert!(verify_message(&message, SignaturePolicy::Strict));
+
+		message.data = b"tampered".to_vec();
+		assert!(!verify_message(&message, SignaturePolicy::Strict));
+		assert!(!verify_message(&message, SignaturePolicy::Permissive));
+	}
+
+	#[test]
+	fn unsigned() {
+		let keypair = Keypair::generate_ed25519().unwrap();
+		let message = message(&keypair);
+		assert!(!verify_message(&message, SignaturePolicy::Strict));
+		assert!(verify_message(&message, SignaturePolicy::Permissive));
+	}
+
+	#[test]
+	fn key_of_another_peer() {
+		let keypair = Keypair::generate_ed25519().unwrap();
+		let other = Keypair::generate_ed25519().unwrap();
+
+		// The message is correctly signed by `other`, but claims to come from `keypair`.
+		let message = sign_message(&other, message(&keypair)).wait().unwrap();
+		assert!(!verify_message(&message, SignaturePolicy::Strict));
+	}
+}
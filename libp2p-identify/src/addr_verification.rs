@@ -0,0 +1,330 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Implementation of the `/ipfs/id/dialback/1.0.0` protocol, which allows a node to ask a remote
+//! to dial one of its candidate external addresses, in order to verify that this address is
+//! actually reachable before advertising it with the identify protocol.
+//!
+//! The dialer of the substream sends the address to verify, the listener tries to dial it with
+//! its own transport, and answers whether it succeeded. The substream is then closed.
+//!
+//! To protect against the listener being used to dial arbitrary third-party nodes, the listener
+//! only dials addresses whose first component (usually the IP address) is the same as the one of
+//! the address the request came from. Other requests are answered negatively.
+//!
+//! If a timeout is configured with `with_dial_timeout`, a dial that doesn't finish in time is
+//! answered negatively as well, as if the address was unreachable. The timeout is measured by a
+//! `Clock`, usually a tokio-core `Remote`.
+
+use bytes::{Bytes, BytesMut};
+use futures::{future, Future, IntoFuture, Sink, Stream};
+use libp2p_swarm::{ConnectionUpgrade, Endpoint, Transport};
+use libp2p_swarm::time::Clock;
+use multiaddr::Multiaddr;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::iter;
+use std::time::Duration;
+use tokio_core::reactor::Remote;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::codec::Framed;
+use varint::VarintCodec;
+
+use bytes_to_multiaddr;
+
+/// Prototype for an upgrade to the address verification protocol.
+///
+/// The transport is used when the remote asks us to dial back one of its addresses.
+#[derive(Debug, Clone)]
+pub struct AddrVerification<T, K = Remote> {
+	transport: T,
+	// Maximum duration of a dial back, and the clock that measures it.
+	dial_timeout: Option<(Duration, K)>,
+}
+
+impl<T> AddrVerification<T> {
+	/// Builds a new `AddrVerification`. The transport is used to dial the addresses that the
+	/// remotes want to verify.
+	#[inline]
+	pub fn new(transport: T) -> AddrVerification<T> {
+		AddrVerification {
+			transport: transport,
+			dial_timeout: None,
+		}
+	}
+}
+
+impl<T, K> AddrVerification<T, K> {
+	/// Answers that the address is unreachable if dialing it takes longer than `timeout`. By
+	/// default, there is no timeout other than the one of the transport.
+	///
+	/// The timeout is measured by `clock`. With a tokio-core `Remote`, the timer runs on its
+	/// event loop.
+	#[inline]
+	pub fn with_dial_timeout<L>(self, timeout: Duration, clock: L) -> AddrVerification<T, L>
+		where L: Clock
+	{
+		AddrVerification {
+			transport: self.transport,
+			dial_timeout: Some((timeout, clock)),
+		}
+	}
+}
+
+/// Output of the address verification upgrade.
+pub enum AddrVerificationOutput<C> {
+	/// We opened the substream. The requester can be used to ask the remote to dial back one of
+	/// our addresses.
+	Requester(AddrVerificationRequester<C>),
+
+	/// The remote opened the substream, and we answered its request.
+	Answered {
+		/// The address that the remote asked us to dial.
+		addr: Multiaddr,
+		/// True if we successfully reached the address.
+		reachable: bool,
+	},
+}
+
+/// Allows asking the remote to dial back an address.
+pub struct AddrVerificationRequester<C> {
+	inner: Framed<C, VarintCodec<Vec<u8>>>,
+}
+
+impl<C> AddrVerificationRequester<C>
+	where C: AsyncRead + AsyncWrite + Send + 'static
+{
+	/// Asks the remote to dial `addr`. The future produces `true` if the remote could reach it.
+	///
+	/// Only after the address has been verified should it be added to the `listen_addrs` of the
+	/// `IdentifyProtocol`.
+	pub fn verify(self, addr: Multiaddr) -> Box<Future<Item = bool, Error = IoError> + Send> {
+		let future = self.inner
+			.send(addr.to_string().into_bytes())
+			.and_then(|socket| socket.into_future().map_err(|(err, _)| err))
+			.and_then(|(msg, _)| match msg {
+				Some(ref msg) if msg.len() == 1 => Ok(msg[0] == 1),
+				Some(_) => Err(IoError::new(IoErrorKind::InvalidData,
+											"invalid address verification response")),
+				None => Err(IoError::new(IoErrorKind::UnexpectedEof,
+										 "remote closed the substream before answering")),
+			});
+
+		Box::new(future) as Box<_>
+	}
+}
+
+impl<C, T, K> ConnectionUpgrade<C> for AddrVerification<T, K>
+	where C: AsyncRead + AsyncWrite + Send + 'static,
+		  T: Transport + Send + 'static,
+		  <T::Dial as IntoFuture>::Future: Send + 'static,
+		  K: Clock + Send + 'static,
+		  K::Delay: Send + 'static
+{
+	type NamesIter = iter::Once<(Bytes, Self::UpgradeIdentifier)>;
+	type UpgradeIdentifier = ();
+	type Output = AddrVerificationOutput<C>;
+	type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
+
+	#[inline]
+	fn protocol_names(&self) -> Self::NamesIter {
+		iter::once((Bytes::from("/ipfs/id/dialback/1.0.0"), ()))
+	}
+
+	fn upgrade(self, socket: C, _: (), ty: Endpoint, remote_addr: &Multiaddr) -> Self::Future {
+		let socket = socket.framed(VarintCodec::default());
+
+		match ty {
+			Endpoint::Dialer => {
+				let requester = AddrVerificationRequester { inner: socket };
+				Box::new(future::ok(AddrVerificationOutput::Requester(requester))) as Box<_>
+			}
+
+			Endpoint::Listener => {
+				let transport = self.transport;
+				let dial_timeout = self.dial_timeout;
+				let remote_addr = remote_addr.clone();
+
+				let future = socket.into_future()
+					.map_err(|(err, _)| err)
+					.and_then(|(msg, socket)| {
+						let msg: BytesMut = match msg {
+							Some(msg) => msg,
+							None => return Err(IoError::new(IoErrorKind::UnexpectedEof,
+															"remote didn't send any address")),
+						};
+
+						Ok((bytes_to_multiaddr(msg.to_vec())?, socket))
+					})
+					.and_then(move |(addr, socket)| {
+						dial_back(transport, &remote_addr, addr.clone(), dial_timeout)
+							.and_then(move |reachable| {
+								socket.send(vec![if reachable { 1 } else { 0 }])
+									.map(move |_| AddrVerificationOutput::Answered {
+										addr: addr,
+										reachable: reachable,
+									})
+							})
+					});
+
+				Box::new(future) as Box<_>
+			}
+		}
+	}
+}
+
+// Tries to dial `addr` and produces whether it succeeded. Refuses to dial addresses that don't
+// belong to the same host as `remote_addr`. If `timeout` is `Some`, produces `false` if the dial
+// doesn't finish in time.
+fn dial_back<T, K>(transport: T, remote_addr: &Multiaddr, addr: Multiaddr,
+				   timeout: Option<(Duration, K)>)
+				   -> Box<Future<Item = bool, Error = IoError> + Send>
+	where T: Transport + 'static,
+		  <T::Dial as IntoFuture>::Future: Send + 'static,
+		  K: Clock,
+		  K::Delay: Send + 'static
+{
+	if remote_addr.iter().next().is_none() || remote_addr.iter().next() != addr.iter().next() {
+		return Box::new(future::ok(false)) as Box<_>;
+	}
+
+	let dial = match transport.dial(addr) {
+		// We only care about whether the connection succeeds. It is dropped immediately.
+		Ok(dial) => dial.into_future().then(|result| Ok(result.is_ok())),
+		Err(_) => return Box::new(future::ok(false)) as Box<_>,
+	};
+
+	let (timeout, clock) = match timeout {
+		Some(timeout) => timeout,
+		None => return Box::new(dial) as Box<_>,
+	};
+
+	// If the timer fails, for example because the event loop is gone, we consider that the dial
+	// timed out.
+	let timer = clock.delay(timeout).then(|_| Ok(false));
+	Box::new(dial.select(timer).map(|(reachable, _)| reachable).map_err(|(err, _)| err)) as Box<_>
+}
+
+#[cfg(test)]
+mod tests {
+	extern crate libp2p_swarm_test;
+	extern crate libp2p_tcp_transport;
+	extern crate tokio_core;
+
+	use self::libp2p_swarm_test::MockClock;
+	use self::libp2p_tcp_transport::TcpConfig;
+	use self::tokio_core::net::TcpStream;
+	use self::tokio_core::reactor::Core;
+	use {AddrVerification, AddrVerificationOutput};
+	use super::dial_back;
+	use futures::{future, stream, Async, Future, Stream};
+	use libp2p_swarm::Transport;
+	use multiaddr::Multiaddr;
+	use std::io::Error as IoError;
+	use std::time::Duration;
+
+	// Transport whose dials never finish.
+	#[derive(Debug, Clone)]
+	struct BlackHole;
+
+	impl Transport for BlackHole {
+		type RawConn = TcpStream;
+		type Listener = stream::Empty<(Self::ListenerUpgrade, Multiaddr), IoError>;
+		type ListenerUpgrade = future::Empty<TcpStream, IoError>;
+		type Dial = future::Empty<TcpStream, IoError>;
+
+		fn listen_on(self, addr: Multiaddr)
+					 -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)>
+		{
+			Err((self, addr))
+		}
+
+		fn dial(self, _: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+			Ok(future::empty())
+		}
+
+		fn nat_traversal(&self, _: &Multiaddr, _: &Multiaddr) -> Option<Multiaddr> {
+			None
+		}
+	}
+
+	#[test]
+	fn verify_reachable_addr() {
+		let mut core = Core::new().unwrap();
+		let tcp = TcpConfig::new(core.handle());
+		let with_proto = tcp.clone().with_upgrade(AddrVerification::new(tcp.clone()));
+
+		// The address we want to verify. The listener is kept alive but never polled, which is
+		// enough for the operating system to accept the connection.
+		let (_to_verify_listener, to_verify) = tcp.clone()
+			.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+			.unwrap();
+
+		let (server, addr) = with_proto.clone()
+			.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+			.unwrap();
+		let server = server.into_future()
+			.map_err(|(err, _)| err)
+			.and_then(|(n, _)| n.unwrap().0)
+			.map(|out| match out {
+				AddrVerificationOutput::Answered { reachable, .. } => reachable,
+				_ => panic!(),
+			});
+
+		let dialer = with_proto.dial(addr)
+			.unwrap()
+			.and_then(move |out| match out {
+				AddrVerificationOutput::Requester(requester) => requester.verify(to_verify),
+				_ => panic!(),
+			});
+
+		let (client_result, server_result) = core.run(dialer.join(server)).unwrap();
+		assert!(client_result);
+		assert!(server_result);
+	}
+
+	#[test]
+	fn dial_back_times_out() {
+		let clock = MockClock::new();
+		let remote_addr = "/ip4/127.0.0.1/tcp/1000".parse::<Multiaddr>().unwrap();
+		let addr = "/ip4/127.0.0.1/tcp/2000".parse::<Multiaddr>().unwrap();
+
+		let timeout = Some((Duration::from_secs(30), clock.clone()));
+		let mut dial = dial_back(BlackHole, &remote_addr, addr, timeout);
+		let reachable = future::poll_fn(|| {
+			assert_eq!(dial.poll().unwrap(), Async::NotReady);
+			clock.advance(Duration::from_secs(29));
+			assert_eq!(dial.poll().unwrap(), Async::NotReady);
+			clock.advance(Duration::from_secs(1));
+			dial.poll()
+		}).wait().unwrap();
+		assert!(!reachable);
+	}
+
+	#[test]
+	fn dial_back_times_out_on_event_loop() {
+		let mut core = Core::new().unwrap();
+		let remote_addr = "/ip4/127.0.0.1/tcp/1000".parse::<Multiaddr>().unwrap();
+		let addr = "/ip4/127.0.0.1/tcp/2000".parse::<Multiaddr>().unwrap();
+
+		let timeout = Some((Duration::from_millis(50), core.remote()));
+		let reachable = core.run(dial_back(BlackHole, &remote_addr, addr, timeout)).unwrap();
+		assert!(!reachable);
+	}
+}
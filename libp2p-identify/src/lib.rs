@@ -23,6 +23,22 @@
 //!
 //! When two nodes connect to each other, the listening half sends a message to the dialing half,
 //! indicating the information, and then the protocol stops.
+//!
+//! This crate also contains the `AddrVerification` upgrade, which can be used to ask a remote to
+//! dial back one of our candidate external addresses. An address that the remote couldn't reach
+//! shouldn't be added to the `listen_addrs` that we report.
+//!
+//! # Compatibility
+//!
+//! Nodes running different versions of this crate can talk to each other. The fields that have
+//! been added to the message over time (such as `metadata`) are optional, and the fields that a
+//! node doesn't know about are kept in `IdentifyInfo::unknown_fields` and written back by
+//! `IdentifyInfo::to_bytes()`, so that relaying or caching the information of a newer node
+//! doesn't lose anything.
+//!
+//! A newer node may also report listen addresses that use protocols that we don't support yet.
+//! With `Compatibility::Strict`, such a message is rejected entirely. With
+//! `Compatibility::Lenient`, which is the default, these addresses are skipped instead.
 
 extern crate bytes;
 extern crate futures;
@@ -30,23 +46,34 @@ extern crate multiaddr;
 extern crate libp2p_peerstore;
 extern crate libp2p_swarm;
 extern crate protobuf;
+extern crate tokio_core;
 extern crate tokio_io;
 extern crate varint;
 
-use bytes::{Bytes, BytesMut};
+use bytes::Bytes;
 use futures::{Future, Stream, Sink};
 use libp2p_swarm::{ConnectionUpgrade, Endpoint};
 use multiaddr::Multiaddr;
+use protobuf::{CodedInputStream, CodedOutputStream};
 use protobuf::Message as ProtobufMessage;
 use protobuf::core::parse_from_bytes as protobuf_parse_from_bytes;
 use protobuf::repeated::RepeatedField;
+use std::collections::BTreeMap;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::iter;
+use std::str::FromStr;
 use tokio_io::{AsyncRead, AsyncWrite};
 use varint::VarintCodec;
 
+pub use self::addr_verification::{AddrVerification, AddrVerificationOutput};
+pub use self::addr_verification::AddrVerificationRequester;
+
+mod addr_verification;
 mod structs_proto;
 
+/// Maximum total size, in bytes, of the keys and values of the metadata of a node.
+pub const MAX_METADATA_SIZE: usize = 1024;
+
 /// Prototype for an upgrade to the identity protocol.
 #[derive(Debug, Clone)]
 pub struct IdentifyProtocol {
@@ -59,8 +86,37 @@ pub struct IdentifyProtocol {
 	pub agent_version: String,
 	/// Addresses that we are listening on.
 	pub listen_addrs: Vec<Multiaddr>,
-	/// Protocols supported by us.
+	/// Protocols supported by us, as returned by `SwarmController::supported_protocols()`. The
+	/// remotes that requested a protocol we don't support can find out what to use instead.
 	pub protocols: Vec<String>,
+	/// Application-specific key-value pairs to report to the remote, such as a shard id or a
+	/// region.
+	///
+	/// The entries are sent in the order of their keys. Once the total size of the keys and
+	/// values would go over `MAX_METADATA_SIZE`, the remaining entries are not sent.
+	pub metadata: BTreeMap<String, Vec<u8>>,
+	/// How to handle the parts of the message of the remote that we don't understand.
+	pub compatibility: Compatibility,
+}
+
+/// How the dialer handles a message that contains data it doesn't understand, for example
+/// because the remote runs a newer version of the protocol.
+///
+/// Fields that are unknown to the protobuf schema are always accepted and preserved. This only
+/// concerns the fields that are known but whose content can't be parsed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Compatibility {
+	/// Listen addresses that can't be parsed are skipped.
+	Lenient,
+	/// Any listen address that can't be parsed makes the whole message invalid.
+	Strict,
+}
+
+impl Default for Compatibility {
+	#[inline]
+	fn default() -> Compatibility {
+		Compatibility::Lenient
+	}
 }
 
 /// Information sent from the listener to the dialer.
@@ -79,15 +135,108 @@ pub struct IdentifyInfo {
 	pub observed_addr: Multiaddr,
 	/// Protocols supported by the remote.
 	pub protocols: Vec<String>,
+	/// Application-specific key-value pairs reported by the remote.
+	pub metadata: BTreeMap<String, Vec<u8>>,
+	/// Serialized fields of the message that this version of the protocol doesn't know about.
+	/// They are written back by `to_bytes()`.
+	pub unknown_fields: Vec<u8>,
+}
+
+impl IdentifyInfo {
+	/// Parses the protobuf message sent by the listener.
+	pub fn from_bytes(bytes: &[u8], compatibility: Compatibility)
+					  -> Result<IdentifyInfo, IoError>
+	{
+		let mut msg = protobuf_parse_from_bytes::<structs_proto::Identify>(bytes)
+			.map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+
+		let mut listen_addrs = Vec::new();
+		for addr in msg.take_listenAddrs().into_iter() {
+			match bytes_to_multiaddr(addr) {
+				Ok(addr) => listen_addrs.push(addr),
+				Err(_) if compatibility == Compatibility::Lenient => (),
+				Err(err) => return Err(err),
+			}
+		}
+
+		let observed_addr = bytes_to_multiaddr(msg.take_observedAddr())?;
+		let metadata = metadata_from_proto(msg.take_metadata());
+
+		let unknown_fields = {
+			let mut out = Vec::new();
+			{
+				let mut stream = CodedOutputStream::vec(&mut out);
+				stream.write_unknown_fields(msg.get_unknown_fields())
+					.and_then(|()| stream.flush())
+					.expect("writing to a Vec never fails");
+			}
+			out
+		};
+
+		Ok(IdentifyInfo {
+			public_key: msg.take_publicKey(),
+			protocol_version: msg.take_protocolVersion(),
+			agent_version: msg.take_agentVersion(),
+			listen_addrs: listen_addrs,
+			observed_addr: observed_addr,
+			protocols: msg.take_protocols().into_vec(),
+			metadata: metadata,
+			unknown_fields: unknown_fields,
+		})
+	}
+
+	/// Serializes the information into the protobuf message sent by the listener, including the
+	/// unknown fields.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let listen_addrs = self.listen_addrs
+							   .iter()
+							   .map(|addr| addr.to_string().into_bytes())
+							   .collect();
+
+		let mut message = structs_proto::Identify::new();
+		message.set_agentVersion(self.agent_version.clone());
+		message.set_protocolVersion(self.protocol_version.clone());
+		message.set_publicKey(self.public_key.clone());
+		message.set_listenAddrs(listen_addrs);
+		message.set_observedAddr(self.observed_addr.to_string().into_bytes());
+		message.set_protocols(RepeatedField::from_vec(self.protocols.clone()));
+		message.set_metadata(metadata_to_proto(self.metadata.clone()));
+
+		// The unknown fields have been produced by `from_bytes`, therefore merging them can only
+		// fail if the user modified them.
+		if !self.unknown_fields.is_empty() {
+			let mut stream = CodedInputStream::from_bytes(&self.unknown_fields);
+			if message.merge_from(&mut stream).is_err() {
+				message.mut_unknown_fields().clear();
+			}
+		}
+
+		message.write_to_bytes().expect("writing protobuf failed ; should never happen")
+	}
+
+	/// Returns the value of the metadata entry `key`, if it is valid UTF-8.
+	#[inline]
+	pub fn metadata_str(&self, key: &str) -> Option<&str> {
+		self.metadata.get(key).and_then(|value| std::str::from_utf8(value).ok())
+	}
+
+	/// Parses the value of the metadata entry `key`. Returns `None` if the entry doesn't exist,
+	/// isn't valid UTF-8 or can't be parsed.
+	#[inline]
+	pub fn metadata_parse<T>(&self, key: &str) -> Option<T>
+		where T: FromStr
+	{
+		self.metadata_str(key).and_then(|value| value.parse().ok())
+	}
 }
 
 impl<C> ConnectionUpgrade<C> for IdentifyProtocol
-    where C: AsyncRead + AsyncWrite + 'static
+    where C: AsyncRead + AsyncWrite + Send + 'static
 {
 	type NamesIter = iter::Once<(Bytes, Self::UpgradeIdentifier)>;
 	type UpgradeIdentifier = ();
 	type Output = Option<IdentifyInfo>;
-	type Future = Box<Future<Item = Self::Output, Error = IoError>>;
+	type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
 
 	#[inline]
 	fn protocol_names(&self) -> Self::NamesIter {
@@ -99,11 +248,12 @@ impl<C> ConnectionUpgrade<C> for IdentifyProtocol
 
 		match ty {
 			Endpoint::Dialer => {
+				let compatibility = self.compatibility;
 				let future = socket.into_future()
 				                   .map(|(msg, _)| msg)
 				                   .map_err(|(err, _)| err)
-				                   .and_then(|msg| if let Some(msg) = msg {
-					Ok(Some(parse_proto_msg(msg)?))
+				                   .and_then(move |msg| if let Some(msg) = msg {
+					Ok(Some(IdentifyInfo::from_bytes(&msg, compatibility)?))
 				} else {
 					Ok(None)
 				});
@@ -112,22 +262,19 @@ impl<C> ConnectionUpgrade<C> for IdentifyProtocol
 			}
 
 			Endpoint::Listener => {
-				let listen_addrs = self.listen_addrs
-				                       .into_iter()
-				                       .map(|addr| addr.to_string().into_bytes())
-				                       .collect();
-
-				let mut message = structs_proto::Identify::new();
-				message.set_agentVersion(self.agent_version);
-				message.set_protocolVersion(self.protocol_version);
-				message.set_publicKey(self.public_key);
-				message.set_listenAddrs(listen_addrs);
-				message.set_observedAddr(remote_addr.to_string().into_bytes());
-				message.set_protocols(RepeatedField::from_vec(self.protocols));
-
-				let bytes = message.write_to_bytes()
-					.expect("writing protobuf failed ; should never happen");
-				
+				let info = IdentifyInfo {
+					public_key: self.public_key,
+					protocol_version: self.protocol_version,
+					agent_version: self.agent_version,
+					listen_addrs: self.listen_addrs,
+					observed_addr: remote_addr.clone(),
+					protocols: self.protocols,
+					metadata: self.metadata,
+					unknown_fields: Vec::new(),
+				};
+
+				let bytes = info.to_bytes();
+
 				// On the server side, after sending the information to the client we make the
 				// future produce a `None`. If we were on the client side, this would contain the
 				// information received by the server.
@@ -138,35 +285,47 @@ impl<C> ConnectionUpgrade<C> for IdentifyProtocol
 	}
 }
 
-// Turns a protobuf message into an `IdentifyInfo`. If something bad happens, turn it into
-// an `IoError`.
-fn parse_proto_msg(msg: BytesMut) -> Result<IdentifyInfo, IoError> {
-	match protobuf_parse_from_bytes::<structs_proto::Identify>(&msg) {
-		Ok(mut msg) => {
-			let listen_addrs = {
-				let mut addrs = Vec::new();
-				for addr in msg.take_listenAddrs().into_iter() {
-					addrs.push(bytes_to_multiaddr(addr)?);
-				}
-				addrs
-			};
-
-			let observed_addr = bytes_to_multiaddr(msg.take_observedAddr())?;
-
-			Ok(IdentifyInfo {
-				public_key: msg.take_publicKey(),
-				protocol_version: msg.take_protocolVersion(),
-				agent_version: msg.take_agentVersion(),
-				listen_addrs: listen_addrs,
-				observed_addr: observed_addr,
-				protocols: msg.take_protocols().into_vec(),
-			})
+// Turns the metadata into its protobuf representation. Stops at the first entry that would go
+// over `MAX_METADATA_SIZE`.
+fn metadata_to_proto(metadata: BTreeMap<String, Vec<u8>>)
+					 -> RepeatedField<structs_proto::Metadata>
+{
+	let mut total_size = 0;
+	let mut out = RepeatedField::new();
+
+	for (key, value) in metadata {
+		total_size += key.len() + value.len();
+		if total_size > MAX_METADATA_SIZE {
+			break;
 		}
 
-		Err(err) => {
-			Err(IoError::new(IoErrorKind::InvalidData, err))
+		let mut entry = structs_proto::Metadata::new();
+		entry.set_key(key);
+		entry.set_value(value);
+		out.push(entry);
+	}
+
+	out
+}
+
+// Turns the protobuf representation of the metadata into a map. The entries after the first one
+// that goes over `MAX_METADATA_SIZE` are ignored.
+fn metadata_from_proto(metadata: RepeatedField<structs_proto::Metadata>)
+					   -> BTreeMap<String, Vec<u8>>
+{
+	let mut total_size = 0;
+	let mut out = BTreeMap::new();
+
+	for mut entry in metadata.into_iter() {
+		total_size += entry.get_key().len() + entry.get_value().len();
+		if total_size > MAX_METADATA_SIZE {
+			break;
 		}
+
+		out.insert(entry.take_key(), entry.take_value());
 	}
+
+	out
 }
 
 // Turn a `Vec<u8>` into a `Multiaddr`. If something bad happens, turn it into an `IoError`.
@@ -183,29 +342,31 @@ fn bytes_to_multiaddr(bytes: Vec<u8>) -> Result<Multiaddr, IoError> {
 
 #[cfg(test)]
 mod tests {
-	extern crate libp2p_tcp_transport;
+	extern crate libp2p_memory_transport;
 	extern crate tokio_core;
 
-	use self::libp2p_tcp_transport::TcpConfig;
+	use self::libp2p_memory_transport::MemoryTransport;
 	use self::tokio_core::reactor::Core;
-	use IdentifyProtocol;
+	use {Compatibility, IdentifyInfo, IdentifyProtocol};
 	use futures::{IntoFuture, Future, Stream};
 	use libp2p_swarm::Transport;
+	use multiaddr::Multiaddr;
 
 	#[test]
 	fn basic() {
 		let mut core = Core::new().unwrap();
-		let tcp = TcpConfig::new(core.handle());
-		let with_proto = tcp.with_upgrade(IdentifyProtocol {
+		let with_proto = MemoryTransport.with_upgrade(IdentifyProtocol {
 			public_key: vec![1, 2, 3, 4],
 			protocol_version: "ipfs/1.0.0".to_owned(),
 			agent_version: "agent/version".to_owned(),
 			listen_addrs: vec!["/ip4/5.6.7.8/tcp/12345".parse().unwrap()],
 			protocols: vec!["ping".to_owned(), "kad".to_owned()],
+			metadata: vec![("shard".to_owned(), b"12".to_vec())].into_iter().collect(),
+			compatibility: Compatibility::Strict,
 		});
 
 		let (server, addr) = with_proto.clone()
-		                  		       .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+		                  		       .listen_on("/memory/0".parse().unwrap())
 		                       		   .unwrap();
 		let server = server.into_future()
 		                   .map_err(|(err, _)| err)
@@ -218,5 +379,62 @@ mod tests {
 		assert!(should_be_empty.is_none());
 		let recv = recv.unwrap();
 		assert_eq!(recv.public_key, &[1, 2, 3, 4]);
+		assert_eq!(recv.metadata_parse::<u32>("shard"), Some(12));
+		assert!(recv.metadata_str("region").is_none());
+	}
+
+	// Encodes a length-delimited protobuf field by hand, in order to build messages that follow
+	// older or newer versions of the schema.
+	fn field(number: u8, value: &[u8]) -> Vec<u8> {
+		assert!(number < 16 && value.len() < 128);
+		let mut out = vec![(number << 3) | 2, value.len() as u8];
+		out.extend_from_slice(value);
+		out
+	}
+
+	#[test]
+	fn older_schema() {
+		// A message without the metadata, like the ones sent by older nodes.
+		let mut bytes = field(1, &[1, 2, 3]);
+		bytes.extend(field(2, b"/ip4/1.2.3.4/tcp/5"));
+		bytes.extend(field(4, b"/ip4/6.7.8.9/tcp/10"));
+		bytes.extend(field(5, b"ipfs/1.0.0"));
+
+		let info = IdentifyInfo::from_bytes(&bytes, Compatibility::Strict).unwrap();
+		assert_eq!(info.public_key, &[1, 2, 3]);
+		assert_eq!(info.listen_addrs, vec!["/ip4/1.2.3.4/tcp/5".parse::<Multiaddr>().unwrap()]);
+		assert_eq!(info.protocol_version, "ipfs/1.0.0");
+		assert!(info.metadata.is_empty());
+		assert!(info.unknown_fields.is_empty());
+	}
+
+	#[test]
+	fn newer_schema_preserves_unknown_fields() {
+		let mut bytes = field(1, &[1, 2, 3]);
+		bytes.extend(field(4, b"/ip4/6.7.8.9/tcp/10"));
+		// Field 9 doesn't exist in our version of the schema.
+		let unknown = field(9, b"future");
+		bytes.extend_from_slice(&unknown);
+
+		let info = IdentifyInfo::from_bytes(&bytes, Compatibility::Strict).unwrap();
+		assert_eq!(info.public_key, &[1, 2, 3]);
+		assert_eq!(info.unknown_fields, unknown);
+
+		let reencoded = info.to_bytes();
+		assert!(reencoded.ends_with(&unknown));
+		let info2 = IdentifyInfo::from_bytes(&reencoded, Compatibility::Strict).unwrap();
+		assert_eq!(info2.unknown_fields, unknown);
+		assert_eq!(info2.observed_addr, info.observed_addr);
+	}
+
+	#[test]
+	fn unsupported_listen_addr() {
+		let mut bytes = field(2, b"/ip4/1.2.3.4/tcp/5");
+		bytes.extend(field(2, b"/future-transport/1"));
+		bytes.extend(field(4, b"/ip4/6.7.8.9/tcp/10"));
+
+		assert!(IdentifyInfo::from_bytes(&bytes, Compatibility::Strict).is_err());
+		let info = IdentifyInfo::from_bytes(&bytes, Compatibility::Lenient).unwrap();
+		assert_eq!(info.listen_addrs, vec!["/ip4/1.2.3.4/tcp/5".parse::<Multiaddr>().unwrap()]);
 	}
 }
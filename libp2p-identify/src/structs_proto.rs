@@ -30,6 +30,7 @@ pub struct Identify {
     listenAddrs: ::protobuf::RepeatedField<::std::vec::Vec<u8>>,
     observedAddr: ::protobuf::SingularField<::std::vec::Vec<u8>>,
     protocols: ::protobuf::RepeatedField<::std::string::String>,
+    metadata: ::protobuf::RepeatedField<Metadata>,
     // special fields
     unknown_fields: ::protobuf::UnknownFields,
     cached_size: ::protobuf::CachedSize,
@@ -294,10 +295,48 @@ impl Identify {
     fn mut_protocols_for_reflect(&mut self) -> &mut ::protobuf::RepeatedField<::std::string::String> {
         &mut self.protocols
     }
+
+    // repeated .Metadata metadata = 100;
+
+    pub fn clear_metadata(&mut self) {
+        self.metadata.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_metadata(&mut self, v: ::protobuf::RepeatedField<Metadata>) {
+        self.metadata = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_metadata(&mut self) -> &mut ::protobuf::RepeatedField<Metadata> {
+        &mut self.metadata
+    }
+
+    // Take field
+    pub fn take_metadata(&mut self) -> ::protobuf::RepeatedField<Metadata> {
+        ::std::mem::replace(&mut self.metadata, ::protobuf::RepeatedField::new())
+    }
+
+    pub fn get_metadata(&self) -> &[Metadata] {
+        &self.metadata
+    }
+
+    fn get_metadata_for_reflect(&self) -> &::protobuf::RepeatedField<Metadata> {
+        &self.metadata
+    }
+
+    fn mut_metadata_for_reflect(&mut self) -> &mut ::protobuf::RepeatedField<Metadata> {
+        &mut self.metadata
+    }
 }
 
 impl ::protobuf::Message for Identify {
     fn is_initialized(&self) -> bool {
+        for v in &self.metadata {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
         true
     }
 
@@ -323,6 +362,9 @@ impl ::protobuf::Message for Identify {
                 3 => {
                     ::protobuf::rt::read_repeated_string_into(wire_type, is, &mut self.protocols)?;
                 },
+                100 => {
+                    ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.metadata)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -353,6 +395,10 @@ impl ::protobuf::Message for Identify {
         for value in &self.protocols {
             my_size += ::protobuf::rt::string_size(3, &value);
         };
+        for value in &self.metadata {
+            let len = value.compute_size();
+            my_size += 2 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        };
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -377,6 +423,11 @@ impl ::protobuf::Message for Identify {
         for v in &self.protocols {
             os.write_string(3, &v)?;
         };
+        for v in &self.metadata {
+            os.write_tag(100, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        };
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -451,6 +502,11 @@ impl ::protobuf::MessageStatic for Identify {
                     Identify::get_protocols_for_reflect,
                     Identify::mut_protocols_for_reflect,
                 ));
+                fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Metadata>>(
+                    "metadata",
+                    Identify::get_metadata_for_reflect,
+                    Identify::mut_metadata_for_reflect,
+                ));
                 ::protobuf::reflect::MessageDescriptor::new::<Identify>(
                     "Identify",
                     fields,
@@ -469,6 +525,7 @@ impl ::protobuf::Clear for Identify {
         self.clear_listenAddrs();
         self.clear_observedAddr();
         self.clear_protocols();
+        self.clear_metadata();
         self.unknown_fields.clear();
     }
 }
@@ -485,51 +542,301 @@ impl ::protobuf::reflect::ProtobufValue for Identify {
     }
 }
 
+#[derive(PartialEq,Clone,Default)]
+pub struct Metadata {
+    // message fields
+    key: ::protobuf::SingularField<::std::string::String>,
+    value: ::protobuf::SingularField<::std::vec::Vec<u8>>,
+    // special fields
+    unknown_fields: ::protobuf::UnknownFields,
+    cached_size: ::protobuf::CachedSize,
+}
+
+// see codegen.rs for the explanation why impl Sync explicitly
+unsafe impl ::std::marker::Sync for Metadata {}
+
+impl Metadata {
+    pub fn new() -> Metadata {
+        ::std::default::Default::default()
+    }
+
+    pub fn default_instance() -> &'static Metadata {
+        static mut instance: ::protobuf::lazy::Lazy<Metadata> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const Metadata,
+        };
+        unsafe {
+            instance.get(Metadata::new)
+        }
+    }
+
+    // optional string key = 1;
+
+    pub fn clear_key(&mut self) {
+        self.key.clear();
+    }
+
+    pub fn has_key(&self) -> bool {
+        self.key.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_key(&mut self, v: ::std::string::String) {
+        self.key = ::protobuf::SingularField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_key(&mut self) -> &mut ::std::string::String {
+        if self.key.is_none() {
+            self.key.set_default();
+        }
+        self.key.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_key(&mut self) -> ::std::string::String {
+        self.key.take().unwrap_or_else(|| ::std::string::String::new())
+    }
+
+    pub fn get_key(&self) -> &str {
+        match self.key.as_ref() {
+            Some(v) => &v,
+            None => "",
+        }
+    }
+
+    fn get_key_for_reflect(&self) -> &::protobuf::SingularField<::std::string::String> {
+        &self.key
+    }
+
+    fn mut_key_for_reflect(&mut self) -> &mut ::protobuf::SingularField<::std::string::String> {
+        &mut self.key
+    }
+
+    // optional bytes value = 2;
+
+    pub fn clear_value(&mut self) {
+        self.value.clear();
+    }
+
+    pub fn has_value(&self) -> bool {
+        self.value.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_value(&mut self, v: ::std::vec::Vec<u8>) {
+        self.value = ::protobuf::SingularField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_value(&mut self) -> &mut ::std::vec::Vec<u8> {
+        if self.value.is_none() {
+            self.value.set_default();
+        }
+        self.value.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_value(&mut self) -> ::std::vec::Vec<u8> {
+        self.value.take().unwrap_or_else(|| ::std::vec::Vec::new())
+    }
+
+    pub fn get_value(&self) -> &[u8] {
+        match self.value.as_ref() {
+            Some(v) => &v,
+            None => &[],
+        }
+    }
+
+    fn get_value_for_reflect(&self) -> &::protobuf::SingularField<::std::vec::Vec<u8>> {
+        &self.value
+    }
+
+    fn mut_value_for_reflect(&mut self) -> &mut ::protobuf::SingularField<::std::vec::Vec<u8>> {
+        &mut self.value
+    }
+}
+
+impl ::protobuf::Message for Metadata {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    ::protobuf::rt::read_singular_string_into(wire_type, is, &mut self.key)?;
+                },
+                2 => {
+                    ::protobuf::rt::read_singular_bytes_into(wire_type, is, &mut self.value)?;
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        if let Some(ref v) = self.key.as_ref() {
+            my_size += ::protobuf::rt::string_size(1, &v);
+        }
+        if let Some(ref v) = self.value.as_ref() {
+            my_size += ::protobuf::rt::bytes_size(2, &v);
+        }
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream) -> ::protobuf::ProtobufResult<()> {
+        if let Some(ref v) = self.key.as_ref() {
+            os.write_string(1, &v)?;
+        }
+        if let Some(ref v) = self.value.as_ref() {
+            os.write_bytes(2, &v)?;
+        }
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self as &::std::any::Any
+    }
+    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
+        self as &mut ::std::any::Any
+    }
+    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        ::protobuf::MessageStatic::descriptor_static(None::<Self>)
+    }
+}
+
+impl ::protobuf::MessageStatic for Metadata {
+    fn new() -> Metadata {
+        Metadata::new()
+    }
+
+    fn descriptor_static(_: ::std::option::Option<Metadata>) -> &'static ::protobuf::reflect::MessageDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
+                    "key",
+                    Metadata::get_key_for_reflect,
+                    Metadata::mut_key_for_reflect,
+                ));
+                fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeBytes>(
+                    "value",
+                    Metadata::get_value_for_reflect,
+                    Metadata::mut_value_for_reflect,
+                ));
+                ::protobuf::reflect::MessageDescriptor::new::<Metadata>(
+                    "Metadata",
+                    fields,
+                    file_descriptor_proto()
+                )
+            })
+        }
+    }
+}
+
+impl ::protobuf::Clear for Metadata {
+    fn clear(&mut self) {
+        self.clear_key();
+        self.clear_value();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for Metadata {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for Metadata {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    }
+}
+
 static file_descriptor_proto_data: &'static [u8] = b"\
-    \n\rstructs.proto\"\xda\x01\n\x08Identify\x12(\n\x0fprotocolVersion\x18\
+    \n\rstructs.proto\"\x81\x02\n\x08Identify\x12(\n\x0fprotocolVersion\x18\
     \x05\x20\x01(\tR\x0fprotocolVersion\x12\"\n\x0cagentVersion\x18\x06\x20\
-    \x01(\tR\x0cagentVersion\x12\x1c\n\tpublicKey\x18\x01\x20\x01(\x0cR\tpub\
-    licKey\x12\x20\n\x0blistenAddrs\x18\x02\x20\x03(\x0cR\x0blistenAddrs\x12\
-    \"\n\x0cobservedAddr\x18\x04\x20\x01(\x0cR\x0cobservedAddr\x12\x1c\n\tpr\
-    otocols\x18\x03\x20\x03(\tR\tprotocolsJ\xc2\t\n\x06\x12\x04\0\0\x16\x01\
-    \n\n\n\x02\x04\0\x12\x04\0\0\x16\x01\n\n\n\x03\x04\0\x01\x12\x03\0\x08\
-    \x10\nX\n\x04\x04\0\x02\0\x12\x03\x02\x02&\x1a8\x20protocolVersion\x20de\
-    termines\x20compatibility\x20between\x20peers\n\"\x11\x20e.g.\x20ipfs/1.\
-    0.0\n\n\x0c\n\x05\x04\0\x02\0\x04\x12\x03\x02\x02\n\n\x0c\n\x05\x04\0\
-    \x02\0\x05\x12\x03\x02\x0b\x11\n\x0c\n\x05\x04\0\x02\0\x01\x12\x03\x02\
-    \x12!\n\x0c\n\x05\x04\0\x02\0\x03\x12\x03\x02$%\n\x9f\x01\n\x04\x04\0\
-    \x02\x01\x12\x03\x06\x02#\x1a|\x20agentVersion\x20is\x20like\x20a\x20Use\
-    rAgent\x20string\x20in\x20browsers,\x20or\x20client\x20version\x20in\x20\
-    bittorrent\n\x20includes\x20the\x20client\x20name\x20and\x20client.\n\"\
-    \x14\x20e.g.\x20go-ipfs/0.1.0\n\n\x0c\n\x05\x04\0\x02\x01\x04\x12\x03\
-    \x06\x02\n\n\x0c\n\x05\x04\0\x02\x01\x05\x12\x03\x06\x0b\x11\n\x0c\n\x05\
-    \x04\0\x02\x01\x01\x12\x03\x06\x12\x1e\n\x0c\n\x05\x04\0\x02\x01\x03\x12\
-    \x03\x06!\"\n\xe3\x01\n\x04\x04\0\x02\x02\x12\x03\x0b\x02\x1f\x1a\xd5\
-    \x01\x20publicKey\x20is\x20this\x20node's\x20public\x20key\x20(which\x20\
-    also\x20gives\x20its\x20node.ID)\n\x20-\x20may\x20not\x20need\x20to\x20b\
-    e\x20sent,\x20as\x20secure\x20channel\x20implies\x20it\x20has\x20been\
-    \x20sent.\n\x20-\x20then\x20again,\x20if\x20we\x20change\x20/\x20disable\
-    \x20secure\x20channel,\x20may\x20still\x20want\x20it.\n\n\x0c\n\x05\x04\
-    \0\x02\x02\x04\x12\x03\x0b\x02\n\n\x0c\n\x05\x04\0\x02\x02\x05\x12\x03\
-    \x0b\x0b\x10\n\x0c\n\x05\x04\0\x02\x02\x01\x12\x03\x0b\x11\x1a\n\x0c\n\
-    \x05\x04\0\x02\x02\x03\x12\x03\x0b\x1d\x1e\n]\n\x04\x04\0\x02\x03\x12\
-    \x03\x0e\x02!\x1aP\x20listenAddrs\x20are\x20the\x20multiaddrs\x20the\x20\
-    sender\x20node\x20listens\x20for\x20open\x20connections\x20on\n\n\x0c\n\
-    \x05\x04\0\x02\x03\x04\x12\x03\x0e\x02\n\n\x0c\n\x05\x04\0\x02\x03\x05\
-    \x12\x03\x0e\x0b\x10\n\x0c\n\x05\x04\0\x02\x03\x01\x12\x03\x0e\x11\x1c\n\
+    \x01(\tR\x0cagentVersion\x12\x1c\n\tpublicKey\x18\x01\x20\x01(\x0cR\tpu\
+    blicKey\x12\x20\n\x0blistenAddrs\x18\x02\x20\x03(\x0cR\x0blistenAddrs\
+    \x12\"\n\x0cobservedAddr\x18\x04\x20\x01(\x0cR\x0cobservedAddr\x12\x1c\
+    \n\tprotocols\x18\x03\x20\x03(\tR\tprotocols\x12%\n\x08metadata\x18d\
+    \x20\x03(\x0b2\t.MetadataR\x08metadata\"2\n\x08Metadata\x12\x10\n\x03ke\
+    y\x18\x01\x20\x01(\tR\x03key\x12\x14\n\x05value\x18\x02\x20\x01(\x0cR\
+    \x05valueJ\xc2\t\n\x06\x12\x04\0\0\x16\x01\n\n\n\x02\x04\0\x12\x04\0\0\
+    \x16\x01\n\n\n\x03\x04\0\x01\x12\x03\0\x08\x10\nX\n\x04\x04\0\x02\0\x12\
+    \x03\x02\x02&\x1a8\x20protocolVersion\x20determines\x20compatibility\
+    \x20between\x20peers\n\"\x11\x20e.g.\x20ipfs/1.0.0\n\n\x0c\n\x05\x04\0\
+    \x02\0\x04\x12\x03\x02\x02\n\n\x0c\n\x05\x04\0\x02\0\x05\x12\x03\x02\
+    \x0b\x11\n\x0c\n\x05\x04\0\x02\0\x01\x12\x03\x02\x12!\n\x0c\n\x05\x04\0\
+    \x02\0\x03\x12\x03\x02$%\n\x9f\x01\n\x04\x04\0\x02\x01\x12\x03\x06\x02#\
+    \x1a|\x20agentVersion\x20is\x20like\x20a\x20UserAgent\x20string\x20in\
+    \x20browsers,\x20or\x20client\x20version\x20in\x20bittorrent\n\x20inclu\
+    des\x20the\x20client\x20name\x20and\x20client.\n\"\x14\x20e.g.\x20go-ip\
+    fs/0.1.0\n\n\x0c\n\x05\x04\0\x02\x01\x04\x12\x03\x06\x02\n\n\x0c\n\x05\
+    \x04\0\x02\x01\x05\x12\x03\x06\x0b\x11\n\x0c\n\x05\x04\0\x02\x01\x01\
+    \x12\x03\x06\x12\x1e\n\x0c\n\x05\x04\0\x02\x01\x03\x12\x03\x06!\"\n\xe3\
+    \x01\n\x04\x04\0\x02\x02\x12\x03\x0b\x02\x1f\x1a\xd5\x01\x20publicKey\
+    \x20is\x20this\x20node's\x20public\x20key\x20(which\x20also\x20gives\
+    \x20its\x20node.ID)\n\x20-\x20may\x20not\x20need\x20to\x20be\x20sent,\
+    \x20as\x20secure\x20channel\x20implies\x20it\x20has\x20been\x20sent.\n\
+    \x20-\x20then\x20again,\x20if\x20we\x20change\x20/\x20disable\x20secure\
+    \x20channel,\x20may\x20still\x20want\x20it.\n\n\x0c\n\x05\x04\0\x02\x02\
+    \x04\x12\x03\x0b\x02\n\n\x0c\n\x05\x04\0\x02\x02\x05\x12\x03\x0b\x0b\
+    \x10\n\x0c\n\x05\x04\0\x02\x02\x01\x12\x03\x0b\x11\x1a\n\x0c\n\x05\x04\
+    \0\x02\x02\x03\x12\x03\x0b\x1d\x1e\n]\n\x04\x04\0\x02\x03\x12\x03\x0e\
+    \x02!\x1aP\x20listenAddrs\x20are\x20the\x20multiaddrs\x20the\x20sender\
+    \x20node\x20listens\x20for\x20open\x20connections\x20on\n\n\x0c\n\x05\
+    \x04\0\x02\x03\x04\x12\x03\x0e\x02\n\n\x0c\n\x05\x04\0\x02\x03\x05\x12\
+    \x03\x0e\x0b\x10\n\x0c\n\x05\x04\0\x02\x03\x01\x12\x03\x0e\x11\x1c\n\
     \x0c\n\x05\x04\0\x02\x03\x03\x12\x03\x0e\x1f\x20\n\x81\x02\n\x04\x04\0\
-    \x02\x04\x12\x03\x13\x02\"\x1a\xf3\x01\x20oservedAddr\x20is\x20the\x20mu\
-    ltiaddr\x20of\x20the\x20remote\x20endpoint\x20that\x20the\x20sender\x20n\
-    ode\x20perceives\n\x20this\x20is\x20useful\x20information\x20to\x20conve\
-    y\x20to\x20the\x20other\x20side,\x20as\x20it\x20helps\x20the\x20remote\
-    \x20endpoint\n\x20determine\x20whether\x20its\x20connection\x20to\x20the\
-    \x20local\x20peer\x20goes\x20through\x20NAT.\n\n\x0c\n\x05\x04\0\x02\x04\
-    \x04\x12\x03\x13\x02\n\n\x0c\n\x05\x04\0\x02\x04\x05\x12\x03\x13\x0b\x10\
-    \n\x0c\n\x05\x04\0\x02\x04\x01\x12\x03\x13\x11\x1d\n\x0c\n\x05\x04\0\x02\
-    \x04\x03\x12\x03\x13\x20!\n\x0b\n\x04\x04\0\x02\x05\x12\x03\x15\x02\x20\
-    \n\x0c\n\x05\x04\0\x02\x05\x04\x12\x03\x15\x02\n\n\x0c\n\x05\x04\0\x02\
-    \x05\x05\x12\x03\x15\x0b\x11\n\x0c\n\x05\x04\0\x02\x05\x01\x12\x03\x15\
-    \x12\x1b\n\x0c\n\x05\x04\0\x02\x05\x03\x12\x03\x15\x1e\x1f\
+    \x02\x04\x12\x03\x13\x02\"\x1a\xf3\x01\x20oservedAddr\x20is\x20the\x20m\
+    ultiaddr\x20of\x20the\x20remote\x20endpoint\x20that\x20the\x20sender\
+    \x20node\x20perceives\n\x20this\x20is\x20useful\x20information\x20to\
+    \x20convey\x20to\x20the\x20other\x20side,\x20as\x20it\x20helps\x20the\
+    \x20remote\x20endpoint\n\x20determine\x20whether\x20its\x20connection\
+    \x20to\x20the\x20local\x20peer\x20goes\x20through\x20NAT.\n\n\x0c\n\x05\
+    \x04\0\x02\x04\x04\x12\x03\x13\x02\n\n\x0c\n\x05\x04\0\x02\x04\x05\x12\
+    \x03\x13\x0b\x10\n\x0c\n\x05\x04\0\x02\x04\x01\x12\x03\x13\x11\x1d\n\
+    \x0c\n\x05\x04\0\x02\x04\x03\x12\x03\x13\x20!\n\x0b\n\x04\x04\0\x02\x05\
+    \x12\x03\x15\x02\x20\n\x0c\n\x05\x04\0\x02\x05\x04\x12\x03\x15\x02\n\n\
+    \x0c\n\x05\x04\0\x02\x05\x05\x12\x03\x15\x0b\x11\n\x0c\n\x05\x04\0\x02\
+    \x05\x01\x12\x03\x15\x12\x1b\n\x0c\n\x05\x04\0\x02\x05\x03\x12\x03\x15\
+    \x1e\x1f\
 ";
 
 static mut file_descriptor_proto_lazy: ::protobuf::lazy::Lazy<::protobuf::descriptor::FileDescriptorProto> = ::protobuf::lazy::Lazy {
@@ -0,0 +1,166 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Minimal decoding of the varints and protobuf messages, without allocating.
+
+use error::Error;
+
+/// Decodes an unsigned varint at the start of `data`. Returns the value and the number of bytes
+/// that have been read.
+pub fn decode_varint(data: &[u8]) -> Result<(u64, usize), Error> {
+	let mut value = 0u64;
+	for (n, &byte) in data.iter().enumerate() {
+		if n >= 10 || (n == 9 && byte > 1) {
+			return Err(Error::InvalidVarint);
+		}
+
+		value |= u64::from(byte & 0x7f) << (7 * n);
+		if byte & 0x80 == 0 {
+			return Ok((value, n + 1));
+		}
+	}
+
+	Err(Error::UnexpectedEof)
+}
+
+/// Encodes `value` as an unsigned varint in `buf`. Returns the part of `buf` that has been
+/// written.
+pub fn encode_varint(mut value: u64, buf: &mut [u8; 10]) -> &[u8] {
+	let mut len = 0;
+	loop {
+		let byte = (value & 0x7f) as u8;
+		value >>= 7;
+		if value == 0 {
+			buf[len] = byte;
+			return &buf[.. len + 1];
+		}
+
+		buf[len] = byte | 0x80;
+		len += 1;
+	}
+}
+
+/// Splits `data` after `len` bytes.
+#[inline]
+pub fn split(data: &[u8], len: u64) -> Result<(&[u8], &[u8]), Error> {
+	if len > data.len() as u64 {
+		return Err(Error::UnexpectedEof);
+	}
+
+	Ok(data.split_at(len as usize))
+}
+
+/// Value of a field of a protobuf message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FieldValue<'a> {
+	Varint(u64),
+	Bytes(&'a [u8]),
+}
+
+/// Iterator over the fields of a protobuf message. Produces the field number and its value.
+///
+/// Fixed-size fields are skipped, as none of the messages that we decode use them.
+pub struct Fields<'a> {
+	data: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+	#[inline]
+	pub fn new(data: &'a [u8]) -> Fields<'a> {
+		Fields { data: data }
+	}
+
+	fn next_field(&mut self) -> Result<Option<(u64, FieldValue<'a>)>, Error> {
+		loop {
+			if self.data.is_empty() {
+				return Ok(None);
+			}
+
+			let (key, len) = decode_varint(self.data)?;
+			let rest = &self.data[len..];
+			let field = key >> 3;
+
+			let (value, rest) = match key & 0x7 {
+				0 => {
+					let (value, len) = decode_varint(rest)?;
+					(Some(FieldValue::Varint(value)), &rest[len..])
+				},
+				1 => (None, split(rest, 8)?.1),
+				2 => {
+					let (value_len, len) = decode_varint(rest)?;
+					let (value, rest) = split(&rest[len..], value_len)?;
+					(Some(FieldValue::Bytes(value)), rest)
+				},
+				5 => (None, split(rest, 4)?.1),
+				_ => return Err(Error::InvalidProtobuf),
+			};
+
+			self.data = rest;
+			if let Some(value) = value {
+				return Ok(Some((field, value)));
+			}
+		}
+	}
+}
+
+impl<'a> Iterator for Fields<'a> {
+	type Item = Result<(u64, FieldValue<'a>), Error>;
+
+	#[inline]
+	fn next(&mut self) -> Option<Self::Item> {
+		match self.next_field() {
+			Ok(Some(field)) => Some(Ok(field)),
+			Ok(None) => None,
+			Err(err) => {
+				// Stop after the first error, as we don't know where the next field starts.
+				self.data = &[];
+				Some(Err(err))
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{decode_varint, encode_varint, FieldValue, Fields};
+	use error::Error;
+	use std::vec::Vec;
+
+	#[test]
+	fn varints() {
+		assert_eq!(decode_varint(&[0x05]), Ok((5, 1)));
+		assert_eq!(decode_varint(&[0xac, 0x02, 0xff]), Ok((300, 2)));
+		assert_eq!(decode_varint(&[0x80]), Err(Error::UnexpectedEof));
+		assert_eq!(decode_varint(&[0xff; 11]), Err(Error::InvalidVarint));
+		assert_eq!(encode_varint(300, &mut [0; 10]), &[0xac, 0x02]);
+		assert_eq!(decode_varint(encode_varint(::core::u64::MAX, &mut [0; 10])),
+				   Ok((::core::u64::MAX, 10)));
+	}
+
+	#[test]
+	fn fields() {
+		let data = [0x08, 0x01, 0x15, 0, 0, 0, 0, 0x12, 0x02, 0xaa, 0xbb];
+		let fields = Fields::new(&data).collect::<Result<Vec<_>, _>>().unwrap();
+		assert_eq!(fields, vec![
+			(1, FieldValue::Varint(1)),
+			(2, FieldValue::Bytes(&[0xaa, 0xbb])),
+		]);
+	}
+}
@@ -0,0 +1,78 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use core::fmt;
+
+/// Error that can happen when parsing or verifying identity-related data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+	/// The data ended in the middle of an element.
+	UnexpectedEof,
+	/// A varint is malformed or doesn't fit in 64 bits.
+	InvalidVarint,
+	/// The multihash of a peer ID is malformed.
+	InvalidMultihash,
+	/// The multiaddress contains a protocol that we don't know.
+	UnknownProtocol(u64),
+	/// The protobuf message is malformed or misses a required field.
+	InvalidProtobuf,
+	/// The public key has a type that we don't know.
+	UnknownKeyType(u64),
+	/// The public key of a record doesn't match the expected peer ID.
+	PeerIdMismatch,
+	/// The signature of a record is invalid.
+	InvalidSignature,
+	/// The payload of a signed record doesn't have the expected type.
+	UnexpectedPayloadType,
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Error::UnexpectedEof => write!(f, "unexpected end of data"),
+			Error::InvalidVarint => write!(f, "invalid varint"),
+			Error::InvalidMultihash => write!(f, "invalid multihash"),
+			Error::UnknownProtocol(code) => write!(f, "unknown multiaddr protocol {}", code),
+			Error::InvalidProtobuf => write!(f, "invalid protobuf message"),
+			Error::UnknownKeyType(ty) => write!(f, "unknown public key type {}", ty),
+			Error::PeerIdMismatch => write!(f, "public key doesn't match the peer ID"),
+			Error::InvalidSignature => write!(f, "invalid signature"),
+			Error::UnexpectedPayloadType => write!(f, "unexpected payload type"),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for Error {
+	#[inline]
+	fn description(&self) -> &str {
+		match *self {
+			Error::UnexpectedEof => "unexpected end of data",
+			Error::InvalidVarint => "invalid varint",
+			Error::InvalidMultihash => "invalid multihash",
+			Error::UnknownProtocol(_) => "unknown multiaddr protocol",
+			Error::InvalidProtobuf => "invalid protobuf message",
+			Error::UnknownKeyType(_) => "unknown public key type",
+			Error::PeerIdMismatch => "public key doesn't match the peer ID",
+			Error::InvalidSignature => "invalid signature",
+			Error::UnexpectedPayloadType => "unexpected payload type",
+		}
+	}
+}
@@ -0,0 +1,85 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Handling of the identities of libp2p peers without the standard library.
+//!
+//! This crate contains the parts of libp2p that don't need any I/O: parsing and checking peer
+//! IDs, public keys, multiaddresses and signed records. It is `no_std` and never allocates, which
+//! makes it possible for constrained devices to verify the identities and records of other nodes
+//! even if they can't run the transports of `libp2p-swarm`.
+//!
+//! All the types of this crate borrow the data they have been decoded from. For example a
+//! `PeerIdRef` is a reference to the bytes of a multihash, and is the borrowed equivalent of the
+//! `PeerId` of `libp2p-peerstore`.
+//!
+//! The `std` feature, enabled by default, only adds implementations of the `std::error::Error`
+//! trait.
+//!
+//! # Example
+//!
+//! ```
+//! use libp2p_identity_core::{MultiaddrRef, PeerIdBuf, PublicKeyRef};
+//!
+//! // `PublicKey` protobuf message of an ed25519 key.
+//! let public_key = [0x08, 0x01, 0x12, 0x01, 0x2a];
+//! let peer_id = PeerIdBuf::from_public_key(&public_key);
+//!
+//! // Binary representation of `/ip4/127.0.0.1/p2p/<peer_id>`.
+//! let mut addr = [0; 42];
+//! addr[.. 8].copy_from_slice(&[0x04, 127, 0, 0, 1, 0xa4, 0x03, 34]);
+//! addr[8 ..].copy_from_slice(peer_id.as_bytes());
+//!
+//! let addr = MultiaddrRef::from_bytes(&addr).unwrap();
+//! let remote = addr.peer_id().unwrap().unwrap();
+//! assert!(PublicKeyRef::from_protobuf(&public_key).unwrap().matches(&remote));
+//! ```
+//!
+//! Checking the signature of a `SignedRecordRef` is delegated to an implementation of the
+//! `SignatureVerifier` trait, as this crate doesn't contain any cryptographic code besides the
+//! SHA2-256 hash used by peer IDs. The `record` module of libp2p-keys provides one, and creates
+//! the signed records with a `Signer`.
+//!
+//! A `PeerRecordRef` is the content of a signed record in which a peer lists the addresses it is
+//! listening on. Use `PeerRecordRef::from_signed_record` to check that the record has been signed
+//! by the peer it is about.
+
+#![no_std]
+
+#[cfg(any(feature = "std", test))]
+#[macro_use]
+extern crate std;
+
+extern crate sha2;
+
+pub use self::error::Error;
+pub use self::multiaddr::{Component, Components, MultiaddrRef, IPFS_CODE, P2P_CODE};
+pub use self::peer_id::{PeerIdBuf, PeerIdRef, IDENTITY_CODE, SHA2_256_CODE};
+pub use self::peer_record::{PeerRecordAddrs, PeerRecordRef};
+pub use self::peer_record::{PEER_RECORD_DOMAIN, PEER_RECORD_PAYLOAD_TYPE};
+pub use self::public_key::{KeyType, PublicKeyRef};
+pub use self::record::{SignatureVerifier, SignedRecordRef};
+
+mod encoding;
+mod error;
+mod multiaddr;
+mod peer_id;
+mod peer_record;
+mod public_key;
+mod record;
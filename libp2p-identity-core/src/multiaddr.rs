@@ -0,0 +1,196 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use encoding::{decode_varint, split};
+use error::Error;
+use peer_id::PeerIdRef;
+
+/// Multiaddr code of the `/p2p` protocol.
+pub const P2P_CODE: u64 = 420;
+/// Multiaddr code of the `/ipfs` protocol, the former name of `/p2p`.
+pub const IPFS_CODE: u64 = 421;
+
+/// Reference to a multiaddress in its binary representation, whose components have been
+/// validated.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MultiaddrRef<'a> {
+	bytes: &'a [u8],
+}
+
+/// Component of a multiaddress.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Component<'a> {
+	/// Multiaddr code of the protocol.
+	pub code: u64,
+	/// Raw value of the component, without its length prefix.
+	pub value: &'a [u8],
+}
+
+impl<'a> MultiaddrRef<'a> {
+	/// Checks whether `bytes` is a valid binary multiaddress, and if so returns a `MultiaddrRef`
+	/// pointing to it.
+	pub fn from_bytes(bytes: &'a [u8]) -> Result<MultiaddrRef<'a>, Error> {
+		let mut rest = bytes;
+		while !rest.is_empty() {
+			rest = next_component(rest)?.1;
+		}
+
+		Ok(MultiaddrRef { bytes: bytes })
+	}
+
+	/// Returns the binary representation of the multiaddress.
+	#[inline]
+	pub fn as_bytes(&self) -> &'a [u8] {
+		self.bytes
+	}
+
+	/// Returns an iterator to the components of the multiaddress.
+	#[inline]
+	pub fn iter(&self) -> Components<'a> {
+		Components { rest: self.bytes }
+	}
+
+	/// Returns the peer ID of the last `/p2p` or `/ipfs` component, if any.
+	pub fn peer_id(&self) -> Option<Result<PeerIdRef<'a>, Error>> {
+		self.iter()
+			.filter(|c| c.code == P2P_CODE || c.code == IPFS_CODE)
+			.last()
+			.map(|c| PeerIdRef::from_bytes(c.value))
+	}
+}
+
+impl<'a> IntoIterator for MultiaddrRef<'a> {
+	type Item = Component<'a>;
+	type IntoIter = Components<'a>;
+
+	#[inline]
+	fn into_iter(self) -> Components<'a> {
+		self.iter()
+	}
+}
+
+/// Iterator to the components of a `MultiaddrRef`.
+#[derive(Debug, Clone)]
+pub struct Components<'a> {
+	rest: &'a [u8],
+}
+
+impl<'a> Iterator for Components<'a> {
+	type Item = Component<'a>;
+
+	#[inline]
+	fn next(&mut self) -> Option<Component<'a>> {
+		if self.rest.is_empty() {
+			return None;
+		}
+
+		let (component, rest) = next_component(self.rest)
+			.expect("the multiaddress has been validated when building the MultiaddrRef");
+		self.rest = rest;
+		Some(component)
+	}
+}
+
+// Size of the value of a protocol.
+enum ValueSize {
+	Fixed(u64),
+	// The value is prefixed with its length.
+	Variable,
+}
+
+// Returns the size of the value of the protocol whose code is `code`.
+fn value_size(code: u64) -> Result<ValueSize, Error> {
+	match code {
+		// ip4
+		4 => Ok(ValueSize::Fixed(4)),
+		// tcp, udp, dccp, sctp
+		6 | 17 | 33 | 132 => Ok(ValueSize::Fixed(2)),
+		// ip6
+		41 => Ok(ValueSize::Fixed(16)),
+		// onion
+		444 => Ok(ValueSize::Fixed(10)),
+		// dns4, dns6, unix, p2p, ipfs
+		54 | 55 | 400 | P2P_CODE | IPFS_CODE => Ok(ValueSize::Variable),
+		// udt, utp, http, https, quic, ws, wss, p2p-websocket-star, p2p-webrtc-star,
+		// p2p-webrtc-direct, p2p-circuit
+		301 | 302 | 480 | 443 | 460 | 477 | 478 | 479 | 275 | 276 | 290 => {
+			Ok(ValueSize::Fixed(0))
+		},
+		other => Err(Error::UnknownProtocol(other)),
+	}
+}
+
+// Decodes the component at the start of `data`. Returns the component and the remaining data.
+fn next_component(data: &[u8]) -> Result<(Component, &[u8]), Error> {
+	let (code, code_len) = decode_varint(data)?;
+	let rest = &data[code_len..];
+
+	let (value, rest) = match value_size(code)? {
+		ValueSize::Fixed(len) => split(rest, len)?,
+		ValueSize::Variable => {
+			let (len, len_len) = decode_varint(rest)?;
+			split(&rest[len_len..], len)?
+		},
+	};
+
+	let component = Component {
+		code: code,
+		value: value,
+	};
+
+	Ok((component, rest))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Component, MultiaddrRef};
+	use error::Error;
+	use peer_id::PeerIdBuf;
+	use std::vec::Vec;
+
+	#[test]
+	fn components() {
+		// /ip4/127.0.0.1/tcp/80/ws
+		let bytes = [0x04, 127, 0, 0, 1, 0x06, 0, 80, 0xdd, 0x03];
+		let addr = MultiaddrRef::from_bytes(&bytes).unwrap();
+		assert_eq!(addr.iter().collect::<Vec<_>>(), vec![
+			Component { code: 4, value: &[127, 0, 0, 1] },
+			Component { code: 6, value: &[0, 80] },
+			Component { code: 477, value: &[] },
+		]);
+		assert!(addr.peer_id().is_none());
+	}
+
+	#[test]
+	fn peer_id() {
+		let peer_id = PeerIdBuf::from_public_key(&[1, 2, 3]);
+		// /ip4/127.0.0.1/p2p/<peer_id>
+		let mut bytes = vec![0x04, 127, 0, 0, 1, 0xa4, 0x03, 34];
+		bytes.extend_from_slice(peer_id.as_bytes());
+		let addr = MultiaddrRef::from_bytes(&bytes).unwrap();
+		assert_eq!(addr.peer_id(), Some(Ok(peer_id.as_peer_id())));
+	}
+
+	#[test]
+	fn invalid() {
+		assert_eq!(MultiaddrRef::from_bytes(&[0x04, 127, 0]), Err(Error::UnexpectedEof));
+		assert_eq!(MultiaddrRef::from_bytes(&[0x07]), Err(Error::UnknownProtocol(7)));
+	}
+}
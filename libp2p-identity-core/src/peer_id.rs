@@ -0,0 +1,155 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use encoding::{decode_varint, split};
+use error::Error;
+use sha2::{Digest, Sha256};
+
+/// Multihash code of the identity "hash", where the digest is the data itself.
+pub const IDENTITY_CODE: u64 = 0x00;
+/// Multihash code of SHA2-256, which is what `PeerIdBuf::from_public_key` uses.
+pub const SHA2_256_CODE: u64 = 0x12;
+
+/// Reference to the identifier of a peer, which is the multihash of its public key.
+///
+/// This is the borrowed equivalent of the `PeerId` of `libp2p-peerstore`, and uses the same
+/// representation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PeerIdRef<'a> {
+	multihash: &'a [u8],
+	code: u64,
+	digest: &'a [u8],
+}
+
+impl<'a> PeerIdRef<'a> {
+	/// Checks whether `data` is a valid multihash, and if so returns a `PeerIdRef` pointing to it.
+	pub fn from_bytes(data: &'a [u8]) -> Result<PeerIdRef<'a>, Error> {
+		let (code, code_len) = decode_varint(data).map_err(|_| Error::InvalidMultihash)?;
+		let rest = &data[code_len..];
+		let (digest_len, len_len) = decode_varint(rest).map_err(|_| Error::InvalidMultihash)?;
+		let (digest, rest) = split(&rest[len_len..], digest_len)
+			.map_err(|_| Error::InvalidMultihash)?;
+
+		if !rest.is_empty() {
+			return Err(Error::InvalidMultihash);
+		}
+
+		Ok(PeerIdRef {
+			multihash: data,
+			code: code,
+			digest: digest,
+		})
+	}
+
+	/// Returns the raw bytes of the multihash.
+	#[inline]
+	pub fn as_bytes(&self) -> &'a [u8] {
+		self.multihash
+	}
+
+	/// Returns the multihash code of the hash algorithm.
+	#[inline]
+	pub fn hash_code(&self) -> u64 {
+		self.code
+	}
+
+	/// Returns the raw bytes of the hash.
+	#[inline]
+	pub fn digest(&self) -> &'a [u8] {
+		self.digest
+	}
+
+	/// Checks whether `public_key` is the public key of this peer.
+	///
+	/// Always returns `false` if the hash algorithm is neither SHA2-256 nor the identity.
+	pub fn is_public_key(&self, public_key: &[u8]) -> bool {
+		match self.code {
+			SHA2_256_CODE => Sha256::digest(public_key).as_slice() == self.digest,
+			IDENTITY_CODE => public_key == self.digest,
+			_ => false,
+		}
+	}
+}
+
+/// Identifier of a peer stored inline, without allocating.
+///
+/// Only supports SHA2-256 multihashes, which are the ones produced from public keys.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PeerIdBuf {
+	// Code and length of the multihash, followed with the SHA2-256 digest.
+	multihash: [u8; 34],
+}
+
+impl PeerIdBuf {
+	/// Builds the identifier of the peer whose public key is `public_key`.
+	pub fn from_public_key(public_key: &[u8]) -> PeerIdBuf {
+		let mut multihash = [0; 34];
+		multihash[0] = SHA2_256_CODE as u8;
+		multihash[1] = 32;
+		multihash[2..].copy_from_slice(Sha256::digest(public_key).as_slice());
+		PeerIdBuf { multihash: multihash }
+	}
+
+	/// Returns a reference to this identifier.
+	#[inline]
+	pub fn as_peer_id(&self) -> PeerIdRef {
+		PeerIdRef {
+			multihash: &self.multihash,
+			code: SHA2_256_CODE,
+			digest: &self.multihash[2..],
+		}
+	}
+
+	/// Returns the raw bytes of the multihash.
+	#[inline]
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.multihash
+	}
+}
+
+impl ::core::fmt::Debug for PeerIdBuf {
+	#[inline]
+	fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+		self.as_peer_id().fmt(f)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{PeerIdBuf, PeerIdRef, SHA2_256_CODE};
+	use error::Error;
+
+	#[test]
+	fn from_public_key_round_trip() {
+		let peer_id = PeerIdBuf::from_public_key(&[1, 2, 3, 4]);
+		let parsed = PeerIdRef::from_bytes(peer_id.as_bytes()).unwrap();
+		assert_eq!(parsed, peer_id.as_peer_id());
+		assert_eq!(parsed.hash_code(), SHA2_256_CODE);
+		assert!(parsed.is_public_key(&[1, 2, 3, 4]));
+		assert!(!parsed.is_public_key(&[1, 2, 3, 5]));
+	}
+
+	#[test]
+	fn invalid_multihash() {
+		assert_eq!(PeerIdRef::from_bytes(&[0x12, 0x20, 0]), Err(Error::InvalidMultihash));
+		assert_eq!(PeerIdRef::from_bytes(&[0x00, 0x01, 7, 8]), Err(Error::InvalidMultihash));
+		assert!(PeerIdRef::from_bytes(&[0x00, 0x01, 7]).unwrap().is_public_key(&[7]));
+	}
+}
@@ -0,0 +1,218 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use encoding::{FieldValue, Fields};
+use error::Error;
+use multiaddr::MultiaddrRef;
+use peer_id::PeerIdRef;
+use record::{SignatureVerifier, SignedRecordRef};
+
+/// Domain of the signature of the signed records that contain a peer record.
+pub const PEER_RECORD_DOMAIN: &'static [u8] = b"libp2p-peer-record";
+/// Payload type of the signed records that contain a peer record. This is the multicodec
+/// `libp2p-peer-record`.
+pub const PEER_RECORD_PAYLOAD_TYPE: &'static [u8] = &[0x03, 0x01];
+
+/// Reference to a peer record, decoded from a `PeerRecord` protobuf message.
+///
+/// A peer record contains the addresses that a peer is listening on. When it is wrapped in a
+/// `SignedRecordRef` signed by the peer itself, the addresses are certified: they come from the
+/// peer and not from a third party that could have modified them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PeerRecordRef<'a> {
+	peer_id: PeerIdRef<'a>,
+	seq: u64,
+	encoded: &'a [u8],
+}
+
+impl<'a> PeerRecordRef<'a> {
+	/// Decodes a `PeerRecord` protobuf message, without checking any signature.
+	pub fn from_protobuf(encoded: &'a [u8]) -> Result<PeerRecordRef<'a>, Error> {
+		let mut peer_id = None;
+		let mut seq = None;
+
+		for field in Fields::new(encoded) {
+			match field? {
+				(1, FieldValue::Bytes(value)) => peer_id = Some(PeerIdRef::from_bytes(value)?),
+				(2, FieldValue::Varint(value)) => seq = Some(value),
+				(3, FieldValue::Bytes(value)) => {
+					address_info(value)?;
+				},
+				(1, _) | (2, _) | (3, _) => return Err(Error::InvalidProtobuf),
+				_ => (),
+			}
+		}
+
+		match (peer_id, seq) {
+			(Some(peer_id), Some(seq)) => {
+				Ok(PeerRecordRef {
+					peer_id: peer_id,
+					seq: seq,
+					encoded: encoded,
+				})
+			},
+			_ => Err(Error::InvalidProtobuf),
+		}
+	}
+
+	/// Decodes the peer record contained in a signed record, and checks that the signed record
+	/// has been signed by the peer the record is about.
+	pub fn from_signed_record<V>(record: &SignedRecordRef<'a>, verifier: &V)
+								 -> Result<PeerRecordRef<'a>, Error>
+		where V: SignatureVerifier + ?Sized
+	{
+		if record.payload_type() != PEER_RECORD_PAYLOAD_TYPE {
+			return Err(Error::UnexpectedPayloadType);
+		}
+
+		let peer_record = PeerRecordRef::from_protobuf(record.payload_unchecked())?;
+		record.verify(PEER_RECORD_DOMAIN, &peer_record.peer_id, verifier)?;
+		Ok(peer_record)
+	}
+
+	/// Returns the peer the record is about.
+	#[inline]
+	pub fn peer_id(&self) -> PeerIdRef<'a> {
+		self.peer_id
+	}
+
+	/// Returns the sequence number of the record. A record with a higher sequence number
+	/// replaces the previous records of the same peer.
+	#[inline]
+	pub fn seq(&self) -> u64 {
+		self.seq
+	}
+
+	/// Returns an iterator to the addresses of the peer.
+	#[inline]
+	pub fn addrs(&self) -> PeerRecordAddrs<'a> {
+		PeerRecordAddrs { fields: Fields::new(self.encoded) }
+	}
+}
+
+/// Iterator to the addresses of a `PeerRecordRef`.
+pub struct PeerRecordAddrs<'a> {
+	fields: Fields<'a>,
+}
+
+impl<'a> Iterator for PeerRecordAddrs<'a> {
+	type Item = MultiaddrRef<'a>;
+
+	fn next(&mut self) -> Option<MultiaddrRef<'a>> {
+		loop {
+			match self.fields.next() {
+				// The record has been validated when it was decoded, so we can ignore errors.
+				Some(Ok((3, FieldValue::Bytes(value)))) => {
+					if let Ok(addr) = address_info(value) {
+						return Some(addr);
+					}
+				},
+				Some(_) => (),
+				None => return None,
+			}
+		}
+	}
+}
+
+// Decodes an `AddressInfo` protobuf message.
+fn address_info(encoded: &[u8]) -> Result<MultiaddrRef, Error> {
+	let mut addr = None;
+	for field in Fields::new(encoded) {
+		match field? {
+			(1, FieldValue::Bytes(value)) => addr = Some(MultiaddrRef::from_bytes(value)?),
+			(1, _) => return Err(Error::InvalidProtobuf),
+			_ => (),
+		}
+	}
+
+	addr.ok_or(Error::InvalidProtobuf)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::PeerRecordRef;
+	use error::Error;
+	use peer_id::PeerIdBuf;
+	use public_key::PublicKeyRef;
+	use record::{SignatureVerifier, SignedRecordRef};
+	use std::vec::Vec;
+
+	// "Signs" by returning the last byte of the message.
+	struct LastByte;
+	impl SignatureVerifier for LastByte {
+		fn verify(&self, _: &PublicKeyRef, message: &[&[u8]], signature: &[u8]) -> bool {
+			let message = message.concat();
+			signature == &message[message.len() - 1 ..]
+		}
+	}
+
+	const ADDR: [u8; 8] = [0x04, 127, 0, 0, 1, 0x06, 0x0f, 0xa1];
+
+	fn peer_record(peer_id: &PeerIdBuf) -> Vec<u8> {
+		let mut out = vec![0x0a, peer_id.as_bytes().len() as u8];
+		out.extend_from_slice(peer_id.as_bytes());
+		out.extend_from_slice(&[0x10, 0x05, 0x1a, 0x0a, 0x0a, 0x08]);
+		out.extend_from_slice(&ADDR);
+		out
+	}
+
+	fn envelope(public_key: &[u8], payload_type: &[u8], payload: &[u8]) -> Vec<u8> {
+		let mut out = vec![0x0a, public_key.len() as u8];
+		out.extend_from_slice(public_key);
+		out.extend_from_slice(&[0x12, payload_type.len() as u8]);
+		out.extend_from_slice(payload_type);
+		out.extend_from_slice(&[0x1a, payload.len() as u8]);
+		out.extend_from_slice(payload);
+		out.extend_from_slice(&[0x2a, 0x01, payload[payload.len() - 1]]);
+		out
+	}
+
+	#[test]
+	fn valid_record() {
+		let public_key = [0x08, 0x01, 0x12, 0x01, 9];
+		let peer_id = PeerIdBuf::from_public_key(&public_key);
+		let encoded = envelope(&public_key, &[0x03, 0x01], &peer_record(&peer_id));
+
+		let signed = SignedRecordRef::from_protobuf(&encoded).unwrap();
+		let record = PeerRecordRef::from_signed_record(&signed, &LastByte).unwrap();
+		assert_eq!(record.peer_id(), peer_id.as_peer_id());
+		assert_eq!(record.seq(), 5);
+		let addrs = record.addrs().map(|a| a.as_bytes()).collect::<Vec<_>>();
+		assert_eq!(addrs, vec![&ADDR[..]]);
+	}
+
+	#[test]
+	fn invalid_records() {
+		let public_key = [0x08, 0x01, 0x12, 0x01, 9];
+		let peer_id = PeerIdBuf::from_public_key(&public_key);
+		let other = PeerIdBuf::from_public_key(&[0]);
+
+		let encoded = envelope(&public_key, b"t", &peer_record(&peer_id));
+		let signed = SignedRecordRef::from_protobuf(&encoded).unwrap();
+		assert_eq!(PeerRecordRef::from_signed_record(&signed, &LastByte),
+				   Err(Error::UnexpectedPayloadType));
+
+		// The record is about another peer than the one who signed it.
+		let encoded = envelope(&public_key, &[0x03, 0x01], &peer_record(&other));
+		let signed = SignedRecordRef::from_protobuf(&encoded).unwrap();
+		assert_eq!(PeerRecordRef::from_signed_record(&signed, &LastByte),
+				   Err(Error::PeerIdMismatch));
+	}
+}
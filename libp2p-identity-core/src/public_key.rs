@@ -0,0 +1,129 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use encoding::{FieldValue, Fields};
+use error::Error;
+use peer_id::PeerIdRef;
+
+/// Type of a public key, as found in the `PublicKey` protobuf message of libp2p.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum KeyType {
+	/// RSA key, whose data is a DER-encoded `SubjectPublicKeyInfo`.
+	Rsa,
+	/// Ed25519 key, whose data is the 32 bytes of the key.
+	Ed25519,
+	/// Secp256k1 key, whose data is the compressed point.
+	Secp256k1,
+}
+
+impl KeyType {
+	/// Returns the `KeyType` corresponding to the value of the protobuf enum.
+	#[inline]
+	pub fn from_protobuf(value: u64) -> Result<KeyType, Error> {
+		match value {
+			0 => Ok(KeyType::Rsa),
+			1 => Ok(KeyType::Ed25519),
+			2 => Ok(KeyType::Secp256k1),
+			other => Err(Error::UnknownKeyType(other)),
+		}
+	}
+}
+
+/// Reference to a public key decoded from a `PublicKey` protobuf message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PublicKeyRef<'a> {
+	key_type: KeyType,
+	data: &'a [u8],
+	// The whole protobuf message, which is what peer IDs are derived from.
+	encoded: &'a [u8],
+}
+
+impl<'a> PublicKeyRef<'a> {
+	/// Decodes a `PublicKey` protobuf message.
+	pub fn from_protobuf(encoded: &'a [u8]) -> Result<PublicKeyRef<'a>, Error> {
+		let mut key_type = None;
+		let mut data = None;
+
+		for field in Fields::new(encoded) {
+			match field? {
+				(1, FieldValue::Varint(value)) => key_type = Some(KeyType::from_protobuf(value)?),
+				(2, FieldValue::Bytes(value)) => data = Some(value),
+				(1, _) | (2, _) => return Err(Error::InvalidProtobuf),
+				_ => (),
+			}
+		}
+
+		match (key_type, data) {
+			(Some(key_type), Some(data)) => Ok(PublicKeyRef {
+				key_type: key_type,
+				data: data,
+				encoded: encoded,
+			}),
+			_ => Err(Error::InvalidProtobuf),
+		}
+	}
+
+	/// Returns the type of the key.
+	#[inline]
+	pub fn key_type(&self) -> KeyType {
+		self.key_type
+	}
+
+	/// Returns the raw data of the key, whose format depends on the type of the key.
+	#[inline]
+	pub fn data(&self) -> &'a [u8] {
+		self.data
+	}
+
+	/// Returns the protobuf message that the key has been decoded from.
+	#[inline]
+	pub fn encoded(&self) -> &'a [u8] {
+		self.encoded
+	}
+
+	/// Returns true if this is the public key of `peer_id`.
+	#[inline]
+	pub fn matches(&self, peer_id: &PeerIdRef) -> bool {
+		peer_id.is_public_key(self.encoded)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{KeyType, PublicKeyRef};
+	use error::Error;
+	use peer_id::PeerIdBuf;
+
+	#[test]
+	fn decode_public_key() {
+		let encoded = [0x08, 0x01, 0x12, 0x03, 1, 2, 3];
+		let key = PublicKeyRef::from_protobuf(&encoded).unwrap();
+		assert_eq!(key.key_type(), KeyType::Ed25519);
+		assert_eq!(key.data(), &[1, 2, 3]);
+		assert!(key.matches(&PeerIdBuf::from_public_key(&encoded).as_peer_id()));
+	}
+
+	#[test]
+	fn missing_field() {
+		assert_eq!(PublicKeyRef::from_protobuf(&[0x08, 0x01]), Err(Error::InvalidProtobuf));
+		assert_eq!(PublicKeyRef::from_protobuf(&[0x08, 0x07, 0x12, 0x00]),
+				   Err(Error::UnknownKeyType(7)));
+	}
+}
@@ -0,0 +1,179 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use encoding::{encode_varint, FieldValue, Fields};
+use error::Error;
+use peer_id::PeerIdRef;
+use public_key::PublicKeyRef;
+
+/// Checks signatures made with a public key.
+///
+/// This crate doesn't contain any cryptographic code, so that constrained devices can pick an
+/// implementation that fits them and only support the key types that they need.
+pub trait SignatureVerifier {
+	/// Returns true if `signature` is a valid signature made with `key` of the concatenation of
+	/// the slices of `message`.
+	fn verify(&self, key: &PublicKeyRef, message: &[&[u8]], signature: &[u8]) -> bool;
+}
+
+/// Reference to a record signed by a peer, decoded from an `Envelope` protobuf message.
+///
+/// The envelope contains the public key of the signer, the type and the content of the payload,
+/// and the signature. The signature covers the domain of the record (which isn't transmitted and
+/// must be known by the receiver), the type of the payload and the payload, each of them prefixed
+/// with its length as a varint.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SignedRecordRef<'a> {
+	public_key: PublicKeyRef<'a>,
+	payload_type: &'a [u8],
+	payload: &'a [u8],
+	signature: &'a [u8],
+}
+
+impl<'a> SignedRecordRef<'a> {
+	/// Decodes an `Envelope` protobuf message. The signature isn't checked.
+	pub fn from_protobuf(encoded: &'a [u8]) -> Result<SignedRecordRef<'a>, Error> {
+		let mut public_key = None;
+		let mut payload_type = None;
+		let mut payload = None;
+		let mut signature = None;
+
+		for field in Fields::new(encoded) {
+			match field? {
+				(1, FieldValue::Bytes(value)) => {
+					public_key = Some(PublicKeyRef::from_protobuf(value)?)
+				},
+				(2, FieldValue::Bytes(value)) => payload_type = Some(value),
+				(3, FieldValue::Bytes(value)) => payload = Some(value),
+				(5, FieldValue::Bytes(value)) => signature = Some(value),
+				(1, _) | (2, _) | (3, _) | (5, _) => return Err(Error::InvalidProtobuf),
+				_ => (),
+			}
+		}
+
+		match (public_key, payload_type, payload, signature) {
+			(Some(public_key), Some(payload_type), Some(payload), Some(signature)) => {
+				Ok(SignedRecordRef {
+					public_key: public_key,
+					payload_type: payload_type,
+					payload: payload,
+					signature: signature,
+				})
+			},
+			_ => Err(Error::InvalidProtobuf),
+		}
+	}
+
+	/// Returns the public key of the signer.
+	#[inline]
+	pub fn public_key(&self) -> PublicKeyRef<'a> {
+		self.public_key
+	}
+
+	/// Returns the type of the payload, which indicates how to decode it.
+	#[inline]
+	pub fn payload_type(&self) -> &'a [u8] {
+		self.payload_type
+	}
+
+	/// Returns the payload, without checking the signature.
+	#[inline]
+	pub fn payload_unchecked(&self) -> &'a [u8] {
+		self.payload
+	}
+
+	/// Checks that the record has been signed by `peer_id` for the given `domain`. If so, returns
+	/// the payload.
+	pub fn verify<V>(&self, domain: &[u8], peer_id: &PeerIdRef, verifier: &V)
+					 -> Result<&'a [u8], Error>
+		where V: SignatureVerifier + ?Sized
+	{
+		if !self.public_key.matches(peer_id) {
+			return Err(Error::PeerIdMismatch);
+		}
+
+		let mut domain_len = [0; 10];
+		let mut payload_type_len = [0; 10];
+		let mut payload_len = [0; 10];
+		let message = [
+			encode_varint(domain.len() as u64, &mut domain_len),
+			domain,
+			encode_varint(self.payload_type.len() as u64, &mut payload_type_len),
+			self.payload_type,
+			encode_varint(self.payload.len() as u64, &mut payload_len),
+			self.payload,
+		];
+
+		if verifier.verify(&self.public_key, &message, self.signature) {
+			Ok(self.payload)
+		} else {
+			Err(Error::InvalidSignature)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{SignatureVerifier, SignedRecordRef};
+	use error::Error;
+	use peer_id::PeerIdBuf;
+	use public_key::PublicKeyRef;
+	use std::vec::Vec;
+
+	// "Signs" by returning the last byte of the message.
+	struct LastByte;
+	impl SignatureVerifier for LastByte {
+		fn verify(&self, _: &PublicKeyRef, message: &[&[u8]], signature: &[u8]) -> bool {
+			let message = message.concat();
+			signature == &message[message.len() - 1 ..]
+		}
+	}
+
+	fn envelope(public_key: &[u8], signature: u8) -> Vec<u8> {
+		let mut out = vec![0x0a, public_key.len() as u8];
+		out.extend_from_slice(public_key);
+		out.extend_from_slice(&[0x12, 0x01, b't', 0x1a, 0x02, b'h', b'i', 0x2a, 0x01, signature]);
+		out
+	}
+
+	#[test]
+	fn valid_record() {
+		let public_key = [0x08, 0x01, 0x12, 0x01, 9];
+		let peer_id = PeerIdBuf::from_public_key(&public_key);
+		let encoded = envelope(&public_key, b'i');
+		let record = SignedRecordRef::from_protobuf(&encoded).unwrap();
+		assert_eq!(record.payload_type(), b"t");
+		assert_eq!(record.verify(b"domain", &peer_id.as_peer_id(), &LastByte), Ok(&b"hi"[..]));
+	}
+
+	#[test]
+	fn invalid_records() {
+		let public_key = [0x08, 0x01, 0x12, 0x01, 9];
+		let peer_id = PeerIdBuf::from_public_key(&public_key);
+		let other = PeerIdBuf::from_public_key(&[0]);
+
+		let encoded = envelope(&public_key, b'x');
+		let record = SignedRecordRef::from_protobuf(&encoded).unwrap();
+		assert_eq!(record.verify(b"domain", &peer_id.as_peer_id(), &LastByte),
+				   Err(Error::InvalidSignature));
+		assert_eq!(record.verify(b"domain", &other.as_peer_id(), &LastByte),
+				   Err(Error::PeerIdMismatch));
+	}
+}
@@ -0,0 +1,1562 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `Kademlia` struct, which ties the routing table, the queries and the handlers of
+//! the connections together.
+
+use futures::{Async, Future};
+use handler::{KademliaHandler, KademliaHandlerEvent, KademliaHandlerIn};
+use kbucket::{KadKey, KBucketsTable, UpdateOutcome};
+use libp2p_swarm::{PartitionDetector, PartitionEvent, PeerId};
+use libp2p_swarm::time::{self, Instant};
+use multiaddr::Multiaddr;
+use protocol::{KadConnectionType, KadPeer, KadRecord, KadRequestMsg, KadResponseMsg};
+use query::{QueryConfig, QueryState, QueryStatePollOut};
+use rand::{self, ChaChaRng, Rng};
+use record::{NamespacedValidator, RecordValidator};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Error as IoError;
+use std::time::Duration;
+use store::{MemoryRecordStore, RecordStore, StoredProvider, StoredRecord};
+use tokio_core::reactor::{Handle, Timeout};
+
+/// Interval in seconds at which the timeouts of the requests are checked.
+const TICK_SECS: u64 = 1;
+/// Duration in seconds during which the provider records are valid.
+const PROVIDER_TTL_SECS: u64 = 24 * 60 * 60;
+/// Interval in seconds at which the keys we provide are announced again. Also the interval at
+/// which the expired provider records and records are removed.
+const REPROVIDE_INTERVAL_SECS: u64 = 12 * 60 * 60;
+/// Duration in seconds during which the records received from the remotes are kept.
+const RECORD_TTL_SECS: u64 = 36 * 60 * 60;
+/// Interval in seconds at which the buckets are refreshed, once `bootstrap` has been called.
+/// Also the age after which a bucket that hasn't been looked up is refreshed.
+const REFRESH_INTERVAL_SECS: u64 = 10 * 60;
+
+/// Configuration of `Kademlia`.
+///
+/// The default values are the ones of the public IPFS network. Small private networks usually
+/// benefit from shorter timeouts, and may use a lower replication factor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KademliaConfig {
+	/// Maximum number of requests in progress at the same time for a query, also known as α.
+	/// Defaults to 3.
+	pub parallelism: usize,
+	/// Number of entries of each bucket, number of peers returned by the queries and to the
+	/// remotes, and number of peers a record is stored at. Also known as k. Defaults to 20.
+	pub replication_factor: usize,
+	/// Delay after which a query finishes, even if it isn't done. Defaults to 60 seconds.
+	pub query_timeout: Duration,
+	/// Delay after which a request to a peer is considered as failed. Defaults to 10 seconds.
+	pub rpc_timeout: Duration,
+	/// If true, the node performs queries but doesn't serve the DHT: the requests of the remotes
+	/// are rejected, and therefore the remotes never add the node to their routing table.
+	/// Useful for nodes that are behind a NAT or that have few resources. Defaults to false.
+	pub client_mode: bool,
+}
+
+impl Default for KademliaConfig {
+	#[inline]
+	fn default() -> KademliaConfig {
+		KademliaConfig {
+			parallelism: 3,
+			replication_factor: 20,
+			query_timeout: Duration::from_secs(60),
+			rpc_timeout: Duration::from_secs(10),
+			client_mode: false,
+		}
+	}
+}
+
+/// Identifier of a query started by `Kademlia`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct QueryId(u64);
+
+/// Action that the user must perform on behalf of `Kademlia`.
+#[derive(Debug, Clone)]
+pub enum KademliaAction {
+	/// A connection to the peer must be opened. Once it is open, call `inject_connected`. If it
+	/// can't be opened, call `inject_dial_failure`.
+	Dial {
+		/// The peer to connect to.
+		peer_id: PeerId,
+		/// The known addresses of the peer.
+		addrs: Vec<Multiaddr>,
+	},
+	/// The event must be injected in the `KademliaHandler` of the connection to the peer.
+	SendEvent {
+		/// The peer whose handler must receive the event.
+		peer_id: PeerId,
+		/// The event to inject.
+		event: KademliaHandlerIn<QueryId>,
+	},
+	/// An event for the user.
+	GenerateEvent(KademliaEvent),
+}
+
+/// Event produced by `Kademlia` for the user.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KademliaEvent {
+	/// A query started with `find_node` has finished.
+	FindNodeResult {
+		/// Identifier returned by `find_node`.
+		query_id: QueryId,
+		/// The key that was looked up.
+		key: Vec<u8>,
+		/// The closest peers to the key that answered, from the closest to the furthest.
+		closer_peers: Vec<PeerId>,
+	},
+	/// A query started with `bootstrap` has finished.
+	BootstrapResult {
+		/// Identifier returned by `bootstrap`.
+		query_id: QueryId,
+		/// The closest peers to the local node that answered, from the closest to the furthest.
+		closer_peers: Vec<PeerId>,
+	},
+	/// A query started with `get_providers` has finished.
+	GetProvidersResult {
+		/// Identifier returned by `get_providers`.
+		query_id: QueryId,
+		/// The key whose providers were looked up.
+		key: Vec<u8>,
+		/// The providers that were found, with their addresses.
+		providers: Vec<(PeerId, Vec<Multiaddr>)>,
+		/// The closest peers to the key that answered, from the closest to the furthest.
+		closer_peers: Vec<PeerId>,
+	},
+	/// A query started with `get_value` has finished.
+	GetValueResult {
+		/// Identifier returned by `get_value`.
+		query_id: QueryId,
+		/// The key of the record.
+		key: Vec<u8>,
+		/// The best of the values found, as chosen by the `RecordValidator`. `None` if no valid
+		/// record has been found.
+		value: Option<Vec<u8>>,
+		/// All the valid values found, with the peer that sent them.
+		records: Vec<(PeerId, Vec<u8>)>,
+		/// The closest peers to the key that answered, from the closest to the furthest.
+		closer_peers: Vec<PeerId>,
+	},
+	/// A query started with `put_value` has finished.
+	PutValueResult {
+		/// Identifier returned by `put_value`.
+		query_id: QueryId,
+		/// The key of the record.
+		key: Vec<u8>,
+		/// The peers that have accepted the record.
+		stored_at: Vec<PeerId>,
+	},
+	/// The `PartitionDetector` passed to `with_partition_detector` changed its opinion.
+	Partition(PartitionEvent),
+}
+
+/// State of the Kademlia DHT of the local node.
+///
+/// `Kademlia` doesn't open any connection by itself. Instead, `poll` produces `KademliaAction`s
+/// that ask the user to connect to a peer or to pass an event to the `KademliaHandler` of a
+/// connection. In return, the user must report the connections and the events of the handlers
+/// with the `inject_*` methods.
+///
+/// The provider records announced by the remotes are stored in a `RecordStore`, and are given
+/// back to the remotes that look for the providers of a key. The same goes for the records,
+/// which are checked with a `RecordValidator` before being stored.
+pub struct Kademlia {
+	config: KademliaConfig,
+	kbuckets: KBucketsTable<Vec<Multiaddr>>,
+	queries: HashMap<QueryId, QueryInfo>,
+	next_query_id: u64,
+	// Peers we are connected to.
+	connected_peers: HashSet<PeerId>,
+	// Requests waiting for a connection to their peer to be opened.
+	pending_rpcs: Vec<(PeerId, KademliaHandlerIn<QueryId>)>,
+	// Actions to produce from `poll`.
+	queued_actions: VecDeque<KademliaAction>,
+	// Records and providers of keys, including the local node for the keys in `provided_keys`.
+	store: Box<RecordStore>,
+	// Keys that the local node provides.
+	provided_keys: HashSet<Vec<u8>>,
+	// Addresses of the local node, sent to the remotes alongside the provider records.
+	listen_addrs: Vec<Multiaddr>,
+	validator: Box<RecordValidator>,
+	// Records being sent to the closest peers of their key, after a `put_value` query.
+	put_queries: HashMap<QueryId, PutInfo>,
+	// Pings sent to the least recently seen peers of the full buckets, with the time after which
+	// they are considered as failed. If a peer doesn't answer, the pending entry of its bucket
+	// takes its place.
+	pings: HashMap<QueryId, (PeerId, Instant)>,
+	handle: Handle,
+	// Wakes the task up while queries are in progress, so that their requests time out.
+	timer: Option<Timeout>,
+	// Fires when the provided keys must be announced again and the expired records removed.
+	reprovide_timer: Option<Timeout>,
+	// True once `bootstrap` has been called, meaning that the buckets are refreshed periodically.
+	refresh_enabled: bool,
+	// Fires when the buckets must be refreshed.
+	refresh_timer: Option<Timeout>,
+	// Generates the keys that refresh the buckets.
+	rng: Box<Rng + Send>,
+	// Receives the results of the queries, if any.
+	partition: Option<PartitionDetector>,
+}
+
+// A record being sent to the closest peers of its key.
+struct PutInfo {
+	key: Vec<u8>,
+	// Peers that haven't answered yet.
+	pending: HashSet<PeerId>,
+	// Peers that have accepted the record.
+	stored_at: Vec<PeerId>,
+	// The peers that haven't answered at this time are considered as failed.
+	deadline: Instant,
+}
+
+// A query in progress.
+struct QueryInfo {
+	state: QueryState,
+	// Key being looked up.
+	key: Vec<u8>,
+	// Addresses of the peers returned by the remotes during the query.
+	addrs: HashMap<PeerId, Vec<Multiaddr>>,
+	kind: QueryKind,
+}
+
+// What a query is for.
+enum QueryKind {
+	// Started with `find_node`.
+	FindNode,
+	// Started with `bootstrap`. Looks for the closest peers to the local node.
+	Bootstrap,
+	// Refreshes the routing table. Doesn't report anything.
+	Refresh,
+	// Started with `get_providers`. Contains the providers found so far.
+	GetProviders(Vec<(PeerId, Vec<Multiaddr>)>),
+	// Looks for the closest peers to a key we provide, in order to send them `ADD_PROVIDER`.
+	AddProvider,
+	// Started with `get_value`. Contains the valid values found so far, and the number of
+	// values after which the query stops.
+	GetValue {
+		records: Vec<(PeerId, Vec<u8>)>,
+		quorum: usize,
+	},
+	// Started with `put_value`. Looks for the closest peers to the key of the record, in order
+	// to send them `PUT_VALUE`.
+	PutValue(KadRecord),
+}
+
+impl Kademlia {
+	/// Builds a new `Kademlia` with an empty routing table and the default configuration.
+	/// `handle` is used for the timers.
+	#[inline]
+	pub fn new(local_peer_id: PeerId, handle: Handle) -> Kademlia {
+		Kademlia::with_config(local_peer_id, handle, KademliaConfig::default())
+	}
+
+	/// Builds a new `Kademlia` with an empty routing table. `handle` is used for the timers.
+	///
+	/// # Panic
+	///
+	/// Panics if the parallelism or the replication factor is 0.
+	pub fn with_config(local_peer_id: PeerId, handle: Handle, config: KademliaConfig)
+					   -> Kademlia
+	{
+		assert!(config.parallelism > 0, "the parallelism must be at least 1");
+		assert!(config.replication_factor > 0, "the replication factor must be at least 1");
+
+		Kademlia {
+			config: config,
+			kbuckets: KBucketsTable::new(local_peer_id, config.replication_factor),
+			queries: HashMap::new(),
+			next_query_id: 0,
+			connected_peers: HashSet::new(),
+			pending_rpcs: Vec::new(),
+			queued_actions: VecDeque::new(),
+			store: Box::new(MemoryRecordStore::new()),
+			provided_keys: HashSet::new(),
+			listen_addrs: Vec::new(),
+			validator: Box::new(NamespacedValidator::default()),
+			put_queries: HashMap::new(),
+			pings: HashMap::new(),
+			handle: handle,
+			timer: None,
+			reprovide_timer: None,
+			refresh_enabled: false,
+			refresh_timer: None,
+			rng: Box::new(rand::thread_rng().gen::<ChaChaRng>()),
+			partition: None,
+		}
+	}
+
+	/// Sets the random number generator that generates the keys looked up to refresh the
+	/// buckets. By default, it is seeded from the random number generator of the operating
+	/// system. A generator built from a fixed seed makes the lookups the same from one run to
+	/// another, which is useful for simulations and tests.
+	#[inline]
+	pub fn with_rng<R>(mut self, rng: R) -> Kademlia
+		where R: Rng + Send + 'static
+	{
+		self.rng = Box::new(rng);
+		self
+	}
+
+	/// Sets the validator of the records. The default is a `NamespacedValidator` that only
+	/// accepts the public keys of the peers, in the `pk` namespace.
+	#[inline]
+	pub fn with_record_validator<V>(mut self, validator: V) -> Kademlia
+		where V: RecordValidator + 'static
+	{
+		self.validator = Box::new(validator);
+		self
+	}
+
+	/// Sets the storage of the records and of the provider records. The default is a
+	/// `MemoryRecordStore` with the default limits.
+	#[inline]
+	pub fn with_record_store<S>(mut self, store: S) -> Kademlia
+		where S: RecordStore + 'static
+	{
+		self.store = Box::new(store);
+		self
+	}
+
+	/// Sets a `PartitionDetector` that is told the outcome of every query. A query fails if none
+	/// of the peers it contacted answered. The events of the detector are reported as
+	/// `KademliaEvent::Partition`.
+	#[inline]
+	pub fn with_partition_detector(mut self, detector: PartitionDetector) -> Kademlia {
+		self.partition = Some(detector);
+		self
+	}
+
+	/// Returns the `PartitionDetector` passed to `with_partition_detector`, if any.
+	#[inline]
+	pub fn partition_detector(&self) -> Option<&PartitionDetector> {
+		self.partition.as_ref()
+	}
+
+	/// Returns the `PartitionDetector` passed to `with_partition_detector`, if any. The events
+	/// of the swarm can be injected into it, but the events it returns must then be handled by
+	/// the caller.
+	#[inline]
+	pub fn partition_detector_mut(&mut self) -> Option<&mut PartitionDetector> {
+		self.partition.as_mut()
+	}
+
+	/// Sets the addresses the local node is reachable at. They are sent to the remotes alongside
+	/// the keys we provide.
+	#[inline]
+	pub fn set_listen_addrs(&mut self, addrs: Vec<Multiaddr>) {
+		self.listen_addrs = addrs;
+	}
+
+	/// Returns the configuration.
+	#[inline]
+	pub fn config(&self) -> &KademliaConfig {
+		&self.config
+	}
+
+	/// Builds the handler to use for a new connection. In client mode, the handler rejects the
+	/// requests of the remote.
+	#[inline]
+	pub fn new_handler<S>(&self) -> KademliaHandler<S, QueryId> {
+		KademliaHandler::new().with_inbound_requests(!self.config.client_mode)
+	}
+
+	/// Returns the routing table.
+	#[inline]
+	pub fn kbuckets(&self) -> &KBucketsTable<Vec<Multiaddr>> {
+		&self.kbuckets
+	}
+
+	/// Adds an address of a peer to the routing table, for example the address of a bootstrap
+	/// node. If the bucket of the peer is full, its least recently seen peer is pinged, and the
+	/// new peer only takes its place if it doesn't answer.
+	pub fn add_address(&mut self, peer_id: &PeerId, addr: Multiaddr) {
+		if let Some(addrs) = self.kbuckets.get_mut(peer_id) {
+			if !addrs.contains(&addr) {
+				addrs.push(addr);
+			}
+			return;
+		}
+
+		if let UpdateOutcome::Full(oldest) = self.kbuckets.update(peer_id.clone(), vec![addr]) {
+			self.ping(oldest);
+		}
+	}
+
+	// Checks whether `peer_id`, the least recently seen peer of a full bucket, is still alive.
+	fn ping(&mut self, peer_id: PeerId) {
+		if self.pings.values().any(|&(ref id, _)| *id == peer_id) {
+			return;
+		}
+
+		let query_id = QueryId(self.next_query_id);
+		self.next_query_id += 1;
+		let deadline = Instant::now() + self.config.rpc_timeout;
+		self.pings.insert(query_id, (peer_id.clone(), deadline));
+		self.send_rpc(peer_id, query_id, KadRequestMsg::Ping, Vec::new());
+	}
+
+	/// Starts looking for the peers that are the closest to `key`. The result is reported with a
+	/// `KademliaEvent::FindNodeResult`.
+	#[inline]
+	pub fn find_node(&mut self, key: Vec<u8>) -> QueryId {
+		self.start_query(key, QueryKind::FindNode)
+	}
+
+	/// Joins the network, by looking for the closest peers to the local node. The peers to start
+	/// from, usually well-known bootstrap nodes, must have been passed to `add_address`
+	/// beforehand. The result is reported with a `KademliaEvent::BootstrapResult`.
+	///
+	/// Once the lookup has finished, the buckets further than the closest peer are filled by
+	/// looking up a random key in each of them. Afterwards, the routing table is refreshed
+	/// periodically: the local node is looked up again, as well as a random key in each bucket
+	/// that hasn't been looked up recently.
+	pub fn bootstrap(&mut self) -> QueryId {
+		self.refresh_enabled = true;
+		let key = self.kbuckets.local_peer_id().as_bytes().to_owned();
+		self.start_query(key, QueryKind::Bootstrap)
+	}
+
+	// Looks up a random key in each bucket that hasn't been refreshed for `max_age`.
+	fn refresh_buckets(&mut self, max_age: Duration) {
+		for index in self.kbuckets.buckets_to_refresh(max_age) {
+			// The closest buckets can't be refreshed, but they are most likely empty anyway.
+			if let Some(key) = self.kbuckets.random_key_in_bucket(index, &mut self.rng) {
+				self.start_query(key, QueryKind::Refresh);
+			}
+		}
+	}
+
+	/// Starts looking for the providers of `key`. The result is reported with a
+	/// `KademliaEvent::GetProvidersResult`, which also contains the providers stored locally.
+	pub fn get_providers(&mut self, key: Vec<u8>) -> QueryId {
+		let local_providers = self.local_providers(&key);
+		self.start_query(key, QueryKind::GetProviders(local_providers))
+	}
+
+	/// Announces that the local node provides `key`. The closest peers to the key are looked up
+	/// and sent an `ADD_PROVIDER` request, and this is repeated periodically until
+	/// `stop_providing` is called.
+	///
+	/// > **Note**: The addresses passed to `set_listen_addrs` are sent alongside the key, so that
+	/// >           the remotes can connect to us.
+	pub fn start_providing(&mut self, key: Vec<u8>) {
+		self.provided_keys.insert(key.clone());
+		self.announce(key);
+	}
+
+	/// Stops announcing that the local node provides `key`. The records that the remotes have
+	/// already stored expire by themselves.
+	pub fn stop_providing(&mut self, key: &[u8]) {
+		self.provided_keys.remove(key);
+		let local_peer_id = self.kbuckets.local_peer_id().clone();
+		self.store.remove_provider(key, &local_peer_id);
+	}
+
+	/// Stores a record in the DHT. The record is stored locally, then sent to the closest peers
+	/// to `key`. The result is reported with a `KademliaEvent::PutValueResult`.
+	///
+	/// Returns an error if the record is rejected by the `RecordValidator`, or can't be stored in
+	/// the `RecordStore`.
+	pub fn put_value(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<QueryId, IoError> {
+		self.validator.validate(&key, &value)?;
+		self.store_record(key.clone(), value.clone())?;
+		let record = KadRecord { key: key.clone(), value: value };
+		Ok(self.start_query(key, QueryKind::PutValue(record)))
+	}
+
+	/// Looks for the record stored under `key`. The query stops once `quorum` valid values have
+	/// been found, including the one stored locally, or once the closest peers to the key have
+	/// all answered. The result is reported with a `KademliaEvent::GetValueResult`.
+	///
+	/// # Panic
+	///
+	/// Panics if `quorum` is 0.
+	pub fn get_value(&mut self, key: Vec<u8>, quorum: usize) -> QueryId {
+		assert!(quorum > 0, "the quorum must be at least 1");
+		let local_peer_id = self.kbuckets.local_peer_id().clone();
+		let records = self.store.get(&key)
+			.map(|record| (local_peer_id, record.value))
+			.into_iter()
+			.collect();
+		self.start_query(key, QueryKind::GetValue { records: records, quorum: quorum })
+	}
+
+	// Stores a record locally. The record must have been validated. If a record is already
+	// stored under `key`, the validator chooses which one to keep.
+	fn store_record(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), IoError> {
+		let keep_existing = match self.store.get(&key) {
+			Some(existing) => self.validator.select(&key, &[existing.value, value.clone()]) == 0,
+			None => false,
+		};
+
+		if keep_existing {
+			return Ok(());
+		}
+
+		let expires = time::system_now() + Duration::from_secs(RECORD_TTL_SECS);
+		self.store.put(key, StoredRecord { value: value, expires: expires })
+	}
+
+	// Returns the providers of `key` stored locally, with their addresses.
+	fn local_providers(&self, key: &[u8]) -> Vec<(PeerId, Vec<Multiaddr>)> {
+		self.store.providers(key)
+			.into_iter()
+			.map(|provider| self.provider_addrs(provider.peer_id, provider.addrs))
+			.collect()
+	}
+
+	// Stores the local node as a provider of `key` and starts a query that sends `ADD_PROVIDER`
+	// to the closest peers.
+	fn announce(&mut self, key: Vec<u8>) {
+		let provider = StoredProvider {
+			peer_id: self.kbuckets.local_peer_id().clone(),
+			addrs: Vec::new(),
+			expires: time::system_now() + Duration::from_secs(PROVIDER_TTL_SECS),
+		};
+		// Even if the store is full, the remotes can still store the provider record.
+		let _ = self.store.add_provider(key.clone(), provider);
+		self.start_query(key, QueryKind::AddProvider);
+	}
+
+	// Starts a new query looking for the closest peers to `key`.
+	fn start_query(&mut self, key: Vec<u8>, kind: QueryKind) -> QueryId {
+		let query_id = QueryId(self.next_query_id);
+		self.next_query_id += 1;
+
+		let target = KadKey::new(&key);
+		self.kbuckets.mark_refreshed(&target);
+		let num_results = self.config.replication_factor;
+		let state = QueryState::new(QueryConfig {
+			target: target,
+			known_closest_peers: self.kbuckets.find_closest(&target, num_results),
+			parallelism: self.config.parallelism,
+			num_results: num_results,
+			rpc_timeout: self.config.rpc_timeout,
+			timeout: self.config.query_timeout,
+		});
+
+		self.queries.insert(query_id, QueryInfo {
+			state: state,
+			key: key,
+			addrs: HashMap::new(),
+			kind: kind,
+		});
+
+		query_id
+	}
+
+	/// Indicates that a connection to `peer_id` has been opened.
+	pub fn inject_connected(&mut self, peer_id: &PeerId) {
+		self.connected_peers.insert(peer_id.clone());
+
+		for n in (0 .. self.pending_rpcs.len()).rev() {
+			if self.pending_rpcs[n].0 == *peer_id {
+				let (peer_id, event) = self.pending_rpcs.remove(n);
+				self.queued_actions.push_back(KademliaAction::SendEvent {
+					peer_id: peer_id,
+					event: event,
+				});
+			}
+		}
+	}
+
+	/// Indicates that the connection to `peer_id` has been closed. The requests in progress to
+	/// this peer are considered as failed.
+	pub fn inject_disconnected(&mut self, peer_id: &PeerId) {
+		self.connected_peers.remove(peer_id);
+		let query_ids = self.queries.keys()
+			.chain(self.put_queries.keys())
+			.chain(self.pings.keys())
+			.cloned()
+			.collect::<Vec<_>>();
+		for query_id in query_ids {
+			self.fail_rpc(peer_id, query_id);
+		}
+	}
+
+	/// Indicates that dialing `peer_id` after a `KademliaAction::Dial` has failed. The peer is
+	/// removed from the routing table.
+	pub fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+		self.kbuckets.remove(peer_id);
+		for n in (0 .. self.pending_rpcs.len()).rev() {
+			if self.pending_rpcs[n].0 != *peer_id {
+				continue;
+			}
+
+			if let (_, KademliaHandlerIn::Request { user_data, .. }) = self.pending_rpcs.remove(n) {
+				self.inject_rpc_error(peer_id, user_data);
+			}
+		}
+	}
+
+	/// Injects an event produced by the `KademliaHandler` of the connection to `peer_id`.
+	pub fn inject_node_event(&mut self, peer_id: &PeerId, event: KademliaHandlerEvent<QueryId>) {
+		match event {
+			KademliaHandlerEvent::Request { request, request_id } => {
+				// The handlers built by `new_handler` don't report any request in client mode.
+				if self.config.client_mode {
+					return;
+				}
+				if let Some(response) = self.answer(peer_id, request) {
+					self.queued_actions.push_back(KademliaAction::SendEvent {
+						peer_id: peer_id.clone(),
+						event: KademliaHandlerIn::Response {
+							request_id: request_id,
+							response: response,
+						},
+					});
+				}
+			},
+
+			KademliaHandlerEvent::Response { response, user_data } => {
+				self.inject_response(peer_id, response, user_data);
+			},
+
+			KademliaHandlerEvent::RequestError { user_data, .. } => {
+				self.inject_rpc_error(peer_id, user_data);
+			},
+		}
+	}
+
+	/// Returns the next action to perform.
+	pub fn poll(&mut self) -> Async<KademliaAction> {
+		loop {
+			if self.reprovide_timer.is_none() {
+				let interval = Duration::from_secs(REPROVIDE_INTERVAL_SECS);
+				self.reprovide_timer = Timeout::new(interval, &self.handle).ok();
+			}
+			let polled = self.reprovide_timer.as_mut().map(|timer| timer.poll());
+			match polled {
+				Some(Ok(Async::Ready(()))) => self.reprovide_timer = None,
+				_ => break,
+			}
+
+			self.store.remove_expired();
+			let keys = self.provided_keys.iter().cloned().collect::<Vec<_>>();
+			for key in keys {
+				self.announce(key);
+			}
+		}
+
+		if self.refresh_enabled {
+			loop {
+				if self.refresh_timer.is_none() {
+					let interval = Duration::from_secs(REFRESH_INTERVAL_SECS);
+					self.refresh_timer = Timeout::new(interval, &self.handle).ok();
+				}
+				let polled = self.refresh_timer.as_mut().map(|timer| timer.poll());
+				match polled {
+					Some(Ok(Async::Ready(()))) => self.refresh_timer = None,
+					_ => break,
+				}
+
+				let key = self.kbuckets.local_peer_id().as_bytes().to_owned();
+				self.start_query(key, QueryKind::Refresh);
+				self.refresh_buckets(Duration::from_secs(REFRESH_INTERVAL_SECS));
+			}
+		}
+
+		if !self.queries.is_empty() || !self.put_queries.is_empty() || !self.pings.is_empty() {
+			loop {
+				if self.timer.is_none() {
+					self.timer = Timeout::new(Duration::from_secs(TICK_SECS), &self.handle).ok();
+				}
+				let polled = self.timer.as_mut().map(|timer| timer.poll());
+				match polled {
+					Some(Ok(Async::Ready(()))) => self.timer = None,
+					_ => break,
+				}
+			}
+		}
+
+		let now = Instant::now();
+		let expired_puts = self.put_queries.iter()
+			.filter(|&(_, put)| put.deadline <= now)
+			.map(|(query_id, _)| *query_id)
+			.collect::<Vec<_>>();
+		for query_id in expired_puts {
+			self.finish_put(query_id);
+		}
+
+		let expired_pings = self.pings.iter()
+			.filter(|&(_, &(_, deadline))| deadline <= now)
+			.map(|(query_id, &(ref peer_id, _))| (*query_id, peer_id.clone()))
+			.collect::<Vec<_>>();
+		for (query_id, peer_id) in expired_pings {
+			self.fail_rpc(&peer_id, query_id);
+		}
+
+		let query_ids = self.queries.keys().cloned().collect::<Vec<_>>();
+		for query_id in query_ids {
+			let mut finished = false;
+			loop {
+				let (peer_id, request, addrs) = {
+					let query = self.queries.get_mut(&query_id)
+						.expect("the IDs have been collected from the list of queries");
+					if let QueryKind::GetValue { ref records, quorum } = query.kind {
+						if records.len() >= quorum {
+							finished = true;
+							break;
+						}
+					}
+					let peer_id = match query.state.poll() {
+						QueryStatePollOut::SendRpc(peer_id) => peer_id.clone(),
+						QueryStatePollOut::NotReady => break,
+						QueryStatePollOut::Finished => {
+							finished = true;
+							break;
+						},
+					};
+					let key = query.key.clone();
+					let request = match query.kind {
+						QueryKind::GetProviders(_) => KadRequestMsg::GetProviders { key: key },
+						QueryKind::GetValue { .. } => KadRequestMsg::GetValue { key: key },
+						QueryKind::FindNode |
+						QueryKind::Bootstrap |
+						QueryKind::Refresh |
+						QueryKind::AddProvider |
+						QueryKind::PutValue(_) => KadRequestMsg::FindNode { key: key },
+					};
+					let addrs = query.addrs.get(&peer_id).cloned().unwrap_or_default();
+					(peer_id, request, addrs)
+				};
+
+				self.send_rpc(peer_id, query_id, request, addrs);
+			}
+
+			if finished {
+				self.finish_query(query_id);
+			}
+		}
+
+		match self.queued_actions.pop_front() {
+			Some(action) => Async::Ready(action),
+			None => Async::NotReady,
+		}
+	}
+
+	// Processes a request of `peer_id` and builds the response, if any.
+	fn answer(&mut self, peer_id: &PeerId, request: KadRequestMsg) -> Option<KadResponseMsg> {
+		match request {
+			KadRequestMsg::Ping => Some(KadResponseMsg::Pong),
+			KadRequestMsg::FindNode { key } => Some(KadResponseMsg::FindNode {
+				closer_peers: self.closest_kad_peers(&KadKey::new(&key)),
+			}),
+			KadRequestMsg::GetProviders { key } => {
+				let provider_peers = self.local_providers(&key)
+					.into_iter()
+					.map(|(peer_id, addrs)| KadPeer {
+						connection_ty: self.connection_ty(&peer_id),
+						node_id: peer_id,
+						multiaddrs: addrs,
+					})
+					.collect();
+				Some(KadResponseMsg::GetProviders {
+					closer_peers: self.closest_kad_peers(&KadKey::new(&key)),
+					provider_peers: provider_peers,
+				})
+			},
+			KadRequestMsg::AddProvider { key, provider } => {
+				// Peers can only announce themselves.
+				if provider.node_id == *peer_id {
+					let provider = StoredProvider {
+						peer_id: provider.node_id,
+						addrs: provider.multiaddrs,
+						expires: time::system_now() + Duration::from_secs(PROVIDER_TTL_SECS),
+					};
+					// There is no response, therefore the remote can't be told about errors.
+					let _ = self.store.add_provider(key, provider);
+				}
+				None
+			},
+			KadRequestMsg::GetValue { key } => {
+				let record = self.store.get(&key).map(|record| KadRecord {
+					key: key.clone(),
+					value: record.value,
+				});
+				Some(KadResponseMsg::GetValue {
+					record: record,
+					closer_peers: self.closest_kad_peers(&KadKey::new(&key)),
+				})
+			},
+			KadRequestMsg::PutValue { record } => {
+				// Not answering makes the request of the remote fail.
+				if self.validator.validate(&record.key, &record.value).is_err() {
+					return None;
+				}
+				match self.store_record(record.key.clone(), record.value.clone()) {
+					Ok(()) => Some(KadResponseMsg::PutValue { record: record }),
+					Err(_) => None,
+				}
+			},
+		}
+	}
+
+	// Returns the addresses to report for a provider. The local node is stored without any
+	// address, as they can change over time.
+	fn provider_addrs(&self, peer_id: PeerId, addrs: Vec<Multiaddr>) -> (PeerId, Vec<Multiaddr>) {
+		if peer_id == *self.kbuckets.local_peer_id() {
+			(peer_id, self.listen_addrs.clone())
+		} else {
+			(peer_id, addrs)
+		}
+	}
+
+	// Returns the connection type to report to the remotes for `peer_id`.
+	fn connection_ty(&self, peer_id: &PeerId) -> KadConnectionType {
+		if peer_id == self.kbuckets.local_peer_id() || self.connected_peers.contains(peer_id) {
+			KadConnectionType::Connected
+		} else {
+			KadConnectionType::NotConnected
+		}
+	}
+
+	// Returns the peers of the routing table that are the closest to `target`, with their
+	// addresses.
+	fn closest_kad_peers(&self, target: &KadKey) -> Vec<KadPeer> {
+		self.kbuckets.find_closest(target, self.config.replication_factor)
+			.into_iter()
+			.map(|peer_id| KadPeer {
+				multiaddrs: self.kbuckets.get(&peer_id).cloned().unwrap_or_default(),
+				connection_ty: self.connection_ty(&peer_id),
+				node_id: peer_id,
+			})
+			.collect()
+	}
+
+	// Processes the response of a remote to a request of a query.
+	fn inject_response(&mut self, peer_id: &PeerId, response: KadResponseMsg, query_id: QueryId) {
+		let (closer_peers, provider_peers, record) = match response {
+			KadResponseMsg::FindNode { closer_peers } => (closer_peers, Vec::new(), None),
+			KadResponseMsg::GetProviders { closer_peers, provider_peers } => {
+				(closer_peers, provider_peers, None)
+			},
+			KadResponseMsg::GetValue { closer_peers, record } => {
+				(closer_peers, Vec::new(), record)
+			},
+			KadResponseMsg::PutValue { .. } => {
+				self.inject_put_result(peer_id, query_id, true);
+				return;
+			},
+			KadResponseMsg::Pong => {
+				let answered = self.pings.get(&query_id).map_or(false, |p| p.0 == *peer_id);
+				if answered {
+					self.pings.remove(&query_id);
+					// The peer keeps its place in the table and the pending entry is discarded.
+					if let Some(addrs) = self.kbuckets.get(peer_id).cloned() {
+						self.kbuckets.update(peer_id.clone(), addrs);
+					}
+				}
+				return;
+			},
+		};
+
+		let (learned_addrs, key) = match self.queries.get(&query_id) {
+			Some(query) => {
+				(query.addrs.get(peer_id).cloned().unwrap_or_default(), query.key.clone())
+			},
+			None => return,
+		};
+
+		// The records sent by the remotes can't be trusted.
+		let value = record.and_then(|record| {
+			if record.key == key && self.validator.validate(&key, &record.value).is_ok() {
+				Some(record.value)
+			} else {
+				None
+			}
+		});
+
+		// The remote answered, therefore it is a good candidate for the routing table.
+		if let Some(addrs) = self.kbuckets.get(peer_id).cloned() {
+			self.kbuckets.update(peer_id.clone(), addrs);
+		}
+		for addr in learned_addrs {
+			self.add_address(peer_id, addr);
+		}
+
+		let local_peer_id = self.kbuckets.local_peer_id().clone();
+		let query = self.queries.get_mut(&query_id).expect("checked above");
+
+		if let QueryKind::GetProviders(ref mut providers) = query.kind {
+			for provider in provider_peers {
+				if let Some(pos) = providers.iter().position(|p| p.0 == provider.node_id) {
+					let addrs = &mut providers[pos].1;
+					for addr in provider.multiaddrs {
+						if !addrs.contains(&addr) {
+							addrs.push(addr);
+						}
+					}
+				} else {
+					providers.push((provider.node_id, provider.multiaddrs));
+				}
+			}
+		}
+
+		if let QueryKind::GetValue { ref mut records, .. } = query.kind {
+			if let Some(value) = value {
+				if records.iter().all(|r| r.0 != *peer_id) {
+					records.push((peer_id.clone(), value));
+				}
+			}
+		}
+
+		let mut closer_ids = Vec::with_capacity(closer_peers.len());
+		for peer in closer_peers {
+			if peer.node_id == local_peer_id {
+				continue;
+			}
+			let addrs = query.addrs.entry(peer.node_id.clone()).or_insert_with(Vec::new);
+			for addr in peer.multiaddrs {
+				if !addrs.contains(&addr) {
+					addrs.push(addr);
+				}
+			}
+			closer_ids.push(peer.node_id);
+		}
+
+		query.state.inject_rpc_result(peer_id, closer_ids);
+	}
+
+	// Sends a request to a peer on behalf of a query, dialing the peer if necessary. `query_addrs`
+	// are the addresses of the peer learned during the query.
+	fn send_rpc(&mut self, peer_id: PeerId, query_id: QueryId, request: KadRequestMsg,
+				query_addrs: Vec<Multiaddr>)
+	{
+		let event = KademliaHandlerIn::Request { request: request, user_data: query_id };
+		if self.connected_peers.contains(&peer_id) {
+			self.queued_actions.push_back(KademliaAction::SendEvent {
+				peer_id: peer_id,
+				event: event,
+			});
+			return;
+		}
+
+		let already_dialing = self.pending_rpcs.iter().any(|&(ref id, _)| *id == peer_id);
+		if !already_dialing {
+			let mut addrs = self.kbuckets.get(&peer_id).cloned().unwrap_or_default();
+			for addr in query_addrs {
+				if !addrs.contains(&addr) {
+					addrs.push(addr);
+				}
+			}
+
+			if addrs.is_empty() {
+				self.inject_rpc_error(&peer_id, query_id);
+				return;
+			}
+
+			self.queued_actions.push_back(KademliaAction::Dial {
+				peer_id: peer_id.clone(),
+				addrs: addrs,
+			});
+		}
+
+		self.pending_rpcs.push((peer_id, event));
+	}
+
+	// Indicates that `peer_id` couldn't be reached or failed to answer a request. The peer is
+	// removed from the routing table, and the pending entry of its bucket, if any, takes its
+	// place.
+	fn inject_rpc_error(&mut self, peer_id: &PeerId, query_id: QueryId) {
+		self.kbuckets.remove(peer_id);
+		self.fail_rpc(peer_id, query_id);
+	}
+
+	// Indicates that a request sent on behalf of a query or of a ping hasn't been answered.
+	fn fail_rpc(&mut self, peer_id: &PeerId, query_id: QueryId) {
+		if self.pings.get(&query_id).map_or(false, |p| p.0 == *peer_id) {
+			self.pings.remove(&query_id);
+			self.kbuckets.remove(peer_id);
+			return;
+		}
+
+		if let Some(query) = self.queries.get_mut(&query_id) {
+			query.state.inject_rpc_error(peer_id);
+		}
+		self.inject_put_result(peer_id, query_id, false);
+	}
+
+	// Indicates that a peer has answered, or failed to answer, a `PUT_VALUE` request.
+	fn inject_put_result(&mut self, peer_id: &PeerId, query_id: QueryId, success: bool) {
+		let done = match self.put_queries.get_mut(&query_id) {
+			Some(put) => {
+				if put.pending.remove(peer_id) && success {
+					put.stored_at.push(peer_id.clone());
+				}
+				put.pending.is_empty()
+			},
+			None => return,
+		};
+
+		if done {
+			self.finish_put(query_id);
+		}
+	}
+
+	// Removes a `PUT_VALUE` in progress and reports its result.
+	fn finish_put(&mut self, query_id: QueryId) {
+		if let Some(put) = self.put_queries.remove(&query_id) {
+			let event = KademliaEvent::PutValueResult {
+				query_id: query_id,
+				key: put.key,
+				stored_at: put.stored_at,
+			};
+			self.queued_actions.push_back(KademliaAction::GenerateEvent(event));
+		}
+	}
+
+	// Removes a finished query and reports its result.
+	fn finish_query(&mut self, query_id: QueryId) {
+		let query = match self.queries.remove(&query_id) {
+			Some(query) => query,
+			None => return,
+		};
+
+		let closer_peers = query.state.into_closest_peers();
+		let partition_event = match self.partition {
+			Some(ref mut detector) => detector.inject_lookup_result(!closer_peers.is_empty()),
+			None => None,
+		};
+
+		let event = match query.kind {
+			QueryKind::FindNode => Some(KademliaEvent::FindNodeResult {
+				query_id: query_id,
+				key: query.key,
+				closer_peers: closer_peers,
+			}),
+			QueryKind::Bootstrap => {
+				self.refresh_buckets(Duration::from_secs(0));
+				Some(KademliaEvent::BootstrapResult {
+					query_id: query_id,
+					closer_peers: closer_peers,
+				})
+			},
+			QueryKind::Refresh => None,
+			QueryKind::GetProviders(providers) => Some(KademliaEvent::GetProvidersResult {
+				query_id: query_id,
+				key: query.key,
+				providers: providers,
+				closer_peers: closer_peers,
+			}),
+			QueryKind::GetValue { records, .. } => {
+				let value = if records.is_empty() {
+					None
+				} else {
+					let values = records.iter().map(|r| r.1.clone()).collect::<Vec<_>>();
+					let best = self.validator.select(&query.key, &values);
+					values.into_iter().nth(best)
+				};
+				Some(KademliaEvent::GetValueResult {
+					query_id: query_id,
+					key: query.key,
+					value: value,
+					records: records,
+					closer_peers: closer_peers,
+				})
+			},
+			QueryKind::PutValue(record) => {
+				let deadline = Instant::now() + self.config.rpc_timeout;
+				self.put_queries.insert(query_id, PutInfo {
+					key: query.key,
+					pending: closer_peers.iter().cloned().collect(),
+					stored_at: Vec::new(),
+					deadline: deadline,
+				});
+				if closer_peers.is_empty() {
+					self.finish_put(query_id);
+				}
+
+				let mut addrs = query.addrs;
+				for peer_id in closer_peers {
+					let request = KadRequestMsg::PutValue { record: record.clone() };
+					let peer_addrs = addrs.remove(&peer_id).unwrap_or_default();
+					self.send_rpc(peer_id, query_id, request, peer_addrs);
+				}
+				None
+			},
+			QueryKind::AddProvider => {
+				let provider = KadPeer {
+					node_id: self.kbuckets.local_peer_id().clone(),
+					multiaddrs: self.listen_addrs.clone(),
+					connection_ty: KadConnectionType::Connected,
+				};
+				let mut addrs = query.addrs;
+				for peer_id in closer_peers {
+					let request = KadRequestMsg::AddProvider {
+						key: query.key.clone(),
+						provider: provider.clone(),
+					};
+					let peer_addrs = addrs.remove(&peer_id).unwrap_or_default();
+					self.send_rpc(peer_id, query_id, request, peer_addrs);
+				}
+				None
+			},
+		};
+
+		if let Some(event) = event {
+			self.queued_actions.push_back(KademliaAction::GenerateEvent(event));
+		}
+		if let Some(event) = partition_event {
+			let event = KademliaEvent::Partition(event);
+			self.queued_actions.push_back(KademliaAction::GenerateEvent(event));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Kademlia, KademliaAction, KademliaConfig, KademliaEvent, QueryId};
+	use futures::{future, Async};
+	use handler::{KademliaHandlerEvent, KademliaHandlerIn, KademliaRequestId};
+	use kbucket::KadKey;
+	use libp2p_swarm::{PartitionConfig, PartitionDetector, PartitionEvent, PeerId};
+	use multiaddr::Multiaddr;
+	use protocol::{KadRequestMsg, KadResponseMsg};
+	use record::{NamespacedValidator, RecordValidator};
+	use std::collections::HashMap;
+	use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+	use std::time::{Duration, SystemTime};
+	use store::{MemoryRecordStore, MemoryRecordStoreConfig, StoredRecord};
+	use tokio_core::reactor::{Core, Handle};
+
+	fn substream_closed() -> IoError {
+		IoError::new(IoErrorKind::UnexpectedEof, "substream closed by remote")
+	}
+
+	// Accepts any value, and prefers the longest one.
+	struct Longest;
+
+	impl RecordValidator for Longest {
+		fn validate(&self, _: &[u8], _: &[u8]) -> Result<(), IoError> {
+			Ok(())
+		}
+
+		fn select(&self, _: &[u8], values: &[Vec<u8>]) -> usize {
+			(0 .. values.len()).max_by_key(|&n| values[n].len()).unwrap()
+		}
+	}
+
+	// Ten nodes in a ring, where each node only knows the next two nodes. The actions of the
+	// nodes are delivered to each other as if they were connected through handlers.
+	struct Network {
+		peers: Vec<PeerId>,
+		nodes: Vec<Kademlia>,
+		requests: HashMap<KademliaRequestId, QueryId>,
+		next_request_id: u64,
+	}
+
+	impl Network {
+		fn new(handle: &Handle) -> Network {
+			Network::with_config(handle, KademliaConfig::default())
+		}
+
+		fn with_config(handle: &Handle, config: KademliaConfig) -> Network {
+			let peers = (0 .. 10u8).map(|n| PeerId::from_public_key(&[n])).collect::<Vec<_>>();
+			let mut nodes = peers.iter()
+				.map(|peer_id| {
+					let validator = NamespacedValidator::default().with("test", Longest);
+					Kademlia::with_config(peer_id.clone(), handle.clone(), config)
+						.with_record_validator(validator)
+				})
+				.collect::<Vec<_>>();
+			for n in 0 .. peers.len() {
+				nodes[n].set_listen_addrs(vec![Network::addr(n)]);
+				for offset in 1 .. 3 {
+					let other = (n + offset) % peers.len();
+					nodes[n].add_address(&peers[other], Network::addr(other));
+				}
+			}
+
+			Network {
+				peers: peers,
+				nodes: nodes,
+				requests: HashMap::new(),
+				next_request_id: 0,
+			}
+		}
+
+		fn addr(n: usize) -> Multiaddr {
+			format!("/ip4/127.0.0.1/tcp/{}", 1000 + n).parse().unwrap()
+		}
+
+		// Delivers the actions until a node produces an event, or until nothing happens anymore.
+		// Must be called within a task, because of the timers.
+		fn run(&mut self) -> Option<KademliaEvent> {
+			loop {
+				let mut progress = false;
+				for n in 0 .. self.nodes.len() {
+					let action = match self.nodes[n].poll() {
+						Async::Ready(action) => action,
+						Async::NotReady => continue,
+					};
+					progress = true;
+
+					match action {
+						KademliaAction::Dial { peer_id, .. } => {
+							self.nodes[n].inject_connected(&peer_id)
+						},
+						KademliaAction::SendEvent { peer_id, event } => {
+							let remote = self.peers.iter().position(|p| *p == peer_id).unwrap();
+							let event = match event {
+								KademliaHandlerIn::Request { request, user_data } => {
+									// The handlers of the nodes in client mode close the
+									// substreams opened by the remotes.
+									if self.nodes[remote].config().client_mode {
+										let error = KademliaHandlerEvent::RequestError {
+											error: substream_closed(),
+											user_data: user_data,
+										};
+										self.nodes[n].inject_node_event(&peer_id, error);
+										continue;
+									}
+									let request_id = KademliaRequestId(self.next_request_id);
+									self.next_request_id += 1;
+									self.requests.insert(request_id, user_data);
+									KademliaHandlerEvent::Request {
+										request: request,
+										request_id: request_id,
+									}
+								},
+								KademliaHandlerIn::Response { request_id, response } => {
+									KademliaHandlerEvent::Response {
+										response: response,
+										user_data: self.requests.remove(&request_id).unwrap(),
+									}
+								},
+							};
+							let local = self.peers[n].clone();
+							self.nodes[remote].inject_node_event(&local, event);
+						},
+						KademliaAction::GenerateEvent(event) => return Some(event),
+					}
+				}
+
+				if !progress {
+					return None;
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn find_node_through_network() {
+		let mut core = Core::new().unwrap();
+		let mut network = Network::new(&core.handle());
+
+		let target = b"target".to_vec();
+		let query_id = network.nodes[0].find_node(target.clone());
+		let result = core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+
+		let key = KadKey::new(&target);
+		let mut expected = network.peers[1 ..].to_vec();
+		expected.sort_by_key(|peer_id| key.distance(&KadKey::from_peer_id(peer_id)));
+		assert_eq!(result, Some(KademliaEvent::FindNodeResult {
+			query_id: query_id,
+			key: target,
+			closer_peers: expected,
+		}));
+
+		// The nodes that answered have been added to the routing table.
+		assert_eq!(network.nodes[0].kbuckets().len(), network.peers.len() - 1);
+	}
+
+	#[test]
+	fn providers_through_network() {
+		let mut core = Core::new().unwrap();
+		let mut network = Network::new(&core.handle());
+
+		let key = b"content".to_vec();
+		network.nodes[3].start_providing(key.clone());
+		let result = core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		assert_eq!(result, None);
+
+		// All the other nodes are among the closest peers to the key, and have stored the record.
+		let provider = (network.peers[3].clone(), vec![Network::addr(3)]);
+		for n in (0 .. network.nodes.len()).filter(|&n| n != 3) {
+			assert_eq!(network.nodes[n].local_providers(&key), vec![provider.clone()]);
+		}
+
+		network.nodes[7].store.remove_provider(&key, &network.peers[3]);
+		let query_id = network.nodes[7].get_providers(key.clone());
+		let result = core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		match result {
+			Some(KademliaEvent::GetProvidersResult { query_id: id, key: k, providers, .. }) => {
+				assert_eq!(id, query_id);
+				assert_eq!(k, key);
+				assert_eq!(providers, vec![provider]);
+			},
+			_ => panic!("expected the providers"),
+		}
+
+		// Once we stop providing, the local record is removed.
+		network.nodes[3].stop_providing(&key);
+		assert!(network.nodes[3].local_providers(&key).is_empty());
+	}
+
+	#[test]
+	fn records_through_network() {
+		let mut core = Core::new().unwrap();
+		let mut network = Network::new(&core.handle());
+		let key = b"/test/key".to_vec();
+
+		assert!(network.nodes[2].put_value(b"/unknown/key".to_vec(), vec![1]).is_err());
+
+		// A node whose store is full rejects the record.
+		let config = MemoryRecordStoreConfig { max_records: 0, .. Default::default() };
+		network.nodes[6].store = Box::new(MemoryRecordStore::with_config(config));
+
+		let query_id = network.nodes[2].put_value(key.clone(), b"short".to_vec()).unwrap();
+		let result = core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		match result {
+			Some(KademliaEvent::PutValueResult { query_id: id, key: k, mut stored_at }) => {
+				assert_eq!(id, query_id);
+				assert_eq!(k, key);
+				let mut expected = network.peers.clone();
+				expected.remove(6);
+				expected.remove(2);
+				stored_at.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+				expected.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+				assert_eq!(stored_at, expected);
+			},
+			_ => panic!("expected the result of the put"),
+		}
+
+		// A node has a better value, which must be chosen over the others.
+		let record = StoredRecord {
+			value: b"longer value".to_vec(),
+			expires: SystemTime::now() + Duration::from_secs(3600),
+		};
+		network.nodes[5].store.put(key.clone(), record).unwrap();
+
+		let query_id = network.nodes[8].get_value(key.clone(), 10);
+		let result = core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		match result {
+			Some(KademliaEvent::GetValueResult { query_id: id, value, records, .. }) => {
+				assert_eq!(id, query_id);
+				assert_eq!(value, Some(b"longer value".to_vec()));
+				assert_eq!(records.len(), 9);
+			},
+			_ => panic!("expected the result of the get"),
+		}
+
+		// With a quorum of 1, the local record is enough.
+		network.nodes[8].get_value(key.clone(), 1);
+		let result = core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		match result {
+			Some(KademliaEvent::GetValueResult { value, records, .. }) => {
+				assert_eq!(value, Some(b"short".to_vec()));
+				assert_eq!(records, vec![(network.peers[8].clone(), b"short".to_vec())]);
+			},
+			_ => panic!("expected the result of the get"),
+		}
+	}
+
+	#[test]
+	fn bootstrap() {
+		let mut core = Core::new().unwrap();
+		let mut network = Network::new(&core.handle());
+
+		let query_id = network.nodes[4].bootstrap();
+		let result = core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		let local_key = KadKey::from_peer_id(&network.peers[4]);
+		let mut expected = network.peers.clone();
+		expected.remove(4);
+		expected.sort_by_key(|peer_id| local_key.distance(&KadKey::from_peer_id(peer_id)));
+		assert_eq!(result, Some(KademliaEvent::BootstrapResult {
+			query_id: query_id,
+			closer_peers: expected,
+		}));
+
+		// The buckets are then refreshed, without reporting anything.
+		assert!(!network.nodes[4].queries.is_empty());
+		let result = core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		assert_eq!(result, None);
+		assert_eq!(network.nodes[4].kbuckets().len(), network.peers.len() - 1);
+	}
+
+	#[test]
+	fn replication_factor() {
+		let mut core = Core::new().unwrap();
+		let config = KademliaConfig { replication_factor: 3, .. KademliaConfig::default() };
+		let mut network = Network::with_config(&core.handle(), config);
+		assert_eq!(network.nodes[0].config().replication_factor, 3);
+
+		network.nodes[0].find_node(b"target".to_vec());
+		match core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap() {
+			Some(KademliaEvent::FindNodeResult { closer_peers, .. }) => {
+				assert_eq!(closer_peers.len(), 3);
+			},
+			_ => panic!("expected the result of the query"),
+		}
+	}
+
+	// Builds a node whose buckets contain two peers, and fills its furthest bucket with `peers`.
+	fn full_bucket(handle: &Handle) -> (Kademlia, Vec<PeerId>) {
+		let local = PeerId::from_public_key(&[0]);
+		let local_key = KadKey::from_peer_id(&local);
+		let config = KademliaConfig { replication_factor: 2, .. KademliaConfig::default() };
+		let mut node = Kademlia::with_config(local, handle.clone(), config);
+
+		let peers = (1 .. 255u8)
+			.map(|n| PeerId::from_public_key(&[n]))
+			.filter(|p| local_key.distance(&KadKey::from_peer_id(p)).bucket_index() == Some(255))
+			.take(3)
+			.collect::<Vec<_>>();
+		for (n, peer_id) in peers.iter().enumerate() {
+			node.add_address(peer_id, Network::addr(n));
+		}
+		(node, peers)
+	}
+
+	#[test]
+	fn full_bucket_replaces_dead_entry() {
+		let mut core = Core::new().unwrap();
+		let (mut node, peers) = full_bucket(&core.handle());
+		assert!(node.kbuckets().get(&peers[2]).is_none());
+
+		// The least recently seen peer is pinged, which requires dialing it.
+		match core.run(future::lazy(|| Ok::<_, ()>(node.poll()))).unwrap() {
+			Async::Ready(KademliaAction::Dial { ref peer_id, .. }) if *peer_id == peers[0] => (),
+			_ => panic!("expected a dial to the oldest peer"),
+		}
+
+		node.inject_dial_failure(&peers[0]);
+		assert!(node.kbuckets().get(&peers[0]).is_none());
+		assert_eq!(node.kbuckets().get(&peers[2]), Some(&vec![Network::addr(2)]));
+		assert_eq!(node.kbuckets().len(), 2);
+	}
+
+	#[test]
+	fn full_bucket_keeps_live_entry() {
+		let mut core = Core::new().unwrap();
+		let (mut node, peers) = full_bucket(&core.handle());
+
+		match core.run(future::lazy(|| Ok::<_, ()>(node.poll()))).unwrap() {
+			Async::Ready(KademliaAction::Dial { ref peer_id, .. }) if *peer_id == peers[0] => (),
+			_ => panic!("expected a dial to the oldest peer"),
+		}
+		node.inject_connected(&peers[0]);
+		let user_data = match core.run(future::lazy(|| Ok::<_, ()>(node.poll()))).unwrap() {
+			Async::Ready(KademliaAction::SendEvent {
+				event: KademliaHandlerIn::Request { request: KadRequestMsg::Ping, user_data },
+				..
+			}) => user_data,
+			_ => panic!("expected a ping"),
+		};
+
+		let pong = KademliaHandlerEvent::Response {
+			response: KadResponseMsg::Pong,
+			user_data: user_data,
+		};
+		node.inject_node_event(&peers[0], pong);
+		assert!(node.kbuckets().get(&peers[0]).is_some());
+		assert!(node.kbuckets().get(&peers[2]).is_none());
+
+		// The discarded entry doesn't come back once another peer is removed.
+		node.inject_dial_failure(&peers[1]);
+		assert!(node.kbuckets().get(&peers[2]).is_none());
+		assert_eq!(node.kbuckets().len(), 1);
+	}
+
+	#[test]
+	#[should_panic]
+	fn zero_parallelism() {
+		let core = Core::new().unwrap();
+		let config = KademliaConfig { parallelism: 0, .. KademliaConfig::default() };
+		Kademlia::with_config(PeerId::from_public_key(&[0]), core.handle(), config);
+	}
+
+	#[test]
+	fn client_mode() {
+		let mut core = Core::new().unwrap();
+		let mut network = Network::new(&core.handle());
+		network.nodes[5].config.client_mode = true;
+
+		let target = b"target".to_vec();
+		network.nodes[0].find_node(target.clone());
+		let result = core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap();
+		let key = KadKey::new(&target);
+		let mut expected = network.peers.clone();
+		expected.remove(5);
+		expected.remove(0);
+		expected.sort_by_key(|peer_id| key.distance(&KadKey::from_peer_id(peer_id)));
+		match result {
+			Some(KademliaEvent::FindNodeResult { closer_peers, .. }) => {
+				assert_eq!(closer_peers, expected)
+			},
+			_ => panic!("expected the result of the query"),
+		}
+		// The node in client mode has been learned from the other nodes, but it never answered.
+		assert!(network.nodes[0].kbuckets().get(&network.peers[5]).is_none());
+
+		// The node in client mode can still perform queries.
+		network.nodes[5].find_node(target);
+		match core.run(future::lazy(|| Ok::<_, ()>(network.run()))).unwrap() {
+			Some(KademliaEvent::FindNodeResult { closer_peers, .. }) => {
+				assert_eq!(closer_peers.len(), network.peers.len() - 1)
+			},
+			_ => panic!("expected the result of the query"),
+		}
+	}
+
+	#[test]
+	fn failed_queries_suggest_partition() {
+		let mut core = Core::new().unwrap();
+		let detector = PartitionDetector::new(PartitionConfig {
+			window: 4,
+			min_samples: 2,
+			.. PartitionConfig::default()
+		});
+		let mut kademlia = Kademlia::new(PeerId::from_public_key(&[0]), core.handle())
+			.with_partition_detector(detector);
+
+		// Nobody answers the queries of a node that doesn't know any peer.
+		kademlia.find_node(b"first".to_vec());
+		kademlia.find_node(b"second".to_vec());
+		let events = core.run(future::lazy(|| {
+			let mut events = Vec::new();
+			while let Async::Ready(action) = kademlia.poll() {
+				if let KademliaAction::GenerateEvent(event) = action {
+					events.push(event);
+				}
+			}
+			Ok::<_, ()>(events)
+		})).unwrap();
+
+		assert_eq!(events.len(), 3);
+		match (&events[0], &events[1]) {
+			(&KademliaEvent::FindNodeResult { closer_peers: ref a, .. },
+			 &KademliaEvent::FindNodeResult { closer_peers: ref b, .. }) => {
+				assert!(a.is_empty() && b.is_empty())
+			},
+			_ => panic!("expected the results of the queries"),
+		}
+		match events[2] {
+			KademliaEvent::Partition(PartitionEvent::Suspected(ref evidence)) => {
+				assert_eq!(evidence.lookup_failure_ratio, Some(1.0))
+			},
+			_ => panic!("expected a suspected partition"),
+		}
+		assert!(kademlia.partition_detector().unwrap().is_suspected());
+	}
+}
@@ -0,0 +1,393 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains `KademliaHandler`, which handles the Kademlia substreams of a single connection.
+
+use futures::{Async, Future, Poll};
+use libp2p_swarm::{NodeHandlerEndpoint, ProtocolsHandler, ProtocolsHandlerEvent};
+use protocol::{read_request, send_request, send_request_without_response, send_response};
+use protocol::ReadRequestFuture;
+use protocol::{KadRequestMsg, KadResponseMsg, KadSubstream, KademliaProtocolConfig};
+use std::collections::{HashMap, VecDeque};
+use std::io::Error as IoError;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Identifier of a request received from the remote. Must be passed back alongside the response.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct KademliaRequestId(pub(crate) u64);
+
+/// Event sent to a `KademliaHandler`.
+#[derive(Debug, Clone)]
+pub enum KademliaHandlerIn<TUserData> {
+	/// Sends a request to the remote on a new substream. The outcome is reported with a
+	/// `Response` or a `RequestError` event that contains `user_data`.
+	///
+	/// > **Note**: Requests that don't expect any response, such as `AddProvider`, are sent
+	/// >           without reporting anything, except if opening the substream fails.
+	Request {
+		/// The request to send.
+		request: KadRequestMsg,
+		/// Data given back alongside the outcome of the request.
+		user_data: TUserData,
+	},
+	/// Answers a request of the remote.
+	Response {
+		/// Identifier of the request, as reported by the `Request` event.
+		request_id: KademliaRequestId,
+		/// The response to send.
+		response: KadResponseMsg,
+	},
+}
+
+/// Event produced by a `KademliaHandler`.
+#[derive(Debug)]
+pub enum KademliaHandlerEvent<TUserData> {
+	/// The remote sent a request. It must be answered with a `KademliaHandlerIn::Response`,
+	/// except if it doesn't expect any response (see `KadRequestMsg::expects_response`).
+	Request {
+		/// The request of the remote.
+		request: KadRequestMsg,
+		/// Identifier to pass back alongside the response.
+		request_id: KademliaRequestId,
+	},
+	/// The remote answered one of our requests.
+	Response {
+		/// The response of the remote.
+		response: KadResponseMsg,
+		/// Data that was passed alongside the request.
+		user_data: TUserData,
+	},
+	/// One of our requests failed.
+	RequestError {
+		/// The error that happened.
+		error: IoError,
+		/// Data that was passed alongside the request.
+		user_data: TUserData,
+	},
+}
+
+/// Implementation of `ProtocolsHandler` for the Kademlia protocol.
+///
+/// Sends the requests injected with `KademliaHandlerIn::Request`, each on a new substream, and
+/// reports the requests sent by the remote. The handler doesn't know anything about the routing
+/// table: the requests of the remote must be answered from the outside.
+///
+/// If the inbound requests are disabled with `with_inbound_requests`, the substreams opened by
+/// the remote are closed immediately, which is how nodes in client mode reject the requests.
+pub struct KademliaHandler<S, TUserData> {
+	// Requests waiting for an outbound substream to be requested.
+	pending_requests: VecDeque<(KadRequestMsg, TUserData)>,
+	// Requests that have been sent, waiting for the response.
+	outbound: Vec<(Box<Future<Item = KadResponseMsg, Error = IoError>>, TUserData)>,
+	// Substreams opened by the remote, waiting for the request.
+	inbound: Vec<Box<ReadRequestFuture<S>>>,
+	// Requests of the remote waiting to be answered, with the substream to answer on.
+	waiting_responses: HashMap<KademliaRequestId, KadSubstream<S>>,
+	// Responses, and requests without a response, being sent.
+	sending: Vec<Box<Future<Item = (), Error = IoError>>>,
+	// Events to produce at the next call to `poll`.
+	events: VecDeque<KademliaHandlerEvent<TUserData>>,
+	next_request_id: u64,
+	// If false, the substreams opened by the remote are closed immediately.
+	inbound_requests: bool,
+	shutting_down: bool,
+}
+
+impl<S, TUserData> KademliaHandler<S, TUserData> {
+	/// Builds a new `KademliaHandler`.
+	#[inline]
+	pub fn new() -> KademliaHandler<S, TUserData> {
+		KademliaHandler {
+			pending_requests: VecDeque::new(),
+			outbound: Vec::new(),
+			inbound: Vec::new(),
+			waiting_responses: HashMap::new(),
+			sending: Vec::new(),
+			events: VecDeque::new(),
+			next_request_id: 0,
+			inbound_requests: true,
+			shutting_down: false,
+		}
+	}
+
+	/// Sets whether the requests of the remote are accepted. Defaults to true.
+	#[inline]
+	pub fn with_inbound_requests(mut self, inbound_requests: bool) -> Self {
+		self.inbound_requests = inbound_requests;
+		self
+	}
+}
+
+impl<S, TUserData> Default for KademliaHandler<S, TUserData> {
+	#[inline]
+	fn default() -> KademliaHandler<S, TUserData> {
+		KademliaHandler::new()
+	}
+}
+
+impl<S, TUserData> ProtocolsHandler for KademliaHandler<S, TUserData>
+	where S: AsyncRead + AsyncWrite + 'static
+{
+	type InEvent = KademliaHandlerIn<TUserData>;
+	type OutEvent = KademliaHandlerEvent<TUserData>;
+	type Substream = S;
+	type Protocol = KademliaProtocolConfig;
+	type OutboundOpenInfo = (KadRequestMsg, TUserData);
+
+	#[inline]
+	fn listen_protocol(&self) -> KademliaProtocolConfig {
+		KademliaProtocolConfig
+	}
+
+	fn inject_fully_negotiated(&mut self, substream: KadSubstream<S>,
+							   endpoint: NodeHandlerEndpoint<(KadRequestMsg, TUserData)>)
+	{
+		match endpoint {
+			NodeHandlerEndpoint::Dialer((request, user_data)) => {
+				if request.expects_response() {
+					self.outbound.push((send_request(substream, &request), user_data));
+				} else {
+					self.sending.push(send_request_without_response(substream, &request));
+				}
+			},
+			NodeHandlerEndpoint::Listener => {
+				// Dropping the substream closes it.
+				if self.inbound_requests {
+					self.inbound.push(read_request(substream));
+				}
+			},
+		}
+	}
+
+	fn inject_event(&mut self, event: KademliaHandlerIn<TUserData>) {
+		match event {
+			KademliaHandlerIn::Request { request, user_data } => {
+				self.pending_requests.push_back((request, user_data));
+			},
+			KademliaHandlerIn::Response { request_id, response } => {
+				// The substream may have been dropped if the handler is shutting down.
+				if let Some(substream) = self.waiting_responses.remove(&request_id) {
+					self.sending.push(send_response(substream, &response));
+				}
+			},
+		}
+	}
+
+	fn inject_dial_upgrade_error(&mut self, (_, user_data): (KadRequestMsg, TUserData),
+								 error: &IoError)
+	{
+		self.events.push_back(KademliaHandlerEvent::RequestError {
+			error: IoError::new(error.kind(), error.to_string()),
+			user_data: user_data,
+		});
+	}
+
+	#[inline]
+	fn shutdown(&mut self) {
+		self.shutting_down = true;
+	}
+
+	fn poll(&mut self) -> Poll<
+		Option<ProtocolsHandlerEvent<KademliaProtocolConfig, (KadRequestMsg, TUserData),
+									 KademliaHandlerEvent<TUserData>>>,
+		IoError
+	> {
+		if self.shutting_down {
+			return Ok(Async::Ready(None));
+		}
+
+		if let Some(event) = self.events.pop_front() {
+			return Ok(Async::Ready(Some(ProtocolsHandlerEvent::Custom(event))));
+		}
+
+		if let Some(info) = self.pending_requests.pop_front() {
+			return Ok(Async::Ready(Some(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+				upgrade: KademliaProtocolConfig,
+				info: info,
+			})));
+		}
+
+		for n in (0 .. self.outbound.len()).rev() {
+			let (mut future, user_data) = self.outbound.swap_remove(n);
+			let event = match future.poll() {
+				Ok(Async::Ready(response)) => KademliaHandlerEvent::Response {
+					response: response,
+					user_data: user_data,
+				},
+				Ok(Async::NotReady) => {
+					self.outbound.push((future, user_data));
+					continue;
+				},
+				Err(error) => KademliaHandlerEvent::RequestError {
+					error: error,
+					user_data: user_data,
+				},
+			};
+			return Ok(Async::Ready(Some(ProtocolsHandlerEvent::Custom(event))));
+		}
+
+		for n in (0 .. self.inbound.len()).rev() {
+			let mut future = self.inbound.swap_remove(n);
+			match future.poll() {
+				Ok(Async::Ready((request, substream))) => {
+					let request_id = KademliaRequestId(self.next_request_id);
+					self.next_request_id += 1;
+					if request.expects_response() {
+						self.waiting_responses.insert(request_id, substream);
+					}
+					let event = KademliaHandlerEvent::Request {
+						request: request,
+						request_id: request_id,
+					};
+					return Ok(Async::Ready(Some(ProtocolsHandlerEvent::Custom(event))));
+				},
+				Ok(Async::NotReady) => self.inbound.push(future),
+				// The remote sent an invalid request or closed the substream ; dropping it.
+				Err(_) => (),
+			}
+		}
+
+		for n in (0 .. self.sending.len()).rev() {
+			let mut future = self.sending.swap_remove(n);
+			if let Ok(Async::NotReady) = future.poll() {
+				self.sending.push(future);
+			}
+		}
+
+		Ok(Async::NotReady)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{KademliaHandler, KademliaHandlerEvent, KademliaHandlerIn};
+	use futures::{future, Async, Future, Stream};
+	use libp2p_swarm::{ConnectionUpgrade, Endpoint, NodeHandlerEndpoint, ProtocolsHandler};
+	use libp2p_swarm::ProtocolsHandlerEvent;
+	use protocol::{KadRequestMsg, KadResponseMsg, KademliaProtocolConfig};
+	use tokio_core::net::{TcpListener, TcpStream};
+	use tokio_core::reactor::Core;
+
+	#[test]
+	fn request_response() {
+		let mut core = Core::new().unwrap();
+		let addr = "/ip4/127.0.0.1/tcp/10000".parse().unwrap();
+
+		let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+		let listener_addr = listener.local_addr().unwrap();
+		let incoming = listener.incoming().into_future().map_err(|(err, _)| err);
+		let dial = TcpStream::connect(&listener_addr, &core.handle());
+		let (incoming, dialed) = core.run(incoming.join(dial)).unwrap();
+		let incoming = incoming.0.unwrap().0;
+
+		let mut dialer = KademliaHandler::new();
+		let mut listener = KademliaHandler::<_, ()>::new();
+
+		dialer.inject_event(KademliaHandlerIn::Request {
+			request: KadRequestMsg::Ping,
+			user_data: 5,
+		});
+		let info = match dialer.poll().unwrap() {
+			Async::Ready(Some(ProtocolsHandlerEvent::OutboundSubstreamRequest { info, .. })) => {
+				info
+			},
+			_ => panic!("expected a substream request"),
+		};
+
+		let substream = KademliaProtocolConfig.upgrade(dialed, (), Endpoint::Dialer, &addr);
+		dialer.inject_fully_negotiated(core.run(substream).unwrap(),
+									   NodeHandlerEndpoint::Dialer(info));
+		let substream = KademliaProtocolConfig.upgrade(incoming, (), Endpoint::Listener, &addr);
+		listener.inject_fully_negotiated(core.run(substream).unwrap(),
+										 NodeHandlerEndpoint::Listener);
+
+		// Each side has to be polled in order for the other side to make progress.
+		let request_id = match core.run(future::poll_fn(|| {
+			assert!(dialer.poll().unwrap().is_not_ready());
+			listener.poll()
+		})).unwrap() {
+			Some(ProtocolsHandlerEvent::Custom(KademliaHandlerEvent::Request {
+				request: KadRequestMsg::Ping,
+				request_id,
+			})) => request_id,
+			_ => panic!("expected a request"),
+		};
+
+		listener.inject_event(KademliaHandlerIn::Response {
+			request_id: request_id,
+			response: KadResponseMsg::Pong,
+		});
+		match core.run(future::poll_fn(|| {
+			assert!(listener.poll().unwrap().is_not_ready());
+			dialer.poll()
+		})).unwrap() {
+			Some(ProtocolsHandlerEvent::Custom(KademliaHandlerEvent::Response {
+				response: KadResponseMsg::Pong,
+				user_data: 5,
+			})) => (),
+			_ => panic!("expected a response"),
+		}
+	}
+
+	#[test]
+	fn inbound_requests_rejected() {
+		let mut core = Core::new().unwrap();
+		let addr = "/ip4/127.0.0.1/tcp/10000".parse().unwrap();
+
+		let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+		let listener_addr = listener.local_addr().unwrap();
+		let incoming = listener.incoming().into_future().map_err(|(err, _)| err);
+		let dial = TcpStream::connect(&listener_addr, &core.handle());
+		let (incoming, dialed) = core.run(incoming.join(dial)).unwrap();
+		let incoming = incoming.0.unwrap().0;
+
+		let mut dialer = KademliaHandler::new();
+		let mut listener = KademliaHandler::<_, ()>::new().with_inbound_requests(false);
+
+		dialer.inject_event(KademliaHandlerIn::Request {
+			request: KadRequestMsg::Ping,
+			user_data: 5,
+		});
+		let info = match dialer.poll().unwrap() {
+			Async::Ready(Some(ProtocolsHandlerEvent::OutboundSubstreamRequest { info, .. })) => {
+				info
+			},
+			_ => panic!("expected a substream request"),
+		};
+
+		let substream = KademliaProtocolConfig.upgrade(dialed, (), Endpoint::Dialer, &addr);
+		dialer.inject_fully_negotiated(core.run(substream).unwrap(),
+									   NodeHandlerEndpoint::Dialer(info));
+		let substream = KademliaProtocolConfig.upgrade(incoming, (), Endpoint::Listener, &addr);
+		listener.inject_fully_negotiated(core.run(substream).unwrap(),
+										 NodeHandlerEndpoint::Listener);
+
+		match core.run(future::poll_fn(|| {
+			assert!(listener.poll().unwrap().is_not_ready());
+			dialer.poll()
+		})).unwrap() {
+			Some(ProtocolsHandlerEvent::Custom(KademliaHandlerEvent::RequestError {
+				user_data: 5,
+				..
+			})) => (),
+			_ => panic!("expected an error"),
+		}
+	}
+}
@@ -0,0 +1,507 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `KBucketsTable` struct, which is the routing table of Kademlia.
+//!
+//! The peers and the keys live in the same 256 bits keyspace, where the position of a peer or a
+//! key is the SHA-256 hash of its bytes. The distance between two positions is the XOR of their
+//! hashes, interpreted as an integer.
+//!
+//! The routing table contains one bucket for each possible length of the common prefix between
+//! the local peer and a remote. Each bucket contains at most `k` entries, ordered from the least
+//! recently seen to the most recently seen.
+//!
+//! When a peer is inserted in a full bucket, it is kept aside as the pending entry of the bucket.
+//! The least recently seen peer should then be checked: if it is removed, the pending entry takes
+//! its place, and if it is seen again, the pending entry is discarded.
+
+use libp2p_swarm::PeerId;
+use libp2p_swarm::time::Instant;
+use multihash;
+use rand::Rng;
+use std::time::Duration;
+
+/// Number of bits of the keyspace, and therefore number of buckets.
+const NUM_BUCKETS: usize = 256;
+/// Number of furthest buckets for which `random_key_in_bucket` can generate a key. Generating a
+/// key in a bucket takes twice as many attempts as in the next furthest one.
+const RANDOM_KEY_BUCKETS: usize = 16;
+
+/// Position of a peer or of a key in the Kademlia keyspace.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct KadKey([u8; 32]);
+
+impl KadKey {
+	/// Returns the position of an arbitrary key in the keyspace.
+	pub fn new(key: &[u8]) -> KadKey {
+		let hash = multihash::encode(multihash::Hash::SHA2256, key)
+			.expect("sha2-256 is always supported");
+		// The hash is prefixed with the code of the algorithm and with the length of the digest.
+		let mut out = [0; 32];
+		out.copy_from_slice(&hash[2 ..]);
+		KadKey(out)
+	}
+
+	/// Returns the position of a peer in the keyspace.
+	#[inline]
+	pub fn from_peer_id(peer_id: &PeerId) -> KadKey {
+		KadKey::new(peer_id.as_bytes())
+	}
+
+	/// Returns the distance between two positions.
+	#[inline]
+	pub fn distance(&self, other: &KadKey) -> Distance {
+		let mut out = [0; 32];
+		for (n, byte) in out.iter_mut().enumerate() {
+			*byte = self.0[n] ^ other.0[n];
+		}
+		Distance(out)
+	}
+
+	/// Returns the hash of the key.
+	#[inline]
+	pub fn as_bytes(&self) -> &[u8; 32] {
+		&self.0
+	}
+}
+
+/// Distance between two positions in the keyspace. Compares as a 256 bits big-endian integer.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Distance([u8; 32]);
+
+impl Distance {
+	/// Returns the index of the bucket of a peer at this distance from the local peer, or `None`
+	/// if the distance is zero.
+	///
+	/// Bucket `n` contains the peers whose distance is between `2^n` (included) and `2^(n+1)`
+	/// (excluded). In other words, the higher the index, the further the peers.
+	pub fn bucket_index(&self) -> Option<usize> {
+		let mut leading_zeros = 0;
+		for &byte in self.0.iter() {
+			leading_zeros += byte.leading_zeros() as usize;
+			if byte != 0 {
+				break;
+			}
+		}
+
+		if leading_zeros == NUM_BUCKETS {
+			None
+		} else {
+			Some(NUM_BUCKETS - 1 - leading_zeros)
+		}
+	}
+}
+
+/// Routing table of Kademlia. Associates a value of type `V` to each peer, usually the list of
+/// its addresses.
+#[derive(Debug, Clone)]
+pub struct KBucketsTable<V> {
+	local_peer_id: PeerId,
+	local_key: KadKey,
+	buckets: Vec<KBucket<V>>,
+	max_entries_per_bucket: usize,
+}
+
+#[derive(Debug, Clone)]
+struct KBucket<V> {
+	// Entries of the bucket, from the least recently seen to the most recently seen.
+	entries: Vec<(PeerId, V)>,
+	// Peer waiting for a place in the bucket while the bucket is full.
+	pending: Option<(PeerId, V)>,
+	// Last time a peer has been added to the bucket or a lookup targeted it.
+	last_refresh: Instant,
+}
+
+/// Outcome of `KBucketsTable::update`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateOutcome {
+	/// The peer has been added to its bucket.
+	Added,
+	/// The peer was already in its bucket. Its value has been replaced and it is now considered
+	/// as the most recently seen peer of the bucket.
+	Updated,
+	/// The bucket of the peer is full. The peer has become the pending entry of the bucket,
+	/// replacing the previous one if any. Contains the least recently seen peer of the bucket: if
+	/// it is removed with `remove`, for example because it turns out to be unreachable, the
+	/// pending entry takes its place. If it is passed to `update` instead, the pending entry is
+	/// discarded.
+	Full(PeerId),
+	/// The peer is the local peer, which is never added to the table.
+	SelfEntry,
+}
+
+impl<V> KBucketsTable<V> {
+	/// Builds an empty routing table around `local_peer_id`. Each bucket contains at most
+	/// `max_entries_per_bucket` peers, which corresponds to the `k` parameter of Kademlia.
+	pub fn new(local_peer_id: PeerId, max_entries_per_bucket: usize) -> KBucketsTable<V> {
+		let now = Instant::now();
+		KBucketsTable {
+			local_key: KadKey::from_peer_id(&local_peer_id),
+			local_peer_id: local_peer_id,
+			buckets: (0 .. NUM_BUCKETS)
+				.map(|_| KBucket { entries: Vec::new(), pending: None, last_refresh: now })
+				.collect(),
+			max_entries_per_bucket: max_entries_per_bucket,
+		}
+	}
+
+	/// Returns the ID of the local peer.
+	#[inline]
+	pub fn local_peer_id(&self) -> &PeerId {
+		&self.local_peer_id
+	}
+
+	/// Returns the position of the local peer in the keyspace.
+	#[inline]
+	pub fn local_key(&self) -> &KadKey {
+		&self.local_key
+	}
+
+	/// Returns the maximum number of entries of a bucket.
+	#[inline]
+	pub fn max_entries_per_bucket(&self) -> usize {
+		self.max_entries_per_bucket
+	}
+
+	/// Inserts a peer in the table or updates its value, and marks it as the most recently seen
+	/// peer of its bucket. If the bucket is full, the peer becomes the pending entry of the bucket
+	/// instead.
+	pub fn update(&mut self, peer_id: PeerId, value: V) -> UpdateOutcome {
+		let index = match self.bucket_index(&peer_id) {
+			Some(index) => index,
+			None => return UpdateOutcome::SelfEntry,
+		};
+
+		let max_entries = self.max_entries_per_bucket;
+		let bucket = &mut self.buckets[index];
+		if let Some(pos) = bucket.entries.iter().position(|&(ref id, _)| *id == peer_id) {
+			let (id, _) = bucket.entries.remove(pos);
+			bucket.entries.push((id, value));
+			// The least recently seen peer is alive, therefore it keeps its place.
+			if pos == 0 {
+				bucket.pending = None;
+			}
+			return UpdateOutcome::Updated;
+		}
+
+		if bucket.entries.len() >= max_entries {
+			bucket.pending = Some((peer_id, value));
+			return UpdateOutcome::Full(bucket.entries[0].0.clone());
+		}
+
+		bucket.entries.push((peer_id, value));
+		bucket.last_refresh = Instant::now();
+		UpdateOutcome::Added
+	}
+
+	/// Returns the value of a peer, if it is in the table.
+	pub fn get(&self, peer_id: &PeerId) -> Option<&V> {
+		let index = self.bucket_index(peer_id)?;
+		self.buckets[index].entries.iter()
+			.find(|&&(ref id, _)| id == peer_id)
+			.map(|&(_, ref value)| value)
+	}
+
+	/// Returns the value of a peer, if it is in the table. Doesn't change the order of the
+	/// entries of the bucket.
+	pub fn get_mut(&mut self, peer_id: &PeerId) -> Option<&mut V> {
+		let index = self.bucket_index(peer_id)?;
+		self.buckets[index].entries.iter_mut()
+			.find(|&&mut (ref id, _)| id == peer_id)
+			.map(|&mut (_, ref mut value)| value)
+	}
+
+	/// Removes a peer from the table, or discards it if it is the pending entry of its bucket.
+	/// Returns its value if it was in the table.
+	///
+	/// If the bucket of the peer has a pending entry, the pending entry takes the place of the
+	/// removed peer as the most recently seen peer of the bucket.
+	pub fn remove(&mut self, peer_id: &PeerId) -> Option<V> {
+		let index = self.bucket_index(peer_id)?;
+		let bucket = &mut self.buckets[index];
+		let pos = match bucket.entries.iter().position(|&(ref id, _)| id == peer_id) {
+			Some(pos) => pos,
+			None => {
+				if bucket.pending.as_ref().map_or(false, |&(ref id, _)| id == peer_id) {
+					return bucket.pending.take().map(|(_, value)| value);
+				}
+				return None;
+			},
+		};
+
+		let (_, value) = bucket.entries.remove(pos);
+		if let Some(pending) = bucket.pending.take() {
+			bucket.entries.push(pending);
+			bucket.last_refresh = Instant::now();
+		}
+		Some(value)
+	}
+
+	/// Marks the bucket that contains `target` as refreshed, meaning that the closest peers to a
+	/// key in this bucket have just been looked up.
+	pub fn mark_refreshed(&mut self, target: &KadKey) {
+		if let Some(index) = self.local_key.distance(target).bucket_index() {
+			self.buckets[index].last_refresh = Instant::now();
+		}
+	}
+
+	/// Returns the indices of the buckets that haven't been refreshed or haven't received a new
+	/// peer for `max_age`.
+	///
+	/// The buckets closer than the closest non-empty bucket are ignored, since they are most
+	/// likely empty for a good reason. Returns nothing if the table is empty.
+	pub fn buckets_to_refresh(&self, max_age: Duration) -> Vec<usize> {
+		let closest = match self.buckets.iter().position(|bucket| !bucket.entries.is_empty()) {
+			Some(index) => index,
+			None => return Vec::new(),
+		};
+
+		let now = Instant::now();
+		(closest .. NUM_BUCKETS)
+			.filter(|&index| now.duration_since(self.buckets[index].last_refresh) >= max_age)
+			.collect()
+	}
+
+	/// Returns a random key whose position is in the bucket `index`, or `None` if generating one
+	/// would take too long. Only the 16 furthest buckets are supported, which is enough unless
+	/// the network contains millions of nodes. The key is generated with `rng`, so that the same
+	/// seed produces the same keys.
+	pub fn random_key_in_bucket<R>(&self, index: usize, rng: &mut R) -> Option<Vec<u8>>
+		where R: Rng
+	{
+		if index >= NUM_BUCKETS || index < NUM_BUCKETS - RANDOM_KEY_BUCKETS {
+			return None;
+		}
+
+		// A random key is in the bucket with a probability of 2^(index - 256). Trying 16 times
+		// the expected number of attempts makes a failure extremely unlikely.
+		let attempts = 16usize << (NUM_BUCKETS - index);
+		for _ in 0 .. attempts {
+			let key: [u8; 32] = rng.gen();
+			if self.local_key.distance(&KadKey::new(&key)).bucket_index() == Some(index) {
+				return Some(key.to_vec());
+			}
+		}
+
+		None
+	}
+
+	/// Returns the number of peers in the table.
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.buckets.iter().map(|bucket| bucket.entries.len()).sum()
+	}
+
+	/// Returns true if the table doesn't contain any peer.
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.buckets.iter().all(|bucket| bucket.entries.is_empty())
+	}
+
+	/// Returns an iterator to all the peers of the table and their values.
+	#[inline]
+	pub fn iter(&self) -> KBucketsIter<V> {
+		KBucketsIter {
+			buckets: self.buckets.iter(),
+			current: None,
+		}
+	}
+
+	/// Returns the `count` peers of the table that are the closest to `target`, from the closest
+	/// to the furthest.
+	pub fn find_closest(&self, target: &KadKey, count: usize) -> Vec<PeerId> {
+		let mut peers = self.iter()
+			.map(|(peer_id, _)| (target.distance(&KadKey::from_peer_id(peer_id)), peer_id))
+			.collect::<Vec<_>>();
+		peers.sort_by(|a, b| a.0.cmp(&b.0));
+		peers.into_iter().take(count).map(|(_, peer_id)| peer_id.clone()).collect()
+	}
+
+	// Returns the index of the bucket of `peer_id`, or `None` if it is the local peer.
+	#[inline]
+	fn bucket_index(&self, peer_id: &PeerId) -> Option<usize> {
+		self.local_key.distance(&KadKey::from_peer_id(peer_id)).bucket_index()
+	}
+}
+
+/// Iterator to the entries of a `KBucketsTable`.
+pub struct KBucketsIter<'a, V: 'a> {
+	buckets: ::std::slice::Iter<'a, KBucket<V>>,
+	current: Option<::std::slice::Iter<'a, (PeerId, V)>>,
+}
+
+impl<'a, V> Iterator for KBucketsIter<'a, V> {
+	type Item = (&'a PeerId, &'a V);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			if let Some(ref mut current) = self.current {
+				if let Some(&(ref peer_id, ref value)) = current.next() {
+					return Some((peer_id, value));
+				}
+			}
+			let bucket = self.buckets.next()?;
+			self.current = Some(bucket.entries.iter());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{KadKey, KBucketsTable, UpdateOutcome};
+	use libp2p_swarm::PeerId;
+	use rand::{self, SeedableRng, XorShiftRng};
+	use std::time::Duration;
+
+	fn peer(n: u32) -> PeerId {
+		let bytes = [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8];
+		PeerId::from_public_key(&bytes)
+	}
+
+	#[test]
+	fn distance_and_bucket_index() {
+		let key = KadKey::new(b"hello");
+		assert_eq!(key.distance(&key).bucket_index(), None);
+		assert_eq!(key.distance(&KadKey::new(b"world")), KadKey::new(b"world").distance(&key));
+
+		let mut other = *key.as_bytes();
+		other[31] ^= 1;
+		assert_eq!(key.distance(&KadKey(other)).bucket_index(), Some(0));
+		other[0] ^= 0x80;
+		assert_eq!(key.distance(&KadKey(other)).bucket_index(), Some(255));
+	}
+
+	#[test]
+	fn update_and_remove() {
+		let local = peer(0);
+		let mut table = KBucketsTable::new(local.clone(), 20);
+		assert_eq!(table.update(local, ()), UpdateOutcome::SelfEntry);
+		assert_eq!(table.update(peer(1), ()), UpdateOutcome::Added);
+		assert_eq!(table.update(peer(1), ()), UpdateOutcome::Updated);
+		assert_eq!(table.len(), 1);
+		assert!(table.get(&peer(1)).is_some());
+		assert_eq!(table.remove(&peer(1)), Some(()));
+		assert!(table.is_empty());
+	}
+
+	#[test]
+	fn full_bucket() {
+		let local = peer(0);
+		let mut table = KBucketsTable::new(local, 2);
+
+		// Half of the keyspace is in the furthest bucket, therefore filling it is quick.
+		let in_furthest = (1 ..)
+			.map(peer)
+			.filter(|p| table.bucket_index(p) == Some(255))
+			.take(3)
+			.collect::<Vec<_>>();
+		let (first, second, third) = (in_furthest[0].clone(), in_furthest[1].clone(),
+									  in_furthest[2].clone());
+
+		assert_eq!(table.update(first.clone(), ()), UpdateOutcome::Added);
+		assert_eq!(table.update(second.clone(), ()), UpdateOutcome::Added);
+		assert_eq!(table.update(third.clone(), ()), UpdateOutcome::Full(first.clone()));
+		// Seeing the first peer again makes the second one the least recently seen.
+		assert_eq!(table.update(first, ()), UpdateOutcome::Updated);
+		assert_eq!(table.update(third, ()), UpdateOutcome::Full(second));
+	}
+
+	#[test]
+	fn pending_entry() {
+		let local = peer(0);
+		let mut table = KBucketsTable::new(local, 2);
+
+		let p = (1 ..)
+			.map(peer)
+			.filter(|p| table.bucket_index(p) == Some(255))
+			.take(4)
+			.collect::<Vec<_>>();
+		table.update(p[0].clone(), 0);
+		table.update(p[1].clone(), 1);
+
+		// The oldest peer is seen again, therefore the pending entry is discarded.
+		assert_eq!(table.update(p[2].clone(), 2), UpdateOutcome::Full(p[0].clone()));
+		assert_eq!(table.update(p[0].clone(), 0), UpdateOutcome::Updated);
+		assert_eq!(table.remove(&p[2]), None);
+
+		// The oldest peer is removed, therefore the pending entry takes its place.
+		assert_eq!(table.update(p[3].clone(), 3), UpdateOutcome::Full(p[1].clone()));
+		assert!(table.get(&p[3]).is_none());
+		assert_eq!(table.remove(&p[1]), Some(1));
+		assert_eq!(table.get(&p[3]), Some(&3));
+		assert_eq!(table.len(), 2);
+	}
+
+	#[test]
+	fn find_closest() {
+		let mut table = KBucketsTable::new(peer(0), 20);
+		for n in 1 .. 100 {
+			table.update(peer(n), ());
+		}
+
+		let target = KadKey::new(b"target");
+		let closest = table.find_closest(&target, 10);
+		assert_eq!(closest.len(), 10);
+		for pair in closest.windows(2) {
+			let a = target.distance(&KadKey::from_peer_id(&pair[0]));
+			let b = target.distance(&KadKey::from_peer_id(&pair[1]));
+			assert!(a < b);
+		}
+		let furthest = target.distance(&KadKey::from_peer_id(&closest[9]));
+		for (peer_id, _) in table.iter() {
+			if !closest.contains(peer_id) {
+				assert!(target.distance(&KadKey::from_peer_id(peer_id)) > furthest);
+			}
+		}
+	}
+
+	#[test]
+	fn refresh() {
+		let local = peer(0);
+		let mut table = KBucketsTable::new(local, 20);
+		assert!(table.buckets_to_refresh(Duration::from_secs(0)).is_empty());
+
+		let far = (1 ..).map(peer).find(|p| table.bucket_index(p) == Some(255)).unwrap();
+		table.update(far, ());
+		assert_eq!(table.buckets_to_refresh(Duration::from_secs(0)), vec![255]);
+		assert!(table.buckets_to_refresh(Duration::from_secs(3600)).is_empty());
+
+		let mut rng = rand::thread_rng();
+		for &index in &[240, 250, 255] {
+			let key = table.random_key_in_bucket(index, &mut rng).unwrap();
+			let distance = table.local_key().distance(&KadKey::new(&key));
+			assert_eq!(distance.bucket_index(), Some(index));
+		}
+		assert!(table.random_key_in_bucket(0, &mut rng).is_none());
+		assert!(table.random_key_in_bucket(256, &mut rng).is_none());
+	}
+
+	#[test]
+	fn same_seed_same_random_key() {
+		let table = KBucketsTable::<()>::new(peer(0), 20);
+		let keys = (0 .. 2)
+			.map(|_| {
+				let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+				table.random_key_in_bucket(250, &mut rng).unwrap()
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(keys[0], keys[1]);
+	}
+}
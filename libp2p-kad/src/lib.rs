@@ -0,0 +1,171 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+// TODO: use this once stable ; for now we just copy-paste the content of the README.md
+//#![doc(include = "../README.md")]
+
+//! Implementation of the Kademlia distributed hash table, compatible with the `/ipfs/kad/1.0.0`
+//! protocol of the other implementations of libp2p.
+//!
+//! Kademlia lets a node discover the peers whose identity is the closest to an arbitrary key,
+//! where the distance between a peer and a key is the XOR of their SHA-256 hashes. Each node
+//! keeps a routing table of the peers it knows, organized in *k-buckets*, and looks up a key by
+//! repeatedly asking the closest peers it knows of for even closer peers.
+//!
+//! # Usage
+//!
+//! This crate is made of several layers:
+//!
+//! - `KBucketsTable` is the routing table, and `QueryState` is the state machine of an iterative
+//!   lookup. They don't perform any I/O and can be used on their own.
+//! - `KademliaProtocolConfig` is the upgrade for the protocol, and `KadRequestMsg` and
+//!   `KadResponseMsg` are the messages that the nodes exchange.
+//! - `KademliaHandler` implements the `ProtocolsHandler` trait of `libp2p-swarm`. It sends the
+//!   requests to the remote of a connection, and reports the requests of the remote.
+//! - `Kademlia` ties everything together. It contains the routing table and the queries in
+//!   progress, answers the requests of the remotes, and produces `KademliaAction`s that tell
+//!   the user which peers to dial and which events to pass to the handlers.
+//!
+//! The user is responsible for the connections: dialing the peers requested by a
+//! `KademliaAction::Dial`, driving the `KademliaHandler` built by `Kademlia::new_handler` on each
+//! connection, and passing the events of the handlers to `Kademlia::inject_node_event`.
+//!
+//! ```
+//! extern crate libp2p_kad;
+//! extern crate libp2p_swarm;
+//! extern crate tokio_core;
+//!
+//! use libp2p_kad::Kademlia;
+//! use libp2p_swarm::PeerId;
+//!
+//! # fn main() {
+//! let core = tokio_core::reactor::Core::new().unwrap();
+//! let local_peer_id = PeerId::from_public_key(&[1, 2, 3, 4]);
+//! let mut kademlia = Kademlia::new(local_peer_id, core.handle());
+//!
+//! // The address of a bootstrap node.
+//! let bootstrap = PeerId::from_public_key(&[5, 6, 7, 8]);
+//! kademlia.add_address(&bootstrap, "/ip4/1.2.3.4/tcp/4001".parse().unwrap());
+//!
+//! // Looks for the peers that are the closest to a key. The result is produced by `poll()` as
+//! // a `KademliaEvent::FindNodeResult`.
+//! let query_id = kademlia.find_node(b"some key".to_vec());
+//! # }
+//! ```
+//!
+//! Peers are only added to the routing table when they are passed to `add_address`, or when
+//! they answer one of our requests. Peers that merely send us requests aren't added, since
+//! nothing proves that they are reachable.
+//!
+//! When a bucket is full, the new peer is kept aside while the least recently seen peer of the
+//! bucket is pinged, and only takes its place if it doesn't answer. The peers that can't be
+//! dialed or that fail to answer a request are removed from the routing table.
+//!
+//! # Configuration
+//!
+//! The number of requests in progress at the same time for a query (α), the size of the
+//! buckets and number of peers returned by the queries (k), and the timeouts of the queries and
+//! of the individual requests can be changed by building the `Kademlia` with
+//! `Kademlia::with_config`. The defaults are tailored for the public IPFS network, which is large
+//! and contains many unreachable nodes. A small private network can use a lower k and much
+//! shorter timeouts, so that failed requests don't slow the queries down.
+//!
+//! # Client mode
+//!
+//! Nodes that can't usefully serve the DHT, for example because they are behind a NAT or have
+//! few resources, can set `KademliaConfig::client_mode`. Such a node performs queries like any
+//! other node, but the handlers built by `Kademlia::new_handler` reject the requests of the
+//! remotes. Since the remotes only add the peers that answer their requests to their routing
+//! table, the node is never advertised to the rest of the network.
+//!
+//! # Bootstrapping
+//!
+//! A new node only knows the bootstrap nodes passed to `add_address`. Calling
+//! `Kademlia::bootstrap` looks up the local node in order to discover its neighbours, then fills
+//! the rest of the routing table by looking up random keys. Afterwards, the buckets that
+//! haven't been used by a query for a while are refreshed periodically with random lookups, so
+//! that the routing table of long-running nodes doesn't only contain dead peers.
+//!
+//! A node can also lose its connectivity after bootstrapping, for example behind a broken NAT.
+//! `Kademlia::with_partition_detector` passes the outcome of every query to a
+//! `PartitionDetector` of `libp2p-swarm`, a query being a failure when none of the peers it
+//! contacted answered. The detector reports `KademliaEvent::Partition` when too many queries
+//! fail, which suggests that the node has been cut off from the rest of the network.
+//!
+//! # Content routing
+//!
+//! Besides looking up peers, Kademlia can be used to find the nodes that provide a piece of
+//! content. `Kademlia::start_providing` announces that the local node provides a key, by
+//! sending an `ADD_PROVIDER` request to the closest peers to that key. The announcement is
+//! repeated periodically until `stop_providing` is called, since the remotes forget the
+//! provider records after some time.
+//!
+//! `Kademlia::get_providers` looks up the providers of a key, and reports them with their
+//! addresses as a `KademliaEvent::GetProvidersResult`. Don't forget to call `set_listen_addrs`,
+//! otherwise the nodes that find us as a provider have no way to connect to us.
+//!
+//! # Records
+//!
+//! Small records can be stored in the DHT with `Kademlia::put_value`, which sends them to the
+//! closest peers to their key, and retrieved with `get_value`. Since anyone can store a record,
+//! the records are checked by a `RecordValidator` before being stored or returned, and the
+//! validator chooses the best record when several values are found for the same key.
+//!
+//! By default, only the public keys of the peers are accepted, under `/pk/<peer id>`. Use
+//! `Kademlia::with_record_validator` to accept other records, usually with a
+//! `NamespacedValidator` that dispatches the keys to a validator depending on their prefix.
+//!
+//! `get_value` takes a quorum, which is the number of valid values to gather before the query
+//! stops. A higher quorum makes it harder for a few malicious nodes to hide the best record.
+//!
+//! The records and the provider records are kept in a `RecordStore`. The default is a
+//! `MemoryRecordStore`, which limits the number and the size of the records so that the remotes
+//! can't exhaust the memory of the node. Nodes that store many records can use
+//! `Kademlia::with_record_store` to keep them elsewhere, for example in a database so that they
+//! survive restarts.
+
+extern crate bytes;
+extern crate futures;
+extern crate libp2p_swarm;
+extern crate multiaddr;
+extern crate multihash;
+extern crate rand;
+extern crate tokio_core;
+extern crate tokio_io;
+extern crate varint;
+
+pub use self::behaviour::{Kademlia, KademliaAction, KademliaConfig, KademliaEvent, QueryId};
+pub use self::handler::{KademliaHandler, KademliaHandlerEvent, KademliaHandlerIn};
+pub use self::handler::KademliaRequestId;
+pub use self::kbucket::{Distance, KadKey, KBucketsIter, KBucketsTable, UpdateOutcome};
+pub use self::protocol::{KadConnectionType, KadPeer, KadRecord, KadRequestMsg, KadResponseMsg};
+pub use self::protocol::{KadSubstream, KademliaProtocolConfig};
+pub use self::query::{QueryConfig, QueryState, QueryStatePollOut};
+pub use self::record::{NamespacedValidator, PublicKeyValidator, RecordValidator};
+pub use self::store::{MemoryRecordStore, MemoryRecordStoreConfig, RecordStore};
+pub use self::store::{StoredProvider, StoredRecord};
+
+mod behaviour;
+mod handler;
+mod kbucket;
+mod protocol;
+mod query;
+mod record;
+mod store;
@@ -0,0 +1,727 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `KademliaProtocolConfig` upgrade, and the messages of the `/ipfs/kad/1.0.0`
+//! protocol.
+//!
+//! Each request is sent on a new substream. The dialer sends a single request, the listener
+//! answers with a single response, and the substream is then closed. Messages are `Message`
+//! protobuf messages prefixed with their length as a varint:
+//!
+//! ```text
+//! message Message {
+//!     enum MessageType {
+//!         PUT_VALUE = 0; GET_VALUE = 1; ADD_PROVIDER = 2; GET_PROVIDERS = 3; FIND_NODE = 4;
+//!         PING = 5;
+//!     }
+//!     enum ConnectionType {
+//!         NOT_CONNECTED = 0; CONNECTED = 1; CAN_CONNECT = 2; CANNOT_CONNECT = 3;
+//!     }
+//!     message Peer {
+//!         bytes id = 1;
+//!         repeated bytes addrs = 2;
+//!         ConnectionType connection = 3;
+//!     }
+//!     message Record {
+//!         bytes key = 1;
+//!         bytes value = 2;
+//!         string timeReceived = 5;
+//!     }
+//!     MessageType type = 1;
+//!     bytes key = 2;
+//!     Record record = 3;
+//!     repeated Peer closerPeers = 8;
+//!     repeated Peer providerPeers = 9;
+//! }
+//! ```
+//!
+//! The only exception is `ADD_PROVIDER`, which doesn't have any response.
+
+use bytes::Bytes;
+use futures::{future, Future, Sink, Stream};
+use libp2p_swarm::{ConnectionUpgrade, Endpoint, PeerId};
+use multiaddr::{AddrComponent, Multiaddr};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::iter;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::codec::Framed;
+use varint::VarintCodec;
+
+// Values of the `MessageType` enum.
+const MSG_PUT_VALUE: u64 = 0;
+const MSG_GET_VALUE: u64 = 1;
+const MSG_ADD_PROVIDER: u64 = 2;
+const MSG_GET_PROVIDERS: u64 = 3;
+const MSG_FIND_NODE: u64 = 4;
+const MSG_PING: u64 = 5;
+
+/// Implementation of the `ConnectionUpgrade` trait for the `/ipfs/kad/1.0.0` protocol.
+///
+/// The output is the substream with the framing of the protocol. The requests and the responses
+/// are exchanged on it by the `KademliaHandler`.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct KademliaProtocolConfig;
+
+/// Substream on which the `/ipfs/kad/1.0.0` protocol has been negotiated.
+pub type KadSubstream<C> = Framed<C, VarintCodec<Vec<u8>>>;
+
+impl<C> ConnectionUpgrade<C> for KademliaProtocolConfig
+	where C: AsyncRead + AsyncWrite + 'static
+{
+	type NamesIter = iter::Once<(Bytes, ())>;
+	type UpgradeIdentifier = ();
+	type Output = KadSubstream<C>;
+	type Future = future::FutureResult<Self::Output, IoError>;
+
+	#[inline]
+	fn protocol_names(&self) -> Self::NamesIter {
+		iter::once((Bytes::from("/ipfs/kad/1.0.0"), ()))
+	}
+
+	#[inline]
+	fn upgrade(self, socket: C, _: (), _: Endpoint, _: &Multiaddr) -> Self::Future {
+		future::ok(socket.framed(VarintCodec::default()))
+	}
+}
+
+/// Request that a node sends to a remote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KadRequestMsg {
+	/// Checks whether the remote is alive.
+	Ping,
+	/// Asks the remote for the peers of its routing table that are the closest to `key`.
+	FindNode {
+		/// Key to find the closest peers of. Usually the bytes of a `PeerId`, but can be
+		/// anything.
+		key: Vec<u8>,
+	},
+	/// Asks the remote for the providers of `key` that it knows of, and for the peers of its
+	/// routing table that are the closest to `key`.
+	GetProviders {
+		/// Key to find the providers of.
+		key: Vec<u8>,
+	},
+	/// Indicates to the remote that we provide `key`. Doesn't have any response.
+	AddProvider {
+		/// Key that is provided.
+		key: Vec<u8>,
+		/// The provider, which must be the sender of the message.
+		provider: KadPeer,
+	},
+	/// Asks the remote for the record stored under `key`, and for the peers of its routing
+	/// table that are the closest to `key`.
+	GetValue {
+		/// Key of the record.
+		key: Vec<u8>,
+	},
+	/// Asks the remote to store a record.
+	PutValue {
+		/// The record to store.
+		record: KadRecord,
+	},
+}
+
+/// Response to a `KadRequestMsg`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KadResponseMsg {
+	/// Response to a `Ping`.
+	Pong,
+	/// Response to a `FindNode`.
+	FindNode {
+		/// Peers of the routing table of the remote that are the closest to the key.
+		closer_peers: Vec<KadPeer>,
+	},
+	/// Response to a `GetProviders`.
+	GetProviders {
+		/// Peers of the routing table of the remote that are the closest to the key.
+		closer_peers: Vec<KadPeer>,
+		/// Providers of the key known by the remote.
+		provider_peers: Vec<KadPeer>,
+	},
+	/// Response to a `GetValue`.
+	GetValue {
+		/// The record stored by the remote under the key, if any.
+		record: Option<KadRecord>,
+		/// Peers of the routing table of the remote that are the closest to the key.
+		closer_peers: Vec<KadPeer>,
+	},
+	/// Response to a `PutValue`, sent if the remote has accepted the record.
+	PutValue {
+		/// The record that was stored.
+		record: KadRecord,
+	},
+}
+
+/// Record stored in the DHT.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KadRecord {
+	/// Key of the record.
+	pub key: Vec<u8>,
+	/// Value of the record.
+	pub value: Vec<u8>,
+}
+
+/// Information about a peer, as transmitted in the messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KadPeer {
+	/// Identifier of the peer.
+	pub node_id: PeerId,
+	/// Addresses of the peer. The addresses that we can't parse are skipped.
+	pub multiaddrs: Vec<Multiaddr>,
+	/// Whether the sender of the message is connected to the peer.
+	pub connection_ty: KadConnectionType,
+}
+
+/// Status of the connection between the sender of a message and a peer of the message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum KadConnectionType {
+	/// Not connected, and no information about whether the peer is reachable.
+	NotConnected,
+	/// Currently connected.
+	Connected,
+	/// Recently connected.
+	CanConnect,
+	/// Recently failed to connect.
+	CannotConnect,
+}
+
+impl KadConnectionType {
+	// Value in the `ConnectionType` protobuf enum.
+	#[inline]
+	fn to_proto(self) -> u64 {
+		match self {
+			KadConnectionType::NotConnected => 0,
+			KadConnectionType::Connected => 1,
+			KadConnectionType::CanConnect => 2,
+			KadConnectionType::CannotConnect => 3,
+		}
+	}
+
+	// Unknown values are considered as `NotConnected`.
+	#[inline]
+	fn from_proto(value: u64) -> KadConnectionType {
+		match value {
+			1 => KadConnectionType::Connected,
+			2 => KadConnectionType::CanConnect,
+			3 => KadConnectionType::CannotConnect,
+			_ => KadConnectionType::NotConnected,
+		}
+	}
+}
+
+impl KadRequestMsg {
+	/// Parses the protobuf message of a request.
+	pub fn from_bytes(bytes: &[u8]) -> Result<KadRequestMsg, IoError> {
+		let message = RawMessage::decode(bytes)?;
+		match message.ty {
+			MSG_PING => Ok(KadRequestMsg::Ping),
+			MSG_FIND_NODE => Ok(KadRequestMsg::FindNode { key: message.key }),
+			MSG_GET_PROVIDERS => Ok(KadRequestMsg::GetProviders { key: message.key }),
+			MSG_ADD_PROVIDER => {
+				let provider = message.provider_peers.into_iter().next()
+					.ok_or_else(|| invalid_message("missing provider"))?;
+				Ok(KadRequestMsg::AddProvider { key: message.key, provider: provider })
+			},
+			MSG_GET_VALUE => Ok(KadRequestMsg::GetValue { key: message.key }),
+			MSG_PUT_VALUE => {
+				let record = message.record.ok_or_else(|| invalid_message("missing record"))?;
+				if record.key != message.key {
+					return Err(invalid_message("the key of the record doesn't match"));
+				}
+				Ok(KadRequestMsg::PutValue { record: record })
+			},
+			_ => Err(unsupported_type()),
+		}
+	}
+
+	/// Returns true if the remote answers this request. Only `AddProvider` doesn't have any
+	/// response.
+	#[inline]
+	pub fn expects_response(&self) -> bool {
+		match *self {
+			KadRequestMsg::AddProvider { .. } => false,
+			_ => true,
+		}
+	}
+
+	/// Serializes the request into its protobuf message.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut out = Vec::new();
+		match *self {
+			KadRequestMsg::Ping => write_varint_field(&mut out, 1, MSG_PING),
+			KadRequestMsg::FindNode { ref key } => {
+				write_varint_field(&mut out, 1, MSG_FIND_NODE);
+				write_bytes_field(&mut out, 2, key);
+			},
+			KadRequestMsg::GetProviders { ref key } => {
+				write_varint_field(&mut out, 1, MSG_GET_PROVIDERS);
+				write_bytes_field(&mut out, 2, key);
+			},
+			KadRequestMsg::AddProvider { ref key, ref provider } => {
+				write_varint_field(&mut out, 1, MSG_ADD_PROVIDER);
+				write_bytes_field(&mut out, 2, key);
+				write_bytes_field(&mut out, 9, &provider.to_bytes());
+			},
+			KadRequestMsg::GetValue { ref key } => {
+				write_varint_field(&mut out, 1, MSG_GET_VALUE);
+				write_bytes_field(&mut out, 2, key);
+			},
+			KadRequestMsg::PutValue { ref record } => {
+				write_varint_field(&mut out, 1, MSG_PUT_VALUE);
+				write_bytes_field(&mut out, 2, &record.key);
+				write_bytes_field(&mut out, 3, &record.to_bytes());
+			},
+		}
+		out
+	}
+}
+
+impl KadResponseMsg {
+	/// Parses the protobuf message of a response.
+	pub fn from_bytes(bytes: &[u8]) -> Result<KadResponseMsg, IoError> {
+		let message = RawMessage::decode(bytes)?;
+		match message.ty {
+			MSG_PING => Ok(KadResponseMsg::Pong),
+			MSG_FIND_NODE => Ok(KadResponseMsg::FindNode { closer_peers: message.closer_peers }),
+			MSG_GET_PROVIDERS => Ok(KadResponseMsg::GetProviders {
+				closer_peers: message.closer_peers,
+				provider_peers: message.provider_peers,
+			}),
+			MSG_GET_VALUE => Ok(KadResponseMsg::GetValue {
+				record: message.record,
+				closer_peers: message.closer_peers,
+			}),
+			MSG_PUT_VALUE => {
+				let record = message.record.ok_or_else(|| invalid_message("missing record"))?;
+				Ok(KadResponseMsg::PutValue { record: record })
+			},
+			_ => Err(unsupported_type()),
+		}
+	}
+
+	/// Serializes the response into its protobuf message.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut out = Vec::new();
+		match *self {
+			KadResponseMsg::Pong => write_varint_field(&mut out, 1, MSG_PING),
+			KadResponseMsg::FindNode { ref closer_peers } => {
+				write_varint_field(&mut out, 1, MSG_FIND_NODE);
+				for peer in closer_peers {
+					write_bytes_field(&mut out, 8, &peer.to_bytes());
+				}
+			},
+			KadResponseMsg::GetProviders { ref closer_peers, ref provider_peers } => {
+				write_varint_field(&mut out, 1, MSG_GET_PROVIDERS);
+				for peer in closer_peers {
+					write_bytes_field(&mut out, 8, &peer.to_bytes());
+				}
+				for peer in provider_peers {
+					write_bytes_field(&mut out, 9, &peer.to_bytes());
+				}
+			},
+			KadResponseMsg::GetValue { ref record, ref closer_peers } => {
+				write_varint_field(&mut out, 1, MSG_GET_VALUE);
+				if let Some(ref record) = *record {
+					write_bytes_field(&mut out, 2, &record.key);
+					write_bytes_field(&mut out, 3, &record.to_bytes());
+				}
+				for peer in closer_peers {
+					write_bytes_field(&mut out, 8, &peer.to_bytes());
+				}
+			},
+			KadResponseMsg::PutValue { ref record } => {
+				write_varint_field(&mut out, 1, MSG_PUT_VALUE);
+				write_bytes_field(&mut out, 2, &record.key);
+				write_bytes_field(&mut out, 3, &record.to_bytes());
+			},
+		}
+		out
+	}
+}
+
+impl KadRecord {
+	// Serializes the record into a `Record` protobuf message.
+	fn to_bytes(&self) -> Vec<u8> {
+		let mut out = Vec::new();
+		write_bytes_field(&mut out, 1, &self.key);
+		write_bytes_field(&mut out, 2, &self.value);
+		out
+	}
+
+	// Parses a `Record` protobuf message. The time at which the remote received the record is
+	// ignored.
+	fn from_bytes(bytes: &[u8]) -> Result<KadRecord, IoError> {
+		let mut record = KadRecord {
+			key: Vec::new(),
+			value: Vec::new(),
+		};
+
+		decode_fields(bytes, |field, value| {
+			match (field, value) {
+				(1, FieldValue::Bytes(key)) => record.key = key.to_owned(),
+				(2, FieldValue::Bytes(value)) => record.value = value.to_owned(),
+				_ => (),
+			}
+			Ok(())
+		})?;
+
+		Ok(record)
+	}
+}
+
+impl KadPeer {
+	// Serializes the peer into a `Peer` protobuf message.
+	fn to_bytes(&self) -> Vec<u8> {
+		let mut out = Vec::new();
+		write_bytes_field(&mut out, 1, self.node_id.as_bytes());
+		for addr in &self.multiaddrs {
+			write_bytes_field(&mut out, 2, &addr.to_bytes());
+		}
+		write_varint_field(&mut out, 3, self.connection_ty.to_proto());
+		out
+	}
+
+	// Parses a `Peer` protobuf message.
+	fn from_bytes(bytes: &[u8]) -> Result<KadPeer, IoError> {
+		let mut node_id = None;
+		let mut multiaddrs = Vec::new();
+		let mut connection_ty = KadConnectionType::NotConnected;
+
+		decode_fields(bytes, |field, value| {
+			match (field, value) {
+				(1, FieldValue::Bytes(id)) => {
+					let id = PeerId::from_bytes(id.to_owned())
+						.map_err(|_| invalid_message("invalid peer ID"))?;
+					node_id = Some(id);
+				},
+				(2, FieldValue::Bytes(addr)) => {
+					if let Some(addr) = multiaddr_from_bytes(addr) {
+						multiaddrs.push(addr);
+					}
+				},
+				(3, FieldValue::Varint(value)) => {
+					connection_ty = KadConnectionType::from_proto(value);
+				},
+				_ => (),
+			}
+			Ok(())
+		})?;
+
+		Ok(KadPeer {
+			node_id: node_id.ok_or_else(|| invalid_message("missing peer ID"))?,
+			multiaddrs: multiaddrs,
+			connection_ty: connection_ty,
+		})
+	}
+}
+
+// Fields of a `Message` protobuf message that we know about.
+struct RawMessage {
+	ty: u64,
+	key: Vec<u8>,
+	record: Option<KadRecord>,
+	closer_peers: Vec<KadPeer>,
+	provider_peers: Vec<KadPeer>,
+}
+
+impl RawMessage {
+	fn decode(bytes: &[u8]) -> Result<RawMessage, IoError> {
+		// The type is omitted by the encoder if it is 0, since this is the default value.
+		let mut message = RawMessage {
+			ty: 0,
+			key: Vec::new(),
+			record: None,
+			closer_peers: Vec::new(),
+			provider_peers: Vec::new(),
+		};
+
+		decode_fields(bytes, |field, value| {
+			match (field, value) {
+				(1, FieldValue::Varint(ty)) => message.ty = ty,
+				(2, FieldValue::Bytes(key)) => message.key = key.to_owned(),
+				(3, FieldValue::Bytes(record)) => {
+					message.record = Some(KadRecord::from_bytes(record)?);
+				},
+				(8, FieldValue::Bytes(peer)) => {
+					message.closer_peers.push(KadPeer::from_bytes(peer)?);
+				},
+				(9, FieldValue::Bytes(peer)) => {
+					message.provider_peers.push(KadPeer::from_bytes(peer)?);
+				},
+				_ => (),
+			}
+			Ok(())
+		})?;
+
+		Ok(message)
+	}
+}
+
+/// Sends a request on a substream that we opened, and waits for the response.
+pub(crate) fn send_request<C>(substream: KadSubstream<C>, request: &KadRequestMsg)
+							  -> Box<Future<Item = KadResponseMsg, Error = IoError>>
+	where C: AsyncRead + AsyncWrite + 'static
+{
+	let future = substream.send(request.to_bytes())
+		.and_then(|substream| substream.into_future().map_err(|(err, _)| err))
+		.and_then(|(response, _)| match response {
+			Some(response) => KadResponseMsg::from_bytes(&response),
+			None => Err(IoError::new(IoErrorKind::UnexpectedEof, "substream closed by remote")),
+		});
+	Box::new(future)
+}
+
+/// Sends a request that doesn't have any response on a substream that we opened.
+pub(crate) fn send_request_without_response<C>(substream: KadSubstream<C>,
+											   request: &KadRequestMsg)
+											   -> Box<Future<Item = (), Error = IoError>>
+	where C: AsyncRead + AsyncWrite + 'static
+{
+	Box::new(substream.send(request.to_bytes()).map(|_| ()))
+}
+
+/// Future that produces the request of the remote, and the substream to send the response on.
+pub(crate) type ReadRequestFuture<C> =
+	Future<Item = (KadRequestMsg, KadSubstream<C>), Error = IoError>;
+
+/// Waits for the request on a substream opened by the remote.
+pub(crate) fn read_request<C>(substream: KadSubstream<C>) -> Box<ReadRequestFuture<C>>
+	where C: AsyncRead + AsyncWrite + 'static
+{
+	let future = substream.into_future()
+		.map_err(|(err, _)| err)
+		.and_then(|(request, substream)| match request {
+			Some(request) => Ok((KadRequestMsg::from_bytes(&request)?, substream)),
+			None => Err(IoError::new(IoErrorKind::UnexpectedEof, "substream closed by remote")),
+		});
+	Box::new(future)
+}
+
+/// Sends the response to a request received with `read_request`.
+pub(crate) fn send_response<C>(substream: KadSubstream<C>, response: &KadResponseMsg)
+							   -> Box<Future<Item = (), Error = IoError>>
+	where C: AsyncRead + AsyncWrite + 'static
+{
+	Box::new(substream.send(response.to_bytes()).map(|_| ()))
+}
+
+// Value of a field of a protobuf message.
+enum FieldValue<'a> {
+	Varint(u64),
+	Bytes(&'a [u8]),
+}
+
+// Calls `f` for each field of a protobuf message. The fixed-size fields are skipped, as the
+// protocol doesn't use them.
+fn decode_fields<'a, F>(mut data: &'a [u8], mut f: F) -> Result<(), IoError>
+	where F: FnMut(u64, FieldValue<'a>) -> Result<(), IoError>
+{
+	while !data.is_empty() {
+		let key = read_varint(&mut data)?;
+		let value = match key & 0x7 {
+			0 => Some(FieldValue::Varint(read_varint(&mut data)?)),
+			1 | 5 => {
+				let len = if key & 0x7 == 1 { 8 } else { 4 };
+				if len > data.len() {
+					return Err(invalid_message("unexpected end of message"));
+				}
+				data = &data[len ..];
+				None
+			},
+			2 => {
+				let len = read_varint(&mut data)?;
+				if len > data.len() as u64 {
+					return Err(invalid_message("unexpected end of message"));
+				}
+				let (value, rest) = data.split_at(len as usize);
+				data = rest;
+				Some(FieldValue::Bytes(value))
+			},
+			_ => return Err(invalid_message("invalid wire type")),
+		};
+
+		if let Some(value) = value {
+			f(key >> 3, value)?;
+		}
+	}
+
+	Ok(())
+}
+
+// Decodes the binary representation of a multiaddress. Returns `None` if it is invalid or
+// contains protocols that we don't know.
+fn multiaddr_from_bytes(mut bytes: &[u8]) -> Option<Multiaddr> {
+	let mut components = Vec::new();
+	while !bytes.is_empty() {
+		let (component, rest) = AddrComponent::from_bytes(bytes).ok()?;
+		components.push(component);
+		bytes = rest;
+	}
+	Some(components.into_iter().collect())
+}
+
+fn write_varint_field(out: &mut Vec<u8>, field: u64, value: u64) {
+	write_varint(out, field << 3);
+	write_varint(out, value);
+}
+
+fn write_bytes_field(out: &mut Vec<u8>, field: u64, value: &[u8]) {
+	write_varint(out, (field << 3) | 2);
+	write_varint(out, value.len() as u64);
+	out.extend_from_slice(value);
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+	while value >= 0x80 {
+		out.push((value as u8) | 0x80);
+		value >>= 7;
+	}
+	out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64, IoError> {
+	let mut value = 0u64;
+	for shift in 0 .. 10 {
+		let remaining: &[u8] = *data;
+		let byte = match remaining.first() {
+			Some(&byte) => byte,
+			None => return Err(invalid_message("unexpected end of message")),
+		};
+		*data = &remaining[1 ..];
+		value |= u64::from(byte & 0x7f) << (7 * shift);
+		if byte & 0x80 == 0 {
+			return Ok(value);
+		}
+	}
+
+	Err(invalid_message("invalid varint"))
+}
+
+#[inline]
+fn invalid_message(msg: &str) -> IoError {
+	IoError::new(IoErrorKind::InvalidData, msg)
+}
+
+#[inline]
+fn unsupported_type() -> IoError {
+	invalid_message("unsupported message type")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{read_request, send_request, send_response};
+	use super::{KadConnectionType, KadPeer, KadRecord, KadRequestMsg, KadResponseMsg};
+	use super::KademliaProtocolConfig;
+	use futures::{Future, Stream};
+	use libp2p_swarm::{ConnectionUpgrade, Endpoint, PeerId};
+	use tokio_core::net::{TcpListener, TcpStream};
+	use tokio_core::reactor::Core;
+
+	#[test]
+	fn messages_roundtrip() {
+		let peer = KadPeer {
+			node_id: PeerId::from_public_key(&[1, 2, 3]),
+			multiaddrs: vec!["/ip4/1.2.3.4/tcp/4001".parse().unwrap()],
+			connection_ty: KadConnectionType::CanConnect,
+		};
+
+		let record = KadRecord { key: vec![7, 8], value: vec![9; 40] };
+
+		let requests = vec![
+			KadRequestMsg::Ping,
+			KadRequestMsg::GetValue { key: vec![7, 8] },
+			KadRequestMsg::PutValue { record: record.clone() },
+			KadRequestMsg::FindNode { key: vec![1, 2, 3] },
+			KadRequestMsg::GetProviders { key: vec![4, 5] },
+			KadRequestMsg::AddProvider { key: vec![6], provider: peer.clone() },
+		];
+		for request in requests {
+			assert_eq!(KadRequestMsg::from_bytes(&request.to_bytes()).unwrap(), request);
+		}
+
+		let responses = vec![
+			KadResponseMsg::Pong,
+			KadResponseMsg::FindNode { closer_peers: vec![peer.clone(), peer.clone()] },
+			KadResponseMsg::GetProviders {
+				closer_peers: vec![peer.clone()],
+				provider_peers: vec![peer.clone()],
+			},
+			KadResponseMsg::GetValue { record: None, closer_peers: vec![peer.clone()] },
+			KadResponseMsg::GetValue { record: Some(record.clone()), closer_peers: vec![peer] },
+			KadResponseMsg::PutValue { record: record },
+		];
+		for response in responses {
+			assert_eq!(KadResponseMsg::from_bytes(&response.to_bytes()).unwrap(), response);
+		}
+	}
+
+	#[test]
+	fn unknown_addr_skipped() {
+		let peer_id = PeerId::from_public_key(&[1, 2, 3]);
+		// `Peer` message with an address that uses an unknown protocol code, and a valid one.
+		let mut peer = vec![0x0a, peer_id.as_bytes().len() as u8];
+		peer.extend_from_slice(peer_id.as_bytes());
+		peer.extend_from_slice(&[0x12, 2, 0xff, 0x7f]);
+		peer.extend_from_slice(&[0x12, 5, 0x04, 1, 2, 3, 4]);
+
+		let mut message = vec![0x08, 0x04, 0x42, peer.len() as u8];
+		message.extend_from_slice(&peer);
+
+		match KadResponseMsg::from_bytes(&message).unwrap() {
+			KadResponseMsg::FindNode { closer_peers } => {
+				assert_eq!(closer_peers.len(), 1);
+				assert_eq!(closer_peers[0].node_id, peer_id);
+				assert_eq!(closer_peers[0].multiaddrs, vec!["/ip4/1.2.3.4".parse().unwrap()]);
+			},
+			_ => panic!("expected a FIND_NODE response"),
+		}
+	}
+
+	#[test]
+	fn request_response() {
+		let mut core = Core::new().unwrap();
+
+		let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+		let listener_addr = listener.local_addr().unwrap();
+
+		let server = listener.incoming()
+			.into_future()
+			.map_err(|(err, _)| err)
+			.and_then(|(socket, _)| {
+				KademliaProtocolConfig.upgrade(socket.unwrap().0, (), Endpoint::Listener,
+											   &"/ip4/127.0.0.1/tcp/10000".parse().unwrap())
+			})
+			.and_then(read_request)
+			.and_then(|(request, substream)| {
+				assert_eq!(request, KadRequestMsg::Ping);
+				send_response(substream, &KadResponseMsg::Pong)
+			});
+
+		let client = TcpStream::connect(&listener_addr, &core.handle())
+			.and_then(|socket| {
+				KademliaProtocolConfig.upgrade(socket, (), Endpoint::Dialer,
+											   &"/ip4/127.0.0.1/tcp/10000".parse().unwrap())
+			})
+			.and_then(|substream| send_request(substream, &KadRequestMsg::Ping));
+
+		let ((), response) = core.run(server.join(client)).unwrap();
+		assert_eq!(response, KadResponseMsg::Pong);
+	}
+}
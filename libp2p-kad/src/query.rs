@@ -0,0 +1,343 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `QueryState` struct, the state machine of an iterative Kademlia lookup.
+//!
+//! A lookup starts from the peers of the local routing table that are the closest to the target,
+//! and repeatedly asks the closest peers that haven't been queried yet for even closer peers. At
+//! most `parallelism` requests are in progress at the same time. The lookup finishes once the
+//! `num_results` closest peers that it knows of have all answered, or once there is no peer left
+//! to query.
+//!
+//! The state machine doesn't perform any I/O. The user is responsible for sending the requests
+//! asked by `poll` and for reporting their outcome with `inject_rpc_result` and
+//! `inject_rpc_error`.
+
+use kbucket::{Distance, KadKey};
+use libp2p_swarm::PeerId;
+use libp2p_swarm::time::Instant;
+use std::time::Duration;
+
+/// State of an iterative lookup.
+#[derive(Debug, Clone)]
+pub struct QueryState {
+	target: KadKey,
+	// Peers known by the query, sorted by increasing distance to the target.
+	closest_peers: Vec<(Distance, PeerId, QueryPeerState)>,
+	parallelism: usize,
+	num_results: usize,
+	rpc_timeout: Duration,
+	// Moment when the whole query times out.
+	deadline: Instant,
+	finished: bool,
+}
+
+/// Configuration of a `QueryState`.
+#[derive(Debug, Clone)]
+pub struct QueryConfig<I> {
+	/// Position of the target of the lookup in the keyspace.
+	pub target: KadKey,
+	/// Peers to start the lookup from, usually the closest peers of the local routing table.
+	pub known_closest_peers: I,
+	/// Maximum number of requests in progress at the same time. Called `α` in the paper.
+	pub parallelism: usize,
+	/// Number of peers to find. Called `k` in the paper.
+	pub num_results: usize,
+	/// Delay after which a request is considered as failed.
+	pub rpc_timeout: Duration,
+	/// Delay after which the whole lookup finishes, even if it isn't done.
+	pub timeout: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum QueryPeerState {
+	// We haven't sent a request to the peer yet.
+	NotContacted,
+	// A request to the peer is in progress, and times out at the given moment.
+	InProgress(Instant),
+	// The peer answered.
+	Succeeded,
+	// The request failed or timed out.
+	Failed,
+}
+
+/// Output of `QueryState::poll`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryStatePollOut<'a> {
+	/// The query is finished. Call `into_closest_peers` to obtain the result.
+	Finished,
+	/// A request must be sent to this peer. Its outcome must then be reported with
+	/// `inject_rpc_result` or `inject_rpc_error`.
+	SendRpc(&'a PeerId),
+	/// Nothing to do until a request finishes or times out.
+	NotReady,
+}
+
+impl QueryState {
+	/// Starts a new lookup.
+	pub fn new<I>(config: QueryConfig<I>) -> QueryState
+		where I: IntoIterator<Item = PeerId>
+	{
+		let target = config.target;
+		let mut closest_peers = config.known_closest_peers
+			.into_iter()
+			.map(|peer_id| {
+				let distance = target.distance(&KadKey::from_peer_id(&peer_id));
+				(distance, peer_id, QueryPeerState::NotContacted)
+			})
+			.collect::<Vec<_>>();
+		closest_peers.sort_by(|a, b| a.0.cmp(&b.0));
+		closest_peers.dedup_by(|a, b| a.1 == b.1);
+
+		QueryState {
+			target: target,
+			closest_peers: closest_peers,
+			parallelism: config.parallelism,
+			num_results: config.num_results,
+			rpc_timeout: config.rpc_timeout,
+			deadline: Instant::now() + config.timeout,
+			finished: false,
+		}
+	}
+
+	/// Returns the position of the target of the lookup.
+	#[inline]
+	pub fn target(&self) -> &KadKey {
+		&self.target
+	}
+
+	/// Reports that `peer_id` answered, and gives the peers that it returned.
+	///
+	/// Has no effect if no request to this peer is in progress.
+	pub fn inject_rpc_result<I>(&mut self, peer_id: &PeerId, closer_peers: I)
+		where I: IntoIterator<Item = PeerId>
+	{
+		if self.finished {
+			return;
+		}
+
+		let was_in_progress = match self.closest_peers.iter_mut()
+			.find(|&&mut (_, ref id, _)| id == peer_id)
+		{
+			Some(&mut (_, _, ref mut state)) => match *state {
+				QueryPeerState::InProgress(_) => {
+					*state = QueryPeerState::Succeeded;
+					true
+				},
+				_ => false,
+			},
+			None => false,
+		};
+
+		if !was_in_progress {
+			return;
+		}
+
+		for peer_id in closer_peers {
+			let distance = self.target.distance(&KadKey::from_peer_id(&peer_id));
+			let pos = match self.closest_peers.binary_search_by(|p| p.0.cmp(&distance)) {
+				// The peer is already known.
+				Ok(_) => continue,
+				Err(pos) => pos,
+			};
+			self.closest_peers.insert(pos, (distance, peer_id, QueryPeerState::NotContacted));
+		}
+	}
+
+	/// Reports that the request to `peer_id` failed.
+	///
+	/// Has no effect if no request to this peer is in progress.
+	pub fn inject_rpc_error(&mut self, peer_id: &PeerId) {
+		if let Some(&mut (_, _, ref mut state)) = self.closest_peers.iter_mut()
+			.find(|&&mut (_, ref id, _)| id == peer_id)
+		{
+			if let QueryPeerState::InProgress(_) = *state {
+				*state = QueryPeerState::Failed;
+			}
+		}
+	}
+
+	/// Advances the lookup. Should be called again after each `SendRpc`, and after each call to
+	/// `inject_rpc_result` or `inject_rpc_error`. Since requests can time out, it should also be
+	/// called from time to time while the query is in progress.
+	pub fn poll(&mut self) -> QueryStatePollOut {
+		if self.finished {
+			return QueryStatePollOut::Finished;
+		}
+
+		let now = Instant::now();
+		if now >= self.deadline {
+			self.finished = true;
+			return QueryStatePollOut::Finished;
+		}
+
+		// We only look at the `num_results` closest peers that haven't failed. We count the
+		// requests in progress and the peers that answered, and pick the closest peer that
+		// hasn't been contacted yet.
+		let mut in_progress = 0;
+		let mut succeeded = 0;
+		let mut not_contacted = 0;
+		let mut to_contact = None;
+
+		for (n, &mut (_, _, ref mut state)) in self.closest_peers.iter_mut().enumerate() {
+			if let QueryPeerState::InProgress(timeout) = *state {
+				if now >= timeout {
+					*state = QueryPeerState::Failed;
+				}
+			}
+
+			match *state {
+				QueryPeerState::NotContacted => {
+					if to_contact.is_none() {
+						to_contact = Some(n);
+					}
+					not_contacted += 1;
+				},
+				QueryPeerState::InProgress(_) => in_progress += 1,
+				QueryPeerState::Succeeded => succeeded += 1,
+				QueryPeerState::Failed => continue,
+			}
+
+			if in_progress + succeeded + not_contacted >= self.num_results {
+				break;
+			}
+		}
+
+		if succeeded >= self.num_results || (to_contact.is_none() && in_progress == 0) {
+			self.finished = true;
+			return QueryStatePollOut::Finished;
+		}
+
+		match to_contact {
+			Some(n) if in_progress < self.parallelism => {
+				self.closest_peers[n].2 = QueryPeerState::InProgress(now + self.rpc_timeout);
+				QueryStatePollOut::SendRpc(&self.closest_peers[n].1)
+			},
+			_ => QueryStatePollOut::NotReady,
+		}
+	}
+
+	/// Returns true if the query is finished.
+	#[inline]
+	pub fn is_finished(&self) -> bool {
+		self.finished
+	}
+
+	/// Consumes the query and returns the closest peers that answered, from the closest to the
+	/// furthest.
+	pub fn into_closest_peers(self) -> Vec<PeerId> {
+		let num_results = self.num_results;
+		self.closest_peers
+			.into_iter()
+			.filter(|&(_, _, ref state)| *state == QueryPeerState::Succeeded)
+			.take(num_results)
+			.map(|(_, peer_id, _)| peer_id)
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{QueryConfig, QueryState, QueryStatePollOut};
+	use kbucket::{KadKey, KBucketsTable};
+	use libp2p_swarm::PeerId;
+	use std::collections::HashMap;
+	use std::time::Duration;
+
+	fn config<I>(target: KadKey, known: I) -> QueryConfig<I> {
+		QueryConfig {
+			target: target,
+			known_closest_peers: known,
+			parallelism: 3,
+			num_results: 20,
+			rpc_timeout: Duration::from_secs(10),
+			timeout: Duration::from_secs(60),
+		}
+	}
+
+	#[test]
+	fn finds_closest_peers() {
+		// Network of 200 peers sorted by distance to the target, where each peer only knows the
+		// 20 peers that are just closer to the target than itself. Starting from the furthest
+		// peer, the query has to go through the whole network.
+		let target = KadKey::new(b"target");
+		let mut peers = (0 .. 200u32)
+			.map(|n| PeerId::from_public_key(&[(n >> 8) as u8, n as u8]))
+			.collect::<Vec<_>>();
+		peers.sort_by_key(|peer_id| target.distance(&KadKey::from_peer_id(peer_id)));
+		let mut tables = HashMap::new();
+		for (n, peer_id) in peers.iter().enumerate() {
+			let mut table = KBucketsTable::new(peer_id.clone(), 200);
+			for closer in &peers[n.saturating_sub(20) .. n] {
+				table.update(closer.clone(), ());
+			}
+			tables.insert(peer_id.clone(), table);
+		}
+
+		let mut query = QueryState::new(config(target, vec![peers[199].clone()]));
+		let mut pending = Vec::new();
+		loop {
+			let peer_id = match query.poll() {
+				QueryStatePollOut::SendRpc(peer_id) => peer_id.clone(),
+				QueryStatePollOut::NotReady => {
+					assert!(!pending.is_empty() && pending.len() <= 3);
+					// Answer the requests in reverse order.
+					let peer_id = pending.pop().unwrap();
+					let closer = tables[&peer_id].find_closest(&target, 20);
+					query.inject_rpc_result(&peer_id, closer);
+					continue;
+				},
+				QueryStatePollOut::Finished => break,
+			};
+			pending.push(peer_id);
+		}
+
+		assert_eq!(query.into_closest_peers(), &peers[.. 20]);
+	}
+
+	#[test]
+	fn failures() {
+		let peers = (0 .. 5u8).map(|n| PeerId::from_public_key(&[n])).collect::<Vec<_>>();
+		let mut query = QueryState::new(config(KadKey::new(b"target"), peers.clone()));
+
+		for _ in 0 .. 3 {
+			match query.poll() {
+				QueryStatePollOut::SendRpc(_) => (),
+				_ => panic!("expected a request"),
+			}
+		}
+		// The parallelism is 3.
+		assert_eq!(query.poll(), QueryStatePollOut::NotReady);
+
+		for peer_id in &peers {
+			query.inject_rpc_error(peer_id);
+		}
+		for _ in 0 .. 2 {
+			let peer_id = match query.poll() {
+				QueryStatePollOut::SendRpc(peer_id) => peer_id.clone(),
+				_ => panic!("expected a request"),
+			};
+			query.inject_rpc_result(&peer_id, Vec::new());
+		}
+
+		assert_eq!(query.poll(), QueryStatePollOut::Finished);
+		assert_eq!(query.into_closest_peers().len(), 2);
+	}
+}
@@ -0,0 +1,182 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `RecordValidator` trait, which decides which records are accepted in the DHT.
+
+use libp2p_swarm::PeerId;
+use std::collections::HashMap;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+
+/// Checks the records stored in the DHT, and chooses between conflicting records.
+///
+/// Anyone can send a record to a node, therefore the records must be validated both before
+/// storing them and after receiving them as the result of a query.
+pub trait RecordValidator {
+	/// Checks whether `value` is a valid value for `key`.
+	fn validate(&self, key: &[u8], value: &[u8]) -> Result<(), IoError>;
+
+	/// Returns the index of the best value among `values`, which are all valid values for `key`.
+	/// `values` is never empty.
+	fn select(&self, key: &[u8], values: &[Vec<u8>]) -> usize;
+}
+
+/// Validator for the public keys of the peers, stored under `/pk/<peer id>`.
+///
+/// The value must be the public key in the `PublicKey` protobuf format, whose hash is the peer
+/// ID of the key. Since only one value is valid for each key, `select` always returns the first
+/// one.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PublicKeyValidator;
+
+impl RecordValidator for PublicKeyValidator {
+	fn validate(&self, key: &[u8], value: &[u8]) -> Result<(), IoError> {
+		if !key.starts_with(b"/pk/") {
+			return Err(invalid_record("the key isn't in the /pk/ namespace"));
+		}
+
+		if PeerId::from_public_key(value).as_bytes() != &key[4 ..] {
+			return Err(invalid_record("the public key doesn't match the key"));
+		}
+
+		Ok(())
+	}
+
+	#[inline]
+	fn select(&self, _: &[u8], _: &[Vec<u8>]) -> usize {
+		0
+	}
+}
+
+/// Validator that dispatches the records to other validators depending on the namespace of their
+/// key. The namespace of `/ns/rest` is `ns`.
+///
+/// The records whose namespace isn't known are rejected.
+pub struct NamespacedValidator {
+	validators: HashMap<Vec<u8>, Box<RecordValidator>>,
+}
+
+impl NamespacedValidator {
+	/// Builds a `NamespacedValidator` that doesn't know any namespace.
+	#[inline]
+	pub fn new() -> NamespacedValidator {
+		NamespacedValidator {
+			validators: HashMap::new(),
+		}
+	}
+
+	/// Uses `validator` for the records whose key is in `namespace`. Replaces the validator
+	/// previously registered for this namespace, if any.
+	pub fn insert<V>(&mut self, namespace: &str, validator: V)
+		where V: RecordValidator + 'static
+	{
+		self.validators.insert(namespace.as_bytes().to_owned(), Box::new(validator));
+	}
+
+	/// Same as `insert`, but consumes and returns `self`.
+	#[inline]
+	pub fn with<V>(mut self, namespace: &str, validator: V) -> NamespacedValidator
+		where V: RecordValidator + 'static
+	{
+		self.insert(namespace, validator);
+		self
+	}
+
+	// Returns the validator for the namespace of `key`.
+	fn validator(&self, key: &[u8]) -> Result<&RecordValidator, IoError> {
+		if key.first() != Some(&b'/') {
+			return Err(invalid_record("the key doesn't have any namespace"));
+		}
+
+		let namespace = key[1 ..].split(|&b| b == b'/').next().unwrap_or(&[]);
+		match self.validators.get(namespace) {
+			Some(validator) => Ok(&**validator),
+			None => Err(invalid_record("unknown namespace")),
+		}
+	}
+}
+
+impl Default for NamespacedValidator {
+	/// Builds a `NamespacedValidator` that knows the `pk` namespace, like the other
+	/// implementations of libp2p.
+	#[inline]
+	fn default() -> NamespacedValidator {
+		NamespacedValidator::new().with("pk", PublicKeyValidator)
+	}
+}
+
+impl RecordValidator for NamespacedValidator {
+	#[inline]
+	fn validate(&self, key: &[u8], value: &[u8]) -> Result<(), IoError> {
+		self.validator(key)?.validate(key, value)
+	}
+
+	fn select(&self, key: &[u8], values: &[Vec<u8>]) -> usize {
+		match self.validator(key) {
+			Ok(validator) => validator.select(key, values),
+			Err(_) => 0,
+		}
+	}
+}
+
+#[inline]
+fn invalid_record(msg: &str) -> IoError {
+	IoError::new(IoErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{NamespacedValidator, PublicKeyValidator, RecordValidator};
+	use libp2p_swarm::PeerId;
+	use std::io::Error as IoError;
+
+	#[test]
+	fn public_key() {
+		let public_key = vec![8, 1, 18, 3, 1, 2, 3];
+		let mut key = b"/pk/".to_vec();
+		key.extend_from_slice(PeerId::from_public_key(&public_key).as_bytes());
+
+		assert!(PublicKeyValidator.validate(&key, &public_key).is_ok());
+		assert!(PublicKeyValidator.validate(&key, &[8, 1, 18, 3, 4, 5, 6]).is_err());
+		assert!(PublicKeyValidator.validate(b"/other/key", &public_key).is_err());
+	}
+
+	#[test]
+	fn namespaces() {
+		// Accepts any value, and prefers the longest one.
+		struct Longest;
+		impl RecordValidator for Longest {
+			fn validate(&self, _: &[u8], _: &[u8]) -> Result<(), IoError> {
+				Ok(())
+			}
+
+			fn select(&self, _: &[u8], values: &[Vec<u8>]) -> usize {
+				(0 .. values.len()).max_by_key(|&n| values[n].len()).unwrap()
+			}
+		}
+
+		let validator = NamespacedValidator::default().with("longest", Longest);
+		assert!(validator.validate(b"/longest/a", b"value").is_ok());
+		assert!(validator.validate(b"/longest", b"value").is_ok());
+		assert!(validator.validate(b"/unknown/a", b"value").is_err());
+		assert!(validator.validate(b"no namespace", b"value").is_err());
+		assert!(validator.validate(b"/pk/invalid", b"value").is_err());
+		assert_eq!(validator.select(b"/longest/a", &[vec![1], vec![1, 2], vec![3]]), 1);
+	}
+}
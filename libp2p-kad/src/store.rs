@@ -0,0 +1,310 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `RecordStore` trait, which stores the records and the provider records of the
+//! DHT, and `MemoryRecordStore`, its in-memory implementation.
+
+use libp2p_swarm::PeerId;
+use libp2p_swarm::time;
+use multiaddr::Multiaddr;
+use std::collections::HashMap;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::time::SystemTime;
+
+/// Storage of the records and of the provider records of a `Kademlia`.
+///
+/// The expirations are `SystemTime`s rather than `Instant`s, so that an implementation can
+/// persist the records across restarts of the node, for example in a database.
+pub trait RecordStore {
+	/// Returns the record stored under `key`, if it exists and hasn't expired.
+	fn get(&self, key: &[u8]) -> Option<StoredRecord>;
+
+	/// Stores a record under `key`, replacing the existing one if any. Returns an error if the
+	/// record can't be stored, for example because the store is full.
+	fn put(&mut self, key: Vec<u8>, record: StoredRecord) -> Result<(), IoError>;
+
+	/// Removes the record stored under `key`, if any.
+	fn remove(&mut self, key: &[u8]);
+
+	/// Returns the providers of `key` that haven't expired.
+	fn providers(&self, key: &[u8]) -> Vec<StoredProvider>;
+
+	/// Adds a provider of `key`. If the peer is already a provider of the key, its addresses and
+	/// its expiration are replaced. Returns an error if the provider can't be stored, for
+	/// example because the store is full.
+	fn add_provider(&mut self, key: Vec<u8>, provider: StoredProvider) -> Result<(), IoError>;
+
+	/// Removes `peer_id` from the providers of `key`.
+	fn remove_provider(&mut self, key: &[u8], peer_id: &PeerId);
+
+	/// Removes the records and the provider records that have expired. Called periodically.
+	fn remove_expired(&mut self);
+}
+
+/// Record stored in a `RecordStore`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredRecord {
+	/// Value of the record.
+	pub value: Vec<u8>,
+	/// Time after which the record must be forgotten.
+	pub expires: SystemTime,
+}
+
+/// Provider record stored in a `RecordStore`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredProvider {
+	/// The provider.
+	pub peer_id: PeerId,
+	/// The addresses of the provider.
+	pub addrs: Vec<Multiaddr>,
+	/// Time after which the provider record must be forgotten.
+	pub expires: SystemTime,
+}
+
+/// Limits of a `MemoryRecordStore`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryRecordStoreConfig {
+	/// Maximum number of records. Defaults to 1024.
+	pub max_records: usize,
+	/// Maximum size of the value of a record, in bytes. Defaults to 65536.
+	pub max_value_bytes: usize,
+	/// Maximum number of keys that have providers. Defaults to 1024.
+	pub max_provided_keys: usize,
+	/// Maximum number of providers for each key. Defaults to 20.
+	pub max_providers_per_key: usize,
+}
+
+impl Default for MemoryRecordStoreConfig {
+	#[inline]
+	fn default() -> MemoryRecordStoreConfig {
+		MemoryRecordStoreConfig {
+			max_records: 1024,
+			max_value_bytes: 65536,
+			max_provided_keys: 1024,
+			max_providers_per_key: 20,
+		}
+	}
+}
+
+/// Implementation of `RecordStore` that keeps everything in memory.
+///
+/// Since the remotes can send us as many records as they want, the number and the size of the
+/// records are limited. Once a limit is reached, the new records are rejected until some of the
+/// existing ones expire.
+#[derive(Debug, Clone)]
+pub struct MemoryRecordStore {
+	config: MemoryRecordStoreConfig,
+	records: HashMap<Vec<u8>, StoredRecord>,
+	providers: HashMap<Vec<u8>, Vec<StoredProvider>>,
+}
+
+impl MemoryRecordStore {
+	/// Builds an empty `MemoryRecordStore` with the default limits.
+	#[inline]
+	pub fn new() -> MemoryRecordStore {
+		MemoryRecordStore::with_config(MemoryRecordStoreConfig::default())
+	}
+
+	/// Builds an empty `MemoryRecordStore`.
+	#[inline]
+	pub fn with_config(config: MemoryRecordStoreConfig) -> MemoryRecordStore {
+		MemoryRecordStore {
+			config: config,
+			records: HashMap::new(),
+			providers: HashMap::new(),
+		}
+	}
+}
+
+impl Default for MemoryRecordStore {
+	#[inline]
+	fn default() -> MemoryRecordStore {
+		MemoryRecordStore::new()
+	}
+}
+
+impl RecordStore for MemoryRecordStore {
+	fn get(&self, key: &[u8]) -> Option<StoredRecord> {
+		match self.records.get(key) {
+			Some(record) if record.expires > time::system_now() => Some(record.clone()),
+			_ => None,
+		}
+	}
+
+	fn put(&mut self, key: Vec<u8>, record: StoredRecord) -> Result<(), IoError> {
+		if record.value.len() > self.config.max_value_bytes {
+			return Err(IoError::new(IoErrorKind::InvalidInput, "the record is too large"));
+		}
+
+		if !self.records.contains_key(&key) && self.records.len() >= self.config.max_records {
+			return Err(store_full());
+		}
+
+		self.records.insert(key, record);
+		Ok(())
+	}
+
+	#[inline]
+	fn remove(&mut self, key: &[u8]) {
+		self.records.remove(key);
+	}
+
+	fn providers(&self, key: &[u8]) -> Vec<StoredProvider> {
+		let now = time::system_now();
+		self.providers.get(key)
+			.into_iter()
+			.flat_map(|providers| providers.iter())
+			.filter(|provider| provider.expires > now)
+			.cloned()
+			.collect()
+	}
+
+	fn add_provider(&mut self, key: Vec<u8>, provider: StoredProvider) -> Result<(), IoError> {
+		if !self.providers.contains_key(&key) &&
+			self.providers.len() >= self.config.max_provided_keys
+		{
+			return Err(store_full());
+		}
+
+		let now = time::system_now();
+		let max_providers = self.config.max_providers_per_key;
+		let providers = self.providers.entry(key).or_insert_with(Vec::new);
+		providers.retain(|p| p.expires > now && p.peer_id != provider.peer_id);
+		if providers.len() >= max_providers {
+			return Err(store_full());
+		}
+
+		providers.push(provider);
+		Ok(())
+	}
+
+	fn remove_provider(&mut self, key: &[u8], peer_id: &PeerId) {
+		let now_empty = match self.providers.get_mut(key) {
+			Some(providers) => {
+				providers.retain(|provider| provider.peer_id != *peer_id);
+				providers.is_empty()
+			},
+			None => return,
+		};
+
+		if now_empty {
+			self.providers.remove(key);
+		}
+	}
+
+	fn remove_expired(&mut self) {
+		let now = time::system_now();
+		self.records.retain(|_, record| record.expires > now);
+		for providers in self.providers.values_mut() {
+			providers.retain(|provider| provider.expires > now);
+		}
+		self.providers.retain(|_, providers| !providers.is_empty());
+	}
+}
+
+#[inline]
+fn store_full() -> IoError {
+	IoError::new(IoErrorKind::Other, "the record store is full")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{MemoryRecordStore, MemoryRecordStoreConfig, RecordStore};
+	use super::{StoredProvider, StoredRecord};
+	use libp2p_swarm::PeerId;
+	use multiaddr::Multiaddr;
+	use std::time::{Duration, SystemTime};
+
+	fn provider(n: u8, addrs: Vec<Multiaddr>, ttl: Duration) -> StoredProvider {
+		StoredProvider {
+			peer_id: PeerId::from_public_key(&[n]),
+			addrs: addrs,
+			expires: SystemTime::now() + ttl,
+		}
+	}
+
+	fn record(value: &[u8]) -> StoredRecord {
+		StoredRecord {
+			value: value.to_owned(),
+			expires: SystemTime::now() + Duration::from_secs(3600),
+		}
+	}
+
+	#[test]
+	fn records() {
+		let config = MemoryRecordStoreConfig {
+			max_records: 2,
+			max_value_bytes: 4,
+			.. MemoryRecordStoreConfig::default()
+		};
+		let mut store = MemoryRecordStore::with_config(config);
+
+		assert!(store.put(b"a".to_vec(), record(b"12345")).is_err());
+		store.put(b"a".to_vec(), record(b"1")).unwrap();
+		store.put(b"b".to_vec(), record(b"2")).unwrap();
+		assert!(store.put(b"c".to_vec(), record(b"3")).is_err());
+		// Replacing a record is always possible.
+		store.put(b"a".to_vec(), record(b"4")).unwrap();
+		assert_eq!(store.get(b"a").unwrap().value, b"4".to_vec());
+
+		store.remove(b"b");
+		assert!(store.get(b"b").is_none());
+		store.put(b"c".to_vec(), record(b"3")).unwrap();
+	}
+
+	#[test]
+	fn providers() {
+		let config = MemoryRecordStoreConfig {
+			max_providers_per_key: 2,
+			.. MemoryRecordStoreConfig::default()
+		};
+		let mut store = MemoryRecordStore::with_config(config);
+		let addr: Multiaddr = "/ip4/1.2.3.4/tcp/4001".parse().unwrap();
+		let ttl = Duration::from_secs(3600);
+
+		store.add_provider(b"key".to_vec(), provider(1, Vec::new(), ttl)).unwrap();
+		store.add_provider(b"key".to_vec(), provider(2, Vec::new(), ttl)).unwrap();
+		store.add_provider(b"key".to_vec(), provider(1, vec![addr.clone()], ttl)).unwrap();
+		assert!(store.add_provider(b"key".to_vec(), provider(3, Vec::new(), ttl)).is_err());
+		assert_eq!(store.providers(b"key").len(), 2);
+		assert!(store.providers(b"other").is_empty());
+
+		store.remove_provider(b"key", &PeerId::from_public_key(&[2]));
+		let providers = store.providers(b"key");
+		assert_eq!(providers.len(), 1);
+		assert_eq!(providers[0].addrs, vec![addr]);
+	}
+
+	#[test]
+	fn expiration() {
+		let mut store = MemoryRecordStore::new();
+		let expired = SystemTime::now() - Duration::from_secs(1);
+		store.put(b"key".to_vec(), StoredRecord { value: vec![1], expires: expired }).unwrap();
+		let mut expired_provider = provider(1, Vec::new(), Duration::from_secs(0));
+		expired_provider.expires = expired;
+		store.add_provider(b"key".to_vec(), expired_provider).unwrap();
+
+		assert!(store.get(b"key").is_none());
+		assert!(store.providers(b"key").is_empty());
+		store.remove_expired();
+		assert!(store.records.is_empty());
+		assert!(store.providers.is_empty());
+	}
+}
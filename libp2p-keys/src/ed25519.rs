@@ -0,0 +1,148 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Ed25519 keys.
+
+use error::{DecodingError, GenerationError};
+use ring::rand::SystemRandom;
+use ring::signature::{self, Ed25519KeyPair, ED25519};
+use std::fmt;
+use std::sync::Arc;
+use untrusted::Input;
+
+/// Ed25519 key pair.
+///
+/// Cloning a `Keypair` is cheap, as the private key is shared between the clones.
+#[derive(Clone)]
+pub struct Keypair {
+	inner: Arc<Ed25519KeyPair>,
+	// PKCS#8 document the key pair has been decoded from, as the private key can't be extracted
+	// from `Ed25519KeyPair`.
+	pkcs8: Arc<Vec<u8>>,
+}
+
+impl Keypair {
+	/// Generates a new random key pair.
+	pub fn generate() -> Result<Keypair, GenerationError> {
+		let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+			.map_err(|_| GenerationError)?;
+		Keypair::from_pkcs8(&pkcs8[..]).map_err(|_| GenerationError)
+	}
+
+	/// Decodes a key pair from a PKCS#8 document, as produced by `to_pkcs8`.
+	pub fn from_pkcs8(pkcs8: &[u8]) -> Result<Keypair, DecodingError> {
+		let inner = Ed25519KeyPair::from_pkcs8(Input::from(pkcs8))
+			.map_err(|_| DecodingError::InvalidKey)?;
+		Ok(Keypair {
+			inner: Arc::new(inner),
+			pkcs8: Arc::new(pkcs8.to_vec()),
+		})
+	}
+
+	/// Returns the PKCS#8 document of the key pair, which contains the private key.
+	#[inline]
+	pub fn to_pkcs8(&self) -> &[u8] {
+		&self.pkcs8
+	}
+
+	/// Returns the public key.
+	pub fn public(&self) -> PublicKey {
+		let mut bytes = [0; 32];
+		bytes.copy_from_slice(self.inner.public_key_bytes());
+		PublicKey(bytes)
+	}
+
+	/// Signs `msg` with the private key.
+	#[inline]
+	pub fn sign(&self, msg: &[u8]) -> Vec<u8> {
+		self.inner.sign(msg).as_ref().to_vec()
+	}
+}
+
+impl fmt::Debug for Keypair {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		// The private key is intentionally not printed.
+		f.debug_struct("Keypair").field("public", &self.public()).finish()
+	}
+}
+
+/// Ed25519 public key.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PublicKey([u8; 32]);
+
+impl PublicKey {
+	/// Builds a public key from its 32 bytes.
+	pub fn from_bytes(bytes: &[u8]) -> Result<PublicKey, DecodingError> {
+		if bytes.len() != 32 {
+			return Err(DecodingError::InvalidKey);
+		}
+
+		let mut key = [0; 32];
+		key.copy_from_slice(bytes);
+		Ok(PublicKey(key))
+	}
+
+	/// Returns the 32 bytes of the key.
+	#[inline]
+	pub fn as_bytes(&self) -> &[u8; 32] {
+		&self.0
+	}
+
+	/// Returns true if `sig` is a valid signature of `msg` by this key.
+	#[inline]
+	pub fn verify(&self, msg: &[u8], sig: &[u8]) -> bool {
+		signature::verify(&ED25519, Input::from(&self.0), Input::from(msg), Input::from(sig))
+			.is_ok()
+	}
+}
+
+impl fmt::Debug for PublicKey {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "PublicKey(")?;
+		for byte in self.0.iter() {
+			write!(f, "{:02x}", byte)?;
+		}
+		write!(f, ")")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Keypair;
+
+	#[test]
+	fn sign_verify() {
+		let keypair = Keypair::generate().unwrap();
+		let signature = keypair.sign(b"hello");
+		assert!(keypair.public().verify(b"hello", &signature));
+		assert!(!keypair.public().verify(b"world", &signature));
+
+		let other = Keypair::generate().unwrap();
+		assert!(!other.public().verify(b"hello", &signature));
+	}
+
+	#[test]
+	fn pkcs8_roundtrip() {
+		let keypair = Keypair::generate().unwrap();
+		let decoded = Keypair::from_pkcs8(keypair.to_pkcs8()).unwrap();
+		assert_eq!(keypair.public(), decoded.public());
+		assert!(Keypair::from_pkcs8(&[1, 2, 3]).is_err());
+	}
+}
@@ -0,0 +1,91 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Defines the errors that can happen when decoding or generating keys, or when signing.
+
+use std::error;
+use std::fmt;
+
+/// Error while decoding a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodingError {
+	/// The `PublicKey` protobuf message is malformed.
+	InvalidProtobuf,
+
+	/// The type of the key isn't supported.
+	UnsupportedKeyType,
+
+	/// The data of the key is invalid for its type.
+	InvalidKey,
+}
+
+impl error::Error for DecodingError {
+	#[inline]
+	fn description(&self) -> &str {
+		match *self {
+			DecodingError::InvalidProtobuf => "The `PublicKey` protobuf message is malformed",
+			DecodingError::UnsupportedKeyType => "The type of the key isn't supported",
+			DecodingError::InvalidKey => "The data of the key is invalid for its type",
+		}
+	}
+}
+
+impl fmt::Display for DecodingError {
+	#[inline]
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		write!(fmt, "{}", error::Error::description(self))
+	}
+}
+
+/// Error while generating a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenerationError;
+
+impl error::Error for GenerationError {
+	#[inline]
+	fn description(&self) -> &str {
+		"Failed to generate a key"
+	}
+}
+
+impl fmt::Display for GenerationError {
+	#[inline]
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		write!(fmt, "{}", error::Error::description(self))
+	}
+}
+
+/// Error while signing data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigningError;
+
+impl error::Error for SigningError {
+	#[inline]
+	fn description(&self) -> &str {
+		"Failed to sign the data"
+	}
+}
+
+impl fmt::Display for SigningError {
+	#[inline]
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		write!(fmt, "{}", error::Error::description(self))
+	}
+}
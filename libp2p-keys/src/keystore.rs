@@ -0,0 +1,462 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Storage of key pairs on the disk, encrypted with a passphrase.
+//!
+//! A `Keystore` is a directory that contains one file per key pair, named after the key pair.
+//! The key pair named `identity` is by convention the identity of the node, and can be loaded
+//! with `Keystore::identity()`, which generates it the first time. This gives the node the same
+//! peer ID across restarts.
+//!
+//! Each file contains the key pair encrypted with ChaCha20-Poly1305, with a key derived from the
+//! passphrase with scrypt. The parameters of scrypt and the random salt are stored in the file,
+//! so that the work factor can be changed without making the existing files unreadable.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use libp2p_keys::keystore::Keystore;
+//!
+//! let keystore = Keystore::new("/var/lib/my-node/keys", "correct horse battery staple");
+//! let keypair = keystore.identity().unwrap();
+//! println!("{:?}", keypair.public());
+//! ```
+
+use crypto::scrypt::{scrypt, ScryptParams};
+use ed25519;
+use error::{DecodingError, GenerationError};
+use ring::aead::{self, OpeningKey, SealingKey, CHACHA20_POLY1305};
+use ring::rand::{SecureRandom, SystemRandom};
+use rsa;
+use secp256k1;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::path::PathBuf;
+use tempfile::NamedTempFile;
+use Keypair;
+
+/// Name of the key pair that contains the identity of the node.
+pub const IDENTITY_KEY_NAME: &'static str = "identity";
+
+/// Default base 2 logarithm of the scrypt work factor.
+pub const DEFAULT_LOG_N: u8 = 15;
+
+// Maximum value of `log_n` that we accept when reading a file, so that a modified file can't make
+// us allocate gigabytes of memory.
+const MAX_LOG_N: u8 = 20;
+
+// Version of the format of the files.
+const VERSION: u8 = 1;
+// Length of the header that precedes the encrypted key pair: the version, `log_n`, `r` and `p`,
+// the salt and the nonce.
+const HEADER_LEN: usize = 1 + 1 + 4 + 4 + SALT_LEN + NONCE_LEN;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+/// Directory of key pairs encrypted with a passphrase. See the module-level documentation.
+pub struct Keystore {
+	directory: PathBuf,
+	passphrase: Vec<u8>,
+	log_n: u8,
+}
+
+impl Keystore {
+	/// Builds a `Keystore` that stores its files in `directory`. The directory is created when
+	/// the first key pair is stored.
+	pub fn new<P>(directory: P, passphrase: &str) -> Keystore
+		where P: Into<PathBuf>
+	{
+		Keystore {
+			directory: directory.into(),
+			passphrase: passphrase.as_bytes().to_vec(),
+			log_n: DEFAULT_LOG_N,
+		}
+	}
+
+	/// Sets the base 2 logarithm of the scrypt work factor used when storing key pairs. Higher
+	/// values make brute-forcing the passphrase harder, but also make loading the keys slower.
+	///
+	/// # Panic
+	///
+	/// Panics if `log_n` is 0 or larger than 20.
+	#[inline]
+	pub fn with_work_factor(mut self, log_n: u8) -> Keystore {
+		assert!(log_n >= 1 && log_n <= MAX_LOG_N, "invalid scrypt work factor");
+		self.log_n = log_n;
+		self
+	}
+
+	/// Loads the identity of the node, or generates an Ed25519 key pair and stores it if there
+	/// is none yet.
+	#[inline]
+	pub fn identity(&self) -> Result<Keypair, KeystoreError> {
+		self.load_or_generate(IDENTITY_KEY_NAME, Keypair::generate_ed25519)
+	}
+
+	/// Loads the key pair named `name`, or generates one with `generate` and stores it if there
+	/// is none yet.
+	pub fn load_or_generate<F>(&self, name: &str, generate: F) -> Result<Keypair, KeystoreError>
+		where F: FnOnce() -> Result<Keypair, GenerationError>
+	{
+		if let Some(keypair) = self.load(name)? {
+			return Ok(keypair);
+		}
+
+		let keypair = generate().map_err(KeystoreError::Generation)?;
+		self.store(name, &keypair)?;
+		Ok(keypair)
+	}
+
+	/// Loads the key pair named `name`. Returns `Ok(None)` if there is no such key pair.
+	pub fn load(&self, name: &str) -> Result<Option<Keypair>, KeystoreError> {
+		let path = self.path(name)?;
+		let mut data = Vec::new();
+		match fs::File::open(&path) {
+			Ok(mut file) => { file.read_to_end(&mut data)?; },
+			Err(ref err) if err.kind() == IoErrorKind::NotFound => return Ok(None),
+			Err(err) => return Err(err.into()),
+		}
+
+		let plaintext = self.decrypt(&data)?;
+		decode_keypair(&plaintext).map(Some).map_err(KeystoreError::Decoding)
+	}
+
+	/// Stores `keypair` under the name `name`, replacing the key pair that had this name if any.
+	///
+	/// The file is written atomically: if an error happens, the previous content is untouched.
+	pub fn store(&self, name: &str, keypair: &Keypair) -> Result<(), KeystoreError> {
+		let path = self.path(name)?;
+		let data = self.encrypt(&encode_keypair(keypair))?;
+
+		fs::create_dir_all(&self.directory)?;
+		// The temporary file is only readable by the current user.
+		let mut temporary_file = NamedTempFile::new_in(&self.directory)?;
+		temporary_file.write_all(&data)?;
+		temporary_file.sync_data()?;
+		temporary_file.persist(&path).map_err(|err| err.error)?;
+		Ok(())
+	}
+
+	/// Removes the key pair named `name`. Does nothing if there is no such key pair.
+	pub fn remove(&self, name: &str) -> Result<(), KeystoreError> {
+		match fs::remove_file(self.path(name)?) {
+			Ok(()) => Ok(()),
+			Err(ref err) if err.kind() == IoErrorKind::NotFound => Ok(()),
+			Err(err) => Err(err.into()),
+		}
+	}
+
+	/// Returns the names of the key pairs of the keystore.
+	pub fn names(&self) -> Result<Vec<String>, KeystoreError> {
+		let entries = match fs::read_dir(&self.directory) {
+			Ok(entries) => entries,
+			Err(ref err) if err.kind() == IoErrorKind::NotFound => return Ok(Vec::new()),
+			Err(err) => return Err(err.into()),
+		};
+
+		let mut names = Vec::new();
+		for entry in entries {
+			let file_name = entry?.file_name();
+			let name = match file_name.to_str() {
+				Some(name) if name.ends_with(".key") => &name[.. name.len() - 4],
+				_ => continue,
+			};
+			if is_valid_name(name) {
+				names.push(name.to_owned());
+			}
+		}
+		names.sort();
+		Ok(names)
+	}
+
+	// Returns the path of the file of the key pair named `name`.
+	fn path(&self, name: &str) -> Result<PathBuf, KeystoreError> {
+		if !is_valid_name(name) {
+			return Err(KeystoreError::InvalidName);
+		}
+		Ok(self.directory.join(format!("{}.key", name)))
+	}
+
+	// Encrypts `plaintext` and prepends the header.
+	fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, KeystoreError> {
+		let rng = SystemRandom::new();
+		let mut salt = [0; SALT_LEN];
+		let mut nonce = [0; NONCE_LEN];
+		rng.fill(&mut salt).map_err(|_| KeystoreError::Generation(GenerationError))?;
+		rng.fill(&mut nonce).map_err(|_| KeystoreError::Generation(GenerationError))?;
+
+		let mut out = Vec::with_capacity(HEADER_LEN + plaintext.len() + TAG_LEN);
+		out.push(VERSION);
+		out.push(self.log_n);
+		write_u32(&mut out, SCRYPT_R);
+		write_u32(&mut out, SCRYPT_P);
+		out.extend_from_slice(&salt);
+		out.extend_from_slice(&nonce);
+
+		let key = derive_key(&self.passphrase, &salt, self.log_n, SCRYPT_R, SCRYPT_P);
+		let key = SealingKey::new(&CHACHA20_POLY1305, &key)
+			.expect("the key has the length required by ChaCha20-Poly1305");
+		// The header is authenticated as well, so that the parameters can't be tampered with.
+		let mut in_out = Vec::with_capacity(plaintext.len() + TAG_LEN);
+		in_out.extend_from_slice(plaintext);
+		in_out.extend_from_slice(&[0; TAG_LEN]);
+		let len = aead::seal_in_place(&key, &nonce, &out, &mut in_out, TAG_LEN)
+			.expect("the buffer has room for the tag");
+		out.extend_from_slice(&in_out[.. len]);
+		Ok(out)
+	}
+
+	// Checks the header of `data` and decrypts the rest.
+	fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, KeystoreError> {
+		if data.len() < HEADER_LEN + TAG_LEN || data[0] != VERSION {
+			return Err(KeystoreError::InvalidFormat);
+		}
+
+		let (header, ciphertext) = data.split_at(HEADER_LEN);
+		let log_n = header[1];
+		let r = read_u32(&header[2 .. 6]);
+		let p = read_u32(&header[6 .. 10]);
+		if log_n < 1 || log_n > MAX_LOG_N || r < 1 || r > 16 || p < 1 || p > 4 {
+			return Err(KeystoreError::InvalidFormat);
+		}
+		let salt = &header[10 .. 10 + SALT_LEN];
+		let nonce = &header[10 + SALT_LEN ..];
+
+		let key = derive_key(&self.passphrase, salt, log_n, r, p);
+		let key = OpeningKey::new(&CHACHA20_POLY1305, &key)
+			.expect("the key has the length required by ChaCha20-Poly1305");
+		let mut in_out = ciphertext.to_vec();
+		let len = aead::open_in_place(&key, nonce, header, 0, &mut in_out)
+			.map_err(|_| KeystoreError::DecryptionFailed)?
+			.len();
+		in_out.truncate(len);
+		Ok(in_out)
+	}
+}
+
+impl fmt::Debug for Keystore {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		// The passphrase is intentionally not printed.
+		f.debug_struct("Keystore")
+			.field("directory", &self.directory)
+			.field("log_n", &self.log_n)
+			.finish()
+	}
+}
+
+/// Error while loading or storing a key pair.
+#[derive(Debug)]
+pub enum KeystoreError {
+	/// Error while accessing the disk.
+	Io(IoError),
+
+	/// The name of the key pair is empty or contains characters other than ASCII letters,
+	/// digits, `-` and `_`.
+	InvalidName,
+
+	/// The file isn't a key pair written by a `Keystore`.
+	InvalidFormat,
+
+	/// The passphrase is wrong or the file has been corrupted.
+	DecryptionFailed,
+
+	/// The key pair has been decrypted but is invalid.
+	Decoding(DecodingError),
+
+	/// Error while generating a key pair.
+	Generation(GenerationError),
+}
+
+impl From<IoError> for KeystoreError {
+	#[inline]
+	fn from(err: IoError) -> KeystoreError {
+		KeystoreError::Io(err)
+	}
+}
+
+impl Error for KeystoreError {
+	#[inline]
+	fn description(&self) -> &str {
+		match *self {
+			KeystoreError::Io(_) => "I/O error while accessing the keystore",
+			KeystoreError::InvalidName => "Invalid name of key pair",
+			KeystoreError::InvalidFormat => "The file isn't a key pair written by a keystore",
+			KeystoreError::DecryptionFailed => "Wrong passphrase or corrupted file",
+			KeystoreError::Decoding(_) => "The stored key pair is invalid",
+			KeystoreError::Generation(_) => "Failed to generate a key pair",
+		}
+	}
+
+	#[inline]
+	fn cause(&self) -> Option<&Error> {
+		match *self {
+			KeystoreError::Io(ref err) => Some(err),
+			KeystoreError::Decoding(ref err) => Some(err),
+			KeystoreError::Generation(ref err) => Some(err),
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for KeystoreError {
+	#[inline]
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match *self {
+			KeystoreError::Io(ref err) => write!(fmt, "{}: {}", self.description(), err),
+			_ => write!(fmt, "{}", self.description()),
+		}
+	}
+}
+
+// Returns true if `name` can be used as the name of a file.
+fn is_valid_name(name: &str) -> bool {
+	!name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+// Derives the encryption key from the passphrase.
+fn derive_key(passphrase: &[u8], salt: &[u8], log_n: u8, r: u32, p: u32) -> [u8; 32] {
+	let mut key = [0; 32];
+	scrypt(passphrase, salt, &ScryptParams::new(log_n, r, p), &mut key);
+	key
+}
+
+// Encodes a key pair as a byte indicating its type, followed with its private key. The types
+// have the same values as in the `PublicKey` protobuf message.
+fn encode_keypair(keypair: &Keypair) -> Vec<u8> {
+	let mut out = Vec::new();
+	match *keypair {
+		Keypair::Rsa(ref keypair) => {
+			out.push(0);
+			out.extend_from_slice(keypair.to_pkcs8());
+		},
+		Keypair::Ed25519(ref keypair) => {
+			out.push(1);
+			out.extend_from_slice(keypair.to_pkcs8());
+		},
+		Keypair::Secp256k1(ref keypair) => {
+			out.push(2);
+			out.extend_from_slice(&keypair.secret_key_bytes());
+		},
+	}
+	out
+}
+
+// Decodes a key pair encoded with `encode_keypair`.
+fn decode_keypair(data: &[u8]) -> Result<Keypair, DecodingError> {
+	match data.split_first() {
+		Some((&0, pkcs8)) => rsa::Keypair::from_pkcs8(pkcs8).map(Keypair::Rsa),
+		Some((&1, pkcs8)) => ed25519::Keypair::from_pkcs8(pkcs8).map(Keypair::Ed25519),
+		Some((&2, secret)) => {
+			secp256k1::Keypair::from_secret_key_bytes(secret).map(Keypair::Secp256k1)
+		},
+		Some(_) => Err(DecodingError::UnsupportedKeyType),
+		None => Err(DecodingError::InvalidKey),
+	}
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+	out.push((value >> 24) as u8);
+	out.push((value >> 16) as u8);
+	out.push((value >> 8) as u8);
+	out.push(value as u8);
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+	(bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Keystore, KeystoreError};
+	use ring::rand::{SecureRandom, SystemRandom};
+	use std::env;
+	use std::fs;
+	use std::path::PathBuf;
+	use Keypair;
+
+	// Returns a path in the temporary directory that doesn't exist yet.
+	fn temp_dir() -> PathBuf {
+		let mut random = [0; 8];
+		SystemRandom::new().fill(&mut random).unwrap();
+		let name = random.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+		env::temp_dir().join(format!("libp2p-keystore-{}", name))
+	}
+
+	#[test]
+	fn store_and_load() {
+		let dir = temp_dir();
+		let keystore = Keystore::new(dir.clone(), "passphrase").with_work_factor(4);
+		assert!(keystore.load("foo").unwrap().is_none());
+
+		let keypairs = vec![
+			Keypair::generate_ed25519().unwrap(),
+			Keypair::generate_secp256k1().unwrap(),
+			Keypair::rsa_from_pkcs8(include_bytes!("../tests/test-private-key.pk8")).unwrap(),
+		];
+		for (n, keypair) in keypairs.iter().enumerate() {
+			let name = format!("key-{}", n);
+			keystore.store(&name, keypair).unwrap();
+			let loaded = keystore.load(&name).unwrap().unwrap();
+			assert_eq!(loaded.public(), keypair.public());
+		}
+		assert_eq!(keystore.names().unwrap(), vec!["key-0", "key-1", "key-2"]);
+
+		keystore.remove("key-1").unwrap();
+		assert!(keystore.load("key-1").unwrap().is_none());
+		fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn identity_is_stable() {
+		let dir = temp_dir();
+		let first = Keystore::new(dir.clone(), "passphrase").with_work_factor(4)
+			.identity().unwrap();
+		let second = Keystore::new(dir.clone(), "passphrase").identity().unwrap();
+		assert_eq!(first.public(), second.public());
+		fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn wrong_passphrase() {
+		let dir = temp_dir();
+		Keystore::new(dir.clone(), "passphrase").with_work_factor(4).identity().unwrap();
+		match Keystore::new(dir.clone(), "wrong").load("identity") {
+			Err(KeystoreError::DecryptionFailed) => (),
+			other => panic!("unexpected result: {:?}", other),
+		}
+		fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn invalid_names() {
+		let keystore = Keystore::new(temp_dir(), "passphrase");
+		for name in &["", "../foo", "foo.key", "foo/bar"] {
+			match keystore.load(name) {
+				Err(KeystoreError::InvalidName) => (),
+				other => panic!("unexpected result: {:?}", other),
+			}
+		}
+	}
+}
@@ -0,0 +1,258 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+// TODO: use this once stable ; for now we just copy-paste the content of the README.md
+//#![doc(include = "../README.md")]
+
+//! Identity keys of the libp2p nodes.
+//!
+//! Each node of the network owns a key pair. The public key identifies the node: the peer ID of
+//! a node is the hash of the `PublicKey` protobuf message of its public key. The private key
+//! signs the data that proves this identity to the other nodes, for example the static key of
+//! the Noise handshake.
+//!
+//! The `Keypair` and `PublicKey` enums abstract over the types of keys. Ed25519, Secp256k1 and
+//! RSA keys are supported. Secp256k1 makes it possible to reuse the keys of blockchain nodes, and
+//! RSA keys are encoded like go-libp2p does, so that the peer IDs of the go-ipfs nodes that still
+//! use them are the same.
+//!
+//! # Example
+//!
+//! ```
+//! use libp2p_keys::{Keypair, PublicKey};
+//!
+//! let keypair = Keypair::generate_ed25519().unwrap();
+//! let signature = keypair.sign(b"hello").unwrap();
+//!
+//! // The public key can be sent to the other nodes in the `PublicKey` protobuf format.
+//! let encoded = keypair.public().into_protobuf_encoding();
+//! let public_key = PublicKey::from_protobuf_encoding(&encoded).unwrap();
+//! assert!(public_key.verify(b"hello", &signature));
+//! ```
+//!
+//! The protobuf encoding of the public key is what the handshakes send to the remote, and what
+//! `PeerId::from_public_key` of `libp2p-peerstore` hashes.
+//!
+//! The `keystore` module stores key pairs on the disk, encrypted with a passphrase. A node that
+//! loads its identity with `Keystore::identity()` keeps the same peer ID across restarts.
+//!
+//! # Signers
+//!
+//! The crates that sign data with the identity key of the local node, such as `libp2p-secio`,
+//! `libp2p-noise`, `libp2p-tls` and `libp2p-gossipsub`, don't take a `Keypair` but an
+//! `Arc<Signer>`. The `Signer` trait produces the signatures asynchronously, which makes it
+//! possible to keep the private key in a hardware security module, and to use the same key for
+//! all of them. `Keypair` implements `Signer`.
+//!
+//! The `record` module signs the records defined by `libp2p-identity-core`, such as the peer
+//! records, and checks their signatures.
+
+extern crate crypto;
+extern crate futures;
+#[macro_use]
+extern crate lazy_static;
+extern crate libp2p_identity_core;
+extern crate ring;
+extern crate secp256k1 as libsecp256k1;
+extern crate tempfile;
+extern crate untrusted;
+
+pub use self::error::{DecodingError, GenerationError, SigningError};
+pub use self::signer::Signer;
+
+use libp2p_identity_core::{Error as IdentityError, KeyType, PublicKeyRef};
+
+pub mod ed25519;
+pub mod keystore;
+pub mod record;
+pub mod rsa;
+pub mod secp256k1;
+
+mod error;
+mod signer;
+
+/// Key pair of a node, of any of the supported types.
+#[derive(Debug, Clone)]
+pub enum Keypair {
+	/// Ed25519 key pair.
+	Ed25519(ed25519::Keypair),
+	/// Secp256k1 key pair.
+	Secp256k1(secp256k1::Keypair),
+	/// RSA key pair.
+	Rsa(rsa::Keypair),
+}
+
+impl Keypair {
+	/// Generates a new random Ed25519 key pair.
+	#[inline]
+	pub fn generate_ed25519() -> Result<Keypair, GenerationError> {
+		ed25519::Keypair::generate().map(Keypair::Ed25519)
+	}
+
+	/// Generates a new random Secp256k1 key pair.
+	#[inline]
+	pub fn generate_secp256k1() -> Result<Keypair, GenerationError> {
+		secp256k1::Keypair::generate().map(Keypair::Secp256k1)
+	}
+
+	/// Decodes an RSA key pair from a PKCS#8 document.
+	///
+	/// > **Note**: RSA keys can't be generated by this library. See the `rsa` module.
+	#[inline]
+	pub fn rsa_from_pkcs8(pkcs8: &[u8]) -> Result<Keypair, DecodingError> {
+		rsa::Keypair::from_pkcs8(pkcs8).map(Keypair::Rsa)
+	}
+
+	/// Signs `msg` with the private key.
+	///
+	/// Only the signing of RSA keys can fail.
+	#[inline]
+	pub fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, SigningError> {
+		match *self {
+			Keypair::Ed25519(ref keypair) => Ok(keypair.sign(msg)),
+			Keypair::Secp256k1(ref keypair) => Ok(keypair.sign(msg)),
+			Keypair::Rsa(ref keypair) => keypair.sign(msg),
+		}
+	}
+
+	/// Returns the public key.
+	#[inline]
+	pub fn public(&self) -> PublicKey {
+		match *self {
+			Keypair::Ed25519(ref keypair) => PublicKey::Ed25519(keypair.public()),
+			Keypair::Secp256k1(ref keypair) => PublicKey::Secp256k1(keypair.public()),
+			Keypair::Rsa(ref keypair) => PublicKey::Rsa(keypair.public()),
+		}
+	}
+}
+
+/// Public key of a node, of any of the supported types.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PublicKey {
+	/// Ed25519 public key.
+	Ed25519(ed25519::PublicKey),
+	/// Secp256k1 public key.
+	Secp256k1(secp256k1::PublicKey),
+	/// RSA public key.
+	Rsa(rsa::PublicKey),
+}
+
+impl PublicKey {
+	/// Returns true if `sig` is a valid signature of `msg` by this key.
+	#[inline]
+	pub fn verify(&self, msg: &[u8], sig: &[u8]) -> bool {
+		match *self {
+			PublicKey::Ed25519(ref key) => key.verify(msg, sig),
+			PublicKey::Secp256k1(ref key) => key.verify(msg, sig),
+			PublicKey::Rsa(ref key) => key.verify(msg, sig),
+		}
+	}
+
+	/// Encodes the key as a `PublicKey` protobuf message.
+	pub fn into_protobuf_encoding(&self) -> Vec<u8> {
+		let (key_type, data) = match *self {
+			PublicKey::Ed25519(ref key) => (1, key.as_bytes().to_vec()),
+			PublicKey::Secp256k1(ref key) => (2, key.to_bytes().to_vec()),
+			PublicKey::Rsa(ref key) => (0, key.as_pkix().to_vec()),
+		};
+
+		// Field 1 is the type of the key as a varint, and field 2 is the data of the key.
+		let mut out = Vec::with_capacity(data.len() + 6);
+		out.push(0x08);
+		out.push(key_type);
+		out.push(0x12);
+		write_varint(&mut out, data.len() as u64);
+		out.extend_from_slice(&data);
+		out
+	}
+
+	/// Decodes a `PublicKey` protobuf message.
+	pub fn from_protobuf_encoding(bytes: &[u8]) -> Result<PublicKey, DecodingError> {
+		let key = PublicKeyRef::from_protobuf(bytes).map_err(|err| match err {
+			IdentityError::UnknownKeyType(_) => DecodingError::UnsupportedKeyType,
+			_ => DecodingError::InvalidProtobuf,
+		})?;
+		match key.key_type() {
+			KeyType::Ed25519 => ed25519::PublicKey::from_bytes(key.data()).map(PublicKey::Ed25519),
+			KeyType::Secp256k1 => {
+				secp256k1::PublicKey::from_bytes(key.data()).map(PublicKey::Secp256k1)
+			},
+			KeyType::Rsa => rsa::PublicKey::from_pkix(key.data()).map(PublicKey::Rsa),
+		}
+	}
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+	while value >= 0x80 {
+		out.push((value as u8) | 0x80);
+		value >>= 7;
+	}
+	out.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+	use {DecodingError, Keypair, PublicKey};
+
+	#[test]
+	fn protobuf_roundtrip() {
+		let public = Keypair::generate_ed25519().unwrap().public();
+		let encoded = public.into_protobuf_encoding();
+		assert_eq!(&encoded[.. 4], &[0x08, 0x01, 0x12, 0x20]);
+		assert_eq!(PublicKey::from_protobuf_encoding(&encoded), Ok(public));
+	}
+
+	#[test]
+	fn secp256k1_protobuf_roundtrip() {
+		let keypair = Keypair::generate_secp256k1().unwrap();
+		let encoded = keypair.public().into_protobuf_encoding();
+		assert_eq!(&encoded[.. 4], &[0x08, 0x02, 0x12, 0x21]);
+
+		let public = PublicKey::from_protobuf_encoding(&encoded).unwrap();
+		assert_eq!(public, keypair.public());
+		assert!(public.verify(b"hello", &keypair.sign(b"hello").unwrap()));
+	}
+
+	#[test]
+	fn rsa_protobuf_roundtrip() {
+		let keypair = Keypair::rsa_from_pkcs8(include_bytes!("../tests/test-private-key.pk8"))
+			.unwrap();
+		let encoded = keypair.public().into_protobuf_encoding();
+		// The data of the message is the DER-encoded `SubjectPublicKeyInfo`, like in go-libp2p.
+		let pkix = include_bytes!("../tests/test-public-key.der");
+		assert_eq!(&encoded[.. 5], &[0x08, 0x00, 0x12, 0xa6, 0x02]);
+		assert_eq!(&encoded[5 ..], &pkix[..]);
+
+		let public = PublicKey::from_protobuf_encoding(&encoded).unwrap();
+		assert_eq!(public, keypair.public());
+		assert!(public.verify(b"hello", &keypair.sign(b"hello").unwrap()));
+	}
+
+	#[test]
+	fn decoding_errors() {
+		assert_eq!(PublicKey::from_protobuf_encoding(&[0x08]), Err(DecodingError::InvalidProtobuf));
+		assert_eq!(PublicKey::from_protobuf_encoding(&[0x08, 0x01, 0x12, 0x01, 0x2a]),
+				   Err(DecodingError::InvalidKey));
+		assert_eq!(PublicKey::from_protobuf_encoding(&[0x08, 0x00, 0x12, 0x01, 0x2a]),
+				   Err(DecodingError::InvalidKey));
+		assert_eq!(PublicKey::from_protobuf_encoding(&[0x08, 0x07, 0x12, 0x01, 0x2a]),
+				   Err(DecodingError::UnsupportedKeyType));
+	}
+}
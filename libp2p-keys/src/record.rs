@@ -0,0 +1,165 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Signing and verification of the signed records of `libp2p-identity-core`.
+//!
+//! `libp2p-identity-core` decodes the signed records but doesn't contain any cryptographic code.
+//! This module builds the records with a `Signer`, and provides a `SignatureVerifier` that
+//! supports all the key types of this crate.
+
+use error::SigningError;
+use futures::Future;
+use libp2p_identity_core::{PeerIdBuf, PublicKeyRef, SignatureVerifier};
+use libp2p_identity_core::{PEER_RECORD_DOMAIN, PEER_RECORD_PAYLOAD_TYPE};
+use signer::Signer;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use {write_varint, PublicKey};
+
+/// Implementation of `SignatureVerifier` for all the key types of this crate.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Verifier;
+
+impl SignatureVerifier for Verifier {
+	fn verify(&self, key: &PublicKeyRef, message: &[&[u8]], signature: &[u8]) -> bool {
+		match PublicKey::from_protobuf_encoding(key.encoded()) {
+			Ok(public_key) => public_key.verify(&message.concat(), signature),
+			Err(_) => false,
+		}
+	}
+}
+
+/// Signs `payload` for `domain` with the identity key of the local node, and produces the
+/// `Envelope` protobuf message that contains it. The receivers decode it with
+/// `SignedRecordRef::from_protobuf`.
+pub fn sign_record(signer: &Signer, domain: &[u8], payload_type: &[u8], payload: Vec<u8>)
+				   -> Box<Future<Item = Vec<u8>, Error = SigningError> + Send>
+{
+	// The signature covers the domain, the type of the payload and the payload, each of them
+	// prefixed with its length.
+	let mut signed_data = Vec::new();
+	for part in &[domain, payload_type, &payload[..]] {
+		write_varint(&mut signed_data, part.len() as u64);
+		signed_data.extend_from_slice(part);
+	}
+
+	let public_key = signer.public().into_protobuf_encoding();
+	let payload_type = payload_type.to_vec();
+	let future = signer.sign(&signed_data).map(move |signature| {
+		let mut envelope = Vec::new();
+		write_bytes_field(&mut envelope, 1, &public_key);
+		write_bytes_field(&mut envelope, 2, &payload_type);
+		write_bytes_field(&mut envelope, 3, &payload);
+		write_bytes_field(&mut envelope, 5, &signature);
+		envelope
+	});
+	Box::new(future)
+}
+
+/// Builds and signs the peer record of the local node, which lists the binary representations
+/// of the multiaddresses it is listening on. `seq` must be greater than the one of the previous
+/// records of the node, so that the new record replaces them. By convention, it is the current
+/// time in nanoseconds since the UNIX epoch, which is what `sign_peer_record_now` uses.
+///
+/// The receivers check it with `PeerRecordRef::from_signed_record`.
+pub fn sign_peer_record<I>(signer: &Signer, seq: u64, addrs: I)
+						   -> Box<Future<Item = Vec<u8>, Error = SigningError> + Send>
+	where I: IntoIterator,
+		  I::Item: AsRef<[u8]>
+{
+	let peer_id = PeerIdBuf::from_public_key(&signer.public().into_protobuf_encoding());
+
+	let mut record = Vec::new();
+	write_bytes_field(&mut record, 1, peer_id.as_bytes());
+	record.push(0x10);
+	write_varint(&mut record, seq);
+	for addr in addrs {
+		let mut address_info = Vec::new();
+		write_bytes_field(&mut address_info, 1, addr.as_ref());
+		write_bytes_field(&mut record, 3, &address_info);
+	}
+
+	sign_record(signer, PEER_RECORD_DOMAIN, PEER_RECORD_PAYLOAD_TYPE, record)
+}
+
+/// Same as `sign_peer_record`, with the current time in nanoseconds since the UNIX epoch as
+/// sequence number. The receivers can then measure the offset of our clock.
+pub fn sign_peer_record_now<I>(signer: &Signer, addrs: I)
+							   -> Box<Future<Item = Vec<u8>, Error = SigningError> + Send>
+	where I: IntoIterator,
+		  I::Item: AsRef<[u8]>
+{
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::new(0, 0));
+	let seq = now.as_secs() * 1_000_000_000 + u64::from(now.subsec_nanos());
+	sign_peer_record(signer, seq, addrs)
+}
+
+// Writes a length-delimited protobuf field.
+fn write_bytes_field(out: &mut Vec<u8>, field: u8, value: &[u8]) {
+	out.push((field << 3) | 2);
+	write_varint(out, value.len() as u64);
+	out.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{sign_peer_record, sign_peer_record_now, sign_record, Verifier};
+	use futures::Future;
+	use libp2p_identity_core::{Error as IdentityError, PeerIdBuf, PeerRecordRef, SignedRecordRef};
+	use Keypair;
+
+	#[test]
+	fn peer_record_roundtrip() {
+		let keypair = Keypair::generate_ed25519().unwrap();
+		let addr = [0x04, 127, 0, 0, 1, 0x06, 0x0f, 0xa1];
+		let envelope = sign_peer_record(&keypair, 3, vec![&addr[..]]).wait().unwrap();
+
+		let signed = SignedRecordRef::from_protobuf(&envelope).unwrap();
+		let record = PeerRecordRef::from_signed_record(&signed, &Verifier).unwrap();
+		let peer_id = PeerIdBuf::from_public_key(&keypair.public().into_protobuf_encoding());
+		assert_eq!(record.peer_id(), peer_id.as_peer_id());
+		assert_eq!(record.seq(), 3);
+		assert_eq!(record.addrs().map(|a| a.as_bytes()).collect::<Vec<_>>(), vec![&addr[..]]);
+	}
+
+	#[test]
+	fn peer_record_now() {
+		let keypair = Keypair::generate_ed25519().unwrap();
+		let first = sign_peer_record_now(&keypair, Vec::<Vec<u8>>::new()).wait().unwrap();
+		let second = sign_peer_record_now(&keypair, Vec::<Vec<u8>>::new()).wait().unwrap();
+
+		let seq = |envelope: &[u8]| {
+			let signed = SignedRecordRef::from_protobuf(envelope).unwrap();
+			PeerRecordRef::from_signed_record(&signed, &Verifier).unwrap().seq()
+		};
+		assert!(seq(&second) > seq(&first));
+	}
+
+	#[test]
+	fn wrong_domain() {
+		let keypair = Keypair::generate_ed25519().unwrap();
+		let envelope = sign_record(&keypair, b"domain", b"t", b"hi".to_vec()).wait().unwrap();
+
+		let signed = SignedRecordRef::from_protobuf(&envelope).unwrap();
+		let peer_id = PeerIdBuf::from_public_key(&keypair.public().into_protobuf_encoding());
+		assert_eq!(signed.verify(b"domain", &peer_id.as_peer_id(), &Verifier), Ok(&b"hi"[..]));
+		assert_eq!(signed.verify(b"other", &peer_id.as_peer_id(), &Verifier),
+				   Err(IdentityError::InvalidSignature));
+	}
+}
@@ -0,0 +1,294 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! RSA keys.
+//!
+//! The public keys are encoded in the DER format of the X.509 `SubjectPublicKeyInfo` structure,
+//! which is what go-libp2p uses. The signatures use the PKCS#1 v1.5 padding scheme with SHA-256.
+//!
+//! > **Note**: RSA keys can't be generated by this library. They can be generated with
+//! >           `openssl genrsa -out private.pem 2048` then converted to the PKCS#8 format with
+//! >           `openssl pkcs8 -in private.pem -topk8 -nocrypt -outform DER -out private.pk8`.
+
+use error::{DecodingError, SigningError};
+use ring::rand::SystemRandom;
+use ring::signature::{self, RSAKeyPair, RSASigningState};
+use ring::signature::{RSA_PKCS1_2048_8192_SHA256, RSA_PKCS1_SHA256};
+use std::fmt;
+use std::sync::Arc;
+use untrusted::{Input, Reader};
+
+/// RSA key pair.
+///
+/// Cloning a `Keypair` is cheap, as the private key is shared between the clones.
+#[derive(Clone)]
+pub struct Keypair {
+	inner: Arc<RSAKeyPair>,
+	// PKCS#8 document the key pair has been decoded from, as the private key can't be extracted
+	// from `RSAKeyPair`.
+	pkcs8: Arc<Vec<u8>>,
+	public: PublicKey,
+}
+
+impl Keypair {
+	/// Decodes a key pair from a PKCS#8 document.
+	pub fn from_pkcs8(pkcs8: &[u8]) -> Result<Keypair, DecodingError> {
+		let inner = RSAKeyPair::from_pkcs8(Input::from(pkcs8))
+			.map_err(|_| DecodingError::InvalidKey)?;
+		// `RSAKeyPair` doesn't give access to the public key, so we extract it from the document.
+		let public = public_key_from_pkcs8(pkcs8).map_err(|_| DecodingError::InvalidKey)?;
+		Ok(Keypair {
+			inner: Arc::new(inner),
+			pkcs8: Arc::new(pkcs8.to_vec()),
+			public: PublicKey(encode_pkix(&public)),
+		})
+	}
+
+	/// Returns the PKCS#8 document of the key pair, which contains the private key.
+	#[inline]
+	pub fn to_pkcs8(&self) -> &[u8] {
+		&self.pkcs8
+	}
+
+	/// Returns the public key.
+	#[inline]
+	pub fn public(&self) -> PublicKey {
+		self.public.clone()
+	}
+
+	/// Signs `msg` with the private key.
+	pub fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, SigningError> {
+		let mut state = RSASigningState::new(self.inner.clone()).map_err(|_| SigningError)?;
+		let mut signature = vec![0; self.inner.public_modulus_len()];
+		state.sign(&RSA_PKCS1_SHA256, &SystemRandom::new(), msg, &mut signature)
+			.map_err(|_| SigningError)?;
+		Ok(signature)
+	}
+}
+
+impl fmt::Debug for Keypair {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		// The private key is intentionally not printed.
+		f.debug_struct("Keypair").field("public", &self.public).finish()
+	}
+}
+
+/// RSA public key.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PublicKey(Vec<u8>);
+
+impl PublicKey {
+	/// Decodes a public key from a DER-encoded X.509 `SubjectPublicKeyInfo`, as produced by
+	/// `openssl rsa -pubout -outform DER` or by `x509.MarshalPKIXPublicKey` in Go.
+	pub fn from_pkix(bytes: &[u8]) -> Result<PublicKey, DecodingError> {
+		public_key_from_pkix(bytes).map_err(|_| DecodingError::InvalidKey)?;
+		Ok(PublicKey(bytes.to_vec()))
+	}
+
+	/// Returns the DER-encoded X.509 `SubjectPublicKeyInfo` of the key.
+	#[inline]
+	pub fn as_pkix(&self) -> &[u8] {
+		&self.0
+	}
+
+	/// Returns true if `sig` is a valid signature of `msg` by this key.
+	pub fn verify(&self, msg: &[u8], sig: &[u8]) -> bool {
+		let public = match public_key_from_pkix(&self.0) {
+			Ok(public) => public,
+			Err(()) => return false,
+		};
+		signature::verify(&RSA_PKCS1_2048_8192_SHA256, Input::from(public), Input::from(msg),
+						  Input::from(sig)).is_ok()
+	}
+}
+
+impl fmt::Debug for PublicKey {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "PublicKey(")?;
+		for byte in self.0.iter() {
+			write!(f, "{:02x}", byte)?;
+		}
+		write!(f, ")")
+	}
+}
+
+// DER tags of the types we need.
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const SEQUENCE: u8 = 0x30;
+
+// Content of the `AlgorithmIdentifier` of RSA keys: the `rsaEncryption` OID and NULL parameters.
+const RSA_ALGORITHM: &'static [u8] = &[
+	0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01, 0x05, 0x00
+];
+
+// Extracts the PKCS#1 `RSAPublicKey` structure from a `SubjectPublicKeyInfo`.
+fn public_key_from_pkix(pkix: &[u8]) -> Result<&[u8], ()> {
+	let public = Input::from(pkix).read_all((), |reader| {
+		read_tlv(reader, SEQUENCE)?.read_all((), |reader| {
+			if read_tlv(reader, SEQUENCE)?.as_slice_less_safe() != RSA_ALGORITHM {
+				return Err(());
+			}
+			read_tlv(reader, BIT_STRING)
+		})
+	})?;
+
+	// The first byte of a bit string is the number of unused bits.
+	let public = public.read_all((), |reader| {
+		if reader.read_byte().map_err(|_| ())? != 0 {
+			return Err(());
+		}
+		Ok(reader.skip_to_end())
+	})?;
+
+	// Checks that the `RSAPublicKey` contains a modulus and an exponent.
+	public.read_all((), |reader| {
+		read_tlv(reader, SEQUENCE)?.read_all((), |reader| {
+			read_tlv(reader, INTEGER)?;
+			read_tlv(reader, INTEGER)?;
+			Ok(())
+		})
+	})?;
+
+	Ok(public.as_slice_less_safe())
+}
+
+// Builds the PKCS#1 `RSAPublicKey` structure corresponding to a PKCS#8 document.
+fn public_key_from_pkcs8(pkcs8: &[u8]) -> Result<Vec<u8>, ()> {
+	let private = Input::from(pkcs8).read_all((), |reader| {
+		read_tlv(reader, SEQUENCE)?.read_all((), |reader| {
+			read_tlv(reader, INTEGER)?;
+			read_tlv(reader, SEQUENCE)?;
+			let private = read_tlv(reader, OCTET_STRING)?;
+			// Skips the optional attributes.
+			reader.skip_to_end();
+			Ok(private)
+		})
+	})?;
+
+	// The `RSAPrivateKey` starts with its version, the modulus and the public exponent.
+	let (modulus, exponent) = private.read_all((), |reader| {
+		read_tlv(reader, SEQUENCE)?.read_all((), |reader| {
+			read_tlv(reader, INTEGER)?;
+			let modulus = read_tlv(reader, INTEGER)?;
+			let exponent = read_tlv(reader, INTEGER)?;
+			reader.skip_to_end();
+			Ok((modulus, exponent))
+		})
+	})?;
+
+	let mut integers = Vec::new();
+	write_tlv(&mut integers, INTEGER, modulus.as_slice_less_safe());
+	write_tlv(&mut integers, INTEGER, exponent.as_slice_less_safe());
+	let mut public = Vec::new();
+	write_tlv(&mut public, SEQUENCE, &integers);
+	Ok(public)
+}
+
+// Wraps a PKCS#1 `RSAPublicKey` structure in a `SubjectPublicKeyInfo`.
+fn encode_pkix(public: &[u8]) -> Vec<u8> {
+	let mut bit_string = Vec::with_capacity(public.len() + 1);
+	bit_string.push(0);
+	bit_string.extend_from_slice(public);
+
+	let mut content = Vec::new();
+	write_tlv(&mut content, SEQUENCE, RSA_ALGORITHM);
+	write_tlv(&mut content, BIT_STRING, &bit_string);
+	let mut pkix = Vec::new();
+	write_tlv(&mut pkix, SEQUENCE, &content);
+	pkix
+}
+
+// Reads a DER value of type `tag` and returns its content.
+fn read_tlv<'a>(reader: &mut Reader<'a>, tag: u8) -> Result<Input<'a>, ()> {
+	if reader.read_byte().map_err(|_| ())? != tag {
+		return Err(());
+	}
+
+	// Lengths of 128 bytes or more are encoded as a number of bytes, followed with the length
+	// in big endian. Two bytes are enough for the keys we support.
+	let len = match reader.read_byte().map_err(|_| ())? {
+		len if len < 0x80 => len as usize,
+		0x81 => match reader.read_byte().map_err(|_| ())? {
+			len if len >= 0x80 => len as usize,
+			_ => return Err(()),
+		},
+		0x82 => {
+			let high = reader.read_byte().map_err(|_| ())? as usize;
+			let low = reader.read_byte().map_err(|_| ())? as usize;
+			match (high << 8) | low {
+				len if len >= 0x100 => len,
+				_ => return Err(()),
+			}
+		},
+		_ => return Err(()),
+	};
+
+	reader.skip_and_get_input(len).map_err(|_| ())
+}
+
+// Writes a DER value of type `tag` with the given content.
+fn write_tlv(out: &mut Vec<u8>, tag: u8, content: &[u8]) {
+	out.push(tag);
+	if content.len() < 0x80 {
+		out.push(content.len() as u8);
+	} else if content.len() < 0x100 {
+		out.push(0x81);
+		out.push(content.len() as u8);
+	} else {
+		assert!(content.len() < 0x10000, "RSA keys are never that large");
+		out.push(0x82);
+		out.push((content.len() >> 8) as u8);
+		out.push(content.len() as u8);
+	}
+	out.extend_from_slice(content);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Keypair, PublicKey};
+
+	#[test]
+	fn public_key_from_pkcs8() {
+		let keypair = Keypair::from_pkcs8(include_bytes!("../tests/test-private-key.pk8")).unwrap();
+		let expected = include_bytes!("../tests/test-public-key.der");
+		assert_eq!(keypair.public().as_pkix(), &expected[..]);
+		assert_eq!(PublicKey::from_pkix(expected).unwrap(), keypair.public());
+	}
+
+	#[test]
+	fn sign_verify() {
+		let keypair = Keypair::from_pkcs8(include_bytes!("../tests/test-private-key.pk8")).unwrap();
+		let signature = keypair.sign(b"hello").unwrap();
+		assert!(keypair.public().verify(b"hello", &signature));
+		assert!(!keypair.public().verify(b"world", &signature));
+		assert!(!keypair.public().verify(b"hello", &[1, 2, 3]));
+	}
+
+	#[test]
+	fn invalid_pkix() {
+		let mut bytes = include_bytes!("../tests/test-public-key.der").to_vec();
+		assert!(PublicKey::from_pkix(&bytes[.. 100]).is_err());
+		// Corrupts the OID of the algorithm.
+		bytes[10] ^= 0xff;
+		assert!(PublicKey::from_pkix(&bytes).is_err());
+	}
+}
@@ -0,0 +1,175 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Secp256k1 keys.
+//!
+//! The signatures are ECDSA signatures of the SHA-256 hash of the message, encoded in DER. The
+//! public keys are encoded as compressed points.
+
+use error::{DecodingError, GenerationError};
+use libsecp256k1::{self, Message, Secp256k1, SecretKey, Signature};
+use ring::digest::{self, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt;
+
+lazy_static! {
+	// Context used for all the operations. Building it is expensive.
+	static ref SECP: Secp256k1<libsecp256k1::All> = Secp256k1::new();
+}
+
+/// Secp256k1 key pair.
+#[derive(Clone)]
+pub struct Keypair {
+	secret: SecretKey,
+	public: PublicKey,
+}
+
+impl Keypair {
+	/// Generates a new random key pair.
+	pub fn generate() -> Result<Keypair, GenerationError> {
+		let rng = SystemRandom::new();
+		loop {
+			let mut bytes = [0; 32];
+			rng.fill(&mut bytes).map_err(|_| GenerationError)?;
+			// A few values, such as zero, aren't valid secret keys. The probability of
+			// generating them is negligible, but we try again if it happens.
+			if let Ok(keypair) = Keypair::from_secret_key_bytes(&bytes) {
+				return Ok(keypair);
+			}
+		}
+	}
+
+	/// Builds a key pair from the 32 bytes of the secret key.
+	pub fn from_secret_key_bytes(bytes: &[u8]) -> Result<Keypair, DecodingError> {
+		let secret = SecretKey::from_slice(&SECP, bytes).map_err(|_| DecodingError::InvalidKey)?;
+		let public = libsecp256k1::key::PublicKey::from_secret_key(&SECP, &secret);
+		Ok(Keypair {
+			secret: secret,
+			public: PublicKey(public),
+		})
+	}
+
+	/// Returns the 32 bytes of the secret key.
+	pub fn secret_key_bytes(&self) -> [u8; 32] {
+		let mut bytes = [0; 32];
+		bytes.copy_from_slice(&self.secret[..]);
+		bytes
+	}
+
+	/// Returns the public key.
+	#[inline]
+	pub fn public(&self) -> PublicKey {
+		self.public.clone()
+	}
+
+	/// Signs `msg` with the private key.
+	pub fn sign(&self, msg: &[u8]) -> Vec<u8> {
+		SECP.sign(&hash(msg), &self.secret).serialize_der(&SECP)
+	}
+}
+
+impl fmt::Debug for Keypair {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		// The private key is intentionally not printed.
+		f.debug_struct("Keypair").field("public", &self.public).finish()
+	}
+}
+
+/// Secp256k1 public key.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PublicKey(libsecp256k1::key::PublicKey);
+
+impl PublicKey {
+	/// Decodes a public key from a compressed or uncompressed point.
+	pub fn from_bytes(bytes: &[u8]) -> Result<PublicKey, DecodingError> {
+		libsecp256k1::key::PublicKey::from_slice(&SECP, bytes)
+			.map(PublicKey)
+			.map_err(|_| DecodingError::InvalidKey)
+	}
+
+	/// Returns the 33 bytes of the compressed point.
+	#[inline]
+	pub fn to_bytes(&self) -> [u8; 33] {
+		self.0.serialize()
+	}
+
+	/// Returns true if `sig` is a valid DER-encoded signature of `msg` by this key.
+	pub fn verify(&self, msg: &[u8], sig: &[u8]) -> bool {
+		let mut signature = match Signature::from_der(&SECP, sig) {
+			Ok(signature) => signature,
+			Err(_) => return false,
+		};
+		// Other implementations may produce signatures whose `s` is in the upper half, which
+		// the library refuses.
+		signature.normalize_s(&SECP);
+		SECP.verify(&hash(msg), &signature, &self.0).is_ok()
+	}
+}
+
+impl ::std::hash::Hash for PublicKey {
+	#[inline]
+	fn hash<H: ::std::hash::Hasher>(&self, state: &mut H) {
+		self.to_bytes().hash(state)
+	}
+}
+
+impl fmt::Debug for PublicKey {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "PublicKey(")?;
+		for byte in self.to_bytes().iter() {
+			write!(f, "{:02x}", byte)?;
+		}
+		write!(f, ")")
+	}
+}
+
+// Returns the SHA-256 hash of `msg`, which is what is signed.
+fn hash(msg: &[u8]) -> Message {
+	Message::from_slice(digest::digest(&SHA256, msg).as_ref())
+		.expect("a SHA-256 hash has the length of a message")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Keypair, PublicKey};
+
+	#[test]
+	fn sign_verify() {
+		let keypair = Keypair::generate().unwrap();
+		let signature = keypair.sign(b"hello");
+		assert!(keypair.public().verify(b"hello", &signature));
+		assert!(!keypair.public().verify(b"world", &signature));
+		assert!(!keypair.public().verify(b"hello", &[1, 2, 3]));
+
+		let other = Keypair::generate().unwrap();
+		assert!(!other.public().verify(b"hello", &signature));
+	}
+
+	#[test]
+	fn key_encodings() {
+		let keypair = Keypair::generate().unwrap();
+		let decoded = Keypair::from_secret_key_bytes(&keypair.secret_key_bytes()).unwrap();
+		assert_eq!(decoded.public(), keypair.public());
+
+		let public = PublicKey::from_bytes(&keypair.public().to_bytes()).unwrap();
+		assert_eq!(public, keypair.public());
+		assert!(Keypair::from_secret_key_bytes(&[0; 32]).is_err());
+	}
+}
@@ -0,0 +1,63 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Defines the `Signer` trait, which abstracts over the way the identity key of the local node
+//! produces signatures.
+//!
+//! The handshakes, the signed records and the pubsub messages never need to access the private
+//! key directly. They only ever ask for a signature of some data. This makes it possible to keep
+//! the private key inside of a hardware security module or a secure enclave, and to never have it
+//! exist as a plain file on disk.
+
+use error::SigningError;
+use futures::future;
+use futures::Future;
+use {Keypair, PublicKey};
+
+/// Implemented on objects that can sign data with the identity key of the local node.
+///
+/// The users of the identity key store it as an `Arc<Signer>`, so that a single hardware-backed
+/// key can be shared by all of them. `Keypair` implements this trait for the keys that are held
+/// in memory.
+pub trait Signer: Send + Sync {
+	/// Returns the public key that verifies the signatures.
+	fn public(&self) -> PublicKey;
+
+	/// Signs `data` with the private key.
+	///
+	/// The signature must be the one that `PublicKey::verify` expects for the type of the key.
+	/// For example, RSA signatures must use the PKCS#1 v1.5 padding scheme with SHA-256.
+	///
+	/// Since signing may involve communicating with an external device, this method returns a
+	/// future instead of the signature directly.
+	fn sign(&self, data: &[u8]) -> Box<Future<Item = Vec<u8>, Error = SigningError> + Send>;
+}
+
+impl Signer for Keypair {
+	#[inline]
+	fn public(&self) -> PublicKey {
+		Keypair::public(self)
+	}
+
+	#[inline]
+	fn sign(&self, data: &[u8]) -> Box<Future<Item = Vec<u8>, Error = SigningError> + Send> {
+		Box::new(future::result(Keypair::sign(self, data)))
+	}
+}
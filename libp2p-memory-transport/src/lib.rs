@@ -0,0 +1,296 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+// TODO: use this once stable ; for now we just copy-paste the content of the README.md
+//#![doc(include = "../README.md")]
+
+//! Implementation of the libp2p `Transport` trait that connects the listeners and dialers of the
+//! same process through in-memory channels.
+//!
+//! This transport is meant for tests. No socket is opened, therefore the tests don't depend on
+//! the ports available on the machine and can run in parallel.
+//!
+//! # Usage
+//!
+//! The addresses have the form `/memory/<id>`. Listening on `/memory/0` picks an id that isn't
+//! used yet, and the actual address is returned by `listen_on`. Any `MemoryTransport` of the
+//! process can then dial this address.
+//!
+//! ```
+//! extern crate libp2p_memory_transport;
+//! extern crate libp2p_swarm;
+//!
+//! use libp2p_memory_transport::MemoryTransport;
+//! use libp2p_swarm::Transport;
+//!
+//! # fn main() {
+//! let (_listener, addr) = MemoryTransport.listen_on("/memory/0".parse().unwrap())
+//!     .unwrap_or_else(|_| panic!("memory addresses are supported"));
+//! let _dial = MemoryTransport.dial(addr);
+//! # }
+//! ```
+//!
+//! The address is freed when the listener is destroyed. Dialing an address that nobody listens
+//! on fails with a `ConnectionRefused` error. The listener sees each dialer as coming from a
+//! `/memory/<id>` address that is unique but can't be dialed.
+
+extern crate futures;
+#[macro_use]
+extern crate lazy_static;
+extern crate libp2p_swarm as swarm;
+extern crate multiaddr;
+extern crate parking_lot;
+extern crate rand;
+extern crate rw_stream_sink;
+
+use futures::{future, Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use futures::sync::mpsc;
+use multiaddr::{AddrComponent, Multiaddr};
+use parking_lot::Mutex;
+use rw_stream_sink::RwStreamSink;
+use std::collections::HashMap;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use swarm::Transport;
+
+lazy_static! {
+	// The listeners of the process, by id.
+	static ref LISTENERS: Mutex<HashMap<u64, mpsc::UnboundedSender<(Connection, Multiaddr)>>> =
+		Mutex::new(HashMap::new());
+}
+
+/// Transport whose connections are in-memory channels. See the crate-level documentation.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MemoryTransport;
+
+/// One end of an in-memory connection.
+pub type Connection = RwStreamSink<Chan>;
+
+/// Carries the data of an in-memory connection. Wrapped in a `Connection`.
+pub struct Chan {
+	incoming: mpsc::UnboundedReceiver<Vec<u8>>,
+	outgoing: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl Stream for Chan {
+	type Item = Vec<u8>;
+	type Error = IoError;
+
+	#[inline]
+	fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+		Ok(self.incoming.poll().expect("an unbounded receiver never produces an error"))
+	}
+}
+
+impl Sink for Chan {
+	type SinkItem = Vec<u8>;
+	type SinkError = IoError;
+
+	#[inline]
+	fn start_send(&mut self, item: Vec<u8>) -> StartSend<Vec<u8>, IoError> {
+		match self.outgoing.start_send(item) {
+			Ok(AsyncSink::Ready) => Ok(AsyncSink::Ready),
+			Ok(AsyncSink::NotReady(item)) => Ok(AsyncSink::NotReady(item)),
+			Err(_) => Err(closed_error()),
+		}
+	}
+
+	#[inline]
+	fn poll_complete(&mut self) -> Poll<(), IoError> {
+		match self.outgoing.poll_complete() {
+			Ok(Async::Ready(())) => Ok(Async::Ready(())),
+			Ok(Async::NotReady) => Ok(Async::NotReady),
+			Err(_) => Err(closed_error()),
+		}
+	}
+}
+
+// Error produced when writing on a connection whose other end has been dropped.
+fn closed_error() -> IoError {
+	IoError::new(IoErrorKind::BrokenPipe, "the remote closed the connection")
+}
+
+// Builds the two ends of a new connection.
+fn connection_pair() -> (Connection, Connection) {
+	let (a_tx, a_rx) = mpsc::unbounded();
+	let (b_tx, b_rx) = mpsc::unbounded();
+	let a = Chan { incoming: a_rx, outgoing: b_tx };
+	let b = Chan { incoming: b_rx, outgoing: a_tx };
+	(RwStreamSink::new(a), RwStreamSink::new(b))
+}
+
+// Returns the id of a `/memory/<id>` address.
+fn memory_addr_id(addr: &Multiaddr) -> Option<u64> {
+	let mut iter = addr.iter();
+	match (iter.next(), iter.next()) {
+		(Some(AddrComponent::Memory(id)), None) => Some(id),
+		_ => None,
+	}
+}
+
+// Returns a non-zero id that no listener uses.
+fn unused_id(listeners: &HashMap<u64, mpsc::UnboundedSender<(Connection, Multiaddr)>>) -> u64 {
+	loop {
+		let id = rand::random();
+		if id != 0 && !listeners.contains_key(&id) {
+			break id;
+		}
+	}
+}
+
+/// Produces the connections dialed to a `/memory/<id>` address. Frees the address when
+/// destroyed.
+pub struct Listener {
+	id: u64,
+	incoming: mpsc::UnboundedReceiver<(Connection, Multiaddr)>,
+}
+
+impl Stream for Listener {
+	type Item = (future::FutureResult<Connection, IoError>, Multiaddr);
+	type Error = IoError;
+
+	#[inline]
+	fn poll(&mut self) -> Poll<Option<Self::Item>, IoError> {
+		match self.incoming.poll().expect("an unbounded receiver never produces an error") {
+			Async::Ready(Some((connection, addr))) => {
+				Ok(Async::Ready(Some((future::ok(connection), addr))))
+			},
+			// The sender is only destroyed along with the listener.
+			Async::Ready(None) => Ok(Async::Ready(None)),
+			Async::NotReady => Ok(Async::NotReady),
+		}
+	}
+}
+
+impl Drop for Listener {
+	#[inline]
+	fn drop(&mut self) {
+		LISTENERS.lock().remove(&self.id);
+	}
+}
+
+impl Transport for MemoryTransport {
+	type RawConn = Connection;
+	type Listener = future::Either<Listener, future::IntoStream<future::FutureResult<
+		(Self::ListenerUpgrade, Multiaddr), IoError>>>;
+	type ListenerUpgrade = future::FutureResult<Connection, IoError>;
+	type Dial = future::FutureResult<Connection, IoError>;
+
+	fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+		let id = match memory_addr_id(&addr) {
+			Some(id) => id,
+			None => return Err((self, addr)),
+		};
+
+		let mut listeners = LISTENERS.lock();
+		let id = if id == 0 { unused_id(&listeners) } else { id };
+		if listeners.contains_key(&id) {
+			let err = IoError::new(IoErrorKind::AddrInUse, "memory address already in use");
+			return Ok((future::Either::B(future::err(err).into_stream()), addr));
+		}
+
+		let (tx, rx) = mpsc::unbounded();
+		listeners.insert(id, tx);
+		let listener = Listener {
+			id: id,
+			incoming: rx,
+		};
+		Ok((future::Either::A(listener), AddrComponent::Memory(id).into()))
+	}
+
+	fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+		let id = match memory_addr_id(&addr) {
+			Some(id) => id,
+			None => return Err((self, addr)),
+		};
+
+		let listeners = LISTENERS.lock();
+		let listener = match listeners.get(&id) {
+			Some(listener) => listener,
+			None => {
+				let err = IoError::new(IoErrorKind::ConnectionRefused, "nobody listens there");
+				return Ok(future::err(err));
+			},
+		};
+
+		let (local, remote) = connection_pair();
+		let dialer_addr = AddrComponent::Memory(unused_id(&listeners)).into();
+		match listener.unbounded_send((remote, dialer_addr)) {
+			Ok(()) => Ok(future::ok(local)),
+			Err(_) => {
+				let err = IoError::new(IoErrorKind::ConnectionRefused, "listener closed");
+				Ok(future::err(err))
+			},
+		}
+	}
+
+	#[inline]
+	fn nat_traversal(&self, _: &Multiaddr, _: &Multiaddr) -> Option<Multiaddr> {
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	extern crate tokio_core;
+	extern crate tokio_io;
+
+	use self::tokio_core::reactor::Core;
+	use futures::{Future, Stream};
+	use std::io::ErrorKind as IoErrorKind;
+	use swarm::Transport;
+	use MemoryTransport;
+
+	#[test]
+	fn communicate() {
+		let mut core = Core::new().unwrap();
+
+		let (listener, addr) = MemoryTransport.listen_on("/memory/0".parse().unwrap())
+			.unwrap_or_else(|_| panic!());
+		assert_ne!(addr.to_string(), "/memory/0");
+
+		let server = listener
+			.into_future()
+			.map_err(|(err, _)| err)
+			.and_then(|(conn, _)| conn.unwrap().0)
+			.and_then(|conn| tokio_io::io::read_exact(conn, [0; 5]))
+			.map(|(_, buf)| buf);
+
+		let client = MemoryTransport.dial(addr)
+			.unwrap_or_else(|_| panic!())
+			.and_then(|conn| tokio_io::io::write_all(conn, b"hello"));
+
+		let (received, _) = core.run(server.join(client)).unwrap();
+		assert_eq!(&received, b"hello");
+	}
+
+	#[test]
+	fn address_freed_on_drop() {
+		let (listener, addr) = MemoryTransport.listen_on("/memory/0".parse().unwrap())
+			.unwrap_or_else(|_| panic!());
+
+		let (second, _) = MemoryTransport.listen_on(addr.clone()).unwrap_or_else(|_| panic!());
+		let err = second.into_future().wait().map(|_| ()).map_err(|(err, _)| err).unwrap_err();
+		assert_eq!(err.kind(), IoErrorKind::AddrInUse);
+
+		drop(listener);
+		let err = MemoryTransport.dial(addr).unwrap_or_else(|_| panic!()).wait().unwrap_err();
+		assert_eq!(err.kind(), IoErrorKind::ConnectionRefused);
+	}
+}
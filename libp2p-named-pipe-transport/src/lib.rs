@@ -0,0 +1,71 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+// TODO: use this once stable ; for now we just copy-paste the content of the README.md
+//#![doc(include = "../README.md")]
+
+//! Implementation of the libp2p `Transport` trait for Windows named pipes.
+//!
+//! Named pipes are the Windows equivalent of Unix domain sockets, and are useful for connecting
+//! processes that run on the same machine. A pipe named `\\.\pipe\<name>` is designated by the
+//! `/unix/<name>` multiaddress. The name must not be empty and must not contain any backslash.
+//!
+//! # Usage
+//!
+//! This library only works on Windows. On other platforms, the crate is empty.
+//!
+//! Create [a tokio `Core`](https://docs.rs/tokio-core/0.1/tokio_core/reactor/struct.Core.html),
+//! then grab a handle by calling the `handle()` method on it, then create a `NamedPipeConfig` and
+//! pass the handle.
+//!
+//! ```ignore
+//! extern crate libp2p_named_pipe_transport;
+//! extern crate tokio_core;
+//!
+//! use libp2p_named_pipe_transport::NamedPipeConfig;
+//! use tokio_core::reactor::Core;
+//!
+//! # fn main() {
+//! let mut core = Core::new().unwrap();
+//! let pipes = NamedPipeConfig::new(core.handle());
+//! # }
+//! ```
+//!
+//! The `NamedPipeConfig` struct implements the `Transport` trait of the `swarm` library. See the
+//! documentation of `swarm` and of libp2p in general to learn how to use the `Transport` trait.
+//!
+//! > **Note**: A listener always keeps one instance of the pipe waiting for a client. Dialing a
+//! >           pipe fails if no listener exists, or if another client connected to the waiting
+//! >           instance and the listener hasn't created the next one yet.
+
+extern crate futures;
+extern crate libp2p_swarm as swarm;
+extern crate multiaddr;
+extern crate tokio_core;
+extern crate tokio_io;
+
+#[cfg(windows)]
+extern crate mio_named_pipes;
+
+#[cfg(windows)]
+mod windows;
+
+#[cfg(windows)]
+pub use self::windows::{NamedPipeConfig, NamedPipeListener, NamedPipeStream};
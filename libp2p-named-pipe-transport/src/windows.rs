@@ -0,0 +1,245 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::{Async, Poll, Stream};
+use futures::future::{self, FutureResult};
+use mio_named_pipes::NamedPipe;
+use multiaddr::{AddrComponent, Multiaddr};
+use std::fs::OpenOptions;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::os::windows::fs::OpenOptionsExt;
+use std::os::windows::io::{FromRawHandle, IntoRawHandle};
+use swarm::Transport;
+use tokio_core::reactor::{Handle, PollEvented};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+// Flag of `CreateFile` that enables the asynchronous operations on the handle.
+const FILE_FLAG_OVERLAPPED: u32 = 0x4000_0000;
+
+/// Represents the configuration for a named pipe transport capability for libp2p.
+///
+/// Each connection created by this config is tied to a tokio reactor.
+#[derive(Debug, Clone)]
+pub struct NamedPipeConfig {
+	event_loop: Handle,
+}
+
+impl NamedPipeConfig {
+	/// Creates a new configuration object for named pipes. The `Handle` is a tokio reactor the
+	/// connections will be created with.
+	#[inline]
+	pub fn new(handle: Handle) -> NamedPipeConfig {
+		NamedPipeConfig { event_loop: handle }
+	}
+}
+
+impl Transport for NamedPipeConfig {
+	type RawConn = NamedPipeStream;
+	type Listener = NamedPipeListener;
+	type ListenerUpgrade = FutureResult<Self::RawConn, IoError>;
+	type Dial = FutureResult<Self::RawConn, IoError>;
+
+	fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+		let name = match multiaddr_to_pipe_name(&addr) {
+			Some(name) => name,
+			None => return Err((self, addr)),
+		};
+
+		// Like for TCP, an error while creating the pipe is reported by the listener.
+		let pending = create_instance(&name, &self.event_loop);
+		let listener = NamedPipeListener {
+			name: name,
+			addr: addr.clone(),
+			event_loop: self.event_loop,
+			pending: Some(pending),
+		};
+
+		Ok((listener, addr))
+	}
+
+	fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+		let name = match multiaddr_to_pipe_name(&addr) {
+			Some(name) => name,
+			None => return Err((self, addr)),
+		};
+
+		let result = OpenOptions::new()
+			.read(true)
+			.write(true)
+			.custom_flags(FILE_FLAG_OVERLAPPED)
+			.open(&name)
+			.and_then(|file| {
+				let pipe = unsafe { NamedPipe::from_raw_handle(file.into_raw_handle()) };
+				PollEvented::new(pipe, &self.event_loop)
+			})
+			.map(|inner| NamedPipeStream { inner: inner });
+
+		Ok(future::result(result))
+	}
+
+	#[inline]
+	fn nat_traversal(&self, _: &Multiaddr, _: &Multiaddr) -> Option<Multiaddr> {
+		// Named pipes are local to the machine.
+		None
+	}
+}
+
+/// Stream of the clients that connect to a named pipe.
+#[derive(Debug)]
+pub struct NamedPipeListener {
+	// Path of the pipe.
+	name: String,
+	// Address that was passed to `listen_on`, reported for each client.
+	addr: Multiaddr,
+	event_loop: Handle,
+	// Instance of the pipe that waits for a client, or the error that happened when creating it.
+	// `None` if the creation must be retried.
+	pending: Option<Result<PollEvented<NamedPipe>, IoError>>,
+}
+
+impl Stream for NamedPipeListener {
+	type Item = (FutureResult<NamedPipeStream, IoError>, Multiaddr);
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Option<Self::Item>, IoError> {
+		let pending = match self.pending.take() {
+			Some(Ok(pipe)) => pipe,
+			Some(Err(err)) => return Err(err),
+			None => create_instance(&self.name, &self.event_loop)?,
+		};
+
+		if let Async::NotReady = pending.poll_write() {
+			self.pending = Some(Ok(pending));
+			return Ok(Async::NotReady);
+		}
+
+		match pending.get_ref().connect() {
+			Ok(()) => (),
+			Err(ref err) if err.kind() == IoErrorKind::WouldBlock => {
+				// The pipe becomes writable once a client is connected.
+				pending.need_write();
+				self.pending = Some(Ok(pending));
+				return Ok(Async::NotReady);
+			},
+			Err(err) => return Err(err),
+		};
+
+		// Create the next instance right away, so that the other clients don't have to wait for
+		// the listener to be polled again.
+		self.pending = Some(create_instance(&self.name, &self.event_loop));
+		let stream = NamedPipeStream { inner: pending };
+		Ok(Async::Ready(Some((future::ok(stream), self.addr.clone()))))
+	}
+}
+
+/// Connection through a named pipe.
+#[derive(Debug)]
+pub struct NamedPipeStream {
+	inner: PollEvented<NamedPipe>,
+}
+
+impl Read for NamedPipeStream {
+	#[inline]
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+		self.inner.read(buf)
+	}
+}
+
+impl AsyncRead for NamedPipeStream {}
+
+impl Write for NamedPipeStream {
+	#[inline]
+	fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+		self.inner.write(buf)
+	}
+
+	#[inline]
+	fn flush(&mut self) -> Result<(), IoError> {
+		self.inner.flush()
+	}
+}
+
+impl AsyncWrite for NamedPipeStream {
+	#[inline]
+	fn shutdown(&mut self) -> Poll<(), IoError> {
+		Ok(Async::Ready(()))
+	}
+}
+
+// Creates a new instance of the pipe and registers it to the reactor.
+fn create_instance(name: &str, event_loop: &Handle) -> Result<PollEvented<NamedPipe>, IoError> {
+	let pipe = NamedPipe::new(name)?;
+	PollEvented::new(pipe, event_loop)
+}
+
+// Turns a `/unix/<name>` multiaddress into the path of the pipe.
+fn multiaddr_to_pipe_name(addr: &Multiaddr) -> Option<String> {
+	let mut iter = addr.iter();
+	match (iter.next(), iter.next()) {
+		(Some(AddrComponent::UNIX(ref name)), None) if !name.is_empty() && !name.contains('\\') => {
+			Some(format!(r"\\.\pipe\{}", name))
+		},
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{multiaddr_to_pipe_name, NamedPipeConfig};
+	use futures::{Future, Stream};
+	use multiaddr::{AddrComponent, Multiaddr};
+	use swarm::Transport;
+	use tokio_core::reactor::Core;
+	use tokio_io;
+
+	#[test]
+	fn multiaddr_conversion() {
+		let addr: Multiaddr = AddrComponent::UNIX("libp2p".to_owned()).into();
+		assert_eq!(multiaddr_to_pipe_name(&addr), Some(r"\\.\pipe\libp2p".to_owned()));
+
+		let addr: Multiaddr = AddrComponent::UNIX(r"lib\p2p".to_owned()).into();
+		assert_eq!(multiaddr_to_pipe_name(&addr), None);
+		let addr: Multiaddr = AddrComponent::UNIX(String::new()).into();
+		assert_eq!(multiaddr_to_pipe_name(&addr), None);
+		assert_eq!(multiaddr_to_pipe_name(&"/ip4/127.0.0.1/tcp/1234".parse().unwrap()), None);
+	}
+
+	#[test]
+	fn communicate() {
+		let mut core = Core::new().unwrap();
+		let pipes = NamedPipeConfig::new(core.handle());
+		let addr: Multiaddr = AddrComponent::UNIX("libp2p-named-pipe-test".to_owned()).into();
+
+		let (listener, _) = pipes.clone().listen_on(addr.clone()).unwrap();
+		let server = listener
+			.into_future()
+			.map_err(|(err, _)| err)
+			.and_then(|(conn, _)| conn.unwrap().0)
+			.and_then(|conn| tokio_io::io::read_exact(conn, [0; 5]))
+			.map(|(_, received)| received);
+
+		let client = pipes.dial(addr)
+			.unwrap()
+			.and_then(|conn| tokio_io::io::write_all(conn, b"hello"));
+
+		let (received, _) = core.run(server.join(client)).unwrap();
+		assert_eq!(&received, b"hello");
+	}
+}
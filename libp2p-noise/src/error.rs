@@ -0,0 +1,120 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Defines the `NoiseError` enum that groups all possible errors of the Noise handshake and of
+//! the encrypted connection.
+
+use std::error;
+use std::fmt;
+use std::io::Error as IoError;
+
+/// Error at the Noise layer.
+#[derive(Debug)]
+pub enum NoiseError {
+	/// I/O error.
+	IoError(IoError),
+
+	/// A handshake message is shorter than the keys it should contain.
+	MessageTooShort,
+
+	/// A message is too large to fit in a frame.
+	MessageTooLarge,
+
+	/// Failed to decrypt a message, or the authentication tag didn't match.
+	DecryptionFailed,
+
+	/// The maximum number of messages that can be encrypted with the same key has been reached.
+	NonceExhausted,
+
+	/// Failed to generate a key.
+	KeyGenerationFailed,
+
+	/// Failed to parse the payload of a handshake message.
+	PayloadParsingFailure,
+
+	/// The remote uses a type of identity key that isn't supported.
+	UnsupportedKeyType,
+
+	/// Failed to sign the static key with our identity key.
+	SigningFailure,
+
+	/// The signature of the static key of the remote doesn't verify its identity key.
+	SignatureVerificationFailed,
+}
+
+impl error::Error for NoiseError {
+	#[inline]
+	fn description(&self) -> &str {
+		match *self {
+			NoiseError::IoError(_) => {
+				"I/O error"
+			}
+			NoiseError::MessageTooShort => {
+				"A handshake message is shorter than the keys it should contain"
+			}
+			NoiseError::MessageTooLarge => {
+				"A message is too large to fit in a frame"
+			}
+			NoiseError::DecryptionFailed => {
+				"Failed to decrypt a message, or the authentication tag didn't match"
+			}
+			NoiseError::NonceExhausted => {
+				"The maximum number of messages that can be encrypted with the same key has been \
+				 reached"
+			}
+			NoiseError::KeyGenerationFailed => {
+				"Failed to generate a key"
+			}
+			NoiseError::PayloadParsingFailure => {
+				"Failed to parse the payload of a handshake message"
+			}
+			NoiseError::UnsupportedKeyType => {
+				"The remote uses a type of identity key that isn't supported"
+			}
+			NoiseError::SigningFailure => {
+				"Failed to sign the static key with our identity key"
+			}
+			NoiseError::SignatureVerificationFailed => {
+				"The signature of the static key of the remote doesn't verify its identity key"
+			}
+		}
+	}
+
+	fn cause(&self) -> Option<&error::Error> {
+		match *self {
+			NoiseError::IoError(ref err) => Some(err),
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for NoiseError {
+	#[inline]
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		write!(fmt, "{}", error::Error::description(self))
+	}
+}
+
+impl From<IoError> for NoiseError {
+	#[inline]
+	fn from(err: IoError) -> NoiseError {
+		NoiseError::IoError(err)
+	}
+}
@@ -0,0 +1,321 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Implementation of the `XX` handshake pattern, and of the exchange of the libp2p payloads.
+//!
+//! ```text
+//! -> e
+//! <- e, ee, s, es
+//! -> s, se
+//! ```
+//!
+//! The responder sends its payload in the second message, and the initiator in the third one.
+//! Each message is prefixed with its length as a 16 bits big-endian integer.
+
+use error::NoiseError;
+use futures::Future;
+use futures::future;
+use io::NoiseOutput;
+use payload::{self, Payload};
+use libp2p_keys::Signer;
+use std::sync::Arc;
+use symmetric::{CipherState, Keypair, SymmetricState, KEY_LEN};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::io::{flush, read_exact, write_all};
+
+/// Name of the Noise protocol, which is mixed into the handshake hash.
+const PROTOCOL_NAME: &'static [u8] = b"Noise_XX_25519_ChaChaPoly_SHA256";
+
+/// Maximum length of a message, as it must fit in the 16 bits length prefix.
+pub const MAX_MESSAGE_LEN: usize = 65535;
+
+/// Performs the handshake on `socket`.
+///
+/// On success, produces the encrypted connection along with the `PublicKey` protobuf message of
+/// the identity key of the remote.
+pub fn handshake<S>(
+	socket: S,
+	static_keypair: Keypair,
+	identity_key: Vec<u8>,
+	signer: Arc<Signer>,
+	initiator: bool,
+) -> Box<Future<Item = NoiseOutput<S>, Error = NoiseError> + Send>
+	where S: AsyncRead + AsyncWrite + Send + 'static
+{
+	let mut state = match HandshakeState::new(static_keypair) {
+		Ok(state) => state,
+		Err(err) => return Box::new(future::err(err)),
+	};
+
+	let future = signer.sign(&payload::signed_data(state.s.public()))
+		.map_err(|_| NoiseError::SigningFailure)
+		.map(move |identity_sig| {
+			let payload = Payload {
+				identity_key: identity_key,
+				identity_sig: identity_sig,
+			};
+			payload.encode()
+		});
+
+	if initiator {
+		let future = future
+			.and_then(move |payload| -> Result<_, NoiseError> {
+				let msg = state.write_message_1()?;
+				Ok((payload, state, msg))
+			})
+			.and_then(move |(payload, state, msg)| {
+				send_frame(socket, msg).map(move |socket| (socket, payload, state))
+			})
+			.and_then(|(socket, payload, state)| {
+				recv_frame(socket).map(move |(socket, msg)| (socket, msg, payload, state))
+			})
+			.and_then(|(socket, msg, payload, mut state)| -> Result<_, NoiseError> {
+				let remote_payload = state.read_message_2(&msg)?;
+				let remote_key = verify_payload(&remote_payload, &state.rs)?;
+				let msg = state.write_message_3(&payload)?;
+				Ok((socket, msg, state, remote_key))
+			})
+			.and_then(|(socket, msg, state, remote_key)| {
+				send_frame(socket, msg).map(move |socket| {
+					let (send, recv) = state.into_ciphers(true);
+					debug!(target: "libp2p-noise", "noise handshake success as initiator");
+					NoiseOutput::new(socket, send, recv, remote_key)
+				})
+			});
+		Box::new(future)
+	} else {
+		let future = future
+			.and_then(move |payload| {
+				recv_frame(socket).map(move |(socket, msg)| (socket, msg, payload, state))
+			})
+			.and_then(|(socket, msg, payload, mut state)| -> Result<_, NoiseError> {
+				state.read_message_1(&msg)?;
+				let msg = state.write_message_2(&payload)?;
+				Ok((socket, msg, state))
+			})
+			.and_then(|(socket, msg, state)| {
+				send_frame(socket, msg).map(move |socket| (socket, state))
+			})
+			.and_then(|(socket, state)| {
+				recv_frame(socket).map(move |(socket, msg)| (socket, msg, state))
+			})
+			.and_then(|(socket, msg, mut state)| -> Result<_, NoiseError> {
+				let remote_payload = state.read_message_3(&msg)?;
+				let remote_key = verify_payload(&remote_payload, &state.rs)?;
+				let (send, recv) = state.into_ciphers(false);
+				debug!(target: "libp2p-noise", "noise handshake success as responder");
+				Ok(NoiseOutput::new(socket, send, recv, remote_key))
+			});
+		Box::new(future)
+	}
+}
+
+// Decodes the payload of the remote and checks that it signs the static key of the remote.
+// Returns the identity key of the remote.
+fn verify_payload(payload: &[u8], remote_static: &[u8; KEY_LEN]) -> Result<Vec<u8>, NoiseError> {
+	let payload = Payload::decode(payload)?;
+	if let Err(err) = payload.verify(remote_static) {
+		debug!(target: "libp2p-noise", "failed to verify the payload of the remote: {:?}", err);
+		return Err(err);
+	}
+	Ok(payload.identity_key)
+}
+
+// Writes a message prefixed with its length.
+fn send_frame<S>(socket: S, msg: Vec<u8>) -> Box<Future<Item = S, Error = NoiseError> + Send>
+	where S: AsyncWrite + Send + 'static
+{
+	if msg.len() > MAX_MESSAGE_LEN {
+		return Box::new(future::err(NoiseError::MessageTooLarge));
+	}
+
+	let mut frame = Vec::with_capacity(2 + msg.len());
+	frame.push((msg.len() >> 8) as u8);
+	frame.push(msg.len() as u8);
+	frame.extend_from_slice(&msg);
+
+	let future = write_all(socket, frame)
+		.and_then(|(socket, _)| flush(socket))
+		.map_err(NoiseError::from);
+	Box::new(future)
+}
+
+// Reads a message prefixed with its length.
+fn recv_frame<S>(socket: S) -> Box<Future<Item = (S, Vec<u8>), Error = NoiseError> + Send>
+	where S: AsyncRead + Send + 'static
+{
+	let future = read_exact(socket, [0; 2])
+		.and_then(|(socket, len)| {
+			let len = ((len[0] as usize) << 8) | (len[1] as usize);
+			read_exact(socket, vec![0; len])
+		})
+		.map_err(NoiseError::from);
+	Box::new(future)
+}
+
+// State of the handshake of one of the two nodes.
+struct HandshakeState {
+	symmetric: SymmetricState,
+	// Local static key.
+	s: Keypair,
+	// Local ephemeral key.
+	e: Keypair,
+	// Ephemeral key of the remote.
+	re: [u8; KEY_LEN],
+	// Static key of the remote.
+	rs: [u8; KEY_LEN],
+}
+
+impl HandshakeState {
+	fn new(s: Keypair) -> Result<HandshakeState, NoiseError> {
+		let mut symmetric = SymmetricState::new(PROTOCOL_NAME);
+		// The prologue is empty.
+		symmetric.mix_hash(&[]);
+
+		Ok(HandshakeState {
+			symmetric: symmetric,
+			s: s,
+			e: Keypair::generate()?,
+			re: [0; KEY_LEN],
+			rs: [0; KEY_LEN],
+		})
+	}
+
+	// -> e
+	fn write_message_1(&mut self) -> Result<Vec<u8>, NoiseError> {
+		let mut msg = self.write_e();
+		msg.extend(self.symmetric.encrypt_and_hash(&[])?);
+		Ok(msg)
+	}
+
+	fn read_message_1(&mut self, msg: &[u8]) -> Result<(), NoiseError> {
+		let rest = self.read_e(msg)?;
+		self.symmetric.decrypt_and_hash(rest)?;
+		Ok(())
+	}
+
+	// <- e, ee, s, es
+	fn write_message_2(&mut self, payload: &[u8]) -> Result<Vec<u8>, NoiseError> {
+		let mut msg = self.write_e();
+		let ee = self.e.dh(&self.re);
+		self.symmetric.mix_key(&ee);
+		msg.extend(self.symmetric.encrypt_and_hash(self.s.public())?);
+		let es = self.s.dh(&self.re);
+		self.symmetric.mix_key(&es);
+		msg.extend(self.symmetric.encrypt_and_hash(payload)?);
+		Ok(msg)
+	}
+
+	fn read_message_2(&mut self, msg: &[u8]) -> Result<Vec<u8>, NoiseError> {
+		let rest = self.read_e(msg)?;
+		let ee = self.e.dh(&self.re);
+		self.symmetric.mix_key(&ee);
+		let rest = self.read_s(rest)?;
+		let es = self.e.dh(&self.rs);
+		self.symmetric.mix_key(&es);
+		self.symmetric.decrypt_and_hash(rest)
+	}
+
+	// -> s, se
+	fn write_message_3(&mut self, payload: &[u8]) -> Result<Vec<u8>, NoiseError> {
+		let mut msg = self.symmetric.encrypt_and_hash(self.s.public())?;
+		let se = self.s.dh(&self.re);
+		self.symmetric.mix_key(&se);
+		msg.extend(self.symmetric.encrypt_and_hash(payload)?);
+		Ok(msg)
+	}
+
+	fn read_message_3(&mut self, msg: &[u8]) -> Result<Vec<u8>, NoiseError> {
+		let rest = self.read_s(msg)?;
+		let se = self.e.dh(&self.rs);
+		self.symmetric.mix_key(&se);
+		self.symmetric.decrypt_and_hash(rest)
+	}
+
+	// Returns the local ephemeral key and mixes it into the hash.
+	fn write_e(&mut self) -> Vec<u8> {
+		self.symmetric.mix_hash(self.e.public());
+		self.e.public().to_vec()
+	}
+
+	// Reads the ephemeral key of the remote at the start of `msg`, and returns the rest.
+	fn read_e<'a>(&mut self, msg: &'a [u8]) -> Result<&'a [u8], NoiseError> {
+		if msg.len() < KEY_LEN {
+			return Err(NoiseError::MessageTooShort);
+		}
+
+		self.re.copy_from_slice(&msg[.. KEY_LEN]);
+		self.symmetric.mix_hash(&msg[.. KEY_LEN]);
+		Ok(&msg[KEY_LEN ..])
+	}
+
+	// Decrypts the static key of the remote at the start of `msg`, and returns the rest.
+	fn read_s<'a>(&mut self, msg: &'a [u8]) -> Result<&'a [u8], NoiseError> {
+		let len = KEY_LEN + self.symmetric.overhead();
+		if msg.len() < len {
+			return Err(NoiseError::MessageTooShort);
+		}
+
+		let rs = self.symmetric.decrypt_and_hash(&msg[.. len])?;
+		self.rs.copy_from_slice(&rs);
+		Ok(&msg[len ..])
+	}
+
+	// Returns the cipher for the messages we send, and the one for the messages we receive.
+	fn into_ciphers(self, initiator: bool) -> (CipherState, CipherState) {
+		let (initiator_cipher, responder_cipher) = self.symmetric.split();
+		if initiator {
+			(initiator_cipher, responder_cipher)
+		} else {
+			(responder_cipher, initiator_cipher)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::HandshakeState;
+	use symmetric::Keypair;
+
+	#[test]
+	fn xx_pattern() {
+		let initiator_static = Keypair::generate().unwrap();
+		let responder_static = Keypair::generate().unwrap();
+		let mut initiator = HandshakeState::new(initiator_static.clone()).unwrap();
+		let mut responder = HandshakeState::new(responder_static.clone()).unwrap();
+
+		let msg = initiator.write_message_1().unwrap();
+		assert_eq!(msg.len(), 32);
+		responder.read_message_1(&msg).unwrap();
+
+		let msg = responder.write_message_2(b"responder").unwrap();
+		assert_eq!(initiator.read_message_2(&msg).unwrap(), b"responder");
+		assert_eq!(&initiator.rs, responder_static.public());
+
+		let msg = initiator.write_message_3(b"initiator").unwrap();
+		assert_eq!(responder.read_message_3(&msg).unwrap(), b"initiator");
+		assert_eq!(&responder.rs, initiator_static.public());
+
+		let (mut send, _) = initiator.into_ciphers(true);
+		let (_, mut recv) = responder.into_ciphers(false);
+		let ciphertext = send.encrypt(&[], b"hello").unwrap();
+		assert_eq!(recv.decrypt(&[], &ciphertext).unwrap(), b"hello");
+	}
+}
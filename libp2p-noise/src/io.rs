@@ -0,0 +1,217 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Defines the `NoiseOutput` struct, the encrypted connection produced by the handshake.
+//!
+//! The data is sent in frames of at most `MAX_MESSAGE_LEN` bytes of ciphertext, each prefixed
+//! with its length as a 16 bits big-endian integer.
+
+use error::NoiseError;
+use futures::{Async, Poll};
+use handshake::MAX_MESSAGE_LEN;
+use std::cmp;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use symmetric::{CipherState, TAG_LEN};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+// Maximum number of bytes of plaintext in a frame.
+const MAX_PLAINTEXT_LEN: usize = MAX_MESSAGE_LEN - TAG_LEN;
+
+/// Encrypted and authenticated connection with the remote.
+///
+/// Implements `AsyncRead` and `AsyncWrite`. The data that is written is buffered until the
+/// buffer contains a full frame or until `flush()` is called.
+pub struct NoiseOutput<S> {
+	socket: S,
+	send: CipherState,
+	recv: CipherState,
+	remote_public_key: Vec<u8>,
+
+	// Length prefix of the frame being received, and number of bytes of it received so far.
+	read_len: [u8; 2],
+	read_len_pos: usize,
+	// Ciphertext of the frame being received, and number of bytes of it received so far.
+	read_frame: Vec<u8>,
+	read_frame_pos: usize,
+	// Decrypted data that hasn't been read yet, starting at `read_plain_pos`.
+	read_plain: Vec<u8>,
+	read_plain_pos: usize,
+
+	// Data written by the user that hasn't been encrypted yet.
+	write_plain: Vec<u8>,
+	// Encrypted frame being sent, and number of bytes of it sent so far.
+	write_frame: Vec<u8>,
+	write_frame_pos: usize,
+}
+
+impl<S> NoiseOutput<S> {
+	/// Builds a `NoiseOutput` from the ciphers derived by the handshake.
+	pub(crate) fn new(socket: S, send: CipherState, recv: CipherState, remote_public_key: Vec<u8>)
+					  -> NoiseOutput<S>
+	{
+		NoiseOutput {
+			socket: socket,
+			send: send,
+			recv: recv,
+			remote_public_key: remote_public_key,
+			read_len: [0; 2],
+			read_len_pos: 0,
+			read_frame: Vec::new(),
+			read_frame_pos: 0,
+			read_plain: Vec::new(),
+			read_plain_pos: 0,
+			write_plain: Vec::new(),
+			write_frame: Vec::new(),
+			write_frame_pos: 0,
+		}
+	}
+
+	/// Returns the `PublicKey` protobuf message of the identity key of the remote, whose
+	/// signature of the Noise static key has been verified during the handshake.
+	#[inline]
+	pub fn remote_public_key(&self) -> &[u8] {
+		&self.remote_public_key
+	}
+}
+
+impl<S> NoiseOutput<S>
+	where S: Write
+{
+	// Sends the rest of the frame being sent, if any.
+	fn flush_frame(&mut self) -> Result<(), IoError> {
+		while self.write_frame_pos < self.write_frame.len() {
+			let written = self.socket.write(&self.write_frame[self.write_frame_pos ..])?;
+			if written == 0 {
+				return Err(IoError::new(IoErrorKind::WriteZero, "failed to write frame"));
+			}
+			self.write_frame_pos += written;
+		}
+
+		self.write_frame.clear();
+		self.write_frame_pos = 0;
+		Ok(())
+	}
+
+	// Encrypts the buffered data into a new frame. Must only be called when no frame is being
+	// sent.
+	fn encrypt_frame(&mut self) -> Result<(), IoError> {
+		debug_assert!(self.write_frame.is_empty());
+		let ciphertext = self.send.encrypt(&[], &self.write_plain).map_err(to_io_error)?;
+		self.write_frame.push((ciphertext.len() >> 8) as u8);
+		self.write_frame.push(ciphertext.len() as u8);
+		self.write_frame.extend_from_slice(&ciphertext);
+		self.write_plain.clear();
+		Ok(())
+	}
+}
+
+impl<S> Read for NoiseOutput<S>
+	where S: Read
+{
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+		loop {
+			if self.read_plain_pos < self.read_plain.len() {
+				let len = cmp::min(buf.len(), self.read_plain.len() - self.read_plain_pos);
+				buf[.. len].copy_from_slice(&self.read_plain[self.read_plain_pos ..][.. len]);
+				self.read_plain_pos += len;
+				return Ok(len);
+			}
+
+			if self.read_len_pos < 2 {
+				let read = self.socket.read(&mut self.read_len[self.read_len_pos ..])?;
+				if read == 0 {
+					if self.read_len_pos == 0 {
+						return Ok(0);
+					}
+					return Err(IoErrorKind::UnexpectedEof.into());
+				}
+				self.read_len_pos += read;
+				if self.read_len_pos < 2 {
+					continue;
+				}
+
+				let len = ((self.read_len[0] as usize) << 8) | (self.read_len[1] as usize);
+				self.read_frame = vec![0; len];
+				self.read_frame_pos = 0;
+			}
+
+			if self.read_frame_pos < self.read_frame.len() {
+				let read = self.socket.read(&mut self.read_frame[self.read_frame_pos ..])?;
+				if read == 0 {
+					return Err(IoErrorKind::UnexpectedEof.into());
+				}
+				self.read_frame_pos += read;
+				continue;
+			}
+
+			self.read_plain = self.recv.decrypt(&[], &self.read_frame).map_err(to_io_error)?;
+			self.read_plain_pos = 0;
+			self.read_len_pos = 0;
+		}
+	}
+}
+
+impl<S> AsyncRead for NoiseOutput<S>
+	where S: AsyncRead
+{
+}
+
+impl<S> Write for NoiseOutput<S>
+	where S: Write
+{
+	fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+		self.flush_frame()?;
+		if self.write_plain.len() >= MAX_PLAINTEXT_LEN {
+			self.encrypt_frame()?;
+			self.flush_frame()?;
+		}
+
+		let len = cmp::min(buf.len(), MAX_PLAINTEXT_LEN - self.write_plain.len());
+		self.write_plain.extend_from_slice(&buf[.. len]);
+		Ok(len)
+	}
+
+	fn flush(&mut self) -> Result<(), IoError> {
+		self.flush_frame()?;
+		if !self.write_plain.is_empty() {
+			self.encrypt_frame()?;
+			self.flush_frame()?;
+		}
+		self.socket.flush()
+	}
+}
+
+impl<S> AsyncWrite for NoiseOutput<S>
+	where S: AsyncWrite
+{
+	fn shutdown(&mut self) -> Poll<(), IoError> {
+		match self.flush() {
+			Ok(()) => (),
+			Err(ref err) if err.kind() == IoErrorKind::WouldBlock => return Ok(Async::NotReady),
+			Err(err) => return Err(err),
+		}
+		self.socket.shutdown()
+	}
+}
+
+#[inline]
+fn to_io_error(err: NoiseError) -> IoError {
+	IoError::new(IoErrorKind::InvalidData, err)
+}
@@ -0,0 +1,294 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+// TODO: use this once stable ; for now we just copy-paste the content of the README.md
+//#![doc(include = "../README.md")]
+
+//! Implementation of the Noise protocol, as a modern alternative to secio.
+//!
+//! Noise is a framework for building cryptographic handshakes. This crate implements the `XX`
+//! handshake pattern with Curve25519, ChaCha20-Poly1305 and SHA-256, under the protocol name
+//! `/noise`. During the handshake, each node generates an ephemeral key and sends the static
+//! key of its `NoiseConfig`, along with a payload that contains its libp2p identity key and the
+//! signature of the static key by the identity key. The payload authenticates the remote: a
+//! successful handshake means that the remote owns the private key of its identity key.
+//!
+//! # Connection upgrade
+//!
+//! The `NoiseConfig` struct implements the `ConnectionUpgrade` trait. You can apply it over a
+//! `Transport` by using the `with_upgrade` method. The output of the upgrade is a `NoiseOutput`,
+//! which implements `AsyncRead` and `AsyncWrite`, and whose `remote_public_key()` method
+//! returns the identity key of the remote.
+//!
+//! ```no_run
+//! extern crate libp2p_keys;
+//! extern crate libp2p_noise;
+//! extern crate libp2p_swarm;
+//! extern crate libp2p_tcp_transport;
+//! extern crate tokio_core;
+//!
+//! # fn main() {
+//! use libp2p_keys::Keypair;
+//! use libp2p_noise::NoiseConfig;
+//! use libp2p_swarm::Transport;
+//! use libp2p_tcp_transport::TcpConfig;
+//! use std::sync::Arc;
+//! use tokio_core::reactor::Core;
+//!
+//! let core = Core::new().unwrap();
+//! let keypair = Keypair::generate_ed25519().unwrap();
+//! let transport = TcpConfig::new(core.handle())
+//! 	.with_upgrade(NoiseConfig::new(Arc::new(keypair)).unwrap());
+//! # }
+//! ```
+//!
+//! The identity key is accessed through the `Signer` trait of `libp2p-keys`, which `Keypair`
+//! implements, so that it can be kept in a hardware security module. The signature of the remote
+//! is checked with the `PublicKey` of `libp2p-keys`, hence the remote can use any type of identity
+//! key that this crate supports: Ed25519, Secp256k1 or RSA.
+//!
+//! > **Note**: The static key is generated when the `NoiseConfig` is created, and is shared by
+//! >           its clones. Creating a new `NoiseConfig` for each connection works but is wasteful.
+
+extern crate bytes;
+extern crate crypto;
+extern crate futures;
+extern crate libp2p_keys;
+extern crate libp2p_swarm;
+#[macro_use]
+extern crate log;
+extern crate ring;
+extern crate tokio_io;
+
+pub use self::error::NoiseError;
+pub use self::io::NoiseOutput;
+pub use libp2p_keys::Signer;
+
+use bytes::Bytes;
+use futures::Future;
+use libp2p_swarm::{ConnectionUpgrade, Endpoint, Multiaddr};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::iter;
+use std::sync::Arc;
+use symmetric::Keypair;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+mod error;
+mod handshake;
+mod io;
+mod payload;
+mod symmetric;
+
+/// Implementation of the `ConnectionUpgrade` trait of `libp2p_swarm`. Automatically applies
+/// Noise on any connection.
+#[derive(Clone)]
+pub struct NoiseConfig {
+	static_keypair: Keypair,
+	identity_key: Vec<u8>,
+	signer: Arc<Signer>,
+}
+
+impl NoiseConfig {
+	/// Builds a `NoiseConfig` from the signer of the identity key of the local node.
+	///
+	/// A new static key is generated.
+	pub fn new(signer: Arc<Signer>) -> Result<NoiseConfig, NoiseError> {
+		Ok(NoiseConfig {
+			static_keypair: Keypair::generate()?,
+			identity_key: signer.public().into_protobuf_encoding(),
+			signer: signer,
+		})
+	}
+}
+
+impl<S> ConnectionUpgrade<S> for NoiseConfig
+	where S: AsyncRead + AsyncWrite + Send + 'static
+{
+	type Output = NoiseOutput<S>;
+	type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
+	type NamesIter = iter::Once<(Bytes, ())>;
+	type UpgradeIdentifier = ();
+
+	#[inline]
+	fn protocol_names(&self) -> Self::NamesIter {
+		iter::once(("/noise".into(), ()))
+	}
+
+	#[inline]
+	fn upgrade(self, incoming: S, _: (), endpoint: Endpoint, remote_addr: &Multiaddr)
+			   -> Self::Future
+	{
+		info!(target: "libp2p-noise", "starting noise upgrade with {:?}", remote_addr);
+
+		let future = handshake::handshake(
+			incoming,
+			self.static_keypair,
+			self.identity_key,
+			self.signer,
+			endpoint == Endpoint::Dialer,
+		);
+		Box::new(future.map_err(map_err))
+	}
+}
+
+#[inline]
+fn map_err(err: NoiseError) -> IoError {
+	debug!(target: "libp2p-noise", "error during noise handshake {:?}", err);
+	IoError::new(IoErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+	extern crate tokio_core;
+	use self::tokio_core::net::{TcpListener, TcpStream};
+	use self::tokio_core::reactor::Core;
+	use futures::{Future, Stream};
+	use libp2p_swarm::{ConnectionUpgrade, Endpoint, Multiaddr};
+	use libp2p_keys::{Keypair, PublicKey, SigningError};
+	use std::sync::Arc;
+	use tokio_io::io::{flush, read_exact, write_all};
+	use {NoiseConfig, Signer};
+
+	// Generates an Ed25519 identity, and returns its `PublicKey` protobuf message and its signer.
+	fn identity() -> (Vec<u8>, Arc<Keypair>) {
+		signer(Keypair::generate_ed25519().unwrap())
+	}
+
+	// Returns the `PublicKey` protobuf message of a key pair, and the key pair as a signer.
+	fn signer(keypair: Keypair) -> (Vec<u8>, Arc<Keypair>) {
+		(keypair.public().into_protobuf_encoding(), Arc::new(keypair))
+	}
+
+	// Claims the identity key `public`, but signs with `keypair`.
+	struct Impostor {
+		public: PublicKey,
+		keypair: Keypair,
+	}
+
+	impl Signer for Impostor {
+		fn public(&self) -> PublicKey {
+			self.public.clone()
+		}
+
+		fn sign(&self, data: &[u8]) -> Box<Future<Item = Vec<u8>, Error = SigningError> + Send> {
+			Signer::sign(&self.keypair, data)
+		}
+	}
+
+	// Runs the handshake between the two configs, and sends some data in both directions.
+	fn communicate(server_config: NoiseConfig, client_config: NoiseConfig)
+				   -> Result<(Vec<u8>, Vec<u8>), ::std::io::Error>
+	{
+		let mut core = Core::new().unwrap();
+		let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+		let listener_addr = listener.local_addr().unwrap();
+		let addr: Multiaddr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+
+		let server_addr = addr.clone();
+		let server = listener.incoming()
+			.into_future()
+			.map_err(|(err, _)| err)
+			.and_then(move |(connec, _)| {
+				server_config.upgrade(connec.unwrap().0, (), Endpoint::Listener, &server_addr)
+			})
+			.and_then(|conn| {
+				let remote_key = conn.remote_public_key().to_vec();
+				read_exact(conn, [0; 5])
+					.and_then(|(conn, buf)| {
+						assert_eq!(&buf, b"hello");
+						write_all(conn, b"world")
+					})
+					.and_then(|(conn, _)| flush(conn))
+					.map(move |_| remote_key)
+			});
+
+		let client = TcpStream::connect(&listener_addr, &core.handle())
+			.and_then(move |stream| client_config.upgrade(stream, (), Endpoint::Dialer, &addr))
+			.and_then(|conn| {
+				let remote_key = conn.remote_public_key().to_vec();
+				write_all(conn, b"hello")
+					.and_then(|(conn, _)| flush(conn))
+					.and_then(|conn| read_exact(conn, [0; 5]))
+					.map(move |(_, buf)| {
+						assert_eq!(&buf, b"world");
+						remote_key
+					})
+			});
+
+		core.run(server.join(client))
+	}
+
+	#[test]
+	fn handshake_and_communicate() {
+		let (server_key, server_signer) = identity();
+		let (client_key, client_signer) = identity();
+		let server_config = NoiseConfig::new(server_signer).unwrap();
+		let client_config = NoiseConfig::new(client_signer).unwrap();
+
+		let (server_remote_key, client_remote_key) =
+			communicate(server_config, client_config).unwrap();
+		assert_eq!(server_remote_key, client_key);
+		assert_eq!(client_remote_key, server_key);
+	}
+
+	#[test]
+	fn rsa_handshake() {
+		let (server_key, server_signer) = signer(
+			Keypair::rsa_from_pkcs8(include_bytes!("../tests/test-private-key.pk8")).unwrap()
+		);
+		let (client_key, client_signer) = signer(
+			Keypair::rsa_from_pkcs8(include_bytes!("../tests/test-private-key-2.pk8")).unwrap()
+		);
+		let server_config = NoiseConfig::new(server_signer).unwrap();
+		let client_config = NoiseConfig::new(client_signer).unwrap();
+
+		let (server_remote_key, client_remote_key) =
+			communicate(server_config, client_config).unwrap();
+		assert_eq!(server_remote_key, client_key);
+		assert_eq!(client_remote_key, server_key);
+	}
+
+	#[test]
+	fn secp256k1_handshake() {
+		let (server_key, server_signer) = signer(Keypair::generate_secp256k1().unwrap());
+		let (client_key, client_signer) = signer(Keypair::generate_secp256k1().unwrap());
+		let server_config = NoiseConfig::new(server_signer).unwrap();
+		let client_config = NoiseConfig::new(client_signer).unwrap();
+
+		let (server_remote_key, client_remote_key) =
+			communicate(server_config, client_config).unwrap();
+		assert_eq!(server_remote_key, client_key);
+		assert_eq!(client_remote_key, server_key);
+	}
+
+	#[test]
+	fn wrong_signature_fails() {
+		let (_, server_signer) = identity();
+		let server_config = NoiseConfig::new(server_signer).unwrap();
+		// The client claims an identity key, but signs with another one.
+		let impostor = Impostor {
+			public: Keypair::generate_ed25519().unwrap().public(),
+			keypair: Keypair::generate_ed25519().unwrap(),
+		};
+		let client_config = NoiseConfig::new(Arc::new(impostor)).unwrap();
+
+		assert!(communicate(server_config, client_config).is_err());
+	}
+}
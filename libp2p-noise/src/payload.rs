@@ -0,0 +1,173 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Encoding and verification of the payload that the handshake messages carry.
+//!
+//! The payload is a `NoiseHandshakePayload` protobuf message that contains the identity key of the
+//! node, and the signature of its Noise static key by this identity key:
+//!
+//! ```text
+//! message NoiseHandshakePayload {
+//!     bytes identity_key = 1;
+//!     bytes identity_sig = 2;
+//! }
+//! ```
+
+use error::NoiseError;
+use libp2p_keys::{DecodingError, PublicKey};
+
+// Prefix of the data that the identity key signs.
+const STATIC_KEY_DOMAIN: &'static [u8] = b"noise-libp2p-static-key:";
+
+/// Payload of the second and third messages of the handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payload {
+	/// `PublicKey` protobuf message of the identity key.
+	pub identity_key: Vec<u8>,
+	/// Signature of the static key.
+	pub identity_sig: Vec<u8>,
+}
+
+impl Payload {
+	/// Encodes the payload as a protobuf message.
+	pub fn encode(&self) -> Vec<u8> {
+		let mut out = Vec::with_capacity(self.identity_key.len() + self.identity_sig.len() + 8);
+		encode_bytes_field(&mut out, 1, &self.identity_key);
+		encode_bytes_field(&mut out, 2, &self.identity_sig);
+		out
+	}
+
+	/// Decodes a payload from a protobuf message. Unknown fields are ignored.
+	pub fn decode(mut data: &[u8]) -> Result<Payload, NoiseError> {
+		let mut identity_key = None;
+		let mut identity_sig = None;
+
+		while !data.is_empty() {
+			let key = read_varint(&mut data)?;
+			match (key >> 3, key & 0x7) {
+				(field, 2) => {
+					let len = read_varint(&mut data)? as usize;
+					if data.len() < len {
+						return Err(NoiseError::PayloadParsingFailure);
+					}
+					let (value, rest) = data.split_at(len);
+					data = rest;
+					match field {
+						1 => identity_key = Some(value.to_vec()),
+						2 => identity_sig = Some(value.to_vec()),
+						_ => (),
+					}
+				},
+				(_, 0) => { read_varint(&mut data)?; },
+				(_, 1) if data.len() >= 8 => data = &data[8 ..],
+				(_, 5) if data.len() >= 4 => data = &data[4 ..],
+				_ => return Err(NoiseError::PayloadParsingFailure),
+			}
+		}
+
+		match (identity_key, identity_sig) {
+			(Some(identity_key), Some(identity_sig)) => Ok(Payload {
+				identity_key: identity_key,
+				identity_sig: identity_sig,
+			}),
+			_ => Err(NoiseError::PayloadParsingFailure),
+		}
+	}
+
+	/// Checks that the signature is the signature of `static_key` by the identity key.
+	pub fn verify(&self, static_key: &[u8]) -> Result<(), NoiseError> {
+		let identity_key = PublicKey::from_protobuf_encoding(&self.identity_key)
+			.map_err(|err| match err {
+				DecodingError::UnsupportedKeyType => NoiseError::UnsupportedKeyType,
+				_ => NoiseError::PayloadParsingFailure,
+			})?;
+
+		if identity_key.verify(&signed_data(static_key), &self.identity_sig) {
+			Ok(())
+		} else {
+			Err(NoiseError::SignatureVerificationFailed)
+		}
+	}
+}
+
+/// Returns the data that the identity key must sign for the given static key.
+pub fn signed_data(static_key: &[u8]) -> Vec<u8> {
+	let mut data = Vec::with_capacity(STATIC_KEY_DOMAIN.len() + static_key.len());
+	data.extend_from_slice(STATIC_KEY_DOMAIN);
+	data.extend_from_slice(static_key);
+	data
+}
+
+// Appends a length-delimited field to `out`.
+fn encode_bytes_field(out: &mut Vec<u8>, field: u64, value: &[u8]) {
+	write_varint(out, (field << 3) | 2);
+	write_varint(out, value.len() as u64);
+	out.extend_from_slice(value);
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+	while value >= 0x80 {
+		out.push((value as u8) | 0x80);
+		value >>= 7;
+	}
+	out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64, NoiseError> {
+	let mut value = 0u64;
+	for shift in 0 .. 10 {
+		let remaining: &[u8] = *data;
+		let byte = match remaining.first() {
+			Some(&byte) => byte,
+			None => return Err(NoiseError::PayloadParsingFailure),
+		};
+		*data = &remaining[1 ..];
+		value |= u64::from(byte & 0x7f) << (7 * shift);
+		if byte & 0x80 == 0 {
+			return Ok(value);
+		}
+	}
+
+	Err(NoiseError::PayloadParsingFailure)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Payload;
+
+	#[test]
+	fn encode_decode() {
+		let payload = Payload {
+			identity_key: vec![0x08, 0x01, 0x12, 0x01, 0x2a],
+			identity_sig: vec![7; 200],
+		};
+		let encoded = payload.encode();
+		assert_eq!(&encoded[.. 2], &[0x0a, 5]);
+		assert_eq!(Payload::decode(&encoded).unwrap(), payload);
+
+		// Unknown fields are skipped.
+		let mut encoded = encoded;
+		encoded.extend_from_slice(&[0x18, 0x96, 0x01, 0x22, 0x01, 0xff]);
+		assert_eq!(Payload::decode(&encoded).unwrap(), payload);
+
+		assert!(Payload::decode(&encoded[.. 10]).is_err());
+		assert!(Payload::decode(&[0x0a, 0x00]).is_err());
+	}
+}
@@ -0,0 +1,290 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Implementation of the `CipherState` and `SymmetricState` objects of the Noise specification,
+//! for the `25519_ChaChaPoly_SHA256` suite.
+//!
+//! See <https://noiseprotocol.org/noise.html#the-cipherstate-object>.
+
+use crypto::curve25519::{curve25519, curve25519_base};
+use error::NoiseError;
+use ring::aead::{self, OpeningKey, SealingKey, CHACHA20_POLY1305};
+use ring::digest::{self, SHA256};
+use ring::hmac::{self, SigningContext, SigningKey};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Length of the keys and of the hashes.
+pub const KEY_LEN: usize = 32;
+/// Length of the authentication tag appended to each encrypted message.
+pub const TAG_LEN: usize = 16;
+
+/// Curve25519 key pair used for the Diffie-Hellman exchanges.
+#[derive(Clone)]
+pub struct Keypair {
+	secret: [u8; KEY_LEN],
+	public: [u8; KEY_LEN],
+}
+
+impl Keypair {
+	/// Generates a new random key pair.
+	pub fn generate() -> Result<Keypair, NoiseError> {
+		let mut secret = [0; KEY_LEN];
+		SystemRandom::new()
+			.fill(&mut secret)
+			.map_err(|_| NoiseError::KeyGenerationFailed)?;
+		let public = curve25519_base(&secret);
+		Ok(Keypair {
+			secret: secret,
+			public: public,
+		})
+	}
+
+	/// Returns the public key.
+	#[inline]
+	pub fn public(&self) -> &[u8; KEY_LEN] {
+		&self.public
+	}
+
+	/// Performs a Diffie-Hellman exchange with the public key of the remote.
+	#[inline]
+	pub fn dh(&self, remote_public: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+		curve25519(&self.secret, remote_public)
+	}
+}
+
+/// Encrypts or decrypts messages in one direction, with a counter as nonce.
+pub struct CipherState {
+	// `None` until a key has been derived, in which case the messages are sent in plain text.
+	keys: Option<(SealingKey, OpeningKey)>,
+	nonce: u64,
+}
+
+impl CipherState {
+	/// Builds a `CipherState` without any key.
+	#[inline]
+	pub fn empty() -> CipherState {
+		CipherState {
+			keys: None,
+			nonce: 0,
+		}
+	}
+
+	/// Builds a `CipherState` with the given key.
+	pub fn new(key: &[u8; KEY_LEN]) -> CipherState {
+		let sealing = SealingKey::new(&CHACHA20_POLY1305, key)
+			.expect("the key has the length expected by the algorithm");
+		let opening = OpeningKey::new(&CHACHA20_POLY1305, key)
+			.expect("the key has the length expected by the algorithm");
+		CipherState {
+			keys: Some((sealing, opening)),
+			nonce: 0,
+		}
+	}
+
+	/// Returns true if a key has been derived.
+	#[inline]
+	pub fn has_key(&self) -> bool {
+		self.keys.is_some()
+	}
+
+	/// Encrypts `plaintext` and authenticates it along with `ad`.
+	pub fn encrypt(&mut self, ad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, NoiseError> {
+		let nonce = self.next_nonce()?;
+		match self.keys {
+			Some((ref key, _)) => {
+				let mut in_out = Vec::with_capacity(plaintext.len() + TAG_LEN);
+				in_out.extend_from_slice(plaintext);
+				in_out.extend_from_slice(&[0; TAG_LEN]);
+				let len = aead::seal_in_place(key, &nonce, ad, &mut in_out, TAG_LEN)
+					.expect("the buffer has room for the tag");
+				in_out.truncate(len);
+				Ok(in_out)
+			},
+			None => Ok(plaintext.to_vec()),
+		}
+	}
+
+	/// Decrypts `ciphertext` and checks its authentication tag, which also covers `ad`.
+	pub fn decrypt(&mut self, ad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, NoiseError> {
+		let nonce = self.next_nonce()?;
+		match self.keys {
+			Some((_, ref key)) => {
+				let mut in_out = ciphertext.to_vec();
+				let len = aead::open_in_place(key, &nonce, ad, 0, &mut in_out)
+					.map_err(|_| NoiseError::DecryptionFailed)?
+					.len();
+				in_out.truncate(len);
+				Ok(in_out)
+			},
+			None => Ok(ciphertext.to_vec()),
+		}
+	}
+
+	// Returns the nonce to use for the next message, which is the little-endian encoding of the
+	// counter preceded with four zero bytes.
+	fn next_nonce(&mut self) -> Result<[u8; 12], NoiseError> {
+		if self.keys.is_none() {
+			return Ok([0; 12]);
+		}
+
+		// The maximum value is reserved by the specification.
+		if self.nonce == u64::max_value() {
+			return Err(NoiseError::NonceExhausted);
+		}
+
+		let mut nonce = [0; 12];
+		for (n, byte) in nonce[4 ..].iter_mut().enumerate() {
+			*byte = (self.nonce >> (8 * n)) as u8;
+		}
+		self.nonce += 1;
+		Ok(nonce)
+	}
+}
+
+/// State of the handshake that is shared between the messages: the chaining key, the hash of
+/// everything that has been exchanged so far, and the current cipher.
+pub struct SymmetricState {
+	ck: [u8; KEY_LEN],
+	h: [u8; KEY_LEN],
+	cipher: CipherState,
+}
+
+impl SymmetricState {
+	/// Initializes the state with the name of the protocol. The name must not be longer than
+	/// `KEY_LEN`.
+	pub fn new(protocol_name: &[u8]) -> SymmetricState {
+		assert!(protocol_name.len() <= KEY_LEN);
+		let mut h = [0; KEY_LEN];
+		h[.. protocol_name.len()].copy_from_slice(protocol_name);
+
+		SymmetricState {
+			ck: h,
+			h: h,
+			cipher: CipherState::empty(),
+		}
+	}
+
+	/// Mixes `data` into the hash.
+	pub fn mix_hash(&mut self, data: &[u8]) {
+		let mut context = digest::Context::new(&SHA256);
+		context.update(&self.h);
+		context.update(data);
+		self.h.copy_from_slice(context.finish().as_ref());
+	}
+
+	/// Mixes the result of a Diffie-Hellman exchange into the chaining key, and derives a new
+	/// key for the cipher.
+	pub fn mix_key(&mut self, input_key_material: &[u8]) {
+		let (ck, key) = hkdf(&self.ck, input_key_material);
+		self.ck = ck;
+		self.cipher = CipherState::new(&key);
+	}
+
+	/// Encrypts `plaintext` if a key has been derived, and mixes the result into the hash.
+	pub fn encrypt_and_hash(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, NoiseError> {
+		let ciphertext = self.cipher.encrypt(&self.h, plaintext)?;
+		self.mix_hash(&ciphertext);
+		Ok(ciphertext)
+	}
+
+	/// Decrypts `ciphertext` if a key has been derived, and mixes it into the hash.
+	pub fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, NoiseError> {
+		let plaintext = self.cipher.decrypt(&self.h, ciphertext)?;
+		self.mix_hash(ciphertext);
+		Ok(plaintext)
+	}
+
+	/// Returns the number of bytes that `encrypt_and_hash` adds to the plaintext.
+	#[inline]
+	pub fn overhead(&self) -> usize {
+		if self.cipher.has_key() { TAG_LEN } else { 0 }
+	}
+
+	/// Derives the two ciphers used after the handshake. The first one encrypts the messages
+	/// sent by the initiator, and the second one the messages sent by the responder.
+	pub fn split(self) -> (CipherState, CipherState) {
+		let (key1, key2) = hkdf(&self.ck, &[]);
+		(CipherState::new(&key1), CipherState::new(&key2))
+	}
+}
+
+// The `HKDF` function of the specification, producing two outputs.
+fn hkdf(chaining_key: &[u8; KEY_LEN], input_key_material: &[u8]) -> ([u8; KEY_LEN], [u8; KEY_LEN]) {
+	let temp_key = hmac::sign(&SigningKey::new(&SHA256, chaining_key), input_key_material);
+	let temp_key = SigningKey::new(&SHA256, temp_key.as_ref());
+
+	let mut output1 = [0; KEY_LEN];
+	output1.copy_from_slice(hmac::sign(&temp_key, &[1]).as_ref());
+
+	let mut context = SigningContext::with_key(&temp_key);
+	context.update(&output1);
+	context.update(&[2]);
+	let mut output2 = [0; KEY_LEN];
+	output2.copy_from_slice(context.sign().as_ref());
+
+	(output1, output2)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{CipherState, Keypair, SymmetricState};
+
+	#[test]
+	fn dh_agrees() {
+		let a = Keypair::generate().unwrap();
+		let b = Keypair::generate().unwrap();
+		assert_eq!(a.dh(b.public()), b.dh(a.public()));
+	}
+
+	#[test]
+	fn encrypt_decrypt() {
+		let mut sender = CipherState::new(&[5; 32]);
+		let mut receiver = CipherState::new(&[5; 32]);
+
+		for _ in 0 .. 3 {
+			let ciphertext = sender.encrypt(b"ad", b"hello").unwrap();
+			assert_eq!(ciphertext.len(), 5 + 16);
+			assert_eq!(receiver.decrypt(b"ad", &ciphertext).unwrap(), b"hello");
+		}
+
+		// Tampering with the message or decrypting it twice fails.
+		let mut ciphertext = sender.encrypt(b"ad", b"hello").unwrap();
+		ciphertext[0] ^= 1;
+		assert!(receiver.decrypt(b"ad", &ciphertext).is_err());
+		let ciphertext = sender.encrypt(b"ad", b"hello").unwrap();
+		assert!(receiver.decrypt(b"other", &ciphertext).is_err());
+	}
+
+	#[test]
+	fn symmetric_states_agree() {
+		let mut a = SymmetricState::new(b"Noise_XX_25519_ChaChaPoly_SHA256");
+		let mut b = SymmetricState::new(b"Noise_XX_25519_ChaChaPoly_SHA256");
+
+		a.mix_key(&[1; 32]);
+		b.mix_key(&[1; 32]);
+		let ciphertext = a.encrypt_and_hash(b"payload").unwrap();
+		assert_eq!(b.decrypt_and_hash(&ciphertext).unwrap(), b"payload");
+
+		let (mut a_send, _) = a.split();
+		let (mut b_recv, _) = b.split();
+		let ciphertext = a_send.encrypt(&[], b"data").unwrap();
+		assert_eq!(b_recv.decrypt(&[], &ciphertext).unwrap(), b"data");
+	}
+}
@@ -0,0 +1,75 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Notifications about the modifications of a peer store.
+
+use PeerId;
+use futures::sync::mpsc;
+use multiaddr::Multiaddr;
+use std::sync::Mutex;
+
+/// Event that happened in a peer store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerstoreEvent {
+	/// A peer has been added to the peer store.
+	PeerAdded(PeerId),
+	/// A new address has been added to a peer.
+	AddrAdded {
+		/// The peer the address belongs to.
+		peer_id: PeerId,
+		/// The address that has been added.
+		addr: Multiaddr,
+	},
+	/// An expired address has been removed by `Peerstore::collect_garbage`.
+	AddrExpired {
+		/// The peer the address belonged to.
+		peer_id: PeerId,
+		/// The address that has expired.
+		addr: Multiaddr,
+	},
+	/// The public key of a peer has been learned or has changed.
+	PublicKeyLearned(PeerId),
+}
+
+// Dispatches events to the subscribers of a peer store.
+pub(crate) struct EventsNotifier {
+	subscribers: Mutex<Vec<mpsc::UnboundedSender<PeerstoreEvent>>>,
+}
+
+impl EventsNotifier {
+	#[inline]
+	pub fn new() -> EventsNotifier {
+		EventsNotifier { subscribers: Mutex::new(Vec::new()) }
+	}
+
+	// Returns a stream that produces all the events notified from now on.
+	pub fn subscribe(&self) -> mpsc::UnboundedReceiver<PeerstoreEvent> {
+		let (tx, rx) = mpsc::unbounded();
+		self.subscribers.lock().unwrap().push(tx);
+		rx
+	}
+
+	// Sends an event to all the subscribers. Subscribers whose stream has been dropped are
+	// removed.
+	pub fn notify(&self, event: PeerstoreEvent) {
+		let mut subscribers = self.subscribers.lock().unwrap();
+		subscribers.retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+	}
+}
@@ -0,0 +1,354 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Conversion from and to the key-value layout of the persistent peerstore of go-libp2p.
+//!
+//! go-libp2p stores its address book in a datastore (usually LevelDB or Badger) with the
+//! following layout, where `<peer>` is the base32 encoding (without padding) of the peer ID:
+//!
+//! - `/peers/addrs/<peer>` contains an `AddrBookRecord` protobuf message with the addresses of
+//!   the peer, their expiration, and the latest signed peer record of the peer.
+//! - `/peers/keys/<peer>/pub` contains the public key of the peer.
+//!
+//! The `export` and `import` functions of this module convert the content of a peer store from
+//! and to a list of such key-value pairs. Reading or writing the datastore of go-libp2p itself
+//! is left to the user. Other keys, such as the metadata or the private keys, are ignored.
+
+use {PeerAccess, PeerId, Peerstore};
+use data_encoding::BASE32_NOPAD;
+use libp2p_identity_core::{PeerRecordRef, SignatureVerifier, SignedRecordRef};
+use multiaddr::Multiaddr;
+use peerstore::multiaddr_from_bytes;
+use std::collections::HashSet;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use ttl;
+
+// Prefix of the keys of the address book.
+const ADDRS_PREFIX: &'static str = "/peers/addrs/";
+// Prefix and suffix of the keys of the public keys.
+const KEYS_PREFIX: &'static str = "/peers/keys/";
+const PUB_KEY_SUFFIX: &'static str = "/pub";
+
+/// Writes the addresses, signed peer records and public keys of all the peers of `store` as the
+/// key-value pairs that the persistent peerstore of go-libp2p would store.
+pub fn export<P>(store: P) -> Vec<(String, Vec<u8>)>
+	where P: Peerstore + Copy
+{
+	let now = SystemTime::now();
+	let mut out = Vec::new();
+
+	for peer_id in store.peers() {
+		let peer = match store.peer(&peer_id) {
+			Some(peer) => peer,
+			None => continue,
+		};
+		let encoded_id = BASE32_NOPAD.encode(peer_id.as_bytes());
+
+		// `AddrBookRecord` message.
+		let mut record = Vec::new();
+		write_bytes_field(&mut record, 1, peer_id.as_bytes());
+		for (addr, expires) in peer.addrs_with_expiration() {
+			let expiry = expires.duration_since(UNIX_EPOCH).unwrap_or(Duration::new(0, 0));
+			let ttl = expires.duration_since(now).unwrap_or(Duration::new(0, 0));
+			let ttl_nanos = ttl.as_secs()
+				.saturating_mul(1_000_000_000)
+				.saturating_add(u64::from(ttl.subsec_nanos()));
+
+			// `AddrEntry` message. The expiry is in seconds and the TTL in nanoseconds, both as
+			// signed 64 bits integers.
+			let mut entry = Vec::new();
+			write_bytes_field(&mut entry, 1, &addr.to_bytes());
+			write_varint_field(&mut entry, 2, expiry.as_secs().min(i64::max_value() as u64));
+			write_varint_field(&mut entry, 3, ttl_nanos.min(i64::max_value() as u64));
+			write_bytes_field(&mut record, 2, &entry);
+		}
+
+		if let Some(envelope) = peer.signed_peer_record() {
+			let seq = SignedRecordRef::from_protobuf(&envelope)
+				.and_then(|signed| PeerRecordRef::from_protobuf(signed.payload_unchecked()))
+				.map(|record| record.seq());
+			if let Ok(seq) = seq {
+				// `CertifiedRecord` message.
+				let mut certified = Vec::new();
+				write_varint_field(&mut certified, 1, seq);
+				write_bytes_field(&mut certified, 2, &envelope);
+				write_bytes_field(&mut record, 3, &certified);
+			}
+		}
+
+		out.push((format!("{}{}", ADDRS_PREFIX, encoded_id), record));
+
+		if let Some(public_key) = peer.public_key() {
+			out.push((format!("{}{}{}", KEYS_PREFIX, encoded_id, PUB_KEY_SUFFIX), public_key));
+		}
+	}
+
+	out
+}
+
+/// Loads the key-value pairs of the persistent peerstore of go-libp2p into `store`. Returns the
+/// number of peers that were loaded.
+///
+/// The addresses keep their expiration. The signed peer records are checked with `verifier`,
+/// and are ignored if their signature is invalid. Public keys that don't match the peer ID are
+/// ignored as well.
+///
+/// If one of the entries is malformed, an error is returned and the peer store isn't modified.
+pub fn import<P, I, V>(store: P, entries: I, verifier: &V) -> Result<usize, IoError>
+	where P: Peerstore + Copy,
+	      I: IntoIterator<Item = (String, Vec<u8>)>,
+	      V: SignatureVerifier + ?Sized
+{
+	// We decode everything before touching the peer store, so that an invalid input doesn't
+	// result in a partial import.
+	let mut records = Vec::new();
+	let mut public_keys = Vec::new();
+	for (key, value) in entries {
+		if key.starts_with(ADDRS_PREFIX) {
+			records.push(decode_addr_book_record(&value)?);
+		} else if key.starts_with(KEYS_PREFIX) && key.ends_with(PUB_KEY_SUFFIX) {
+			let encoded_id = &key[KEYS_PREFIX.len() .. key.len() - PUB_KEY_SUFFIX.len()];
+			let peer_id = BASE32_NOPAD.decode(encoded_id.as_bytes()).ok()
+				.and_then(|bytes| PeerId::from_bytes(bytes).ok())
+				.ok_or_else(|| invalid_data("invalid peer ID in key"))?;
+			public_keys.push((peer_id, value));
+		}
+	}
+
+	let now = SystemTime::now();
+	let mut peers = HashSet::new();
+
+	for record in records {
+		let mut peer = store.peer_or_create(&record.peer_id);
+		for (addr, expiry) in record.addrs {
+			// Go uses very large TTLs for permanent addresses, which we cap.
+			let expires = UNIX_EPOCH + Duration::from_secs(expiry);
+			if let Ok(ttl) = expires.duration_since(now) {
+				peer.add_addr(addr, ttl.min(ttl::permanent()));
+			}
+		}
+		if let Some(envelope) = record.certified_record {
+			// The addresses of the record are also in the list of addresses, so the TTL is
+			// irrelevant as long as it doesn't extend their expiration.
+			let _ = peer.add_signed_peer_record(envelope, Duration::new(0, 0), verifier);
+		}
+		peers.insert(record.peer_id);
+	}
+
+	for (peer_id, public_key) in public_keys {
+		let _ = store.peer_or_create(&peer_id).set_public_key(public_key);
+		peers.insert(peer_id);
+	}
+
+	Ok(peers.len())
+}
+
+// Decoded `AddrBookRecord` message.
+struct AddrBookRecord {
+	peer_id: PeerId,
+	// Addresses and their expiration in seconds since the UNIX epoch.
+	addrs: Vec<(Multiaddr, u64)>,
+	// `Envelope` message of the signed peer record, if any.
+	certified_record: Option<Vec<u8>>,
+}
+
+fn decode_addr_book_record(data: &[u8]) -> Result<AddrBookRecord, IoError> {
+	let mut peer_id = None;
+	let mut addrs = Vec::new();
+	let mut certified_record = None;
+
+	for (field, value) in decode_fields(data)? {
+		match (field, value) {
+			(1, Field::Bytes(id)) => {
+				let id = PeerId::from_bytes(id.to_owned())
+					.map_err(|_| invalid_data("invalid peer ID"))?;
+				peer_id = Some(id);
+			},
+			(2, Field::Bytes(entry)) => {
+				let mut addr = None;
+				let mut expiry = None;
+				for (field, value) in decode_fields(entry)? {
+					match (field, value) {
+						(1, Field::Bytes(bytes)) => addr = multiaddr_from_bytes(bytes),
+						(2, Field::Varint(value)) => expiry = Some(value as i64),
+						_ => (),
+					}
+				}
+				// Addresses with protocols that we don't know are skipped, while negative
+				// expirations are considered as already expired.
+				if let (Some(addr), Some(expiry)) = (addr, expiry) {
+					addrs.push((addr, if expiry < 0 { 0 } else { expiry as u64 }));
+				}
+			},
+			(3, Field::Bytes(certified)) => {
+				for (field, value) in decode_fields(certified)? {
+					if let (2, Field::Bytes(raw)) = (field, value) {
+						certified_record = Some(raw.to_owned());
+					}
+				}
+			},
+			_ => (),
+		}
+	}
+
+	Ok(AddrBookRecord {
+		peer_id: peer_id.ok_or_else(|| invalid_data("missing peer ID in address book record"))?,
+		addrs: addrs,
+		certified_record: certified_record,
+	})
+}
+
+// Value of a field of a protobuf message.
+enum Field<'a> {
+	Varint(u64),
+	Bytes(&'a [u8]),
+}
+
+// Decodes the fields of a protobuf message. Fixed-size fields are skipped, as none of the
+// messages that we decode use them.
+fn decode_fields(mut data: &[u8]) -> Result<Vec<(u64, Field)>, IoError> {
+	let mut out = Vec::new();
+	while !data.is_empty() {
+		let (key, len) = read_varint(data)?;
+		data = &data[len ..];
+		let (value, skip) = match key & 0x7 {
+			0 => {
+				let (value, len) = read_varint(data)?;
+				(Some(Field::Varint(value)), len)
+			},
+			1 => (None, 8),
+			2 => {
+				let (value_len, len) = read_varint(data)?;
+				if value_len > (data.len() - len) as u64 {
+					return Err(invalid_data("unexpected end of protobuf message"));
+				}
+				let value = &data[len .. len + value_len as usize];
+				(Some(Field::Bytes(value)), len + value_len as usize)
+			},
+			5 => (None, 4),
+			_ => return Err(invalid_data("invalid protobuf wire type")),
+		};
+		if skip > data.len() {
+			return Err(invalid_data("unexpected end of protobuf message"));
+		}
+		data = &data[skip ..];
+		if let Some(value) = value {
+			out.push((key >> 3, value));
+		}
+	}
+	Ok(out)
+}
+
+// Decodes a varint at the start of `data`. Returns the value and the number of bytes read.
+fn read_varint(data: &[u8]) -> Result<(u64, usize), IoError> {
+	let mut value = 0u64;
+	for (n, &byte) in data.iter().enumerate().take(10) {
+		value |= u64::from(byte & 0x7f) << (7 * n);
+		if byte & 0x80 == 0 {
+			return Ok((value, n + 1));
+		}
+	}
+	Err(invalid_data("invalid varint"))
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+	while value >= 0x80 {
+		out.push((value as u8) | 0x80);
+		value >>= 7;
+	}
+	out.push(value as u8);
+}
+
+fn write_varint_field(out: &mut Vec<u8>, field: u64, value: u64) {
+	write_varint(out, field << 3);
+	write_varint(out, value);
+}
+
+fn write_bytes_field(out: &mut Vec<u8>, field: u64, value: &[u8]) {
+	write_varint(out, (field << 3) | 2);
+	write_varint(out, value.len() as u64);
+	out.extend_from_slice(value);
+}
+
+#[inline]
+fn invalid_data(msg: &str) -> IoError {
+	IoError::new(IoErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+	use {PeerAccess, PeerId, Peerstore};
+	use go_layout;
+	use libp2p_identity_core::{PublicKeyRef, SignatureVerifier};
+	use memory_peerstore::MemoryPeerstore;
+	use multiaddr::Multiaddr;
+	use std::time::Duration;
+
+	struct RefuseAll;
+	impl SignatureVerifier for RefuseAll {
+		fn verify(&self, _: &PublicKeyRef, _: &[&[u8]], _: &[u8]) -> bool {
+			false
+		}
+	}
+
+	#[test]
+	fn export_then_import() {
+		let peer_store = MemoryPeerstore::empty();
+		let peer_id = PeerId::from_public_key(&[1, 2, 3]);
+		let addr = "/ip4/1.2.3.4/tcp/4001".parse::<Multiaddr>().unwrap();
+		{
+			let mut peer = peer_store.peer_or_create(&peer_id);
+			peer.add_addr(addr.clone(), Duration::from_secs(3600));
+			peer.set_public_key(vec![1, 2, 3]).unwrap();
+		}
+
+		let exported = go_layout::export(&peer_store);
+		assert_eq!(exported.len(), 2);
+		assert!(exported[0].0.starts_with("/peers/addrs/"));
+
+		let other_store = MemoryPeerstore::empty();
+		assert_eq!(go_layout::import(&other_store, exported, &RefuseAll).unwrap(), 1);
+		let peer = other_store.peer(&peer_id).unwrap();
+		assert_eq!(peer.addrs().collect::<Vec<_>>(), &[addr]);
+		assert_eq!(peer.public_key(), Some(vec![1, 2, 3]));
+	}
+
+	#[test]
+	fn import_go_record() {
+		let peer_id = PeerId::from_public_key(&[1, 2, 3]);
+		let addr = "/ip4/1.2.3.4/tcp/4001".parse::<Multiaddr>().unwrap();
+
+		// `AddrBookRecord` as written by go-libp2p, with an address that expires in 2100.
+		let mut record = vec![0x0a, peer_id.as_bytes().len() as u8];
+		record.extend_from_slice(peer_id.as_bytes());
+		record.extend_from_slice(&[0x12, 18, 0x0a, 8]);
+		record.extend_from_slice(&addr.to_bytes());
+		record.extend_from_slice(&[0x10, 0x80, 0xae, 0x99, 0xa4, 0x0f, 0x18, 0x01]);
+
+		let peer_store = MemoryPeerstore::empty();
+		let entries = vec![("/peers/addrs/ignored".to_owned(), record)];
+		assert_eq!(go_layout::import(&peer_store, entries, &RefuseAll).unwrap(), 1);
+		assert_eq!(peer_store.peer(&peer_id).unwrap().addrs().collect::<Vec<_>>(), &[addr]);
+
+		let entries = vec![("/peers/addrs/ignored".to_owned(), vec![0x0a, 0x05, 0x00])];
+		assert!(go_layout::import(&peer_store, entries, &RefuseAll).is_err());
+	}
+}
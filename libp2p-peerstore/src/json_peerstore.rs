@@ -21,21 +21,26 @@
 //! Implementation of the `Peerstore` trait that uses a single JSON file as backend.
 
 use super::TTL;
-use PeerId;
-use base58::{FromBase58, ToBase58};
+use {AddrSource, Ban, PeerId};
 use datastore::{Datastore, Query, JsonFileDatastore, JsonFileDatastoreEntry};
+use events::{EventsNotifier, PeerstoreEvent};
 use futures::{Future, Stream};
+use futures::sync::mpsc;
+use libp2p_identity_core::{Error as IdentityError, SignatureVerifier};
 use multiaddr::Multiaddr;
 use peer_info::{PeerInfo, AddAddrBehaviour};
-use peerstore::{Peerstore, PeerAccess};
+use peerstore::{new_ban, verify_peer_record, Peerstore, PeerAccess};
+use std::collections::HashMap;
 use std::io::Error as IoError;
 use std::iter;
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 use std::vec::IntoIter as VecIntoIter;
 
 /// Peerstore backend that uses a Json file.
 pub struct JsonPeerstore {
 	store: JsonFileDatastore<PeerInfo>,
+	events: EventsNotifier,
 }
 
 impl JsonPeerstore {
@@ -47,7 +52,10 @@ impl JsonPeerstore {
 	pub fn new<P>(path: P) -> Result<JsonPeerstore, IoError>
 		where P: Into<PathBuf>
 	{
-		Ok(JsonPeerstore { store: JsonFileDatastore::new(path)? })
+		Ok(JsonPeerstore {
+			store: JsonFileDatastore::new(path)?,
+			events: EventsNotifier::new(),
+		})
 	}
 
 	/// Flushes the content of the peer store to the disk.
@@ -67,14 +75,20 @@ impl<'a> Peerstore for &'a JsonPeerstore {
 
 	#[inline]
 	fn peer(self, peer_id: &PeerId) -> Option<Self::PeerAccess> {
-		let hash = peer_id.as_bytes().to_base58();
-		self.store.lock(hash.into()).map(JsonPeerstoreAccess)
+		let hash = peer_id.to_base58();
+		self.store
+			.lock(hash.into())
+			.map(|entry| JsonPeerstoreAccess(entry, peer_id.clone(), &self.events))
 	}
 
 	#[inline]
 	fn peer_or_create(self, peer_id: &PeerId) -> Self::PeerAccess {
-		let hash = peer_id.as_bytes().to_base58();
-		JsonPeerstoreAccess(self.store.lock_or_create(hash.into()))
+		let hash = peer_id.to_base58();
+		if !self.store.has(&hash) {
+			self.events.notify(PeerstoreEvent::PeerAdded(peer_id.clone()));
+		}
+		let entry = self.store.lock_or_create(hash.into());
+		JsonPeerstoreAccess(entry, peer_id.clone(), &self.events)
 	}
 
 	fn peers(self) -> Self::PeersIter {
@@ -90,7 +104,7 @@ impl<'a> Peerstore for &'a JsonPeerstore {
 		let list = query.filter_map(|(key, _)| {
 			// We filter out invalid elements. This can happen if the JSON storage file was
 			// corrupted or manually modified by the user.
-			PeerId::from_bytes(key.from_base58().ok()?).ok()
+			PeerId::from_base58(&key).ok()
 		})
 		                .collect()
 		                .wait(); // Wait can never block for the JSON datastore.
@@ -102,9 +116,35 @@ impl<'a> Peerstore for &'a JsonPeerstore {
 			Box::new(iter::empty()) as Box<_>
 		}
 	}
+
+	fn add_peers<I>(self, peers: I, ttl: TTL, source: AddrSource)
+		where I: IntoIterator<Item = (PeerId, Vec<Multiaddr>)>
+	{
+		// We group the addresses by peer, so that the entry of each peer is only locked once.
+		let mut by_peer: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+		for (peer_id, addrs) in peers {
+			by_peer.entry(peer_id).or_insert_with(Vec::new).extend(addrs);
+		}
+
+		for (peer_id, addrs) in by_peer {
+			let mut peer = self.peer_or_create(&peer_id);
+			for addr in addrs {
+				peer.add_addr_with_source(addr, ttl, source);
+			}
+		}
+	}
+
+	#[inline]
+	fn subscribe(self) -> mpsc::UnboundedReceiver<PeerstoreEvent> {
+		self.events.subscribe()
+	}
 }
 
-pub struct JsonPeerstoreAccess<'a>(JsonFileDatastoreEntry<'a, PeerInfo>);
+pub struct JsonPeerstoreAccess<'a>(
+	JsonFileDatastoreEntry<'a, PeerInfo>,
+	PeerId,
+	&'a EventsNotifier,
+);
 
 impl<'a> PeerAccess for JsonPeerstoreAccess<'a> {
 	type AddrsIter = VecIntoIter<Multiaddr>;
@@ -115,19 +155,143 @@ impl<'a> PeerAccess for JsonPeerstoreAccess<'a> {
 	}
 
 	#[inline]
-	fn add_addr(&mut self, addr: Multiaddr, ttl: TTL) {
-		self.0.add_addr(addr, ttl, AddAddrBehaviour::IgnoreTtlIfInferior);
+	fn addrs_with_expiration(&self) -> Vec<(Multiaddr, SystemTime)> {
+		self.0.addrs_with_expiration()
 	}
 
 	#[inline]
+	fn addrs_by_confidence(&self) -> Self::AddrsIter {
+		self.0.addrs_by_confidence().into_iter()
+	}
+
+	fn add_addr_with_source(&mut self, addr: Multiaddr, ttl: TTL, source: AddrSource) {
+		let behaviour = AddAddrBehaviour::IgnoreTtlIfInferior;
+		if self.0.add_addr(addr.clone(), ttl, source, behaviour) {
+			self.2.notify(PeerstoreEvent::AddrAdded { peer_id: self.1.clone(), addr: addr });
+		}
+	}
+
 	fn set_addr_ttl(&mut self, addr: Multiaddr, ttl: TTL) {
-		self.0.add_addr(addr, ttl, AddAddrBehaviour::OverwriteTtl);
+		let behaviour = AddAddrBehaviour::OverwriteTtl;
+		if self.0.add_addr(addr.clone(), ttl, AddrSource::Unknown, behaviour) {
+			self.2.notify(PeerstoreEvent::AddrAdded { peer_id: self.1.clone(), addr: addr });
+		}
 	}
 
 	#[inline]
 	fn clear_addrs(&mut self) {
 		self.0.set_addrs(iter::empty());
 	}
+
+	#[inline]
+	fn report_dial_success(&mut self, addr: &Multiaddr) {
+		self.0.report_dial_success(addr);
+	}
+
+	#[inline]
+	fn report_dial_failure(&mut self, addr: &Multiaddr) {
+		self.0.report_dial_failure(addr);
+	}
+
+	fn collect_garbage(&mut self) -> usize {
+		let expired = self.0.remove_expired();
+		let num_expired = expired.len();
+		for addr in expired {
+			self.2.notify(PeerstoreEvent::AddrExpired { peer_id: self.1.clone(), addr: addr });
+		}
+		num_expired
+	}
+
+	#[inline]
+	fn protocols(&self) -> Vec<String> {
+		self.0.protocols().to_vec()
+	}
+
+	#[inline]
+	fn add_protocol(&mut self, protocol: String) {
+		self.0.add_protocol(protocol);
+	}
+
+	#[inline]
+	fn remove_protocol(&mut self, protocol: &str) {
+		self.0.remove_protocol(protocol);
+	}
+
+	#[inline]
+	fn signed_peer_record(&self) -> Option<Vec<u8>> {
+		self.0.signed_record().map(|envelope| envelope.to_owned())
+	}
+
+	fn add_signed_peer_record<V>(&mut self, envelope: Vec<u8>, ttl: TTL, verifier: &V)
+								 -> Result<bool, IdentityError>
+		where V: SignatureVerifier + ?Sized
+	{
+		let (seq, addrs) = verify_peer_record(&envelope, &self.1, verifier)?;
+		match self.0.set_signed_record(envelope, seq, addrs, ttl) {
+			Some(added) => {
+				for addr in added {
+				self.2.notify(PeerstoreEvent::AddrAdded {
+					peer_id: self.1.clone(),
+					addr: addr,
+				});
+				}
+				Ok(true)
+			},
+			None => Ok(false),
+		}
+	}
+
+	#[inline]
+	fn public_key(&self) -> Option<Vec<u8>> {
+		self.0.public_key().map(|key| key.to_owned())
+	}
+
+	fn set_public_key(&mut self, key: Vec<u8>) -> Result<(), Vec<u8>> {
+		if PeerId::from_public_key(&key) != self.1 {
+			return Err(key);
+		}
+		let changed = self.0.public_key() != Some(&key[..]);
+		self.0.set_public_key(key);
+		if changed {
+			self.2.notify(PeerstoreEvent::PublicKeyLearned(self.1.clone()));
+		}
+		Ok(())
+	}
+
+	#[inline]
+	fn latency(&self) -> Option<Duration> {
+		self.0.latency()
+	}
+
+	#[inline]
+	fn record_latency(&mut self, rtt: Duration) {
+		self.0.record_latency(rtt);
+	}
+
+	#[inline]
+	fn last_seen(&self) -> Option<SystemTime> {
+		self.0.last_seen()
+	}
+
+	#[inline]
+	fn mark_seen(&mut self) {
+		self.0.mark_seen();
+	}
+
+	#[inline]
+	fn ban(&self) -> Option<Ban> {
+		self.0.ban().cloned()
+	}
+
+	#[inline]
+	fn set_ban(&mut self, reason: String, issuer: String, ttl: TTL) {
+		self.0.set_ban(Some(new_ban(reason, issuer, ttl)));
+	}
+
+	#[inline]
+	fn unban(&mut self) {
+		self.0.set_ban(None);
+	}
 }
 
 #[cfg(test)]
@@ -137,4 +301,41 @@ mod tests {
 		{::json_peerstore::JsonPeerstore::new(temp_file.path()).unwrap()}
 		{let temp_file = self::tempfile::NamedTempFile::new().unwrap()}
 	);
+
+	#[test]
+	fn survives_reopen() {
+		let temp_file = self::tempfile::NamedTempFile::new().unwrap();
+		let peer_id = PeerId::from_public_key(&[1, 2, 3]);
+		let addr = "/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>().unwrap();
+
+		{
+			let peer_store = ::json_peerstore::JsonPeerstore::new(temp_file.path()).unwrap();
+			peer_store.peer_or_create(&peer_id).add_addr(addr.clone(), Duration::from_secs(60));
+			peer_store.flush().unwrap();
+		}
+
+		let peer_store = ::json_peerstore::JsonPeerstore::new(temp_file.path()).unwrap();
+		let addrs = peer_store.peer(&peer_id).unwrap().addrs().collect::<Vec<_>>();
+		assert_eq!(addrs, &[addr]);
+	}
+
+	#[test]
+	fn bans_survive_restart() {
+		let temp_file = self::tempfile::NamedTempFile::new().unwrap();
+		let peer_id = PeerId::from_public_key(&[1, 2, 3]);
+
+		{
+			let peer_store = ::json_peerstore::JsonPeerstore::new(temp_file.path()).unwrap();
+			peer_store.peer_or_create(&peer_id)
+				.set_ban("spam".to_owned(), "admin".to_owned(), Duration::from_secs(3600));
+			peer_store.flush().unwrap();
+		}
+
+		// The swarm of the restarted node starts with an empty ban list.
+		let peer_store = ::json_peerstore::JsonPeerstore::new(temp_file.path()).unwrap();
+		let ban_list = ::libp2p_swarm::BanList::new();
+		assert!(!ban_list.is_peer_banned(&peer_id));
+		assert_eq!(peer_store.restore_bans(&ban_list), 1);
+		assert!(ban_list.is_peer_banned(&peer_id));
+	}
 }
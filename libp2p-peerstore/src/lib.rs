@@ -25,10 +25,72 @@
 //! values are the public key and a list of multiaddresses. Additionally, the multiaddresses stored
 //! by the `peerstore` have a time-to-live after which they disappear.
 //!
+//! Expired addresses are never returned, but they are only removed from the storage by calling
+//! `Peerstore::collect_garbage`, which should be done from time to time. The `ttl` module
+//! contains presets for the time-to-live of addresses depending on how they were learned: for
+//! example `ttl::connected()` for the addresses of peers we are connected to, and
+//! `ttl::temporary()` for addresses reported by other peers.
+//!
+//! Each address also remembers where it was learned from (see `AddrSource`), when it was last
+//! successfully dialed, and how many times dialing it has failed since then. Report the outcome
+//! of dialing attempts with `PeerAccess::report_dial_success` and
+//! `PeerAccess::report_dial_failure`, and use `PeerAccess::addrs_by_confidence` to obtain the
+//! addresses in the order in which they should be tried.
+//!
+//! Peers can also prove which addresses they are listening on with a signed peer record. Such a
+//! record is stored with `PeerAccess::add_signed_peer_record`, which checks its signature. The
+//! addresses it contains are certified, and are always tried before the other addresses. The
+//! record itself can be retrieved with `PeerAccess::signed_peer_record` in order to forward it to
+//! other peers.
+//!
+//! The sequence number of a record is the moment when it was created. The records received from
+//! the peer itself can be stored with `PeerAccess::add_signed_peer_record_with_skew` instead, which
+//! also measures the offset of the clock of the peer in a `ClockSkew`.
+//!
+//! The peerstore can also store the list of protocols that a peer supports, as learned for
+//! example through the identify protocol. Components such as the DHT can then use
+//! `Peerstore::peers_supporting` to pick peers that actually speak their protocol. More generally,
+//! `Peerstore::peers_matching` iterates over the peers that match a predicate.
+//!
+//! Finally, the public key of a peer can be stored with `PeerAccess::set_public_key`, so that
+//! signatures from known peers can be verified without being connected to them. The peerstore
+//! refuses to store a key that doesn't correspond to the ID of the peer.
+//!
+//! Protocols such as ping can record round-trip time samples with `PeerAccess::record_latency`.
+//! The peerstore keeps a smoothed average of these samples and the last time each peer was seen,
+//! which can be used when ranking candidate peers.
+//!
+//! Other components can react to the modifications of the address book without polling it, by
+//! calling `Peerstore::subscribe`. This returns a stream of `PeerstoreEvent`s, which are produced
+//! when a peer is added, when an address is added or has expired, and when a public key is
+//! learned.
+//!
 //! This crate consists of a generic `Peerstore` trait and the follow implementations:
 //!
 //! - `JsonPeerstore`: Stores the information in a single JSON file.
-//! - `MemoryPeerstore`: Stores the information in memory.
+//! - `MemoryPeerstore`: Stores the information in memory. Peers are spread over multiple shards
+//!   with their own lock, so that tasks accessing different peers don't block each other.
+//! - `SledPeerstore`: Stores the information in an embedded `sled` database. Contrary to
+//!   `JsonPeerstore`, the content doesn't have to fit in memory and modifications are written as
+//!   soon as they are made.
+//!
+//! The peerstore also keeps track of the peers that are banned, with the reason of the ban, who
+//! issued it, and when it expires. With a persistent backend such as `JsonPeerstore` or
+//! `SledPeerstore`, addresses and bans survive restarts of the node. When the node starts,
+//! `Peerstore::restore_bans` passes the stored bans to the `BanList` of the swarm.
+//!
+//! The content of any peer store can be written as human-readable JSON with `Peerstore::export`,
+//! and loaded back with `Peerstore::import`. This can be used to seed a node with a list of
+//! bootstrap peers, or to inspect its address book with a text editor. The file written by
+//! `JsonPeerstore` is pretty-printed as well.
+//!
+//! Nodes migrating from go-libp2p can carry their address book across with the `go_layout`
+//! module, which converts the content of a peer store from and to the key-value pairs stored by
+//! the persistent peerstore of go-libp2p.
+//!
+//! Large lists of peers, such as bootstrap lists, should be added with `Peerstore::add_peers`,
+//! which avoids locking the storage (and, for the sled backend, writing to the disk) for every
+//! address.
 //!
 //! Note that the peerstore implementations do not consider information inside a peer store to be
 //! critical. In case of an error (eg. corrupted file, disk error, etc.) they will prefer to lose
@@ -42,9 +104,8 @@
 //! 
 //! # fn main() {
 //! use libp2p_peerstore::memory_peerstore::MemoryPeerstore;
-//! use libp2p_peerstore::{PeerId, Peerstore, PeerAccess};
+//! use libp2p_peerstore::{ttl, PeerId, Peerstore, PeerAccess};
 //! use multiaddr::Multiaddr;
-//! use std::time::Duration;
 //! 
 //! // In this example we use a `MemoryPeerstore`, but you can easily swap it for another backend.
 //! let mut peerstore = MemoryPeerstore::empty();
@@ -55,7 +116,7 @@
 //!     // `peer_or_create` mutably borrows the peerstore, so we have to do it in a local scope.
 //!     let mut peer = peerstore.peer_or_create(&peer_id);
 //!     peer.add_addr("/ip4/10.11.12.13/tcp/20000".parse::<Multiaddr>().unwrap(),
-//!                   Duration::from_millis(5000));
+//!                   ttl::temporary());
 //! }
 //! 
 //! // Now let's load back the info.
@@ -67,91 +128,36 @@
 //! # }
 //! ```
 
-extern crate base58;
+extern crate data_encoding;
 extern crate datastore;
 extern crate futures;
+extern crate libp2p_identity_core;
+extern crate libp2p_swarm;
 extern crate multiaddr;
-extern crate multihash;
 extern crate owning_ref;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
+extern crate sled;
 
-use std::fmt;
-use base58::ToBase58;
-
+pub use libp2p_swarm::PeerId;
+pub use self::events::PeerstoreEvent;
+pub use self::peer_info::{AddrSource, Ban};
 pub use self::peerstore::{Peerstore, PeerAccess};
+pub use self::query::{PeersMatching, PeersSupporting};
 
 #[macro_use]
 mod peerstore_tests;
 
+mod events;
+pub mod go_layout;
 pub mod json_peerstore;
 pub mod memory_peerstore;
 mod peerstore;
 mod peer_info;
+mod query;
+pub mod sled_peerstore;
+pub mod ttl;
 
 pub type TTL = std::time::Duration;
-
-/// Identifier of a peer of the network.
-///
-/// The data is a multihash of the public key of the peer.
-// TODO: maybe keep things in decoded version?
-#[derive(Clone, PartialEq, Eq, Hash)]
-pub struct PeerId {
-    multihash: Vec<u8>,
-}
-
-impl fmt::Debug for PeerId {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "PeerId({})", self.multihash.to_base58())
-    }
-}
-
-impl PeerId {
-    /// Builds a `PeerId` from a public key.
-    #[inline]
-    pub fn from_public_key(public_key: &[u8]) -> PeerId {
-        let data = multihash::encode(multihash::Hash::SHA2256, public_key)
-            .expect("sha2-256 is always supported");
-        PeerId { multihash: data }
-    }
-
-    /// Checks whether `data` is a valid `PeerId`. If so, returns the `PeerId`. If not, returns
-    /// back the data as an error.
-    #[inline]
-    pub fn from_bytes(data: Vec<u8>) -> Result<PeerId, Vec<u8>> {
-        match multihash::decode(&data) {
-            Ok(_) => Ok(PeerId { multihash: data }),
-            Err(_) => Err(data),
-        }
-    }
-
-    /// Returns a raw bytes representation of this `PeerId`.
-    #[inline]
-    pub fn into_bytes(self) -> Vec<u8> {
-        self.multihash
-    }
-
-    /// Returns a raw bytes representation of this `PeerId`.
-    #[inline]
-    pub fn as_bytes(&self) -> &[u8] {
-        &self.multihash
-    }
-
-    /// Returns the raw bytes of the hash of this `PeerId`.
-    #[inline]
-    pub fn hash(&self) -> &[u8] {
-        let multihash::Multihash { digest, .. } = multihash::decode(&self.multihash)
-            .expect("our inner value should always be valid");
-        digest
-    }
-
-    /// Checks whether the public key passed as parameter matches the public key of this `PeerId`.
-    pub fn is_public_key(&self, public_key: &[u8]) -> bool {
-        let multihash::Multihash { alg, .. } = multihash::decode(&self.multihash)
-            .expect("our inner value should always be valid");
-        let compare = multihash::encode(alg, public_key)
-            .expect("unsupported multihash algorithm");     // TODO: what to do here?
-        compare == self.multihash
-    }
-}
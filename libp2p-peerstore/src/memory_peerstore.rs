@@ -21,26 +21,70 @@
 //! Implementation of the `Peerstore` trait that simple stores peers in memory.
 
 use super::TTL;
-use PeerId;
+use {AddrSource, Ban, PeerId};
 use multiaddr::Multiaddr;
 use owning_ref::OwningRefMut;
+use events::{EventsNotifier, PeerstoreEvent};
+use futures::sync::mpsc;
+use libp2p_identity_core::{Error as IdentityError, SignatureVerifier};
 use peer_info::{PeerInfo, AddAddrBehaviour};
-use peerstore::{Peerstore, PeerAccess};
+use peerstore::{new_ban, verify_peer_record, Peerstore, PeerAccess};
 use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::iter;
 use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
 use std::vec::IntoIter as VecIntoIter;
 
+// Default number of shards of a `MemoryPeerstore`.
+const DEFAULT_SHARDS: usize = 16;
+
 /// Implementation of the `Peerstore` trait that simply stores the peer information in memory.
+///
+/// The peers are spread over multiple shards, each protected by its own lock. Accessing a peer
+/// only locks the shard the peer belongs to, which means that multiple tasks can access
+/// different peers at the same time.
 pub struct MemoryPeerstore {
-	store: Mutex<HashMap<PeerId, PeerInfo>>,
+	shards: Vec<Mutex<HashMap<PeerId, PeerInfo>>>,
+	events: EventsNotifier,
 }
 
 impl MemoryPeerstore {
 	/// Initializes a new `MemoryPeerstore`. The database is initially empty.
 	#[inline]
 	pub fn empty() -> MemoryPeerstore {
-		MemoryPeerstore { store: Mutex::new(HashMap::new()) }
+		MemoryPeerstore::with_shards(DEFAULT_SHARDS)
+	}
+
+	/// Initializes a new empty `MemoryPeerstore` whose peers are spread over `num_shards`
+	/// shards. A higher number reduces the contention when many tasks access the peerstore at
+	/// the same time.
+	///
+	/// # Panic
+	///
+	/// Panics if `num_shards` is 0.
+	pub fn with_shards(num_shards: usize) -> MemoryPeerstore {
+		assert!(num_shards >= 1, "a MemoryPeerstore needs at least one shard");
+		MemoryPeerstore {
+			shards: (0 .. num_shards).map(|_| Mutex::new(HashMap::new())).collect(),
+			events: EventsNotifier::new(),
+		}
+	}
+
+	// Returns the index of the shard that contains the given peer.
+	#[inline]
+	fn shard_index(&self, peer_id: &PeerId) -> usize {
+		let mut hasher = DefaultHasher::new();
+		peer_id.hash(&mut hasher);
+		(hasher.finish() % self.shards.len() as u64) as usize
+	}
+
+	// Returns the shard that contains the given peer.
+	#[inline]
+	fn shard(&self, peer_id: &PeerId) -> &Mutex<HashMap<PeerId, PeerInfo>> {
+		&self.shards[self.shard_index(peer_id)]
 	}
 }
 
@@ -56,29 +100,83 @@ impl<'a> Peerstore for &'a MemoryPeerstore {
 	type PeersIter = VecIntoIter<PeerId>;
 
 	fn peer(self, peer_id: &PeerId) -> Option<Self::PeerAccess> {
-		let lock = self.store.lock().unwrap();
+		let lock = self.shard(peer_id).lock().unwrap();
 		OwningRefMut::new(lock)
 			.try_map_mut(|n| n.get_mut(peer_id).ok_or(()))
 			.ok()
-			.map(MemoryPeerstoreAccess)
+			.map(|r| MemoryPeerstoreAccess(r, peer_id.clone(), &self.events))
 	}
 
 	fn peer_or_create(self, peer_id: &PeerId) -> Self::PeerAccess {
-		let lock = self.store.lock().unwrap();
+		let lock = self.shard(peer_id).lock().unwrap();
+		if !lock.contains_key(peer_id) {
+			self.events.notify(PeerstoreEvent::PeerAdded(peer_id.clone()));
+		}
 		let r = OwningRefMut::new(lock)
 			.map_mut(|n| n.entry(peer_id.clone()).or_insert_with(|| PeerInfo::new()));
-		MemoryPeerstoreAccess(r)
+		MemoryPeerstoreAccess(r, peer_id.clone(), &self.events)
 	}
 
 	fn peers(self) -> Self::PeersIter {
-		let lock = self.store.lock().unwrap();
-		lock.keys().cloned().collect::<Vec<_>>().into_iter()
+		let mut peers = Vec::new();
+		for shard in self.shards.iter() {
+			// We only lock one shard at a time, so that we don't block the whole peerstore.
+			let lock = shard.lock().unwrap();
+			peers.extend(lock.keys().cloned());
+		}
+		peers.into_iter()
+	}
+
+	fn add_peers<I>(self, peers: I, ttl: TTL, source: AddrSource)
+		where I: IntoIterator<Item = (PeerId, Vec<Multiaddr>)>
+	{
+		// We group the peers by shard, so that each shard is only locked once.
+		let mut by_shard = (0 .. self.shards.len()).map(|_| Vec::new()).collect::<Vec<_>>();
+		for (peer_id, addrs) in peers {
+			by_shard[self.shard_index(&peer_id)].push((peer_id, addrs));
+		}
+
+		for (shard, peers) in self.shards.iter().zip(by_shard.into_iter()) {
+			if peers.is_empty() {
+				continue;
+			}
+
+			let mut lock = shard.lock().unwrap();
+			for (peer_id, addrs) in peers {
+				let info = match lock.entry(peer_id.clone()) {
+					Entry::Occupied(entry) => entry.into_mut(),
+					Entry::Vacant(entry) => {
+						self.events.notify(PeerstoreEvent::PeerAdded(peer_id.clone()));
+						entry.insert(PeerInfo::new())
+					},
+				};
+
+				for addr in addrs {
+					let behaviour = AddAddrBehaviour::IgnoreTtlIfInferior;
+					if info.add_addr(addr.clone(), ttl, source, behaviour) {
+						self.events.notify(PeerstoreEvent::AddrAdded {
+							peer_id: peer_id.clone(),
+							addr: addr,
+						});
+					}
+				}
+			}
+		}
+	}
+
+	#[inline]
+	fn subscribe(self) -> mpsc::UnboundedReceiver<PeerstoreEvent> {
+		self.events.subscribe()
 	}
 }
 
 // Note: Rust doesn't provide a `MutexGuard::map` method, otherwise we could directly store a
 // 		 `MutexGuard<'a, (&'a PeerId, &'a PeerInfo)>`.
-pub struct MemoryPeerstoreAccess<'a>(OwningRefMut<MutexGuard<'a, HashMap<PeerId, PeerInfo>>, PeerInfo>);
+pub struct MemoryPeerstoreAccess<'a>(
+	OwningRefMut<MutexGuard<'a, HashMap<PeerId, PeerInfo>>, PeerInfo>,
+	PeerId,
+	&'a EventsNotifier,
+);
 
 impl<'a> PeerAccess for MemoryPeerstoreAccess<'a> {
 	type AddrsIter = VecIntoIter<Multiaddr>;
@@ -89,19 +187,143 @@ impl<'a> PeerAccess for MemoryPeerstoreAccess<'a> {
 	}
 
 	#[inline]
-	fn add_addr(&mut self, addr: Multiaddr, ttl: TTL) {
-		self.0.add_addr(addr, ttl, AddAddrBehaviour::IgnoreTtlIfInferior);
+	fn addrs_with_expiration(&self) -> Vec<(Multiaddr, SystemTime)> {
+		self.0.addrs_with_expiration()
 	}
 
 	#[inline]
+	fn addrs_by_confidence(&self) -> Self::AddrsIter {
+		self.0.addrs_by_confidence().into_iter()
+	}
+
+	fn add_addr_with_source(&mut self, addr: Multiaddr, ttl: TTL, source: AddrSource) {
+		let behaviour = AddAddrBehaviour::IgnoreTtlIfInferior;
+		if self.0.add_addr(addr.clone(), ttl, source, behaviour) {
+			self.2.notify(PeerstoreEvent::AddrAdded { peer_id: self.1.clone(), addr: addr });
+		}
+	}
+
 	fn set_addr_ttl(&mut self, addr: Multiaddr, ttl: TTL) {
-		self.0.add_addr(addr, ttl, AddAddrBehaviour::OverwriteTtl);
+		let behaviour = AddAddrBehaviour::OverwriteTtl;
+		if self.0.add_addr(addr.clone(), ttl, AddrSource::Unknown, behaviour) {
+			self.2.notify(PeerstoreEvent::AddrAdded { peer_id: self.1.clone(), addr: addr });
+		}
 	}
 
 	#[inline]
 	fn clear_addrs(&mut self) {
 		self.0.set_addrs(iter::empty());
 	}
+
+	#[inline]
+	fn report_dial_success(&mut self, addr: &Multiaddr) {
+		self.0.report_dial_success(addr);
+	}
+
+	#[inline]
+	fn report_dial_failure(&mut self, addr: &Multiaddr) {
+		self.0.report_dial_failure(addr);
+	}
+
+	fn collect_garbage(&mut self) -> usize {
+		let expired = self.0.remove_expired();
+		let num_expired = expired.len();
+		for addr in expired {
+			self.2.notify(PeerstoreEvent::AddrExpired { peer_id: self.1.clone(), addr: addr });
+		}
+		num_expired
+	}
+
+	#[inline]
+	fn protocols(&self) -> Vec<String> {
+		self.0.protocols().to_vec()
+	}
+
+	#[inline]
+	fn add_protocol(&mut self, protocol: String) {
+		self.0.add_protocol(protocol);
+	}
+
+	#[inline]
+	fn remove_protocol(&mut self, protocol: &str) {
+		self.0.remove_protocol(protocol);
+	}
+
+	#[inline]
+	fn signed_peer_record(&self) -> Option<Vec<u8>> {
+		self.0.signed_record().map(|envelope| envelope.to_owned())
+	}
+
+	fn add_signed_peer_record<V>(&mut self, envelope: Vec<u8>, ttl: TTL, verifier: &V)
+								 -> Result<bool, IdentityError>
+		where V: SignatureVerifier + ?Sized
+	{
+		let (seq, addrs) = verify_peer_record(&envelope, &self.1, verifier)?;
+		match self.0.set_signed_record(envelope, seq, addrs, ttl) {
+			Some(added) => {
+				for addr in added {
+				self.2.notify(PeerstoreEvent::AddrAdded {
+					peer_id: self.1.clone(),
+					addr: addr,
+				});
+				}
+				Ok(true)
+			},
+			None => Ok(false),
+		}
+	}
+
+	#[inline]
+	fn public_key(&self) -> Option<Vec<u8>> {
+		self.0.public_key().map(|key| key.to_owned())
+	}
+
+	fn set_public_key(&mut self, key: Vec<u8>) -> Result<(), Vec<u8>> {
+		if PeerId::from_public_key(&key) != self.1 {
+			return Err(key);
+		}
+		let changed = self.0.public_key() != Some(&key[..]);
+		self.0.set_public_key(key);
+		if changed {
+			self.2.notify(PeerstoreEvent::PublicKeyLearned(self.1.clone()));
+		}
+		Ok(())
+	}
+
+	#[inline]
+	fn latency(&self) -> Option<Duration> {
+		self.0.latency()
+	}
+
+	#[inline]
+	fn record_latency(&mut self, rtt: Duration) {
+		self.0.record_latency(rtt);
+	}
+
+	#[inline]
+	fn last_seen(&self) -> Option<SystemTime> {
+		self.0.last_seen()
+	}
+
+	#[inline]
+	fn mark_seen(&mut self) {
+		self.0.mark_seen();
+	}
+
+	#[inline]
+	fn ban(&self) -> Option<Ban> {
+		self.0.ban().cloned()
+	}
+
+	#[inline]
+	fn set_ban(&mut self, reason: String, issuer: String, ttl: TTL) {
+		self.0.set_ban(Some(new_ban(reason, issuer, ttl)));
+	}
+
+	#[inline]
+	fn unban(&mut self) {
+		self.0.set_ban(None);
+	}
 }
 
 #[cfg(test)]
@@ -109,4 +331,28 @@ mod tests {
 	peerstore_tests!({
 		::memory_peerstore::MemoryPeerstore::empty()
 	});
+
+	#[test]
+	fn concurrent_access() {
+		use std::sync::Arc;
+		use memory_peerstore::MemoryPeerstore;
+
+		let peer_store = Arc::new(MemoryPeerstore::with_shards(4));
+		let threads = (0 .. 8u8)
+			.map(|n| {
+				let peer_store = peer_store.clone();
+				thread::spawn(move || {
+					for m in 0 .. 32u8 {
+						let peer_id = PeerId::from_public_key(&[n, m]);
+						peer_store.peer_or_create(&peer_id).add_protocol(format!("/proto/{}", m));
+					}
+				})
+			})
+			.collect::<Vec<_>>();
+		for thread in threads {
+			thread.join().unwrap();
+		}
+
+		assert_eq!(peer_store.peers().count(), 8 * 32);
+	}
 }
@@ -37,15 +37,117 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 /// Information about a peer.
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct PeerInfo {
-	// Adresses, and the time at which they will be considered expired.
-	addrs: Vec<(Multiaddr, SystemTime)>,
+	// Adresses, alongside with their metadata.
+	addrs: Vec<AddrInfo>,
+	// Ban of the peer, if any. Can be expired.
+	ban: Option<Ban>,
+	// Names of the protocols supported by the peer, as reported by the identify protocol.
+	protocols: Vec<String>,
+	// Public key of the peer, if known. Must correspond to the ID of the peer.
+	public_key: Option<Vec<u8>>,
+	// Smoothed round-trip time to the peer, if any sample has been recorded.
+	latency: Option<Duration>,
+	// Last time we have heard from the peer.
+	last_seen: Option<SystemTime>,
+	// Sequence number and `Envelope` protobuf message of the latest signed peer record.
+	signed_record: Option<(u64, Vec<u8>)>,
+}
+
+/// Information about the ban of a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ban {
+	/// Human-readable reason why the peer has been banned.
+	pub reason: String,
+	/// Who issued the ban, for example the name of the operator or of the subsystem.
+	pub issuer: String,
+	/// Moment when the ban expires.
+	pub expires: SystemTime,
+}
+
+/// Where an address of a peer was learned from.
+///
+/// The sources are listed from the most trustworthy to the least trustworthy.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AddrSource {
+	/// The address was added manually, for example by the operator or in a list of bootstrap
+	/// nodes.
+	Manual,
+	/// The address was reported by the peer itself through the identify protocol.
+	Identify,
+	/// The address was found in the DHT.
+	Dht,
+	/// The origin of the address isn't known.
+	Unknown,
+}
+
+impl AddrSource {
+	// Returns a number that is lower for more trustworthy sources.
+	#[inline]
+	fn rank(&self) -> u8 {
+		match *self {
+			AddrSource::Manual => 0,
+			AddrSource::Identify => 1,
+			AddrSource::Dht => 2,
+			AddrSource::Unknown => 3,
+		}
+	}
+}
+
+impl Default for AddrSource {
+	#[inline]
+	fn default() -> AddrSource {
+		AddrSource::Unknown
+	}
+}
+
+// An address of a peer and its metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AddrInfo {
+	addr: Multiaddr,
+	// Time at which the address will be considered expired.
+	expires: SystemTime,
+	source: AddrSource,
+	// Last time we successfully dialed this address.
+	last_success: Option<SystemTime>,
+	// Number of times dialing this address has failed since the last success.
+	failures: u32,
+	// True if the address is part of the signed peer record of the peer.
+	certified: bool,
+}
+
+impl AddrInfo {
+	// Compares two addresses by how likely dialing them is to succeed. The address that is the
+	// most likely to succeed comes first.
+	//
+	// Certified addresses always come first. Then we look at the number of failures since the
+	// last success, then at the last time the address was successfully dialed, and finally at the
+	// source of the address.
+	fn cmp_confidence(&self, other: &AddrInfo) -> Ordering {
+		other.certified.cmp(&self.certified)
+			.then_with(|| self.failures.cmp(&other.failures))
+			.then_with(|| match (self.last_success, other.last_success) {
+				(Some(a), Some(b)) => b.cmp(&a),
+				(Some(_), None) => Ordering::Less,
+				(None, Some(_)) => Ordering::Greater,
+				(None, None) => Ordering::Equal,
+			})
+			.then_with(|| self.source.rank().cmp(&other.source.rank()))
+	}
 }
 
 impl PeerInfo {
 	/// Builds a new empty `PeerInfo`.
 	#[inline]
 	pub fn new() -> PeerInfo {
-		PeerInfo { addrs: vec![] }
+		PeerInfo {
+			addrs: vec![],
+			ban: None,
+			protocols: vec![],
+			public_key: None,
+			latency: None,
+			last_seen: None,
+			signed_record: None,
+		}
 	}
 
 	/// Returns the list of the non-expired addresses stored in this `PeerInfo`.
@@ -56,13 +158,36 @@ impl PeerInfo {
 	#[inline]
 	pub fn addrs<'a>(&'a self) -> Box<Iterator<Item = &'a Multiaddr> + 'a> {
 		let now = SystemTime::now();
-		Box::new(self.addrs.iter().filter_map(move |&(ref addr, ref expires)| if *expires >= now {
-			Some(addr)
+		Box::new(self.addrs.iter().filter_map(move |info| if info.expires >= now {
+			Some(&info.addr)
 		} else {
 			None
 		}))
 	}
 
+	/// Returns the list of the non-expired addresses stored in this `PeerInfo`, alongside with
+	/// the moment when they expire.
+	pub fn addrs_with_expiration(&self) -> Vec<(Multiaddr, SystemTime)> {
+		let now = SystemTime::now();
+		self.addrs.iter()
+			.filter(|info| info.expires >= now)
+			.map(|info| (info.addr.clone(), info.expires))
+			.collect()
+	}
+
+	/// Returns the list of the non-expired addresses stored in this `PeerInfo`, sorted by
+	/// decreasing likeliness that dialing them succeeds.
+	///
+	/// Addresses that failed the fewest times since they were last successfully dialed come
+	/// first. Ties are broken by the time of the last successful dial, then by the source of the
+	/// address.
+	pub fn addrs_by_confidence(&self) -> Vec<Multiaddr> {
+		let now = SystemTime::now();
+		let mut addrs = self.addrs.iter().filter(|info| info.expires >= now).collect::<Vec<_>>();
+		addrs.sort_by(|a, b| a.cmp_confidence(b));
+		addrs.into_iter().map(|info| info.addr.clone()).collect()
+	}
+
 	/// Sets the list of addresses and their time-to-live.
 	///
 	/// This removes all previously-stored addresses and replaces them with new ones.
@@ -71,26 +196,194 @@ impl PeerInfo {
 		where I: IntoIterator<Item = (Multiaddr, TTL)>
 	{
 		let now = SystemTime::now();
-		self.addrs = addrs.into_iter().map(move |(addr, ttl)| (addr, now + ttl)).collect();
+		self.addrs = addrs.into_iter()
+			.map(move |(addr, ttl)| AddrInfo {
+				addr: addr,
+				expires: now + ttl,
+				source: AddrSource::Unknown,
+				last_success: None,
+				failures: 0,
+				certified: false,
+			})
+			.collect();
 	}
 
 	/// Adds a single address and its time-to-live.
 	///
-	/// If the peer info already knows about that address, then what happens depends on the
-	/// `behaviour` parameter.
-	pub fn add_addr(&mut self, addr: Multiaddr, ttl: TTL, behaviour: AddAddrBehaviour) {
+	/// If the peer info already knows about that address, then what happens to the TTL depends
+	/// on the `behaviour` parameter. The source of the address is replaced if `source` is more
+	/// trustworthy.
+	///
+	/// Returns true if the address wasn't known before.
+	pub fn add_addr(&mut self, addr: Multiaddr, ttl: TTL, source: AddrSource,
+					behaviour: AddAddrBehaviour) -> bool
+	{
 		let expires = SystemTime::now() + ttl;
 
-		if let Some(&mut (_, ref mut existing_expires)) =
-			self.addrs.iter_mut().find(|&&mut (ref a, _)| a == &addr)
-		{
-			if behaviour == AddAddrBehaviour::OverwriteTtl || *existing_expires < expires {
-				*existing_expires = expires;
+		if let Some(existing) = self.addrs.iter_mut().find(|info| info.addr == addr) {
+			if behaviour == AddAddrBehaviour::OverwriteTtl || existing.expires < expires {
+				existing.expires = expires;
+			}
+			if source.rank() < existing.source.rank() {
+				existing.source = source;
+			}
+			return false;
+		}
+
+		self.addrs.push(AddrInfo {
+			addr: addr,
+			expires: expires,
+			source: source,
+			last_success: None,
+			failures: 0,
+			certified: false,
+		});
+		true
+	}
+
+	/// Records that dialing the given address succeeded. Has no effect if the address is
+	/// unknown.
+	pub fn report_dial_success(&mut self, addr: &Multiaddr) {
+		if let Some(info) = self.addrs.iter_mut().find(|info| &info.addr == addr) {
+			info.last_success = Some(SystemTime::now());
+			info.failures = 0;
+		}
+	}
+
+	/// Records that dialing the given address failed. Has no effect if the address is unknown.
+	pub fn report_dial_failure(&mut self, addr: &Multiaddr) {
+		if let Some(info) = self.addrs.iter_mut().find(|info| &info.addr == addr) {
+			info.failures = info.failures.saturating_add(1);
+		}
+	}
+
+	/// Removes the addresses and the ban that have expired. Returns the addresses that were
+	/// removed.
+	pub fn remove_expired(&mut self) -> Vec<Multiaddr> {
+		let now = SystemTime::now();
+		let (kept, expired): (Vec<_>, Vec<_>) =
+			self.addrs.drain(..).partition(|info| info.expires >= now);
+		self.addrs = kept;
+		if self.ban.as_ref().map(|ban| ban.expires < now).unwrap_or(false) {
+			self.ban = None;
+		}
+		expired.into_iter().map(|info| info.addr).collect()
+	}
+
+	/// Returns the ban of the peer, if it is banned and the ban hasn't expired.
+	#[inline]
+	pub fn ban(&self) -> Option<&Ban> {
+		let now = SystemTime::now();
+		self.ban.as_ref().and_then(|ban| if ban.expires >= now { Some(ban) } else { None })
+	}
+
+	/// Sets or removes the ban of the peer.
+	#[inline]
+	pub fn set_ban(&mut self, ban: Option<Ban>) {
+		self.ban = ban;
+	}
+
+	/// Returns the public key of the peer, if known.
+	#[inline]
+	pub fn public_key(&self) -> Option<&[u8]> {
+		self.public_key.as_ref().map(|key| &key[..])
+	}
+
+	/// Sets the public key of the peer.
+	///
+	/// > **Note**: It is the responsibility of the caller to check that the key corresponds to
+	/// >           the ID of the peer.
+	#[inline]
+	pub fn set_public_key(&mut self, key: Vec<u8>) {
+		self.public_key = Some(key);
+	}
+
+	/// Returns the `Envelope` protobuf message of the latest signed peer record, if any.
+	#[inline]
+	pub fn signed_record(&self) -> Option<&[u8]> {
+		self.signed_record.as_ref().map(|&(_, ref envelope)| &envelope[..])
+	}
+
+	/// Stores a signed peer record, whose signature must have been checked by the caller.
+	///
+	/// The addresses of the record are added with the given TTL and marked as certified, while
+	/// the addresses of the previous record lose their certification. If a record with a higher
+	/// or equal sequence number is already stored, nothing happens and `None` is returned.
+	/// Otherwise, returns the addresses that weren't known before.
+	pub fn set_signed_record(&mut self, envelope: Vec<u8>, seq: u64, addrs: Vec<Multiaddr>,
+							 ttl: TTL) -> Option<Vec<Multiaddr>>
+	{
+		if self.signed_record.as_ref().map(|&(s, _)| s >= seq).unwrap_or(false) {
+			return None;
+		}
+
+		for info in self.addrs.iter_mut() {
+			info.certified = false;
+		}
+
+		let mut added = Vec::new();
+		for addr in addrs {
+			let behaviour = AddAddrBehaviour::IgnoreTtlIfInferior;
+			if self.add_addr(addr.clone(), ttl, AddrSource::Identify, behaviour) {
+				added.push(addr.clone());
+			}
+			if let Some(info) = self.addrs.iter_mut().find(|info| info.addr == addr) {
+				info.certified = true;
 			}
-			return;
 		}
 
-		self.addrs.push((addr, expires));
+		self.signed_record = Some((seq, envelope));
+		Some(added)
+	}
+
+	/// Returns the smoothed round-trip time to the peer, if any sample has been recorded.
+	#[inline]
+	pub fn latency(&self) -> Option<Duration> {
+		self.latency
+	}
+
+	/// Records a round-trip time sample, and marks the peer as seen.
+	///
+	/// The latency is an exponentially-weighted moving average of the samples, where each new
+	/// sample has a weight of 10%.
+	pub fn record_latency(&mut self, rtt: Duration) {
+		self.latency = Some(match self.latency {
+			Some(latency) => latency * 9 / 10 + rtt / 10,
+			None => rtt,
+		});
+		self.mark_seen();
+	}
+
+	/// Returns the last time we have heard from the peer, if ever.
+	#[inline]
+	pub fn last_seen(&self) -> Option<SystemTime> {
+		self.last_seen
+	}
+
+	/// Records that we have just heard from the peer.
+	#[inline]
+	pub fn mark_seen(&mut self) {
+		self.last_seen = Some(SystemTime::now());
+	}
+
+	/// Returns the names of the protocols supported by the peer.
+	#[inline]
+	pub fn protocols(&self) -> &[String] {
+		&self.protocols
+	}
+
+	/// Adds a protocol to the list of protocols supported by the peer, if it isn't already in it.
+	#[inline]
+	pub fn add_protocol(&mut self, protocol: String) {
+		if !self.protocols.contains(&protocol) {
+			self.protocols.push(protocol);
+		}
+	}
+
+	/// Removes a protocol from the list of protocols supported by the peer.
+	#[inline]
+	pub fn remove_protocol(&mut self, protocol: &str) {
+		self.protocols.retain(|p| p != protocol);
 	}
 }
 
@@ -107,30 +400,67 @@ impl Serialize for PeerInfo {
 	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
 		where S: Serializer
 	{
-		let mut s = serializer.serialize_struct("PeerInfo", 2)?;
+		let mut s = serializer.serialize_struct("PeerInfo", 7)?;
 		s.serialize_field(
 			"addrs",
 			&self.addrs
 			     .iter()
-			     .map(|&(ref addr, ref expires)| {
-				let addr = addr.to_bytes();
-				let from_epoch = expires.duration_since(UNIX_EPOCH)
-					// This `unwrap_or` case happens if the user has their system time set to
-					// before EPOCH. Times-to-live will be be longer than expected, but it's a very
-					// improbable corner case and is not attackable in any way, so we don't really
-					// care.
-				    .unwrap_or(Duration::new(0, 0));
-				let secs = from_epoch.as_secs()
-				                     .saturating_mul(1_000)
-				                     .saturating_add(from_epoch.subsec_nanos() as u64 / 1_000_000);
-				(addr, secs)
-			})
+			     .map(|info| SerializedAddr {
+				     addr: info.addr.to_string(),
+				     expires: millis_since_epoch(&info.expires),
+				     source: info.source,
+				     last_success: info.last_success.as_ref().map(millis_since_epoch),
+				     failures: info.failures,
+				     certified: info.certified,
+			     })
 			     .collect::<Vec<_>>(),
 		)?;
+		s.serialize_field(
+			"ban",
+			&self.ban
+			     .as_ref()
+			     .map(|ban| (&ban.reason, &ban.issuer, millis_since_epoch(&ban.expires))),
+		)?;
+		s.serialize_field("protocols", &self.protocols)?;
+		s.serialize_field("public_key", &self.public_key)?;
+		s.serialize_field("latency", &self.latency.map(|latency| {
+			latency.as_secs()
+			       .saturating_mul(1_000_000)
+			       .saturating_add(latency.subsec_nanos() as u64 / 1_000)
+		}))?;
+		s.serialize_field("last_seen", &self.last_seen.as_ref().map(millis_since_epoch))?;
+		s.serialize_field("signed_record", &self.signed_record)?;
 		s.end()
 	}
 }
 
+// Serialized form of an address and its metadata. Times are in milliseconds since the UNIX epoch.
+#[derive(Serialize, Deserialize)]
+struct SerializedAddr {
+	addr: String,
+	expires: u64,
+	#[serde(default)]
+	source: AddrSource,
+	#[serde(default)]
+	last_success: Option<u64>,
+	#[serde(default)]
+	failures: u32,
+	#[serde(default)]
+	certified: bool,
+}
+
+// Turns a `SystemTime` into a number of milliseconds since the UNIX epoch.
+pub(crate) fn millis_since_epoch(time: &SystemTime) -> u64 {
+	let from_epoch = time.duration_since(UNIX_EPOCH)
+		// This `unwrap_or` case happens if the user has their system time set to before EPOCH.
+		// Times-to-live will be be longer than expected, but it's a very improbable corner case
+		// and is not attackable in any way, so we don't really care.
+		.unwrap_or(Duration::new(0, 0));
+	from_epoch.as_secs()
+	          .saturating_mul(1_000)
+	          .saturating_add(from_epoch.subsec_nanos() as u64 / 1_000_000)
+}
+
 impl<'de> Deserialize<'de> for PeerInfo {
 	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
 		where D: Deserializer<'de>
@@ -139,26 +469,83 @@ impl<'de> Deserialize<'de> for PeerInfo {
 		let interm = {
 			#[derive(Deserialize)]
 			struct Interm {
-				addrs: Vec<(String, u64)>,
+				addrs: Vec<IntermAddr>,
+				// Files written before bans were introduced don't have this field.
+				#[serde(default)]
+				ban: Option<(String, String, u64)>,
+				// Files written before protocols were introduced don't have this field.
+				#[serde(default)]
+				protocols: Vec<String>,
+				// Files written before public keys were introduced don't have this field.
+				#[serde(default)]
+				public_key: Option<Vec<u8>>,
+				// Files written before the latency and last seen timestamp were introduced don't
+				// have these fields. The latency is in microseconds.
+				#[serde(default)]
+				latency: Option<u64>,
+				#[serde(default)]
+				last_seen: Option<u64>,
+				// Files written before signed peer records were introduced don't have this field.
+				#[serde(default)]
+				signed_record: Option<(u64, Vec<u8>)>,
+			}
+			// Files written before addresses had metadata only contain the address and the
+			// moment when it expires.
+			#[derive(Deserialize)]
+			#[serde(untagged)]
+			enum IntermAddr {
+				Legacy(String, u64),
+				WithMetadata(SerializedAddr),
 			}
 			Interm::deserialize(deserializer)?
 		};
 
 		let addrs = {
 			let mut out = Vec::with_capacity(interm.addrs.len());
-			for (addr, since_epoch) in interm.addrs {
-				let addr = match addr.parse::<Multiaddr>() {
+			for addr in interm.addrs {
+				let addr = match addr {
+					IntermAddr::Legacy(addr, expires) => SerializedAddr {
+						addr: addr,
+						expires: expires,
+						source: AddrSource::Unknown,
+						last_success: None,
+						failures: 0,
+						certified: false,
+					},
+					IntermAddr::WithMetadata(addr) => addr,
+				};
+				let multiaddr = match addr.addr.parse::<Multiaddr>() {
 					Ok(a) => a,
 					Err(err) => return Err(DeserializerError::custom(err)),
 				};
-				let expires = UNIX_EPOCH + Duration::from_millis(since_epoch);
-				out.push((addr, expires));
+				out.push(AddrInfo {
+					addr: multiaddr,
+					expires: UNIX_EPOCH + Duration::from_millis(addr.expires),
+					source: addr.source,
+					last_success: addr.last_success.map(|t| UNIX_EPOCH + Duration::from_millis(t)),
+					failures: addr.failures,
+					certified: addr.certified,
+				});
 			}
 			out
 		};
 
+		let ban = interm.ban.map(|(reason, issuer, expires)| Ban {
+			reason: reason,
+			issuer: issuer,
+			expires: UNIX_EPOCH + Duration::from_millis(expires),
+		});
+
 		Ok(PeerInfo {
 			addrs: addrs,
+			ban: ban,
+			protocols: interm.protocols,
+			public_key: interm.public_key,
+			latency: interm.latency.map(|us| {
+				Duration::new(us / 1_000_000, (us % 1_000_000) as u32 * 1_000)
+			}),
+			last_seen: interm.last_seen.map(|t| UNIX_EPOCH + Duration::from_millis(t)),
+			signed_record: interm.signed_record,
 		})
 	}
 }
@@ -174,3 +561,32 @@ impl PartialOrd for PeerInfo {
 		None
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use peer_info::{AddAddrBehaviour, AddrSource, PeerInfo};
+	use serde_json;
+
+	#[test]
+	fn metadata_roundtrip() {
+		let mut info = PeerInfo::new();
+		let addr = "/ip4/0.0.0.0/tcp/0".parse().unwrap();
+		let behaviour = AddAddrBehaviour::IgnoreTtlIfInferior;
+		info.add_addr(addr, ::ttl::temporary(), AddrSource::Identify, behaviour);
+		info.report_dial_failure(&"/ip4/0.0.0.0/tcp/0".parse().unwrap());
+
+		// Times are serialized with a precision of one millisecond, so we compare the serialized
+		// forms rather than the `PeerInfo`s themselves.
+		let serialized = serde_json::to_string(&info).unwrap();
+		let reloaded = serde_json::from_str::<PeerInfo>(&serialized).unwrap();
+		assert_eq!(serde_json::to_string(&reloaded).unwrap(), serialized);
+		assert!(serialized.contains("Identify"));
+	}
+
+	#[test]
+	fn load_legacy_addrs() {
+		let json = r#"{"addrs": [["/ip4/0.0.0.0/tcp/0", 4102444800000]], "ban": null}"#;
+		let info = serde_json::from_str::<PeerInfo>(json).unwrap();
+		assert_eq!(info.addrs().count(), 1);
+	}
+}
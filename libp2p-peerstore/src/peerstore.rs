@@ -18,8 +18,19 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use {PeerId, TTL};
+use {AddrSource, Ban, PeerId, TTL};
 use multiaddr::Multiaddr;
+use events::PeerstoreEvent;
+use futures::sync::mpsc;
+use libp2p_identity_core::{Error as IdentityError, PeerRecordRef};
+use libp2p_identity_core::{SignatureVerifier, SignedRecordRef};
+use libp2p_swarm::{BanList, ClockSkew};
+use multiaddr::AddrComponent;
+use peer_info::millis_since_epoch;
+use query::{PeersMatching, PeersSupporting};
+use serde_json;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Implemented on objects that store peers.
 ///
@@ -47,6 +58,194 @@ pub trait Peerstore {
 	/// any time. If that is the case, you have to take into account that this is only an
 	/// indication.
 	fn peers(self) -> Self::PeersIter;
+
+	/// Returns a stream of the modifications that happen in this peer store from now on, such
+	/// as peers or addresses being added, so that other components can react to them without
+	/// polling the peer store.
+	///
+	/// The stream is unbounded, so it should be processed in a timely fashion.
+	fn subscribe(self) -> mpsc::UnboundedReceiver<PeerstoreEvent>;
+
+	/// Adds many peers and their addresses at once, as if `add_addr_with_source` had been called
+	/// for each address.
+	///
+	/// This is faster than adding the peers one by one. The `MemoryPeerstore` locks each of its
+	/// shards once, the `JsonPeerstore` locks the entry of each peer once, and the `SledPeerstore`
+	/// writes the whole batch to the database at once. A peer can appear multiple times.
+	fn add_peers<I>(self, peers: I, ttl: TTL, source: AddrSource)
+		where Self: Sized + Copy,
+		      I: IntoIterator<Item = (PeerId, Vec<Multiaddr>)>
+	{
+		for (peer_id, addrs) in peers {
+			let mut peer = self.peer_or_create(&peer_id);
+			for addr in addrs {
+				peer.add_addr_with_source(addr, ttl, source);
+			}
+		}
+	}
+
+	/// Returns the list of peers that are currently banned, alongside with their ban.
+	fn banned_peers(self) -> Vec<(PeerId, Ban)>
+		where Self: Sized + Copy
+	{
+		self.peers()
+			.filter_map(|peer_id| {
+				let ban = self.peer(&peer_id)?.ban()?;
+				Some((peer_id, ban))
+			})
+			.collect()
+	}
+
+	/// Bans in `ban_list` the peers that are banned in this peer store, until their ban expires.
+	/// Returns the number of peers that have been banned.
+	///
+	/// Call this with the `BanList` of the swarm when the node starts, so that the bans stored
+	/// by a persistent peer store are enforced again after a restart.
+	fn restore_bans(self, ban_list: &BanList) -> usize
+		where Self: Sized + Copy
+	{
+		let now = SystemTime::now();
+		let mut restored = 0;
+		for (peer_id, ban) in self.banned_peers() {
+			match ban.expires.duration_since(now) {
+				Ok(remaining) => {
+					ban_list.ban_peer(peer_id, remaining);
+					restored += 1;
+				},
+				// The ban expired in the meantime.
+				Err(_) => (),
+			}
+		}
+		restored
+	}
+
+	/// Returns an iterator to the peers that are known to support the given protocol.
+	#[inline]
+	fn peers_supporting<'p>(self, protocol: &'p str) -> PeersSupporting<'p, Self>
+		where Self: Sized + Copy
+	{
+		PeersSupporting::new(self, protocol)
+	}
+
+	/// Returns an iterator to the peers for which `predicate` returns true.
+	///
+	/// The predicate is called with an access to each peer in turn, for example
+	/// `peers_matching(|peer| peer.latency().is_some() && !peer.is_banned())`.
+	#[inline]
+	fn peers_matching<F>(self, predicate: F) -> PeersMatching<Self, F>
+		where Self: Sized + Copy,
+		      F: FnMut(&Self::PeerAccess) -> bool
+	{
+		PeersMatching::new(self, predicate)
+	}
+
+	/// Removes the expired addresses and bans of all the peers. Returns the number of addresses
+	/// that were removed.
+	///
+	/// Expired addresses are never returned by `PeerAccess::addrs`, but they are still stored
+	/// until this method is called. It should therefore be called from time to time, so that
+	/// stale addresses don't accumulate forever.
+	fn collect_garbage(self) -> usize
+		where Self: Sized + Copy
+	{
+		self.peers()
+			.filter_map(|peer_id| self.peer(&peer_id))
+			.map(|mut peer| peer.collect_garbage())
+			.sum()
+	}
+
+	/// Writes the list of peers, with their addresses and their ban, to `writer` as
+	/// human-readable JSON.
+	///
+	/// The output can be loaded back with `import`, possibly in another peer store.
+	fn export<W>(self, writer: W) -> Result<(), IoError>
+		where Self: Sized + Copy,
+		      W: Write
+	{
+		let peers = self.peers()
+			.filter_map(|peer_id| {
+				let peer = self.peer(&peer_id)?;
+				Some(ExportedPeer {
+					peer_id: peer_id.to_base58(),
+					addrs: peer.addrs().map(|addr| addr.to_string()).collect(),
+					ban: peer.ban().map(|ban| ExportedBan {
+						expires: millis_since_epoch(&ban.expires),
+						reason: ban.reason,
+						issuer: ban.issuer,
+					}),
+				})
+			})
+			.collect::<Vec<_>>();
+
+		serde_json::to_writer_pretty(writer, &peers)
+			.map_err(|err| IoError::new(IoErrorKind::Other, err))
+	}
+
+	/// Loads peers from JSON produced by `export`, or written by hand. Returns the number of
+	/// peers that were loaded.
+	///
+	/// The addresses are added with the given `ttl`, as if `add_addr` had been called. Bans that
+	/// have already expired are ignored. The `ban` field of each peer is optional, which makes
+	/// it easy to write a list of bootstrap peers by hand:
+	///
+	/// ```json
+	/// [
+	///     {
+	///         "peer_id": "QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ",
+	///         "addrs": ["/ip4/104.131.131.82/tcp/4001"]
+	///     }
+	/// ]
+	/// ```
+	///
+	/// If the input is invalid, an error is returned and the peer store isn't modified.
+	fn import<R>(self, reader: R, ttl: TTL) -> Result<usize, IoError>
+		where Self: Sized + Copy,
+		      R: Read
+	{
+		let peers: Vec<ExportedPeer> = serde_json::from_reader(reader)
+			.map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+
+		// We parse everything before touching the peer store, so that an invalid input doesn't
+		// result in a partial import.
+		let mut parsed = Vec::with_capacity(peers.len());
+		for peer in peers {
+			let peer_id = PeerId::from_base58(&peer.peer_id)
+				.map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+			let mut addrs = Vec::with_capacity(peer.addrs.len());
+			for addr in peer.addrs {
+				match addr.parse::<Multiaddr>() {
+					Ok(addr) => addrs.push(addr),
+					Err(_) => {
+						let msg = format!("invalid multiaddress: {}", addr);
+						return Err(IoError::new(IoErrorKind::InvalidData, msg));
+					}
+				}
+			}
+			parsed.push((peer_id, addrs, peer.ban));
+		}
+
+		let num_peers = parsed.len();
+		let mut bans = Vec::new();
+		let addrs = parsed.into_iter()
+			.map(|(peer_id, addrs, ban)| {
+				if let Some(ban) = ban {
+					bans.push((peer_id.clone(), ban));
+				}
+				(peer_id, addrs)
+			})
+			.collect::<Vec<_>>();
+		self.add_peers(addrs, ttl, AddrSource::Manual);
+
+		let now = SystemTime::now();
+		for (peer_id, ban) in bans {
+			let expires = UNIX_EPOCH + Duration::from_millis(ban.expires);
+			if let Ok(remaining) = expires.duration_since(now) {
+				self.peer_or_create(&peer_id).set_ban(ban.reason, ban.issuer, remaining);
+			}
+		}
+
+		Ok(num_peers)
+	}
 }
 
 /// Implemented on objects that represent an open access to a peer stored in a peer store.
@@ -62,11 +261,33 @@ pub trait PeerAccess {
 	/// >   		the moment when you get them and the moment when you process them.
 	fn addrs(&self) -> Self::AddrsIter;
 
-	/// Adds an address to a peer.
+	/// Returns all known and non-expired addresses for a given peer, alongside with the moment
+	/// when they expire.
+	fn addrs_with_expiration(&self) -> Vec<(Multiaddr, SystemTime)>;
+
+	/// Returns all known and non-expired addresses for a given peer, sorted by decreasing
+	/// likeliness that dialing them succeeds.
+	///
+	/// Certified addresses, in other words the ones of the signed peer record of the peer, come
+	/// first. Then come the addresses that failed the fewest times since they were last
+	/// successfully dialed. Ties are broken by the time of the last successful dial, then by the
+	/// source of the address. Dialers should try the addresses in this order.
+	fn addrs_by_confidence(&self) -> Self::AddrsIter;
+
+	/// Adds an address to a peer, and indicates where it was learned from.
+	///
+	/// If the manager already has this address stored and with a longer TTL, then the TTL isn't
+	/// modified. The source of the address is replaced if `source` is more trustworthy.
+	fn add_addr_with_source(&mut self, addr: Multiaddr, ttl: TTL, source: AddrSource);
+
+	/// Adds an address to a peer, whose source is unknown.
 	///
 	/// If the manager already has this address stored and with a longer TTL, then the operation
 	/// is a no-op.
-	fn add_addr(&mut self, addr: Multiaddr, ttl: TTL);
+	#[inline]
+	fn add_addr(&mut self, addr: Multiaddr, ttl: TTL) {
+		self.add_addr_with_source(addr, ttl, AddrSource::Unknown)
+	}
 
 	// Similar to calling `add_addr` multiple times in a row.
 	#[inline]
@@ -95,4 +316,207 @@ pub trait PeerAccess {
 
 	/// Removes all previously stored addresses.
 	fn clear_addrs(&mut self);
+
+	/// Records that dialing the given address succeeded. Has no effect if the address is
+	/// unknown.
+	fn report_dial_success(&mut self, addr: &Multiaddr);
+
+	/// Records that dialing the given address failed. Has no effect if the address is unknown.
+	fn report_dial_failure(&mut self, addr: &Multiaddr);
+
+	/// Removes the expired addresses and the expired ban of the peer. Returns the number of
+	/// addresses that were removed.
+	fn collect_garbage(&mut self) -> usize;
+
+	/// Returns the ban of the peer, if it is currently banned.
+	fn ban(&self) -> Option<Ban>;
+
+	/// Bans the peer for the given duration. Replaces the existing ban, if any.
+	///
+	/// The ban is stored alongside with the rest of the information about the peer. If the
+	/// peerstore is persistent, it therefore survives restarts, and `Peerstore::restore_bans`
+	/// passes it to the swarm when the node starts again.
+	fn set_ban(&mut self, reason: String, issuer: String, ttl: TTL);
+
+	/// Lifts the ban of the peer. Has no effect if the peer isn't banned.
+	fn unban(&mut self);
+
+	/// Returns the latest signed peer record of the peer as an `Envelope` protobuf message, if
+	/// any. It can be forwarded as is to other peers, who can check its signature themselves.
+	fn signed_peer_record(&self) -> Option<Vec<u8>>;
+
+	/// Stores a signed peer record of the peer, in the format of an `Envelope` protobuf message.
+	///
+	/// The signature is checked with `verifier`, and the record must be about this peer and
+	/// signed by it. Its addresses are added with the given TTL and are certified, which means
+	/// that they come first in `addrs_by_confidence`. The addresses of the previous record, if
+	/// any, lose their certification.
+	///
+	/// Returns `Ok(false)` if the sequence number of the record isn't higher than the one of the
+	/// record already stored, in which case the record is ignored.
+	fn add_signed_peer_record<V>(&mut self, envelope: Vec<u8>, ttl: TTL, verifier: &V)
+								 -> Result<bool, IdentityError>
+		where V: SignatureVerifier + ?Sized;
+
+	/// Same as `add_signed_peer_record`. If the record is stored, also measures the offset of the
+	/// clock of the peer in `clock_skew`, as the sequence number of a peer record is the moment
+	/// when it was created, in nanoseconds since the UNIX epoch.
+	///
+	/// Only use this for the records received from the peer itself, for example through the
+	/// identify protocol. The records forwarded by other nodes can be old.
+	fn add_signed_peer_record_with_skew<V>(&mut self, envelope: Vec<u8>, ttl: TTL, verifier: &V,
+										   clock_skew: &ClockSkew<PeerId>)
+										   -> Result<bool, IdentityError>
+		where V: SignatureVerifier + ?Sized
+	{
+		let created = SignedRecordRef::from_protobuf(&envelope)
+			.and_then(|signed| PeerRecordRef::from_protobuf(signed.payload_unchecked()))
+			.map(|record| (record.peer_id().as_bytes().to_vec(), record.seq()));
+
+		let added = self.add_signed_peer_record(envelope, ttl, verifier)?;
+		if let (true, Ok((peer_id, seq))) = (added, created) {
+			if let Ok(peer_id) = PeerId::from_bytes(peer_id) {
+				clock_skew.record_now(peer_id, seq_to_time(seq));
+			}
+		}
+		Ok(added)
+	}
+
+	/// Returns the public key of the peer, if known.
+	fn public_key(&self) -> Option<Vec<u8>>;
+
+	/// Stores the public key of the peer, as learned for example from the identify protocol or
+	/// the security handshake. Replaces the existing key, if any.
+	///
+	/// The key is only stored if it corresponds to the ID of the peer, in other words if
+	/// `PeerId::from_public_key(&key)` is equal to the ID of the peer. Otherwise it is returned
+	/// back as an error.
+	fn set_public_key(&mut self, key: Vec<u8>) -> Result<(), Vec<u8>>;
+
+	/// Returns the smoothed round-trip time to the peer, if any sample has been recorded.
+	///
+	/// Dialing logic can use this value to rank candidate peers.
+	fn latency(&self) -> Option<Duration>;
+
+	/// Records a round-trip time sample, as measured for example by the ping protocol. Also
+	/// marks the peer as seen.
+	///
+	/// The latency returned by `latency()` is an exponentially-weighted moving average of the
+	/// samples.
+	fn record_latency(&mut self, rtt: Duration);
+
+	/// Returns the last time we have heard from the peer, if ever.
+	fn last_seen(&self) -> Option<SystemTime>;
+
+	/// Records that we have just heard from the peer.
+	fn mark_seen(&mut self);
+
+	/// Returns the names of the protocols that the peer supports, as reported for example by the
+	/// identify protocol.
+	fn protocols(&self) -> Vec<String>;
+
+	/// Adds a protocol to the list of protocols supported by the peer. Has no effect if the
+	/// protocol is already in the list.
+	fn add_protocol(&mut self, protocol: String);
+
+	// Similar to calling `add_protocol` multiple times in a row.
+	#[inline]
+	fn add_protocols<I>(&mut self, protocols: I)
+		where I: IntoIterator<Item = String>
+	{
+		for protocol in protocols.into_iter() {
+			self.add_protocol(protocol);
+		}
+	}
+
+	/// Removes a protocol from the list of protocols supported by the peer.
+	fn remove_protocol(&mut self, protocol: &str);
+
+	// Similar to calling `remove_protocol` multiple times in a row.
+	#[inline]
+	fn remove_protocols<'p, I>(&mut self, protocols: I)
+		where I: IntoIterator<Item = &'p str>
+	{
+		for protocol in protocols.into_iter() {
+			self.remove_protocol(protocol);
+		}
+	}
+
+	/// Returns true if the peer is known to support the given protocol.
+	#[inline]
+	fn supports_protocol(&self, protocol: &str) -> bool {
+		self.protocols().iter().any(|p| p == protocol)
+	}
+
+	/// Returns true if the peer is currently banned.
+	#[inline]
+	fn is_banned(&self) -> bool {
+		self.ban().is_some()
+	}
+}
+
+// Builds a `Ban` that expires after `ttl`.
+#[inline]
+pub(crate) fn new_ban(reason: String, issuer: String, ttl: TTL) -> Ban {
+	Ban {
+		reason: reason,
+		issuer: issuer,
+		expires: SystemTime::now() + ttl,
+	}
+}
+
+// Checks that `envelope` is a signed peer record about `peer_id` and signed by it. Returns the
+// sequence number and the addresses of the record. Addresses that contain protocols we don't
+// know are skipped.
+pub(crate) fn verify_peer_record<V>(envelope: &[u8], peer_id: &PeerId, verifier: &V)
+									-> Result<(u64, Vec<Multiaddr>), IdentityError>
+	where V: SignatureVerifier + ?Sized
+{
+	let signed = SignedRecordRef::from_protobuf(envelope)?;
+	let record = PeerRecordRef::from_signed_record(&signed, verifier)?;
+	if record.peer_id().as_bytes() != peer_id.as_bytes() {
+		return Err(IdentityError::PeerIdMismatch);
+	}
+
+	let addrs = record.addrs()
+		.filter_map(|addr| multiaddr_from_bytes(addr.as_bytes()))
+		.collect::<Vec<_>>();
+
+	Ok((record.seq(), addrs))
+}
+
+// Converts the sequence number of a peer record, which is a number of nanoseconds since the UNIX
+// epoch, to the moment when the record was created.
+fn seq_to_time(seq: u64) -> SystemTime {
+	UNIX_EPOCH + Duration::new(seq / 1_000_000_000, (seq % 1_000_000_000) as u32)
+}
+
+// Decodes the binary representation of a multiaddress. Returns `None` if it is invalid or
+// contains protocols that we don't know.
+pub(crate) fn multiaddr_from_bytes(mut bytes: &[u8]) -> Option<Multiaddr> {
+	let mut components = Vec::new();
+	while !bytes.is_empty() {
+		let (component, rest) = AddrComponent::from_bytes(bytes).ok()?;
+		components.push(component);
+		bytes = rest;
+	}
+	Some(components.into_iter().collect())
+}
+
+// Format of a peer in the JSON produced by `Peerstore::export`.
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportedPeer {
+	peer_id: String,
+	addrs: Vec<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	ban: Option<ExportedBan>,
+}
+
+// Format of a ban in the JSON produced by `Peerstore::export`.
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportedBan {
+	reason: String,
+	issuer: String,
+	// Number of milliseconds since the UNIX epoch.
+	expires: u64,
 }
@@ -32,7 +32,7 @@ macro_rules! peerstore_tests {
         extern crate multihash;
         use std::thread;
         use std::time::Duration;
-        use {Peerstore, PeerAccess, PeerId};
+        use {AddrSource, Peerstore, PeerAccess, PeerId, PeerstoreEvent};
         use multiaddr::Multiaddr;
 
         #[test]
@@ -72,6 +72,28 @@ macro_rules! peerstore_tests {
             assert_eq!(addrs.count(), 0);
         }
 
+        #[test]
+        fn add_peers() {
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let peer_id1 = PeerId::from_public_key(&[1, 2, 3]);
+            let peer_id2 = PeerId::from_public_key(&[4, 5, 6]);
+            let addr1 = "/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>().unwrap();
+            let addr2 = "/ip4/0.0.0.1/tcp/0".parse::<Multiaddr>().unwrap();
+
+            let ttl = Duration::from_millis(5000);
+            peer_store.peer_or_create(&peer_id1).add_addr(addr1.clone(), ttl);
+            peer_store.add_peers(vec![
+                (peer_id1.clone(), vec![addr2.clone()]),
+                (peer_id2.clone(), vec![addr1.clone()]),
+                (peer_id2.clone(), vec![addr2.clone()]),
+            ], ttl, AddrSource::Manual);
+
+            assert_eq!(peer_store.peers().count(), 2);
+            assert_eq!(peer_store.peer(&peer_id1).unwrap().addrs().count(), 2);
+            assert_eq!(peer_store.peer(&peer_id2).unwrap().addrs().count(), 2);
+        }
+
         #[test]
         fn clear_addrs() {
             $($stmt;)*
@@ -122,5 +144,329 @@ macro_rules! peerstore_tests {
             thread::sleep(Duration::from_millis(2));
             assert_eq!(peer_store.peer(&peer_id).unwrap().addrs().count(), 1);
         }
+
+        #[test]
+        fn addrs_by_confidence() {
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let peer_id = PeerId::from_public_key(&[1, 2, 3]);
+            let mut peer = peer_store.peer_or_create(&peer_id);
+
+            let addr1 = "/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>().unwrap();
+            let addr2 = "/ip4/0.0.0.1/tcp/0".parse::<Multiaddr>().unwrap();
+            let addr3 = "/ip4/0.0.0.2/tcp/0".parse::<Multiaddr>().unwrap();
+
+            let ttl = Duration::from_millis(5000);
+            peer.add_addr_with_source(addr1.clone(), ttl, AddrSource::Dht);
+            peer.add_addr_with_source(addr2.clone(), ttl, AddrSource::Manual);
+            peer.add_addr(addr3.clone(), ttl);
+            assert_eq!(peer.addrs_by_confidence().collect::<Vec<_>>(),
+                       &[addr2.clone(), addr1.clone(), addr3.clone()]);
+
+            peer.report_dial_failure(&addr2);
+            peer.report_dial_success(&addr3);
+            assert_eq!(peer.addrs_by_confidence().collect::<Vec<_>>(), &[addr3, addr1, addr2]);
+        }
+
+        // "Signs" by returning the last byte of the message.
+        struct LastByte;
+        impl ::libp2p_identity_core::SignatureVerifier for LastByte {
+            fn verify(&self, _: &::libp2p_identity_core::PublicKeyRef, message: &[&[u8]],
+                      signature: &[u8]) -> bool
+            {
+                let message = message.concat();
+                signature == &message[message.len() - 1 ..]
+            }
+        }
+
+        // Builds an `Envelope` containing a `PeerRecord`.
+        fn envelope(public_key: &[u8], mut seq: u64, addr: &Multiaddr) -> Vec<u8> {
+            let peer_id = PeerId::from_public_key(public_key);
+            let addr = addr.to_bytes();
+            let mut record = vec![0x0a, peer_id.as_bytes().len() as u8];
+            record.extend_from_slice(peer_id.as_bytes());
+            record.push(0x10);
+            while seq >= 0x80 {
+                record.push((seq as u8) | 0x80);
+                seq >>= 7;
+            }
+            record.push(seq as u8);
+            record.extend_from_slice(&[0x1a, addr.len() as u8 + 2]);
+            record.extend_from_slice(&[0x0a, addr.len() as u8]);
+            record.extend_from_slice(&addr);
+
+            let mut out = vec![0x0a, public_key.len() as u8];
+            out.extend_from_slice(public_key);
+            out.extend_from_slice(&[0x12, 0x02, 0x03, 0x01, 0x1a, record.len() as u8]);
+            out.extend_from_slice(&record);
+            out.extend_from_slice(&[0x2a, 0x01, record[record.len() - 1]]);
+            out
+        }
+
+        #[test]
+        fn signed_peer_record() {
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let public_key = [0x08, 0x01, 0x12, 0x01, 9];
+            let peer_id = PeerId::from_public_key(&public_key);
+            let addr1 = "/ip4/1.2.3.4/tcp/4001".parse::<Multiaddr>().unwrap();
+            let addr2 = "/ip4/5.6.7.8/tcp/4001".parse::<Multiaddr>().unwrap();
+            let ttl = Duration::from_millis(5000);
+
+            let mut peer = peer_store.peer_or_create(&peer_id);
+            peer.add_addr_with_source(addr1.clone(), ttl, AddrSource::Manual);
+            let record = envelope(&public_key, 1, &addr2);
+            assert_eq!(peer.add_signed_peer_record(record.clone(), ttl, &LastByte), Ok(true));
+            assert_eq!(peer.signed_peer_record(), Some(record.clone()));
+            assert_eq!(peer.addrs_by_confidence().collect::<Vec<_>>(),
+                       &[addr2.clone(), addr1.clone()]);
+
+            // Records that aren't newer than the stored one are ignored.
+            assert_eq!(peer.add_signed_peer_record(record, ttl, &LastByte), Ok(false));
+
+            // Records signed by another peer are refused.
+            let other = envelope(&[0x08, 0x01, 0x12, 0x01, 10], 2, &addr1);
+            assert!(peer.add_signed_peer_record(other, ttl, &LastByte).is_err());
+            assert_eq!(peer.addrs_by_confidence().next(), Some(addr2));
+        }
+
+        #[test]
+        fn signed_peer_record_clock_skew() {
+            use libp2p_swarm::ClockSkew;
+            use std::time::{SystemTime, UNIX_EPOCH};
+
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let public_key = [0x08, 0x01, 0x12, 0x01, 9];
+            let peer_id = PeerId::from_public_key(&public_key);
+            let addr = "/ip4/1.2.3.4/tcp/4001".parse::<Multiaddr>().unwrap();
+            let ttl = Duration::from_millis(5000);
+            let skew = ClockSkew::new(4);
+
+            // The record has been created 10 seconds in our future.
+            let created = SystemTime::now() + Duration::from_secs(10);
+            let created = created.duration_since(UNIX_EPOCH).unwrap();
+            let seq = created.as_secs() * 1_000_000_000 + u64::from(created.subsec_nanos());
+            let record = envelope(&public_key, seq, &addr);
+
+            let mut peer = peer_store.peer_or_create(&peer_id);
+            let added = peer.add_signed_peer_record_with_skew(record.clone(), ttl, &LastByte,
+                                                              &skew);
+            assert_eq!(added, Ok(true));
+            let offset = skew.remote_offset(&peer_id).unwrap();
+            assert!(offset > 9000 && offset <= 10000);
+
+            // A record that is replayed isn't measured again.
+            let added = peer.add_signed_peer_record_with_skew(record, ttl, &LastByte, &skew);
+            assert_eq!(added, Ok(false));
+            assert_eq!(skew.network_offset().map(|(_, n)| n), Some(1));
+        }
+
+        #[test]
+        fn public_key() {
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let peer_id = PeerId::from_public_key(&[1, 2, 3]);
+
+            let mut peer = peer_store.peer_or_create(&peer_id);
+            assert!(peer.public_key().is_none());
+            assert_eq!(peer.set_public_key(vec![4, 5, 6]), Err(vec![4, 5, 6]));
+            assert!(peer.public_key().is_none());
+            assert!(peer.set_public_key(vec![1, 2, 3]).is_ok());
+            assert_eq!(peer.public_key(), Some(vec![1, 2, 3]));
+        }
+
+        #[test]
+        fn latency() {
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let peer_id = PeerId::from_public_key(&[1, 2, 3]);
+
+            let mut peer = peer_store.peer_or_create(&peer_id);
+            assert!(peer.latency().is_none());
+            assert!(peer.last_seen().is_none());
+
+            peer.record_latency(Duration::from_millis(100));
+            assert_eq!(peer.latency(), Some(Duration::from_millis(100)));
+            assert!(peer.last_seen().is_some());
+
+            peer.record_latency(Duration::from_millis(200));
+            assert_eq!(peer.latency(), Some(Duration::from_millis(110)));
+        }
+
+        #[test]
+        fn events() {
+            use futures::{Future, Stream};
+
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let events = peer_store.subscribe();
+            let peer_id = PeerId::from_public_key(&[1, 2, 3]);
+            let addr = "/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>().unwrap();
+
+            {
+                let mut peer = peer_store.peer_or_create(&peer_id);
+                peer.add_addr(addr.clone(), Duration::from_millis(0));
+                peer.add_addr(addr.clone(), Duration::from_millis(0));
+                peer.set_public_key(vec![1, 2, 3]).unwrap();
+            }
+            thread::sleep(Duration::from_millis(2));
+            peer_store.collect_garbage();
+
+            let events = events.take(4).collect().wait().unwrap();
+            assert_eq!(events, vec![
+                PeerstoreEvent::PeerAdded(peer_id.clone()),
+                PeerstoreEvent::AddrAdded { peer_id: peer_id.clone(), addr: addr.clone() },
+                PeerstoreEvent::PublicKeyLearned(peer_id.clone()),
+                PeerstoreEvent::AddrExpired { peer_id: peer_id.clone(), addr: addr },
+            ]);
+        }
+
+        #[test]
+        fn protocols() {
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let peer_id1 = PeerId::from_public_key(&[1, 2, 3]);
+            let peer_id2 = PeerId::from_public_key(&[4, 5, 6]);
+
+            peer_store.peer_or_create(&peer_id1)
+                .add_protocols(vec!["/ipfs/kad/1.0.0".to_owned(), "/ipfs/ping/1.0.0".to_owned()]);
+            peer_store.peer_or_create(&peer_id2).add_protocol("/ipfs/ping/1.0.0".to_owned());
+            assert!(peer_store.peer(&peer_id1).unwrap().supports_protocol("/ipfs/kad/1.0.0"));
+            assert!(!peer_store.peer(&peer_id2).unwrap().supports_protocol("/ipfs/kad/1.0.0"));
+            let kad_peers = peer_store.peers_supporting("/ipfs/kad/1.0.0").collect::<Vec<_>>();
+            assert_eq!(kad_peers, &[peer_id1.clone()]);
+            assert_eq!(peer_store.peers_supporting("/ipfs/ping/1.0.0").count(), 2);
+
+            peer_store.peer(&peer_id1).unwrap().remove_protocols(vec!["/ipfs/kad/1.0.0"]);
+            assert_eq!(peer_store.peer(&peer_id1).unwrap().protocols(), &["/ipfs/ping/1.0.0"]);
+            assert_eq!(peer_store.peers_supporting("/ipfs/kad/1.0.0").count(), 0);
+        }
+
+        #[test]
+        fn peers_matching() {
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let peer_id1 = PeerId::from_public_key(&[1, 2, 3]);
+            let peer_id2 = PeerId::from_public_key(&[4, 5, 6]);
+
+            peer_store.peer_or_create(&peer_id1).record_latency(Duration::from_millis(20));
+            peer_store.peer_or_create(&peer_id2).record_latency(Duration::from_millis(200));
+
+            let fast = peer_store
+                .peers_matching(|peer| peer.latency().unwrap() < Duration::from_millis(100))
+                .collect::<Vec<_>>();
+            assert_eq!(fast, &[peer_id1]);
+            assert_eq!(peer_store.peers_matching(|_| true).count(), 2);
+        }
+
+        #[test]
+        fn ban_then_unban() {
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let peer_id = PeerId::from_public_key(&[1, 2, 3]);
+
+            peer_store.peer_or_create(&peer_id)
+                .set_ban("spam".to_owned(), "admin".to_owned(), Duration::from_millis(5000));
+            assert!(peer_store.peer(&peer_id).unwrap().is_banned());
+
+            let banned = peer_store.banned_peers();
+            assert_eq!(banned.len(), 1);
+            assert_eq!(banned[0].0, peer_id);
+            assert_eq!(banned[0].1.reason, "spam");
+            assert_eq!(banned[0].1.issuer, "admin");
+
+            peer_store.peer(&peer_id).unwrap().unban();
+            assert!(!peer_store.peer(&peer_id).unwrap().is_banned());
+            assert!(peer_store.banned_peers().is_empty());
+        }
+
+        #[test]
+        fn restore_bans() {
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let peer_id = PeerId::from_public_key(&[1, 2, 3]);
+            let other_peer_id = PeerId::from_public_key(&[4, 5, 6]);
+
+            peer_store.peer_or_create(&peer_id)
+                .set_ban("spam".to_owned(), "admin".to_owned(), Duration::from_millis(5000));
+            peer_store.peer_or_create(&other_peer_id);
+
+            let ban_list = ::libp2p_swarm::BanList::new();
+            assert_eq!(peer_store.restore_bans(&ban_list), 1);
+            assert!(ban_list.is_peer_banned(&peer_id));
+            assert!(!ban_list.is_peer_banned(&other_peer_id));
+        }
+
+        #[test]
+        fn ban_expires() {
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let peer_id = PeerId::from_public_key(&[1, 2, 3]);
+
+            peer_store.peer_or_create(&peer_id)
+                .set_ban("spam".to_owned(), "admin".to_owned(), Duration::from_millis(0));
+            thread::sleep(Duration::from_millis(2));
+            assert!(!peer_store.peer(&peer_id).unwrap().is_banned());
+        }
+
+        #[test]
+        fn collect_garbage() {
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let peer_id = PeerId::from_public_key(&[1, 2, 3]);
+
+            let addr1 = "/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>().unwrap();
+            let addr2 = "/ip4/0.0.0.1/tcp/0".parse::<Multiaddr>().unwrap();
+
+            peer_store.peer_or_create(&peer_id).add_addr(addr1.clone(), ::ttl::permanent());
+            peer_store.peer_or_create(&peer_id).add_addr(addr2.clone(), Duration::from_millis(0));
+            thread::sleep(Duration::from_millis(2));
+
+            assert_eq!(peer_store.collect_garbage(), 1);
+            assert_eq!(peer_store.collect_garbage(), 0);
+            let addrs = peer_store.peer(&peer_id).unwrap().addrs().collect::<Vec<_>>();
+            assert_eq!(addrs, &[addr1]);
+        }
+
+        #[test]
+        fn export_then_import() {
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let peer_id = PeerId::from_public_key(&[1, 2, 3]);
+            let addr = "/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>().unwrap();
+
+            {
+                let mut peer = peer_store.peer_or_create(&peer_id);
+                peer.add_addr(addr.clone(), Duration::from_millis(5000));
+                peer.set_ban("spam".to_owned(), "admin".to_owned(), Duration::from_millis(5000));
+            }
+
+            let mut exported = Vec::new();
+            peer_store.export(&mut exported).unwrap();
+
+            {
+                let mut peer = peer_store.peer(&peer_id).unwrap();
+                peer.clear_addrs();
+                peer.unban();
+            }
+
+            let num = peer_store.import(&exported[..], Duration::from_millis(5000)).unwrap();
+            assert_eq!(num, 1);
+            let peer = peer_store.peer(&peer_id).unwrap();
+            assert_eq!(peer.addrs().collect::<Vec<_>>(), &[addr]);
+            assert_eq!(peer.ban().unwrap().reason, "spam");
+        }
+
+        #[test]
+        fn import_invalid() {
+            $($stmt;)*
+            let peer_store = $create_peerstore;
+            let input = br#"[{"peer_id": "QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ",
+                              "addrs": ["/ip4/0.0.0.0/tcp/0"]},
+                             {"peer_id": "not a peer id", "addrs": []}]"#;
+            assert!(peer_store.import(&input[..], Duration::from_millis(5000)).is_err());
+            assert_eq!(peer_store.peers().count(), 0);
+        }
     };
 }
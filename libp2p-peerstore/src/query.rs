@@ -0,0 +1,113 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Iterators returned by the query methods of the `Peerstore` trait.
+
+use PeerId;
+use peerstore::{Peerstore, PeerAccess};
+
+/// Iterator to the peers of a peer store that match a predicate.
+///
+/// Returned by `Peerstore::peers_matching`. Each peer is only accessed when the iterator reaches
+/// it, and the access is released before moving on to the next peer.
+pub struct PeersMatching<P, F>
+	where P: Peerstore
+{
+	store: P,
+	peers: P::PeersIter,
+	predicate: F,
+}
+
+impl<P, F> PeersMatching<P, F>
+	where P: Peerstore + Copy
+{
+	#[inline]
+	pub(crate) fn new(store: P, predicate: F) -> PeersMatching<P, F> {
+		PeersMatching {
+			store: store,
+			peers: store.peers(),
+			predicate: predicate,
+		}
+	}
+}
+
+impl<P, F> Iterator for PeersMatching<P, F>
+	where P: Peerstore + Copy,
+	      F: FnMut(&P::PeerAccess) -> bool
+{
+	type Item = PeerId;
+
+	fn next(&mut self) -> Option<PeerId> {
+		loop {
+			let peer_id = self.peers.next()?;
+			// The peer may have been removed since we listed the peers.
+			let matches = match self.store.peer(&peer_id) {
+				Some(peer) => (self.predicate)(&peer),
+				None => false,
+			};
+			if matches {
+				return Some(peer_id);
+			}
+		}
+	}
+}
+
+/// Iterator to the peers of a peer store that support a protocol.
+///
+/// Returned by `Peerstore::peers_supporting`.
+pub struct PeersSupporting<'p, P>
+	where P: Peerstore
+{
+	store: P,
+	peers: P::PeersIter,
+	protocol: &'p str,
+}
+
+impl<'p, P> PeersSupporting<'p, P>
+	where P: Peerstore + Copy
+{
+	#[inline]
+	pub(crate) fn new(store: P, protocol: &'p str) -> PeersSupporting<'p, P> {
+		PeersSupporting {
+			store: store,
+			peers: store.peers(),
+			protocol: protocol,
+		}
+	}
+}
+
+impl<'p, P> Iterator for PeersSupporting<'p, P>
+	where P: Peerstore + Copy
+{
+	type Item = PeerId;
+
+	fn next(&mut self) -> Option<PeerId> {
+		loop {
+			let peer_id = self.peers.next()?;
+			let supported = match self.store.peer(&peer_id) {
+				Some(peer) => peer.supports_protocol(self.protocol),
+				None => false,
+			};
+			if supported {
+				return Some(peer_id);
+			}
+		}
+	}
+}
@@ -0,0 +1,414 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Implementation of the `Peerstore` trait that uses an embedded `sled` database as backend.
+
+use super::TTL;
+use {AddrSource, Ban, PeerId};
+use multiaddr::Multiaddr;
+use events::{EventsNotifier, PeerstoreEvent};
+use futures::sync::mpsc;
+use libp2p_identity_core::{Error as IdentityError, SignatureVerifier};
+use peer_info::{PeerInfo, AddAddrBehaviour};
+use peerstore::{new_ban, verify_peer_record, Peerstore, PeerAccess};
+use serde_json;
+use sled;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::iter;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+use std::vec::IntoIter as VecIntoIter;
+
+/// Peerstore backend that uses an embedded `sled` key-value database.
+///
+/// Contrary to `JsonPeerstore`, the content of the database doesn't need to be loaded in memory
+/// and modifications are written to the disk as soon as a `SledPeerstoreAccess` is destroyed.
+pub struct SledPeerstore {
+	db: sled::Db,
+	// Only one `SledPeerstoreAccess` can exist at any given time, so that two accesses to the
+	// same peer don't overwrite each other's modifications.
+	lock: Mutex<()>,
+	events: EventsNotifier,
+}
+
+impl SledPeerstore {
+	/// Opens a peerstore tied to a `sled` database at the given path.
+	///
+	/// If the database exists, this function will open it. Otherwise it is created.
+	#[inline]
+	pub fn open<P>(path: P) -> Result<SledPeerstore, IoError>
+		where P: AsRef<Path>
+	{
+		let db = sled::open(path).map_err(sled_to_io_error)?;
+		Ok(SledPeerstore {
+			db: db,
+			lock: Mutex::new(()),
+			events: EventsNotifier::new(),
+		})
+	}
+
+	/// Flushes the content of the peer store to the disk.
+	///
+	/// Modifications are normally flushed in the background by `sled`. Call this method if you
+	/// need the guarantee that they have reached the disk.
+	#[inline]
+	pub fn flush(&self) -> Result<(), IoError> {
+		self.db.flush().map(|_| ()).map_err(sled_to_io_error)
+	}
+
+	// Loads the information about a peer from the database. Entries that can't be decoded are
+	// treated as if they didn't exist.
+	fn load(&self, key: &[u8]) -> Option<PeerInfo> {
+		match self.db.get(key) {
+			Ok(Some(value)) => serde_json::from_slice(&value).ok(),
+			_ => None,
+		}
+	}
+}
+
+impl<'a> Peerstore for &'a SledPeerstore {
+	type PeerAccess = SledPeerstoreAccess<'a>;
+	type PeersIter = VecIntoIter<PeerId>;
+
+	fn peer(self, peer_id: &PeerId) -> Option<Self::PeerAccess> {
+		let lock = self.lock.lock().unwrap();
+		let key = peer_id.as_bytes().to_owned();
+		let info = self.load(&key)?;
+		Some(SledPeerstoreAccess {
+			store: self,
+			_lock: lock,
+			peer_id: peer_id.clone(),
+			key: key,
+			info: info,
+		})
+	}
+
+	fn peer_or_create(self, peer_id: &PeerId) -> Self::PeerAccess {
+		let lock = self.lock.lock().unwrap();
+		let key = peer_id.as_bytes().to_owned();
+		let info = match self.load(&key) {
+			Some(info) => info,
+			None => {
+				self.events.notify(PeerstoreEvent::PeerAdded(peer_id.clone()));
+				PeerInfo::new()
+			},
+		};
+		SledPeerstoreAccess {
+			store: self,
+			_lock: lock,
+			peer_id: peer_id.clone(),
+			key: key,
+			info: info,
+		}
+	}
+
+	fn peers(self) -> Self::PeersIter {
+		// We filter out invalid elements and ignore I/O errors. This can happen if the database
+		// was corrupted.
+		self.db
+			.iter()
+			.keys()
+			.filter_map(|key| key.ok())
+			.filter_map(|key| PeerId::from_bytes(key.to_vec()).ok())
+			.collect::<Vec<_>>()
+			.into_iter()
+	}
+
+	fn add_peers<I>(self, peers: I, ttl: TTL, source: AddrSource)
+		where I: IntoIterator<Item = (PeerId, Vec<Multiaddr>)>
+	{
+		let _lock = self.lock.lock().unwrap();
+
+		// We first apply all the modifications in memory, then write all the peers to the
+		// database in a single batch.
+		let mut infos = HashMap::new();
+		for (peer_id, addrs) in peers {
+			let info = match infos.entry(peer_id.clone()) {
+				Entry::Occupied(entry) => entry.into_mut(),
+				Entry::Vacant(entry) => {
+					let info = match self.load(peer_id.as_bytes()) {
+						Some(info) => info,
+						None => {
+							self.events.notify(PeerstoreEvent::PeerAdded(peer_id.clone()));
+							PeerInfo::new()
+						},
+					};
+					entry.insert(info)
+				},
+			};
+
+			for addr in addrs {
+				let behaviour = AddAddrBehaviour::IgnoreTtlIfInferior;
+				if info.add_addr(addr.clone(), ttl, source, behaviour) {
+					self.events.notify(PeerstoreEvent::AddrAdded {
+						peer_id: peer_id.clone(),
+						addr: addr,
+					});
+				}
+			}
+		}
+
+		let mut batch = sled::Batch::default();
+		for (peer_id, info) in infos {
+			if let Ok(value) = serde_json::to_vec(&info) {
+				batch.insert(peer_id.as_bytes(), value);
+			}
+		}
+		let _ = self.db.apply_batch(batch);
+	}
+
+	#[inline]
+	fn subscribe(self) -> mpsc::UnboundedReceiver<PeerstoreEvent> {
+		self.events.subscribe()
+	}
+}
+
+/// Access to a peer of a `SledPeerstore`.
+///
+/// The modifications are written to the database when this object is destroyed.
+pub struct SledPeerstoreAccess<'a> {
+	store: &'a SledPeerstore,
+	_lock: MutexGuard<'a, ()>,
+	peer_id: PeerId,
+	key: Vec<u8>,
+	info: PeerInfo,
+}
+
+impl<'a> PeerAccess for SledPeerstoreAccess<'a> {
+	type AddrsIter = VecIntoIter<Multiaddr>;
+
+	#[inline]
+	fn addrs(&self) -> Self::AddrsIter {
+		self.info.addrs().cloned().collect::<Vec<_>>().into_iter()
+	}
+
+	#[inline]
+	fn addrs_with_expiration(&self) -> Vec<(Multiaddr, SystemTime)> {
+		self.info.addrs_with_expiration()
+	}
+
+	#[inline]
+	fn addrs_by_confidence(&self) -> Self::AddrsIter {
+		self.info.addrs_by_confidence().into_iter()
+	}
+
+	fn add_addr_with_source(&mut self, addr: Multiaddr, ttl: TTL, source: AddrSource) {
+		let behaviour = AddAddrBehaviour::IgnoreTtlIfInferior;
+		if self.info.add_addr(addr.clone(), ttl, source, behaviour) {
+			self.store.events.notify(PeerstoreEvent::AddrAdded {
+				peer_id: self.peer_id.clone(),
+				addr: addr,
+			});
+		}
+	}
+
+	fn set_addr_ttl(&mut self, addr: Multiaddr, ttl: TTL) {
+		let behaviour = AddAddrBehaviour::OverwriteTtl;
+		if self.info.add_addr(addr.clone(), ttl, AddrSource::Unknown, behaviour) {
+			self.store.events.notify(PeerstoreEvent::AddrAdded {
+				peer_id: self.peer_id.clone(),
+				addr: addr,
+			});
+		}
+	}
+
+	#[inline]
+	fn clear_addrs(&mut self) {
+		self.info.set_addrs(iter::empty());
+	}
+
+	#[inline]
+	fn report_dial_success(&mut self, addr: &Multiaddr) {
+		self.info.report_dial_success(addr);
+	}
+
+	#[inline]
+	fn report_dial_failure(&mut self, addr: &Multiaddr) {
+		self.info.report_dial_failure(addr);
+	}
+
+	fn collect_garbage(&mut self) -> usize {
+		let expired = self.info.remove_expired();
+		let num_expired = expired.len();
+		for addr in expired {
+			self.store.events.notify(PeerstoreEvent::AddrExpired {
+				peer_id: self.peer_id.clone(),
+				addr: addr,
+			});
+		}
+		num_expired
+	}
+
+	#[inline]
+	fn protocols(&self) -> Vec<String> {
+		self.info.protocols().to_vec()
+	}
+
+	#[inline]
+	fn add_protocol(&mut self, protocol: String) {
+		self.info.add_protocol(protocol);
+	}
+
+	#[inline]
+	fn remove_protocol(&mut self, protocol: &str) {
+		self.info.remove_protocol(protocol);
+	}
+
+	#[inline]
+	fn signed_peer_record(&self) -> Option<Vec<u8>> {
+		self.info.signed_record().map(|envelope| envelope.to_owned())
+	}
+
+	fn add_signed_peer_record<V>(&mut self, envelope: Vec<u8>, ttl: TTL, verifier: &V)
+								 -> Result<bool, IdentityError>
+		where V: SignatureVerifier + ?Sized
+	{
+		let (seq, addrs) = verify_peer_record(&envelope, &self.peer_id, verifier)?;
+		match self.info.set_signed_record(envelope, seq, addrs, ttl) {
+			Some(added) => {
+				for addr in added {
+				self.store.events.notify(PeerstoreEvent::AddrAdded {
+					peer_id: self.peer_id.clone(),
+					addr: addr,
+				});
+				}
+				Ok(true)
+			},
+			None => Ok(false),
+		}
+	}
+
+	#[inline]
+	fn public_key(&self) -> Option<Vec<u8>> {
+		self.info.public_key().map(|key| key.to_owned())
+	}
+
+	fn set_public_key(&mut self, key: Vec<u8>) -> Result<(), Vec<u8>> {
+		if PeerId::from_public_key(&key) != self.peer_id {
+			return Err(key);
+		}
+		let changed = self.info.public_key() != Some(&key[..]);
+		self.info.set_public_key(key);
+		if changed {
+			self.store.events.notify(PeerstoreEvent::PublicKeyLearned(self.peer_id.clone()));
+		}
+		Ok(())
+	}
+
+	#[inline]
+	fn latency(&self) -> Option<Duration> {
+		self.info.latency()
+	}
+
+	#[inline]
+	fn record_latency(&mut self, rtt: Duration) {
+		self.info.record_latency(rtt);
+	}
+
+	#[inline]
+	fn last_seen(&self) -> Option<SystemTime> {
+		self.info.last_seen()
+	}
+
+	#[inline]
+	fn mark_seen(&mut self) {
+		self.info.mark_seen();
+	}
+
+	#[inline]
+	fn ban(&self) -> Option<Ban> {
+		self.info.ban().cloned()
+	}
+
+	#[inline]
+	fn set_ban(&mut self, reason: String, issuer: String, ttl: TTL) {
+		self.info.set_ban(Some(new_ban(reason, issuer, ttl)));
+	}
+
+	#[inline]
+	fn unban(&mut self) {
+		self.info.set_ban(None);
+	}
+}
+
+impl<'a> Drop for SledPeerstoreAccess<'a> {
+	fn drop(&mut self) {
+		// As with the other backends, the information in the peer store isn't considered
+		// critical. If writing fails, the modifications are simply lost.
+		if let Ok(value) = serde_json::to_vec(&self.info) {
+			let _ = self.store.db.insert(&self.key[..], value);
+		}
+	}
+}
+
+// Turns an error produced by `sled` into an `IoError`.
+#[inline]
+fn sled_to_io_error(err: sled::Error) -> IoError {
+	IoError::new(IoErrorKind::Other, err)
+}
+
+#[cfg(test)]
+mod tests {
+	extern crate tempdir;
+	peerstore_tests!(
+		{::sled_peerstore::SledPeerstore::open(temp_dir.path()).unwrap()}
+		{let temp_dir = self::tempdir::TempDir::new("peerstore").unwrap()}
+	);
+
+	#[test]
+	fn survives_reopen() {
+		let temp_dir = self::tempdir::TempDir::new("peerstore").unwrap();
+		let peer_id = PeerId::from_public_key(&[1, 2, 3]);
+		let addr = "/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>().unwrap();
+
+		{
+			let peer_store = ::sled_peerstore::SledPeerstore::open(temp_dir.path()).unwrap();
+			peer_store.peer_or_create(&peer_id).add_addr(addr.clone(), Duration::from_secs(60));
+			peer_store.flush().unwrap();
+		}
+
+		let peer_store = ::sled_peerstore::SledPeerstore::open(temp_dir.path()).unwrap();
+		let addrs = peer_store.peer(&peer_id).unwrap().addrs().collect::<Vec<_>>();
+		assert_eq!(addrs, &[addr]);
+	}
+
+	#[test]
+	fn bans_survive_restart() {
+		let temp_dir = self::tempdir::TempDir::new("peerstore").unwrap();
+		let peer_id = PeerId::from_public_key(&[1, 2, 3]);
+
+		{
+			let peer_store = ::sled_peerstore::SledPeerstore::open(temp_dir.path()).unwrap();
+			peer_store.peer_or_create(&peer_id)
+				.set_ban("spam".to_owned(), "admin".to_owned(), Duration::from_secs(3600));
+			peer_store.flush().unwrap();
+		}
+
+		// The swarm of the restarted node starts with an empty ban list.
+		let peer_store = ::sled_peerstore::SledPeerstore::open(temp_dir.path()).unwrap();
+		let ban_list = ::libp2p_swarm::BanList::new();
+		assert!(!ban_list.is_peer_banned(&peer_id));
+		assert_eq!(peer_store.restore_bans(&ban_list), 1);
+		assert!(ban_list.is_peer_banned(&peer_id));
+	}
+}
@@ -0,0 +1,57 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Presets for the time-to-live of the addresses stored in a peer store.
+//!
+//! Which preset to use depends on how the address was learned. For example an address that we
+//! are currently connected to should be added with `connected()`, and its TTL should be lowered
+//! to `recently_connected()` with `set_addr_ttl` once the connection is closed.
+
+use TTL;
+use std::time::Duration;
+
+/// TTL of addresses that should never expire, such as the ones of bootstrap nodes.
+#[inline]
+pub fn permanent() -> TTL {
+	// We don't use the maximum value of `Duration`, as adding it to the current time overflows.
+	Duration::from_secs(100 * 365 * 24 * 3600)
+}
+
+/// TTL of the addresses of the peers we are currently connected to.
+///
+/// It is slightly lower than `permanent()`, so that `add_addr` with a `permanent()` TTL
+/// overrides it.
+#[inline]
+pub fn connected() -> TTL {
+	permanent() - Duration::from_secs(1)
+}
+
+/// TTL of the addresses of the peers we have recently been connected to.
+#[inline]
+pub fn recently_connected() -> TTL {
+	Duration::from_secs(10 * 60)
+}
+
+/// TTL of addresses that we have learned about but never successfully connected to, for
+/// example addresses reported by other peers.
+#[inline]
+pub fn temporary() -> TTL {
+	Duration::from_secs(2 * 60)
+}
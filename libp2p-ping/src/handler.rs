@@ -0,0 +1,336 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains `PeriodicPingHandler`, which periodically pings the remote on a connection.
+
+use futures::{Async, Future, Poll};
+use libp2p_swarm::{NodeHandlerEndpoint, ProtocolsHandler, ProtocolsHandlerEvent};
+use libp2p_swarm::time::{Clock, Instant};
+use std::error::Error;
+use std::io::Error as IoError;
+use std::marker::PhantomData;
+use std::time::Duration;
+use tokio_io::{AsyncRead, AsyncWrite};
+use {Ping, Pinger};
+
+/// Event produced by a `PeriodicPingHandler`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PingEvent {
+	/// The remote answered a ping. Contains the round-trip time.
+	Pong(Duration),
+	/// The remote didn't answer a ping before the timeout, or we failed to open a substream to
+	/// send it.
+	Failure,
+}
+
+/// Implementation of `ProtocolsHandler` that pings the remote at a regular interval, and answers
+/// the pings of the remote.
+///
+/// Each ping produces a `PingEvent`, which contains the round-trip time if the remote answered.
+/// After a configurable number of consecutive failures, the handler finishes, which closes the
+/// connection.
+///
+/// The timers and the round-trip times use a `Clock`, which is usually a tokio-core `Handle`.
+pub struct PeriodicPingHandler<S, C>
+	where C: Clock
+{
+	clock: C,
+	interval: Duration,
+	timeout: Duration,
+	max_failures: u32,
+	// Substream that we opened, with the future that processes it.
+	outbound: Option<(Pinger, Box<Future<Item = (), Error = IoError> + Send>)>,
+	// True if we asked for an outbound substream and are waiting for it.
+	outbound_requested: bool,
+	// True if opening an outbound substream failed and we didn't report it yet.
+	outbound_failed: bool,
+	// Futures that process the substreams opened by the remote, and answer its pings.
+	inbound: Vec<Box<Future<Item = (), Error = IoError> + Send>>,
+	// Fires when the next ping is due. `None` if a ping is due right now.
+	next_ping: Option<C::Delay>,
+	// Ping in progress, with the moment it was sent and its timeout.
+	pending_ping: Option<(Instant, Box<Future<Item = (), Error = Box<Error + Send + Sync>> + Send>,
+						  C::Delay)>,
+	// Number of pings that have failed in a row.
+	failures: u32,
+	// True if `shutdown()` has been called.
+	shutting_down: bool,
+	marker: PhantomData<S>,
+}
+
+impl<S, C> PeriodicPingHandler<S, C>
+	where C: Clock
+{
+	/// Builds a new `PeriodicPingHandler` that uses `clock` for its timers.
+	///
+	/// By default, the remote is pinged every 15 seconds, a ping fails if no answer has been
+	/// received after 20 seconds, and the connection is closed after 3 failures in a row.
+	#[inline]
+	pub fn new(clock: C) -> PeriodicPingHandler<S, C> {
+		PeriodicPingHandler {
+			clock: clock,
+			interval: Duration::from_secs(15),
+			timeout: Duration::from_secs(20),
+			max_failures: 3,
+			outbound: None,
+			outbound_requested: false,
+			outbound_failed: false,
+			inbound: Vec::new(),
+			next_ping: None,
+			pending_ping: None,
+			failures: 0,
+			shutting_down: false,
+			marker: PhantomData,
+		}
+	}
+
+	/// Sets the delay between the end of a ping and the start of the next one.
+	#[inline]
+	pub fn with_interval(mut self, interval: Duration) -> PeriodicPingHandler<S, C> {
+		self.interval = interval;
+		self
+	}
+
+	/// Sets the delay after which a ping that hasn't been answered is considered as failed.
+	#[inline]
+	pub fn with_timeout(mut self, timeout: Duration) -> PeriodicPingHandler<S, C> {
+		self.timeout = timeout;
+		self
+	}
+
+	/// Sets the number of consecutive failures after which the connection is closed.
+	///
+	/// # Panics
+	///
+	/// Panics if `max_failures` is 0.
+	#[inline]
+	pub fn with_max_failures(mut self, max_failures: u32) -> PeriodicPingHandler<S, C> {
+		assert!(max_failures >= 1, "max_failures must be at least 1");
+		self.max_failures = max_failures;
+		self
+	}
+
+	// Records a failure and schedules the next ping.
+	fn ping_failed(&mut self) -> Poll<Option<ProtocolsHandlerEvent<Ping, (), PingEvent>>, IoError> {
+		self.failures += 1;
+		self.next_ping = Some(self.clock.delay(self.interval));
+		Ok(Async::Ready(Some(ProtocolsHandlerEvent::Custom(PingEvent::Failure))))
+	}
+}
+
+impl<S, C> ProtocolsHandler for PeriodicPingHandler<S, C>
+	where S: AsyncRead + AsyncWrite + Send + 'static,
+		  C: Clock
+{
+	type InEvent = ();
+	type OutEvent = PingEvent;
+	type Substream = S;
+	type Protocol = Ping;
+	type OutboundOpenInfo = ();
+
+	#[inline]
+	fn listen_protocol(&self) -> Ping {
+		Ping
+	}
+
+	fn inject_fully_negotiated(&mut self,
+							   (pinger, ponger): (Pinger,
+												  Box<Future<Item = (), Error = IoError> + Send>),
+							   endpoint: NodeHandlerEndpoint<()>)
+	{
+		match endpoint {
+			NodeHandlerEndpoint::Dialer(()) => {
+				self.outbound_requested = false;
+				self.outbound = Some((pinger, ponger));
+			},
+			NodeHandlerEndpoint::Listener => self.inbound.push(ponger),
+		}
+	}
+
+	#[inline]
+	fn inject_event(&mut self, _: ()) {}
+
+	#[inline]
+	fn inject_dial_upgrade_error(&mut self, _: (), _: &IoError) {
+		self.outbound_requested = false;
+		self.outbound_failed = true;
+	}
+
+	#[inline]
+	fn shutdown(&mut self) {
+		self.shutting_down = true;
+	}
+
+	fn poll(&mut self) -> Poll<Option<ProtocolsHandlerEvent<Ping, (), PingEvent>>, IoError> {
+		if self.shutting_down || self.failures >= self.max_failures {
+			return Ok(Async::Ready(None));
+		}
+
+		// Answer the pings of the remote. Substreams that are closed or erroneous are dropped.
+		for n in (0 .. self.inbound.len()).rev() {
+			let mut ponger = self.inbound.swap_remove(n);
+			if let Ok(Async::NotReady) = ponger.poll() {
+				self.inbound.push(ponger);
+			}
+		}
+
+		// If the remote closed our substream, we will open a new one for the next ping.
+		let outbound_closed = match self.outbound {
+			Some((_, ref mut ponger)) => match ponger.poll() {
+				Ok(Async::NotReady) => false,
+				Ok(Async::Ready(())) | Err(_) => true,
+			},
+			None => false,
+		};
+		if outbound_closed {
+			self.outbound = None;
+		}
+
+		if self.outbound_failed {
+			self.outbound_failed = false;
+			return self.ping_failed();
+		}
+
+		loop {
+			let ping_result = match self.pending_ping {
+				Some((ref sent, ref mut ping, ref mut timeout)) => match ping.poll() {
+					Ok(Async::Ready(())) => Some(Some(self.clock.now() - *sent)),
+					Ok(Async::NotReady) => match timeout.poll()? {
+						Async::Ready(()) => Some(None),
+						Async::NotReady => return Ok(Async::NotReady),
+					},
+					// The substream has been closed while the ping was in progress.
+					Err(_) => Some(None),
+				},
+				None => None,
+			};
+
+			match ping_result {
+				Some(Some(rtt)) => {
+					self.pending_ping = None;
+					self.failures = 0;
+					self.next_ping = Some(self.clock.delay(self.interval));
+					let event = ProtocolsHandlerEvent::Custom(PingEvent::Pong(rtt));
+					return Ok(Async::Ready(Some(event)));
+				},
+				Some(None) => {
+					self.pending_ping = None;
+					return self.ping_failed();
+				},
+				None => (),
+			}
+
+			if let Some(mut next_ping) = self.next_ping.take() {
+				if let Async::NotReady = next_ping.poll()? {
+					self.next_ping = Some(next_ping);
+					return Ok(Async::NotReady);
+				}
+			}
+
+			// A ping is due. We start it and loop again in order to poll it.
+			match self.outbound {
+				Some((ref mut pinger, _)) => {
+					let timeout = self.clock.delay(self.timeout);
+					self.pending_ping = Some((self.clock.now(), pinger.ping(), timeout));
+				},
+				None if !self.outbound_requested => {
+					self.outbound_requested = true;
+					return Ok(Async::Ready(Some(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+						upgrade: Ping,
+						info: (),
+					})));
+				},
+				None => return Ok(Async::NotReady),
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	extern crate libp2p_swarm_test;
+	extern crate multiplex;
+
+	use self::libp2p_swarm_test::{MockClock, Scenario};
+	use self::multiplex::MultiplexConfig;
+	use super::{PeriodicPingHandler, PingEvent};
+	use futures::{future, Stream};
+	use libp2p_swarm::{HandledNode, ProtocolsHandler, ProtocolsHandlerEvent, SwarmEvent};
+	use parking_lot::Mutex;
+	use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+	use std::sync::Arc;
+	use std::time::Duration;
+	use tokio_core::net::TcpStream;
+	use tokio_core::reactor::Core;
+
+	#[test]
+	fn reports_rtt() {
+		let clock = MockClock::new();
+		let events = Arc::new(Mutex::new(Vec::new()));
+
+		let handler_clock = clock.clone();
+		let handler_events = events.clone();
+		Scenario::new(MultiplexConfig::new(), move |muxer, addr| {
+			let events = handler_events.clone();
+			let handler = PeriodicPingHandler::new(handler_clock.clone());
+			HandledNode::new(muxer, addr, handler).for_each(move |event| {
+				events.lock().push(event);
+				Ok(())
+			})
+		})
+			.clock(clock)
+			.node("a")
+			.node("b")
+			.connect("a", "b")
+			.expect_event("b", "incoming connection", |event| match *event {
+				SwarmEvent::ConnectionEstablished { .. } => true,
+				_ => false,
+			})
+			// Both nodes ping each other as soon as they are connected, then once per interval.
+			.advance_time(Duration::from_secs(15))
+			.run();
+
+		// The clock doesn't move while the pings are in progress.
+		assert_eq!(*events.lock(), vec![PingEvent::Pong(Duration::new(0, 0)); 4]);
+	}
+
+	#[test]
+	fn closes_after_failures() {
+		let mut core = Core::new().unwrap();
+		let mut handler = PeriodicPingHandler::<TcpStream, _>::new(core.handle())
+			.with_interval(Duration::from_millis(10))
+			.with_max_failures(2);
+
+		for _ in 0 .. 2 {
+			match core.run(future::poll_fn(|| handler.poll())).unwrap() {
+				Some(ProtocolsHandlerEvent::OutboundSubstreamRequest { .. }) => (),
+				_ => panic!("expected a substream request"),
+			}
+			let err = IoError::new(IoErrorKind::Other, "unsupported");
+			handler.inject_dial_upgrade_error((), &err);
+			match core.run(future::poll_fn(|| handler.poll())).unwrap() {
+				Some(ProtocolsHandlerEvent::Custom(PingEvent::Failure)) => (),
+				_ => panic!("expected a failure"),
+			}
+		}
+
+		assert!(core.run(future::poll_fn(|| handler.poll())).unwrap().is_none());
+	}
+}
@@ -34,11 +34,27 @@
 //! is a future that will process the data received on the socket and will be signalled only when
 //! the connection closes.
 //!
+//! If you need the network traffic to be reproducible from one run to another (for example in
+//! simulations or tests), you can use `SeededPing` instead of `Ping`. It generates the payloads of
+//! the pings from a deterministic seed instead of the random number generator of the OS.
+//!
+//! # Periodic pings
+//!
+//! The `PeriodicPingHandler` struct implements the `ProtocolsHandler` trait of `libp2p-swarm`.
+//! Once driven by a `HandledNode`, it pings the remote at a regular interval and produces a
+//! `PingEvent` for each ping, which contains the round-trip time if the remote answered in time.
+//! It also answers the pings sent by the remote. After a configurable number of consecutive
+//! failures, the handler finishes, which closes the connection.
+//!
+//! The timers of the handler and the round-trip times are read from a `Clock` of
+//! `libp2p_swarm::time`, usually a tokio-core `Handle`. Tests can pass the `MockClock` of
+//! `libp2p-swarm-test` instead, so that the time only moves when they decide.
+//!
 //! # About timeouts
 //!
-//! For technical reasons, this crate doesn't handle timeouts. The action of pinging returns a
-//! future that is signalled only when the remote answers. If the remote is not responsive, the
-//! future will never be signalled.
+//! Apart from `PeriodicPingHandler`, this crate doesn't handle timeouts. The action of pinging
+//! returns a future that is signalled only when the remote answers. If the remote is not
+//! responsive, the future will never be signalled.
 //!
 //! For implementation reasons, resources allocated for a ping are only ever fully reclaimed after
 //! a pong has been received by the remote. Therefore if you repeatidely ping a non-responsive
@@ -86,6 +102,7 @@ extern crate log;
 extern crate multistream_select;
 extern crate parking_lot;
 extern crate rand;
+extern crate tokio_core;
 extern crate tokio_io;
 
 use bytes::{Bytes, BytesMut, BufMut};
@@ -96,7 +113,7 @@ use libp2p_swarm::Multiaddr;
 use libp2p_swarm::transport::{ConnectionUpgrade, Endpoint};
 use log::Level;
 use parking_lot::Mutex;
-use rand::Rand;
+use rand::{Rand, Rng, SeedableRng, XorShiftRng};
 use rand::os::OsRng;
 use std::collections::HashMap;
 use std::error::Error;
@@ -106,6 +123,10 @@ use std::sync::Arc;
 use tokio_io::{AsyncRead, AsyncWrite};
 use tokio_io::codec::{Encoder, Decoder};
 
+pub use self::handler::{PeriodicPingHandler, PingEvent};
+
+mod handler;
+
 /// Represents a prototype for an upgrade to handle the ping protocol.
 ///
 /// According to the design of libp2p, this struct would normally contain the configuration options
@@ -114,7 +135,7 @@ use tokio_io::codec::{Encoder, Decoder};
 pub struct Ping;
 
 impl<C> ConnectionUpgrade<C> for Ping
-    where C: AsyncRead + AsyncWrite + 'static
+    where C: AsyncRead + AsyncWrite + Send + 'static
 {
 	type NamesIter = iter::Once<(Bytes, Self::UpgradeIdentifier)>;
 	type UpgradeIdentifier = ();
@@ -124,103 +145,185 @@ impl<C> ConnectionUpgrade<C> for Ping
 		iter::once(("/ipfs/ping/1.0.0".into(), ()))
 	}
 
-	type Output = (Pinger, Box<Future<Item = (), Error = IoError>>);
+	type Output = (Pinger, Box<Future<Item = (), Error = IoError> + Send>);
 	type Future = FutureResult<Self::Output, IoError>;
 
 	#[inline]
 	fn upgrade(self, socket: C, _: Self::UpgradeIdentifier, _: Endpoint, remote_addr: &Multiaddr)
 				-> Self::Future
 	{
-		// # How does it work?
-		//
-		// All the actual processing is performed by the *ponger*.
-		// We use a channel in order to send ping requests from the pinger to the ponger.
-
-		let (tx, rx) = mpsc::channel(8);
-		// Ignore the errors if `tx` closed. `tx` is only ever closed if the ponger is closed,
-		// which means that the connection to the remote is closed. Therefore we make the `rx`
-		// never produce anything.
-		let rx = rx.then(|r| Ok(r.ok())).filter_map(|a| a);
-
 		let os_rng = match OsRng::new() {
 			Ok(r) => r,
 			Err(err) => return Err(err).into_future(),
 		};
 
-		let pinger = Pinger {
-			send: tx,
-			os_rng: os_rng,
-		};
+		Ok(upgrade_with_rng(socket, Box::new(os_rng), remote_addr)).into_future()
+	}
+}
 
-		// Hashmap that associates outgoing payloads to one-shot senders.
-		// TODO: can't figure out how to make it work without using an Arc/Mutex
-		let expected_pongs = Arc::new(Mutex::new(HashMap::with_capacity(4)));
+/// Same as `Ping`, except that the payloads of the pings are generated from a deterministic
+/// seed instead of from the random number generator of the operating system.
+///
+/// This is useful for simulations and tests that need to reproduce the exact same network
+/// traffic from one run to another. Each connection starts from the same seed, which is mixed
+/// with the endpoint of the connection, so that the pings sent at the same time by the dialer and
+/// the listener have different payloads even if both nodes use the same seed. Otherwise, each
+/// node would take the ping of the other for the answer to its own.
+///
+/// > **Note**: The payloads are predictable by design, so you shouldn't use this outside of tests.
+#[derive(Debug, Copy, Clone)]
+pub struct SeededPing {
+	seed: [u32; 4],
+}
 
-		let sink_stream = socket.framed(Codec).map(|msg| Message::Received(msg.freeze()));
-		let (sink, stream) = sink_stream.split();
+impl SeededPing {
+	/// Builds a `SeededPing` from the given seed.
+	///
+	/// # Panics
+	///
+	/// Panics if the seed is entirely made of zeroes.
+	#[inline]
+	pub fn new(seed: [u32; 4]) -> SeededPing {
+		assert!(seed.iter().any(|&n| n != 0), "the seed of a SeededPing must not be all zeroes");
+		SeededPing { seed: seed }
+	}
+}
 
-		let remote_addr = if log_enabled!(target: "libp2p-ping", Level::Debug) {
-			Some(remote_addr.clone())
-		} else {
-			None
-		};
+impl<C> ConnectionUpgrade<C> for SeededPing
+    where C: AsyncRead + AsyncWrite + Send + 'static
+{
+	type NamesIter = iter::Once<(Bytes, Self::UpgradeIdentifier)>;
+	type UpgradeIdentifier = ();
+
+	#[inline]
+	fn protocol_names(&self) -> Self::NamesIter {
+		iter::once(("/ipfs/ping/1.0.0".into(), ()))
+	}
 
-		let future = loop_fn((sink, stream.select(rx)), move |(sink, stream)| {
-			let expected_pongs = expected_pongs.clone();
-			let remote_addr = remote_addr.clone();
+	type Output = (Pinger, Box<Future<Item = (), Error = IoError> + Send>);
+	type Future = FutureResult<Self::Output, IoError>;
 
-			stream.into_future().map_err(|(err, _)| err).and_then(move |(message, stream)| {
-				let mut expected_pongs = expected_pongs.lock();
+	#[inline]
+	fn upgrade(self, socket: C, _: Self::UpgradeIdentifier, endpoint: Endpoint,
+			   remote_addr: &Multiaddr) -> Self::Future
+	{
+		let rng: XorShiftRng = SeedableRng::from_seed(endpoint_seed(self.seed, endpoint));
+		Ok(upgrade_with_rng(socket, Box::new(rng), remote_addr)).into_future()
+	}
+}
 
-				if let Some(message) = message {
-					match message {
-						Message::Ping(payload, finished) => {
-							// Ping requested by the user through the `Pinger`.
-							debug!(target: "libp2p-ping", "Sending ping to {:?} with payload {:?}",
+// Mixes the seed of a `SeededPing` with the endpoint of the connection. The dialer uses the seed
+// as it is, and the listener flips some of its bits.
+fn endpoint_seed(seed: [u32; 4], endpoint: Endpoint) -> [u32; 4] {
+	const LISTENER_MASK: [u32; 4] = [0x9e3779b9, 0x7f4a7c15, 0xf39cc060, 0x5ced1a2b];
+
+	match endpoint {
+		Endpoint::Dialer => seed,
+		Endpoint::Listener => {
+			let mut mixed = [0; 4];
+			for (n, word) in mixed.iter_mut().enumerate() {
+				*word = seed[n] ^ LISTENER_MASK[n];
+			}
+			// The seed of a `XorShiftRng` must not be all zeroes.
+			if mixed.iter().all(|&n| n == 0) {
+				mixed = [1, 0, 0, 0];
+			}
+			mixed
+		},
+	}
+}
+
+// Common implementation of the upgrade of `Ping` and `SeededPing`. The `rng` is used to generate
+// the payloads of the pings.
+fn upgrade_with_rng<C>(socket: C, rng: Box<Rng + Send>, remote_addr: &Multiaddr)
+					   -> (Pinger, Box<Future<Item = (), Error = IoError> + Send>)
+	where C: AsyncRead + AsyncWrite + Send + 'static
+{
+	// # How does it work?
+	//
+	// All the actual processing is performed by the *ponger*.
+	// We use a channel in order to send ping requests from the pinger to the ponger.
+
+	let (tx, rx) = mpsc::channel(8);
+	// Ignore the errors if `tx` closed. `tx` is only ever closed if the ponger is closed,
+	// which means that the connection to the remote is closed. Therefore we make the `rx`
+	// never produce anything.
+	let rx = rx.then(|r| Ok(r.ok())).filter_map(|a| a);
+
+	let pinger = Pinger {
+		send: tx,
+		rng: rng,
+	};
+
+	// Hashmap that associates outgoing payloads to one-shot senders.
+	// TODO: can't figure out how to make it work without using an Arc/Mutex
+	let expected_pongs = Arc::new(Mutex::new(HashMap::with_capacity(4)));
+
+	let sink_stream = socket.framed(Codec).map(|msg| Message::Received(msg.freeze()));
+	let (sink, stream) = sink_stream.split();
+
+	let remote_addr = if log_enabled!(target: "libp2p-ping", Level::Debug) {
+		Some(remote_addr.clone())
+	} else {
+		None
+	};
+
+	let future = loop_fn((sink, stream.select(rx)), move |(sink, stream)| {
+		let expected_pongs = expected_pongs.clone();
+		let remote_addr = remote_addr.clone();
+
+		stream.into_future().map_err(|(err, _)| err).and_then(move |(message, stream)| {
+			let mut expected_pongs = expected_pongs.lock();
+
+			if let Some(message) = message {
+				match message {
+					Message::Ping(payload, finished) => {
+						// Ping requested by the user through the `Pinger`.
+						debug!(target: "libp2p-ping", "Sending ping to {:?} with payload {:?}",
+							   remote_addr.expect("debug log level is enabled"), payload);
+						expected_pongs.insert(payload.clone(), finished);
+						Box::new(
+							sink.send(payload).map(|sink| Loop::Continue((sink, stream))),
+						) as Box<Future<Item = _, Error = _> + Send>
+					}
+					Message::Received(payload) => {
+						// Received a payload from the remote.
+						if let Some(fut) = expected_pongs.remove(&payload) {
+							// Payload was ours. Signalling future.
+							// Errors can happen if the user closed the receiving end of
+							// the future, which is fine to ignore.
+							debug!(target: "libp2p-ping", "Received pong from {:?} \
+														   (payload={:?}) ; ping fufilled",
+								   remote_addr.expect("debug log level is enabled"), payload);
+							let _ = fut.send(());
+							Box::new(Ok(Loop::Continue((sink, stream))).into_future()) as
+								Box<Future<Item = _, Error = _> + Send>
+						} else {
+							// Payload was not ours. Sending it back.
+							debug!(target: "libp2p-ping", "Received ping from {:?} \
+														   (payload={:?}) ; sending back",
 								   remote_addr.expect("debug log level is enabled"), payload);
-							expected_pongs.insert(payload.clone(), finished);
 							Box::new(
 								sink.send(payload).map(|sink| Loop::Continue((sink, stream))),
-							) as Box<Future<Item = _, Error = _>>
-						}
-						Message::Received(payload) => {
-							// Received a payload from the remote.
-							if let Some(fut) = expected_pongs.remove(&payload) {
-								// Payload was ours. Signalling future.
-								// Errors can happen if the user closed the receiving end of
-								// the future, which is fine to ignore.
-								debug!(target: "libp2p-ping", "Received pong from {:?} \
-															   (payload={:?}) ; ping fufilled",
-									   remote_addr.expect("debug log level is enabled"), payload);
-								let _ = fut.send(());
-								Box::new(Ok(Loop::Continue((sink, stream))).into_future()) as
-									Box<Future<Item = _, Error = _>>
-							} else {
-								// Payload was not ours. Sending it back.
-								debug!(target: "libp2p-ping", "Received ping from {:?} \
-															   (payload={:?}) ; sending back",
-									   remote_addr.expect("debug log level is enabled"), payload);
-								Box::new(
-									sink.send(payload).map(|sink| Loop::Continue((sink, stream))),
-								) as Box<Future<Item = _, Error = _>>
-							}
+							) as Box<Future<Item = _, Error = _> + Send>
 						}
 					}
-
-				} else {
-					Box::new(Ok(Loop::Break(())).into_future()) as Box<Future<Item = _, Error = _>>
 				}
-			})
-		});
 
-		Ok((pinger, Box::new(future) as Box<_>)).into_future()
-	}
+			} else {
+				Box::new(Ok(Loop::Break(())).into_future()) as
+					Box<Future<Item = _, Error = _> + Send>
+			}
+		})
+	});
+
+	(pinger, Box::new(future) as Box<_>)
 }
 
 /// Controller for the ping service. Makes it possible to send pings to the remote.
 pub struct Pinger {
 	send: mpsc::Sender<Message>,
-	os_rng: OsRng,
+	rng: Box<Rng + Send>,
 }
 
 impl Pinger {
@@ -228,9 +331,9 @@ impl Pinger {
 	///
 	/// **Note**: Please be aware that there is no timeout on the ping. You should handle the
 	/// 		  timeout yourself when you call this function.
-	pub fn ping(&mut self) -> Box<Future<Item = (), Error = Box<Error + Send + Sync>>> {
+	pub fn ping(&mut self) -> Box<Future<Item = (), Error = Box<Error + Send + Sync>> + Send> {
 		let (tx, rx) = oneshot::channel();
-		let payload: [u8; 32] = Rand::rand(&mut self.os_rng);
+		let payload: [u8; 32] = Rand::rand(&mut self.rng);
 		debug!(target: "libp2p-ping", "Preparing for ping with payload {:?}", payload);
 		// Ignore errors if the ponger has been already destroyed. The returned future will never
 		// be signalled.
@@ -281,7 +384,7 @@ mod tests {
 	use self::tokio_core::net::TcpListener;
 	use self::tokio_core::net::TcpStream;
 	use self::tokio_core::reactor::Core;
-	use super::Ping;
+	use super::{endpoint_seed, Ping, SeededPing};
 	use futures::future::join_all;
 	use futures::Future;
 	use futures::Stream;
@@ -317,6 +420,46 @@ mod tests {
 		core.run(server.join(client)).unwrap();
 	}
 
+	#[test]
+	fn seeded_ping_pong() {
+		let mut core = Core::new().unwrap();
+
+		let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+		let listener_addr = listener.local_addr().unwrap();
+
+		let server = listener.incoming()
+		                     .into_future()
+		                     .map_err(|(e, _)| e.into())
+		                     .and_then(|(c, _)| {
+								 SeededPing::new([1, 2, 3, 4])
+									 .upgrade(c.unwrap().0, (), Endpoint::Listener,
+											  &"/ip4/127.0.0.1/tcp/10000".parse().unwrap())
+							 })
+		                     .and_then(|(_, service)| service.map_err(|_| panic!()));
+
+		let client = TcpStream::connect(&listener_addr, &core.handle())
+			.map_err(|e| e.into())
+			.and_then(|c| {
+				SeededPing::new([1, 2, 3, 4])
+					.upgrade(c, (), Endpoint::Dialer, &"/ip4/127.0.0.1/tcp/10000".parse().unwrap())
+			})
+			.and_then(|(mut pinger, service)| {
+				pinger.ping().map_err(|_| panic!()).select(service).map_err(|_| panic!())
+			});
+
+		core.run(server.select(client)).unwrap_or_else(|_| panic!());
+	}
+
+	#[test]
+	fn seeded_ping_endpoints_differ() {
+		for &seed in &[[1, 2, 3, 4], [0x9e3779b9, 0x7f4a7c15, 0xf39cc060, 0x5ced1a2b]] {
+			let dialer = endpoint_seed(seed, Endpoint::Dialer);
+			let listener = endpoint_seed(seed, Endpoint::Listener);
+			assert_ne!(dialer, listener);
+			assert!(listener.iter().any(|&n| n != 0));
+		}
+	}
+
 	#[test]
 	fn multipings() {
 		// Check that we can send multiple pings in a row and it will still work.
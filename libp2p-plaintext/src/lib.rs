@@ -0,0 +1,385 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+// TODO: use this once stable ; for now we just copy-paste the content of the README.md
+//#![doc(include = "../README.md")]
+
+//! Implementation of the `/plaintext/2.0.0` protocol.
+//!
+//! Contrary to the `PlainTextConfig` of `libp2p-swarm`, which doesn't do anything, this protocol
+//! makes the two nodes exchange their public key and their peer ID at the start of the
+//! connection. The rest of the communications are neither encrypted nor authenticated.
+//!
+//! This is useful for test networks and benchmarks, where the nodes need to know each other's
+//! identity but the cost of the cryptography isn't wanted.
+//!
+//! > **Note**: Nothing proves that the remote owns the private key of the public key it sends.
+//! >           Never use this protocol on a network that you don't trust.
+//!
+//! # Usage
+//!
+//! The `PlainText2Config` struct implements the `ConnectionUpgrade` trait. It is built from the
+//! `PublicKey` protobuf message of the public key of the local node. The output of the upgrade
+//! is a `PlainTextOutput`, which implements `AsyncRead` and `AsyncWrite` and gives access to the
+//! public key and the peer ID of the remote.
+//!
+//! ```
+//! extern crate libp2p_plaintext;
+//! extern crate libp2p_swarm;
+//! extern crate libp2p_tcp_transport;
+//! extern crate tokio_core;
+//!
+//! use libp2p_plaintext::PlainText2Config;
+//! use libp2p_swarm::Transport;
+//! use libp2p_tcp_transport::TcpConfig;
+//! use tokio_core::reactor::Core;
+//!
+//! # fn main() {
+//! let core = Core::new().unwrap();
+//! // `PublicKey` protobuf message of an ed25519 key.
+//! let public_key = vec![0x08, 0x01, 0x12, 0x01, 0x2a];
+//! let transport = TcpConfig::new(core.handle())
+//! 	.with_upgrade(PlainText2Config::new(public_key));
+//! # }
+//! ```
+//!
+//! Each node sends an `Exchange` protobuf message prefixed with its length as a varint, and
+//! checks that the peer ID of the message of the remote corresponds to its public key:
+//!
+//! ```text
+//! message Exchange {
+//!     bytes id = 1;
+//!     PublicKey pubkey = 2;
+//! }
+//! ```
+
+extern crate bytes;
+extern crate futures;
+extern crate libp2p_identity_core;
+extern crate libp2p_swarm;
+extern crate tokio_io;
+
+use bytes::Bytes;
+use futures::{future, Future, Poll};
+use libp2p_identity_core::{PeerIdBuf, PeerIdRef, PublicKeyRef};
+use libp2p_swarm::{ConnectionUpgrade, Endpoint, Multiaddr};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::iter;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::io::{flush, read_exact, write_all};
+
+/// Maximum length of the `Exchange` message of the remote.
+const MAX_EXCHANGE_LEN: u64 = 4096;
+
+/// Implementation of the `ConnectionUpgrade` trait of `libp2p_swarm` for the `/plaintext/2.0.0`
+/// protocol.
+#[derive(Debug, Clone)]
+pub struct PlainText2Config {
+	public_key: Vec<u8>,
+}
+
+impl PlainText2Config {
+	/// Builds a `PlainText2Config` from the `PublicKey` protobuf message of the public key of
+	/// the local node.
+	#[inline]
+	pub fn new(public_key: Vec<u8>) -> PlainText2Config {
+		PlainText2Config {
+			public_key: public_key,
+		}
+	}
+}
+
+impl<C> ConnectionUpgrade<C> for PlainText2Config
+	where C: AsyncRead + AsyncWrite + Send + 'static
+{
+	type Output = PlainTextOutput<C>;
+	type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
+	type NamesIter = iter::Once<(Bytes, ())>;
+	type UpgradeIdentifier = ();
+
+	#[inline]
+	fn protocol_names(&self) -> Self::NamesIter {
+		iter::once((Bytes::from("/plaintext/2.0.0"), ()))
+	}
+
+	fn upgrade(self, socket: C, _: (), _: Endpoint, _: &Multiaddr) -> Self::Future {
+		let exchange = encode_exchange(&self.public_key);
+
+		// Both sides send their message first, so there is no risk of deadlock.
+		let future = write_all(socket, exchange)
+			.and_then(|(socket, _)| flush(socket))
+			.and_then(read_length)
+			.and_then(|(socket, len)| {
+				if len > MAX_EXCHANGE_LEN {
+					let err = IoError::new(IoErrorKind::InvalidData, "exchange message too large");
+					return future::Either::A(future::err(err));
+				}
+				future::Either::B(read_exact(socket, vec![0; len as usize]))
+			})
+			.and_then(|(socket, message)| -> Result<_, IoError> {
+				let (peer_id, public_key) = decode_exchange(&message)?;
+				Ok(PlainTextOutput {
+					socket: socket,
+					remote_peer_id: peer_id,
+					remote_public_key: public_key,
+				})
+			});
+
+		Box::new(future)
+	}
+}
+
+/// Connection whose remote has sent its public key and its peer ID.
+///
+/// Implements `AsyncRead` and `AsyncWrite` by forwarding to the underlying socket.
+#[derive(Debug)]
+pub struct PlainTextOutput<S> {
+	socket: S,
+	remote_peer_id: Vec<u8>,
+	remote_public_key: Vec<u8>,
+}
+
+impl<S> PlainTextOutput<S> {
+	/// Returns the peer ID sent by the remote, which matches its public key.
+	#[inline]
+	pub fn remote_peer_id(&self) -> PeerIdRef {
+		PeerIdRef::from_bytes(&self.remote_peer_id).expect("the peer ID was checked when received")
+	}
+
+	/// Returns the `PublicKey` protobuf message sent by the remote.
+	#[inline]
+	pub fn remote_public_key(&self) -> &[u8] {
+		&self.remote_public_key
+	}
+
+	/// Returns the underlying socket.
+	#[inline]
+	pub fn into_inner(self) -> S {
+		self.socket
+	}
+}
+
+impl<S> Read for PlainTextOutput<S>
+	where S: Read
+{
+	#[inline]
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+		self.socket.read(buf)
+	}
+}
+
+impl<S> AsyncRead for PlainTextOutput<S>
+	where S: AsyncRead
+{
+}
+
+impl<S> Write for PlainTextOutput<S>
+	where S: Write
+{
+	#[inline]
+	fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+		self.socket.write(buf)
+	}
+
+	#[inline]
+	fn flush(&mut self) -> Result<(), IoError> {
+		self.socket.flush()
+	}
+}
+
+impl<S> AsyncWrite for PlainTextOutput<S>
+	where S: AsyncWrite
+{
+	#[inline]
+	fn shutdown(&mut self) -> Poll<(), IoError> {
+		self.socket.shutdown()
+	}
+}
+
+// Builds the `Exchange` message of `public_key`, prefixed with its length.
+fn encode_exchange(public_key: &[u8]) -> Vec<u8> {
+	let peer_id = PeerIdBuf::from_public_key(public_key);
+
+	let mut message = Vec::with_capacity(public_key.len() + 48);
+	write_bytes_field(&mut message, 1, peer_id.as_bytes());
+	write_bytes_field(&mut message, 2, public_key);
+
+	let mut out = Vec::with_capacity(message.len() + 2);
+	write_varint(&mut out, message.len() as u64);
+	out.extend_from_slice(&message);
+	out
+}
+
+// Decodes an `Exchange` message and checks that the peer ID matches the public key. Returns the
+// peer ID and the public key.
+fn decode_exchange(mut data: &[u8]) -> Result<(Vec<u8>, Vec<u8>), IoError> {
+	let mut peer_id = None;
+	let mut public_key = None;
+
+	while !data.is_empty() {
+		let key = read_varint(&mut data)?;
+		match key & 0x7 {
+			0 => { read_varint(&mut data)?; },
+			2 => {
+				let len = read_varint(&mut data)?;
+				if len > data.len() as u64 {
+					return Err(invalid_exchange());
+				}
+				let (value, rest) = data.split_at(len as usize);
+				data = rest;
+				match key >> 3 {
+					1 => peer_id = Some(value),
+					2 => public_key = Some(value),
+					_ => (),
+				}
+			},
+			_ => return Err(invalid_exchange()),
+		}
+	}
+
+	let (peer_id, public_key) = match (peer_id, public_key) {
+		(Some(peer_id), Some(public_key)) => (peer_id, public_key),
+		_ => return Err(invalid_exchange()),
+	};
+
+	let parsed_key = PublicKeyRef::from_protobuf(public_key).map_err(|_| invalid_exchange())?;
+	let parsed_id = PeerIdRef::from_bytes(peer_id).map_err(|_| invalid_exchange())?;
+	if !parsed_key.matches(&parsed_id) {
+		return Err(IoError::new(IoErrorKind::InvalidData, "peer ID doesn't match public key"));
+	}
+
+	Ok((peer_id.to_vec(), public_key.to_vec()))
+}
+
+// Reads the varint that prefixes the `Exchange` message, one byte at a time.
+fn read_length<S>(socket: S) -> Box<Future<Item = (S, u64), Error = IoError> + Send>
+	where S: AsyncRead + Send + 'static
+{
+	let future = future::loop_fn((socket, 0u64, 0u32), |(socket, value, shift)| {
+		read_exact(socket, [0; 1]).and_then(move |(socket, byte)| {
+			let value = value | (u64::from(byte[0] & 0x7f) << shift);
+			if byte[0] & 0x80 == 0 {
+				Ok(future::Loop::Break((socket, value)))
+			} else if shift >= 63 {
+				Err(invalid_exchange())
+			} else {
+				Ok(future::Loop::Continue((socket, value, shift + 7)))
+			}
+		})
+	});
+
+	Box::new(future)
+}
+
+fn write_bytes_field(out: &mut Vec<u8>, field: u64, value: &[u8]) {
+	write_varint(out, (field << 3) | 2);
+	write_varint(out, value.len() as u64);
+	out.extend_from_slice(value);
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+	while value >= 0x80 {
+		out.push((value as u8) | 0x80);
+		value >>= 7;
+	}
+	out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64, IoError> {
+	let mut value = 0u64;
+	for shift in 0 .. 10 {
+		let remaining: &[u8] = *data;
+		let byte = match remaining.first() {
+			Some(&byte) => byte,
+			None => return Err(invalid_exchange()),
+		};
+		*data = &remaining[1 ..];
+		value |= u64::from(byte & 0x7f) << (7 * shift);
+		if byte & 0x80 == 0 {
+			return Ok(value);
+		}
+	}
+
+	Err(invalid_exchange())
+}
+
+#[inline]
+fn invalid_exchange() -> IoError {
+	IoError::new(IoErrorKind::InvalidData, "invalid exchange message")
+}
+
+#[cfg(test)]
+mod tests {
+	extern crate tokio_core;
+	use self::tokio_core::net::{TcpListener, TcpStream};
+	use self::tokio_core::reactor::Core;
+	use super::{decode_exchange, encode_exchange, PlainText2Config};
+	use futures::{Future, Stream};
+	use libp2p_identity_core::PeerIdBuf;
+	use libp2p_swarm::{ConnectionUpgrade, Endpoint, Multiaddr};
+
+	#[test]
+	fn exchange_roundtrip() {
+		let public_key = vec![0x08, 0x01, 0x12, 0x01, 0x2a];
+		let encoded = encode_exchange(&public_key);
+		assert_eq!(encoded[0] as usize, encoded.len() - 1);
+
+		let (peer_id, decoded_key) = decode_exchange(&encoded[1 ..]).unwrap();
+		assert_eq!(decoded_key, public_key);
+		assert_eq!(&peer_id[..], PeerIdBuf::from_public_key(&public_key).as_bytes());
+	}
+
+	#[test]
+	fn mismatching_peer_id() {
+		let mut encoded = encode_exchange(&[0x08, 0x01, 0x12, 0x01, 0x2a]);
+		// Corrupts the public key.
+		*encoded.last_mut().unwrap() = 0x2b;
+		assert!(decode_exchange(&encoded[1 ..]).is_err());
+	}
+
+	#[test]
+	fn upgrade() {
+		let mut core = Core::new().unwrap();
+		let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+		let listener_addr = listener.local_addr().unwrap();
+		let addr: Multiaddr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+
+		let server_key = vec![0x08, 0x01, 0x12, 0x01, 0x01];
+		let client_key = vec![0x08, 0x01, 0x12, 0x01, 0x02];
+
+		let server_config = PlainText2Config::new(server_key.clone());
+		let server_addr = addr.clone();
+		let server = listener.incoming()
+			.into_future()
+			.map_err(|(err, _)| err)
+			.and_then(move |(connec, _)| {
+				server_config.upgrade(connec.unwrap().0, (), Endpoint::Listener, &server_addr)
+			});
+
+		let client_config = PlainText2Config::new(client_key.clone());
+		let client = TcpStream::connect(&listener_addr, &core.handle())
+			.and_then(move |stream| client_config.upgrade(stream, (), Endpoint::Dialer, &addr));
+
+		let (server, client) = core.run(server.join(client)).unwrap();
+		assert_eq!(server.remote_public_key(), &client_key[..]);
+		assert_eq!(client.remote_public_key(), &server_key[..]);
+		assert!(server.remote_peer_id().is_public_key(&client_key));
+	}
+}
@@ -0,0 +1,165 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `PreSharedKey` of a private network, and its parsing from the format of the
+//! `swarm.key` files.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Length of a `PreSharedKey`, in bytes.
+pub const KEY_LEN: usize = 32;
+
+// First line of a `swarm.key` file.
+const KEY_HEADER: &'static str = "/key/swarm/psk/1.0.0/";
+
+/// Secret key shared by all the nodes of a private network.
+///
+/// Can be parsed from the content of a `swarm.key` file, which has the following format:
+///
+/// ```text
+/// /key/swarm/psk/1.0.0/
+/// /base16/
+/// <the 32 bytes of the key as 64 hexadecimal characters>
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct PreSharedKey([u8; KEY_LEN]);
+
+impl PreSharedKey {
+	/// Builds a key from its raw bytes.
+	#[inline]
+	pub fn new(key: [u8; KEY_LEN]) -> PreSharedKey {
+		PreSharedKey(key)
+	}
+
+	/// Returns the raw bytes of the key.
+	#[inline]
+	pub fn as_bytes(&self) -> &[u8; KEY_LEN] {
+		&self.0
+	}
+}
+
+impl fmt::Debug for PreSharedKey {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		// Never print the key itself, as it ends up in logs.
+		f.debug_tuple("PreSharedKey").field(&"<hidden>").finish()
+	}
+}
+
+impl FromStr for PreSharedKey {
+	type Err = KeyParseError;
+
+	fn from_str(s: &str) -> Result<PreSharedKey, KeyParseError> {
+		let mut lines = s.lines().map(str::trim).filter(|line| !line.is_empty());
+
+		if lines.next() != Some(KEY_HEADER) {
+			return Err(KeyParseError::InvalidHeader);
+		}
+
+		match lines.next() {
+			Some("/base16/") => (),
+			Some(encoding) => return Err(KeyParseError::UnsupportedEncoding(encoding.to_owned())),
+			None => return Err(KeyParseError::InvalidKey),
+		}
+
+		let hex = lines.next().ok_or(KeyParseError::InvalidKey)?;
+		if lines.next().is_some() || hex.len() != KEY_LEN * 2 ||
+			!hex.chars().all(|c| c.is_digit(16))
+		{
+			return Err(KeyParseError::InvalidKey);
+		}
+
+		let mut key = [0; KEY_LEN];
+		for (n, byte) in key.iter_mut().enumerate() {
+			*byte = u8::from_str_radix(&hex[n * 2 .. n * 2 + 2], 16)
+				.expect("the key only contains hexadecimal digits");
+		}
+
+		Ok(PreSharedKey(key))
+	}
+}
+
+/// Error while parsing a `PreSharedKey`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyParseError {
+	/// The first line isn't `/key/swarm/psk/1.0.0/`.
+	InvalidHeader,
+	/// The key is stored with an encoding other than `/base16/`.
+	UnsupportedEncoding(String),
+	/// The key is missing, or isn't made of 64 hexadecimal characters.
+	InvalidKey,
+}
+
+impl Error for KeyParseError {
+	#[inline]
+	fn description(&self) -> &str {
+		match *self {
+			KeyParseError::InvalidHeader => "invalid header for a swarm key",
+			KeyParseError::UnsupportedEncoding(_) => "unsupported encoding for a swarm key",
+			KeyParseError::InvalidKey => "invalid swarm key",
+		}
+	}
+}
+
+impl fmt::Display for KeyParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match *self {
+			KeyParseError::UnsupportedEncoding(ref encoding) => {
+				write!(f, "unsupported encoding for a swarm key: {}", encoding)
+			}
+			_ => write!(f, "{}", self.description()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{KeyParseError, PreSharedKey};
+
+	#[test]
+	fn parse_swarm_key() {
+		let key = "/key/swarm/psk/1.0.0/\n/base16/\n\
+				   000102030405060708090a0b0c0d0e0f101112131415161718191A1B1C1D1E1F\n"
+			.parse::<PreSharedKey>()
+			.unwrap();
+
+		let mut expected = [0; 32];
+		for (n, byte) in expected.iter_mut().enumerate() {
+			*byte = n as u8;
+		}
+		assert_eq!(key.as_bytes(), &expected);
+	}
+
+	#[test]
+	fn parse_errors() {
+		let hex = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+		let parse = |s: String| s.parse::<PreSharedKey>().map(|_| ());
+		assert_eq!(parse(format!("/key/swarm/psk/2.0.0/\n/base16/\n{}", hex)),
+				   Err(KeyParseError::InvalidHeader));
+		assert_eq!(parse(format!("/key/swarm/psk/1.0.0/\n/base64/\n{}", hex)),
+				   Err(KeyParseError::UnsupportedEncoding("/base64/".to_owned())));
+		assert_eq!(parse(format!("/key/swarm/psk/1.0.0/\n/base16/\n{}", &hex[2 ..])),
+				   Err(KeyParseError::InvalidKey));
+		assert_eq!(parse(format!("/key/swarm/psk/1.0.0/\n/base16/\n{}zz", &hex[2 ..])),
+				   Err(KeyParseError::InvalidKey));
+	}
+}
@@ -0,0 +1,317 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+// TODO: use this once stable ; for now we just copy-paste the content of the README.md
+//#![doc(include = "../README.md")]
+
+//! Implementation of the private networks of libp2p ("pnet"), as a wrapper around a `Transport`.
+//!
+//! All the nodes of a private network share a secret key, and only the nodes that know this key
+//! can connect to each other. Each connection starts with both sides sending a random 24 bytes
+//! nonce. Afterwards, all the bytes that go through the connection are encrypted with XSalsa20,
+//! using the shared key and the nonce of the side that sends them. A node that doesn't know the
+//! key can't produce or understand anything meaningful, and the protocol negotiation that
+//! follows fails.
+//!
+//! The key is usually stored in a `swarm.key` file, whose format is compatible with go-ipfs.
+//!
+//! # Usage
+//!
+//! ```
+//! extern crate libp2p_pnet;
+//! extern crate libp2p_tcp_transport;
+//! extern crate tokio_core;
+//!
+//! use libp2p_pnet::{PnetConfig, PreSharedKey};
+//! use libp2p_tcp_transport::TcpConfig;
+//! use tokio_core::reactor::Core;
+//!
+//! # fn main() {
+//! let core = Core::new().unwrap();
+//! let key = "/key/swarm/psk/1.0.0/\n/base16/\n\
+//!            6189c5cf0b87fb800c1a9feeda73c6ab5e998db48fb9e6a978575c770ceef683"
+//!     .parse::<PreSharedKey>()
+//!     .unwrap();
+//! let transport = PnetConfig::new(TcpConfig::new(core.handle()), key);
+//! # }
+//! ```
+//!
+//! Put the `PnetConfig` directly on top of the transport, below the upgrades, so that the protocol
+//! negotiation already happens inside the private network.
+//!
+//! > **Note**: The shared key only keeps the other nodes out of the network. It doesn't
+//! >           authenticate the remote, and doesn't protect the data against tampering. An
+//! >           encryption upgrade such as secio is still needed on top.
+
+extern crate crypto;
+extern crate futures;
+extern crate libp2p_swarm as swarm;
+extern crate multiaddr;
+extern crate rand;
+extern crate tokio_io;
+
+#[cfg(test)]
+extern crate libp2p_tcp_transport as tcp;
+#[cfg(test)]
+extern crate tokio_core;
+
+mod key;
+
+pub use self::key::{KeyParseError, PreSharedKey, KEY_LEN};
+
+use crypto::salsa20::Salsa20;
+use crypto::symmetriccipher::SynchronousStreamCipher;
+use futures::{future, Async, Future, IntoFuture, Poll, Stream};
+use multiaddr::Multiaddr;
+use rand::{OsRng, Rng};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use swarm::Transport;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Length of the nonce sent by each side at the start of a connection, in bytes.
+pub const NONCE_LEN: usize = 24;
+
+/// Wraps around a `Transport` and restricts its connections to the nodes that know the
+/// `PreSharedKey`.
+#[derive(Debug, Clone)]
+pub struct PnetConfig<T> {
+	inner: T,
+	key: PreSharedKey,
+}
+
+impl<T> PnetConfig<T> {
+	/// Wraps around `inner`. The connections are encrypted with `key`.
+	#[inline]
+	pub fn new(inner: T, key: PreSharedKey) -> PnetConfig<T> {
+		PnetConfig {
+			inner: inner,
+			key: key,
+		}
+	}
+}
+
+impl<T> Transport for PnetConfig<T>
+	where T: Transport + 'static,
+		  T::RawConn: Send,
+		  T::Listener: Send,
+		  T::ListenerUpgrade: Send,
+		  <T::Dial as IntoFuture>::Future: Send,
+{
+	type RawConn = PnetOutput<T::RawConn>;
+	type Listener = Box<Stream<Item = (Self::ListenerUpgrade, Multiaddr), Error = IoError> + Send>;
+	type ListenerUpgrade = Box<Future<Item = Self::RawConn, Error = IoError> + Send>;
+	type Dial = Box<Future<Item = Self::RawConn, Error = IoError> + Send>;
+
+	fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+		let key = self.key;
+
+		match self.inner.listen_on(addr) {
+			Ok((listener, addr)) => {
+				let listener = listener.map(move |(upgrade, addr)| {
+					let upgrade = upgrade.and_then(move |conn| handshake(conn, key));
+					(Box::new(upgrade) as Box<Future<Item = _, Error = _> + Send>, addr)
+				});
+				Ok((Box::new(listener), addr))
+			}
+			Err((inner, addr)) => Err((PnetConfig::new(inner, key), addr)),
+		}
+	}
+
+	fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+		let key = self.key;
+
+		match self.inner.dial(addr) {
+			Ok(dial) => Ok(Box::new(dial.into_future().and_then(move |conn| handshake(conn, key)))),
+			Err((inner, addr)) => Err((PnetConfig::new(inner, key), addr)),
+		}
+	}
+
+	#[inline]
+	fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+		self.inner.nat_traversal(server, observed)
+	}
+
+	#[inline]
+	fn expand_listen_addr(&self, addr: &Multiaddr) -> Vec<Multiaddr> {
+		self.inner.expand_listen_addr(addr)
+	}
+}
+
+// Sends our nonce, receives the nonce of the remote, then sets up the ciphers of the connection.
+fn handshake<S>(socket: S, key: PreSharedKey)
+				-> Box<Future<Item = PnetOutput<S>, Error = IoError> + Send>
+	where S: AsyncRead + AsyncWrite + Send + 'static
+{
+	let mut local_nonce = [0; NONCE_LEN];
+	match OsRng::new() {
+		Ok(mut rng) => rng.fill_bytes(&mut local_nonce),
+		Err(err) => return Box::new(future::err(err)),
+	}
+
+	let future = tokio_io::io::write_all(socket, local_nonce)
+		.and_then(|(socket, _)| tokio_io::io::flush(socket))
+		.and_then(|socket| tokio_io::io::read_exact(socket, [0; NONCE_LEN]))
+		.map(move |(socket, remote_nonce)| PnetOutput {
+			inner: socket,
+			read_cipher: Salsa20::new_xsalsa20(key.as_bytes(), &remote_nonce),
+			write_cipher: Salsa20::new_xsalsa20(key.as_bytes(), &local_nonce),
+			read_buffer: Vec::new(),
+			write_buffer: Vec::new(),
+		});
+
+	Box::new(future)
+}
+
+/// Connection of a `PnetConfig`. Encrypts everything that is written, and decrypts everything
+/// that is read.
+pub struct PnetOutput<S> {
+	inner: S,
+	read_cipher: Salsa20,
+	write_cipher: Salsa20,
+	// Encrypted data, because the cipher can't process the data in place.
+	read_buffer: Vec<u8>,
+	// Encrypted data that hasn't been written to `inner` yet.
+	write_buffer: Vec<u8>,
+}
+
+impl<S> PnetOutput<S>
+	where S: Write
+{
+	// Writes the content of `write_buffer` to the underlying connection.
+	fn flush_buffer(&mut self) -> Result<(), IoError> {
+		while !self.write_buffer.is_empty() {
+			let written = self.inner.write(&self.write_buffer)?;
+			if written == 0 {
+				let msg = "failed to write the encrypted data";
+				return Err(IoError::new(IoErrorKind::WriteZero, msg));
+			}
+			self.write_buffer.drain(.. written);
+		}
+
+		Ok(())
+	}
+}
+
+impl<S> Read for PnetOutput<S>
+	where S: Read
+{
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+		self.read_buffer.resize(buf.len(), 0);
+		let num_read = self.inner.read(&mut self.read_buffer)?;
+		self.read_cipher.process(&self.read_buffer[.. num_read], &mut buf[.. num_read]);
+		Ok(num_read)
+	}
+}
+
+impl<S> AsyncRead for PnetOutput<S>
+	where S: AsyncRead
+{
+}
+
+impl<S> Write for PnetOutput<S>
+	where S: Write
+{
+	fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+		// The keystream can't go back, therefore the data that has already been encrypted must
+		// be written before accepting more.
+		self.flush_buffer()?;
+		if buf.is_empty() {
+			return Ok(0);
+		}
+
+		self.write_buffer.resize(buf.len(), 0);
+		self.write_cipher.process(buf, &mut self.write_buffer);
+
+		// The data is accepted even if it can't be written right away, and the rest is written
+		// by the next calls.
+		match self.flush_buffer() {
+			Err(ref err) if err.kind() == IoErrorKind::WouldBlock => Ok(buf.len()),
+			Err(err) => Err(err),
+			Ok(()) => Ok(buf.len()),
+		}
+	}
+
+	#[inline]
+	fn flush(&mut self) -> Result<(), IoError> {
+		self.flush_buffer()?;
+		self.inner.flush()
+	}
+}
+
+impl<S> AsyncWrite for PnetOutput<S>
+	where S: AsyncWrite
+{
+	fn shutdown(&mut self) -> Poll<(), IoError> {
+		match self.flush_buffer() {
+			Ok(()) => self.inner.shutdown(),
+			Err(ref err) if err.kind() == IoErrorKind::WouldBlock => Ok(Async::NotReady),
+			Err(err) => Err(err),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{PnetConfig, PreSharedKey, KEY_LEN};
+	use futures::{Future, Stream};
+	use multiaddr::Multiaddr;
+	use swarm::Transport;
+	use tcp::TcpConfig;
+	use tokio_core::reactor::Core;
+	use tokio_io;
+
+	// Sends `[1, 2, 3, 4]` from a node that uses `dialer_key` to a node that uses
+	// `listener_key`, and returns what the listener received.
+	fn transfer(dialer_key: PreSharedKey, listener_key: PreSharedKey) -> [u8; 4] {
+		let mut core = Core::new().unwrap();
+		let listener = PnetConfig::new(TcpConfig::new(core.handle()), listener_key);
+		let dialer = PnetConfig::new(TcpConfig::new(core.handle()), dialer_key);
+
+		let (listener, addr) = listener
+			.listen_on("/ip4/127.0.0.1/tcp/0".parse::<Multiaddr>().unwrap())
+			.unwrap();
+
+		let listener = listener
+			.into_future()
+			.map_err(|(err, _)| err)
+			.and_then(|(conn, _)| conn.unwrap().0)
+			.and_then(|conn| tokio_io::io::read_exact(conn, [0; 4]))
+			.map(|(_, buf)| buf);
+
+		let dialer = dialer.dial(addr)
+			.unwrap()
+			.and_then(|conn| tokio_io::io::write_all(conn, [1, 2, 3, 4]))
+			.and_then(|(conn, _)| tokio_io::io::flush(conn));
+
+		core.run(listener.join(dialer)).unwrap().0
+	}
+
+	#[test]
+	fn same_key() {
+		let key = PreSharedKey::new([7; KEY_LEN]);
+		assert_eq!(transfer(key, key), [1, 2, 3, 4]);
+	}
+
+	#[test]
+	fn different_keys() {
+		let received = transfer(PreSharedKey::new([7; KEY_LEN]), PreSharedKey::new([8; KEY_LEN]));
+		assert_ne!(received, [1, 2, 3, 4]);
+	}
+}
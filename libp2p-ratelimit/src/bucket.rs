@@ -0,0 +1,215 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+//! Token buckets that limit the number of bytes transferred per second.
+
+use futures::{future, Async, Future, Poll};
+use futures::sync::oneshot;
+use parking_lot::Mutex;
+use std::cmp;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::{Remote, Timeout};
+
+/// A token bucket. Each token allows transferring one byte.
+///
+/// The bucket holds at most one second worth of tokens, and starts full.
+#[derive(Debug)]
+pub struct Bucket {
+	// Number of tokens added per second.
+	rate: f64,
+	// Can be negative if the bytes that were transferred exceed what was allowed, for example
+	// when several connections share the bucket.
+	tokens: f64,
+	last_refill: Instant,
+}
+
+impl Bucket {
+	/// Creates a bucket that allows transferring `rate` bytes per second.
+	///
+	/// # Panic
+	///
+	/// Panics if `rate` is 0.
+	pub fn new(rate: usize) -> Bucket {
+		assert_ne!(rate, 0, "the rate of a token bucket must not be zero");
+		Bucket {
+			rate: rate as f64,
+			tokens: rate as f64,
+			last_refill: Instant::now(),
+		}
+	}
+
+	/// Returns the number of tokens available at `now`.
+	pub fn available(&mut self, now: Instant) -> f64 {
+		if now > self.last_refill {
+			let elapsed = now.duration_since(self.last_refill);
+			let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+			self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+			self.last_refill = now;
+		}
+		self.tokens
+	}
+
+	/// Returns the number of tokens we should wait for before transferring anything, if
+	/// `wanted` bytes are wanted. Waiting for a single token would wake up the task too often.
+	#[inline]
+	pub fn chunk(&self, wanted: usize) -> usize {
+		cmp::min(wanted, cmp::max(1, (self.rate / 100.0) as usize))
+	}
+
+	/// Returns the time after which `tokens` tokens will be available, assuming that
+	/// `available()` has just been called.
+	pub fn wait_time(&self, tokens: usize) -> Duration {
+		let secs = ((tokens as f64 - self.tokens) / self.rate).max(0.0);
+		Duration::new(secs as u64, ((secs - secs.floor()) * 1e9) as u32)
+	}
+
+	/// Removes `tokens` tokens from the bucket.
+	#[inline]
+	pub fn consume(&mut self, tokens: usize) {
+		self.tokens -= tokens as f64;
+	}
+}
+
+/// Limits one direction of a connection, with a bucket specific to the connection and a bucket
+/// shared with the other connections.
+pub struct Limiter {
+	connection: Option<Bucket>,
+	global: Option<Arc<Mutex<Bucket>>>,
+	// Wakes up the task once tokens are available again. The timer runs on the event loop, so
+	// that the connection can be used from any thread.
+	delay: Option<oneshot::Receiver<Result<(), IoError>>>,
+}
+
+impl Limiter {
+	/// Creates a limiter. The `None` buckets don't limit anything.
+	#[inline]
+	pub fn new(connection: Option<Bucket>, global: Option<Arc<Mutex<Bucket>>>) -> Limiter {
+		Limiter {
+			connection: connection,
+			global: global,
+			delay: None,
+		}
+	}
+
+	/// Returns the number of bytes, between 1 and `wanted`, that can be transferred now. If
+	/// nothing can be transferred, schedules the current task to be woken up once it can.
+	///
+	/// The bytes that are actually transferred must then be passed to `consume()`.
+	pub fn poll_allowance(&mut self, wanted: usize, event_loop: &Remote)
+						  -> Poll<usize, IoError>
+	{
+		debug_assert_ne!(wanted, 0);
+
+		loop {
+			if let Some(mut delay) = self.delay.take() {
+				match delay.poll() {
+					Ok(Async::Ready(Ok(()))) => (),
+					Ok(Async::Ready(Err(err))) => return Err(err),
+					Ok(Async::NotReady) => {
+						self.delay = Some(delay);
+						return Ok(Async::NotReady);
+					}
+					Err(oneshot::Canceled) => {
+						let msg = "the tokio reactor has been destroyed";
+						return Err(IoError::new(IoErrorKind::Other, msg));
+					}
+				}
+			}
+
+			let now = Instant::now();
+			let mut allowed = wanted;
+			let mut wait = Duration::new(0, 0);
+
+			{
+				let mut check = |bucket: &mut Bucket| {
+					let tokens = bucket.available(now);
+					let chunk = bucket.chunk(wanted);
+					if tokens < chunk as f64 {
+						wait = cmp::max(wait, bucket.wait_time(chunk));
+					} else {
+						allowed = cmp::min(allowed, tokens as usize);
+					}
+				};
+
+				if let Some(ref mut bucket) = self.connection {
+					check(bucket);
+				}
+				if let Some(ref global) = self.global {
+					check(&mut *global.lock());
+				}
+			}
+
+			if wait == Duration::new(0, 0) {
+				return Ok(Async::Ready(allowed));
+			}
+
+			self.delay = Some(delay(event_loop, wait));
+		}
+	}
+
+	/// Removes the tokens of `bytes` bytes that have been transferred.
+	pub fn consume(&mut self, bytes: usize) {
+		if let Some(ref mut bucket) = self.connection {
+			bucket.consume(bytes);
+		}
+		if let Some(ref global) = self.global {
+			global.lock().consume(bytes);
+		}
+	}
+}
+
+// Starts a timer of `wait` on the event loop, and returns a receiver that is signaled when it
+// fires. A `Timeout` can only be created on the thread of the event loop.
+fn delay(event_loop: &Remote, wait: Duration) -> oneshot::Receiver<Result<(), IoError>> {
+	let (tx, rx) = oneshot::channel();
+	event_loop.spawn(move |handle| {
+		future::result(Timeout::new(wait, handle))
+			.flatten()
+			.then(move |result| -> Result<(), ()> {
+				// Ignoring errors if the connection has been destroyed in the meantime.
+				let _ = tx.send(result);
+				Ok(())
+			})
+	});
+	rx
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Bucket;
+	use std::time::{Duration, Instant};
+
+	#[test]
+	fn refill() {
+		let mut bucket = Bucket::new(1000);
+		let start = Instant::now();
+		assert_eq!(bucket.available(start), 1000.0);
+
+		bucket.consume(1200);
+		assert_eq!(bucket.available(start), -200.0);
+		assert_eq!(bucket.chunk(5000), 10);
+		assert_eq!(bucket.wait_time(300), Duration::from_millis(500));
+
+		assert_eq!(bucket.available(start + Duration::from_millis(500)), 300.0);
+		// Never more than one second worth of tokens.
+		assert_eq!(bucket.available(start + Duration::from_secs(10)), 1000.0);
+	}
+}
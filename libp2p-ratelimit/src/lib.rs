@@ -0,0 +1,318 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+// TODO: use this once stable ; for now we just copy-paste the content of the README.md
+//#![doc(include = "../README.md")]
+
+//! Wrapper around a `Transport` that limits the number of bytes per second that can be read from
+//! and written to its connections.
+//!
+//! Useful for nodes that run on metered links. The limits are token buckets, and can apply to
+//! all the connections together, to each connection individually, or both.
+//!
+//! # Usage
+//!
+//! ```
+//! extern crate libp2p_ratelimit;
+//! extern crate libp2p_tcp_transport;
+//! extern crate tokio_core;
+//!
+//! use libp2p_ratelimit::{RateLimited, RateLimits};
+//! use libp2p_tcp_transport::TcpConfig;
+//! use tokio_core::reactor::Core;
+//!
+//! # fn main() {
+//! let core = Core::new().unwrap();
+//! let tcp = TcpConfig::new(core.handle());
+//! // At most 1 MB/s of downloads and 256 kB/s of uploads in total, and 64 kB/s of uploads for
+//! // each connection.
+//! let global = RateLimits { read: Some(1024 * 1024), write: Some(256 * 1024) };
+//! let per_connection = RateLimits { read: None, write: Some(64 * 1024) };
+//! let transport = RateLimited::new(tcp, core.handle(), global, per_connection);
+//! # }
+//! ```
+//!
+//! The limits are applied to the raw connections of the underlying transport. Put the
+//! `RateLimited` below the upgrades (encryption, multiplexing, ...) so that everything that goes
+//! through the connections is accounted for.
+//!
+//! > **Note**: Each bucket can hold one second worth of bytes, which means that after a period of
+//! >           inactivity a burst of up to one second of traffic goes through immediately.
+
+extern crate futures;
+extern crate libp2p_swarm as swarm;
+extern crate multiaddr;
+extern crate parking_lot;
+extern crate tokio_core;
+extern crate tokio_io;
+
+#[cfg(test)]
+extern crate libp2p_tcp_transport as tcp;
+
+mod bucket;
+
+use bucket::{Bucket, Limiter};
+use futures::{Async, Future, IntoFuture, Poll, Stream};
+use multiaddr::Multiaddr;
+use parking_lot::Mutex;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::sync::Arc;
+use swarm::Transport;
+use tokio_core::reactor::{Handle, Remote};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Maximum number of bytes per second in each direction. `None` means no limit.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct RateLimits {
+	/// Maximum number of bytes read per second.
+	pub read: Option<usize>,
+	/// Maximum number of bytes written per second.
+	pub write: Option<usize>,
+}
+
+/// Wraps around a `Transport` and limits the bandwidth of its connections.
+#[derive(Clone)]
+pub struct RateLimited<T> {
+	inner: T,
+	event_loop: Remote,
+	// Buckets shared by all the connections.
+	global_read: Option<Arc<Mutex<Bucket>>>,
+	global_write: Option<Arc<Mutex<Bucket>>>,
+	// Limits of each individual connection.
+	per_connection: RateLimits,
+}
+
+impl<T> RateLimited<T> {
+	/// Wraps around `inner`. The limits of `global` apply to all the connections together,
+	/// while the limits of `per_connection` apply to each connection individually.
+	///
+	/// The `Handle` is used to wake up the connections once they are allowed to transfer data
+	/// again. The timers run on its event loop, which lets the connections be used from any
+	/// thread.
+	///
+	/// # Panic
+	///
+	/// Panics if one of the limits is `Some(0)`.
+	pub fn new(inner: T, handle: Handle, global: RateLimits, per_connection: RateLimits)
+			   -> RateLimited<T>
+	{
+		let bucket = |rate: Option<usize>| rate.map(|rate| Arc::new(Mutex::new(Bucket::new(rate))));
+		assert_ne!(per_connection.read, Some(0), "rate limits must not be zero");
+		assert_ne!(per_connection.write, Some(0), "rate limits must not be zero");
+
+		RateLimited {
+			inner: inner,
+			event_loop: handle.remote().clone(),
+			global_read: bucket(global.read),
+			global_write: bucket(global.write),
+			per_connection: per_connection,
+		}
+	}
+
+	// Wraps around a connection of the underlying transport.
+	fn wrap<C>(&self, inner: C) -> Connection<C> {
+		Connection {
+			inner: inner,
+			event_loop: self.event_loop.clone(),
+			read: Limiter::new(self.per_connection.read.map(Bucket::new), self.global_read.clone()),
+			write: Limiter::new(self.per_connection.write.map(Bucket::new),
+								self.global_write.clone()),
+		}
+	}
+}
+
+impl<T> Transport for RateLimited<T>
+	where T: Transport + 'static,
+		  T::Listener: Send,
+		  T::ListenerUpgrade: Send,
+		  <T::Dial as IntoFuture>::Future: Send,
+{
+	type RawConn = Connection<T::RawConn>;
+	type Listener = Box<Stream<Item = (Self::ListenerUpgrade, Multiaddr), Error = IoError> + Send>;
+	type ListenerUpgrade = Box<Future<Item = Self::RawConn, Error = IoError> + Send>;
+	type Dial = Box<Future<Item = Self::RawConn, Error = IoError> + Send>;
+
+	fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+		let limits = RateLimited {
+			inner: (),
+			event_loop: self.event_loop,
+			global_read: self.global_read,
+			global_write: self.global_write,
+			per_connection: self.per_connection,
+		};
+
+		match self.inner.listen_on(addr) {
+			Ok((listener, addr)) => {
+				let listener = listener.map(move |(upgrade, addr)| {
+					let limits = limits.clone();
+					let upgrade = upgrade.map(move |conn| limits.wrap(conn));
+					(Box::new(upgrade) as Box<Future<Item = _, Error = _> + Send>, addr)
+				});
+				Ok((Box::new(listener), addr))
+			}
+			Err((inner, addr)) => Err((limits.with_inner(inner), addr)),
+		}
+	}
+
+	fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+		let limits = RateLimited {
+			inner: (),
+			event_loop: self.event_loop,
+			global_read: self.global_read,
+			global_write: self.global_write,
+			per_connection: self.per_connection,
+		};
+
+		match self.inner.dial(addr) {
+			Ok(dial) => Ok(Box::new(dial.into_future().map(move |conn| limits.wrap(conn)))),
+			Err((inner, addr)) => Err((limits.with_inner(inner), addr)),
+		}
+	}
+
+	#[inline]
+	fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+		self.inner.nat_traversal(server, observed)
+	}
+
+	#[inline]
+	fn expand_listen_addr(&self, addr: &Multiaddr) -> Vec<Multiaddr> {
+		self.inner.expand_listen_addr(addr)
+	}
+}
+
+impl RateLimited<()> {
+	// Puts back a transport after it has been taken out with `listen_on()` or `dial()`.
+	#[inline]
+	fn with_inner<T>(self, inner: T) -> RateLimited<T> {
+		RateLimited {
+			inner: inner,
+			event_loop: self.event_loop,
+			global_read: self.global_read,
+			global_write: self.global_write,
+			per_connection: self.per_connection,
+		}
+	}
+}
+
+/// Connection of a `RateLimited` transport.
+pub struct Connection<C> {
+	inner: C,
+	event_loop: Remote,
+	read: Limiter,
+	write: Limiter,
+}
+
+impl<C> Read for Connection<C>
+	where C: Read
+{
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+		if buf.is_empty() {
+			return self.inner.read(buf);
+		}
+
+		match self.read.poll_allowance(buf.len(), &self.event_loop)? {
+			Async::Ready(allowed) => {
+				let num = self.inner.read(&mut buf[.. allowed])?;
+				self.read.consume(num);
+				Ok(num)
+			}
+			Async::NotReady => Err(IoErrorKind::WouldBlock.into()),
+		}
+	}
+}
+
+impl<C> AsyncRead for Connection<C>
+	where C: AsyncRead
+{
+	#[inline]
+	unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+		self.inner.prepare_uninitialized_buffer(buf)
+	}
+}
+
+impl<C> Write for Connection<C>
+	where C: Write
+{
+	fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+		if buf.is_empty() {
+			return self.inner.write(buf);
+		}
+
+		match self.write.poll_allowance(buf.len(), &self.event_loop)? {
+			Async::Ready(allowed) => {
+				let num = self.inner.write(&buf[.. allowed])?;
+				self.write.consume(num);
+				Ok(num)
+			}
+			Async::NotReady => Err(IoErrorKind::WouldBlock.into()),
+		}
+	}
+
+	#[inline]
+	fn flush(&mut self) -> Result<(), IoError> {
+		self.inner.flush()
+	}
+}
+
+impl<C> AsyncWrite for Connection<C>
+	where C: AsyncWrite
+{
+	#[inline]
+	fn shutdown(&mut self) -> Poll<(), IoError> {
+		self.inner.shutdown()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{RateLimited, RateLimits};
+	use futures::{Future, Stream};
+	use std::time::{Duration, Instant};
+	use swarm::Transport;
+	use tcp::TcpConfig;
+	use tokio_core::reactor::Core;
+	use tokio_io;
+
+	#[test]
+	fn limits_writes() {
+		let mut core = Core::new().unwrap();
+		let limits = RateLimits { read: None, write: Some(10_000) };
+		let transport = RateLimited::new(TcpConfig::new(core.handle()), core.handle(),
+										 RateLimits::default(), limits);
+
+		let (listener, addr) = transport.clone()
+			.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+			.unwrap();
+		let listener = listener
+			.into_future()
+			.map_err(|(err, _)| err)
+			.and_then(|(upgrade, _)| upgrade.unwrap().0)
+			.and_then(|conn| tokio_io::io::read_exact(conn, vec![0; 20_000]));
+
+		let dialer = transport.dial(addr)
+			.unwrap()
+			.and_then(|conn| tokio_io::io::write_all(conn, vec![5; 20_000]));
+
+		let start = Instant::now();
+		let ((_, received), _) = core.run(listener.join(dialer)).unwrap();
+		assert_eq!(received, vec![5; 20_000]);
+		// The bucket starts with 10 kB, and the next 10 kB take one second.
+		assert!(start.elapsed() >= Duration::from_millis(900));
+	}
+}
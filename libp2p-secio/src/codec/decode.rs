@@ -30,6 +30,10 @@ use futures::StartSend;
 use futures::sink::Sink;
 use futures::stream::Stream;
 use ring::hmac;
+use std::collections::VecDeque;
+
+/// Maximum number of frames that a `DecoderMiddleware` buffers besides the one that it returns.
+pub const DECODE_AHEAD_FRAMES: usize = 4;
 
 /// Wraps around a `Stream<Item = BytesMut>`. The buffers produced by the underlying stream
 /// are decoded using the cipher and hmac.
@@ -38,44 +42,50 @@ use ring::hmac;
 /// prefix. The mechanism for removing the length prefix and splitting the incoming data into
 /// frames isn't handled by this module.
 ///
+/// Whenever the middleware is polled, it drains up to `DECODE_AHEAD_FRAMES + 1` frames that are
+/// already available on the underlying stream, and verifies and decrypts them in the polling
+/// task. The frames that aren't returned are buffered and answer the next polls without touching
+/// the underlying stream. This only batches the work of a poll: decryption still happens
+/// synchronously and doesn't overlap with reading from the socket or with the consumer.
+///
 /// Also implements `Sink` for convenience.
 pub struct DecoderMiddleware<S> {
-	cipher_state: Box<SynchronousStreamCipher>,
+	cipher_state: Box<SynchronousStreamCipher + Send>,
 	hmac_key: hmac::VerificationKey,
 	raw_stream: S,
+	// Frames that have been verified and decrypted but not yielded yet.
+	decoded: VecDeque<Vec<u8>>,
+	// Set to true once `raw_stream` has finished or a frame couldn't be decoded.
+	finished: bool,
+	// Error to return once the frames in `decoded` have been yielded.
+	pending_error: Option<SecioError>,
 }
 
 impl<S> DecoderMiddleware<S> {
 	#[inline]
 	pub fn new(
 		raw_stream: S,
-		cipher: Box<SynchronousStreamCipher>,
+		cipher: Box<SynchronousStreamCipher + Send>,
 		hmac_key: hmac::VerificationKey,
 	) -> DecoderMiddleware<S> {
 		DecoderMiddleware {
 			cipher_state: cipher,
 			hmac_key: hmac_key,
 			raw_stream: raw_stream,
+			decoded: VecDeque::with_capacity(DECODE_AHEAD_FRAMES + 1),
+			finished: false,
+			pending_error: None,
 		}
 	}
-}
-
-impl<S> Stream for DecoderMiddleware<S>
-	where S: Stream<Item = BytesMut>,
-		  S::Error: Into<SecioError>
-{
-	type Item = Vec<u8>;
-	type Error = SecioError;
 
+	/// Returns the number of frames that have been decoded and buffered but not yielded yet.
 	#[inline]
-	fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-		let frame = match self.raw_stream.poll() {
-			Ok(Async::Ready(Some(t))) => t,
-			Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
-			Ok(Async::NotReady) => return Ok(Async::NotReady),
-			Err(err) => return Err(err.into()),
-		};
+	pub fn decoded_ahead(&self) -> usize {
+		self.decoded.len()
+	}
 
+	// Verifies the hmac of a frame and decrypts it.
+	fn decode_frame(&mut self, frame: BytesMut) -> Result<Vec<u8>, SecioError> {
 		let hmac_num_bytes = self.hmac_key.digest_algorithm().output_len;
 
 		if frame.len() < hmac_num_bytes {
@@ -94,8 +104,50 @@ impl<S> Stream for DecoderMiddleware<S>
 		// Note that there is no way to decipher in place with rust-crypto right now.
 		let mut decrypted_data = crypted_data.to_vec();
 		self.cipher_state.process(&crypted_data, &mut decrypted_data);
+		Ok(decrypted_data)
+	}
+}
 
-		Ok(Async::Ready(Some(decrypted_data)))
+impl<S> Stream for DecoderMiddleware<S>
+	where S: Stream<Item = BytesMut>,
+		  S::Error: Into<SecioError>
+{
+	type Item = Vec<u8>;
+	type Error = SecioError;
+
+	fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+		// Synchronously decode the frames that are already available, buffering
+		// `DECODE_AHEAD_FRAMES` of them besides the one that we return.
+		while !self.finished && self.decoded.len() <= DECODE_AHEAD_FRAMES {
+			match self.raw_stream.poll() {
+				Ok(Async::Ready(Some(frame))) => match self.decode_frame(frame) {
+					Ok(decoded) => self.decoded.push_back(decoded),
+					Err(err) => {
+						self.finished = true;
+						self.pending_error = Some(err);
+					},
+				},
+				Ok(Async::Ready(None)) => self.finished = true,
+				Ok(Async::NotReady) => break,
+				Err(err) => {
+					self.finished = true;
+					self.pending_error = Some(err.into());
+				},
+			}
+		}
+
+		if let Some(frame) = self.decoded.pop_front() {
+			return Ok(Async::Ready(Some(frame)));
+		}
+
+		if !self.finished {
+			return Ok(Async::NotReady);
+		}
+
+		match self.pending_error.take() {
+			Some(err) => Err(err),
+			None => Ok(Async::Ready(None)),
+		}
 	}
 }
 
@@ -24,6 +24,13 @@
 use self::decode::DecoderMiddleware;
 use self::encode::EncoderMiddleware;
 
+use crypto::aes::KeySize;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use crypto::{aesni, util};
+use crypto::aessafe::{AesSafe128EncryptorX8, AesSafe192EncryptorX8, AesSafe256EncryptorX8};
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use crypto::blockmodes::CtrMode;
+use crypto::blockmodes::CtrModeX8;
 use crypto::symmetriccipher::SynchronousStreamCipher;
 use ring::hmac;
 use tokio_io::{AsyncRead, AsyncWrite};
@@ -42,9 +49,9 @@ pub type FullCodec<S> = DecoderMiddleware<EncoderMiddleware<length_delimited::Fr
 /// hash algorithm (which are generally decided during the handshake).
 pub fn full_codec<S>(
 	socket: length_delimited::Framed<S>,
-	cipher_encoding: Box<SynchronousStreamCipher>,
+	cipher_encoding: Box<SynchronousStreamCipher + Send>,
 	encoding_hmac: hmac::SigningKey,
-	cipher_decoder: Box<SynchronousStreamCipher>,
+	cipher_decoder: Box<SynchronousStreamCipher + Send>,
 	decoding_hmac: hmac::VerificationKey,
 ) -> FullCodec<S>
 	where S: AsyncRead + AsyncWrite
@@ -55,17 +62,47 @@ pub fn full_codec<S>(
 	codec
 }
 
+/// Builds an AES cipher in CTR mode. Same as `crypto::aes::ctr`, except that the cipher is `Send`,
+/// so that the connections can be moved between threads.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn ctr(key_size: KeySize, key: &[u8], iv: &[u8]) -> Box<SynchronousStreamCipher + Send> {
+	if util::supports_aesni() {
+		let encryptor = aesni::AesNiEncryptor::new(key_size, key);
+		Box::new(CtrMode::new(encryptor, iv.to_vec()))
+	} else {
+		ctr_safe(key_size, key, iv)
+	}
+}
+
+/// Builds an AES cipher in CTR mode. Same as `crypto::aes::ctr`, except that the cipher is `Send`,
+/// so that the connections can be moved between threads.
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+#[inline]
+pub fn ctr(key_size: KeySize, key: &[u8], iv: &[u8]) -> Box<SynchronousStreamCipher + Send> {
+	ctr_safe(key_size, key, iv)
+}
+
+// Builds a cipher that doesn't depend on the instructions of the processor.
+fn ctr_safe(key_size: KeySize, key: &[u8], iv: &[u8]) -> Box<SynchronousStreamCipher + Send> {
+	match key_size {
+		KeySize::KeySize128 => Box::new(CtrModeX8::new(AesSafe128EncryptorX8::new(key), iv)),
+		KeySize::KeySize192 => Box::new(CtrModeX8::new(AesSafe192EncryptorX8::new(key), iv)),
+		KeySize::KeySize256 => Box::new(CtrModeX8::new(AesSafe256EncryptorX8::new(key), iv)),
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	extern crate tokio_core;
 	use super::DecoderMiddleware;
+	use super::decode::DECODE_AHEAD_FRAMES;
 	use super::EncoderMiddleware;
 	use super::full_codec;
 	use bytes::BytesMut;
 	use crypto::aessafe::AesSafe256Encryptor;
 	use crypto::blockmodes::CtrMode;
 	use error::SecioError;
-	use futures::{Future, Sink, Stream};
+	use futures::{stream, Async, Future, Sink, Stream};
 	use futures::sync::mpsc::channel;
 	use rand;
 	use ring::digest::SHA256;
@@ -109,6 +146,77 @@ mod tests {
 		assert_eq!(decoded.unwrap(), data);
 	}
 
+	// Encrypts `frames` with random keys, and returns the ciphertext frames and the keys.
+	fn encrypted_frames(frames: &[BytesMut]) -> (Vec<BytesMut>, [u8; 32], [u8; 32]) {
+		let (data_tx, data_rx) = channel::<BytesMut>(256);
+		let data_tx = data_tx.sink_map_err::<_, IoError>(|_| panic!());
+		let data_rx = data_rx.map_err::<IoError, _>(|_| panic!());
+
+		let cipher_key: [u8; 32] = rand::random();
+		let hmac_key: [u8; 32] = rand::random();
+
+		let encoder =
+			EncoderMiddleware::new(
+				data_tx,
+				Box::new(CtrMode::new(AesSafe256Encryptor::new(&cipher_key), vec![0; 16])),
+				SigningKey::new(&SHA256, &hmac_key),
+			);
+		let (encoder, _) = encoder.send_all(stream::iter_ok::<_, IoError>(frames.to_vec()))
+			.wait()
+			.unwrap();
+		drop(encoder);
+
+		(data_rx.collect().wait().unwrap(), cipher_key, hmac_key)
+	}
+
+	#[test]
+	fn frames_are_decoded_ahead() {
+		let frames = (0 .. 10u8).map(|n| BytesMut::from(vec![n; 32])).collect::<Vec<_>>();
+		let (encrypted, cipher_key, hmac_key) = encrypted_frames(&frames);
+
+		let mut decoder =
+			DecoderMiddleware::new(
+				stream::iter_ok::<_, IoError>(encrypted),
+				Box::new(CtrMode::new(AesSafe256Encryptor::new(&cipher_key), vec![0; 16])),
+				VerificationKey::new(&SHA256, &hmac_key),
+			);
+
+		// Yielding the first frame decodes the following ones in advance.
+		match decoder.poll() {
+			Ok(Async::Ready(Some(frame))) => assert_eq!(frame, frames[0].to_vec()),
+			_ => panic!(),
+		}
+		assert_eq!(decoder.decoded_ahead(), DECODE_AHEAD_FRAMES);
+
+		let decoded = decoder.collect().wait().unwrap();
+		assert_eq!(decoded, frames[1..].iter().map(|f| f.to_vec()).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn frames_decoded_ahead_are_yielded_before_error() {
+		let frames = (0 .. 4u8).map(|n| BytesMut::from(vec![n; 32])).collect::<Vec<_>>();
+		let (mut encrypted, cipher_key, hmac_key) = encrypted_frames(&frames);
+		encrypted[2][0] ^= 0xff;
+
+		let mut decoder =
+			DecoderMiddleware::new(
+				stream::iter_ok::<_, IoError>(encrypted),
+				Box::new(CtrMode::new(AesSafe256Encryptor::new(&cipher_key), vec![0; 16])),
+				VerificationKey::new(&SHA256, &hmac_key),
+			);
+
+		for frame in &frames[.. 2] {
+			match decoder.poll() {
+				Ok(Async::Ready(Some(decoded))) => assert_eq!(decoded, frame.to_vec()),
+				_ => panic!(),
+			}
+		}
+		match decoder.poll() {
+			Err(SecioError::HmacNotMatching) => (),
+			_ => panic!(),
+		}
+	}
+
 	#[test]
 	fn full_codec_encode_then_decode() {
 		let mut core = Core::new().unwrap();
@@ -20,8 +20,8 @@
 
 use algo_support;
 use bytes::BytesMut;
-use codec::{full_codec, FullCodec};
-use crypto::aes::{ctr, KeySize};
+use codec::{ctr, full_codec, FullCodec};
+use crypto::aes::KeySize;
 use error::SecioError;
 use futures::Future;
 use futures::future;
@@ -34,8 +34,7 @@ use ring::{agreement, digest, rand};
 use ring::agreement::EphemeralPrivateKey;
 use ring::hmac::{SigningKey, SigningContext, VerificationKey};
 use ring::rand::SecureRandom;
-use ring::signature::{RSAKeyPair, RSASigningState, RSA_PKCS1_SHA256, RSA_PKCS1_2048_8192_SHA256};
-use ring::signature::verify as signature_verify;
+use libp2p_keys::{PublicKey, Signer};
 use std::cmp::{self, Ordering};
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::mem;
@@ -48,17 +47,18 @@ use untrusted::Input as UntrustedInput;
 /// Performs a handshake on the given socket.
 ///
 /// This function expects that the remote is identified with `remote_public_key`, and the remote
-/// will expect that we are identified with `local_public_key`. Obviously `local_private_key` must
-/// be paired with `local_public_key`. Any mismatch somewhere will produce a `SecioError`.
+/// will expect that we are identified with `local_public_key`. Obviously `local_signer` must sign
+/// with the private key paired with `local_public_key`. Any mismatch somewhere will produce a
+/// `SecioError`.
 ///
 /// On success, returns an object that implements the `Sink` and `Stream` trait whose items are
 /// buffers of data, plus the public key of the remote.
 pub fn handshake<'a, S: 'a>(
 	socket: S,
 	local_public_key: Vec<u8>,
-	local_private_key: Arc<RSAKeyPair>,
-) -> Box<Future<Item = (FullCodec<S>, Vec<u8>), Error = SecioError> + 'a>
-	where S: AsyncRead + AsyncWrite
+	local_signer: Arc<Signer>,
+) -> Box<Future<Item = (FullCodec<S>, Vec<u8>), Error = SecioError> + Send + 'a>
+	where S: AsyncRead + AsyncWrite + Send
 {
 	// TODO: could be rewritten as a coroutine once coroutines land in stable Rust
 
@@ -67,7 +67,7 @@ pub fn handshake<'a, S: 'a>(
 	struct HandshakeContext {
 		// Filled with this function's parameters.
 		local_public_key: Vec<u8>,
-		local_private_key: Arc<RSAKeyPair>,
+		local_signer: Arc<Signer>,
 
 		rng: rand::SystemRandom,
 		// Locally-generated random number. The array size can be changed without any repercussion.
@@ -110,7 +110,7 @@ pub fn handshake<'a, S: 'a>(
 
 	let context = HandshakeContext {
 		local_public_key: local_public_key,
-		local_private_key: local_private_key,
+		local_signer: local_signer,
 		rng: rand::SystemRandom::new(),
 		local_nonce: Default::default(),
 		local_public_key_in_protobuf_bytes: Vec::new(),
@@ -291,45 +291,32 @@ pub fn handshake<'a, S: 'a>(
 		// Send the ephemeral pub key to the remote in an `Exchange` struct. The `Exchange` also
 		// contains a signature of the two propositions encoded with our static public key.
 		.and_then(|(socket, mut context, tmp_priv)| {
-			let exchange = {
+			let local_tmp_pub_key = {
 				let local_tmp_pub_key = &mut context.local_tmp_pub_key[..tmp_priv.public_key_len()];
 				tmp_priv.compute_public_key(local_tmp_pub_key).unwrap();
-				context.local_tmp_priv_key = Some(tmp_priv);
-
-				let mut data_to_sign = context.local_proposition_bytes.clone();
-				data_to_sign.extend_from_slice(&context.remote_proposition_bytes);
-				data_to_sign.extend_from_slice(local_tmp_pub_key);
-
-				let mut exchange = Exchange::new();
-				exchange.set_epubkey(local_tmp_pub_key.to_vec());
-				exchange.set_signature({
-					let mut state = match RSASigningState::new(context.local_private_key.clone()) {
-						Ok(s) => s,
-						Err(_) => {
-							debug!(target: "libp2p-secio", "failed to sign local exchange");
-							return Err(SecioError::SigningFailure);
-						},
-					};
-					let mut signature = vec![0; context.local_private_key.public_modulus_len()];
-					match state.sign(&RSA_PKCS1_SHA256, &context.rng, &data_to_sign,
-									 &mut signature)
-					{
-						Ok(_) => (),
-						Err(_) => {
-							debug!(target: "libp2p-secio", "failed to sign local exchange");
-							return Err(SecioError::SigningFailure);
-						},
-					};
-
-					signature
-				});
-				exchange
+				local_tmp_pub_key.to_vec()
 			};
+			context.local_tmp_priv_key = Some(tmp_priv);
 
-			let local_exch = exchange.write_to_bytes()
-				.expect("can only fail if the protobuf msg is malformed, which can't happen for \
-						 this message in particular");
-			Ok((BytesMut::from(local_exch), socket, context))
+			let mut data_to_sign = context.local_proposition_bytes.clone();
+			data_to_sign.extend_from_slice(&context.remote_proposition_bytes);
+			data_to_sign.extend_from_slice(&local_tmp_pub_key);
+
+			context.local_signer.sign(&data_to_sign)
+				.map_err(|_| {
+					debug!(target: "libp2p-secio", "failed to sign local exchange");
+					SecioError::SigningFailure
+				})
+				.map(move |signature| {
+					let mut exchange = Exchange::new();
+					exchange.set_epubkey(local_tmp_pub_key);
+					exchange.set_signature(signature);
+
+					let local_exch = exchange.write_to_bytes()
+						.expect("can only fail if the protobuf msg is malformed, which can't \
+								 happen for this message in particular");
+					(BytesMut::from(local_exch), socket, context)
+				})
 		})
 
 		// Send our local `Exchange`.
@@ -377,19 +364,18 @@ pub fn handshake<'a, S: 'a>(
 			data_to_verify.extend_from_slice(&context.local_proposition_bytes);
 			data_to_verify.extend_from_slice(remote_exch.get_epubkey());
 
-			// TODO: The ring library doesn't like some stuff in our DER public key, therefore
-			//       we scrap the first 24 bytes of the key. A proper fix would be to write a DER
-			//       parser, but that's not trivial.
-			match signature_verify(&RSA_PKCS1_2048_8192_SHA256,
-								UntrustedInput::from(&context.remote_public_key[24..]),
-								UntrustedInput::from(&data_to_verify),
-								UntrustedInput::from(remote_exch.get_signature()))
-			{
-				Ok(()) => (),
+			let decoded = PublicKey::from_protobuf_encoding(&context.remote_public_key_in_protobuf_bytes);
+			let remote_public_key = match decoded {
+				Ok(key) => key,
 				Err(_) => {
-					debug!(target: "libp2p-secio", "failed to verify the remote's signature");
-					return Err(SecioError::SignatureVerificationFailed)
+					debug!(target: "libp2p-secio", "failed to decode the remote's public key");
+					return Err(SecioError::HandshakeParsingFailure);
 				},
+			};
+
+			if !remote_public_key.verify(&data_to_verify, remote_exch.get_signature()) {
+				debug!(target: "libp2p-secio", "failed to verify the remote's signature");
+				return Err(SecioError::SignatureVerificationFailed)
 			}
 
 			trace!(target: "libp2p-secio", "successfully verified the remote's signature");
@@ -443,8 +429,8 @@ pub fn handshake<'a, S: 'a>(
 					(cipher, hmac)
 				};
 
-				Ok(full_codec(socket, Box::new(encoding_cipher), encoding_hmac,
-							  Box::new(decoding_cipher), decoding_hmac))
+				Ok(full_codec(socket, encoding_cipher, encoding_hmac, decoding_cipher,
+							  decoding_hmac))
 			});
 
 			match codec {
@@ -523,16 +509,15 @@ mod tests {
 	extern crate tokio_core;
 	use super::handshake;
 	use super::stretch_key;
+	use libp2p_keys::Keypair;
 	use futures::Future;
 	use futures::Stream;
 	use ring::digest::SHA256;
 	use ring::hmac::SigningKey;
-	use ring::signature::RSAKeyPair;
 	use std::sync::Arc;
 	use self::tokio_core::net::TcpListener;
 	use self::tokio_core::net::TcpStream;
 	use self::tokio_core::reactor::Core;
-	use untrusted::Input;
 
 	#[test]
 	fn handshake_with_self_succeeds() {
@@ -540,13 +525,13 @@ mod tests {
 
 		let private_key1 = {
 			let pkcs8 = include_bytes!("../tests/test-private-key.pk8");
-			Arc::new(RSAKeyPair::from_pkcs8(Input::from(&pkcs8[..])).unwrap())
+			Arc::new(Keypair::rsa_from_pkcs8(pkcs8).unwrap())
 		};
 		let public_key1 = include_bytes!("../tests/test-public-key.der").to_vec();
 
 		let private_key2 = {
 			let pkcs8 = include_bytes!("../tests/test-private-key-2.pk8");
-			Arc::new(RSAKeyPair::from_pkcs8(Input::from(&pkcs8[..])).unwrap())
+			Arc::new(Keypair::rsa_from_pkcs8(pkcs8).unwrap())
 		};
 		let public_key2 = include_bytes!("../tests/test-public-key-2.der").to_vec();
 
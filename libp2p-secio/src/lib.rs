@@ -69,6 +69,10 @@
 //! # }
 //! ```
 //!
+//! The output of the upgrade is a `SecioOutput`, which implements `AsyncRead` and `AsyncWrite`.
+//! Its `remote_public_key()` method returns the public key that the remote used during the
+//! handshake, which can be used to identify the remote.
+//!
 //! # Manual usage
 //!
 //! > **Note**: You are encouraged to use `SecioConfig` as described above.
@@ -82,6 +86,7 @@
 extern crate bytes;
 extern crate crypto;
 extern crate futures;
+extern crate libp2p_keys;
 extern crate libp2p_swarm;
 #[macro_use]
 extern crate log;
@@ -93,19 +98,19 @@ extern crate tokio_io;
 extern crate untrusted;
 
 pub use self::error::SecioError;
+pub use libp2p_keys::Signer;
 
 use bytes::{Bytes, BytesMut};
 use futures::{Future, Poll, StartSend, Sink, Stream};
 use futures::stream::MapErr as StreamMapErr;
+use libp2p_keys::{rsa, Keypair, PublicKey};
 use libp2p_swarm::Multiaddr;
-use ring::signature::RSAKeyPair;
 use rw_stream_sink::RwStreamSink;
 use std::error::Error;
-use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
 use std::iter;
 use std::sync::Arc;
 use tokio_io::{AsyncRead, AsyncWrite};
-use untrusted::Input;
 
 mod algo_support;
 mod codec;
@@ -144,6 +149,13 @@ pub struct SecioConfig {
 ///												include_bytes!("public.der"));
 /// ```
 ///
+/// # Hardware-backed keys
+///
+/// If the private key is held by a hardware security module or a secure enclave, you can use
+/// `rsa_from_signer` with a custom implementation of the `Signer` trait of `libp2p-keys`. The
+/// private key is then never loaded in memory, and the same signer can be shared with the other
+/// users of the identity key.
+///
 #[derive(Clone)]
 pub struct SecioKeyPair {
 	inner: SecioKeyPairInner,
@@ -154,13 +166,29 @@ impl SecioKeyPair {
 							 -> Result<SecioKeyPair, Box<Error + Send + Sync>>
 		where P: Into<Vec<u8>>
 	{
-		let private = RSAKeyPair::from_pkcs8(Input::from(&private[..]))
-			.map_err(|err| Box::new(err))?;
+		let private = Keypair::rsa_from_pkcs8(private).map_err(|err| Box::new(err))?;
+		SecioKeyPair::rsa_from_signer(public, Arc::new(private))
+	}
+
+	/// Builds a `SecioKeyPair` from an RSA public key in the DER format, and an object that
+	/// signs data with the corresponding private key.
+	///
+	/// Returns an error if `public` isn't an RSA public key, or if it isn't the public key of
+	/// `signer`.
+	pub fn rsa_from_signer<P>(public: P, signer: Arc<Signer>)
+							  -> Result<SecioKeyPair, Box<Error + Send + Sync>>
+		where P: Into<Vec<u8>>
+	{
+		let public = public.into();
+		let key = rsa::PublicKey::from_pkix(&public).map_err(|err| Box::new(err))?;
+		if signer.public() != PublicKey::Rsa(key) {
+			return Err("the signer doesn't sign with the given RSA public key".into());
+		}
 
 		Ok(SecioKeyPair {
 			inner: SecioKeyPairInner::Rsa {
-				public: public.into(),
-				private: Arc::new(private),
+				public: public,
+				signer: signer,
 			}
 		})
 	}
@@ -171,7 +199,7 @@ impl SecioKeyPair {
 enum SecioKeyPairInner {
 	Rsa {
 		public: Vec<u8>,
-		private: Arc<RSAKeyPair>,
+		signer: Arc<Signer>,
 	}
 }
 
@@ -182,15 +210,10 @@ pub enum SecioPublicKey<'a> {
 }
 
 impl<S> libp2p_swarm::ConnectionUpgrade<S> for SecioConfig
-	where S: AsyncRead + AsyncWrite + 'static
+	where S: AsyncRead + AsyncWrite + Send + 'static
 {
-	type Output = RwStreamSink<
-		StreamMapErr<
-			SecioMiddleware<S>,
-			fn(SecioError) -> IoError,
-		>,
-	>;
-	type Future = Box<Future<Item = Self::Output, Error = IoError>>;
+	type Output = SecioOutput<S>;
+	type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
 	type NamesIter = iter::Once<(Bytes, ())>;
 	type UpgradeIdentifier = ();
 
@@ -210,8 +233,12 @@ impl<S> libp2p_swarm::ConnectionUpgrade<S> for SecioConfig
 			self.key,
 		);
 		let wrapped = fut.map(|stream_sink| {
+			let remote_pubkey_der = stream_sink.remote_pubkey_der.clone();
 			let mapped = stream_sink.map_err(map_err as fn(_) -> _);
-			RwStreamSink::new(mapped)
+			SecioOutput {
+				stream: RwStreamSink::new(mapped),
+				remote_pubkey_der: remote_pubkey_der,
+			}
 		}).map_err(map_err);
 		Box::new(wrapped)
 	}
@@ -223,6 +250,64 @@ fn map_err(err: SecioError) -> IoError {
 	IoError::new(IoErrorKind::InvalidData, err)
 }
 
+/// Output of the secio upgrade. Encrypted and authenticated connection with the remote.
+///
+/// Implements `AsyncRead` and `AsyncWrite`, and gives access to the public key that the remote
+/// used during the handshake.
+pub struct SecioOutput<S>
+	where S: AsyncRead + AsyncWrite
+{
+	stream: RwStreamSink<StreamMapErr<SecioMiddleware<S>, fn(SecioError) -> IoError>>,
+	remote_pubkey_der: Vec<u8>,
+}
+
+impl<S> SecioOutput<S>
+	where S: AsyncRead + AsyncWrite
+{
+	/// Returns the public key of the remote in the `DER` format.
+	#[inline]
+	pub fn remote_public_key(&self) -> SecioPublicKey {
+		SecioPublicKey::Rsa(&self.remote_pubkey_der)
+	}
+}
+
+impl<S> Read for SecioOutput<S>
+	where S: AsyncRead + AsyncWrite
+{
+	#[inline]
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+		self.stream.read(buf)
+	}
+}
+
+impl<S> AsyncRead for SecioOutput<S>
+	where S: AsyncRead + AsyncWrite
+{
+}
+
+impl<S> Write for SecioOutput<S>
+	where S: AsyncRead + AsyncWrite
+{
+	#[inline]
+	fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+		self.stream.write(buf)
+	}
+
+	#[inline]
+	fn flush(&mut self) -> Result<(), IoError> {
+		self.stream.flush()
+	}
+}
+
+impl<S> AsyncWrite for SecioOutput<S>
+	where S: AsyncRead + AsyncWrite
+{
+	#[inline]
+	fn shutdown(&mut self) -> Poll<(), IoError> {
+		self.stream.shutdown()
+	}
+}
+
 /// Wraps around an object that implements `AsyncRead` and `AsyncWrite`.
 ///
 /// Implements `Sink` and `Stream` whose items are frames of data. Each frame is encoded
@@ -242,12 +327,12 @@ impl<S> SecioMiddleware<S>
 	pub fn handshake<'a>(
 		socket: S,
 		key_pair: SecioKeyPair,
-	) -> Box<Future<Item = SecioMiddleware<S>, Error = SecioError> + 'a>
-		where S: 'a
+	) -> Box<Future<Item = SecioMiddleware<S>, Error = SecioError> + Send + 'a>
+		where S: Send + 'a
 	{
-		let SecioKeyPairInner::Rsa { private, public } = key_pair.inner;
+		let SecioKeyPairInner::Rsa { signer, public } = key_pair.inner;
 
-		let fut = handshake::handshake(socket, public, private)
+		let fut = handshake::handshake(socket, public, signer)
 			.map(|(inner, pubkey)| {
 				SecioMiddleware {
 					inner: inner,
@@ -292,3 +377,98 @@ impl<S> Stream for SecioMiddleware<S>
 		self.inner.poll()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	extern crate libp2p_tcp_transport;
+	extern crate multiplex;
+	extern crate tokio_core;
+	use self::libp2p_tcp_transport::TcpConfig;
+	use self::multiplex::{MultiplexConfig, Substream};
+	use self::tokio_core::net::{TcpListener, TcpStream};
+	use self::tokio_core::reactor::Core;
+	use futures::{Future, Stream};
+	use futures::future::FutureResult;
+	use libp2p_swarm::{ConnectionReuse, ConnectionUpgrade, Endpoint, Multiaddr, PlainTextConfig};
+	use libp2p_swarm::{SwarmController, SwarmFuture, UpgradedNode, UpgradedNodeDial};
+	use libp2p_swarm::{UpgradedNodeIncoming, UpgradedNodeListener, UpgradedNodeListenerUpgrade};
+	use libp2p_keys::Keypair;
+	use std::io::Error as IoError;
+	use std::sync::Arc;
+	use {SecioConfig, SecioKeyPair, SecioOutput, SecioPublicKey};
+
+	#[test]
+	fn upgrade_exposes_remote_key() {
+		let mut core = Core::new().unwrap();
+
+		let public_key1 = include_bytes!("../tests/test-public-key.der").to_vec();
+		let config1 = SecioConfig {
+			key: SecioKeyPair::rsa_from_pkcs8(include_bytes!("../tests/test-private-key.pk8"),
+											  public_key1.clone()).unwrap(),
+		};
+		let public_key2 = include_bytes!("../tests/test-public-key-2.der").to_vec();
+		let config2 = SecioConfig {
+			key: SecioKeyPair::rsa_from_pkcs8(include_bytes!("../tests/test-private-key-2.pk8"),
+											  public_key2.clone()).unwrap(),
+		};
+
+		let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+		let listener_addr = listener.local_addr().unwrap();
+		let remote_addr: Multiaddr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+
+		let server_addr = remote_addr.clone();
+		let server = listener.incoming()
+			.into_future()
+			.map_err(|(err, _)| err)
+			.and_then(move |(connec, _)| {
+				config1.upgrade(connec.unwrap().0, (), Endpoint::Listener, &server_addr)
+			});
+
+		let client = TcpStream::connect(&listener_addr, &core.handle())
+			.and_then(move |stream| config2.upgrade(stream, (), Endpoint::Dialer, &remote_addr));
+
+		let (server, client) = core.run(server.join(client)).unwrap();
+		match server.remote_public_key() {
+			SecioPublicKey::Rsa(key) => assert_eq!(key, &public_key2[..]),
+		}
+		match client.remote_public_key() {
+			SecioPublicKey::Rsa(key) => assert_eq!(key, &public_key1[..]),
+		}
+	}
+
+	#[test]
+	fn rsa_from_signer_checks_public_key() {
+		let public_key = include_bytes!("../tests/test-public-key.der").to_vec();
+		let signer = Keypair::rsa_from_pkcs8(include_bytes!("../tests/test-private-key.pk8"))
+			.unwrap();
+		assert!(SecioKeyPair::rsa_from_signer(public_key.clone(), Arc::new(signer)).is_ok());
+
+		let other = Keypair::rsa_from_pkcs8(include_bytes!("../tests/test-private-key-2.pk8"))
+			.unwrap();
+		assert!(SecioKeyPair::rsa_from_signer(public_key.clone(), Arc::new(other)).is_err());
+
+		let ed25519 = Keypair::generate_ed25519().unwrap();
+		assert!(SecioKeyPair::rsa_from_signer(public_key, Arc::new(ed25519)).is_err());
+	}
+
+	#[test]
+	fn swarm_is_send() {
+		// Checked at compile time: the swarm can only be moved to another thread if the
+		// transport, the upgrades and their futures are all `Send`.
+		fn assert_send<T: Send>() {}
+
+		type Secio = UpgradedNode<TcpConfig, SecioConfig>;
+		type Transport = ConnectionReuse<Secio, MultiplexConfig>;
+		type Output = Substream<SecioOutput<TcpStream>>;
+		type Handler = fn(Output, Multiaddr) -> Result<(), IoError>;
+		type Swarm = SwarmFuture<Transport, PlainTextConfig, Handler, FutureResult<(), IoError>>;
+
+		assert_send::<Transport>();
+		assert_send::<SwarmController<Transport, PlainTextConfig>>();
+		assert_send::<Swarm>();
+		assert_send::<UpgradedNodeDial<Transport, PlainTextConfig>>();
+		assert_send::<UpgradedNodeIncoming<Transport, PlainTextConfig>>();
+		assert_send::<UpgradedNodeListener<Transport, PlainTextConfig>>();
+		assert_send::<UpgradedNodeListenerUpgrade<Transport, PlainTextConfig>>();
+	}
+}
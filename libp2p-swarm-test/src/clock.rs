@@ -0,0 +1,191 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::{Async, Future, Poll};
+use futures::task::{self, Task};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::io::Error as IoError;
+use std::sync::Arc;
+use std::time::Duration;
+use swarm::time::{Clock, Instant};
+
+/// Clock whose time only moves forward when it is told to. Cloning a `MockClock` gives another
+/// handle to the same clock.
+///
+/// The handlers of a scenario should wait with `delay()` instead of the timers of tokio, so that
+/// the scenario decides when the delays elapse. The handlers that take a `Clock`, such as the
+/// `PeriodicPingHandler` of `libp2p-ping`, can be given a `MockClock` instead of a tokio-core
+/// `Handle`.
+#[derive(Clone)]
+pub struct MockClock {
+	inner: Arc<Mutex<ClockInner>>,
+}
+
+struct ClockInner {
+	// Time at which the clock has been created.
+	start: Instant,
+	// How much the clock has advanced since `start`.
+	elapsed: Duration,
+	// Id to give to the next delay.
+	next_id: u64,
+	// The delays that are waiting, by id. Contains the value of `elapsed` that they wait for and
+	// the task to notify when it is reached.
+	waiting: HashMap<u64, (Duration, Task)>,
+}
+
+impl MockClock {
+	/// Creates a new clock. Its time starts at `Instant::now()`.
+	pub fn new() -> MockClock {
+		MockClock {
+			inner: Arc::new(Mutex::new(ClockInner {
+				start: Instant::now(),
+				elapsed: Duration::new(0, 0),
+				next_id: 0,
+				waiting: HashMap::new(),
+			})),
+		}
+	}
+
+	/// Returns the current time of the clock.
+	#[inline]
+	pub fn now(&self) -> Instant {
+		let inner = self.inner.lock();
+		inner.start + inner.elapsed
+	}
+
+	/// Moves the clock forward by `duration`, and wakes up the delays that have elapsed.
+	pub fn advance(&self, duration: Duration) {
+		let mut inner = self.inner.lock();
+		inner.elapsed += duration;
+		let elapsed = inner.elapsed;
+		let ready = inner.waiting.iter()
+			.filter(|&(_, &(until, _))| until <= elapsed)
+			.map(|(&id, _)| id)
+			.collect::<Vec<_>>();
+		for id in ready {
+			if let Some((_, task)) = inner.waiting.remove(&id) {
+				task.notify();
+			}
+		}
+	}
+
+	/// Returns a future that is ready once the clock has advanced by `duration`.
+	pub fn delay(&self, duration: Duration) -> Delay {
+		let mut inner = self.inner.lock();
+		let id = inner.next_id;
+		inner.next_id += 1;
+		Delay {
+			clock: self.clone(),
+			id: id,
+			until: inner.elapsed + duration,
+		}
+	}
+
+	// Returns how much the clock has advanced since it has been created.
+	pub(crate) fn elapsed(&self) -> Duration {
+		self.inner.lock().elapsed
+	}
+
+	// Returns the value of `elapsed()` that the next delay to elapse waits for, if any delay is
+	// waiting.
+	pub(crate) fn next_deadline(&self) -> Option<Duration> {
+		self.inner.lock().waiting.values().map(|&(until, _)| until).min()
+	}
+}
+
+impl Default for MockClock {
+	#[inline]
+	fn default() -> MockClock {
+		MockClock::new()
+	}
+}
+
+impl Clock for MockClock {
+	type Delay = Delay;
+
+	#[inline]
+	fn now(&self) -> Instant {
+		MockClock::now(self)
+	}
+
+	#[inline]
+	fn delay(&self, duration: Duration) -> Delay {
+		MockClock::delay(self, duration)
+	}
+}
+
+/// Future that is ready once a `MockClock` has advanced far enough. Created with
+/// `MockClock::delay()`.
+pub struct Delay {
+	clock: MockClock,
+	id: u64,
+	until: Duration,
+}
+
+impl Future for Delay {
+	type Item = ();
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<(), IoError> {
+		let mut inner = self.clock.inner.lock();
+		if inner.elapsed >= self.until {
+			inner.waiting.remove(&self.id);
+			return Ok(Async::Ready(()));
+		}
+
+		inner.waiting.insert(self.id, (self.until, task::current()));
+		Ok(Async::NotReady)
+	}
+}
+
+impl Drop for Delay {
+	#[inline]
+	fn drop(&mut self) {
+		self.clock.inner.lock().waiting.remove(&self.id);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::MockClock;
+	use futures::{future, Async, Future};
+	use std::time::Duration;
+
+	#[test]
+	fn delay_waits_for_advance() {
+		let clock = MockClock::new();
+		let start = clock.now();
+		let mut delay = clock.delay(Duration::from_secs(5));
+
+		future::poll_fn(|| {
+			assert_eq!(delay.poll().unwrap(), Async::NotReady);
+			assert_eq!(clock.next_deadline(), Some(Duration::from_secs(5)));
+			clock.advance(Duration::from_secs(4));
+			assert_eq!(delay.poll().unwrap(), Async::NotReady);
+			clock.advance(Duration::from_secs(1));
+			assert_eq!(delay.poll().unwrap(), Async::Ready(()));
+			Ok::<_, ()>(Async::Ready(()))
+		}).wait().unwrap();
+
+		assert_eq!(clock.now() - start, Duration::from_secs(5));
+		assert_eq!(clock.next_deadline(), None);
+	}
+}
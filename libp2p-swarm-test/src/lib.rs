@@ -0,0 +1,737 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+// TODO: use this once stable ; for now we just copy-paste the content of the README.md
+//#![doc(include = "../README.md")]
+
+//! Helpers for writing tests that involve several nodes.
+//!
+//! # Usage
+//!
+//! A `Scenario` describes a list of nodes and a list of steps. Each node runs a swarm whose
+//! transport is a `MemoryTransport`, therefore no socket is opened and the tests can run in
+//! parallel. The steps are executed in order when calling `run()`, which panics if one of them
+//! fails.
+//!
+//! Example:
+//!
+//! ```
+//! extern crate libp2p_swarm;
+//! extern crate libp2p_swarm_test;
+//!
+//! use libp2p_swarm::{PlainTextConfig, SwarmEvent};
+//! use libp2p_swarm_test::Scenario;
+//! use std::io::Error as IoError;
+//!
+//! # fn main() {
+//! Scenario::new(PlainTextConfig, |_, _| Ok::<_, IoError>(()))
+//!     .node("a")
+//!     .node("b")
+//!     .connect("a", "b")
+//!     .expect_event("b", "incoming connection", |event| match *event {
+//!         SwarmEvent::ConnectionEstablished { .. } => true,
+//!         _ => false,
+//!     })
+//!     .run();
+//! # }
+//! ```
+//!
+//! Each node listens on a `/memory/<id>` multiaddress of `libp2p-memory-transport`, whose id is
+//! picked when the scenario runs, and the `connect` steps dial this address. A node that dials
+//! another one is seen under a distinct `/memory` address that can't be dialed.
+//!
+//! # Time
+//!
+//! The scenarios don't depend on the clock of the machine. A scenario has a `MockClock` that only
+//! moves forward during an `advance_time` step, or when the nodes have nothing left to do while
+//! `expect_event` waits. In the latter case the clock jumps to the next `delay()` that it has to
+//! wake up, and `expect_event` gives up once the clock would go past its `timeout()`.
+//!
+//! The handlers that need a timer should use `delay()` on a clock that is given to the scenario
+//! with `clock()`:
+//!
+//! ```
+//! extern crate libp2p_swarm;
+//! extern crate libp2p_swarm_test;
+//!
+//! use libp2p_swarm::{PlainTextConfig, SwarmEvent};
+//! use libp2p_swarm_test::{MockClock, Scenario};
+//! use std::time::Duration;
+//!
+//! # fn main() {
+//! let clock = MockClock::new();
+//! let handler_clock = clock.clone();
+//! Scenario::new(PlainTextConfig, move |_, _| handler_clock.delay(Duration::from_secs(60)))
+//!     .clock(clock)
+//!     .node("a")
+//!     .node("b")
+//!     .connect("a", "b")
+//!     .advance_time(Duration::from_secs(60))
+//!     .expect_event("b", "closed connection", |event| match *event {
+//!         SwarmEvent::ConnectionClosed { .. } => true,
+//!         _ => false,
+//!     })
+//!     .run();
+//! # }
+//! ```
+//!
+//! The `MockClock` implements the `Clock` trait of `libp2p_swarm::time`, therefore it can also be
+//! given to the handlers that take a `Clock`, such as the `PeriodicPingHandler` of `libp2p-ping`.
+
+extern crate futures;
+extern crate libp2p_memory_transport;
+extern crate libp2p_swarm as swarm;
+extern crate multiaddr;
+extern crate parking_lot;
+extern crate tokio_core;
+
+mod clock;
+
+pub use self::clock::{Delay, MockClock};
+
+use futures::{future, Async, Future, IntoFuture, Poll, Stream};
+use futures::executor::{self, Notify};
+use libp2p_memory_transport::{Connection, MemoryTransport};
+use multiaddr::{AddrComponent, Multiaddr};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::Error as IoError;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use swarm::{ConnectionUpgrade, SwarmController, SwarmEvent, SwarmEvents, Transport};
+use swarm::transport::DummyMuxing;
+use swarm::{UpgradedNodeDial, UpgradedNodeIncoming, UpgradedNodeListener};
+use swarm::UpgradedNodeListenerUpgrade;
+use tokio_core::reactor::Core;
+
+/// Transport of the nodes of a scenario.
+pub type NodeTransport = DummyMuxing<MemoryTransport>;
+
+/// Describes the nodes of a test and what should happen between them. See the crate-level
+/// documentation.
+pub struct Scenario<C, H> {
+	upgrade: C,
+	handler: H,
+	nodes: Vec<String>,
+	steps: Vec<Step>,
+	clock: MockClock,
+	timeout: Duration,
+}
+
+// Step of a scenario.
+enum Step {
+	// The first node dials the second one.
+	Connect(String, String),
+	// Wait until the node produces an event that matches. Contains a description of the event.
+	ExpectEvent(String, String, Box<FnMut(&SwarmEvent) -> bool>),
+	// Advance the clock of the scenario.
+	AdvanceTime(Duration),
+}
+
+impl<C, H, F> Scenario<C, H>
+	where C: ConnectionUpgrade<Connection> + Clone + 'static,
+		  C::NamesIter: Clone,
+		  H: FnMut(C::Output, Multiaddr) -> F + Clone + 'static,
+		  F: IntoFuture<Item = (), Error = IoError> + 'static,
+		  F::Future: Send,
+		  UpgradedNodeDial<NodeTransport, C>: Send,
+		  UpgradedNodeIncoming<NodeTransport, C>: Send,
+		  UpgradedNodeListener<NodeTransport, C>: Send,
+		  UpgradedNodeListenerUpgrade<NodeTransport, C>: Send,
+{
+	/// Creates a scenario without any node.
+	///
+	/// All the nodes use `upgrade` on their connections, and pass the output to a clone of
+	/// `handler`, like the arguments of `libp2p_swarm::swarm()`.
+	#[inline]
+	pub fn new(upgrade: C, handler: H) -> Scenario<C, H> {
+		Scenario {
+			upgrade: upgrade,
+			handler: handler,
+			nodes: Vec::new(),
+			steps: Vec::new(),
+			clock: MockClock::new(),
+			timeout: Duration::from_secs(10),
+		}
+	}
+
+	/// Adds a node to the scenario.
+	///
+	/// # Panic
+	///
+	/// Panics if a node with the same name already exists.
+	pub fn node(mut self, name: &str) -> Scenario<C, H> {
+		assert!(!self.nodes.iter().any(|n| n == name), "node {} already exists", name);
+		self.nodes.push(name.to_owned());
+		self
+	}
+
+	/// Adds a step where `dialer` dials `listener`.
+	pub fn connect(mut self, dialer: &str, listener: &str) -> Scenario<C, H> {
+		self.steps.push(Step::Connect(dialer.to_owned(), listener.to_owned()));
+		self
+	}
+
+	/// Adds a step that waits until `node` produces an event for which `matches` returns true.
+	/// The events that don't match are ignored. `description` is used in the panic message if no
+	/// such event happens.
+	pub fn expect_event<M>(mut self, node: &str, description: &str, matches: M)
+						   -> Scenario<C, H>
+		where M: FnMut(&SwarmEvent) -> bool + 'static
+	{
+		let step = Step::ExpectEvent(node.to_owned(), description.to_owned(), Box::new(matches));
+		self.steps.push(step);
+		self
+	}
+
+	/// Adds a step that lets the nodes run until they have nothing left to do, then moves the
+	/// clock of the scenario forward by `duration` and lets them run again.
+	pub fn advance_time(mut self, duration: Duration) -> Scenario<C, H> {
+		self.steps.push(Step::AdvanceTime(duration));
+		self
+	}
+
+	/// Sets how far `expect_event` lets the clock of the scenario move forward before giving up.
+	/// The default value is 10 seconds.
+	#[inline]
+	pub fn timeout(mut self, timeout: Duration) -> Scenario<C, H> {
+		self.timeout = timeout;
+		self
+	}
+
+	/// Sets the clock of the scenario. By default the scenario uses a new `MockClock`, which the
+	/// handlers can't access.
+	#[inline]
+	pub fn clock(mut self, clock: MockClock) -> Scenario<C, H> {
+		self.clock = clock;
+		self
+	}
+
+	/// Runs the scenario.
+	///
+	/// # Panic
+	///
+	/// Panics if a step fails, or if a step refers to a node that doesn't exist.
+	pub fn run(self) {
+		let mut core = Core::new().expect("failed to create the tokio core");
+		// Set to true whenever the future of a node is polled.
+		let active = Rc::new(Cell::new(false));
+
+		let mut controllers = HashMap::new();
+		let mut events = HashMap::new();
+		let mut addrs = HashMap::new();
+		for name in self.nodes.iter() {
+			let transport = MemoryTransport.with_dummy_muxing();
+			let (controller, future) =
+				swarm::swarm(transport, self.upgrade.clone(), self.handler.clone());
+			// Subscribing before listening, so that the `NewListenAddr` event isn't missed.
+			events.insert(name.clone(), controller.events());
+			let (_, addr) = controller.listen_on(any_memory_addr())
+				.expect("memory addresses are always supported");
+			core.handle().spawn(Tracked {
+				inner: future.map_err(|err| panic!("swarm error: {}", err)),
+				polled: active.clone(),
+			});
+			controllers.insert(name.clone(), controller);
+			addrs.insert(name.clone(), addr);
+		}
+
+		for step in self.steps {
+			match step {
+				Step::Connect(dialer, listener) => {
+					let controller: &SwarmController<_, _> = controllers.get(&dialer)
+						.unwrap_or_else(|| panic!("unknown node {}", dialer));
+					let addr: Multiaddr = addrs.get(&listener).cloned()
+						.unwrap_or_else(|| panic!("unknown node {}", listener));
+					controller.dial_to_handler(addr, self.upgrade.clone())
+						.unwrap_or_else(|err| panic!("{} failed to dial {}: {}", dialer,
+													 listener, err));
+				},
+
+				Step::ExpectEvent(node, description, mut matches) => {
+					let node_events: &mut SwarmEvents = events.get_mut(&node)
+						.unwrap_or_else(|| panic!("unknown node {}", node));
+
+					let found = future::poll_fn(|| -> Poll<bool, ()> {
+						loop {
+							match node_events.poll() {
+								Ok(Async::Ready(Some(event))) => if matches(&event) {
+									return Ok(Async::Ready(true));
+								},
+								Ok(Async::Ready(None)) | Err(()) => return Ok(Async::Ready(false)),
+								Ok(Async::NotReady) => return Ok(Async::NotReady),
+							}
+						}
+					});
+
+					let limit = self.clock.elapsed() + self.timeout;
+					match run_until_idle(&mut core, &active, &self.clock, limit, found) {
+						Some(Ok(true)) => (),
+						Some(Ok(false)) => {
+							panic!("{} stopped producing events before: {}", node, description)
+						},
+						None => panic!("timeout while {} waited for: {}", node, description),
+						Some(Err(())) => unreachable!(),
+					}
+				},
+
+				Step::AdvanceTime(duration) => {
+					let now = self.clock.elapsed();
+					run_until_idle(&mut core, &active, &self.clock, now, future::empty::<(), ()>());
+					self.clock.advance(duration);
+					let now = self.clock.elapsed();
+					run_until_idle(&mut core, &active, &self.clock, now, future::empty::<(), ()>());
+				},
+			}
+		}
+	}
+}
+
+// Runs the nodes until `future` is ready. Whenever the nodes have nothing left to do, advances
+// `clock` to the next delay that it has to wake up, unless this would go past `limit`. Returns
+// `None` if the nodes have nothing left to do and `future` still isn't ready.
+fn run_until_idle<F>(core: &mut Core, active: &Cell<bool>, clock: &MockClock, limit: Duration,
+					 future: F) -> Option<Result<F::Item, F::Error>>
+	where F: Future
+{
+	let notified = Arc::new(Notified(AtomicBool::new(false)));
+	let mut future = executor::spawn(future);
+
+	loop {
+		notified.0.store(false, Ordering::SeqCst);
+		match future.poll_future_notify(&notified, 0) {
+			Ok(Async::Ready(item)) => return Some(Ok(item)),
+			Ok(Async::NotReady) => (),
+			Err(err) => return Some(Err(err)),
+		}
+
+		active.set(false);
+		core.turn(Some(Duration::new(0, 0)));
+		if active.get() || notified.0.load(Ordering::SeqCst) {
+			continue;
+		}
+
+		match clock.next_deadline() {
+			Some(deadline) if deadline <= limit => clock.advance(deadline - clock.elapsed()),
+			_ => return None,
+		}
+	}
+}
+
+// Records whether the future polled by `run_until_idle` has been notified.
+struct Notified(AtomicBool);
+
+impl Notify for Notified {
+	#[inline]
+	fn notify(&self, _: usize) {
+		self.0.store(true, Ordering::SeqCst);
+	}
+}
+
+// Wraps the future of a node, and records in `polled` that it has been polled.
+struct Tracked<F> {
+	inner: F,
+	polled: Rc<Cell<bool>>,
+}
+
+impl<F> Future for Tracked<F>
+	where F: Future
+{
+	type Item = F::Item;
+	type Error = F::Error;
+
+	#[inline]
+	fn poll(&mut self) -> Poll<F::Item, F::Error> {
+		self.polled.set(true);
+		self.inner.poll()
+	}
+}
+
+// Returns the address to listen on to get a `/memory` address that isn't used yet.
+fn any_memory_addr() -> Multiaddr {
+	AddrComponent::Memory(0).into()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{any_memory_addr, MockClock, NodeTransport, Scenario};
+	use futures::{future, Future, Stream};
+	use futures::sync::oneshot;
+	use libp2p_memory_transport::MemoryTransport;
+	use parking_lot::Mutex;
+	use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicBool, Ordering};
+	use std::time::Duration;
+	use swarm::{self, ConnectionId, Endpoint, PeerId, PlainTextConfig, SwarmClosing};
+	use swarm::{ErrorLayer, SwarmController, SwarmEvent, SwarmEvents, Transport, UpgradeRecorder};
+	use tokio_core::reactor::Core;
+
+	#[test]
+	fn connect_two_nodes() {
+		Scenario::new(PlainTextConfig, |_, _| Ok::<_, IoError>(()))
+			.node("a")
+			.node("b")
+			.connect("a", "b")
+			.expect_event("a", "outgoing connection", |event| match *event {
+				SwarmEvent::ConnectionEstablished { endpoint, .. } => {
+					endpoint == Endpoint::Dialer
+				},
+				_ => false,
+			})
+			.expect_event("b", "incoming connection", |event| match *event {
+				SwarmEvent::ConnectionEstablished { endpoint, .. } => {
+					endpoint == Endpoint::Listener
+				},
+				_ => false,
+			})
+			.run();
+	}
+
+	#[test]
+	#[should_panic(expected = "timeout while b waited for: a connection")]
+	fn missing_event_panics() {
+		Scenario::new(PlainTextConfig, |_, _| Ok::<_, IoError>(()))
+			.node("a")
+			.node("b")
+			.timeout(Duration::from_millis(50))
+			.expect_event("b", "a connection", |event| match *event {
+				SwarmEvent::ConnectionEstablished { .. } => true,
+				_ => false,
+			})
+			.run();
+	}
+
+	// Connects two nodes whose handlers finish after `delay`, and waits until `b` sees the
+	// connection closed. Advances the clock by `advance` after the connection is open.
+	fn connection_closed_after(delay: Duration, advance: Duration) {
+		let clock = MockClock::new();
+		let handler_clock = clock.clone();
+		Scenario::new(PlainTextConfig, move |_, _| handler_clock.delay(delay))
+			.clock(clock)
+			.node("a")
+			.node("b")
+			.connect("a", "b")
+			.expect_event("b", "incoming connection", |event| match *event {
+				SwarmEvent::ConnectionEstablished { .. } => true,
+				_ => false,
+			})
+			.advance_time(advance)
+			.expect_event("b", "closed connection", |event| match *event {
+				SwarmEvent::ConnectionClosed { .. } => true,
+				_ => false,
+			})
+			.run();
+	}
+
+	#[test]
+	fn expect_event_advances_clock() {
+		connection_closed_after(Duration::from_secs(5), Duration::new(0, 0));
+	}
+
+	#[test]
+	fn advance_time_wakes_up_delays() {
+		connection_closed_after(Duration::from_secs(3600), Duration::from_secs(3600));
+	}
+
+	#[test]
+	#[should_panic(expected = "timeout while b waited for: closed connection")]
+	fn expect_event_stops_at_timeout() {
+		connection_closed_after(Duration::from_secs(3600), Duration::from_secs(60));
+	}
+
+	// Connects `a` to `b`, then shuts `b` down gracefully with `deadline`. If `close_on_signal`
+	// is true, the connections of `b` finish once it starts closing, otherwise they never finish.
+	// Returns true if the connection of `b` finished by itself.
+	fn graceful_shutdown<D>(close_on_signal: bool, deadline: D) -> bool
+		where D: Future<Item = (), Error = IoError> + Send + 'static
+	{
+		let mut core = Core::new().unwrap();
+
+		let closing: Arc<Mutex<Option<SwarmClosing>>> = Arc::new(Mutex::new(None));
+		let finished = Arc::new(AtomicBool::new(false));
+		let transport = MemoryTransport.with_dummy_muxing();
+		let (b, b_future) = {
+			let closing = closing.clone();
+			let finished = finished.clone();
+			swarm::swarm(transport, PlainTextConfig, move |connection, _| {
+				if !close_on_signal {
+					let never = future::empty().map(move |()| drop(connection));
+					return Box::new(never) as Box<Future<Item = (), Error = IoError> + Send>;
+				}
+
+				let finished = finished.clone();
+				let closing = closing.lock().clone().expect("set before listening");
+				Box::new(closing.map(move |()| {
+					finished.store(true, Ordering::SeqCst);
+					drop(connection);
+				}))
+			})
+		};
+		*closing.lock() = Some(b.closing());
+		let events = b.events();
+		let (_, b_addr) = b.listen_on(any_memory_addr()).unwrap();
+
+		let transport = MemoryTransport.with_dummy_muxing();
+		let (a, a_future) = swarm::swarm(transport, PlainTextConfig, |_, _| {
+			future::empty::<(), IoError>()
+		});
+		core.handle().spawn(a_future.map_err(|err| panic!("swarm error: {}", err)));
+		core.handle().spawn(b_future.map_err(|err| panic!("swarm error: {}", err)));
+		a.dial_to_handler(b_addr, PlainTextConfig).unwrap();
+
+		let established = events
+			.filter(|event| match *event {
+				SwarmEvent::ConnectionEstablished { .. } => true,
+				_ => false,
+			})
+			.into_future()
+			.map_err(|_| ());
+		core.run(established).unwrap();
+
+		core.run(b.shutdown_graceful(deadline)).unwrap();
+		finished.load(Ordering::SeqCst)
+	}
+
+	#[test]
+	fn graceful_shutdown_waits_for_connections() {
+		assert!(graceful_shutdown(true, future::empty()));
+	}
+
+	#[test]
+	fn graceful_shutdown_stops_at_deadline() {
+		assert!(!graceful_shutdown(false, future::ok(())));
+	}
+
+	// Records `remote` as the identity of the first two connections established by `controller`,
+	// then waits until one of them is closed. Returns the endpoint of the connection that is kept.
+	fn keep_one_connection(core: &mut Core,
+						   controller: &SwarmController<NodeTransport, PlainTextConfig>,
+						   events: &mut SwarmEvents, remote: &PeerId) -> Endpoint
+	{
+		let established = events.by_ref()
+			.filter_map(|event| match event {
+				SwarmEvent::ConnectionEstablished { id, .. } => Some(id),
+				_ => None,
+			})
+			.take(2)
+			.collect();
+		for id in core.run(established).unwrap() {
+			let recorded = controller.update_connection(id, |info| {
+				info.set_remote_peer_id(remote.clone())
+			});
+			assert!(recorded);
+		}
+
+		let closed = events.by_ref()
+			.filter_map(|event| match event {
+				SwarmEvent::ConnectionClosed { id, .. } => Some(id),
+				_ => None,
+			})
+			.into_future()
+			.map_err(|_| ());
+		let closed: Option<ConnectionId> = core.run(closed).unwrap().0;
+
+		let kept = controller.peer_connection(remote).unwrap();
+		assert_ne!(closed, Some(kept));
+		assert_eq!(controller.connections().len(), 1);
+		controller.connection(kept).unwrap().endpoint()
+	}
+
+	#[test]
+	fn simultaneous_dial_keeps_one_connection() {
+		let mut core = Core::new().unwrap();
+
+		let mut nodes = Vec::new();
+		for key in &[[1u8], [2u8]] {
+			let transport = MemoryTransport.with_dummy_muxing();
+			let (controller, future) = swarm::swarm(transport, PlainTextConfig, |_, _| {
+				future::empty::<(), IoError>()
+			});
+			let peer_id = PeerId::from_public_key(key);
+			controller.set_local_peer_id(peer_id.clone());
+			let events = controller.events();
+			let (_, addr) = controller.listen_on(any_memory_addr()).unwrap();
+			core.handle().spawn(future.map_err(|err| panic!("swarm error: {}", err)));
+			nodes.push((controller, events, peer_id, addr));
+		}
+
+		let (b, mut b_events, b_id, b_addr) = nodes.pop().unwrap();
+		let (a, mut a_events, a_id, a_addr) = nodes.pop().unwrap();
+		a.dial_to_handler(b_addr, PlainTextConfig).unwrap();
+		b.dial_to_handler(a_addr, PlainTextConfig).unwrap();
+
+		let a_endpoint = keep_one_connection(&mut core, &a, &mut a_events, &b_id);
+		let b_endpoint = keep_one_connection(&mut core, &b, &mut b_events, &a_id);
+		// Both nodes kept the same connection, which one of them dialed and the other accepted.
+		assert_ne!(a_endpoint, b_endpoint);
+	}
+
+	#[test]
+	fn banned_peer_is_disconnected() {
+		let mut core = Core::new().unwrap();
+
+		let transport = MemoryTransport.with_dummy_muxing();
+		let (b, b_future) = swarm::swarm(transport, PlainTextConfig, |_, _| {
+			future::empty::<(), IoError>()
+		});
+		let mut events = b.events();
+		let (_, b_addr) = b.listen_on(any_memory_addr()).unwrap();
+
+		let transport = MemoryTransport.with_dummy_muxing();
+		let (a, a_future) = swarm::swarm(transport, PlainTextConfig, |_, _| {
+			future::empty::<(), IoError>()
+		});
+		core.handle().spawn(a_future.map_err(|err| panic!("swarm error: {}", err)));
+		core.handle().spawn(b_future.map_err(|err| panic!("swarm error: {}", err)));
+		let a_id = PeerId::from_public_key(&[1]);
+
+		// Waits for the next connection of `b` and records `a_id` as its identity.
+		let next_connection = |core: &mut Core, events: &mut SwarmEvents| {
+			a.dial_to_handler(b_addr.clone(), PlainTextConfig).unwrap();
+			let established = events.by_ref()
+				.filter_map(|event| match event {
+					SwarmEvent::ConnectionEstablished { id, .. } => Some(id),
+					_ => None,
+				})
+				.into_future()
+				.map_err(|_| ());
+			let id = core.run(established).unwrap().0.unwrap();
+			let recorded = b.update_connection(id, |info| info.set_remote_peer_id(a_id.clone()));
+			(id, recorded)
+		};
+		let next_closed = |core: &mut Core, events: &mut SwarmEvents| {
+			let closed = events.by_ref()
+				.filter_map(|event| match event {
+					SwarmEvent::ConnectionClosed { id, .. } => Some(id),
+					_ => None,
+				})
+				.into_future()
+				.map_err(|_| ());
+			core.run(closed).unwrap().0.unwrap()
+		};
+
+		// Banning the peer closes its existing connection.
+		let (id, recorded) = next_connection(&mut core, &mut events);
+		assert!(recorded);
+		b.ban_list().ban_peer(a_id.clone(), Duration::from_secs(3600));
+		assert_eq!(next_closed(&mut core, &mut events), id);
+
+		// The new connections are closed once the identity of the remote is known.
+		let (id, recorded) = next_connection(&mut core, &mut events);
+		assert!(!recorded);
+		assert_eq!(next_closed(&mut core, &mut events), id);
+		assert!(b.connections().is_empty());
+	}
+
+	#[test]
+	fn custom_dials_are_reported_like_other_dials() {
+		let mut core = Core::new().unwrap();
+
+		let transport = MemoryTransport.with_dummy_muxing();
+		let (b, b_future) = swarm::swarm(transport, PlainTextConfig, |_, _| {
+			future::empty::<(), IoError>()
+		});
+		let (_, b_addr) = b.listen_on(any_memory_addr()).unwrap();
+
+		let transport = MemoryTransport.with_dummy_muxing();
+		let (a, a_future) = swarm::swarm(transport, PlainTextConfig, |_, _| {
+			future::empty::<(), IoError>()
+		});
+		let mut events = a.events();
+		core.handle().spawn(a_future.map_err(|err| panic!("swarm error: {}", err)));
+		core.handle().spawn(b_future.map_err(|err| panic!("swarm error: {}", err)));
+		let next_event = |core: &mut Core, events: &mut SwarmEvents| {
+			core.run(events.by_ref().into_future().map_err(|_| ())).unwrap().0.unwrap()
+		};
+
+		// The connection is processed by `and_then` until `finish_tx` is used.
+		let (finish_tx, finish_rx) = oneshot::channel();
+		a.dial_custom_handler(b_addr, PlainTextConfig, move |connection| {
+			finish_rx
+				.map(move |()| drop(connection))
+				.map_err(|_| IoError::new(IoErrorKind::Other, "finish_tx destroyed"))
+		}).unwrap();
+
+		let id = match next_event(&mut core, &mut events) {
+			SwarmEvent::ConnectionEstablished { id, endpoint: Endpoint::Dialer, .. } => id,
+			other => panic!("unexpected {:?}", other),
+		};
+		assert_eq!(a.connections().len(), 1);
+		assert_eq!(a.connection(id).unwrap().endpoint(), Endpoint::Dialer);
+
+		finish_tx.send(()).unwrap();
+		match next_event(&mut core, &mut events) {
+			SwarmEvent::ConnectionClosed { id: closed, error: None, .. } => assert_eq!(closed, id),
+			other => panic!("unexpected {:?}", other),
+		}
+		assert!(a.connections().is_empty());
+
+		// Nobody listens on the address, so the dial fails.
+		a.dial_custom_handler(any_memory_addr(), PlainTextConfig, |_| Ok::<_, IoError>(())).unwrap();
+		match next_event(&mut core, &mut events) {
+			SwarmEvent::DialFailed { .. } => (),
+			other => panic!("unexpected {:?}", other),
+		}
+	}
+
+	#[test]
+	fn recorded_upgrades_fill_connection_info() {
+		let mut core = Core::new().unwrap();
+
+		let remote = PeerId::from_public_key(&[1]);
+		let recorder = UpgradeRecorder::new();
+		let recorded_id = remote.clone();
+		let security = recorder.record_upgrade(PlainTextConfig, ErrorLayer::Security, move |_: &_| {
+			Some(recorded_id.clone())
+		});
+		let transport = MemoryTransport.with_upgrade(security).with_dummy_muxing();
+		let (b, b_future) = swarm::swarm(transport, PlainTextConfig, |_, _| {
+			future::empty::<(), IoError>()
+		});
+		b.set_upgrade_recorder(recorder);
+		let b_events = b.events();
+		let (_, b_addr) = b.listen_on(any_memory_addr()).unwrap();
+		core.handle().spawn(b_future.map_err(|err| panic!("swarm error: {}", err)));
+
+		let transport = MemoryTransport.with_upgrade(PlainTextConfig).with_dummy_muxing();
+		let (a, a_future) = swarm::swarm(transport, PlainTextConfig, |_, _| {
+			future::empty::<(), IoError>()
+		});
+		core.handle().spawn(a_future.map_err(|err| panic!("swarm error: {}", err)));
+		a.dial_to_handler(b_addr, PlainTextConfig).unwrap();
+
+		let established = b_events
+			.filter_map(|event| match event {
+				SwarmEvent::ConnectionEstablished { id, .. } => Some(id),
+				_ => None,
+			})
+			.into_future()
+			.map_err(|_| ());
+		let id = core.run(established).unwrap().0.unwrap();
+
+		let info = b.connection(id).unwrap();
+		assert_eq!(info.remote_peer_id(), Some(&remote));
+		assert_eq!(info.security_protocol(), Some("/plaintext/1.0.0"));
+		assert_eq!(info.muxer_protocol(), None);
+	}
+}
@@ -0,0 +1,442 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `BanList` struct, which keeps track of the remotes the swarm is allowed to
+//! communicate with.
+//!
+//! Three kinds of bans are supported:
+//!
+//! - Multiaddress *prefixes*. For example banning `/ip4/1.2.3.4` bans `/ip4/1.2.3.4/tcp/80` and
+//!   `/ip4/1.2.3.4/udp/1000`, while banning `/ip4/1.2.3.4/tcp/80` only bans this exact TCP port.
+//!   The prefix is compared component by component.
+//! - Ranges of IP addresses in CIDR notation, with `IpRange`. For example `10.0.0.0/8` bans all the
+//!   multiaddresses whose IP address starts with `10.`.
+//! - Peer IDs. The identity of a remote is only known after the security upgrade, therefore the
+//!   swarm checks it when the identity is recorded with `SwarmController::update_connection()`.
+//!   The multiaddresses whose `/p2p` component contains a banned peer ID are banned as well.
+//!
+//! The allow list works with multiaddress prefixes and IP ranges.
+
+use futures::task::{self, Task};
+use multiaddr::{AddrComponent, Multiaddr};
+use parking_lot::Mutex;
+use peer_id::PeerId;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use time::Instant;
+
+/// List of remotes that are banned or explicitly allowed.
+///
+/// Cloning a `BanList` is cheap and produces an object that shares the same content.
+#[derive(Clone, Default)]
+pub struct BanList {
+	inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+	// List of banned prefixes, and the moment when the ban expires.
+	banned: Vec<(Multiaddr, Instant)>,
+	// List of banned IP ranges, and the moment when the ban expires.
+	banned_ranges: Vec<(IpRange, Instant)>,
+	// Banned peers, and the moment when the ban expires.
+	banned_peers: HashMap<PeerId, Instant>,
+	// If one of these lists is not empty, only the addresses that match one of their prefixes or
+	// ranges are allowed.
+	allowed: Vec<Multiaddr>,
+	allowed_ranges: Vec<IpRange>,
+	// Task to notify when the list is modified, so that existing connections get checked again.
+	to_notify: Option<Task>,
+}
+
+impl Inner {
+	// Removes the bans that have expired.
+	fn remove_expired(&mut self) {
+		let now = Instant::now();
+		self.banned.retain(|&(_, expires)| expires > now);
+		self.banned_ranges.retain(|&(_, expires)| expires > now);
+		self.banned_peers.retain(|_, expires| *expires > now);
+	}
+
+	#[inline]
+	fn notify(&mut self) {
+		if let Some(task) = self.to_notify.take() {
+			task.notify();
+		}
+	}
+}
+
+impl BanList {
+	/// Builds a new empty `BanList`. All addresses are allowed.
+	#[inline]
+	pub fn new() -> BanList {
+		Default::default()
+	}
+
+	/// Bans all the multiaddresses that start with `prefix` for the given duration.
+	///
+	/// If the prefix was already banned, the expiration is updated.
+	pub fn ban(&self, prefix: Multiaddr, duration: Duration) {
+		let expires = Instant::now() + duration;
+		let mut inner = self.inner.lock();
+
+		if let Some(entry) = inner.banned.iter_mut().find(|e| e.0 == prefix) {
+			entry.1 = expires;
+		} else {
+			inner.banned.push((prefix, expires));
+		}
+
+		inner.notify();
+	}
+
+	/// Lifts the ban on the given prefix. Has no effect if it wasn't banned.
+	///
+	/// > **Note**: This only lifts a ban with exactly the same prefix. Addresses that are covered
+	/// >           by another banned prefix stay banned.
+	pub fn unban(&self, prefix: &Multiaddr) {
+		self.inner.lock().banned.retain(|e| &e.0 != prefix);
+	}
+
+	/// Bans all the multiaddresses whose IP address is in `range` for the given duration.
+	///
+	/// If the range was already banned, the expiration is updated.
+	pub fn ban_ip_range(&self, range: IpRange, duration: Duration) {
+		let expires = Instant::now() + duration;
+		let mut inner = self.inner.lock();
+
+		if let Some(entry) = inner.banned_ranges.iter_mut().find(|e| e.0 == range) {
+			entry.1 = expires;
+		} else {
+			inner.banned_ranges.push((range, expires));
+		}
+
+		inner.notify();
+	}
+
+	/// Lifts the ban on the given IP range. Has no effect if it wasn't banned.
+	///
+	/// > **Note**: Like for `unban()`, the IP addresses that are covered by another banned range
+	/// >           stay banned.
+	pub fn unban_ip_range(&self, range: &IpRange) {
+		self.inner.lock().banned_ranges.retain(|e| &e.0 != range);
+	}
+
+	/// Bans the peer for the given duration. Its existing connections are closed, the new
+	/// connections are closed as soon as its identity is known, and the multiaddresses that
+	/// contain its identity can't be dialed.
+	///
+	/// If the peer was already banned, the expiration is updated.
+	pub fn ban_peer(&self, peer_id: PeerId, duration: Duration) {
+		let expires = Instant::now() + duration;
+		let mut inner = self.inner.lock();
+		inner.banned_peers.insert(peer_id, expires);
+		inner.notify();
+	}
+
+	/// Lifts the ban on the given peer. Has no effect if it wasn't banned.
+	pub fn unban_peer(&self, peer_id: &PeerId) {
+		self.inner.lock().banned_peers.remove(peer_id);
+	}
+
+	/// Returns true if the peer is banned.
+	pub fn is_peer_banned(&self, peer_id: &PeerId) -> bool {
+		let mut inner = self.inner.lock();
+		inner.remove_expired();
+		inner.banned_peers.contains_key(peer_id)
+	}
+
+	/// Returns the list of the banned peers, and the moment when their ban expires.
+	pub fn banned_peers(&self) -> Vec<(PeerId, Instant)> {
+		let mut inner = self.inner.lock();
+		inner.remove_expired();
+		inner.banned_peers.iter().map(|(peer, expires)| (peer.clone(), *expires)).collect()
+	}
+
+	/// Adds a prefix to the allow list.
+	///
+	/// As long as the allow list is empty, all the addresses that aren't banned are allowed. Once
+	/// it is not empty, only the addresses that match one of its prefixes or ranges are allowed.
+	pub fn allow(&self, prefix: Multiaddr) {
+		let mut inner = self.inner.lock();
+		if !inner.allowed.contains(&prefix) {
+			inner.allowed.push(prefix);
+		}
+
+		inner.notify();
+	}
+
+	/// Removes a prefix from the allow list.
+	pub fn disallow(&self, prefix: &Multiaddr) {
+		let mut inner = self.inner.lock();
+		inner.allowed.retain(|p| p != prefix);
+		inner.notify();
+	}
+
+	/// Adds an IP range to the allow list. See `allow()`.
+	pub fn allow_ip_range(&self, range: IpRange) {
+		let mut inner = self.inner.lock();
+		if !inner.allowed_ranges.contains(&range) {
+			inner.allowed_ranges.push(range);
+		}
+
+		inner.notify();
+	}
+
+	/// Removes an IP range from the allow list.
+	pub fn disallow_ip_range(&self, range: &IpRange) {
+		let mut inner = self.inner.lock();
+		inner.allowed_ranges.retain(|r| r != range);
+		inner.notify();
+	}
+
+	/// Returns true if we are allowed to communicate with the given multiaddress.
+	pub fn is_allowed(&self, addr: &Multiaddr) -> bool {
+		let mut inner = self.inner.lock();
+		inner.remove_expired();
+
+		if inner.banned.iter().any(|&(ref prefix, _)| is_prefix(prefix, addr)) {
+			return false;
+		}
+
+		let ip = ip_addr(addr);
+		let in_range = |range: &IpRange| ip.as_ref().map_or(false, |ip| range.contains(ip));
+
+		if inner.banned_ranges.iter().any(|&(ref range, _)| in_range(range)) {
+			return false;
+		}
+
+		if !inner.banned_peers.is_empty() {
+			let banned_peer = addr.iter().any(|component| match component {
+				AddrComponent::P2P(id) | AddrComponent::IPFS(id) => {
+					inner.banned_peers.keys().any(|peer| peer.as_bytes() == &id[..])
+				},
+				_ => false,
+			});
+			if banned_peer {
+				return false;
+			}
+		}
+
+		(inner.allowed.is_empty() && inner.allowed_ranges.is_empty()) ||
+			inner.allowed.iter().any(|prefix| is_prefix(prefix, addr)) ||
+			inner.allowed_ranges.iter().any(in_range)
+	}
+
+	// Registers the current task so that it gets notified when a ban is added.
+	#[inline]
+	pub(crate) fn register_task(&self) {
+		self.inner.lock().to_notify = Some(task::current());
+	}
+}
+
+/// Range of IP addresses in CIDR notation, such as `10.0.0.0/8` or `2001:db8::/32`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct IpRange {
+	network: IpAddr,
+	prefix_len: u8,
+}
+
+impl IpRange {
+	/// Builds the range of the addresses whose first `prefix_len` bits are the ones of `network`.
+	///
+	/// Returns `None` if `prefix_len` is larger than the number of bits of `network`.
+	pub fn new(network: IpAddr, prefix_len: u8) -> Option<IpRange> {
+		let max_len = match network {
+			IpAddr::V4(_) => 32,
+			IpAddr::V6(_) => 128,
+		};
+
+		if prefix_len > max_len {
+			return None;
+		}
+
+		Some(IpRange {
+			network: network,
+			prefix_len: prefix_len,
+		})
+	}
+
+	/// Returns the address that was passed to `new()`.
+	#[inline]
+	pub fn network(&self) -> IpAddr {
+		self.network
+	}
+
+	/// Returns the number of bits that the addresses of the range share with `network()`.
+	#[inline]
+	pub fn prefix_len(&self) -> u8 {
+		self.prefix_len
+	}
+
+	/// Returns true if `addr` is in the range. IPv4 addresses are never part of an IPv6 range, and
+	/// vice versa.
+	pub fn contains(&self, addr: &IpAddr) -> bool {
+		match (self.network, *addr) {
+			(IpAddr::V4(network), IpAddr::V4(addr)) => {
+				bits_match(&network.octets(), &addr.octets(), self.prefix_len)
+			},
+			(IpAddr::V6(network), IpAddr::V6(addr)) => {
+				bits_match(&network.octets(), &addr.octets(), self.prefix_len)
+			},
+			_ => false,
+		}
+	}
+}
+
+// Returns true if the first `bits` bits of `a` and `b` are the same.
+fn bits_match(a: &[u8], b: &[u8], bits: u8) -> bool {
+	let full_bytes = (bits / 8) as usize;
+	if a[.. full_bytes] != b[.. full_bytes] {
+		return false;
+	}
+
+	match bits % 8 {
+		0 => true,
+		remaining => {
+			let mask = 0xffu8 << (8 - remaining);
+			a[full_bytes] & mask == b[full_bytes] & mask
+		},
+	}
+}
+
+// Returns the IP address of a multiaddress, if it has one.
+fn ip_addr(addr: &Multiaddr) -> Option<IpAddr> {
+	addr.iter()
+		.filter_map(|component| match component {
+			AddrComponent::IP4(ip) => Some(IpAddr::V4(ip)),
+			AddrComponent::IP6(ip) => Some(IpAddr::V6(ip)),
+			_ => None,
+		})
+		.next()
+}
+
+// Returns true if the components of `prefix` are the first components of `addr`.
+#[inline]
+fn is_prefix(prefix: &Multiaddr, addr: &Multiaddr) -> bool {
+	let mut addr = addr.iter();
+	prefix.iter().all(|component| addr.next() == Some(component))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{BanList, IpRange};
+	use multiaddr::{AddrComponent, Multiaddr};
+	use peer_id::PeerId;
+	use std::thread;
+	use std::time::Duration;
+
+	#[test]
+	fn ban_prefix() {
+		let list = BanList::new();
+		let addr = "/ip4/1.2.3.4/tcp/80".parse::<Multiaddr>().unwrap();
+		assert!(list.is_allowed(&addr));
+
+		list.ban("/ip4/1.2.3.4".parse().unwrap(), Duration::from_secs(3600));
+		assert!(!list.is_allowed(&addr));
+		assert!(list.is_allowed(&"/ip4/1.2.3.5/tcp/80".parse().unwrap()));
+
+		list.unban(&"/ip4/1.2.3.4".parse().unwrap());
+		assert!(list.is_allowed(&addr));
+	}
+
+	#[test]
+	fn ban_expires() {
+		let list = BanList::new();
+		let addr = "/ip4/1.2.3.4/tcp/80".parse::<Multiaddr>().unwrap();
+		list.ban(addr.clone(), Duration::from_millis(0));
+		thread::sleep(Duration::from_millis(2));
+		assert!(list.is_allowed(&addr));
+	}
+
+	#[test]
+	fn allow_list() {
+		let list = BanList::new();
+		list.allow("/ip4/10.0.0.1".parse().unwrap());
+		assert!(list.is_allowed(&"/ip4/10.0.0.1/tcp/80".parse().unwrap()));
+		assert!(!list.is_allowed(&"/ip4/10.0.0.2/tcp/80".parse().unwrap()));
+
+		// Bans take precedence over the allow list.
+		list.ban("/ip4/10.0.0.1/tcp/80".parse().unwrap(), Duration::from_secs(3600));
+		assert!(!list.is_allowed(&"/ip4/10.0.0.1/tcp/80".parse().unwrap()));
+		assert!(list.is_allowed(&"/ip4/10.0.0.1/tcp/81".parse().unwrap()));
+	}
+
+	#[test]
+	fn ip_range_contains() {
+		let range = IpRange::new("10.128.0.0".parse().unwrap(), 9).unwrap();
+		assert!(range.contains(&"10.128.0.1".parse().unwrap()));
+		assert!(range.contains(&"10.255.255.255".parse().unwrap()));
+		assert!(!range.contains(&"10.127.255.255".parse().unwrap()));
+		assert!(!range.contains(&"::ffff:10.128.0.1".parse().unwrap()));
+
+		let range = IpRange::new("2001:db8::".parse().unwrap(), 32).unwrap();
+		assert!(range.contains(&"2001:db8:1::1".parse().unwrap()));
+		assert!(!range.contains(&"2001:db9::1".parse().unwrap()));
+
+		assert!(IpRange::new("0.0.0.0".parse().unwrap(), 0).unwrap()
+			.contains(&"1.2.3.4".parse().unwrap()));
+		assert!(IpRange::new("1.2.3.4".parse().unwrap(), 33).is_none());
+	}
+
+	#[test]
+	fn ban_ip_range() {
+		let list = BanList::new();
+		let range = IpRange::new("192.168.0.0".parse().unwrap(), 16).unwrap();
+		list.ban_ip_range(range, Duration::from_secs(3600));
+		assert!(!list.is_allowed(&"/ip4/192.168.12.1/tcp/80".parse().unwrap()));
+		assert!(list.is_allowed(&"/ip4/192.169.0.1/tcp/80".parse().unwrap()));
+		assert!(list.is_allowed(&"/dns4/example.com/tcp/80".parse().unwrap()));
+
+		list.unban_ip_range(&range);
+		assert!(list.is_allowed(&"/ip4/192.168.12.1/tcp/80".parse().unwrap()));
+	}
+
+	#[test]
+	fn allow_ip_range() {
+		let list = BanList::new();
+		list.allow_ip_range(IpRange::new("10.0.0.0".parse().unwrap(), 8).unwrap());
+		assert!(list.is_allowed(&"/ip4/10.1.2.3/tcp/80".parse().unwrap()));
+		assert!(!list.is_allowed(&"/ip4/11.1.2.3/tcp/80".parse().unwrap()));
+	}
+
+	#[test]
+	fn ban_peer() {
+		let list = BanList::new();
+		let peer = PeerId::from_public_key(&[1, 2, 3]);
+		let addr = "/ip4/1.2.3.4/tcp/80".parse::<Multiaddr>().unwrap()
+			.iter()
+			.chain(Some(AddrComponent::P2P(peer.as_bytes().to_vec())))
+			.collect::<Multiaddr>();
+		assert!(!list.is_peer_banned(&peer));
+		assert!(list.is_allowed(&addr));
+
+		list.ban_peer(peer.clone(), Duration::from_secs(3600));
+		assert!(list.is_peer_banned(&peer));
+		assert!(!list.is_allowed(&addr));
+		assert!(list.is_allowed(&"/ip4/1.2.3.4/tcp/80".parse().unwrap()));
+		assert_eq!(list.banned_peers().len(), 1);
+
+		list.unban_peer(&peer);
+		assert!(!list.is_peer_banned(&peer));
+		assert!(list.is_allowed(&addr));
+	}
+}
@@ -0,0 +1,385 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+//! Contains the `BandwidthLogging` transport wrapper, which counts the bytes that go through the
+//! connections of the underlying transport.
+//!
+//! The counters are kept in a `BandwidthSinks` shared with the user. They are updated atomically
+//! by the connections and can be read from any thread, for example in order to display the
+//! upload and download speeds of the node.
+
+use futures::{Async, Future, IntoFuture, Poll, Stream};
+use multiaddr::Multiaddr;
+use parking_lot::Mutex;
+use std::io::{Error as IoError, Read, Write};
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use time::Instant;
+use tokio_io::{AsyncRead, AsyncWrite};
+use transport::Transport;
+
+/// Wraps around a `Transport` and counts the bytes that go through its connections.
+#[derive(Debug, Clone)]
+pub struct BandwidthLogging<T> {
+	inner: T,
+	sinks: Arc<BandwidthSinks>,
+}
+
+impl<T> BandwidthLogging<T> {
+	/// Wraps around `inner`. Returns the counters alongside with the transport.
+	#[inline]
+	pub fn new(inner: T) -> (BandwidthLogging<T>, Arc<BandwidthSinks>) {
+		let sinks = Arc::new(BandwidthSinks::new());
+		let transport = BandwidthLogging {
+			inner: inner,
+			sinks: sinks.clone(),
+		};
+		(transport, sinks)
+	}
+}
+
+impl<T> Transport for BandwidthLogging<T>
+	where T: Transport
+{
+	type RawConn = Metered<T::RawConn>;
+	type Listener = BandwidthListener<T::Listener>;
+	type ListenerUpgrade = BandwidthFuture<T::ListenerUpgrade>;
+	type Dial = BandwidthFuture<<T::Dial as IntoFuture>::Future>;
+
+	fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+		let sinks = self.sinks;
+		match self.inner.listen_on(addr) {
+			Ok((listener, addr)) => {
+				let listener = BandwidthListener {
+					inner: listener,
+					sinks: sinks,
+				};
+				Ok((listener, addr))
+			}
+			Err((inner, addr)) => Err((BandwidthLogging { inner, sinks }, addr)),
+		}
+	}
+
+	fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+		let sinks = self.sinks;
+		match self.inner.dial(addr.clone()) {
+			Ok(dial) => {
+				Ok(BandwidthFuture {
+					inner: dial.into_future(),
+					sinks: sinks,
+					remote_addr: Some(addr),
+				})
+			}
+			Err((inner, addr)) => Err((BandwidthLogging { inner, sinks }, addr)),
+		}
+	}
+
+	#[inline]
+	fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+		self.inner.nat_traversal(server, observed)
+	}
+
+	#[inline]
+	fn expand_listen_addr(&self, addr: &Multiaddr) -> Vec<Multiaddr> {
+		self.inner.expand_listen_addr(addr)
+	}
+}
+
+/// Counters of the bytes that went through the connections of a `BandwidthLogging`.
+#[derive(Debug)]
+pub struct BandwidthSinks {
+	inbound: AtomicUsize,
+	outbound: AtomicUsize,
+	// Counters of the connections that are still alive.
+	connections: Mutex<Vec<Weak<ConnectionCounters>>>,
+	// Totals and moment of the latest call to `snapshot()`, in order to compute the rates.
+	last_snapshot: Mutex<(usize, usize, Instant)>,
+}
+
+impl BandwidthSinks {
+	fn new() -> BandwidthSinks {
+		BandwidthSinks {
+			inbound: AtomicUsize::new(0),
+			outbound: AtomicUsize::new(0),
+			connections: Mutex::new(Vec::new()),
+			last_snapshot: Mutex::new((0, 0, Instant::now())),
+		}
+	}
+
+	/// Returns the total number of bytes received on all the connections.
+	///
+	/// > **Note**: The counter wraps around once it reaches `usize::max_value()`.
+	#[inline]
+	pub fn total_inbound(&self) -> usize {
+		self.inbound.load(Ordering::Relaxed)
+	}
+
+	/// Returns the total number of bytes sent on all the connections.
+	///
+	/// > **Note**: The counter wraps around once it reaches `usize::max_value()`.
+	#[inline]
+	pub fn total_outbound(&self) -> usize {
+		self.outbound.load(Ordering::Relaxed)
+	}
+
+	/// Returns the counters of the connections that are still alive.
+	pub fn connections(&self) -> Vec<Arc<ConnectionCounters>> {
+		let mut connections = self.connections.lock();
+		connections.retain(|counters| counters.upgrade().is_some());
+		connections.iter().filter_map(|counters| counters.upgrade()).collect()
+	}
+
+	/// Returns the totals, and the rates since the previous call to `snapshot()` (or since the
+	/// creation of the `BandwidthLogging` for the first call).
+	///
+	/// Calling this method periodically, for example once per second, gives the current upload
+	/// and download speeds.
+	pub fn snapshot(&self) -> BandwidthSnapshot {
+		let now = Instant::now();
+		let inbound = self.total_inbound();
+		let outbound = self.total_outbound();
+
+		let mut last_snapshot = self.last_snapshot.lock();
+		let (last_inbound, last_outbound, last_instant) = *last_snapshot;
+		*last_snapshot = (inbound, outbound, now);
+
+		let elapsed = now.duration_since(last_instant);
+		let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+		let rate = |diff: usize| if elapsed > 0.0 { diff as f64 / elapsed } else { 0.0 };
+
+		BandwidthSnapshot {
+			total_inbound: inbound,
+			total_outbound: outbound,
+			inbound_rate: rate(inbound.wrapping_sub(last_inbound)),
+			outbound_rate: rate(outbound.wrapping_sub(last_outbound)),
+		}
+	}
+
+	// Registers a new connection.
+	fn register(&self, remote_addr: Multiaddr) -> Arc<ConnectionCounters> {
+		let counters = Arc::new(ConnectionCounters {
+			remote_addr: remote_addr,
+			inbound: AtomicUsize::new(0),
+			outbound: AtomicUsize::new(0),
+		});
+
+		let mut connections = self.connections.lock();
+		connections.retain(|counters| counters.upgrade().is_some());
+		connections.push(Arc::downgrade(&counters));
+		counters
+	}
+}
+
+/// State of the counters of a `BandwidthSinks` at some point in time.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BandwidthSnapshot {
+	/// Total number of bytes received.
+	pub total_inbound: usize,
+	/// Total number of bytes sent.
+	pub total_outbound: usize,
+	/// Bytes received per second since the previous snapshot.
+	pub inbound_rate: f64,
+	/// Bytes sent per second since the previous snapshot.
+	pub outbound_rate: f64,
+}
+
+/// Counters of the bytes that went through a single connection.
+#[derive(Debug)]
+pub struct ConnectionCounters {
+	remote_addr: Multiaddr,
+	inbound: AtomicUsize,
+	outbound: AtomicUsize,
+}
+
+impl ConnectionCounters {
+	/// Returns the address of the remote.
+	#[inline]
+	pub fn remote_addr(&self) -> &Multiaddr {
+		&self.remote_addr
+	}
+
+	/// Returns the number of bytes received on the connection.
+	#[inline]
+	pub fn inbound(&self) -> usize {
+		self.inbound.load(Ordering::Relaxed)
+	}
+
+	/// Returns the number of bytes sent on the connection.
+	#[inline]
+	pub fn outbound(&self) -> usize {
+		self.outbound.load(Ordering::Relaxed)
+	}
+}
+
+/// Stream of the incoming connections of a `BandwidthLogging`.
+#[must_use = "streams do nothing unless polled"]
+pub struct BandwidthListener<S> {
+	inner: S,
+	sinks: Arc<BandwidthSinks>,
+}
+
+impl<S, F> Stream for BandwidthListener<S>
+	where S: Stream<Item = (F, Multiaddr)>
+{
+	type Item = (BandwidthFuture<F>, Multiaddr);
+	type Error = S::Error;
+
+	fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+		let (upgrade, addr) = match try_ready!(self.inner.poll()) {
+			Some(item) => item,
+			None => return Ok(Async::Ready(None)),
+		};
+
+		let upgrade = BandwidthFuture {
+			inner: upgrade,
+			sinks: self.sinks.clone(),
+			remote_addr: Some(addr.clone()),
+		};
+		Ok(Async::Ready(Some((upgrade, addr))))
+	}
+}
+
+/// Future that produces a connection of a `BandwidthLogging`.
+#[must_use = "futures do nothing unless polled"]
+pub struct BandwidthFuture<F> {
+	inner: F,
+	sinks: Arc<BandwidthSinks>,
+	// Taken when the connection is produced.
+	remote_addr: Option<Multiaddr>,
+}
+
+impl<F> Future for BandwidthFuture<F>
+	where F: Future
+{
+	type Item = Metered<F::Item>;
+	type Error = F::Error;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let inner = try_ready!(self.inner.poll());
+		let remote_addr = self.remote_addr.take().expect("future polled after it finished");
+		let counters = self.sinks.register(remote_addr);
+		Ok(Async::Ready(Metered {
+			inner: inner,
+			sinks: self.sinks.clone(),
+			counters: counters,
+		}))
+	}
+}
+
+/// Connection of a `BandwidthLogging`. Counts the bytes that are read and written.
+pub struct Metered<C> {
+	inner: C,
+	sinks: Arc<BandwidthSinks>,
+	counters: Arc<ConnectionCounters>,
+}
+
+impl<C> Metered<C> {
+	/// Returns the counters of this connection.
+	#[inline]
+	pub fn counters(&self) -> &Arc<ConnectionCounters> {
+		&self.counters
+	}
+}
+
+impl<C> Read for Metered<C>
+	where C: Read
+{
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+		let num = self.inner.read(buf)?;
+		self.sinks.inbound.fetch_add(num, Ordering::Relaxed);
+		self.counters.inbound.fetch_add(num, Ordering::Relaxed);
+		Ok(num)
+	}
+}
+
+impl<C> AsyncRead for Metered<C>
+	where C: AsyncRead
+{
+	#[inline]
+	unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+		self.inner.prepare_uninitialized_buffer(buf)
+	}
+}
+
+impl<C> Write for Metered<C>
+	where C: Write
+{
+	fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+		let num = self.inner.write(buf)?;
+		self.sinks.outbound.fetch_add(num, Ordering::Relaxed);
+		self.counters.outbound.fetch_add(num, Ordering::Relaxed);
+		Ok(num)
+	}
+
+	#[inline]
+	fn flush(&mut self) -> Result<(), IoError> {
+		self.inner.flush()
+	}
+}
+
+impl<C> AsyncWrite for Metered<C>
+	where C: AsyncWrite
+{
+	#[inline]
+	fn shutdown(&mut self) -> Poll<(), IoError> {
+		self.inner.shutdown()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::BandwidthLogging;
+	use std::io::{Cursor, Read, Write};
+	use transport::{DeniedTransport, Transport};
+
+	#[test]
+	fn counts_bytes() {
+		let (_, sinks) = BandwidthLogging::new(DeniedTransport);
+
+		let counters = sinks.register("/ip4/1.2.3.4/tcp/5".parse().unwrap());
+		let mut conn = super::Metered {
+			inner: Cursor::new(vec![0; 16]),
+			sinks: sinks.clone(),
+			counters: counters.clone(),
+		};
+		conn.write_all(&[1, 2, 3, 4]).unwrap();
+		conn.read_exact(&mut [0; 6]).unwrap();
+
+		assert_eq!(counters.outbound(), 4);
+		assert_eq!(counters.inbound(), 6);
+		assert_eq!(sinks.total_outbound(), 4);
+		assert_eq!(sinks.total_inbound(), 6);
+		assert_eq!(sinks.connections().len(), 1);
+
+		let snapshot = sinks.snapshot();
+		assert_eq!(snapshot.total_inbound, 6);
+		assert_eq!(snapshot.total_outbound, 4);
+		assert_eq!(sinks.snapshot().inbound_rate, 0.0);
+
+		drop(conn);
+		drop(counters);
+		assert!(sinks.connections().is_empty());
+	}
+
+	#[test]
+	fn dial_denied() {
+		let (transport, _) = BandwidthLogging::new(DeniedTransport);
+		assert!(transport.dial("/ip4/1.2.3.4/tcp/5".parse().unwrap()).is_err());
+	}
+}
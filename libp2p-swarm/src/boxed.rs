@@ -0,0 +1,263 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `Boxed` transport, which erases the type of the transport it wraps.
+//!
+//! Combining transports with `or_transport()` or `with_upgrade()` produces a type that depends on
+//! the exact combination. This is a problem when the combination is only known at runtime, for
+//! example because the user can enable or disable some protocols in a configuration file. The
+//! `Boxed` transport solves that problem: all the transports whose connections are of type `O`
+//! can be turned into a `Boxed<O>`, and thus stored in the same field or returned by the same
+//! function.
+//!
+//! > **Note**: All the transports must produce the same type of connection. Transports that don't
+//! >           can be made to by applying the same upgrade to them, for example a muxer.
+//!
+//! > **Note**: Erasing the type has a cost. Every listener, upgrade and dial is allocated on the
+//! >           heap, and every call goes through a virtual function.
+
+use futures::{Future, IntoFuture, Stream};
+use multiaddr::Multiaddr;
+use std::fmt;
+use std::io::Error as IoError;
+use std::sync::Arc;
+use tokio_io::{AsyncRead, AsyncWrite};
+use transport::Transport;
+
+/// Stream of incoming connections produced by the listeners of a `Boxed` transport.
+pub type BoxedListener<O> =
+	Box<Stream<Item = (BoxedListenerUpgrade<O>, Multiaddr), Error = IoError> + Send>;
+
+/// Future that resolves to an incoming connection of a `Boxed` transport.
+pub type BoxedListenerUpgrade<O> = Box<Future<Item = O, Error = IoError> + Send>;
+
+/// Future that resolves to an outgoing connection of a `Boxed` transport.
+pub type BoxedDial<O> = Box<Future<Item = O, Error = IoError> + Send>;
+
+/// Transport that wraps around another transport whose type has been erased.
+///
+/// Created with `Transport::boxed()`. Cloning a `Boxed` is cheap, as the wrapped transport is
+/// shared between the clones.
+///
+/// > **Note**: The wrapped transport, its listeners and its futures must be `Send`, so that a
+/// >           `Boxed` can be used by a swarm that runs on another thread.
+pub struct Boxed<O> {
+	inner: Arc<Abstract<O> + Send + Sync>,
+}
+
+impl<O> Clone for Boxed<O> {
+	#[inline]
+	fn clone(&self) -> Boxed<O> {
+		Boxed {
+			inner: self.inner.clone(),
+		}
+	}
+}
+
+impl<O> fmt::Debug for Boxed<O> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		f.debug_struct("Boxed").finish()
+	}
+}
+
+impl<O> Boxed<O> {
+	/// Wraps around `transport`.
+	///
+	/// Prefer using `Transport::boxed()`, which does the same thing.
+	#[inline]
+	pub fn new<T>(transport: T) -> Boxed<O>
+		where T: Transport<RawConn = O> + Clone + Send + Sync + 'static,
+			  T::Listener: Send + 'static,
+			  T::ListenerUpgrade: Send + 'static,
+			  <T::Dial as IntoFuture>::Future: Send + 'static,
+	{
+		Boxed {
+			inner: Arc::new(transport),
+		}
+	}
+}
+
+impl<O> Transport for Boxed<O>
+	where O: AsyncRead + AsyncWrite
+{
+	type RawConn = O;
+	type Listener = BoxedListener<O>;
+	type ListenerUpgrade = BoxedListenerUpgrade<O>;
+	type Dial = BoxedDial<O>;
+
+	#[inline]
+	fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+		match self.inner.listen_on(addr) {
+			Ok(ok) => Ok(ok),
+			Err(addr) => Err((self, addr)),
+		}
+	}
+
+	#[inline]
+	fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+		match self.inner.dial(addr) {
+			Ok(dial) => Ok(dial),
+			Err(addr) => Err((self, addr)),
+		}
+	}
+
+	#[inline]
+	fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+		self.inner.nat_traversal(server, observed)
+	}
+
+	#[inline]
+	fn expand_listen_addr(&self, addr: &Multiaddr) -> Vec<Multiaddr> {
+		self.inner.expand_listen_addr(addr)
+	}
+}
+
+// Object-safe version of `Transport`. Since the methods of `Transport` consume the transport,
+// the implementation clones it before each call.
+trait Abstract<O> {
+	fn listen_on(&self, addr: Multiaddr) -> Result<(BoxedListener<O>, Multiaddr), Multiaddr>;
+	fn dial(&self, addr: Multiaddr) -> Result<BoxedDial<O>, Multiaddr>;
+	fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr>;
+	fn expand_listen_addr(&self, addr: &Multiaddr) -> Vec<Multiaddr>;
+}
+
+impl<T, O> Abstract<O> for T
+	where T: Transport<RawConn = O> + Clone + 'static,
+		  T::Listener: Send + 'static,
+		  T::ListenerUpgrade: Send + 'static,
+		  <T::Dial as IntoFuture>::Future: Send + 'static,
+{
+	fn listen_on(&self, addr: Multiaddr) -> Result<(BoxedListener<O>, Multiaddr), Multiaddr> {
+		let (listener, addr) = Transport::listen_on(self.clone(), addr)
+			.map_err(|(_, addr)| addr)?;
+		let listener = listener.map(|(upgrade, remote_addr)| {
+			let upgrade: BoxedListenerUpgrade<O> = Box::new(upgrade);
+			(upgrade, remote_addr)
+		});
+		Ok((Box::new(listener), addr))
+	}
+
+	fn dial(&self, addr: Multiaddr) -> Result<BoxedDial<O>, Multiaddr> {
+		let dial = Transport::dial(self.clone(), addr)
+			.map_err(|(_, addr)| addr)?;
+		Ok(Box::new(dial.into_future()))
+	}
+
+	#[inline]
+	fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+		Transport::nat_traversal(self, server, observed)
+	}
+
+	#[inline]
+	fn expand_listen_addr(&self, addr: &Multiaddr) -> Vec<Multiaddr> {
+		Transport::expand_listen_addr(self, addr)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Boxed;
+	use futures::{future, stream, Future, Stream};
+	use multiaddr::{AddrComponent, Multiaddr};
+	use std::io::{Cursor, Error as IoError};
+	use transport::{DeniedTransport, Transport};
+
+	// Transport that only supports `/tcp/<n>` addresses, and whose connections contain `n`.
+	#[derive(Debug, Clone)]
+	struct Memory;
+
+	fn parse(addr: &Multiaddr) -> Option<u8> {
+		let mut iter = addr.iter();
+		match (iter.next(), iter.next()) {
+			(Some(AddrComponent::TCP(port)), None) => Some(port as u8),
+			_ => None,
+		}
+	}
+
+	impl Transport for Memory {
+		type RawConn = Cursor<Vec<u8>>;
+		type Listener = stream::Once<(Self::ListenerUpgrade, Multiaddr), IoError>;
+		type ListenerUpgrade = future::FutureResult<Self::RawConn, IoError>;
+		type Dial = Result<Self::RawConn, IoError>;
+
+		fn listen_on(self, addr: Multiaddr)
+					 -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)>
+		{
+			match parse(&addr) {
+				Some(n) => {
+					let upgrade = future::ok(Cursor::new(vec![n]));
+					Ok((stream::once(Ok((upgrade, addr.clone()))), addr))
+				}
+				None => Err((self, addr)),
+			}
+		}
+
+		fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+			match parse(&addr) {
+				Some(n) => Ok(Ok(Cursor::new(vec![n]))),
+				None => Err((self, addr)),
+			}
+		}
+
+		fn nat_traversal(&self, _: &Multiaddr, _: &Multiaddr) -> Option<Multiaddr> {
+			None
+		}
+	}
+
+	// Builds the transport stack at runtime, as an application would do based on its
+	// configuration.
+	fn build(enabled: bool) -> Boxed<Cursor<Vec<u8>>> {
+		if enabled {
+			Memory.boxed()
+		} else {
+			DeniedTransport.boxed()
+		}
+	}
+
+	#[test]
+	fn dial_and_listen() {
+		let transport = build(true);
+		let addr: Multiaddr = "/tcp/7".parse().unwrap();
+
+		let conn = transport.clone().dial(addr.clone()).unwrap_or_else(|_| panic!()).wait();
+		assert_eq!(conn.unwrap().into_inner(), vec![7]);
+
+		let (listener, listen_addr) = transport.listen_on(addr.clone())
+			.unwrap_or_else(|_| panic!());
+		assert_eq!(listen_addr, addr);
+		let incoming = listener.into_future().map_err(|(err, _)| err)
+			.and_then(|(incoming, _)| incoming.unwrap().0)
+			.wait();
+		assert_eq!(incoming.unwrap().into_inner(), vec![7]);
+	}
+
+	#[test]
+	fn unsupported_addr() {
+		let addr: Multiaddr = "/tcp/7".parse().unwrap();
+		match build(false).dial(addr.clone()) {
+			Err((_, a)) => assert_eq!(a, addr),
+			Ok(_) => panic!(),
+		}
+		match build(false).listen_on(addr.clone()) {
+			Err((_, a)) => assert_eq!(a, addr),
+			Ok(_) => panic!(),
+		}
+	}
+}
@@ -0,0 +1,277 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `ClockSkew` struct, which estimates how far the clocks of the remotes are from
+//! ours.
+//!
+//! Some protocols carry timestamps, for example signed records or pubsub messages. Comparing
+//! these timestamps with the moment when we received them gives a (noisy) measurement of the
+//! offset between the clock of the remote and ours. The offset of a remote is the median of its
+//! recent measurements, and the offset of the network is the median of the offsets of the
+//! remotes. A handful of remotes with a wrong clock therefore can't move the estimate.
+//!
+//! This gives applications a loose agreement on time without having to trust an NTP server.
+//! The gossipsub behaviour measures the messages it receives directly from their author, and the
+//! peer store measures the new signed peer records, whose sequence numbers are timestamps.
+//!
+//! The measurements expire and the number of remotes is limited, so that a node that sees many
+//! remotes over time doesn't accumulate measurements forever.
+//!
+//! > **Note**: The measurements include the network latency, which makes the remotes appear
+//! >           slightly behind us. The estimate is only meaningful at the scale of seconds.
+
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use time::{self, Instant};
+
+// Measurements further than this from our clock are ignored, in milliseconds. They don't come
+// from a timestamp, for example because the remote uses a counter as sequence number.
+const MAX_OFFSET_MILLIS: i64 = 24 * 3600 * 1000;
+
+/// Collection of clock offset measurements, indexed by the identity of the remote.
+///
+/// Offsets are expressed in milliseconds. A positive offset means that the clock of the remote is
+/// ahead of ours. Measurements of more than a day are ignored.
+///
+/// The measurements expire after `max_age` (one hour by default), and at most `max_remotes`
+/// remotes (1024 by default) are tracked. When a new remote is measured while this limit is
+/// reached, the remote whose latest measurement is the oldest is forgotten.
+///
+/// Cloning a `ClockSkew` is cheap and produces an object that shares the same content.
+#[derive(Clone)]
+pub struct ClockSkew<K>
+	where K: Hash + Eq
+{
+	// Maximum number of measurements kept for each remote.
+	max_samples: usize,
+	// Maximum number of remotes whose measurements are kept.
+	max_remotes: usize,
+	// Duration after which a measurement is discarded.
+	max_age: Duration,
+	// Offsets measured for each remote, with the moment of the measurement, the oldest first.
+	samples: Arc<Mutex<HashMap<K, VecDeque<(i64, Instant)>>>>,
+}
+
+impl<K> ClockSkew<K>
+	where K: Hash + Eq
+{
+	/// Builds a new empty `ClockSkew`, which keeps the `max_samples` latest measurements of each
+	/// remote.
+	///
+	/// # Panic
+	///
+	/// Panics if `max_samples` is 0.
+	#[inline]
+	pub fn new(max_samples: usize) -> ClockSkew<K> {
+		assert_ne!(max_samples, 0, "the number of clock samples must not be zero");
+
+		ClockSkew {
+			max_samples: max_samples,
+			max_remotes: 1024,
+			max_age: Duration::from_secs(3600),
+			samples: Arc::new(Mutex::new(HashMap::new())),
+		}
+	}
+
+	/// Sets the maximum number of remotes whose measurements are kept.
+	///
+	/// # Panic
+	///
+	/// Panics if `max_remotes` is 0.
+	#[inline]
+	pub fn with_max_remotes(mut self, max_remotes: usize) -> ClockSkew<K> {
+		assert_ne!(max_remotes, 0, "the number of remotes must not be zero");
+		self.max_remotes = max_remotes;
+		self
+	}
+
+	/// Sets the duration after which a measurement is discarded.
+	#[inline]
+	pub fn with_max_age(mut self, max_age: Duration) -> ClockSkew<K> {
+		self.max_age = max_age;
+		self
+	}
+
+	/// Records that a message sent by `remote` at `remote_time` (according to the clock of the
+	/// remote) has been received at `received_at` (according to our clock).
+	pub fn record(&self, remote: K, remote_time: SystemTime, received_at: SystemTime) {
+		let offset = to_millis(remote_time) - to_millis(received_at);
+		if offset.abs() > MAX_OFFSET_MILLIS {
+			return;
+		}
+
+		let now = Instant::now();
+		let mut samples = self.samples.lock();
+		remove_expired(&mut samples, now, self.max_age);
+
+		if !samples.contains_key(&remote) && samples.len() >= self.max_remotes {
+			let oldest = samples.values().filter_map(|list| list.back().map(|s| s.1)).min();
+			let mut evicted = false;
+			samples.retain(|_, list| {
+				if !evicted && list.back().map(|s| s.1) == oldest {
+					evicted = true;
+					false
+				} else {
+					true
+				}
+			});
+		}
+
+		let list = samples.entry(remote).or_insert_with(VecDeque::new);
+		if list.len() >= self.max_samples {
+			list.pop_front();
+		}
+		list.push_back((offset, now));
+	}
+
+	/// Same as `record`, with `received_at` being now.
+	#[inline]
+	pub fn record_now(&self, remote: K, remote_time: SystemTime) {
+		self.record(remote, remote_time, time::system_now())
+	}
+
+	/// Forgets the measurements of `remote`, for example because it has been disconnected for a
+	/// long time.
+	#[inline]
+	pub fn remove(&self, remote: &K) {
+		self.samples.lock().remove(remote);
+	}
+
+	/// Returns the estimated offset of the clock of `remote`, in milliseconds.
+	pub fn remote_offset(&self, remote: &K) -> Option<i64> {
+		let mut samples = self.samples.lock();
+		remove_expired(&mut samples, Instant::now(), self.max_age);
+		samples.get(remote).and_then(|list| median(list.iter().map(|s| s.0).collect()))
+	}
+
+	/// Returns the estimated offset of the clock of the network, in milliseconds, and the number
+	/// of remotes that the estimate is based on.
+	///
+	/// Returns `None` if there is no measurement.
+	pub fn network_offset(&self) -> Option<(i64, usize)> {
+		let mut samples = self.samples.lock();
+		remove_expired(&mut samples, Instant::now(), self.max_age);
+		let offsets = samples.values()
+			.filter_map(|list| median(list.iter().map(|s| s.0).collect()))
+			.collect::<Vec<_>>();
+
+		let num_remotes = offsets.len();
+		median(offsets).map(|offset| (offset, num_remotes))
+	}
+}
+
+// Removes the measurements older than `max_age`, and the remotes that have no measurement left.
+fn remove_expired<K>(samples: &mut HashMap<K, VecDeque<(i64, Instant)>>, now: Instant,
+					 max_age: Duration)
+	where K: Hash + Eq
+{
+	samples.retain(|_, list| {
+		while list.front().map_or(false, |s| now.duration_since(s.1) >= max_age) {
+			list.pop_front();
+		}
+		!list.is_empty()
+	});
+}
+
+// Returns the number of milliseconds between the UNIX epoch and `time`, which can be negative.
+fn to_millis(time: SystemTime) -> i64 {
+	match time.duration_since(UNIX_EPOCH) {
+		Ok(d) => d.as_secs() as i64 * 1000 + i64::from(d.subsec_nanos() / 1_000_000),
+		Err(err) => {
+			let d = err.duration();
+			-(d.as_secs() as i64 * 1000 + i64::from(d.subsec_nanos() / 1_000_000))
+		},
+	}
+}
+
+// Returns the median of `values`, or `None` if it is empty. For an even number of values, the
+// average of the two middle values is returned.
+fn median(mut values: Vec<i64>) -> Option<i64> {
+	if values.is_empty() {
+		return None;
+	}
+
+	values.sort();
+	let mid = values.len() / 2;
+	if values.len() % 2 == 0 {
+		Some((values[mid - 1] + values[mid]) / 2)
+	} else {
+		Some(values[mid])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::ClockSkew;
+	use std::thread;
+	use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+	#[test]
+	fn outliers_dont_matter() {
+		let skew = ClockSkew::new(3);
+		let now = SystemTime::now();
+
+		// Remote 1 is 2 seconds ahead, with one bad measurement.
+		skew.record(1, now + Duration::from_secs(2), now);
+		skew.record(1, now + Duration::from_secs(2), now);
+		skew.record(1, now + Duration::from_secs(60), now);
+		assert_eq!(skew.remote_offset(&1), Some(2000));
+
+		// Remote 2 is 1 second behind, and remote 3 is wildly wrong.
+		skew.record(2, now - Duration::from_secs(1), now);
+		skew.record(3, now + Duration::from_secs(3600), now);
+		assert_eq!(skew.network_offset(), Some((2000, 3)));
+
+		skew.remove(&3);
+		assert_eq!(skew.network_offset(), Some((500, 2)));
+		assert_eq!(skew.remote_offset(&3), None);
+	}
+
+	#[test]
+	fn limited_remotes() {
+		let skew = ClockSkew::new(3).with_max_remotes(2);
+		let now = SystemTime::now();
+
+		// Not a timestamp.
+		skew.record(1, UNIX_EPOCH + Duration::from_secs(5), now);
+		assert_eq!(skew.remote_offset(&1), None);
+
+		// Remote 1 has the oldest measurement, and is forgotten when remote 3 is measured.
+		skew.record(1, now + Duration::from_secs(1), now);
+		thread::sleep(Duration::from_millis(5));
+		skew.record(2, now + Duration::from_secs(2), now);
+		thread::sleep(Duration::from_millis(5));
+		skew.record(3, now + Duration::from_secs(3), now);
+		assert_eq!(skew.remote_offset(&1), None);
+		assert_eq!(skew.network_offset(), Some((2500, 2)));
+	}
+
+	#[test]
+	fn measurements_expire() {
+		let skew = ClockSkew::new(3).with_max_age(Duration::from_secs(0));
+		let now = SystemTime::now();
+		skew.record(1, now + Duration::from_secs(1), now);
+		assert_eq!(skew.remote_offset(&1), None);
+		assert_eq!(skew.network_offset(), None);
+	}
+}
@@ -0,0 +1,571 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `ConnectionInfo` struct, which describes a connection handled by the swarm.
+//!
+//! The swarm fills the information it knows about by itself: the addresses, the endpoint and the
+//! moment when the connection has been established. The identity of the remote and the protocols
+//! that have been negotiated are only known by the upgrades of the transport. Wrapping them with
+//! `UpgradeRecorder::record_upgrade()` makes them record the name of the negotiated protocol and
+//! the identity found in their output. Once the recorder has been passed to
+//! `SwarmController::set_upgrade_recorder()`, the swarm copies what has been recorded into the
+//! information of the connections established with the same remote address. They can also be
+//! recorded by the user with `SwarmController::update_connection()`.
+//!
+//! Once the identity of the local node and the one of a remote are known, only one connection to
+//! this remote is kept. See the `peer_connections` module.
+
+use bytes::Bytes;
+use error::ErrorLayer;
+use futures::{Async, Future, Poll};
+use multiaddr::Multiaddr;
+use parking_lot::Mutex;
+use peer_connections::PeerConnections;
+use peer_id::PeerId;
+use std::collections::{HashMap, VecDeque};
+use std::io::Error as IoError;
+use std::sync::Arc;
+use std::time::SystemTime;
+use std::vec;
+use time;
+use tokio_io::{AsyncRead, AsyncWrite};
+use transport::{ConnectionUpgrade, Endpoint};
+
+/// Number of remote addresses whose recorded information is kept by an `UpgradeRecorder`. The
+/// information is copied when the connection is established, right after the upgrades.
+const MAX_RECORDED_ADDRS: usize = 1024;
+
+/// Identifier of a connection of the swarm. Never reused during the lifetime of a swarm.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(u64);
+
+/// Information about a connection handled by the swarm.
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+	id: ConnectionId,
+	remote_addr: Multiaddr,
+	local_addr: Option<Multiaddr>,
+	endpoint: Endpoint,
+	established: SystemTime,
+	remote_peer_id: Option<PeerId>,
+	security_protocol: Option<String>,
+	muxer_protocol: Option<String>,
+}
+
+impl ConnectionInfo {
+	/// Returns the identifier of the connection.
+	#[inline]
+	pub fn id(&self) -> ConnectionId {
+		self.id
+	}
+
+	/// Returns the address of the remote.
+	#[inline]
+	pub fn remote_addr(&self) -> &Multiaddr {
+		&self.remote_addr
+	}
+
+	/// Returns the address of the listener that accepted the connection. Always `None` for the
+	/// connections that we dialed.
+	#[inline]
+	pub fn local_addr(&self) -> Option<&Multiaddr> {
+		self.local_addr.as_ref()
+	}
+
+	/// Returns whether we dialed the remote or the remote dialed us.
+	#[inline]
+	pub fn endpoint(&self) -> Endpoint {
+		self.endpoint
+	}
+
+	/// Returns the moment when the connection has been successfully upgraded.
+	#[inline]
+	pub fn established(&self) -> SystemTime {
+		self.established
+	}
+
+	/// Returns the identity of the remote, if it has been recorded.
+	#[inline]
+	pub fn remote_peer_id(&self) -> Option<&PeerId> {
+		self.remote_peer_id.as_ref()
+	}
+
+	/// Records the identity of the remote.
+	#[inline]
+	pub fn set_remote_peer_id(&mut self, peer_id: PeerId) {
+		self.remote_peer_id = Some(peer_id);
+	}
+
+	/// Returns the name of the security protocol that has been negotiated (eg. `/secio/1.0.0`),
+	/// if it has been recorded.
+	#[inline]
+	pub fn security_protocol(&self) -> Option<&str> {
+		self.security_protocol.as_ref().map(|p| &p[..])
+	}
+
+	/// Records the name of the security protocol that has been negotiated.
+	#[inline]
+	pub fn set_security_protocol<S: Into<String>>(&mut self, protocol: S) {
+		self.security_protocol = Some(protocol.into());
+	}
+
+	/// Returns the name of the muxer protocol that has been negotiated (eg. `/mplex/6.7.0`), if it
+	/// has been recorded.
+	#[inline]
+	pub fn muxer_protocol(&self) -> Option<&str> {
+		self.muxer_protocol.as_ref().map(|p| &p[..])
+	}
+
+	/// Records the name of the muxer protocol that has been negotiated.
+	#[inline]
+	pub fn set_muxer_protocol<S: Into<String>>(&mut self, protocol: S) {
+		self.muxer_protocol = Some(protocol.into());
+	}
+}
+
+/// Collection of the connections of the swarm. Shared between the controller and the future.
+///
+/// Cloning a `Connections` is cheap and produces an object that shares the same content.
+#[derive(Clone, Default)]
+pub struct Connections {
+	inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+	// Identifier to assign to the next connection.
+	next_id: u64,
+	connections: HashMap<ConnectionId, ConnectionInfo>,
+	// Connection kept for each remote whose identity has been recorded. `None` as long as the
+	// identity of the local node isn't known.
+	peers: Option<PeerConnections<PeerId, ConnectionId>>,
+	// What the upgrades of the transport have found out about the connections.
+	recorder: Option<UpgradeRecorder>,
+}
+
+impl Connections {
+	/// Builds a new empty collection.
+	#[inline]
+	pub fn new() -> Connections {
+		Default::default()
+	}
+
+	/// Registers a connection that has just been established, and returns its identifier. What
+	/// the upgrades have recorded about `remote_addr` is copied into its information.
+	pub fn insert(&self, remote_addr: Multiaddr, local_addr: Option<Multiaddr>,
+				  endpoint: Endpoint) -> ConnectionId
+	{
+		let mut inner = self.inner.lock();
+		let id = ConnectionId(inner.next_id);
+		inner.next_id += 1;
+
+		let recorded = inner.recorder.as_ref()
+			.map(|recorder| recorder.get(&remote_addr))
+			.unwrap_or_default();
+		inner.connections.insert(id, ConnectionInfo {
+			id: id,
+			remote_addr: remote_addr,
+			local_addr: local_addr,
+			endpoint: endpoint,
+			established: time::system_now(),
+			remote_peer_id: recorded.remote_peer_id,
+			security_protocol: recorded.security_protocol,
+			muxer_protocol: recorded.muxer_protocol,
+		});
+
+		id
+	}
+
+	/// Removes a connection that has been closed.
+	pub fn remove(&self, id: ConnectionId) -> Option<ConnectionInfo> {
+		let mut inner = self.inner.lock();
+		let info = inner.connections.remove(&id);
+		if let Some(ref info) = info {
+			if let (Some(peers), Some(remote)) = (inner.peers.as_ref(), info.remote_peer_id()) {
+				peers.remove(remote, &id);
+			}
+		}
+		info
+	}
+
+	/// Removes all the connections.
+	pub fn clear(&self) {
+		let mut inner = self.inner.lock();
+		inner.connections.clear();
+		if let Some(ref peers) = inner.peers {
+			peers.clear();
+		}
+	}
+
+	/// Sets the `UpgradeRecorder` whose information is copied into the connections that are
+	/// registered afterwards.
+	#[inline]
+	pub fn set_recorder(&self, recorder: UpgradeRecorder) {
+		self.inner.lock().recorder = Some(recorder);
+	}
+
+	/// Sets the identity of the local node, which decides which connection to a remote is kept
+	/// when there are two of them. Forgets which connections have been kept so far.
+	#[inline]
+	pub fn set_local_peer_id(&self, peer_id: PeerId) {
+		self.inner.lock().peers = Some(PeerConnections::new(peer_id));
+	}
+
+	/// Registers `id` as a connection to the remote whose identity has been recorded in its
+	/// information. If another connection to the same remote is open, returns the one that must
+	/// be closed, which can be `id` itself.
+	///
+	/// Does nothing if the identity of the remote or of the local node isn't known.
+	pub fn register_peer(&self, id: ConnectionId) -> Option<ConnectionId> {
+		let inner = self.inner.lock();
+		let peers = match inner.peers {
+			Some(ref peers) => peers,
+			None => return None,
+		};
+		let (remote, endpoint) = match inner.connections.get(&id) {
+			Some(&ConnectionInfo { remote_peer_id: Some(ref remote), endpoint, .. }) => {
+				(remote.clone(), endpoint)
+			},
+			_ => return None,
+		};
+
+		// The identity can be recorded several times for the same connection.
+		if peers.get(&remote) == Some(id) {
+			return None;
+		}
+
+		peers.insert(remote, id, endpoint)
+	}
+
+	/// Returns the connection that is kept for `peer_id`, if any.
+	#[inline]
+	pub fn peer_connection(&self, peer_id: &PeerId) -> Option<ConnectionId> {
+		self.inner.lock().peers.as_ref().and_then(|peers| peers.get(peer_id))
+	}
+
+	/// Returns the identity of the remote of a connection, if it is open and the identity has
+	/// been recorded.
+	#[inline]
+	pub fn remote_peer_id(&self, id: ConnectionId) -> Option<PeerId> {
+		self.inner.lock().connections.get(&id).and_then(|info| info.remote_peer_id.clone())
+	}
+
+	/// Returns the information about a connection, if it is still open.
+	#[inline]
+	pub fn get(&self, id: ConnectionId) -> Option<ConnectionInfo> {
+		self.inner.lock().connections.get(&id).cloned()
+	}
+
+	/// Returns the information about all the open connections, ordered by identifier.
+	pub fn list(&self) -> Vec<ConnectionInfo> {
+		let mut list = self.inner.lock().connections.values().cloned().collect::<Vec<_>>();
+		list.sort_by_key(|info| info.id);
+		list
+	}
+
+	/// Calls `update` with the information about a connection. Returns false if the connection
+	/// isn't open.
+	pub fn update<F>(&self, id: ConnectionId, update: F) -> bool
+		where F: FnOnce(&mut ConnectionInfo)
+	{
+		match self.inner.lock().connections.get_mut(&id) {
+			Some(info) => {
+				update(info);
+				true
+			},
+			None => false,
+		}
+	}
+}
+
+/// Collects what the upgrades of a transport find out about the connections, so that the swarm
+/// fills the `ConnectionInfo`s with it. See `SwarmController::set_upgrade_recorder()`.
+///
+/// Cloning an `UpgradeRecorder` is cheap and produces an object that shares the same content.
+#[derive(Clone, Default)]
+pub struct UpgradeRecorder {
+	inner: Arc<Mutex<RecorderInner>>,
+}
+
+#[derive(Default)]
+struct RecorderInner {
+	recorded: HashMap<Multiaddr, Recorded>,
+	// Addresses of `recorded`, from the oldest to the newest.
+	order: VecDeque<Multiaddr>,
+}
+
+// Information recorded by the upgrades about a connection.
+#[derive(Debug, Clone, Default)]
+struct Recorded {
+	remote_peer_id: Option<PeerId>,
+	security_protocol: Option<String>,
+	muxer_protocol: Option<String>,
+}
+
+impl UpgradeRecorder {
+	/// Builds a new recorder that doesn't know about any connection.
+	#[inline]
+	pub fn new() -> UpgradeRecorder {
+		Default::default()
+	}
+
+	/// Wraps `upgrade`, an upgrade of the transport, so that the protocol it negotiates and the
+	/// identity of the remote are recorded. `peer_id` extracts this identity from the output of
+	/// the upgrade, and returns `None` if it isn't known, for example for the muxers.
+	///
+	/// The name of the protocol is recorded as the security protocol if `layer` is
+	/// `ErrorLayer::Security`, and as the muxer protocol if it is `ErrorLayer::Multiplexing`.
+	#[inline]
+	pub fn record_upgrade<U, F>(&self, upgrade: U, layer: ErrorLayer, peer_id: F)
+								-> RecordingUpgrade<U, F>
+	{
+		RecordingUpgrade {
+			inner: upgrade,
+			layer: layer,
+			recorder: self.clone(),
+			peer_id: Arc::new(peer_id),
+		}
+	}
+
+	// Records that an upgrade of `layer` has negotiated `protocol` on the connection with
+	// `remote_addr`, and found out that the remote is `remote_peer_id`.
+	fn record(&self, remote_addr: &Multiaddr, layer: ErrorLayer, protocol: &[u8],
+			  remote_peer_id: Option<PeerId>)
+	{
+		let mut guard = self.inner.lock();
+		let inner = &mut *guard;
+
+		if !inner.recorded.contains_key(remote_addr) {
+			if inner.order.len() >= MAX_RECORDED_ADDRS {
+				if let Some(oldest) = inner.order.pop_front() {
+					inner.recorded.remove(&oldest);
+				}
+			}
+			inner.order.push_back(remote_addr.clone());
+		}
+
+		let recorded = inner.recorded.entry(remote_addr.clone()).or_insert_with(Recorded::default);
+		let protocol = String::from_utf8_lossy(protocol).into_owned();
+		match layer {
+			ErrorLayer::Security => recorded.security_protocol = Some(protocol),
+			ErrorLayer::Multiplexing => recorded.muxer_protocol = Some(protocol),
+			ErrorLayer::Transport | ErrorLayer::Protocol => (),
+		}
+		if remote_peer_id.is_some() {
+			recorded.remote_peer_id = remote_peer_id;
+		}
+	}
+
+	// Returns what has been recorded about the connection with `remote_addr`.
+	fn get(&self, remote_addr: &Multiaddr) -> Recorded {
+		self.inner.lock().recorded.get(remote_addr).cloned().unwrap_or_default()
+	}
+}
+
+/// Wraps around a `ConnectionUpgrade` of the transport, and records the protocol that it has
+/// negotiated and the identity of the remote in an `UpgradeRecorder`. Built with
+/// `UpgradeRecorder::record_upgrade()`.
+///
+/// The `peer_id` function extracts the identity of the remote from the output of the upgrade,
+/// for example from the public key produced by secio. It returns `None` if the upgrade doesn't
+/// know about the identity, which is usually the case of the muxers.
+pub struct RecordingUpgrade<U, F> {
+	inner: U,
+	layer: ErrorLayer,
+	recorder: UpgradeRecorder,
+	peer_id: Arc<F>,
+}
+
+impl<U, F> Clone for RecordingUpgrade<U, F>
+	where U: Clone
+{
+	#[inline]
+	fn clone(&self) -> Self {
+		RecordingUpgrade {
+			inner: self.inner.clone(),
+			layer: self.layer,
+			recorder: self.recorder.clone(),
+			peer_id: self.peer_id.clone(),
+		}
+	}
+}
+
+impl<C, U, F> ConnectionUpgrade<C> for RecordingUpgrade<U, F>
+	where C: AsyncRead + AsyncWrite,
+		  U: ConnectionUpgrade<C>,
+		  F: Fn(&U::Output) -> Option<PeerId>
+{
+	type NamesIter = vec::IntoIter<(Bytes, Self::UpgradeIdentifier)>;
+	type UpgradeIdentifier = (Bytes, U::UpgradeIdentifier);
+	type Output = U::Output;
+	type Future = RecordingUpgradeFuture<U::Future, F>;
+
+	fn protocol_names(&self) -> Self::NamesIter {
+		self.inner.protocol_names()
+			.map(|(name, id)| (name.clone(), (name, id)))
+			.collect::<Vec<_>>()
+			.into_iter()
+	}
+
+	#[inline]
+	fn upgrade(self, socket: C, id: Self::UpgradeIdentifier, ty: Endpoint,
+			   remote_addr: &Multiaddr) -> Self::Future
+	{
+		let (protocol, id) = id;
+		RecordingUpgradeFuture {
+			inner: self.inner.upgrade(socket, id, ty, remote_addr),
+			layer: self.layer,
+			recorder: self.recorder,
+			peer_id: self.peer_id,
+			protocol: protocol,
+			remote_addr: remote_addr.clone(),
+		}
+	}
+}
+
+/// Future produced by a `RecordingUpgrade`.
+#[must_use = "futures do nothing unless polled"]
+pub struct RecordingUpgradeFuture<Fut, F> {
+	inner: Fut,
+	layer: ErrorLayer,
+	recorder: UpgradeRecorder,
+	peer_id: Arc<F>,
+	protocol: Bytes,
+	remote_addr: Multiaddr,
+}
+
+impl<Fut, F> Future for RecordingUpgradeFuture<Fut, F>
+	where Fut: Future<Error = IoError>,
+		  F: Fn(&Fut::Item) -> Option<PeerId>
+{
+	type Item = Fut::Item;
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let output = try_ready!(self.inner.poll());
+		let peer_id = (self.peer_id)(&output);
+		self.recorder.record(&self.remote_addr, self.layer, &self.protocol, peer_id);
+		Ok(Async::Ready(output))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Connections, UpgradeRecorder};
+	use error::ErrorLayer;
+	use futures::Future;
+	use multiaddr::Multiaddr;
+	use peer_id::PeerId;
+	use std::io::Cursor;
+	use transport::{ConnectionUpgrade, Endpoint, PlainTextConfig};
+
+	#[test]
+	fn insert_update_remove() {
+		let connections = Connections::new();
+		let remote = "/ip4/1.2.3.4/tcp/5".parse::<Multiaddr>().unwrap();
+		let local = "/ip4/0.0.0.0/tcp/10".parse::<Multiaddr>().unwrap();
+
+		let a = connections.insert(remote.clone(), Some(local.clone()), Endpoint::Listener);
+		let b = connections.insert(remote.clone(), None, Endpoint::Dialer);
+		assert_ne!(a, b);
+
+		assert!(connections.update(a, |info| info.set_security_protocol("/secio/1.0.0")));
+		let info = connections.get(a).unwrap();
+		assert_eq!(info.local_addr(), Some(&local));
+		assert_eq!(info.security_protocol(), Some("/secio/1.0.0"));
+		assert!(info.muxer_protocol().is_none());
+
+		assert_eq!(connections.list().iter().map(|c| c.id()).collect::<Vec<_>>(), vec![a, b]);
+		assert!(connections.remove(a).is_some());
+		assert!(!connections.update(a, |_| ()));
+		assert_eq!(connections.list().len(), 1);
+	}
+
+	#[test]
+	fn recorded_by_the_upgrades() {
+		let connections = Connections::new();
+		let recorder = UpgradeRecorder::new();
+		connections.set_recorder(recorder.clone());
+		let remote = "/ip4/1.2.3.4/tcp/5".parse::<Multiaddr>().unwrap();
+		let other = "/ip4/1.2.3.4/tcp/6".parse::<Multiaddr>().unwrap();
+		let remote_peer = PeerId::from_public_key(&[4, 5, 6]);
+
+		let peer = remote_peer.clone();
+		let security = recorder.record_upgrade(PlainTextConfig, ErrorLayer::Security,
+											   move |_: &Cursor<Vec<u8>>| Some(peer.clone()));
+		let (name, id) = security.protocol_names().next().unwrap();
+		let socket = Cursor::new(Vec::new());
+		assert!(security.upgrade(socket, id, Endpoint::Dialer, &remote).wait().is_ok());
+
+		let muxer = recorder.record_upgrade(PlainTextConfig, ErrorLayer::Multiplexing,
+											|_: &Cursor<Vec<u8>>| None);
+		let (_, id) = muxer.protocol_names().next().unwrap();
+		let socket = Cursor::new(Vec::new());
+		assert!(muxer.upgrade(socket, id, Endpoint::Dialer, &remote).wait().is_ok());
+
+		let info = connections.get(connections.insert(remote, None, Endpoint::Dialer)).unwrap();
+		assert_eq!(info.remote_peer_id(), Some(&remote_peer));
+		assert_eq!(info.security_protocol().map(|p| p.as_bytes()), Some(&name[..]));
+		assert_eq!(info.muxer_protocol().map(|p| p.as_bytes()), Some(&name[..]));
+
+		// Nothing has been recorded about the other address.
+		let info = connections.get(connections.insert(other, None, Endpoint::Dialer)).unwrap();
+		assert!(info.remote_peer_id().is_none());
+		assert!(info.security_protocol().is_none());
+	}
+
+	#[test]
+	fn duplicate_connection_to_peer() {
+		let connections = Connections::new();
+		let local_peer = PeerId::from_public_key(&[1, 2, 3]);
+		let remote_peer = PeerId::from_public_key(&[4, 5, 6]);
+		let remote = "/ip4/1.2.3.4/tcp/5".parse::<Multiaddr>().unwrap();
+		let local = "/ip4/0.0.0.0/tcp/10".parse::<Multiaddr>().unwrap();
+		connections.set_local_peer_id(local_peer.clone());
+
+		let dialed = connections.insert(remote.clone(), None, Endpoint::Dialer);
+		let accepted = connections.insert(remote, Some(local), Endpoint::Listener);
+		assert!(connections.register_peer(dialed).is_none());
+
+		let peer = remote_peer.clone();
+		assert!(connections.update(dialed, move |info| info.set_remote_peer_id(peer)));
+		assert!(connections.register_peer(dialed).is_none());
+		// Recording the identity a second time doesn't close the connection.
+		assert!(connections.register_peer(dialed).is_none());
+
+		let peer = remote_peer.clone();
+		assert!(connections.update(accepted, move |info| info.set_remote_peer_id(peer)));
+		let closed = connections.register_peer(accepted).unwrap();
+		let kept = connections.peer_connection(&remote_peer).unwrap();
+		assert_ne!(closed, kept);
+		let kept_endpoint = if local_peer < remote_peer {
+			Endpoint::Dialer
+		} else {
+			Endpoint::Listener
+		};
+		assert_eq!(connections.get(kept).unwrap().endpoint(), kept_endpoint);
+
+		// Closing the duplicate keeps the survivor.
+		assert!(connections.remove(closed).is_some());
+		assert_eq!(connections.peer_connection(&remote_peer), Some(kept));
+		assert!(connections.remove(kept).is_some());
+		assert!(connections.peer_connection(&remote_peer).is_none());
+	}
+}
@@ -54,7 +54,8 @@ use parking_lot::Mutex;
 use smallvec::SmallVec;
 use std::io::Error as IoError;
 use std::sync::Arc;
-use transport::{ConnectionUpgrade, MuxedTransport, Transport, UpgradedNode};
+use transport::{ConnectionUpgrade, MuxedTransport, Transport, UpgradedNode, UpgradedNodeDial};
+use transport::{UpgradedNodeListener, UpgradedNodeListenerUpgrade};
 
 /// Allows reusing the same muxed connection multiple times.
 ///
@@ -72,9 +73,12 @@ where
 	shared: Arc<Mutex<Shared<C::Output>>>,
 }
 
+// Error produced by a dial that is shared between the dialer and `incoming`.
+type SharedError = future::SharedError<Mutex<Option<IoError>>>;
+
 struct Shared<O> {
 	// List of futures to dialed connections.
-	incoming: Vec<Box<Stream<Item = (O, Multiaddr), Error = future::SharedError<Mutex<Option<IoError>>>>>>,
+	incoming: Vec<Box<Stream<Item = (O, Multiaddr), Error = SharedError> + Send>>,
 	// Tasks to signal when an element is added to `incoming`. Only used when `incoming` is empty.
 	to_signal: Vec<task::Task>,
 }
@@ -101,13 +105,18 @@ where
 	T: Transport + 'static,                     // TODO: 'static :(
 	C: ConnectionUpgrade<T::RawConn> + 'static, // TODO: 'static :(
 	C: Clone,
-	C::Output: StreamMuxer + Clone,
+	C::Output: StreamMuxer + Clone + Send + Sync,
 	C::NamesIter: Clone, // TODO: not elegant
+	<C::Output as StreamMuxer>::InboundSubstream: Send,
+	<C::Output as StreamMuxer>::OutboundSubstream: Send,
+	UpgradedNodeDial<T, C>: Send,
+	UpgradedNodeListener<T, C>: Send,
+	UpgradedNodeListenerUpgrade<T, C>: Send,
 {
 	type RawConn = <C::Output as StreamMuxer>::Substream;
-	type Listener = Box<Stream<Item = (Self::ListenerUpgrade, Multiaddr), Error = IoError>>;
+	type Listener = Box<Stream<Item = (Self::ListenerUpgrade, Multiaddr), Error = IoError> + Send>;
 	type ListenerUpgrade = FutureResult<Self::RawConn, IoError>;
-	type Dial = Box<Future<Item = Self::RawConn, Error = IoError>>;
+	type Dial = Box<Future<Item = Self::RawConn, Error = IoError> + Send>;
 
 	fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
 		let (listener, new_addr) = match self.inner.listen_on(addr.clone()) {
@@ -158,6 +167,11 @@ where
 	fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
 		self.inner.transport().nat_traversal(server, observed)
 	}
+
+	#[inline]
+	fn expand_listen_addr(&self, addr: &Multiaddr) -> Vec<Multiaddr> {
+		self.inner.transport().expand_listen_addr(addr)
+	}
 }
 
 impl<T, C> MuxedTransport for ConnectionReuse<T, C>
@@ -165,10 +179,17 @@ where
 	T: Transport + 'static,                     // TODO: 'static :(
 	C: ConnectionUpgrade<T::RawConn> + 'static, // TODO: 'static :(
 	C: Clone,
-	C::Output: StreamMuxer + Clone,
+	C::Output: StreamMuxer + Clone + Send + Sync,
 	C::NamesIter: Clone, // TODO: not elegant
+	<C::Output as StreamMuxer>::InboundSubstream: Send,
+	<C::Output as StreamMuxer>::OutboundSubstream: Send,
+	UpgradedNodeDial<T, C>: Send,
+	UpgradedNodeListener<T, C>: Send,
+	UpgradedNodeListenerUpgrade<T, C>: Send,
 {
-	type Incoming = Box<Future<Item = (<C::Output as StreamMuxer>::Substream, Multiaddr), Error = IoError>>;
+	type Incoming = Box<
+		Future<Item = (<C::Output as StreamMuxer>::Substream, Multiaddr), Error = IoError> + Send,
+	>;
 
 	#[inline]
 	fn next_incoming(self) -> Self::Incoming {
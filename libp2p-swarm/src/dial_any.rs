@@ -0,0 +1,186 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `DialAny` future, which dials several addresses of the same node concurrently
+//! and keeps the first connection that succeeds.
+//!
+//! Dialing the addresses of a node one by one can be very slow if some of them are unreachable,
+//! as each attempt has to time out before the next one starts. This is especially common with
+//! nodes that advertise both IPv4 and IPv6 addresses.
+
+use futures::{Async, Future, IntoFuture, Poll};
+use multiaddr::Multiaddr;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::vec::IntoIter as VecIntoIter;
+use TransportError;
+
+/// Builds a future that dials the addresses in `addrs`, with at most `concurrency` attempts
+/// running at the same time.
+///
+/// `dial` is called for each address and must return a future to the connection, or return the
+/// address back if it isn't supported.
+///
+/// The future produces the first connection that succeeds, alongside with its address. The other
+/// attempts are aborted. If all the attempts fail, the error of the last one is produced.
+///
+/// # Panic
+///
+/// Panics if `concurrency` is 0.
+pub fn dial_any<F, Fut, I>(addrs: I, concurrency: usize, dial: F) -> DialAny<F, Fut::Future>
+	where F: FnMut(Multiaddr) -> Result<Fut, Multiaddr>,
+		  Fut: IntoFuture<Error = IoError>,
+		  I: IntoIterator<Item = Multiaddr>
+{
+	assert_ne!(concurrency, 0, "dial concurrency must not be zero");
+
+	DialAny {
+		dial: dial,
+		pending: addrs.into_iter().collect::<Vec<_>>().into_iter(),
+		dialing: Vec::with_capacity(concurrency),
+		concurrency: concurrency,
+		last_error: None,
+	}
+}
+
+/// Future that dials several addresses concurrently. See `dial_any()`.
+#[must_use = "futures do nothing unless polled"]
+pub struct DialAny<F, Fut> {
+	dial: F,
+	// Addresses that we haven't tried yet.
+	pending: VecIntoIter<Multiaddr>,
+	// Attempts in progress.
+	dialing: Vec<(Fut, Multiaddr)>,
+	concurrency: usize,
+	// Error of the latest attempt that failed.
+	last_error: Option<IoError>,
+}
+
+impl<F, Fut, IFut> Future for DialAny<F, Fut>
+	where F: FnMut(Multiaddr) -> Result<IFut, Multiaddr>,
+		  IFut: IntoFuture<Future = Fut, Item = Fut::Item, Error = IoError>,
+		  Fut: Future<Error = IoError>
+{
+	type Item = (Fut::Item, Multiaddr);
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		loop {
+			while self.dialing.len() < self.concurrency {
+				let addr = match self.pending.next() {
+					Some(addr) => addr,
+					None => break,
+				};
+
+				match (self.dial)(addr.clone()) {
+					Ok(fut) => self.dialing.push((fut.into_future(), addr)),
+					Err(addr) => {
+						self.last_error = Some(TransportError::MultiaddrNotSupported(addr).into());
+					}
+				}
+			}
+
+			let mut failed_any = false;
+			for n in (0 .. self.dialing.len()).rev() {
+				match self.dialing[n].0.poll() {
+					Ok(Async::Ready(output)) => {
+						// Dropping the other futures aborts the other attempts.
+						let (_, addr) = self.dialing.swap_remove(n);
+						self.dialing.clear();
+						return Ok(Async::Ready((output, addr)));
+					}
+					Ok(Async::NotReady) => {}
+					Err(err) => {
+						self.dialing.swap_remove(n);
+						self.last_error = Some(err);
+						failed_any = true;
+					}
+				}
+			}
+
+			if self.dialing.is_empty() && self.pending.len() == 0 {
+				let err = self.last_error.take().unwrap_or_else(|| {
+					IoError::new(IoErrorKind::Other, "no address to dial")
+				});
+				return Err(err);
+			}
+
+			// If an attempt failed, we loop again in order to start dialing the next address.
+			if !failed_any {
+				return Ok(Async::NotReady);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::dial_any;
+	use futures::{future, Future};
+	use futures::sync::oneshot;
+	use multiaddr::Multiaddr;
+	use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+
+	type DialFuture = Box<Future<Item = u32, Error = IoError>>;
+
+	#[test]
+	fn first_success_wins() {
+		let addrs: Vec<Multiaddr> = vec![
+			"/ip4/1.1.1.1/tcp/1".parse().unwrap(),
+			"/ip4/2.2.2.2/tcp/2".parse().unwrap(),
+			"/ip4/3.3.3.3/tcp/3".parse().unwrap(),
+		];
+
+		// The first address never answers, the second one fails, and the third one succeeds.
+		let (_never_tx, never_rx) = oneshot::channel::<u32>();
+		let mut never_rx = Some(never_rx);
+		let future = dial_any(addrs, 2, move |addr| -> Result<DialFuture, Multiaddr> {
+			match addr.to_string().as_str() {
+				"/ip4/1.1.1.1/tcp/1" => {
+					let rx = never_rx.take().unwrap();
+					Ok(Box::new(rx.map_err(|_| IoError::new(IoErrorKind::Other, "canceled"))))
+				},
+				"/ip4/2.2.2.2/tcp/2" => Ok(Box::new(future::err(IoErrorKind::Other.into()))),
+				_ => Ok(Box::new(future::ok(3))),
+			}
+		});
+
+		let (output, addr) = future.wait().unwrap();
+		assert_eq!(output, 3);
+		assert_eq!(addr, "/ip4/3.3.3.3/tcp/3".parse::<Multiaddr>().unwrap());
+	}
+
+	#[test]
+	fn all_fail() {
+		let addrs: Vec<Multiaddr> = vec![
+			"/ip4/1.1.1.1/tcp/1".parse().unwrap(),
+			"/ip4/2.2.2.2/tcp/2".parse().unwrap(),
+		];
+
+		let future = dial_any(addrs, 1, |addr| {
+			if addr.to_string() == "/ip4/1.1.1.1/tcp/1" {
+				Err(addr)
+			} else {
+				Ok(future::err::<(), _>(IoError::new(IoErrorKind::ConnectionRefused, "refused")))
+			}
+		});
+
+		assert_eq!(future.wait().unwrap_err().kind(), IoErrorKind::ConnectionRefused);
+	}
+}
@@ -0,0 +1,609 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Errors produced by the transports, the upgrades and the swarm.
+//!
+//! The `Transport` and `ConnectionUpgrade` traits produce `IoError`s, so that any kind of
+//! transport or protocol can be plugged into the swarm. The errors of this module are stored
+//! inside these `IoError`s, and can be recovered with the `From<IoError>` implementations, which
+//! fall back to an I/O error if the `IoError` doesn't contain any of them.
+//!
+//! Conversions exist in both directions, therefore the `?` operator can be used to mix the
+//! errors of this module with `IoError`s.
+//!
+//! A `ContextError` wraps an error with the layer of the stack it comes from, and with the
+//! protocol, the remote and the endpoint of the connection. Since the `Display` implementations
+//! include the causes, a single line shows where in the stack a failure happened.
+
+use bytes::Bytes;
+use multiaddr::Multiaddr;
+use multistream_select::ProtocolChoiceError;
+use peer_id::PeerId;
+use std::error::Error;
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use transport::Endpoint;
+
+/// Error that can happen when upgrading a connection.
+#[derive(Debug)]
+pub enum UpgradeError {
+	/// Failed to negotiate a protocol with the remote.
+	Negotiation(ProtocolChoiceError),
+	/// A protocol has been negotiated, but its handshake failed.
+	Handshake(IoError),
+}
+
+impl From<ProtocolChoiceError> for UpgradeError {
+	#[inline]
+	fn from(err: ProtocolChoiceError) -> UpgradeError {
+		UpgradeError::Negotiation(err)
+	}
+}
+
+impl From<IoError> for UpgradeError {
+	#[inline]
+	fn from(err: IoError) -> UpgradeError {
+		match downcast_io_error::<UpgradeError>(err) {
+			Ok(err) => err,
+			Err(err) => UpgradeError::Handshake(err),
+		}
+	}
+}
+
+impl From<UpgradeError> for IoError {
+	#[inline]
+	fn from(err: UpgradeError) -> IoError {
+		let kind = match err {
+			UpgradeError::Negotiation(_) => IoErrorKind::Other,
+			UpgradeError::Handshake(ref err) => err.kind(),
+		};
+
+		IoError::new(kind, err)
+	}
+}
+
+impl fmt::Display for UpgradeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			UpgradeError::Negotiation(ref err) => write!(f, "protocol negotiation failed: {}", err),
+			UpgradeError::Handshake(ref err) => write!(f, "protocol handshake failed: {}", err),
+		}
+	}
+}
+
+impl Error for UpgradeError {
+	#[inline]
+	fn description(&self) -> &str {
+		match *self {
+			UpgradeError::Negotiation(_) => "protocol negotiation failed",
+			UpgradeError::Handshake(_) => "protocol handshake failed",
+		}
+	}
+
+	#[inline]
+	fn cause(&self) -> Option<&Error> {
+		match *self {
+			UpgradeError::Negotiation(ref err) => Some(err),
+			UpgradeError::Handshake(ref err) => Some(err),
+		}
+	}
+}
+
+/// Error that can happen when dialing or listening with a transport.
+#[derive(Debug)]
+pub enum TransportError {
+	/// The multiaddress isn't supported by the transport.
+	MultiaddrNotSupported(Multiaddr),
+	/// The connection has been opened, but upgrading it failed.
+	Upgrade(UpgradeError),
+	/// I/O error on the underlying connection.
+	Io(IoError),
+}
+
+impl From<UpgradeError> for TransportError {
+	#[inline]
+	fn from(err: UpgradeError) -> TransportError {
+		TransportError::Upgrade(err)
+	}
+}
+
+impl From<IoError> for TransportError {
+	fn from(err: IoError) -> TransportError {
+		let err = match downcast_io_error::<TransportError>(err) {
+			Ok(err) => return err,
+			Err(err) => err,
+		};
+
+		match downcast_io_error::<UpgradeError>(err) {
+			Ok(err) => TransportError::Upgrade(err),
+			Err(err) => TransportError::Io(err),
+		}
+	}
+}
+
+impl From<TransportError> for IoError {
+	fn from(err: TransportError) -> IoError {
+		match err {
+			TransportError::Upgrade(err) => err.into(),
+			// Unwrapping the error so that its kind is preserved.
+			TransportError::Io(err) => err,
+			err @ TransportError::MultiaddrNotSupported(_) => IoError::new(IoErrorKind::Other, err),
+		}
+	}
+}
+
+impl fmt::Display for TransportError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			TransportError::MultiaddrNotSupported(ref addr) => {
+				write!(f, "unsupported multiaddr {}", addr)
+			},
+			TransportError::Upgrade(ref err) => write!(f, "{}", err),
+			TransportError::Io(ref err) => write!(f, "{}", err),
+		}
+	}
+}
+
+impl Error for TransportError {
+	#[inline]
+	fn description(&self) -> &str {
+		match *self {
+			TransportError::MultiaddrNotSupported(_) => "unsupported multiaddr",
+			TransportError::Upgrade(ref err) => err.description(),
+			TransportError::Io(ref err) => err.description(),
+		}
+	}
+
+	#[inline]
+	fn cause(&self) -> Option<&Error> {
+		match *self {
+			TransportError::MultiaddrNotSupported(_) => None,
+			TransportError::Upgrade(ref err) => Some(err),
+			TransportError::Io(ref err) => Some(err),
+		}
+	}
+}
+
+/// Error that can happen when asking the swarm to dial a multiaddress.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DialError {
+	/// The multiaddress isn't supported by the transport.
+	Unsupported(Multiaddr),
+	/// The multiaddress is banned. See `SwarmController::ban_list()`.
+	Banned(Multiaddr),
+	/// The multiaddress points to the local node. See `SwarmController::is_self_addr()`.
+	SelfDial(Multiaddr),
+	/// The `ConnectionGater` of the swarm refused to dial the multiaddress.
+	Denied(Multiaddr),
+}
+
+impl DialError {
+	/// Returns the multiaddress that we tried to dial.
+	#[inline]
+	pub fn into_multiaddr(self) -> Multiaddr {
+		match self {
+			DialError::Unsupported(addr) => addr,
+			DialError::Banned(addr) => addr,
+			DialError::SelfDial(addr) => addr,
+			DialError::Denied(addr) => addr,
+		}
+	}
+}
+
+impl From<DialError> for IoError {
+	#[inline]
+	fn from(err: DialError) -> IoError {
+		IoError::new(IoErrorKind::Other, err)
+	}
+}
+
+impl fmt::Display for DialError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			DialError::Unsupported(ref addr) => write!(f, "unsupported multiaddr {}", addr),
+			DialError::Banned(ref addr) => write!(f, "multiaddr {} is banned", addr),
+			DialError::SelfDial(ref addr) => write!(f, "multiaddr {} points to ourselves", addr),
+			DialError::Denied(ref addr) => write!(f, "dialing multiaddr {} was denied", addr),
+		}
+	}
+}
+
+impl Error for DialError {
+	#[inline]
+	fn description(&self) -> &str {
+		match *self {
+			DialError::Unsupported(_) => "unsupported multiaddr",
+			DialError::Banned(_) => "banned multiaddr",
+			DialError::SelfDial(_) => "tried to dial ourselves",
+			DialError::Denied(_) => "dial denied by the connection gater",
+		}
+	}
+}
+
+/// Error reported by the swarm, either through `SwarmEvent`s or by the `SwarmFuture`.
+#[derive(Debug)]
+pub enum SwarmError {
+	/// The swarm refused to dial a multiaddress.
+	Dial(DialError),
+	/// Error produced by the transport or the upgrade of a connection.
+	Transport(TransportError),
+}
+
+impl From<DialError> for SwarmError {
+	#[inline]
+	fn from(err: DialError) -> SwarmError {
+		SwarmError::Dial(err)
+	}
+}
+
+impl From<TransportError> for SwarmError {
+	#[inline]
+	fn from(err: TransportError) -> SwarmError {
+		SwarmError::Transport(err)
+	}
+}
+
+impl From<UpgradeError> for SwarmError {
+	#[inline]
+	fn from(err: UpgradeError) -> SwarmError {
+		SwarmError::Transport(err.into())
+	}
+}
+
+impl From<IoError> for SwarmError {
+	fn from(err: IoError) -> SwarmError {
+		let err = match downcast_io_error::<SwarmError>(err) {
+			Ok(err) => return err,
+			Err(err) => err,
+		};
+
+		match downcast_io_error::<DialError>(err) {
+			Ok(err) => SwarmError::Dial(err),
+			Err(err) => SwarmError::Transport(err.into()),
+		}
+	}
+}
+
+impl From<SwarmError> for IoError {
+	#[inline]
+	fn from(err: SwarmError) -> IoError {
+		match err {
+			SwarmError::Dial(err) => err.into(),
+			SwarmError::Transport(err) => err.into(),
+		}
+	}
+}
+
+impl fmt::Display for SwarmError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			SwarmError::Dial(ref err) => write!(f, "{}", err),
+			SwarmError::Transport(ref err) => write!(f, "{}", err),
+		}
+	}
+}
+
+impl Error for SwarmError {
+	#[inline]
+	fn description(&self) -> &str {
+		match *self {
+			SwarmError::Dial(ref err) => err.description(),
+			SwarmError::Transport(ref err) => err.description(),
+		}
+	}
+
+	#[inline]
+	fn cause(&self) -> Option<&Error> {
+		match *self {
+			SwarmError::Dial(ref err) => Some(err),
+			SwarmError::Transport(ref err) => Some(err),
+		}
+	}
+}
+
+impl SwarmError {
+	/// Returns the outermost `ContextError` that is stored in this error, if any.
+	pub fn context(&self) -> Option<&ContextError> {
+		match *self {
+			SwarmError::Dial(_) => None,
+			SwarmError::Transport(TransportError::MultiaddrNotSupported(_)) => None,
+			SwarmError::Transport(TransportError::Upgrade(ref err)) => find_context_upgrade(err),
+			SwarmError::Transport(TransportError::Io(ref err)) => ContextError::find(err),
+		}
+	}
+}
+
+/// Layer of the networking stack in which an error happened.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ErrorLayer {
+	/// The raw transport, for example TCP.
+	Transport,
+	/// The encryption layer, for example secio.
+	Security,
+	/// The stream multiplexing layer, for example mplex.
+	Multiplexing,
+	/// A protocol running on top of the connection or of a substream, for example identify.
+	Protocol,
+}
+
+impl fmt::Display for ErrorLayer {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ErrorLayer::Transport => write!(f, "transport"),
+			ErrorLayer::Security => write!(f, "security"),
+			ErrorLayer::Multiplexing => write!(f, "multiplexing"),
+			ErrorLayer::Protocol => write!(f, "protocol"),
+		}
+	}
+}
+
+/// Error that wraps around another error and indicates where it happened.
+///
+/// Can be stored in an `IoError` thanks to the `From` implementation, and found back with
+/// `ContextError::find()` or `SwarmError::context()`. See also `UpgradeExt::with_error_context`,
+/// which attaches a context to the errors of an upgrade.
+#[derive(Debug)]
+pub struct ContextError {
+	layer: ErrorLayer,
+	protocol: Option<Bytes>,
+	endpoint: Option<Endpoint>,
+	remote_addr: Option<Multiaddr>,
+	remote_peer_id: Option<PeerId>,
+	inner: IoError,
+}
+
+impl ContextError {
+	/// Wraps around `inner`, which happened in the given layer.
+	#[inline]
+	pub fn new(layer: ErrorLayer, inner: IoError) -> ContextError {
+		ContextError {
+			layer: layer,
+			protocol: None,
+			endpoint: None,
+			remote_addr: None,
+			remote_peer_id: None,
+			inner: inner,
+		}
+	}
+
+	/// Sets the name of the protocol that produced the error.
+	#[inline]
+	pub fn with_protocol<P>(mut self, protocol: P) -> ContextError
+		where P: Into<Bytes>
+	{
+		self.protocol = Some(protocol.into());
+		self
+	}
+
+	/// Sets whether we dialed the remote or the remote dialed us.
+	#[inline]
+	pub fn with_endpoint(mut self, endpoint: Endpoint) -> ContextError {
+		self.endpoint = Some(endpoint);
+		self
+	}
+
+	/// Sets the address of the remote.
+	#[inline]
+	pub fn with_remote_addr(mut self, addr: Multiaddr) -> ContextError {
+		self.remote_addr = Some(addr);
+		self
+	}
+
+	/// Sets the identity of the remote.
+	#[inline]
+	pub fn with_remote_peer_id(mut self, peer_id: PeerId) -> ContextError {
+		self.remote_peer_id = Some(peer_id);
+		self
+	}
+
+	/// Returns the layer in which the error happened.
+	#[inline]
+	pub fn layer(&self) -> ErrorLayer {
+		self.layer
+	}
+
+	/// Returns the name of the protocol that produced the error, if known.
+	#[inline]
+	pub fn protocol(&self) -> Option<&[u8]> {
+		self.protocol.as_ref().map(|p| &p[..])
+	}
+
+	/// Returns whether we dialed the remote or the remote dialed us, if known.
+	#[inline]
+	pub fn endpoint(&self) -> Option<Endpoint> {
+		self.endpoint
+	}
+
+	/// Returns the address of the remote, if known.
+	#[inline]
+	pub fn remote_addr(&self) -> Option<&Multiaddr> {
+		self.remote_addr.as_ref()
+	}
+
+	/// Returns the identity of the remote, if known.
+	#[inline]
+	pub fn remote_peer_id(&self) -> Option<&PeerId> {
+		self.remote_peer_id.as_ref()
+	}
+
+	/// Returns the wrapped error.
+	#[inline]
+	pub fn get_ref(&self) -> &IoError {
+		&self.inner
+	}
+
+	/// Destroys the `ContextError` and returns the wrapped error.
+	#[inline]
+	pub fn into_inner(self) -> IoError {
+		self.inner
+	}
+
+	/// Returns the outermost `ContextError` that is stored in `err`, if any.
+	///
+	/// Looks through the errors of this module, therefore a `ContextError` that has been wrapped
+	/// in an `UpgradeError` or a `TransportError` is found as well.
+	#[inline]
+	pub fn find(err: &IoError) -> Option<&ContextError> {
+		err.get_ref().and_then(|inner| find_context(inner))
+	}
+}
+
+// Looks for a `ContextError` in an error stored inside an `IoError`.
+fn find_context<'a>(err: &'a (Error + Send + Sync + 'static)) -> Option<&'a ContextError> {
+	if let Some(err) = err.downcast_ref::<ContextError>() {
+		return Some(err);
+	}
+
+	if let Some(err) = err.downcast_ref::<UpgradeError>() {
+		return find_context_upgrade(err);
+	}
+
+	match err.downcast_ref::<TransportError>() {
+		Some(&TransportError::Upgrade(ref err)) => find_context_upgrade(err),
+		Some(&TransportError::Io(ref err)) => ContextError::find(err),
+		_ => None,
+	}
+}
+
+// Looks for a `ContextError` in an `UpgradeError`.
+fn find_context_upgrade(err: &UpgradeError) -> Option<&ContextError> {
+	match *err {
+		UpgradeError::Negotiation(_) => None,
+		UpgradeError::Handshake(ref err) => ContextError::find(err),
+	}
+}
+
+impl From<ContextError> for IoError {
+	#[inline]
+	fn from(err: ContextError) -> IoError {
+		IoError::new(err.inner.kind(), err)
+	}
+}
+
+impl fmt::Display for ContextError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{} error", self.layer)?;
+
+		let mut details = Vec::new();
+		if let Some(ref protocol) = self.protocol {
+			details.push(format!("protocol {}", String::from_utf8_lossy(protocol)));
+		}
+		match self.endpoint {
+			Some(Endpoint::Dialer) => details.push("dialer".to_owned()),
+			Some(Endpoint::Listener) => details.push("listener".to_owned()),
+			None => (),
+		}
+		if let Some(ref addr) = self.remote_addr {
+			details.push(format!("remote {}", addr));
+		}
+		if let Some(ref peer_id) = self.remote_peer_id {
+			details.push(format!("peer {}", peer_id));
+		}
+
+		if !details.is_empty() {
+			write!(f, " ({})", details.join(", "))?;
+		}
+
+		write!(f, ": {}", self.inner)
+	}
+}
+
+impl Error for ContextError {
+	#[inline]
+	fn description(&self) -> &str {
+		match self.layer {
+			ErrorLayer::Transport => "transport error",
+			ErrorLayer::Security => "security error",
+			ErrorLayer::Multiplexing => "multiplexing error",
+			ErrorLayer::Protocol => "protocol error",
+		}
+	}
+
+	#[inline]
+	fn cause(&self) -> Option<&Error> {
+		Some(&self.inner)
+	}
+}
+
+// Extracts an error of type `E` from an `IoError`, or returns the `IoError` back if it doesn't
+// contain such an error.
+fn downcast_io_error<E>(err: IoError) -> Result<E, IoError>
+	where E: Error + Send + Sync + 'static
+{
+	if !err.get_ref().map(|inner| inner.is::<E>()).unwrap_or(false) {
+		return Err(err);
+	}
+
+	let inner = err.into_inner().expect("we checked that the error has an inner error");
+	match inner.downcast::<E>() {
+		Ok(err) => Ok(*err),
+		Err(_) => unreachable!("we checked the type of the inner error"),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ContextError, ErrorLayer, SwarmError, TransportError, UpgradeError};
+	use transport::Endpoint;
+	use multistream_select::ProtocolChoiceError;
+	use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+
+	#[test]
+	fn upgrade_error_round_trip() {
+		let err: IoError = UpgradeError::Negotiation(ProtocolChoiceError::NoProtocolFound).into();
+		match TransportError::from(err) {
+			TransportError::Upgrade(UpgradeError::Negotiation(_)) => (),
+			other => panic!("unexpected {:?}", other),
+		}
+	}
+
+	#[test]
+	fn plain_io_error() {
+		let err = IoError::new(IoErrorKind::ConnectionRefused, "refused");
+		match SwarmError::from(err) {
+			SwarmError::Transport(TransportError::Io(ref err)) => {
+				assert_eq!(err.kind(), IoErrorKind::ConnectionRefused)
+			},
+			other => panic!("unexpected {:?}", other),
+		}
+	}
+
+	#[test]
+	fn context_is_found_through_layers() {
+		let inner = IoError::new(IoErrorKind::InvalidData, "bad frame");
+		let err: IoError = ContextError::new(ErrorLayer::Multiplexing, inner)
+			.with_protocol("/mplex/6.7.0")
+			.with_endpoint(Endpoint::Dialer)
+			.with_remote_addr("/ip4/1.2.3.4/tcp/4001".parse().unwrap())
+			.into();
+		assert_eq!(err.kind(), IoErrorKind::InvalidData);
+
+		// The handshake of an upgrade failed with the error above.
+		let err = SwarmError::from(IoError::from(UpgradeError::from(err)));
+		let context = err.context().expect("the context must be found");
+		assert_eq!(context.layer(), ErrorLayer::Multiplexing);
+		assert_eq!(context.protocol(), Some(&b"/mplex/6.7.0"[..]));
+		assert_eq!(
+			err.to_string(),
+			"protocol handshake failed: multiplexing error (protocol /mplex/6.7.0, dialer, \
+			 remote /ip4/1.2.3.4/tcp/4001): bad frame"
+		);
+	}
+}
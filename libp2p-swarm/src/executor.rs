@@ -0,0 +1,122 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `Executor` trait, which allows running futures in the background, and the
+//! `ThreadPoolExecutor`, which runs them on a thread pool.
+
+use futures::Future;
+use futures_cpupool::CpuPool;
+
+/// Runs futures in the background.
+///
+/// By default the swarm processes all its connections inside of the `SwarmFuture`. Passing an
+/// executor to `SwarmController::set_executor` makes it spawn each connection as a separate task
+/// instead, so that the connections don't all get polled every time one of them makes progress.
+/// The transports that run background tasks, such as the uTP transport or the resolver of the
+/// DNS transport, accept an executor as well.
+///
+/// The executors can be shared between threads, which keeps the `SwarmController` and the
+/// `SwarmFuture` `Send`. This trait is implemented on closures, which makes it possible to use
+/// any executor. For example with a tokio-core `Remote`:
+///
+/// ```ignore
+/// let remote = core.remote();
+/// swarm_controller.set_executor(move |future| remote.spawn(move |_| future));
+/// ```
+pub trait Executor: Send + Sync {
+	/// Spawns a future, which must be polled until it finishes.
+	fn spawn(&self, future: Box<Future<Item = (), Error = ()> + Send>);
+}
+
+impl<F> Executor for F
+	where F: Fn(Box<Future<Item = (), Error = ()> + Send>) + Send + Sync
+{
+	#[inline]
+	fn spawn(&self, future: Box<Future<Item = (), Error = ()> + Send>) {
+		self(future)
+	}
+}
+
+/// Implementation of `Executor` that runs the futures on a pool of threads.
+///
+/// Cloning a `ThreadPoolExecutor` gives access to the same threads. The threads stop once all
+/// the clones are destroyed and all the futures are finished.
+#[derive(Clone)]
+pub struct ThreadPoolExecutor {
+	pool: CpuPool,
+}
+
+impl ThreadPoolExecutor {
+	/// Creates a pool of `num_threads` threads.
+	///
+	/// # Panic
+	///
+	/// Panics if `num_threads` is 0.
+	#[inline]
+	pub fn new(num_threads: usize) -> ThreadPoolExecutor {
+		ThreadPoolExecutor {
+			pool: CpuPool::new(num_threads),
+		}
+	}
+
+	/// Creates a pool with one thread per CPU.
+	#[inline]
+	pub fn with_num_cpus() -> ThreadPoolExecutor {
+		ThreadPoolExecutor {
+			pool: CpuPool::new_num_cpus(),
+		}
+	}
+}
+
+impl Executor for ThreadPoolExecutor {
+	#[inline]
+	fn spawn(&self, future: Box<Future<Item = (), Error = ()> + Send>) {
+		// The future keeps running after the handle returned by the pool is dropped.
+		self.pool.spawn(future).forget()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use executor::{Executor, ThreadPoolExecutor};
+	use futures::future;
+	use std::sync::mpsc;
+	use std::thread;
+
+	#[test]
+	fn thread_pool_runs_the_futures() {
+		let executor = ThreadPoolExecutor::new(2);
+		let (tx, rx) = mpsc::channel();
+		for n in 0 .. 10 {
+			let tx = tx.clone();
+			executor.spawn(Box::new(future::lazy(move || {
+				tx.send((n, thread::current().id())).unwrap();
+				Ok::<_, ()>(())
+			})));
+		}
+
+		let mut results = rx.iter().take(10).collect::<Vec<_>>();
+		results.sort_by_key(|&(n, _)| n);
+		let numbers = results.iter().map(|&(n, _)| n).collect::<Vec<_>>();
+		assert_eq!(numbers, (0 .. 10).collect::<Vec<_>>());
+		// The futures didn't run on the thread that spawned them.
+		assert!(results.iter().all(|&(_, id)| id != thread::current().id()));
+	}
+}
@@ -0,0 +1,227 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `FairScheduler` struct, which merges the streams of several substreams of the
+//! same connection without letting one of them starve the others.
+//!
+//! The streams are polled in a round-robin fashion, and each of them can produce at most a
+//! certain number of elements (its *quota*) per poll cycle. Once all the streams that are ready
+//! have used their quota, the scheduler yields back to the event loop and the next cycle starts.
+//! This guarantees that an extremely busy substream (eg. a request-response protocol) can't delay
+//! the processing of a quieter but important one (eg. pubsub) for too long.
+
+use futures::{Async, Poll, Stream};
+use futures::task::{self, Task};
+use smallvec::SmallVec;
+
+/// Merges multiple streams and polls them fairly.
+///
+/// Each stream is associated to a key of type `K`, which is returned alongside with the elements
+/// and errors it produces.
+pub struct FairScheduler<K, S> {
+	streams: Vec<Entry<K, S>>,
+	// Maximum number of elements that a single stream can produce during a poll cycle.
+	quota: usize,
+	// Index of the stream to poll first.
+	next: usize,
+	// Task to notify when a stream is added.
+	to_notify: Option<Task>,
+}
+
+struct Entry<K, S> {
+	key: K,
+	stream: S,
+	// Number of elements produced by this stream during the current poll cycle.
+	used: usize,
+}
+
+impl<K, S> FairScheduler<K, S> {
+	/// Builds a new empty `FairScheduler`. Each stream can produce at most `quota` elements per
+	/// poll cycle.
+	///
+	/// # Panic
+	///
+	/// Panics if `quota` is 0.
+	#[inline]
+	pub fn new(quota: usize) -> FairScheduler<K, S> {
+		assert_ne!(quota, 0, "the quota of a scheduler must not be zero");
+
+		FairScheduler {
+			streams: Vec::new(),
+			quota: quota,
+			next: 0,
+			to_notify: None,
+		}
+	}
+
+	/// Adds a stream to the scheduler.
+	pub fn push(&mut self, key: K, stream: S) {
+		self.streams.push(Entry {
+			key: key,
+			stream: stream,
+			used: 0,
+		});
+
+		if let Some(task) = self.to_notify.take() {
+			task.notify();
+		}
+	}
+
+	/// Returns the number of streams in the scheduler.
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.streams.len()
+	}
+
+	/// Returns true if the scheduler doesn't contain any stream.
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.streams.is_empty()
+	}
+}
+
+/// Implementation of `Stream` for the `FairScheduler`.
+///
+/// A stream that finishes is silently removed from the scheduler. A stream that produces an
+/// error is removed as well, and the error is produced alongside with the key of the stream. The
+/// scheduler can continue to be polled afterwards.
+///
+/// The scheduler never finishes by itself, as new streams can be pushed at any time.
+impl<K, S> Stream for FairScheduler<K, S>
+	where K: Clone,
+		  S: Stream
+{
+	type Item = (K, S::Item);
+	type Error = (K, S::Error);
+
+	fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+		let mut finished: SmallVec<[usize; 8]> = SmallVec::new();
+		let mut outcome = None;
+		let mut quota_exhausted = false;
+
+		let len = self.streams.len();
+		for offset in 0 .. len {
+			let index = (self.next + offset) % len;
+			let entry = &mut self.streams[index];
+
+			if entry.used >= self.quota {
+				quota_exhausted = true;
+				continue;
+			}
+
+			match entry.stream.poll() {
+				Ok(Async::Ready(Some(item))) => {
+					entry.used += 1;
+					self.next = index + 1;
+					outcome = Some(Ok((entry.key.clone(), item)));
+					break;
+				},
+				Ok(Async::Ready(None)) => {
+					finished.push(index);
+				},
+				Ok(Async::NotReady) => {},
+				Err(err) => {
+					finished.push(index);
+					outcome = Some(Err((entry.key.clone(), err)));
+					break;
+				},
+			}
+		}
+
+		// Removing the streams while preserving the order, so that the round-robin isn't
+		// disturbed.
+		finished.sort();
+		for &index in finished.iter().rev() {
+			self.streams.remove(index);
+			if index < self.next {
+				self.next -= 1;
+			}
+		}
+
+		match outcome {
+			Some(Ok(item)) => Ok(Async::Ready(Some(item))),
+			Some(Err(err)) => Err(err),
+			None => {
+				// End of the poll cycle.
+				for entry in self.streams.iter_mut() {
+					entry.used = 0;
+				}
+
+				// Some streams were skipped because they had used their quota. They may still
+				// be ready, therefore we ask to be polled again after the other futures of the
+				// event loop had a chance to run.
+				if quota_exhausted {
+					task::current().notify();
+				} else {
+					self.to_notify = Some(task::current());
+				}
+
+				Ok(Async::NotReady)
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::FairScheduler;
+	use futures::Async;
+	use futures::executor;
+	use futures::stream;
+	use std::sync::Arc;
+
+	struct NoNotify;
+	impl executor::Notify for NoNotify {
+		fn notify(&self, _: usize) {}
+	}
+
+	#[test]
+	fn busy_stream_doesnt_starve_others() {
+		let mut scheduler = FairScheduler::new(2);
+		scheduler.push(0, stream::iter_ok::<_, ()>((0 .. 100).collect::<Vec<_>>()));
+		scheduler.push(1, stream::iter_ok::<_, ()>(vec![1000, 1001, 1002]));
+
+		let mut spawn = executor::spawn(scheduler);
+		let notify = Arc::new(NoNotify);
+		let mut polled = Vec::new();
+		while polled.len() < 6 {
+			match spawn.poll_stream_notify(&notify, 0) {
+				Ok(Async::Ready(Some(item))) => polled.push(item),
+				Ok(Async::NotReady) => (),
+				other => panic!("unexpected {:?}", other),
+			}
+		}
+
+		assert_eq!(polled, vec![(0, 0), (1, 1000), (0, 1), (1, 1001), (0, 2), (1, 1002)]);
+	}
+
+	#[test]
+	fn quota_ends_cycle() {
+		let mut scheduler = FairScheduler::new(2);
+		scheduler.push(0, stream::iter_ok::<_, ()>((0 .. 100).collect::<Vec<_>>()));
+
+		let mut spawn = executor::spawn(scheduler);
+		let notify = Arc::new(NoNotify);
+		assert_eq!(spawn.poll_stream_notify(&notify, 0), Ok(Async::Ready(Some((0, 0)))));
+		assert_eq!(spawn.poll_stream_notify(&notify, 0), Ok(Async::Ready(Some((0, 1)))));
+		assert_eq!(spawn.poll_stream_notify(&notify, 0), Ok(Async::NotReady));
+		assert_eq!(spawn.poll_stream_notify(&notify, 0), Ok(Async::Ready(Some((0, 2)))));
+	}
+}
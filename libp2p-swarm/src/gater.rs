@@ -0,0 +1,219 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `ConnectionGater` trait, which lets the user decide which connections the swarm
+//! is allowed to open or accept.
+//!
+//! The swarm consults the gater at three stages:
+//!
+//! - Before dialing a multiaddress, with `allow_dial`. A refusal produces a `DialError::Denied`.
+//! - When a listener accepts an incoming connection and before upgrading it, with
+//!   `allow_accept`. A refused connection is dropped immediately.
+//! - Once the identity of the remote is known, with `allow_secured`. The swarm itself doesn't
+//!   know when the security handshake is finished, therefore this stage requires wrapping the
+//!   upgrade in a `GatedUpgrade` (see `SwarmController::gate_upgrade()`).
+//!
+//! The gater can also mark the remotes that are protected with `is_protected`. When the swarm is
+//! configured to shed connections under accept pressure, the protected remotes are still
+//! accepted while the other ones are rejected.
+//!
+//! All the methods have a default implementation that allows everything, so that an
+//! implementation only needs to override the stages it is interested in.
+
+use futures::{Async, Future, Poll};
+use multiaddr::Multiaddr;
+use peer_id::PeerId;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::sync::Arc;
+use tokio_io::{AsyncRead, AsyncWrite};
+use transport::{ConnectionUpgrade, Endpoint};
+
+/// Policy that allows or denies connections. See the module-level documentation.
+pub trait ConnectionGater: Send + Sync {
+	/// Returns false if the swarm must not dial `addr`.
+	#[inline]
+	fn allow_dial(&self, _addr: &Multiaddr) -> bool {
+		true
+	}
+
+	/// Returns false if the connection from `remote_addr`, accepted by the listener on
+	/// `local_addr`, must be dropped before being upgraded.
+	#[inline]
+	fn allow_accept(&self, _local_addr: &Multiaddr, _remote_addr: &Multiaddr) -> bool {
+		true
+	}
+
+	/// Returns true if the incoming connection from `remote_addr` must still be upgraded when
+	/// the swarm sheds connections because too many of them are being upgraded, for example
+	/// because the address belongs to a peer that is tagged as important in the peerstore. See
+	/// `SwarmController::set_shed_under_pressure()`.
+	#[inline]
+	fn is_protected(&self, _remote_addr: &Multiaddr) -> bool {
+		false
+	}
+
+	/// Returns false if the connection with `remote_addr`, whose identity is `remote_peer_id`,
+	/// must be closed.
+	#[inline]
+	fn allow_secured(&self, _remote_addr: &Multiaddr, _endpoint: Endpoint,
+					 _remote_peer_id: &PeerId) -> bool
+	{
+		true
+	}
+}
+
+/// Implementation of `ConnectionGater` that allows everything. Used by default by the swarm.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AllowAllGater;
+
+impl ConnectionGater for AllowAllGater {}
+
+/// Wraps around a `ConnectionUpgrade`, and consults a `ConnectionGater` once the upgrade has
+/// finished.
+///
+/// The `peer_id` function extracts the identity of the remote from the output of the upgrade,
+/// for example from the public key produced by secio. If it returns `None`, the gater isn't
+/// consulted. If the gater denies the connection, the upgrade produces an error of kind
+/// `PermissionDenied`.
+pub struct GatedUpgrade<U, F> {
+	inner: U,
+	gater: Arc<ConnectionGater>,
+	peer_id: Arc<F>,
+}
+
+impl<U, F> GatedUpgrade<U, F> {
+	/// Builds a new `GatedUpgrade`.
+	#[inline]
+	pub fn new(inner: U, gater: Arc<ConnectionGater>, peer_id: F) -> GatedUpgrade<U, F> {
+		GatedUpgrade {
+			inner: inner,
+			gater: gater,
+			peer_id: Arc::new(peer_id),
+		}
+	}
+}
+
+impl<U, F> Clone for GatedUpgrade<U, F>
+	where U: Clone
+{
+	#[inline]
+	fn clone(&self) -> Self {
+		GatedUpgrade {
+			inner: self.inner.clone(),
+			gater: self.gater.clone(),
+			peer_id: self.peer_id.clone(),
+		}
+	}
+}
+
+impl<C, U, F> ConnectionUpgrade<C> for GatedUpgrade<U, F>
+	where C: AsyncRead + AsyncWrite,
+		  U: ConnectionUpgrade<C>,
+		  F: Fn(&U::Output) -> Option<PeerId>
+{
+	type NamesIter = U::NamesIter;
+	type UpgradeIdentifier = U::UpgradeIdentifier;
+	type Output = U::Output;
+	type Future = GatedUpgradeFuture<U::Future, F>;
+
+	#[inline]
+	fn protocol_names(&self) -> Self::NamesIter {
+		self.inner.protocol_names()
+	}
+
+	#[inline]
+	fn upgrade(self, socket: C, id: Self::UpgradeIdentifier, ty: Endpoint,
+			   remote_addr: &Multiaddr) -> Self::Future
+	{
+		GatedUpgradeFuture {
+			inner: self.inner.upgrade(socket, id, ty, remote_addr),
+			gater: self.gater,
+			peer_id: self.peer_id,
+			remote_addr: remote_addr.clone(),
+			endpoint: ty,
+		}
+	}
+}
+
+/// Future produced by a `GatedUpgrade`.
+#[must_use = "futures do nothing unless polled"]
+pub struct GatedUpgradeFuture<Fut, F> {
+	inner: Fut,
+	gater: Arc<ConnectionGater>,
+	peer_id: Arc<F>,
+	remote_addr: Multiaddr,
+	endpoint: Endpoint,
+}
+
+impl<Fut, F> Future for GatedUpgradeFuture<Fut, F>
+	where Fut: Future<Error = IoError>,
+		  F: Fn(&Fut::Item) -> Option<PeerId>
+{
+	type Item = Fut::Item;
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let output = try_ready!(self.inner.poll());
+
+		if let Some(peer_id) = (self.peer_id)(&output) {
+			if !self.gater.allow_secured(&self.remote_addr, self.endpoint, &peer_id) {
+				return Err(IoError::new(IoErrorKind::PermissionDenied,
+										"connection denied by the gater"));
+			}
+		}
+
+		Ok(Async::Ready(output))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ConnectionGater, GatedUpgrade};
+	use futures::Future;
+	use multiaddr::Multiaddr;
+	use peer_id::PeerId;
+	use std::io::{Cursor, ErrorKind as IoErrorKind};
+	use std::sync::Arc;
+	use transport::{ConnectionUpgrade, Endpoint, PlainTextConfig};
+
+	struct DenyPeer(PeerId);
+	impl ConnectionGater for DenyPeer {
+		fn allow_secured(&self, _: &Multiaddr, _: Endpoint, peer_id: &PeerId) -> bool {
+			peer_id != &self.0
+		}
+	}
+
+	#[test]
+	fn secured_stage() {
+		let addr = "/ip4/1.2.3.4/tcp/5".parse::<Multiaddr>().unwrap();
+		let gater = Arc::new(DenyPeer(PeerId::from_public_key(&[1, 2, 3])));
+
+		let upgrade = GatedUpgrade::new(PlainTextConfig, gater.clone(),
+								|_: &Cursor<Vec<u8>>| Some(PeerId::from_public_key(&[4, 5, 6])));
+		let socket = Cursor::new(Vec::new());
+		assert!(upgrade.upgrade(socket, (), Endpoint::Dialer, &addr).wait().is_ok());
+
+		let upgrade = GatedUpgrade::new(PlainTextConfig, gater,
+								|_: &Cursor<Vec<u8>>| Some(PeerId::from_public_key(&[1, 2, 3])));
+		let socket = Cursor::new(Vec::new());
+		let err = upgrade.upgrade(socket, (), Endpoint::Dialer, &addr).wait().unwrap_err();
+		assert_eq!(err.kind(), IoErrorKind::PermissionDenied);
+	}
+}
@@ -0,0 +1,373 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `InboundLimit` transport wrapper, which bounds the number of incoming connections
+//! that are upgraded at the same time.
+//!
+//! When the bound is reached, the listeners stop accepting connections until one of the upgrades
+//! in progress finishes or is dropped. In the meantime, the new connections wait in the backlog
+//! of the operating system instead of each starting a handshake, which protects the node against
+//! a flood of incoming connections.
+//!
+//! The swarm bounds the upgrades of its listeners with the same mechanism, and the limit can be
+//! changed with `SwarmController::set_max_listener_upgrades()`. Since the swarm always applies its
+//! own limit, wrapping the transport of a swarm in an `InboundLimit` adds a second limit on top of
+//! it, and a connection is only accepted once both have a free slot. This wrapper is therefore
+//! meant for the code that uses the listeners of a transport directly.
+
+use futures::{Async, Future, Poll, Stream};
+use futures::task::{self, Task};
+use multiaddr::Multiaddr;
+use parking_lot::Mutex;
+use std::fmt;
+use std::io::Error as IoError;
+use std::sync::Arc;
+use transport::{MuxedTransport, Transport};
+
+/// Wraps around a `Transport` and bounds the number of incoming connections that are upgraded at
+/// the same time, across all the listeners created from this object and its clones.
+#[derive(Debug, Clone)]
+pub struct InboundLimit<T> {
+	inner: T,
+	shared: Arc<UpgradeSlots>,
+}
+
+impl<T> InboundLimit<T> {
+	/// Wraps around `inner`. At most `max` of the `ListenerUpgrade`s produced by the listeners can
+	/// be alive at the same time.
+	///
+	/// # Panic
+	///
+	/// Panics if `max` is 0.
+	#[inline]
+	pub fn new(inner: T, max: usize) -> InboundLimit<T> {
+		assert_ne!(max, 0, "the maximum number of inbound upgrades must not be zero");
+
+		InboundLimit {
+			inner: inner,
+			shared: UpgradeSlots::new(max),
+		}
+	}
+
+	/// Returns the number of incoming connections that are being upgraded.
+	#[inline]
+	pub fn pending_upgrades(&self) -> usize {
+		self.shared.pending()
+	}
+}
+
+impl<T> Transport for InboundLimit<T>
+	where T: Transport
+{
+	type RawConn = T::RawConn;
+	type Listener = InboundLimitListener<T::Listener>;
+	type ListenerUpgrade = InboundLimitUpgrade<T::ListenerUpgrade>;
+	type Dial = T::Dial;
+
+	fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+		let shared = self.shared;
+		match self.inner.listen_on(addr) {
+			Ok((listener, addr)) => {
+				let listener = InboundLimitListener {
+					inner: listener,
+					shared: shared,
+				};
+				Ok((listener, addr))
+			}
+			Err((inner, addr)) => Err((InboundLimit { inner: inner, shared: shared }, addr)),
+		}
+	}
+
+	#[inline]
+	fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+		let shared = self.shared;
+		self.inner
+			.dial(addr)
+			.map_err(|(inner, addr)| (InboundLimit { inner: inner, shared: shared }, addr))
+	}
+
+	#[inline]
+	fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+		self.inner.nat_traversal(server, observed)
+	}
+
+	#[inline]
+	fn expand_listen_addr(&self, addr: &Multiaddr) -> Vec<Multiaddr> {
+		self.inner.expand_listen_addr(addr)
+	}
+}
+
+impl<T> MuxedTransport for InboundLimit<T>
+	where T: MuxedTransport
+{
+	type Incoming = T::Incoming;
+
+	#[inline]
+	fn next_incoming(self) -> Self::Incoming {
+		self.inner.next_incoming()
+	}
+}
+
+/// Slots of the upgrades in progress. Shared between an `InboundLimit`, its listeners and the
+/// upgrades, or between a swarm and the upgrades of its listeners.
+pub(crate) struct UpgradeSlots {
+	state: Mutex<SlotsState>,
+}
+
+impl UpgradeSlots {
+	/// Creates the slots of at most `max` upgrades.
+	pub(crate) fn new(max: usize) -> Arc<UpgradeSlots> {
+		Arc::new(UpgradeSlots {
+			state: Mutex::new(SlotsState {
+				max: max,
+				pending: 0,
+				blocked: Vec::new(),
+			}),
+		})
+	}
+
+	/// Returns the maximum number of upgrades.
+	#[inline]
+	pub(crate) fn max(&self) -> usize {
+		self.state.lock().max
+	}
+
+	/// Modifies the maximum number of upgrades, and wakes up the blocked listeners.
+	pub(crate) fn set_max(&self, max: usize) {
+		let mut state = self.state.lock();
+		state.max = max;
+		for task in state.blocked.drain(..) {
+			task.notify();
+		}
+	}
+
+	/// Returns the number of slots in use.
+	#[inline]
+	pub(crate) fn pending(&self) -> usize {
+		self.state.lock().pending
+	}
+
+	/// Returns true if all the slots are in use.
+	#[inline]
+	pub(crate) fn is_full(&self) -> bool {
+		let state = self.state.lock();
+		state.pending >= state.max
+	}
+}
+
+impl fmt::Debug for UpgradeSlots {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		let state = self.state.lock();
+		f.debug_struct("UpgradeSlots")
+			.field("max", &state.max)
+			.field("pending", &state.pending)
+			.finish()
+	}
+}
+
+struct SlotsState {
+	max: usize,
+	// Number of upgrades that are alive, plus the slots reserved by the listeners being polled.
+	pending: usize,
+	// Listeners to wake up when a slot becomes available.
+	blocked: Vec<Task>,
+}
+
+/// Slot of an upgrade in progress. Frees the slot and wakes up the listeners when dropped.
+#[derive(Debug)]
+pub(crate) struct Slot {
+	shared: Arc<UpgradeSlots>,
+}
+
+impl Slot {
+	// Reserves a slot, or registers the current task to be notified if there is none available.
+	fn reserve(shared: &Arc<UpgradeSlots>) -> Option<Slot> {
+		let mut state = shared.state.lock();
+		if state.pending >= state.max {
+			state.blocked.push(task::current());
+			return None;
+		}
+
+		state.pending += 1;
+		Some(Slot { shared: shared.clone() })
+	}
+
+	/// Takes a slot even if the limit is reached. Used by the swarm for the connections that
+	/// bypass the limit.
+	pub(crate) fn force(shared: &Arc<UpgradeSlots>) -> Slot {
+		shared.state.lock().pending += 1;
+		Slot { shared: shared.clone() }
+	}
+}
+
+impl Drop for Slot {
+	fn drop(&mut self) {
+		let mut state = self.shared.state.lock();
+		state.pending -= 1;
+		for task in state.blocked.drain(..) {
+			task.notify();
+		}
+	}
+}
+
+/// Listener of an `InboundLimit`. Stops accepting connections while the limit is reached.
+#[derive(Debug)]
+pub struct InboundLimitListener<L> {
+	inner: L,
+	shared: Arc<UpgradeSlots>,
+}
+
+impl<L, U> Stream for InboundLimitListener<L>
+	where L: Stream<Item = (U, Multiaddr), Error = IoError>
+{
+	type Item = (InboundLimitUpgrade<U>, Multiaddr);
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+		// The slot is reserved before accepting, so that the listeners of the same transport
+		// never exceed the limit together. It is freed right away if nothing was accepted.
+		let slot = match Slot::reserve(&self.shared) {
+			Some(slot) => slot,
+			None => return Ok(Async::NotReady),
+		};
+
+		match try_ready!(self.inner.poll()) {
+			Some((upgrade, addr)) => {
+				Ok(Async::Ready(Some((InboundLimitUpgrade::new(upgrade, slot), addr))))
+			}
+			None => Ok(Async::Ready(None)),
+		}
+	}
+}
+
+/// Upgrade of an incoming connection of an `InboundLimit`. Frees its slot once finished or
+/// dropped.
+#[derive(Debug)]
+pub struct InboundLimitUpgrade<U> {
+	inner: U,
+	// `None` once the upgrade is finished.
+	slot: Option<Slot>,
+}
+
+impl<U> InboundLimitUpgrade<U> {
+	/// Wraps around an upgrade that holds `slot` until it is finished.
+	#[inline]
+	pub(crate) fn new(inner: U, slot: Slot) -> InboundLimitUpgrade<U> {
+		InboundLimitUpgrade {
+			inner: inner,
+			slot: Some(slot),
+		}
+	}
+}
+
+impl<U> Future for InboundLimitUpgrade<U>
+	where U: Future
+{
+	type Item = U::Item;
+	type Error = U::Error;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let result = self.inner.poll();
+		if let Ok(Async::NotReady) = result {
+			return result;
+		}
+
+		self.slot = None;
+		result
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{InboundLimit, Slot, UpgradeSlots};
+	use futures::{future, stream, Async, Future, Stream};
+	use multiaddr::Multiaddr;
+	use std::io::{Cursor, Error as IoError};
+	use transport::Transport;
+
+	// Transport whose listeners produce three connections that never finish upgrading.
+	#[derive(Debug, Clone)]
+	struct StuckListener;
+
+	impl Transport for StuckListener {
+		type RawConn = Cursor<Vec<u8>>;
+		type Listener = Box<Stream<Item = (Self::ListenerUpgrade, Multiaddr), Error = IoError>>;
+		type ListenerUpgrade = future::Empty<Self::RawConn, IoError>;
+		type Dial = future::Empty<Self::RawConn, IoError>;
+
+		fn listen_on(self, addr: Multiaddr)
+					 -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)>
+		{
+			let remote = "/ip4/1.2.3.4/tcp/5".parse::<Multiaddr>().unwrap();
+			let incoming = (0 .. 3).map(move |_| (future::empty(), remote.clone()));
+			Ok((Box::new(stream::iter_ok(incoming)), addr))
+		}
+
+		fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+			Err((self, addr))
+		}
+
+		fn nat_traversal(&self, _: &Multiaddr, _: &Multiaddr) -> Option<Multiaddr> {
+			None
+		}
+	}
+
+	#[test]
+	fn defers_accept() {
+		let transport = InboundLimit::new(StuckListener, 2);
+		let (mut listener, _) = transport.clone()
+			.listen_on("/ip4/0.0.0.0/tcp/5".parse().unwrap())
+			.unwrap();
+
+		future::lazy(move || -> Result<(), ()> {
+			let first = match listener.poll() {
+				Ok(Async::Ready(Some((upgrade, _)))) => upgrade,
+				_ => panic!("expected an incoming connection"),
+			};
+			let _second = match listener.poll() {
+				Ok(Async::Ready(Some((upgrade, _)))) => upgrade,
+				_ => panic!("expected an incoming connection"),
+			};
+			assert_eq!(transport.pending_upgrades(), 2);
+			assert!(match listener.poll() { Ok(Async::NotReady) => true, _ => false });
+
+			drop(first);
+			assert_eq!(transport.pending_upgrades(), 1);
+			assert!(match listener.poll() { Ok(Async::Ready(Some(_))) => true, _ => false });
+			Ok(())
+		}).wait().unwrap();
+	}
+
+	#[test]
+	fn forced_slots_count_towards_the_limit() {
+		let slots = UpgradeSlots::new(1);
+		let first = Slot::force(&slots);
+		let second = Slot::force(&slots);
+		assert_eq!(slots.pending(), 2);
+		assert!(slots.is_full());
+
+		slots.set_max(3);
+		assert!(!slots.is_full());
+
+		drop(first);
+		drop(second);
+		assert_eq!(slots.pending(), 0);
+	}
+}
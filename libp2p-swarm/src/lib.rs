@@ -41,8 +41,44 @@
 //! to obtain a single object that supports the protocols of both objects at once. This can be done
 //! multiple times in a row in order to chain as many implementations as you want.
 //! 
-//! // TODO: right now only tcp-transport exists, we need to add an example for chaining
-//! //       multiple transports once that makes sense
+//! The order matters: when dialing or listening, the first transport is tried first, and the
+//! second transport is only used if the first one doesn't support the multiaddress. When listening,
+//! the incoming connections of whichever transport was chosen are yielded by a single stream.
+//!
+//! ```ignore
+//! extern crate libp2p_swarm;
+//! extern crate libp2p_tcp_transport;
+//! extern crate libp2p_websocket;
+//! extern crate tokio_core;
+//!
+//! use libp2p_swarm::Transport;
+//! use libp2p_tcp_transport::TcpConfig;
+//! use libp2p_websocket::WsConfig;
+//!
+//! # fn main() {
+//! let tokio_core = tokio_core::reactor::Core::new().unwrap();
+//! let tcp = TcpConfig::new(tokio_core.handle());
+//! // Addresses such as `/ip4/1.2.3.4/tcp/5` are handled by the TCP transport, while addresses such
+//! // as `/ip4/1.2.3.4/tcp/5/ws` are rejected by it and handled by the websockets transport.
+//! let transport = tcp.clone().or_transport(WsConfig::new(tcp));
+//! # }
+//! ```
+//! 
+//! The `BandwidthLogging` struct wraps around a transport and counts the bytes that go through its
+//! connections, globally and per connection. This is useful for displaying the upload and download
+//! speeds of the node without modifying the transports.
+//!
+//! The `InboundLimit` struct wraps around a transport and bounds the number of incoming connections
+//! that are upgraded at the same time. When the bound is reached, the listeners stop accepting
+//! connections until an upgrade finishes. The swarm always bounds the upgrades of its
+//! listeners with the same mechanism, see `SwarmController::set_max_listener_upgrades()`, so
+//! there is no need to wrap the transport of a swarm in an `InboundLimit`: doing so adds a second
+//! limit on top of the one of the swarm.
+//!
+//! The `boxed()` method of the `Transport` trait erases the type of a transport and returns a
+//! `Boxed` struct. All the transports that produce the same type of connection turn into the same
+//! `Boxed` type, which makes it possible to choose the transport stack at runtime (for example
+//! depending on a configuration file) and store it in a single field.
 //! 
 //! ## The `MuxedTransport` trait
 //! 
@@ -104,6 +140,22 @@
 //! 
 //! The concept of *muxing* consists in using a single stream as if it was multiple substreams.
 //! 
+//! A `StreamMuxer` accepts the substreams opened by the remote with `inbound()`, opens new ones
+//! with `outbound()`, and closes the whole connection with `close()`. Protocol upgrades are then
+//! applied on the substreams instead of the raw connection, for example by a `HandledNode`.
+//! 
+//! A single remote can open thousands of substreams on a connection. Wrapping the muxer upgrade
+//! in a `LimitedUpgrade` bounds the number of inbound and outbound substreams that are open at the
+//! same time according to the `SubstreamLimits`. The excess inbound substreams are reset.
+//! 
+//! In order to diagnose head-of-line blocking or to compare muxers, a `MuxerStatsUpgrade` records
+//! the open substreams of each connection, the bytes read and written on each of them, and the
+//! number of times their writes stalled.
+//! 
+//! Over a transport or with a remote that doesn't support multiplexing, a `SingleStreamMuxer`
+//! exposes the connection itself as the only substream, so that the code written against the
+//! `StreamMuxer` API still works.
+//! 
 //! If the output of the connection upgrade instead implements the `StreamMuxer` and `Clone`
 //! traits, then you can turn the `UpgradedNode` struct into a `ConnectionReuse` struct by calling
 //! `ConnectionReuse::from(upgraded_node)`.
@@ -164,6 +216,27 @@
 //! also implements the `ConnectionUpgrade` trait and will choose one of the protocols amongst the
 //! ones supported.
 //!
+//! When several versions of a protocol are supported, the `.with_preference()` method reorders the
+//! protocol names so that, for example, `/ipfs/id/2.0.0` is tried and advertised before
+//! `/ipfs/id/1.0.0`. The best version that both sides support is then chosen deterministically.
+//!
+//! The same methods build a stack of security protocols, for example Noise preferred and secio as
+//! a fallback: `noise.or_upgrade(secio).with_preference(vec!["/noise", "/secio/1.0.0"])`. Each
+//! connection negotiates one of them with multistream-select. Wrapping the stack with
+//! `.with_negotiated_name()` gives a `Negotiated` output whose `protocol_name()` can be recorded
+//! in the connection metadata with `ConnectionInfo::set_security_protocol()`, or the stack can be
+//! wrapped with `UpgradeRecorder::record_upgrade()` so that the swarm records it by itself.
+//!
+//! When two nodes dial each other at the same time, for example during a hole punching attempt,
+//! both sides should use `UpgradedNode::dial_simultaneous()`. If the transport merges both attempts
+//! into a single connection, as a TCP simultaneous open does, the roles are decided during the
+//! protocol negotiation.
+//!
+//! The futures returned by an `UpgradedNode` (`UpgradedNodeDial`, `UpgradedNodeListener` and
+//! `UpgradedNodeIncoming`) don't box anything. They implement `Send` as long as the transport, the
+//! upgrade and their own futures do, which makes it possible to drive them from a multithreaded
+//! executor such as the `tokio` thread pool.
+//!
 //! # Swarm
 //!
 //! Once you have created an object that implements the `Transport` trait, you can put it in a
@@ -201,27 +274,212 @@
 //! core.run(swarm_future).unwrap();
 //! # }
 //! ```
+//!
+//! The `SwarmController` also provides an `events()` method that returns a `Stream` of
+//! `SwarmEvent`s, which can be used to observe what happens in the swarm (new connections, dial
+//! failures, listeners closing, etc.) without having to instrument every future yourself.
+//! Errors that happen on an individual connection are reported through this stream instead of
+//! interrupting the whole swarm.
+//!
+//! The `SwarmController` and the `SwarmFuture` are `Send` as long as the transport, the upgrade,
+//! the handler and the futures they produce are `Send`. This makes it possible to spawn the
+//! `SwarmFuture` on a multithreaded executor, or to pass the `SwarmController` to another thread.
+//! The uTP transport is the exception, as it holds a `Handle` to the event loop.
+//!
+//! The `NewListenAddr` and `ExpiredListenAddr` events tell exactly which addresses the swarm is
+//! reachable on. When a listening socket dies, for example because its network interface went
+//! down, a `ListenerError` is produced, followed with an `ExpiredListenAddr` for each of its
+//! addresses and a `ListenerClosed`.
+//!
+//! When listening on a wildcard address such as `/ip4/0.0.0.0/tcp/0`, the transport expands it
+//! into the concrete address of each network interface with the port that was bound, through
+//! `Transport::expand_listen_addr()`. These are the addresses reported by `NewListenAddr` and
+//! returned by `listen_addrs()`, so that they can be advertised to other nodes. Since interfaces
+//! can appear or disappear, call `refresh_listen_addrs()` periodically to receive the changes.
+//!
+//! By default all the listeners upgrade their incoming connections with the upgrade that was
+//! passed to `swarm()`. Call `listen_on_with_upgrade()` instead of `listen_on()` to use a
+//! different upgrade for a specific listener, for example in order to offer fewer protocols on a
+//! WebSocket listener meant for browsers. The output of that upgrade must be convertible into the
+//! output of the main upgrade, so that it can be passed to the same handler.
+//!
+//! Listeners can be added and removed while the swarm is running. `listen_on()` returns a
+//! `ListenerId` alongside the address, and passing it to `remove_listener()` closes the
+//! listening socket without affecting the connections it has accepted. This lets long-running
+//! nodes react to configuration changes without restarting the swarm.
+//!
+//! Errors are reported as `SwarmError`s, which distinguish the dials refused by the swarm
+//! (`DialError`), the multiaddresses not supported by the transport, the protocol negotiation and
+//! handshake failures (`UpgradeError`), and the plain I/O errors. Transports and upgrades still
+//! produce `IoError`s, and the `From` conversions between these types recover the typed errors
+//! stored inside them.
+//!
+//! Wrapping an upgrade with `with_error_context()` turns the errors of its handshake into
+//! `ContextError`s, which indicate the layer of the stack (`ErrorLayer`), the negotiated protocol,
+//! the endpoint and the remote address. The `Display` implementations include the causes, so that
+//! a single log line tells where a failure happened, and `SwarmError::context()` returns the
+//! context of an error reported by the swarm.
+//!
+//! Protocols that open several substreams on the same connection, such as ping or identify, can
+//! implement the `ProtocolsHandler` trait instead of handling the raw substreams themselves. The
+//! handler chooses the upgrade to apply on the substreams opened by the remote, requests outbound
+//! substreams through `ProtocolsHandlerEvent::OutboundSubstreamRequest`, and receives the upgraded
+//! substreams. A `HandledNode` drives a handler on top of a `StreamMuxer`, and produces the events
+//! of the handler as a `Stream`.
+//!
+//! Calling `shutdown()` on the `SwarmController` stops accepting new connections, closes all the
+//! existing ones, and makes the swarm future finish. `shutdown_graceful()` instead lets the
+//! connections close themselves until a deadline: the futures returned by `closing()` resolve,
+//! and the `HandledNode`s built with `shutdown_on(controller.closing())` shut their substreams
+//! down and close their muxer.
+//!
+//! The swarm refuses to dial its own listen addresses, the addresses passed to
+//! `add_external_addr()`, and the multiaddresses that contain the identity passed to
+//! `set_local_peer_id()`. Such attempts produce a `DialError::SelfDial` instead of a connection
+//! to ourselves.
+//!
+//! The identity of a node is a `PeerId`, the multihash of its public key. It is displayed and
+//! parsed in base58, like in the `/p2p` components of the multiaddresses printed by the other
+//! implementations of libp2p. The `ConnectionInfo`s, the `ContextError`s and the
+//! `ConnectionGater` all use this type.
+//!
+//! At most `DEFAULT_MAX_LISTENER_UPGRADES` incoming connections are upgraded at the same time,
+//! which can be changed with `set_max_listener_upgrades()`. When the limit is reached, new
+//! connections wait in the backlog of the listeners until an upgrade finishes. With
+//! `set_shed_under_pressure()`, the connections are instead rejected immediately while the limit is
+//! reached, except for the ones that the `ConnectionGater` marks as protected, so that the
+//! important peers can still connect during a flood of connections.
+//!
+//! The remotes that request a protocol we don't support are answered with the `na` message of
+//! multistream-select, and the requests are counted per protocol by `unsupported_protocols()`,
+//! which shows operators what the other nodes expect of them. The remotes can find out what we
+//! support from the `protocols` that we report through identify, as returned by
+//! `supported_protocols()`. With
+//! `unsupported_protocols().set_response(UnsupportedResponse::NotAvailableWithHint)`, the `na` is
+//! followed with the list of the protocols we support, for the remotes that understand it.
+//!
+//! The `connections()` method of the `SwarmController` returns a `ConnectionInfo` for each open
+//! connection: the addresses, whether we dialed or were dialed, and when the connection has been
+//! established. The swarm also fills the identity of the remote and the negotiated security and
+//! muxer protocols once the upgrades of the transport have been wrapped with
+//! `UpgradeRecorder::record_upgrade()` and the recorder has been passed to
+//! `set_upgrade_recorder()`. Otherwise they can be recorded with `update_connection()`, using the
+//! identifier found in the `SwarmEvent::ConnectionEstablished` event. The substreams that remotes
+//! open on existing connections are reported with `SwarmEvent::IncomingSubstream` instead, and
+//! aren't connections of their own.
+//!
+//! Once the local identity has been set with `set_local_peer_id()`, recording the identity of a
+//! remote with `update_connection()` keeps a single connection to it. If two nodes dial each other
+//! at the same time, both of them close the same one of the two connections, as decided by
+//! `PeerConnections`, and `peer_connection()` returns the connection on which the substreams to
+//! this remote should be opened.
+//!
+//! A `PartitionDetector` can be fed with the swarm events and with the results of network lookups.
+//! It reports a `PartitionEvent` when the failed dials, the failed lookups or the loss of all the
+//! bootstrap nodes suggest that the local node has been cut off from the rest of the network.
+//! `Kademlia::with_partition_detector` of `libp2p-kad` feeds a detector with the results of its
+//! queries.
+//!
+//! The `BanList` returned by `ban_list()` bans multiaddress prefixes, IP ranges in CIDR notation
+//! with `IpRange`, and peers with `ban_peer()`, each for a duration. Banned addresses can't be
+//! dialed and their connections are dropped. A banned peer is disconnected as soon as its identity
+//! is recorded with `update_connection()`, and its existing connections are closed when the ban is
+//! added.
+//!
+//! A `ConnectionGater` set with `set_connection_gater()` is consulted before dialing, when a
+//! listener accepts a connection, and, if the upgrade is wrapped with `gate_upgrade()`, once the
+//! identity of the remote is known. This makes it possible to block private ranges or to enforce
+//! an allow-list of peers.
+//!
+//! By default the swarm future polls all the connections itself, which means that a single task
+//! is woken up whenever any of them makes progress. Calling `set_executor()` with an `Executor`,
+//! for example a `ThreadPoolExecutor` or a closure that spawns on a tokio-core `Remote`, makes
+//! the swarm spawn each connection as its own task instead. The executor has to be `Send` and
+//! `Sync`, and so do the futures it is given, which keeps the swarm itself `Send`. The uTP and DNS
+//! transports accept an `Executor` as well for their background tasks. The connections spawned
+//! this way are still closed when their address is banned, when the swarm shuts down, or when
+//! the swarm future is destroyed.
 
+extern crate base58;
 extern crate bytes;
 #[macro_use]
 extern crate futures;
+extern crate futures_cpupool;
+extern crate multihash;
 extern crate multistream_select;
 extern crate parking_lot;
 extern crate smallvec;
 extern crate tokio_io;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+extern crate tokio_core;
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+#[macro_use]
+extern crate stdweb;
 
 /// Multi-address re-export.
 pub extern crate multiaddr;
 
+mod ban_list;
+mod bandwidth;
+mod boxed;
+mod clock_skew;
+mod connection_info;
 mod connection_reuse;
+mod dial_any;
+mod error;
+mod executor;
+mod fair_scheduler;
+mod gater;
+mod inbound_limit;
+mod muxer_stats;
+mod partition;
+mod peer_connections;
+mod peer_id;
+mod protocols_handler;
+mod single_stream;
+mod sniff_guard;
+mod substream_limit;
 pub mod swarm;
 pub mod muxing;
+pub mod time;
 pub mod transport;
 
+pub use self::ban_list::{BanList, IpRange};
+pub use self::bandwidth::{BandwidthFuture, BandwidthListener, BandwidthLogging};
+pub use self::bandwidth::{BandwidthSinks, BandwidthSnapshot, ConnectionCounters, Metered};
+pub use self::boxed::{Boxed, BoxedDial, BoxedListener, BoxedListenerUpgrade};
+pub use self::clock_skew::ClockSkew;
+pub use self::connection_info::{ConnectionId, ConnectionInfo, RecordingUpgrade};
+pub use self::connection_info::{RecordingUpgradeFuture, UpgradeRecorder};
 pub use self::connection_reuse::ConnectionReuse;
+pub use self::dial_any::{dial_any, DialAny};
+pub use self::error::{ContextError, DialError, ErrorLayer, SwarmError, TransportError};
+pub use self::error::UpgradeError;
+pub use self::executor::{Executor, ThreadPoolExecutor};
+pub use self::fair_scheduler::FairScheduler;
+pub use self::gater::{AllowAllGater, ConnectionGater, GatedUpgrade, GatedUpgradeFuture};
+pub use self::inbound_limit::{InboundLimit, InboundLimitListener, InboundLimitUpgrade};
 pub use self::multiaddr::Multiaddr;
+pub use self::muxer_stats::{ConnectionMuxerStats, MuxerStats, MuxerStatsFuture, MuxerStatsUpgrade};
+pub use self::muxer_stats::{StatsMuxer, StatsSubstream, StatsSubstreamFuture, SubstreamStats};
 pub use self::muxing::StreamMuxer;
-pub use self::swarm::{swarm, SwarmController, SwarmFuture};
+pub use self::partition::{PartitionConfig, PartitionDetector, PartitionEvent, PartitionEvidence};
+pub use self::peer_connections::PeerConnections;
+pub use self::peer_id::{ParsePeerIdError, PeerId};
+pub use self::protocols_handler::{HandledNode, NodeHandlerEndpoint, ProtocolsHandler};
+pub use self::protocols_handler::ProtocolsHandlerEvent;
+pub use self::single_stream::SingleStreamMuxer;
+pub use self::sniff_guard::{ProtocolMismatch, SniffGuard, SniffedSocket};
+pub use self::substream_limit::{LimitedInbound, LimitedMuxer, LimitedOutbound, LimitedSubstream};
+pub use self::substream_limit::{LimitedUpgrade, LimitedUpgradeFuture, SubstreamLimits};
+pub use self::swarm::{swarm, SwarmController, SwarmEvent, SwarmEvents, SwarmFuture};
+pub use self::swarm::{ListenerId, SwarmClosing, SwarmShutdown, DEFAULT_MAX_LISTENER_UPGRADES};
 pub use self::transport::{ConnectionUpgrade, PlainTextConfig, Transport, UpgradedNode, OrUpgrade};
 pub use self::transport::{Endpoint, SimpleProtocol, MuxedTransport, UpgradeExt};
-pub use self::transport::{DeniedConnectionUpgrade};
+pub use self::transport::{DeniedConnectionUpgrade, PreferenceOrder, UnsupportedProtocols};
+pub use self::transport::{ErrorContext, ErrorContextFuture, Negotiated, NegotiatedName};
+pub use self::transport::NegotiatedNameFuture;
+pub use self::transport::{UpgradedNodeDial, UpgradedNodeIncoming, UpgradedNodeListener};
+pub use self::transport::{UpgradedNodeListenerUpgrade, MAX_UNSUPPORTED_PROTOCOLS};
+pub use self::transport::UpgradedNodeSimultaneousDial;
+pub use multistream_select::UnsupportedResponse;
@@ -0,0 +1,481 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `MuxerStatsUpgrade`, which collects statistics about the substreams of the
+//! multiplexed connections.
+//!
+//! For each connection, the statistics contain the substreams that are open, the number of bytes
+//! read and written on each of them, and the number of write stalls. A write stall happens when a
+//! substream can't accept more data, for example because the yamux window of the remote is
+//! exhausted or because another substream of an mplex connection is being written. A high number
+//! of stalls is a sign of head-of-line blocking.
+//!
+//! The statistics are kept in a `MuxerStats` shared with the user, and can be read from any
+//! thread.
+
+use futures::{Async, Future, Poll};
+use multiaddr::Multiaddr;
+use muxing::StreamMuxer;
+use parking_lot::Mutex;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio_io::{AsyncRead, AsyncWrite};
+use transport::{ConnectionUpgrade, Endpoint};
+
+/// Wraps around a `ConnectionUpgrade` whose output is a `StreamMuxer`, and collects statistics
+/// about the substreams of each connection.
+#[derive(Debug, Clone)]
+pub struct MuxerStatsUpgrade<U> {
+	inner: U,
+	stats: Arc<MuxerStats>,
+}
+
+impl<U> MuxerStatsUpgrade<U> {
+	/// Wraps around `inner`. Returns the statistics alongside with the upgrade.
+	#[inline]
+	pub fn new(inner: U) -> (MuxerStatsUpgrade<U>, Arc<MuxerStats>) {
+		let stats = Arc::new(MuxerStats {
+			connections: Mutex::new(Vec::new()),
+		});
+
+		let upgrade = MuxerStatsUpgrade {
+			inner: inner,
+			stats: stats.clone(),
+		};
+		(upgrade, stats)
+	}
+}
+
+impl<C, U> ConnectionUpgrade<C> for MuxerStatsUpgrade<U>
+	where C: AsyncRead + AsyncWrite,
+		  U: ConnectionUpgrade<C>,
+		  U::Output: StreamMuxer,
+{
+	type NamesIter = U::NamesIter;
+	type UpgradeIdentifier = U::UpgradeIdentifier;
+	type Output = StatsMuxer<U::Output>;
+	type Future = MuxerStatsFuture<U::Future>;
+
+	#[inline]
+	fn protocol_names(&self) -> Self::NamesIter {
+		self.inner.protocol_names()
+	}
+
+	#[inline]
+	fn upgrade(self, socket: C, id: Self::UpgradeIdentifier, ty: Endpoint,
+			   remote_addr: &Multiaddr) -> Self::Future
+	{
+		MuxerStatsFuture {
+			inner: self.inner.upgrade(socket, id, ty, remote_addr),
+			stats: self.stats,
+			remote_addr: Some(remote_addr.clone()),
+		}
+	}
+}
+
+/// Statistics of the connections of a `MuxerStatsUpgrade`.
+#[derive(Debug)]
+pub struct MuxerStats {
+	// Statistics of the connections that are still alive.
+	connections: Mutex<Vec<Weak<ConnectionMuxerStats>>>,
+}
+
+impl MuxerStats {
+	/// Returns the statistics of the connections that are still alive.
+	pub fn connections(&self) -> Vec<Arc<ConnectionMuxerStats>> {
+		let mut connections = self.connections.lock();
+		connections.retain(|stats| stats.upgrade().is_some());
+		connections.iter().filter_map(|stats| stats.upgrade()).collect()
+	}
+
+	// Registers a new connection.
+	fn register(&self, remote_addr: Multiaddr) -> Arc<ConnectionMuxerStats> {
+		let stats = Arc::new(ConnectionMuxerStats {
+			remote_addr: remote_addr,
+			substreams: Mutex::new(Vec::new()),
+			write_stalls: AtomicUsize::new(0),
+		});
+
+		let mut connections = self.connections.lock();
+		connections.retain(|stats| stats.upgrade().is_some());
+		connections.push(Arc::downgrade(&stats));
+		stats
+	}
+}
+
+/// Statistics of a single multiplexed connection.
+#[derive(Debug)]
+pub struct ConnectionMuxerStats {
+	remote_addr: Multiaddr,
+	// Statistics of the substreams that are still open.
+	substreams: Mutex<Vec<Weak<SubstreamStats>>>,
+	write_stalls: AtomicUsize,
+}
+
+impl ConnectionMuxerStats {
+	/// Returns the address of the remote.
+	#[inline]
+	pub fn remote_addr(&self) -> &Multiaddr {
+		&self.remote_addr
+	}
+
+	/// Returns the statistics of the substreams that are still open.
+	pub fn substreams(&self) -> Vec<Arc<SubstreamStats>> {
+		let mut substreams = self.substreams.lock();
+		substreams.retain(|stats| stats.upgrade().is_some());
+		substreams.iter().filter_map(|stats| stats.upgrade()).collect()
+	}
+
+	/// Returns the number of substreams that are open.
+	#[inline]
+	pub fn num_substreams(&self) -> usize {
+		self.substreams().len()
+	}
+
+	/// Returns the number of write stalls of all the substreams of the connection, including the
+	/// ones that have been closed.
+	#[inline]
+	pub fn write_stalls(&self) -> usize {
+		self.write_stalls.load(Ordering::Relaxed)
+	}
+
+	// Registers a new substream.
+	fn register(&self, endpoint: Endpoint) -> Arc<SubstreamStats> {
+		let stats = Arc::new(SubstreamStats {
+			endpoint: endpoint,
+			bytes_read: AtomicUsize::new(0),
+			bytes_written: AtomicUsize::new(0),
+			write_stalls: AtomicUsize::new(0),
+		});
+
+		let mut substreams = self.substreams.lock();
+		substreams.retain(|stats| stats.upgrade().is_some());
+		substreams.push(Arc::downgrade(&stats));
+		stats
+	}
+}
+
+/// Statistics of a single substream.
+#[derive(Debug)]
+pub struct SubstreamStats {
+	endpoint: Endpoint,
+	bytes_read: AtomicUsize,
+	bytes_written: AtomicUsize,
+	write_stalls: AtomicUsize,
+}
+
+impl SubstreamStats {
+	/// Returns `Dialer` if we opened the substream, and `Listener` if the remote did.
+	#[inline]
+	pub fn endpoint(&self) -> Endpoint {
+		self.endpoint
+	}
+
+	/// Returns the number of bytes read on the substream.
+	#[inline]
+	pub fn bytes_read(&self) -> usize {
+		self.bytes_read.load(Ordering::Relaxed)
+	}
+
+	/// Returns the number of bytes written on the substream.
+	#[inline]
+	pub fn bytes_written(&self) -> usize {
+		self.bytes_written.load(Ordering::Relaxed)
+	}
+
+	/// Returns the number of times the substream couldn't accept the data that was written.
+	#[inline]
+	pub fn write_stalls(&self) -> usize {
+		self.write_stalls.load(Ordering::Relaxed)
+	}
+}
+
+/// Future produced by a `MuxerStatsUpgrade`.
+#[must_use = "futures do nothing unless polled"]
+pub struct MuxerStatsFuture<F> {
+	inner: F,
+	stats: Arc<MuxerStats>,
+	// Taken when the muxer is produced.
+	remote_addr: Option<Multiaddr>,
+}
+
+impl<F> Future for MuxerStatsFuture<F>
+	where F: Future<Error = IoError>
+{
+	type Item = StatsMuxer<F::Item>;
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let muxer = try_ready!(self.inner.poll());
+		let remote_addr = self.remote_addr.take().expect("future polled after it finished");
+		Ok(Async::Ready(StatsMuxer {
+			inner: muxer,
+			stats: self.stats.register(remote_addr),
+		}))
+	}
+}
+
+/// Muxer produced by a `MuxerStatsUpgrade`. Registers the statistics of each substream.
+pub struct StatsMuxer<M> {
+	inner: M,
+	stats: Arc<ConnectionMuxerStats>,
+}
+
+impl<M> StatsMuxer<M> {
+	/// Returns the statistics of this connection.
+	#[inline]
+	pub fn stats(&self) -> &Arc<ConnectionMuxerStats> {
+		&self.stats
+	}
+}
+
+impl<M> Clone for StatsMuxer<M>
+	where M: Clone
+{
+	#[inline]
+	fn clone(&self) -> Self {
+		StatsMuxer {
+			inner: self.inner.clone(),
+			stats: self.stats.clone(),
+		}
+	}
+}
+
+impl<M> StreamMuxer for StatsMuxer<M>
+	where M: StreamMuxer
+{
+	type Substream = StatsSubstream<M::Substream>;
+	type InboundSubstream = StatsSubstreamFuture<M::InboundSubstream>;
+	type OutboundSubstream = StatsSubstreamFuture<M::OutboundSubstream>;
+
+	#[inline]
+	fn inbound(self) -> Self::InboundSubstream {
+		StatsSubstreamFuture {
+			inner: self.inner.inbound(),
+			connection: self.stats,
+			endpoint: Endpoint::Listener,
+		}
+	}
+
+	#[inline]
+	fn outbound(self) -> Self::OutboundSubstream {
+		StatsSubstreamFuture {
+			inner: self.inner.outbound(),
+			connection: self.stats,
+			endpoint: Endpoint::Dialer,
+		}
+	}
+
+	#[inline]
+	fn close(&self) -> Poll<(), IoError> {
+		self.inner.close()
+	}
+}
+
+/// Future that produces a substream of a `StatsMuxer`.
+#[must_use = "futures do nothing unless polled"]
+pub struct StatsSubstreamFuture<F> {
+	inner: F,
+	connection: Arc<ConnectionMuxerStats>,
+	endpoint: Endpoint,
+}
+
+impl<F> Future for StatsSubstreamFuture<F>
+	where F: Future<Error = IoError>
+{
+	type Item = StatsSubstream<F::Item>;
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let substream = try_ready!(self.inner.poll());
+		Ok(Async::Ready(StatsSubstream {
+			inner: substream,
+			stats: self.connection.register(self.endpoint),
+			connection: self.connection.clone(),
+		}))
+	}
+}
+
+/// Substream of a `StatsMuxer`. Counts the bytes that are read and written, and the write stalls.
+pub struct StatsSubstream<S> {
+	inner: S,
+	stats: Arc<SubstreamStats>,
+	connection: Arc<ConnectionMuxerStats>,
+}
+
+impl<S> StatsSubstream<S> {
+	/// Returns the statistics of this substream.
+	#[inline]
+	pub fn stats(&self) -> &Arc<SubstreamStats> {
+		&self.stats
+	}
+}
+
+impl<S> Read for StatsSubstream<S>
+	where S: Read
+{
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+		let num = self.inner.read(buf)?;
+		self.stats.bytes_read.fetch_add(num, Ordering::Relaxed);
+		Ok(num)
+	}
+}
+
+impl<S> AsyncRead for StatsSubstream<S>
+	where S: AsyncRead
+{
+	#[inline]
+	unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+		self.inner.prepare_uninitialized_buffer(buf)
+	}
+}
+
+impl<S> Write for StatsSubstream<S>
+	where S: Write
+{
+	fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+		match self.inner.write(buf) {
+			Ok(num) => {
+				self.stats.bytes_written.fetch_add(num, Ordering::Relaxed);
+				Ok(num)
+			},
+			Err(err) => {
+				if err.kind() == IoErrorKind::WouldBlock {
+					self.stats.write_stalls.fetch_add(1, Ordering::Relaxed);
+					self.connection.write_stalls.fetch_add(1, Ordering::Relaxed);
+				}
+				Err(err)
+			},
+		}
+	}
+
+	#[inline]
+	fn flush(&mut self) -> Result<(), IoError> {
+		self.inner.flush()
+	}
+}
+
+impl<S> AsyncWrite for StatsSubstream<S>
+	where S: AsyncWrite
+{
+	#[inline]
+	fn shutdown(&mut self) -> Poll<(), IoError> {
+		self.inner.shutdown()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::MuxerStatsUpgrade;
+	use futures::{future, Async, Future, Poll};
+	use multiaddr::Multiaddr;
+	use muxing::StreamMuxer;
+	use std::io::{Cursor, Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+	use tokio_io::{AsyncRead, AsyncWrite};
+	use transport::{ConnectionUpgrade, Endpoint, SimpleProtocol};
+
+	// Substream that contains `data`, and whose writes stall if `stall` is true.
+	struct DummySubstream {
+		data: Cursor<Vec<u8>>,
+		stall: bool,
+	}
+
+	impl Read for DummySubstream {
+		fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+			self.data.read(buf)
+		}
+	}
+
+	impl AsyncRead for DummySubstream {}
+
+	impl Write for DummySubstream {
+		fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+			if self.stall {
+				return Err(IoErrorKind::WouldBlock.into());
+			}
+			self.data.write(buf)
+		}
+
+		fn flush(&mut self) -> Result<(), IoError> {
+			Ok(())
+		}
+	}
+
+	impl AsyncWrite for DummySubstream {
+		fn shutdown(&mut self) -> Poll<(), IoError> {
+			Ok(Async::Ready(()))
+		}
+	}
+
+	// The remote opens substreams that contain `hello`, and our substreams always stall.
+	#[derive(Clone)]
+	struct DummyMuxer;
+
+	impl StreamMuxer for DummyMuxer {
+		type Substream = DummySubstream;
+		type InboundSubstream = future::FutureResult<DummySubstream, IoError>;
+		type OutboundSubstream = future::FutureResult<DummySubstream, IoError>;
+
+		fn inbound(self) -> Self::InboundSubstream {
+			future::ok(DummySubstream { data: Cursor::new(b"hello".to_vec()), stall: false })
+		}
+
+		fn outbound(self) -> Self::OutboundSubstream {
+			future::ok(DummySubstream { data: Cursor::new(Vec::new()), stall: true })
+		}
+	}
+
+	#[test]
+	fn substream_stats() {
+		let (upgrade, stats) = MuxerStatsUpgrade::new(SimpleProtocol::new("/dummy",
+			|_: Cursor<Vec<u8>>| Ok::<_, IoError>(DummyMuxer)));
+		let addr = "/ip4/1.2.3.4/tcp/5".parse::<Multiaddr>().unwrap();
+		let muxer = upgrade.upgrade(Cursor::new(Vec::new()), (), Endpoint::Dialer, &addr)
+			.wait()
+			.unwrap();
+
+		let mut inbound = muxer.clone().inbound().wait().unwrap();
+		let mut buf = [0; 5];
+		inbound.read_exact(&mut buf).unwrap();
+		assert_eq!(inbound.stats().bytes_read(), 5);
+		assert_eq!(inbound.stats().endpoint(), Endpoint::Listener);
+
+		let mut outbound = muxer.clone().outbound().wait().unwrap();
+		assert!(outbound.write(b"hello").is_err());
+		assert!(outbound.write(b"hello").is_err());
+		assert_eq!(outbound.stats().write_stalls(), 2);
+		assert_eq!(outbound.stats().bytes_written(), 0);
+
+		let connections = stats.connections();
+		assert_eq!(connections.len(), 1);
+		assert_eq!(connections[0].remote_addr(), &addr);
+		assert_eq!(connections[0].num_substreams(), 2);
+
+		drop(outbound);
+		assert_eq!(connections[0].num_substreams(), 1);
+		assert_eq!(connections[0].write_stalls(), 2);
+
+		drop(inbound);
+		drop(muxer);
+		drop(connections);
+		assert!(stats.connections().is_empty());
+	}
+}
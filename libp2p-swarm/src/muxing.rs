@@ -18,6 +18,7 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+use futures::{Async, Poll};
 use futures::future::Future;
 use std::io::Error as IoError;
 use tokio_io::{AsyncRead, AsyncWrite};
@@ -28,6 +29,11 @@ use tokio_io::{AsyncRead, AsyncWrite};
 /// >           then you can clone it and keep the original in order to open additional substreams.
 pub trait StreamMuxer {
 	/// Type of the object that represents the raw substream where data can be read and written.
+	///
+	/// Calling `shutdown()` on a substream should only close its writing side, so that a
+	/// request/response protocol can signal the end of a request and still read the response.
+	/// Dropping a substream that hasn't been closed by both sides should reset it, so that the
+	/// remote gets an error instead of waiting for data that will never come.
 	type Substream: AsyncRead + AsyncWrite;
 	/// Future that will be resolved when a new incoming substream is open.
 	type InboundSubstream: Future<Item = Self::Substream, Error = IoError>;
@@ -40,4 +46,14 @@ pub trait StreamMuxer {
 	/// Opens a new outgoing substream, and produces a future that will be resolved when it becomes
 	/// available.
 	fn outbound(self) -> Self::OutboundSubstream;
+
+	/// Closes the whole connection, including all of its substreams. Returns `Ready` once the
+	/// connection has been closed.
+	///
+	/// The default implementation doesn't do anything, in which case the connection is only
+	/// closed once the muxer and all of its substreams have been dropped.
+	#[inline]
+	fn close(&self) -> Poll<(), IoError> {
+		Ok(Async::Ready(()))
+	}
 }
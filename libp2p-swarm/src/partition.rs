@@ -0,0 +1,318 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `PartitionDetector` struct, which guesses whether the local node has been cut off
+//! from the rest of the network.
+//!
+//! A node that is isolated (eg. behind a broken NAT, or on a network segment that lost its
+//! uplink) usually keeps running without noticing anything. Some activities are unsafe in that
+//! situation, for example accepting the head of a chain that only our isolated neighbours know.
+//! The detector looks at several signals and reports when they all point towards a partition:
+//!
+//! - The fraction of our recent dials that failed.
+//! - The fraction of our recent lookups that failed. The swarm doesn't perform lookups by
+//!   itself. `Kademlia::with_partition_detector` of `libp2p-kad` injects the results of its
+//!   queries, otherwise they must be injected by the user.
+//! - Whether we lost the connections to all our bootstrap nodes after having been connected to
+//!   at least one of them.
+//!
+//! The detector doesn't do anything by itself. Feed it with the events of
+//! `SwarmController::events()` and with the lookup results, and act on the `PartitionEvent`s it
+//! returns. When it is owned by `Kademlia`, the swarm events are injected through
+//! `Kademlia::partition_detector_mut()`.
+
+use connection_info::ConnectionId;
+use multiaddr::Multiaddr;
+use std::collections::{HashSet, VecDeque};
+use swarm::SwarmEvent;
+use transport::Endpoint;
+
+/// Configuration of a `PartitionDetector`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionConfig {
+	/// Number of recent dials and lookups that are taken into account.
+	pub window: usize,
+	/// Minimum number of dials or lookups in the window before their failure ratio is trusted.
+	pub min_samples: usize,
+	/// Fraction of failed dials above which a partition is suspected.
+	pub dial_failure_threshold: f64,
+	/// Fraction of failed lookups above which a partition is suspected.
+	pub lookup_failure_threshold: f64,
+}
+
+impl Default for PartitionConfig {
+	#[inline]
+	fn default() -> PartitionConfig {
+		PartitionConfig {
+			window: 32,
+			min_samples: 8,
+			dial_failure_threshold: 0.9,
+			lookup_failure_threshold: 0.9,
+		}
+	}
+}
+
+/// Event produced by a `PartitionDetector` when its opinion changes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PartitionEvent {
+	/// The node is probably isolated from the rest of the network.
+	Suspected(PartitionEvidence),
+	/// The signals don't indicate a partition anymore.
+	Recovered(PartitionEvidence),
+}
+
+/// State of the signals watched by a `PartitionDetector`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionEvidence {
+	/// Fraction of the recent dials that failed, or `None` if there weren't enough of them.
+	pub dial_failure_ratio: Option<f64>,
+	/// Fraction of the recent lookups that failed, or `None` if there weren't enough of them.
+	pub lookup_failure_ratio: Option<f64>,
+	/// Number of bootstrap nodes we are connected to.
+	pub bootstrap_connected: usize,
+	/// Number of bootstrap nodes that have been registered.
+	pub bootstrap_total: usize,
+	/// True if we were connected to a bootstrap node at some point and are not anymore.
+	pub bootstrap_lost: bool,
+}
+
+impl PartitionEvidence {
+	// Returns true if at least one of the signals points towards a partition.
+	fn is_partitioned(&self, config: &PartitionConfig) -> bool {
+		self.dial_failure_ratio.map(|r| r >= config.dial_failure_threshold).unwrap_or(false) ||
+			self.lookup_failure_ratio.map(|r| r >= config.lookup_failure_threshold)
+				.unwrap_or(false) ||
+			self.bootstrap_lost
+	}
+}
+
+/// Watches connectivity signals and reports suspected network partitions.
+pub struct PartitionDetector {
+	config: PartitionConfig,
+	// Outcome of the recent dials, `true` meaning success.
+	dials: VecDeque<bool>,
+	// Outcome of the recent lookups, `true` meaning success.
+	lookups: VecDeque<bool>,
+	bootstrap: Vec<Multiaddr>,
+	// Connections to one of the bootstrap nodes that are currently open.
+	bootstrap_connections: HashSet<(ConnectionId, usize)>,
+	// True if we have been connected to a bootstrap node at least once.
+	bootstrap_reached: bool,
+	suspected: bool,
+}
+
+impl PartitionDetector {
+	/// Builds a new `PartitionDetector`.
+	///
+	/// # Panic
+	///
+	/// Panics if `config.window` is 0 or is lower than `config.min_samples`.
+	pub fn new(config: PartitionConfig) -> PartitionDetector {
+		assert_ne!(config.window, 0, "the window of a partition detector must not be zero");
+		assert!(config.min_samples <= config.window, "min_samples must not exceed the window");
+
+		PartitionDetector {
+			dials: VecDeque::with_capacity(config.window),
+			lookups: VecDeque::with_capacity(config.window),
+			config: config,
+			bootstrap: Vec::new(),
+			bootstrap_connections: HashSet::new(),
+			bootstrap_reached: false,
+			suspected: false,
+		}
+	}
+
+	/// Registers the address of a bootstrap node. Connections established with exactly this
+	/// address count as connections to the bootstrap node.
+	pub fn add_bootstrap(&mut self, addr: Multiaddr) {
+		if !self.bootstrap.contains(&addr) {
+			self.bootstrap.push(addr);
+		}
+	}
+
+	/// Returns true if a partition is currently suspected.
+	#[inline]
+	pub fn is_suspected(&self) -> bool {
+		self.suspected
+	}
+
+	/// Returns the current state of the signals.
+	pub fn evidence(&self) -> PartitionEvidence {
+		let connected = {
+			let mut connected = self.bootstrap_connections.iter()
+				.map(|&(_, n)| n)
+				.collect::<Vec<_>>();
+			connected.sort();
+			connected.dedup();
+			connected.len()
+		};
+
+		PartitionEvidence {
+			dial_failure_ratio: failure_ratio(&self.dials, self.config.min_samples),
+			lookup_failure_ratio: failure_ratio(&self.lookups, self.config.min_samples),
+			bootstrap_connected: connected,
+			bootstrap_total: self.bootstrap.len(),
+			bootstrap_lost: self.bootstrap_reached && connected == 0,
+		}
+	}
+
+	/// Processes an event of the swarm. Returns an event if the opinion of the detector changed.
+	pub fn inject_swarm_event(&mut self, event: &SwarmEvent) -> Option<PartitionEvent> {
+		match *event {
+			SwarmEvent::ConnectionEstablished { id, ref addr, endpoint } => {
+				if endpoint == Endpoint::Dialer {
+					push_bounded(&mut self.dials, true, self.config.window);
+				}
+
+				if let Some(n) = self.bootstrap.iter().position(|a| a == addr) {
+					self.bootstrap_connections.insert((id, n));
+					self.bootstrap_reached = true;
+				}
+			},
+			SwarmEvent::ConnectionClosed { id, .. } => {
+				self.bootstrap_connections.retain(|&(conn, _)| conn != id);
+			},
+			SwarmEvent::DialFailed { .. } => {
+				push_bounded(&mut self.dials, false, self.config.window);
+			},
+			_ => return None,
+		}
+
+		self.update()
+	}
+
+	/// Records the result of a lookup in the network. Returns an event if the opinion of the
+	/// detector changed.
+	pub fn inject_lookup_result(&mut self, success: bool) -> Option<PartitionEvent> {
+		push_bounded(&mut self.lookups, success, self.config.window);
+		self.update()
+	}
+
+	// Checks whether the opinion changed.
+	fn update(&mut self) -> Option<PartitionEvent> {
+		let evidence = self.evidence();
+		let partitioned = evidence.is_partitioned(&self.config);
+
+		match (self.suspected, partitioned) {
+			(false, true) => {
+				self.suspected = true;
+				Some(PartitionEvent::Suspected(evidence))
+			},
+			(true, false) => {
+				self.suspected = false;
+				Some(PartitionEvent::Recovered(evidence))
+			},
+			_ => None,
+		}
+	}
+}
+
+// Pushes `value` at the back of `list`, removing the oldest element if `list` is full.
+#[inline]
+fn push_bounded(list: &mut VecDeque<bool>, value: bool, max: usize) {
+	if list.len() >= max {
+		list.pop_front();
+	}
+	list.push_back(value);
+}
+
+// Returns the fraction of `false` in `list`, or `None` if it has fewer than `min` elements.
+fn failure_ratio(list: &VecDeque<bool>, min: usize) -> Option<f64> {
+	if list.is_empty() || list.len() < min {
+		return None;
+	}
+
+	let failures = list.iter().filter(|&&success| !success).count();
+	Some(failures as f64 / list.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{PartitionConfig, PartitionDetector, PartitionEvent};
+	use connection_info::Connections;
+	use error::{DialError, SwarmError};
+	use multiaddr::Multiaddr;
+	use std::sync::Arc;
+	use swarm::SwarmEvent;
+	use transport::Endpoint;
+
+	fn config() -> PartitionConfig {
+		PartitionConfig {
+			window: 4,
+			min_samples: 2,
+			.. PartitionConfig::default()
+		}
+	}
+
+	#[test]
+	fn failed_lookups() {
+		let mut detector = PartitionDetector::new(config());
+		assert!(detector.inject_lookup_result(false).is_none());
+		match detector.inject_lookup_result(false) {
+			Some(PartitionEvent::Suspected(ref evidence)) => {
+				assert_eq!(evidence.lookup_failure_ratio, Some(1.0))
+			},
+			other => panic!("unexpected {:?}", other),
+		}
+
+		// The window now contains three failures out of four lookups.
+		assert!(detector.inject_lookup_result(false).is_none());
+		match detector.inject_lookup_result(true) {
+			Some(PartitionEvent::Recovered(_)) => (),
+			other => panic!("unexpected {:?}", other),
+		}
+	}
+
+	#[test]
+	fn failed_dials() {
+		let mut detector = PartitionDetector::new(config());
+		let addr = "/ip4/1.2.3.4/tcp/5".parse::<Multiaddr>().unwrap();
+		let event = SwarmEvent::DialFailed {
+			addr: addr.clone(),
+			error: Arc::new(SwarmError::Dial(DialError::Unsupported(addr))),
+		};
+
+		assert!(detector.inject_swarm_event(&event).is_none());
+		assert!(detector.inject_swarm_event(&event).is_some());
+		assert!(detector.is_suspected());
+	}
+
+	#[test]
+	fn lost_bootstrap() {
+		let mut detector = PartitionDetector::new(config());
+		let bootstrap = "/ip4/1.2.3.4/tcp/5".parse::<Multiaddr>().unwrap();
+		detector.add_bootstrap(bootstrap.clone());
+		let id = Connections::new().insert(bootstrap.clone(), None, Endpoint::Dialer);
+
+		let established = SwarmEvent::ConnectionEstablished {
+			id: id,
+			addr: bootstrap.clone(),
+			endpoint: Endpoint::Dialer,
+		};
+		assert!(detector.inject_swarm_event(&established).is_none());
+		assert_eq!(detector.evidence().bootstrap_connected, 1);
+
+		let closed = SwarmEvent::ConnectionClosed { id: id, addr: bootstrap, error: None };
+		match detector.inject_swarm_event(&closed) {
+			Some(PartitionEvent::Suspected(ref evidence)) => assert!(evidence.bootstrap_lost),
+			other => panic!("unexpected {:?}", other),
+		}
+	}
+}
@@ -0,0 +1,171 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `PeerConnections` struct, which keeps at most one connection per remote peer.
+//!
+//! When two nodes dial each other at the same time, each of them ends up with two connections to
+//! the other: one where it is the dialer and one where it is the listener. Both nodes must agree
+//! on which one to close, without having to communicate. The rule is the following: the
+//! connection that survives is the one that was opened by the node with the lowest identity.
+//!
+//! The swarm itself doesn't know the identity of the remotes (it is only known after a security
+//! upgrade such as secio), therefore it is the responsibility of the user to call `insert` once
+//! the identity of the remote is known. The swarm uses a `PeerConnections` internally once the
+//! identity of a connection has been recorded with `SwarmController::update_connection()`, see
+//! `SwarmController::peer_connection()`.
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use transport::Endpoint;
+
+/// Collection of connections, indexed by the identity of the remote.
+///
+/// Cloning a `PeerConnections` is cheap and produces an object that shares the same content.
+#[derive(Clone)]
+pub struct PeerConnections<K, M>
+	where K: Hash + Eq
+{
+	local_id: K,
+	connections: Arc<Mutex<HashMap<K, (M, Endpoint)>>>,
+}
+
+impl<K, M> PeerConnections<K, M>
+	where K: Hash + Eq + Ord
+{
+	/// Builds a new empty `PeerConnections`. `local_id` is the identity of the local node, and
+	/// must be of the same kind as the identities passed to `insert`.
+	#[inline]
+	pub fn new(local_id: K) -> PeerConnections<K, M> {
+		PeerConnections {
+			local_id: local_id,
+			connections: Arc::new(Mutex::new(HashMap::new())),
+		}
+	}
+
+	/// Registers a new connection to `remote`, where we are `endpoint`.
+	///
+	/// If there was already a connection to this remote, only one of them is kept and the other
+	/// one is returned. The returned connection should be closed (usually by dropping it).
+	/// All the new substreams to this remote should then be opened on the surviving connection.
+	pub fn insert(&self, remote: K, connection: M, endpoint: Endpoint) -> Option<M> {
+		let preferred = self.preferred_endpoint(&remote);
+		let mut connections = self.connections.lock();
+
+		let replace = match connections.get(&remote) {
+			// If both connections have the same endpoint, this isn't a simultaneous dial and we
+			// keep the one that was there first.
+			Some(&(_, existing)) => existing != endpoint && endpoint == preferred,
+			None => true,
+		};
+
+		if replace {
+			connections.insert(remote, (connection, endpoint)).map(|(old, _)| old)
+		} else {
+			Some(connection)
+		}
+	}
+
+	/// Returns the connection to `remote`, if any.
+	#[inline]
+	pub fn get(&self, remote: &K) -> Option<M>
+		where M: Clone
+	{
+		self.connections.lock().get(remote).map(|&(ref c, _)| c.clone())
+	}
+
+	/// Removes `connection` from the connections to `remote`, for example because it has been
+	/// closed. Returns false if the connection to `remote` is another one, in which case nothing
+	/// is removed.
+	///
+	/// A connection that lost a simultaneous dial is closed after the surviving one has been
+	/// inserted, therefore its closing must not remove the survivor.
+	pub fn remove(&self, remote: &K, connection: &M) -> bool
+		where M: PartialEq
+	{
+		let mut connections = self.connections.lock();
+		match connections.get(remote) {
+			Some(&(ref existing, _)) if existing == connection => (),
+			_ => return false,
+		}
+		connections.remove(remote);
+		true
+	}
+
+	/// Removes all the connections.
+	#[inline]
+	pub fn clear(&self) {
+		self.connections.lock().clear();
+	}
+
+	// Returns the endpoint of the connection to `remote` that must survive in case of a
+	// simultaneous dial.
+	#[inline]
+	fn preferred_endpoint(&self, remote: &K) -> Endpoint {
+		if self.local_id < *remote {
+			Endpoint::Dialer
+		} else {
+			Endpoint::Listener
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::PeerConnections;
+	use transport::Endpoint;
+
+	#[test]
+	fn simultaneous_dial_agrees() {
+		let node_a = PeerConnections::new(1);
+		let node_b = PeerConnections::new(2);
+
+		// Connection 10 is dialed by A, connection 20 is dialed by B.
+		assert!(node_a.insert(2, 10, Endpoint::Dialer).is_none());
+		assert!(node_b.insert(1, 20, Endpoint::Dialer).is_none());
+		let closed_by_a = node_a.insert(2, 20, Endpoint::Listener);
+		let closed_by_b = node_b.insert(1, 10, Endpoint::Listener);
+
+		assert_eq!(closed_by_a, Some(20));
+		assert_eq!(closed_by_b, Some(20));
+		assert_eq!(node_a.get(&2), Some(10));
+		assert_eq!(node_b.get(&1), Some(10));
+	}
+
+	#[test]
+	fn same_endpoint_keeps_first() {
+		let node = PeerConnections::new(1);
+		assert!(node.insert(2, 10, Endpoint::Dialer).is_none());
+		assert_eq!(node.insert(2, 11, Endpoint::Dialer), Some(11));
+		assert!(node.remove(&2, &10));
+		assert!(node.get(&2).is_none());
+	}
+
+	#[test]
+	fn removing_loser_keeps_survivor() {
+		let node = PeerConnections::new(1);
+		assert!(node.insert(2, 10, Endpoint::Dialer).is_none());
+		assert_eq!(node.insert(2, 20, Endpoint::Listener), Some(20));
+		assert!(!node.remove(&2, &20));
+		assert_eq!(node.get(&2), Some(10));
+		assert!(!node.remove(&3, &10));
+	}
+}
@@ -0,0 +1,177 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `PeerId` struct, which identifies a node of the network.
+
+use base58::{FromBase58, ToBase58};
+use multihash;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Identifier of a peer of the network.
+///
+/// The data is a multihash of the public key of the peer, encoded as a `PublicKey` protobuf
+/// message. It is what appears in the `/p2p` component of a multiaddress, and is displayed in
+/// base58, like in the other implementations of libp2p.
+// TODO: maybe keep things in decoded version?
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PeerId {
+	multihash: Vec<u8>,
+}
+
+impl fmt::Debug for PeerId {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "PeerId({})", self.to_base58())
+	}
+}
+
+impl fmt::Display for PeerId {
+	#[inline]
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.to_base58())
+	}
+}
+
+impl PeerId {
+	/// Builds a `PeerId` from a public key, encoded as a `PublicKey` protobuf message.
+	#[inline]
+	pub fn from_public_key(public_key: &[u8]) -> PeerId {
+		let data = multihash::encode(multihash::Hash::SHA2256, public_key)
+			.expect("sha2-256 is always supported");
+		PeerId { multihash: data }
+	}
+
+	/// Checks whether `data` is a valid `PeerId`. If so, returns the `PeerId`. If not, returns
+	/// back the data as an error.
+	#[inline]
+	pub fn from_bytes(data: Vec<u8>) -> Result<PeerId, Vec<u8>> {
+		match multihash::decode(&data) {
+			Ok(_) => Ok(PeerId { multihash: data }),
+			Err(_) => Err(data),
+		}
+	}
+
+	/// Decodes a `PeerId` from its base58 representation, as produced by `to_base58`.
+	#[inline]
+	pub fn from_base58(s: &str) -> Result<PeerId, ParsePeerIdError> {
+		let bytes = s.from_base58().map_err(|_| ParsePeerIdError::InvalidBase58)?;
+		PeerId::from_bytes(bytes).map_err(|_| ParsePeerIdError::InvalidMultihash)
+	}
+
+	/// Returns a raw bytes representation of this `PeerId`.
+	#[inline]
+	pub fn into_bytes(self) -> Vec<u8> {
+		self.multihash
+	}
+
+	/// Returns a raw bytes representation of this `PeerId`.
+	#[inline]
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.multihash
+	}
+
+	/// Returns the base58 representation of this `PeerId`. This is also what `Display` prints.
+	#[inline]
+	pub fn to_base58(&self) -> String {
+		self.multihash.to_base58()
+	}
+
+	/// Returns the raw bytes of the hash of this `PeerId`.
+	#[inline]
+	pub fn hash(&self) -> &[u8] {
+		let multihash::Multihash { digest, .. } = multihash::decode(&self.multihash)
+			.expect("our inner value should always be valid");
+		digest
+	}
+
+	/// Checks whether the public key passed as parameter matches the public key of this `PeerId`.
+	pub fn is_public_key(&self, public_key: &[u8]) -> bool {
+		let multihash::Multihash { alg, .. } = multihash::decode(&self.multihash)
+			.expect("our inner value should always be valid");
+		let compare = multihash::encode(alg, public_key)
+			.expect("unsupported multihash algorithm");     // TODO: what to do here?
+		compare == self.multihash
+	}
+}
+
+impl From<PeerId> for Vec<u8> {
+	#[inline]
+	fn from(peer_id: PeerId) -> Vec<u8> {
+		peer_id.into_bytes()
+	}
+}
+
+impl FromStr for PeerId {
+	type Err = ParsePeerIdError;
+
+	#[inline]
+	fn from_str(s: &str) -> Result<PeerId, ParsePeerIdError> {
+		PeerId::from_base58(s)
+	}
+}
+
+/// Error while parsing the base58 representation of a `PeerId`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParsePeerIdError {
+	/// The string isn't valid base58.
+	InvalidBase58,
+	/// The decoded bytes aren't a valid multihash.
+	InvalidMultihash,
+}
+
+impl Error for ParsePeerIdError {
+	#[inline]
+	fn description(&self) -> &str {
+		match *self {
+			ParsePeerIdError::InvalidBase58 => "the peer ID isn't valid base58",
+			ParsePeerIdError::InvalidMultihash => "the peer ID isn't a valid multihash",
+		}
+	}
+}
+
+impl fmt::Display for ParsePeerIdError {
+	#[inline]
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.description())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ParsePeerIdError, PeerId};
+
+	#[test]
+	fn base58_roundtrip() {
+		let peer_id = PeerId::from_public_key(&[1, 2, 3, 4]);
+		let s = peer_id.to_string();
+		assert_eq!(s, peer_id.to_base58());
+		assert!(s.starts_with("Qm"));
+		assert_eq!(s.parse::<PeerId>(), Ok(peer_id.clone()));
+		assert!(peer_id.is_public_key(&[1, 2, 3, 4]));
+		assert!(!peer_id.is_public_key(&[1, 2, 3]));
+	}
+
+	#[test]
+	fn invalid_strings() {
+		assert_eq!("0OIl".parse::<PeerId>(), Err(ParsePeerIdError::InvalidBase58));
+		assert_eq!("abc".parse::<PeerId>(), Err(ParsePeerIdError::InvalidMultihash));
+	}
+}
@@ -0,0 +1,554 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `ProtocolsHandler` trait, which handles the substreams of a single connection,
+//! and the `HandledNode` stream, which drives a `ProtocolsHandler` on top of a `StreamMuxer`.
+//!
+//! A protocol such as ping or identify doesn't care about how the substreams are opened,
+//! negotiated and upgraded. Implementing `ProtocolsHandler` only requires deciding which upgrade
+//! to apply on the substreams opened by the remote, when to open new substreams, and what to do
+//! with the upgraded substreams. The bookkeeping is done by the `HandledNode`.
+
+use bytes::Bytes;
+use futures::{Async, Future, Poll, Stream};
+use multiaddr::Multiaddr;
+use multistream_select::{self, DialerSelectFuture, ListenerSelectFuture};
+use muxing::StreamMuxer;
+use std::io::Error as IoError;
+use std::mem;
+use tokio_io::{AsyncRead, AsyncWrite};
+use transport::{names_with_match, NamesWithMatch};
+use {ConnectionUpgrade, Endpoint, UpgradeError};
+
+/// Handles the substreams of a single connection for a protocol.
+///
+/// The handler is driven by a `HandledNode`, which calls the `inject_*` methods when something
+/// happens on the connection, and calls `poll` afterwards.
+pub trait ProtocolsHandler {
+	/// Event that the handler receives from the outside. See `inject_event`.
+	type InEvent;
+	/// Event that the handler produces for the outside.
+	type OutEvent;
+	/// Type of the substreams of the connection.
+	type Substream: AsyncRead + AsyncWrite;
+	/// Upgrade applied on the substreams, both inbound and outbound.
+	type Protocol: ConnectionUpgrade<Self::Substream>;
+	/// Information attached to an outbound substream request, and given back once the substream
+	/// has been upgraded or has failed.
+	type OutboundOpenInfo;
+
+	/// Returns the upgrade to apply on the next substream opened by the remote.
+	fn listen_protocol(&self) -> Self::Protocol;
+
+	/// Injects the output of a substream that has been opened and upgraded.
+	fn inject_fully_negotiated(
+		&mut self,
+		protocol: <Self::Protocol as ConnectionUpgrade<Self::Substream>>::Output,
+		endpoint: NodeHandlerEndpoint<Self::OutboundOpenInfo>
+	);
+
+	/// Injects an event coming from the outside.
+	fn inject_event(&mut self, event: Self::InEvent);
+
+	/// Indicates that opening or upgrading an outbound substream failed.
+	fn inject_dial_upgrade_error(&mut self, info: Self::OutboundOpenInfo, error: &IoError);
+
+	/// Indicates that the connection is going to be closed. No new inbound substream will be
+	/// injected, and `poll` should produce `None` once the handler is done.
+	fn shutdown(&mut self);
+
+	/// Polls the handler for events, or for requests to open substreams.
+	///
+	/// Producing `None` means that the handler is finished, and that the connection can be
+	/// closed as far as it is concerned.
+	fn poll(&mut self) -> Poll<
+		Option<ProtocolsHandlerEvent<Self::Protocol, Self::OutboundOpenInfo, Self::OutEvent>>,
+		IoError
+	>;
+}
+
+/// Indicates which side opened a substream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeHandlerEndpoint<TOutboundOpenInfo> {
+	/// We opened the substream. Contains the information passed alongside the request.
+	Dialer(TOutboundOpenInfo),
+	/// The remote opened the substream.
+	Listener,
+}
+
+/// Event produced by a `ProtocolsHandler`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolsHandlerEvent<TUpgrade, TOutboundOpenInfo, TCustom> {
+	/// Asks for a new substream to be opened and upgraded with `upgrade`.
+	OutboundSubstreamRequest {
+		/// The upgrade to apply on the substream.
+		upgrade: TUpgrade,
+		/// Given back in `inject_fully_negotiated` or `inject_dial_upgrade_error`.
+		info: TOutboundOpenInfo,
+	},
+
+	/// Event to produce to the outside.
+	Custom(TCustom),
+}
+
+/// Drives a `ProtocolsHandler` on top of a connection that supports multiplexing.
+///
+/// Accepts the substreams opened by the remote, opens the substreams requested by the handler,
+/// negotiates and upgrades all of them, and gives the result to the handler. Produces the events
+/// of the handler, and finishes once the handler has finished and the connection has been closed
+/// with `StreamMuxer::close()`.
+pub struct HandledNode<M, H>
+	where M: StreamMuxer,
+		  H: ProtocolsHandler<Substream = M::Substream>,
+{
+	muxer: M,
+	remote_addr: Multiaddr,
+	handler: H,
+	// Future to the next substream opened by the remote. `None` if we stopped accepting them.
+	inbound: Option<M::InboundSubstream>,
+	// Outbound substreams being opened, with the upgrade to apply on them.
+	outbound: Vec<(M::OutboundSubstream, H::Protocol, H::OutboundOpenInfo)>,
+	// Inbound substreams being negotiated and upgraded.
+	upgrading_in: Vec<SubstreamUpgrade<M::Substream, H::Protocol>>,
+	// Outbound substreams being negotiated and upgraded.
+	upgrading_out: Vec<(SubstreamUpgrade<M::Substream, H::Protocol>, H::OutboundOpenInfo)>,
+	// True if the handler has finished and we are closing the connection.
+	closing: bool,
+	// Future that triggers `shutdown()` when it resolves. See `shutdown_on`.
+	shutdown_signal: Option<Box<Future<Item = (), Error = IoError> + Send>>,
+}
+
+impl<M, H> HandledNode<M, H>
+	where M: StreamMuxer + Clone,
+		  H: ProtocolsHandler<Substream = M::Substream>,
+{
+	/// Builds a new `HandledNode` for the connection to `remote_addr`.
+	#[inline]
+	pub fn new(muxer: M, remote_addr: Multiaddr, handler: H) -> HandledNode<M, H> {
+		HandledNode {
+			inbound: Some(muxer.clone().inbound()),
+			muxer: muxer,
+			remote_addr: remote_addr,
+			handler: handler,
+			outbound: Vec::new(),
+			upgrading_in: Vec::new(),
+			upgrading_out: Vec::new(),
+			closing: false,
+			shutdown_signal: None,
+		}
+	}
+
+	/// Calls `shutdown()` once `signal` resolves or fails. Typically used with
+	/// `SwarmController::closing()`, so that the connection closes its substreams and its muxer
+	/// when the swarm shuts down gracefully.
+	#[inline]
+	pub fn shutdown_on<S>(mut self, signal: S) -> HandledNode<M, H>
+		where S: Future<Item = (), Error = IoError> + Send + 'static
+	{
+		self.shutdown_signal = Some(Box::new(signal));
+		self
+	}
+
+	/// Returns a reference to the handler.
+	#[inline]
+	pub fn handler(&self) -> &H {
+		&self.handler
+	}
+
+	/// Returns a mutable reference to the handler.
+	#[inline]
+	pub fn handler_mut(&mut self) -> &mut H {
+		&mut self.handler
+	}
+
+	/// Injects an event in the handler.
+	///
+	/// > **Note**: The handler only gets a chance to react the next time the `HandledNode` is
+	/// >           polled.
+	#[inline]
+	pub fn inject_event(&mut self, event: H::InEvent) {
+		self.handler.inject_event(event);
+	}
+
+	/// Stops accepting the substreams opened by the remote, and asks the handler to shut down.
+	/// The `HandledNode` finishes once the handler has finished.
+	#[inline]
+	pub fn shutdown(&mut self) {
+		self.inbound = None;
+		self.handler.shutdown();
+	}
+}
+
+impl<M, H> Stream for HandledNode<M, H>
+	where M: StreamMuxer + Clone,
+		  H: ProtocolsHandler<Substream = M::Substream>,
+		  <H::Protocol as ConnectionUpgrade<M::Substream>>::NamesIter: Clone,
+{
+	type Item = H::OutEvent;
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+		let signalled = match self.shutdown_signal {
+			Some(ref mut signal) => match signal.poll() {
+				Ok(Async::NotReady) => false,
+				Ok(Async::Ready(())) | Err(_) => true,
+			},
+			None => false,
+		};
+		if signalled {
+			self.shutdown_signal = None;
+			self.shutdown();
+		}
+
+		loop {
+			if self.closing {
+				try_ready!(self.muxer.close());
+				return Ok(Async::Ready(None));
+			}
+
+			if let Some(mut inbound) = self.inbound.take() {
+				loop {
+					match inbound.poll()? {
+						Async::Ready(substream) => {
+							let upgrade = self.handler.listen_protocol();
+							self.upgrading_in.push(SubstreamUpgrade::listener(substream, upgrade));
+							inbound = self.muxer.clone().inbound();
+						},
+						Async::NotReady => {
+							self.inbound = Some(inbound);
+							break;
+						},
+					}
+				}
+			}
+
+			for n in (0 .. self.outbound.len()).rev() {
+				let (mut opening, upgrade, info) = self.outbound.swap_remove(n);
+				match opening.poll() {
+					Ok(Async::Ready(substream)) => {
+						let upgrade = SubstreamUpgrade::dialer(substream, upgrade);
+						self.upgrading_out.push((upgrade, info));
+					},
+					Ok(Async::NotReady) => self.outbound.push((opening, upgrade, info)),
+					Err(err) => self.handler.inject_dial_upgrade_error(info, &err),
+				}
+			}
+
+			for n in (0 .. self.upgrading_in.len()).rev() {
+				let mut upgrade = self.upgrading_in.swap_remove(n);
+				match upgrade.poll(Endpoint::Listener, &self.remote_addr) {
+					Ok(Async::Ready(output)) => {
+						self.handler.inject_fully_negotiated(output, NodeHandlerEndpoint::Listener);
+					},
+					Ok(Async::NotReady) => self.upgrading_in.push(upgrade),
+					// The remote opened a substream that we can't handle ; dropping it.
+					Err(_) => (),
+				}
+			}
+
+			for n in (0 .. self.upgrading_out.len()).rev() {
+				let (mut upgrade, info) = self.upgrading_out.swap_remove(n);
+				match upgrade.poll(Endpoint::Dialer, &self.remote_addr) {
+					Ok(Async::Ready(output)) => {
+						let endpoint = NodeHandlerEndpoint::Dialer(info);
+						self.handler.inject_fully_negotiated(output, endpoint);
+					},
+					Ok(Async::NotReady) => self.upgrading_out.push((upgrade, info)),
+					Err(err) => self.handler.inject_dial_upgrade_error(info, &err),
+				}
+			}
+
+			// The new outbound substreams must be polled once so that we get notified, therefore
+			// we loop again if the handler requested some.
+			let mut new_outbound = false;
+			loop {
+				match self.handler.poll()? {
+					Async::Ready(Some(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+						upgrade,
+						info,
+					})) => {
+						self.outbound.push((self.muxer.clone().outbound(), upgrade, info));
+						new_outbound = true;
+					},
+					Async::Ready(Some(ProtocolsHandlerEvent::Custom(event))) => {
+						return Ok(Async::Ready(Some(event)));
+					},
+					Async::Ready(None) => {
+						self.inbound = None;
+						self.closing = true;
+						break;
+					},
+					Async::NotReady => break,
+				}
+			}
+
+			if !new_outbound && !self.closing {
+				return Ok(Async::NotReady);
+			}
+		}
+	}
+}
+
+// Negotiates a protocol on a substream, then upgrades it.
+struct SubstreamUpgrade<C, U>
+	where C: AsyncRead + AsyncWrite,
+		  U: ConnectionUpgrade<C>,
+{
+	inner: SubstreamUpgradeState<C, U>,
+}
+
+enum SubstreamUpgradeState<C, U>
+	where C: AsyncRead + AsyncWrite,
+		  U: ConnectionUpgrade<C>,
+{
+	DialerNegotiating {
+		future: DialerSelectFuture<C, NamesWithMatch<U::NamesIter, U::UpgradeIdentifier>,
+								   U::UpgradeIdentifier>,
+		upgrade: U,
+	},
+	ListenerNegotiating {
+		future: ListenerSelectFuture<C, NamesWithMatch<U::NamesIter, U::UpgradeIdentifier>,
+									 U::UpgradeIdentifier, fn(&Bytes)>,
+		upgrade: U,
+	},
+	Upgrading {
+		future: U::Future,
+	},
+	// Temporary state while switching between the other states.
+	Undefined,
+}
+
+impl<C, U> SubstreamUpgrade<C, U>
+	where C: AsyncRead + AsyncWrite,
+		  U: ConnectionUpgrade<C>,
+		  U::NamesIter: Clone,
+{
+	// Starts negotiating a substream that we opened.
+	fn dialer(substream: C, upgrade: U) -> SubstreamUpgrade<C, U> {
+		let names = names_with_match::<C, _>(&upgrade);
+		SubstreamUpgrade {
+			inner: SubstreamUpgradeState::DialerNegotiating {
+				future: multistream_select::dialer_select_proto(substream, names),
+				upgrade: upgrade,
+			},
+		}
+	}
+
+	// Starts negotiating a substream that the remote opened.
+	fn listener(substream: C, upgrade: U) -> SubstreamUpgrade<C, U> {
+		let names = names_with_match::<C, _>(&upgrade);
+		SubstreamUpgrade {
+			inner: SubstreamUpgradeState::ListenerNegotiating {
+				future: multistream_select::listener_select_proto(substream, names),
+				upgrade: upgrade,
+			},
+		}
+	}
+
+	// Polls the negotiation or the upgrade. The endpoint and the address are passed to the
+	// upgrade once a protocol has been negotiated.
+	fn poll(&mut self, endpoint: Endpoint, remote_addr: &Multiaddr) -> Poll<U::Output, IoError> {
+		loop {
+			match mem::replace(&mut self.inner, SubstreamUpgradeState::Undefined) {
+				SubstreamUpgradeState::DialerNegotiating { mut future, upgrade } => {
+					let (id, substream) = match future.poll() {
+						Ok(Async::Ready(val)) => val,
+						Ok(Async::NotReady) => {
+							self.inner = SubstreamUpgradeState::DialerNegotiating {
+								future: future,
+								upgrade: upgrade,
+							};
+							return Ok(Async::NotReady);
+						},
+						Err(err) => return Err(UpgradeError::Negotiation(err).into()),
+					};
+
+					let future = upgrade.upgrade(substream, id, endpoint, remote_addr);
+					self.inner = SubstreamUpgradeState::Upgrading { future: future };
+				},
+
+				SubstreamUpgradeState::ListenerNegotiating { mut future, upgrade } => {
+					let (id, substream) = match future.poll() {
+						Ok(Async::Ready(val)) => val,
+						Ok(Async::NotReady) => {
+							self.inner = SubstreamUpgradeState::ListenerNegotiating {
+								future: future,
+								upgrade: upgrade,
+							};
+							return Ok(Async::NotReady);
+						},
+						Err(err) => return Err(UpgradeError::Negotiation(err).into()),
+					};
+
+					let future = upgrade.upgrade(substream, id, endpoint, remote_addr);
+					self.inner = SubstreamUpgradeState::Upgrading { future: future };
+				},
+
+				SubstreamUpgradeState::Upgrading { mut future } => {
+					return match future.poll() {
+						Ok(Async::Ready(output)) => Ok(Async::Ready(output)),
+						Ok(Async::NotReady) => {
+							self.inner = SubstreamUpgradeState::Upgrading { future: future };
+							Ok(Async::NotReady)
+						},
+						Err(err) => Err(UpgradeError::from(err).into()),
+					};
+				},
+
+				SubstreamUpgradeState::Undefined => {
+					panic!("SubstreamUpgrade polled after completion")
+				},
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{HandledNode, NodeHandlerEndpoint, ProtocolsHandler, ProtocolsHandlerEvent};
+	use futures::{future, Async, Future, Poll, Stream};
+	use futures::sync::oneshot;
+	use muxing::StreamMuxer;
+	use std::io::{Cursor, Error as IoError, ErrorKind as IoErrorKind};
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicBool, Ordering};
+	use transport::PlainTextConfig;
+
+	// Muxer that never receives substreams and fails to open any. Records whether it was closed.
+	#[derive(Clone, Default)]
+	struct FailingMuxer {
+		closed: Arc<AtomicBool>,
+	}
+
+	impl StreamMuxer for FailingMuxer {
+		type Substream = Cursor<Vec<u8>>;
+		type InboundSubstream = future::Empty<Self::Substream, IoError>;
+		type OutboundSubstream = future::FutureResult<Self::Substream, IoError>;
+
+		fn inbound(self) -> Self::InboundSubstream {
+			future::empty()
+		}
+
+		fn outbound(self) -> Self::OutboundSubstream {
+			future::err(IoError::new(IoErrorKind::ConnectionReset, "closed"))
+		}
+
+		fn close(&self) -> Poll<(), IoError> {
+			self.closed.store(true, Ordering::SeqCst);
+			Ok(Async::Ready(()))
+		}
+	}
+
+	// Requests one substream, and reports the failure to open it.
+	struct Handler {
+		requested: bool,
+		failed: Option<u32>,
+		shut_down: bool,
+	}
+
+	impl ProtocolsHandler for Handler {
+		type InEvent = ();
+		type OutEvent = u32;
+		type Substream = Cursor<Vec<u8>>;
+		type Protocol = PlainTextConfig;
+		type OutboundOpenInfo = u32;
+
+		fn listen_protocol(&self) -> PlainTextConfig {
+			PlainTextConfig
+		}
+
+		fn inject_fully_negotiated(&mut self, _: Cursor<Vec<u8>>, _: NodeHandlerEndpoint<u32>) {
+			panic!("no substream can be opened")
+		}
+
+		fn inject_event(&mut self, _: ()) {}
+
+		fn inject_dial_upgrade_error(&mut self, info: u32, _: &IoError) {
+			self.failed = Some(info);
+		}
+
+		fn shutdown(&mut self) {
+			self.shut_down = true;
+		}
+
+		fn poll(&mut self) -> Poll<Option<ProtocolsHandlerEvent<PlainTextConfig, u32, u32>>,
+								   IoError> {
+			if self.shut_down {
+				return Ok(Async::Ready(None));
+			}
+
+			if !self.requested {
+				self.requested = true;
+				return Ok(Async::Ready(Some(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+					upgrade: PlainTextConfig,
+					info: 7,
+				})));
+			}
+
+			match self.failed.take() {
+				Some(info) => Ok(Async::Ready(Some(ProtocolsHandlerEvent::Custom(info)))),
+				None => Ok(Async::NotReady),
+			}
+		}
+	}
+
+	#[test]
+	fn outbound_failure_reaches_handler() {
+		let handler = Handler { requested: false, failed: None, shut_down: false };
+		let addr = "/ip4/127.0.0.1/tcp/1234".parse().unwrap();
+		let node = HandledNode::new(FailingMuxer::default(), addr, handler);
+
+		let (event, _) = node.into_future().map_err(|(err, _)| err).wait().unwrap();
+		assert_eq!(event, Some(7));
+	}
+
+	#[test]
+	fn shutdown_closes_muxer() {
+		let handler = Handler { requested: true, failed: None, shut_down: false };
+		let addr = "/ip4/127.0.0.1/tcp/1234".parse().unwrap();
+		let muxer = FailingMuxer::default();
+		let mut node = HandledNode::new(muxer.clone(), addr, handler);
+		node.shutdown();
+
+		let (event, _) = node.into_future().map_err(|(err, _)| err).wait().unwrap();
+		assert_eq!(event, None);
+		assert!(muxer.closed.load(Ordering::SeqCst));
+	}
+
+	#[test]
+	fn shutdown_signal_closes_muxer() {
+		let handler = Handler { requested: true, failed: None, shut_down: false };
+		let addr = "/ip4/127.0.0.1/tcp/1234".parse().unwrap();
+		let muxer = FailingMuxer::default();
+		let (tx, rx) = oneshot::channel();
+		let mut node = HandledNode::new(muxer.clone(), addr, handler)
+			.shutdown_on(rx.map_err(|_| IoError::new(IoErrorKind::Other, "canceled")));
+
+		// Polling within a task, so that the signal can register it.
+		let poll = future::poll_fn(|| Ok::<_, ()>(Async::Ready(node.poll()))).wait().unwrap();
+		assert!(poll.unwrap().is_not_ready());
+		assert!(!muxer.closed.load(Ordering::SeqCst));
+
+		tx.send(()).unwrap();
+		let (event, _) = node.into_future().map_err(|(err, _)| err).wait().unwrap();
+		assert_eq!(event, None);
+		assert!(muxer.closed.load(Ordering::SeqCst));
+	}
+}
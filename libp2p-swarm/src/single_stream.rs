@@ -0,0 +1,129 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `SingleStreamMuxer`, which implements the `StreamMuxer` trait on top of a
+//! connection that doesn't support multiplexing.
+//!
+//! The connection itself is the only substream. It is produced once by `outbound()` on the side
+//! that dialed the connection, and once by `inbound()` on the side that received it. Any other
+//! attempt to open a substream fails, and no other inbound substream is ever produced.
+//!
+//! This makes it possible to run the code written against the `StreamMuxer` API, such as a
+//! `HandledNode`, over a transport or with a remote that doesn't support any muxer.
+
+use futures::future;
+use muxing::StreamMuxer;
+use parking_lot::Mutex;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::sync::Arc;
+use tokio_io::{AsyncRead, AsyncWrite};
+use transport::Endpoint;
+
+/// Implementation of `StreamMuxer` that exposes a connection as a single substream. See the
+/// module-level documentation.
+pub struct SingleStreamMuxer<C> {
+	// The connection, until it has been produced as the substream.
+	inner: Arc<Mutex<Option<C>>>,
+	endpoint: Endpoint,
+}
+
+impl<C> SingleStreamMuxer<C> {
+	/// Wraps around a connection. `endpoint` indicates whether we dialed the connection or
+	/// received it.
+	#[inline]
+	pub fn new(connection: C, endpoint: Endpoint) -> SingleStreamMuxer<C> {
+		SingleStreamMuxer {
+			inner: Arc::new(Mutex::new(Some(connection))),
+			endpoint: endpoint,
+		}
+	}
+}
+
+impl<C> Clone for SingleStreamMuxer<C> {
+	#[inline]
+	fn clone(&self) -> Self {
+		SingleStreamMuxer {
+			inner: self.inner.clone(),
+			endpoint: self.endpoint,
+		}
+	}
+}
+
+impl<C> StreamMuxer for SingleStreamMuxer<C>
+	where C: AsyncRead + AsyncWrite
+{
+	type Substream = C;
+	type InboundSubstream = future::Either<future::FutureResult<C, IoError>,
+										   future::Empty<C, IoError>>;
+	type OutboundSubstream = future::FutureResult<C, IoError>;
+
+	fn inbound(self) -> Self::InboundSubstream {
+		if self.endpoint == Endpoint::Listener {
+			if let Some(connection) = self.inner.lock().take() {
+				return future::Either::A(future::ok(connection));
+			}
+		}
+
+		future::Either::B(future::empty())
+	}
+
+	fn outbound(self) -> Self::OutboundSubstream {
+		if self.endpoint == Endpoint::Dialer {
+			if let Some(connection) = self.inner.lock().take() {
+				return future::ok(connection);
+			}
+		}
+
+		future::err(IoError::new(IoErrorKind::Other,
+								 "the connection only supports a single substream"))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::SingleStreamMuxer;
+	use futures::{Async, Future};
+	use muxing::StreamMuxer;
+	use std::io::Cursor;
+	use transport::Endpoint;
+
+	#[test]
+	fn dialer() {
+		let muxer = SingleStreamMuxer::new(Cursor::new(vec![1, 2, 3]), Endpoint::Dialer);
+
+		assert!(muxer.clone().inbound().poll().unwrap().is_not_ready());
+		let substream = muxer.clone().outbound().wait().unwrap();
+		assert_eq!(substream.into_inner(), vec![1, 2, 3]);
+		assert!(muxer.clone().outbound().wait().is_err());
+		assert!(muxer.inbound().poll().unwrap().is_not_ready());
+	}
+
+	#[test]
+	fn listener() {
+		let muxer = SingleStreamMuxer::new(Cursor::new(vec![1, 2, 3]), Endpoint::Listener);
+
+		assert!(muxer.clone().outbound().wait().is_err());
+		match muxer.clone().inbound().poll() {
+			Ok(Async::Ready(substream)) => assert_eq!(substream.into_inner(), vec![1, 2, 3]),
+			_ => panic!(),
+		}
+		assert!(muxer.inbound().poll().unwrap().is_not_ready());
+	}
+}
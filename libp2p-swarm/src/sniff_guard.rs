@@ -0,0 +1,264 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `SniffGuard` upgrade adapter, which protects an upgrade against protocol
+//! confusion.
+//!
+//! Once a protocol has been negotiated, a misbehaving remote could immediately start speaking
+//! another protocol. A `SniffGuard` buffers the first bytes sent by the remote and checks their
+//! shape (eg. a magic number or a length prefix) before letting the inner upgrade see them. If
+//! the check fails, reading from the socket produces an error of kind `InvalidData` whose inner
+//! error is a `ProtocolMismatch`.
+//!
+//! When the guard is applied on the upgrade passed to the swarm, such an error is reported with
+//! a `SwarmEvent::UpgradeFailed` or a `SwarmEvent::ConnectionClosed`. It can be detected by
+//! calling `ProtocolMismatch::from_io_error` on the `IoError` contained in the `SwarmError`, for
+//! example in order to lower the score of the remote.
+//!
+//! A `ProtocolsHandler` can also use a guarded upgrade for its substreams and report the errors
+//! of the substreams to its behaviour. The `GossipsubHandler` of `libp2p-gossipsub` does so, and
+//! the violations it reports lower the score of the remote.
+
+use bytes::Bytes;
+use futures::Poll;
+use multiaddr::Multiaddr;
+use std::error::Error;
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::sync::Arc;
+use tokio_io::{AsyncRead, AsyncWrite};
+use transport::{ConnectionUpgrade, Endpoint};
+
+/// Wraps around a `ConnectionUpgrade` and validates the first bytes sent by the remote.
+#[derive(Clone)]
+pub struct SniffGuard<U> {
+	inner: U,
+	prefix_len: usize,
+	check: Arc<Fn(&[u8]) -> bool + Send + Sync>,
+}
+
+impl<U> SniffGuard<U> {
+	/// Builds a new `SniffGuard` around `inner`.
+	///
+	/// The first `prefix_len` bytes sent by the remote are passed to `check`, which must return
+	/// `false` if they don't look like what the protocol is supposed to send. If the remote
+	/// closes the socket earlier, `check` is called with the bytes that were received.
+	#[inline]
+	pub fn new<F>(inner: U, prefix_len: usize, check: F) -> SniffGuard<U>
+		where F: Fn(&[u8]) -> bool + Send + Sync + 'static
+	{
+		SniffGuard {
+			inner: inner,
+			prefix_len: prefix_len,
+			check: Arc::new(check),
+		}
+	}
+}
+
+impl<C, U> ConnectionUpgrade<C> for SniffGuard<U>
+	where C: AsyncRead + AsyncWrite,
+		  U: ConnectionUpgrade<SniffedSocket<C>>
+{
+	type NamesIter = U::NamesIter;
+	type UpgradeIdentifier = U::UpgradeIdentifier;
+	type Output = U::Output;
+	type Future = U::Future;
+
+	#[inline]
+	fn protocol_names(&self) -> Self::NamesIter {
+		self.inner.protocol_names()
+	}
+
+	#[inline]
+	fn upgrade(self, socket: C, id: Self::UpgradeIdentifier, ty: Endpoint,
+			   remote_addr: &Multiaddr) -> Self::Future
+	{
+		let socket = SniffedSocket {
+			inner: socket,
+			buffer: Vec::with_capacity(self.prefix_len),
+			prefix_len: self.prefix_len,
+			check: Some(self.check),
+			read_offset: 0,
+		};
+
+		self.inner.upgrade(socket, id, ty, remote_addr)
+	}
+}
+
+/// Socket produced by a `SniffGuard`.
+pub struct SniffedSocket<C> {
+	inner: C,
+	// Bytes received from the remote before the check has been performed.
+	buffer: Vec<u8>,
+	prefix_len: usize,
+	// Set to `None` once the check has succeeded.
+	check: Option<Arc<Fn(&[u8]) -> bool + Send + Sync>>,
+	// Number of bytes of `buffer` that have already been returned.
+	read_offset: usize,
+}
+
+impl<C> SniffedSocket<C>
+	where C: Read
+{
+	// Fills `buffer` and performs the check if that hasn't been done yet.
+	fn sniff(&mut self) -> Result<(), IoError> {
+		let passed = match self.check {
+			Some(ref check) => {
+				while self.buffer.len() < self.prefix_len {
+					let len = self.buffer.len();
+					self.buffer.resize(self.prefix_len, 0);
+					match self.inner.read(&mut self.buffer[len..]) {
+						Ok(0) => {
+							self.buffer.truncate(len);
+							break;
+						}
+						Ok(n) => self.buffer.truncate(len + n),
+						Err(err) => {
+							self.buffer.truncate(len);
+							return Err(err);
+						}
+					}
+				}
+
+				check(&self.buffer)
+			}
+			None => return Ok(()),
+		};
+
+		if passed {
+			self.check = None;
+			Ok(())
+		} else {
+			let err = ProtocolMismatch { received: Bytes::from(&self.buffer[..]) };
+			Err(IoError::new(IoErrorKind::InvalidData, err))
+		}
+	}
+}
+
+impl<C> Read for SniffedSocket<C>
+	where C: Read
+{
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+		self.sniff()?;
+
+		if self.read_offset < self.buffer.len() {
+			let len = (&self.buffer[self.read_offset..]).read(buf)?;
+			self.read_offset += len;
+			return Ok(len);
+		}
+
+		self.inner.read(buf)
+	}
+}
+
+impl<C> AsyncRead for SniffedSocket<C>
+	where C: AsyncRead
+{
+}
+
+impl<C> Write for SniffedSocket<C>
+	where C: Write
+{
+	#[inline]
+	fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+		self.inner.write(buf)
+	}
+
+	#[inline]
+	fn flush(&mut self) -> Result<(), IoError> {
+		self.inner.flush()
+	}
+}
+
+impl<C> AsyncWrite for SniffedSocket<C>
+	where C: AsyncWrite
+{
+	#[inline]
+	fn shutdown(&mut self) -> Poll<(), IoError> {
+		self.inner.shutdown()
+	}
+}
+
+/// Error produced when the first bytes sent by the remote don't match what the negotiated
+/// protocol is supposed to send.
+#[derive(Debug, Clone)]
+pub struct ProtocolMismatch {
+	received: Bytes,
+}
+
+impl ProtocolMismatch {
+	/// Returns the `ProtocolMismatch` contained in an `IoError`, if any.
+	#[inline]
+	pub fn from_io_error(err: &IoError) -> Option<&ProtocolMismatch> {
+		err.get_ref().and_then(|e| e.downcast_ref::<ProtocolMismatch>())
+	}
+
+	/// Returns the bytes that the remote sent and that failed the check.
+	#[inline]
+	pub fn received(&self) -> &[u8] {
+		&self.received
+	}
+}
+
+impl fmt::Display for ProtocolMismatch {
+	#[inline]
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.description())
+	}
+}
+
+impl Error for ProtocolMismatch {
+	#[inline]
+	fn description(&self) -> &str {
+		"the remote doesn't speak the protocol that was negotiated"
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ProtocolMismatch, SniffGuard};
+	use futures::Future;
+	use multiaddr::Multiaddr;
+	use std::io::{Cursor, Read};
+	use transport::{ConnectionUpgrade, Endpoint, PlainTextConfig};
+
+	#[test]
+	fn valid_prefix_is_replayed() {
+		let guard = SniffGuard::new(PlainTextConfig, 4, |bytes| bytes == b"ping");
+		let addr = "/ip4/127.0.0.1/tcp/1".parse::<Multiaddr>().unwrap();
+		let mut socket = guard.upgrade(Cursor::new(b"ping pong".to_vec()), (),
+									   Endpoint::Listener, &addr).wait().unwrap();
+
+		let mut out = Vec::new();
+		socket.read_to_end(&mut out).unwrap();
+		assert_eq!(out, b"ping pong");
+	}
+
+	#[test]
+	fn invalid_prefix_is_rejected() {
+		let guard = SniffGuard::new(PlainTextConfig, 4, |bytes| bytes == b"ping");
+		let addr = "/ip4/127.0.0.1/tcp/1".parse::<Multiaddr>().unwrap();
+		let mut socket = guard.upgrade(Cursor::new(b"GET / HTTP/1.1".to_vec()), (),
+									   Endpoint::Listener, &addr).wait().unwrap();
+
+		let err = socket.read(&mut [0; 16]).unwrap_err();
+		assert_eq!(ProtocolMismatch::from_io_error(&err).unwrap().received(), b"GET ");
+	}
+}
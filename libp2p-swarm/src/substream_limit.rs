@@ -0,0 +1,492 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `LimitedUpgrade`, which bounds the number of substreams that can be open at the
+//! same time on each connection.
+//!
+//! Without a bound, a single remote can open thousands of substreams on a multiplexed connection
+//! and make us allocate buffers for each of them. Wrapping the muxer upgrade in a
+//! `LimitedUpgrade` produces a `LimitedMuxer`, which counts the substreams that are alive:
+//!
+//! - When the remote opens a substream while the inbound limit is reached, the substream is
+//!   immediately dropped, which resets it, and the callback passed to
+//!   `SubstreamLimits::on_inbound_refused()` is called.
+//! - When we open a substream while the outbound limit is reached, the `OutboundSubstream`
+//!   future produces an error.
+//!
+//! A substream stops counting towards the limit once it has been dropped.
+
+use futures::{Async, Future, Poll};
+use multiaddr::Multiaddr;
+use muxing::StreamMuxer;
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::usize;
+use tokio_io::{AsyncRead, AsyncWrite};
+use transport::{ConnectionUpgrade, Endpoint};
+
+/// Maximum number of substreams of each connection. No limit is applied by default.
+#[derive(Clone)]
+pub struct SubstreamLimits {
+	max_inbound: usize,
+	max_outbound: usize,
+	on_inbound_refused: Option<Arc<Fn(&Multiaddr) + Send + Sync>>,
+}
+
+impl SubstreamLimits {
+	/// Builds a `SubstreamLimits` that doesn't limit anything.
+	#[inline]
+	pub fn new() -> SubstreamLimits {
+		SubstreamLimits {
+			max_inbound: usize::MAX,
+			max_outbound: usize::MAX,
+			on_inbound_refused: None,
+		}
+	}
+
+	/// Sets the maximum number of substreams opened by the remote that can be open at the same
+	/// time.
+	#[inline]
+	pub fn with_max_inbound(mut self, max: usize) -> Self {
+		self.max_inbound = max;
+		self
+	}
+
+	/// Sets the maximum number of substreams opened by us that can be open at the same time.
+	#[inline]
+	pub fn with_max_outbound(mut self, max: usize) -> Self {
+		self.max_outbound = max;
+		self
+	}
+
+	/// Sets a function that is called with the address of the remote every time a substream it
+	/// opened is reset because the inbound limit is reached.
+	#[inline]
+	pub fn on_inbound_refused<F>(mut self, callback: F) -> Self
+		where F: Fn(&Multiaddr) + Send + Sync + 'static
+	{
+		self.on_inbound_refused = Some(Arc::new(callback));
+		self
+	}
+}
+
+impl Default for SubstreamLimits {
+	#[inline]
+	fn default() -> SubstreamLimits {
+		SubstreamLimits::new()
+	}
+}
+
+impl fmt::Debug for SubstreamLimits {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("SubstreamLimits")
+			.field("max_inbound", &self.max_inbound)
+			.field("max_outbound", &self.max_outbound)
+			.finish()
+	}
+}
+
+/// Wraps around a `ConnectionUpgrade` whose output is a `StreamMuxer`, and applies
+/// `SubstreamLimits` on each connection. See the module-level documentation.
+#[derive(Debug, Clone)]
+pub struct LimitedUpgrade<U> {
+	inner: U,
+	limits: SubstreamLimits,
+}
+
+impl<U> LimitedUpgrade<U> {
+	/// Builds a new `LimitedUpgrade`.
+	#[inline]
+	pub fn new(inner: U, limits: SubstreamLimits) -> LimitedUpgrade<U> {
+		LimitedUpgrade {
+			inner: inner,
+			limits: limits,
+		}
+	}
+}
+
+impl<C, U> ConnectionUpgrade<C> for LimitedUpgrade<U>
+	where C: AsyncRead + AsyncWrite,
+		  U: ConnectionUpgrade<C>,
+		  U::Output: StreamMuxer + Clone,
+{
+	type NamesIter = U::NamesIter;
+	type UpgradeIdentifier = U::UpgradeIdentifier;
+	type Output = LimitedMuxer<U::Output>;
+	type Future = LimitedUpgradeFuture<U::Future>;
+
+	#[inline]
+	fn protocol_names(&self) -> Self::NamesIter {
+		self.inner.protocol_names()
+	}
+
+	#[inline]
+	fn upgrade(self, socket: C, id: Self::UpgradeIdentifier, ty: Endpoint,
+			   remote_addr: &Multiaddr) -> Self::Future
+	{
+		LimitedUpgradeFuture {
+			inner: self.inner.upgrade(socket, id, ty, remote_addr),
+			limits: Some(self.limits),
+			remote_addr: Some(remote_addr.clone()),
+		}
+	}
+}
+
+/// Future produced by a `LimitedUpgrade`.
+#[must_use = "futures do nothing unless polled"]
+pub struct LimitedUpgradeFuture<F> {
+	inner: F,
+	limits: Option<SubstreamLimits>,
+	remote_addr: Option<Multiaddr>,
+}
+
+impl<F> Future for LimitedUpgradeFuture<F>
+	where F: Future<Error = IoError>
+{
+	type Item = LimitedMuxer<F::Item>;
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let muxer = try_ready!(self.inner.poll());
+
+		Ok(Async::Ready(LimitedMuxer {
+			inner: muxer,
+			shared: Arc::new(Shared {
+				limits: self.limits.take().expect("future polled after it has finished"),
+				remote_addr: self.remote_addr.take().expect("future polled after it has finished"),
+				num_inbound: AtomicUsize::new(0),
+				num_outbound: AtomicUsize::new(0),
+			}),
+		}))
+	}
+}
+
+/// Wraps around a `StreamMuxer` and limits the number of substreams that are open at the same
+/// time. Produced by a `LimitedUpgrade`.
+pub struct LimitedMuxer<M> {
+	inner: M,
+	shared: Arc<Shared>,
+}
+
+// State shared between a `LimitedMuxer`, its clones and its substreams.
+struct Shared {
+	limits: SubstreamLimits,
+	remote_addr: Multiaddr,
+	num_inbound: AtomicUsize,
+	num_outbound: AtomicUsize,
+}
+
+impl<M> LimitedMuxer<M> {
+	/// Returns the number of substreams opened by the remote that are still alive.
+	#[inline]
+	pub fn num_inbound(&self) -> usize {
+		self.shared.num_inbound.load(Ordering::SeqCst)
+	}
+
+	/// Returns the number of substreams opened by us that are still alive or being opened.
+	#[inline]
+	pub fn num_outbound(&self) -> usize {
+		self.shared.num_outbound.load(Ordering::SeqCst)
+	}
+}
+
+impl<M> Clone for LimitedMuxer<M>
+	where M: Clone
+{
+	#[inline]
+	fn clone(&self) -> Self {
+		LimitedMuxer {
+			inner: self.inner.clone(),
+			shared: self.shared.clone(),
+		}
+	}
+}
+
+impl<M> StreamMuxer for LimitedMuxer<M>
+	where M: StreamMuxer + Clone
+{
+	type Substream = LimitedSubstream<M::Substream>;
+	type InboundSubstream = LimitedInbound<M>;
+	type OutboundSubstream = LimitedOutbound<M::OutboundSubstream>;
+
+	#[inline]
+	fn inbound(self) -> Self::InboundSubstream {
+		LimitedInbound {
+			inner: self.inner.clone().inbound(),
+			muxer: self.inner,
+			shared: self.shared,
+		}
+	}
+
+	fn outbound(self) -> Self::OutboundSubstream {
+		match Slot::reserve(&self.shared, Endpoint::Dialer) {
+			Some(slot) => LimitedOutbound {
+				inner: Some(self.inner.outbound()),
+				slot: Some(slot),
+			},
+			None => LimitedOutbound {
+				inner: None,
+				slot: None,
+			},
+		}
+	}
+
+	#[inline]
+	fn close(&self) -> Poll<(), IoError> {
+		self.inner.close()
+	}
+}
+
+/// Future to the next substream opened by the remote, produced by a `LimitedMuxer`.
+#[must_use = "futures do nothing unless polled"]
+pub struct LimitedInbound<M>
+	where M: StreamMuxer
+{
+	inner: M::InboundSubstream,
+	muxer: M,
+	shared: Arc<Shared>,
+}
+
+impl<M> Future for LimitedInbound<M>
+	where M: StreamMuxer + Clone
+{
+	type Item = LimitedSubstream<M::Substream>;
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		loop {
+			let substream = try_ready!(self.inner.poll());
+
+			if let Some(slot) = Slot::reserve(&self.shared, Endpoint::Listener) {
+				return Ok(Async::Ready(LimitedSubstream {
+					inner: substream,
+					_slot: slot,
+				}));
+			}
+
+			// Dropping the substream resets it, as required by `StreamMuxer::Substream`.
+			drop(substream);
+			if let Some(ref callback) = self.shared.limits.on_inbound_refused {
+				callback(&self.shared.remote_addr);
+			}
+			self.inner = self.muxer.clone().inbound();
+		}
+	}
+}
+
+/// Future to a substream opened by us, produced by a `LimitedMuxer`.
+#[must_use = "futures do nothing unless polled"]
+pub struct LimitedOutbound<F> {
+	// `None` if the limit was reached when the substream was requested.
+	inner: Option<F>,
+	slot: Option<Slot>,
+}
+
+impl<F> Future for LimitedOutbound<F>
+	where F: Future<Error = IoError>
+{
+	type Item = LimitedSubstream<F::Item>;
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let substream = match self.inner {
+			Some(ref mut inner) => try_ready!(inner.poll()),
+			None => {
+				return Err(IoError::new(IoErrorKind::Other,
+										"too many outbound substreams on this connection"));
+			},
+		};
+
+		Ok(Async::Ready(LimitedSubstream {
+			inner: substream,
+			_slot: self.slot.take().expect("future polled after it has finished"),
+		}))
+	}
+}
+
+// Counts a substream towards the limit as long as it is alive.
+struct Slot {
+	shared: Arc<Shared>,
+	endpoint: Endpoint,
+}
+
+impl Slot {
+	// Returns `None` if the limit for `endpoint` is reached.
+	fn reserve(shared: &Arc<Shared>, endpoint: Endpoint) -> Option<Slot> {
+		let (counter, max) = match endpoint {
+			Endpoint::Dialer => (&shared.num_outbound, shared.limits.max_outbound),
+			Endpoint::Listener => (&shared.num_inbound, shared.limits.max_inbound),
+		};
+
+		let mut current = counter.load(Ordering::SeqCst);
+		loop {
+			if current >= max {
+				return None;
+			}
+
+			match counter.compare_exchange(current, current + 1, Ordering::SeqCst,
+										   Ordering::SeqCst) {
+				Ok(_) => break,
+				Err(actual) => current = actual,
+			}
+		}
+
+		Some(Slot {
+			shared: shared.clone(),
+			endpoint: endpoint,
+		})
+	}
+}
+
+impl Drop for Slot {
+	fn drop(&mut self) {
+		let counter = match self.endpoint {
+			Endpoint::Dialer => &self.shared.num_outbound,
+			Endpoint::Listener => &self.shared.num_inbound,
+		};
+
+		counter.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+/// Substream of a `LimitedMuxer`. Stops counting towards the limit when dropped.
+pub struct LimitedSubstream<S> {
+	inner: S,
+	_slot: Slot,
+}
+
+impl<S> Read for LimitedSubstream<S>
+	where S: Read
+{
+	#[inline]
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+		self.inner.read(buf)
+	}
+}
+
+impl<S> AsyncRead for LimitedSubstream<S>
+	where S: AsyncRead
+{
+	#[inline]
+	unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+		self.inner.prepare_uninitialized_buffer(buf)
+	}
+}
+
+impl<S> Write for LimitedSubstream<S>
+	where S: Write
+{
+	#[inline]
+	fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+		self.inner.write(buf)
+	}
+
+	#[inline]
+	fn flush(&mut self) -> Result<(), IoError> {
+		self.inner.flush()
+	}
+}
+
+impl<S> AsyncWrite for LimitedSubstream<S>
+	where S: AsyncWrite
+{
+	#[inline]
+	fn shutdown(&mut self) -> Poll<(), IoError> {
+		self.inner.shutdown()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{LimitedUpgrade, SubstreamLimits};
+	use futures::{future, Future};
+	use multiaddr::Multiaddr;
+	use muxing::StreamMuxer;
+	use std::io::{Cursor, Error as IoError};
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use transport::{ConnectionUpgrade, Endpoint, SimpleProtocol};
+
+	// Muxer whose remote opens `remaining` substreams, and that can always open new ones.
+	#[derive(Clone)]
+	struct DummyMuxer {
+		remaining: Arc<AtomicUsize>,
+	}
+
+	impl StreamMuxer for DummyMuxer {
+		type Substream = Cursor<Vec<u8>>;
+		type InboundSubstream = future::Either<future::FutureResult<Self::Substream, IoError>,
+											   future::Empty<Self::Substream, IoError>>;
+		type OutboundSubstream = future::FutureResult<Self::Substream, IoError>;
+
+		fn inbound(self) -> Self::InboundSubstream {
+			if self.remaining.load(Ordering::SeqCst) == 0 {
+				return future::Either::B(future::empty());
+			}
+
+			self.remaining.fetch_sub(1, Ordering::SeqCst);
+			future::Either::A(future::ok(Cursor::new(Vec::new())))
+		}
+
+		fn outbound(self) -> Self::OutboundSubstream {
+			future::ok(Cursor::new(Vec::new()))
+		}
+	}
+
+	#[test]
+	fn limits_substreams() {
+		let refused = Arc::new(AtomicUsize::new(0));
+		let limits = {
+			let refused = refused.clone();
+			SubstreamLimits::new()
+				.with_max_inbound(2)
+				.with_max_outbound(1)
+				.on_inbound_refused(move |_| { refused.fetch_add(1, Ordering::SeqCst); })
+		};
+
+		let dummy = DummyMuxer { remaining: Arc::new(AtomicUsize::new(3)) };
+		let upgrade = LimitedUpgrade::new(SimpleProtocol::new("/dummy", move |_: Cursor<Vec<u8>>| {
+			Ok::<_, IoError>(dummy.clone())
+		}), limits);
+		let addr = "/ip4/1.2.3.4/tcp/5".parse::<Multiaddr>().unwrap();
+		let muxer = upgrade.upgrade(Cursor::new(Vec::new()), (), Endpoint::Listener, &addr)
+			.wait()
+			.unwrap();
+
+		let first = muxer.clone().inbound().wait().unwrap();
+		let _second = muxer.clone().inbound().wait().unwrap();
+		assert_eq!(muxer.num_inbound(), 2);
+
+		// The third substream is reset, then the remote doesn't open any other.
+		assert!(muxer.clone().inbound().poll().unwrap().is_not_ready());
+		assert_eq!(refused.load(Ordering::SeqCst), 1);
+
+		drop(first);
+		assert_eq!(muxer.num_inbound(), 1);
+
+		let outbound = muxer.clone().outbound().wait().unwrap();
+		assert!(muxer.clone().outbound().wait().is_err());
+		drop(outbound);
+		assert!(muxer.clone().outbound().poll().unwrap().is_ready());
+		assert_eq!(muxer.num_outbound(), 0);
+	}
+}
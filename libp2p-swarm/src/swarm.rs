@@ -19,9 +19,21 @@
 // DEALINGS IN THE SOFTWARE.
 
 use std::io::Error as IoError;
+use std::sync::Arc;
 use futures::{IntoFuture, Future, Stream, Async, Poll, future};
-use futures::sync::mpsc;
-use {ConnectionUpgrade, Multiaddr, MuxedTransport, UpgradedNode};
+use futures::sync::{mpsc, oneshot};
+use futures::task::{self, Task};
+use connection_info::{Connections, UpgradeRecorder};
+use executor::Executor;
+use gater::{AllowAllGater, ConnectionGater, GatedUpgrade};
+use inbound_limit::{InboundLimitUpgrade, Slot, UpgradeSlots};
+use multiaddr::AddrComponent;
+use parking_lot::Mutex;
+use {BanList, ConnectionId, ConnectionInfo, ConnectionUpgrade, DialError, Endpoint, Multiaddr};
+use {MuxedTransport, PeerId, SwarmError};
+use {BoxedDial, BoxedListener, BoxedListenerUpgrade, UnsupportedProtocols, UpgradedNode};
+use transport::{UpgradedNodeDial, UpgradedNodeIncoming, UpgradedNodeListener};
+use transport::UpgradedNodeListenerUpgrade;
 
 /// Creates a swarm.
 ///
@@ -38,32 +50,77 @@ pub fn swarm<T, C, H, F>(transport: T, upgrade: C, handler: H)
           C::NamesIter: Clone,      // TODO: not elegant
           H: FnMut(C::Output, Multiaddr) -> F,
           F: IntoFuture<Item = (), Error = IoError>,
+          UpgradedNodeIncoming<T, C>: Send,
 {
     let (new_dialers_tx, new_dialers_rx) = mpsc::unbounded();
     let (new_listeners_tx, new_listeners_rx) = mpsc::unbounded();
-    let (new_toprocess_tx, new_toprocess_rx) = mpsc::unbounded();
+    let (remove_listeners_tx, remove_listeners_rx) = mpsc::unbounded();
+    let (close_connections_tx, close_connections_rx) = mpsc::unbounded();
+    let (new_custom_dialers_tx, new_custom_dialers_rx) = mpsc::unbounded();
+    let (shutdown_tx, shutdown_rx) = mpsc::unbounded();
+    let (closing_tx, closing_rx) = oneshot::channel();
 
     let upgraded = transport.clone().with_upgrade(upgrade);
+    let events = EventsDispatcher::new();
+    let ban_list = BanList::new();
+    let connections = Connections::new();
+    let gater = Arc::new(Mutex::new(Arc::new(AllowAllGater) as Arc<ConnectionGater>));
+    let executor = Arc::new(Mutex::new(None));
+    let listen_addrs = Arc::new(Mutex::new(Vec::new()));
+    let upgrades_limit = Arc::new(Mutex::new(UpgradesLimit {
+        slots: UpgradeSlots::new(DEFAULT_MAX_LISTENER_UPGRADES),
+        shed_unprotected: false,
+        to_notify: None,
+    }));
 
     let future = SwarmFuture {
         upgraded: upgraded.clone(),
         handler: handler,
         new_listeners: new_listeners_rx,
-        next_incoming: upgraded.clone().next_incoming(),
+        remove_listeners: remove_listeners_rx,
+        close_connections: close_connections_rx,
+        next_incoming: Box::new(upgraded.clone().next_incoming()),
         listeners: Vec::new(),
         listeners_upgrade: Vec::new(),
         dialers: Vec::new(),
         new_dialers: new_dialers_rx,
+        custom_dialers: Vec::new(),
+        new_custom_dialers: new_custom_dialers_rx,
         to_process: Vec::new(),
-        new_toprocess: new_toprocess_rx,
+        shutdown: shutdown_rx,
+        shutdown_requests: Vec::new(),
+        deadline: None,
+        closing: Some(closing_tx),
+        events: events.clone(),
+        ban_list: ban_list.clone(),
+        connections: connections.clone(),
+        gater: gater.clone(),
+        executor: executor.clone(),
+        background: Vec::new(),
+        listen_addrs: listen_addrs.clone(),
+        upgrades_limit: upgrades_limit.clone(),
     };
 
     let controller = SwarmController {
         transport: transport,
         upgraded: upgraded,
         new_listeners: new_listeners_tx,
+        remove_listeners: remove_listeners_tx,
+        close_connections: close_connections_tx,
         new_dialers: new_dialers_tx,
-        new_toprocess: new_toprocess_tx,
+        new_custom_dialers: new_custom_dialers_tx,
+        shutdown: shutdown_tx,
+        closing: closing_rx.shared(),
+        events: events,
+        ban_list: ban_list,
+        connections: connections,
+        gater: gater,
+        executor: executor,
+        listen_addrs: listen_addrs,
+        next_listener_id: Mutex::new(0),
+        external_addrs: Mutex::new(Vec::new()),
+        local_peer_id: Mutex::new(None),
+        upgrades_limit: upgrades_limit,
     };
 
     (controller, future)
@@ -76,11 +133,74 @@ pub struct SwarmController<T, C>
 {
     transport: T,
     upgraded: UpgradedNode<T, C>,
-    new_listeners: mpsc::UnboundedSender<Box<Stream<Item = (Box<Future<Item = C::Output, Error = IoError>>, Multiaddr), Error = IoError>>>,
-    new_dialers: mpsc::UnboundedSender<(Box<Future<Item = C::Output, Error = IoError>>, Multiaddr)>,
-    new_toprocess: mpsc::UnboundedSender<Box<Future<Item = (), Error = IoError>>>,
+    new_listeners: mpsc::UnboundedSender<(BoxedListener<C::Output>, ListenerId, Multiaddr)>,
+    remove_listeners: mpsc::UnboundedSender<ListenerId>,
+    // Connections to close because another connection to the same remote is kept, or because
+    // the remote is banned.
+    close_connections: mpsc::UnboundedSender<ConnectionId>,
+    new_dialers: mpsc::UnboundedSender<(BoxedDial<C::Output>, Multiaddr)>,
+    new_custom_dialers: mpsc::UnboundedSender<(BoxedCustomDial, Multiaddr)>,
+    shutdown: mpsc::UnboundedSender<ShutdownRequest>,
+    // Resolves when a graceful shutdown starts.
+    closing: future::Shared<oneshot::Receiver<()>>,
+    events: EventsDispatcher,
+    ban_list: BanList,
+    connections: Connections,
+    gater: Arc<Mutex<Arc<ConnectionGater>>>,
+    executor: Arc<Mutex<Option<Arc<Executor>>>>,
+    // Addresses of the listeners that are still alive.
+    listen_addrs: Arc<Mutex<Vec<ListenAddr>>>,
+    // Identifier to assign to the next listener.
+    next_listener_id: Mutex<u64>,
+    // Addresses through which remotes can reach us, other than the listen addresses.
+    external_addrs: Mutex<Vec<Multiaddr>>,
+    // Identity of the local node, as it appears in the `/p2p` component of a multiaddr.
+    local_peer_id: Mutex<Option<PeerId>>,
+    upgrades_limit: Arc<Mutex<UpgradesLimit>>,
 }
 
+/// Identifier of a listener of the swarm. Never reused during the lifetime of a swarm.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ListenerId(u64);
+
+// Addresses of a listener that is still alive.
+struct ListenAddr {
+    // Identifier of the listener.
+    id: ListenerId,
+    // Address returned by the transport when we started listening. Can be a wildcard address
+    // such as `/ip4/0.0.0.0/tcp/1234`.
+    bound: Multiaddr,
+    // Concrete addresses through which the listener can be reached, as returned by
+    // `Transport::expand_listen_addr`.
+    reachable: Vec<Multiaddr>,
+}
+
+/// Default value for the maximum number of incoming connections that are upgraded at the same
+/// time. See `SwarmController::set_max_listener_upgrades()`.
+pub const DEFAULT_MAX_LISTENER_UPGRADES: usize = 64;
+
+// Shared between the controller and the future.
+struct UpgradesLimit {
+    // Slots of the incoming connections that are being upgraded. This is the same mechanism as
+    // `InboundLimit`, so that the swarm applies a single limit.
+    slots: Arc<UpgradeSlots>,
+    // If true, the listeners are still polled when `max` is reached, and the connections that the
+    // gater doesn't protect are rejected instead of waiting in the backlog.
+    shed_unprotected: bool,
+    // Task to notify when `max` is modified, so that the listeners get polled again.
+    to_notify: Option<Task>,
+}
+
+// Request to shut the swarm down, with the future after which a graceful shutdown gives up on
+// the connections that are still open. `None` for an immediate shutdown.
+type ShutdownRequest = (oneshot::Sender<()>, Option<ShutdownDeadline>);
+type ShutdownDeadline = Box<Future<Item = (), Error = IoError> + Send>;
+
+// Processing of a connection opened with `dial_custom_handler`.
+type BoxedProcess = Box<Future<Item = (), Error = IoError> + Send>;
+// Dial of `dial_custom_handler`, which produces the processing of the connection once upgraded.
+type BoxedCustomDial = Box<Future<Item = BoxedProcess, Error = IoError> + Send>;
+
 impl<T, C> SwarmController<T, C>
     where T: MuxedTransport + Clone + 'static,      // TODO: 'static :-/
           C: ConnectionUpgrade<T::RawConn> + Clone + 'static,      // TODO: 'static :-/
@@ -89,21 +209,27 @@ impl<T, C> SwarmController<T, C>
     /// Asks the swarm to dial the node with the given multiaddress. The connection is then
     /// upgraded using the `upgrade`, and the output is sent to the handler that was passed when
     /// calling `swarm`.
+    ///
+    /// Returns an error if the multiaddress isn't supported, if it is banned, or if it is one of
+    /// our own addresses.
     // TODO: consider returning a future so that errors can be processed?
-    pub fn dial_to_handler<Du>(&self, multiaddr: Multiaddr, upgrade: Du) -> Result<(), Multiaddr>
+    pub fn dial_to_handler<Du>(&self, multiaddr: Multiaddr, upgrade: Du) -> Result<(), DialError>
         where Du: ConnectionUpgrade<T::RawConn> + Clone + 'static,      // TODO: 'static :-/
               Du::Output: Into<C::Output>,
+              UpgradedNodeDial<T, Du>: Send,
     {
+        let multiaddr = self.check_dial(multiaddr)?;
+
         match self.transport.clone().with_upgrade(upgrade).dial(multiaddr.clone()) {
             Ok(dial) => {
-                let dial = Box::new(dial.map(Into::into)) as Box<Future<Item = _, Error = _>>;
+                let dial = Box::new(dial.map(Into::into)) as BoxedDial<_>;
                 // Ignoring errors if the receiver has been closed, because in that situation
                 // nothing is going to be processed anyway.
                 let _ = self.new_dialers.unbounded_send((dial, multiaddr));
                 Ok(())
             },
             Err((_, multiaddr)) => {
-                Err(multiaddr)
+                Err(DialError::Unsupported(multiaddr))
             },
         }
     }
@@ -112,43 +238,674 @@ impl<T, C> SwarmController<T, C>
     /// upgraded using the `upgrade`, and the output is then passed to `and_then`.
     ///
     /// Contrary to `dial_to_handler`, the output of the upgrade is not given to the handler that
-    /// was passed at initialization.
+    /// was passed at initialization. The connection is otherwise treated like the other ones: it
+    /// produces a `ConnectionEstablished` or a `DialFailed` event, and appears in `connections()`
+    /// until `and_then` has finished.
+    ///
+    /// Returns an error if the multiaddress isn't supported, if it is banned, or if it is one of
+    /// our own addresses.
     // TODO: consider returning a future so that errors can be processed?
     pub fn dial_custom_handler<Du, Df, Dfu>(&self, multiaddr: Multiaddr, upgrade: Du, and_then: Df)
-                                            -> Result<(), Multiaddr>
+                                            -> Result<(), DialError>
         where Du: ConnectionUpgrade<T::RawConn> + 'static,      // TODO: 'static :-/
-              Df: FnOnce(Du::Output) -> Dfu + 'static,          // TODO: 'static :-/
+              Df: FnOnce(Du::Output) -> Dfu + Send + 'static,          // TODO: 'static :-/
               Dfu: IntoFuture<Item = (), Error = IoError> + 'static,        // TODO: 'static :-/
+              Dfu::Future: Send + 'static,        // TODO: 'static :-/
+              UpgradedNodeDial<T, Du>: Send,
     {
-        match self.transport.clone().with_upgrade(upgrade).dial(multiaddr) {
+        let multiaddr = self.check_dial(multiaddr)?;
+
+        match self.transport.clone().with_upgrade(upgrade).dial(multiaddr.clone()) {
             Ok(dial) => {
-                let dial = Box::new(dial.and_then(and_then)) as Box<_>;
+                let dial = dial.map(move |output| {
+                    Box::new(and_then(output).into_future()) as BoxedProcess
+                });
                 // Ignoring errors if the receiver has been closed, because in that situation
                 // nothing is going to be processed anyway.
-                let _ = self.new_toprocess.unbounded_send(dial);
+                let _ = self.new_custom_dialers.unbounded_send((Box::new(dial), multiaddr));
                 Ok(())
             },
             Err((_, multiaddr)) => {
-                Err(multiaddr)
+                Err(DialError::Unsupported(multiaddr))
             },
         }
     }
 
+    // Checks whether we are allowed to dial `multiaddr`. Called before each dial.
+    fn check_dial(&self, multiaddr: Multiaddr) -> Result<Multiaddr, DialError> {
+        if !self.ban_list.is_allowed(&multiaddr) {
+            return Err(DialError::Banned(multiaddr));
+        }
+
+        if self.is_self_addr(&multiaddr) {
+            return Err(DialError::SelfDial(multiaddr));
+        }
+
+        if !self.gater.lock().allow_dial(&multiaddr) {
+            return Err(DialError::Denied(multiaddr));
+        }
+
+        Ok(multiaddr)
+    }
+
     /// Adds a multiaddr to listen on. All the incoming connections will use the `upgrade` that
     /// was passed to `swarm`.
-    pub fn listen_on(&self, multiaddr: Multiaddr) -> Result<Multiaddr, Multiaddr> {
-        match self.upgraded.clone().listen_on(multiaddr) {
+    ///
+    /// Can be called at any time, including while the swarm is running. Returns the identifier
+    /// of the new listener, which can be passed to `remove_listener`, and the address the
+    /// transport is listening on. If it is a wildcard address, such as `/ip4/0.0.0.0/tcp/0`, a
+    /// `NewListenAddr` event is produced for each of the concrete addresses it expands to instead
+    /// of the wildcard address itself.
+    pub fn listen_on(&self, multiaddr: Multiaddr) -> Result<(ListenerId, Multiaddr), Multiaddr>
+        where UpgradedNodeListener<T, C>: Send,
+              UpgradedNodeListenerUpgrade<T, C>: Send,
+    {
+        self.listen_on_upgraded(self.upgraded.clone(), multiaddr)
+    }
+
+    /// Same as `listen_on`, except that the incoming connections of this listener are upgraded
+    /// with `upgrade` instead of the upgrade that was passed to `swarm`. The output is then sent
+    /// to the handler like for the other listeners.
+    ///
+    /// This makes it possible to offer different protocols on different listeners. For example,
+    /// a WebSocket listener can offer only the protocols that browsers support, while a TCP
+    /// listener also offers protocols for legacy nodes.
+    pub fn listen_on_with_upgrade<Lu>(&self, multiaddr: Multiaddr, upgrade: Lu)
+                                      -> Result<(ListenerId, Multiaddr), Multiaddr>
+        where Lu: ConnectionUpgrade<T::RawConn> + Clone + 'static,      // TODO: 'static :-/
+              Lu::NamesIter: Clone,     // TODO: not elegant
+              Lu::Output: Into<C::Output>,
+              UpgradedNodeListener<T, Lu>: Send,
+              UpgradedNodeListenerUpgrade<T, Lu>: Send,
+    {
+        self.listen_on_upgraded(self.upgraded.clone().replace_upgrade(upgrade), multiaddr)
+    }
+
+    // Starts listening on `multiaddr` with the given upgraded transport, and sends the listener
+    // to the swarm future.
+    fn listen_on_upgraded<Lu>(&self, upgraded: UpgradedNode<T, Lu>, multiaddr: Multiaddr)
+                              -> Result<(ListenerId, Multiaddr), Multiaddr>
+        where Lu: ConnectionUpgrade<T::RawConn> + Clone + 'static,      // TODO: 'static :-/
+              Lu::NamesIter: Clone,     // TODO: not elegant
+              Lu::Output: Into<C::Output>,
+              UpgradedNodeListener<T, Lu>: Send,
+              UpgradedNodeListenerUpgrade<T, Lu>: Send,
+    {
+        match upgraded.listen_on(multiaddr) {
             Ok((listener, new_addr)) => {
+                let listener = Box::new(listener.map(|(upgrade, addr)| {
+                    let upgrade = upgrade.map(Into::<C::Output>::into);
+                    (Box::new(upgrade) as BoxedListenerUpgrade<_>, addr)
+                })) as BoxedListener<_>;
+                let id = {
+                    let mut next_listener_id = self.next_listener_id.lock();
+                    let id = ListenerId(*next_listener_id);
+                    *next_listener_id += 1;
+                    id
+                };
+
+                // The address must be registered before the listener is sent, as the swarm
+                // future drops the listeners that aren't registered (see `remove_listener`).
+                let reachable = self.transport.expand_listen_addr(&new_addr);
+                self.listen_addrs.lock().push(ListenAddr {
+                    id: id,
+                    bound: new_addr.clone(),
+                    reachable: reachable.clone(),
+                });
+                for addr in reachable {
+                    self.events.dispatch(SwarmEvent::NewListenAddr { addr: addr });
+                }
+
                 // Ignoring errors if the receiver has been closed, because in that situation
                 // nothing is going to be processed anyway.
-                let _ = self.new_listeners.unbounded_send(listener);
-                Ok(new_addr)
+                let _ = self.new_listeners.unbounded_send((listener, id, new_addr.clone()));
+                Ok((id, new_addr))
             },
             Err((_, multiaddr)) => {
                 Err(multiaddr)
             },
         }
     }
+
+    /// Stops the listener with the given identifier. Its addresses are immediately removed from
+    /// `listen_addrs`, and an `ExpiredListenAddr` for each of them followed with a
+    /// `ListenerClosed` are produced. The connections that have been accepted by this listener
+    /// are not affected.
+    ///
+    /// Returns false if the listener doesn't exist, for example because it has already been
+    /// closed.
+    pub fn remove_listener(&self, id: ListenerId) -> bool {
+        if !listener_closed(&self.listen_addrs, &self.events, id, None) {
+            return false;
+        }
+
+        // Ignoring errors if the receiver has been closed, because in that situation the
+        // listener has been destroyed anyway.
+        let _ = self.remove_listeners.unbounded_send(id);
+        true
+    }
+
+    /// Returns the identifiers of the listeners that are alive, and the address each of them is
+    /// listening on as returned by `listen_on`.
+    pub fn listeners(&self) -> Vec<(ListenerId, Multiaddr)> {
+        self.listen_addrs.lock().iter().map(|a| (a.id, a.bound.clone())).collect()
+    }
+
+    /// Returns the list of addresses through which the swarm can be reached. Wildcard listen
+    /// addresses are replaced with the concrete addresses they expand to.
+    pub fn listen_addrs(&self) -> Vec<Multiaddr> {
+        let mut out = Vec::new();
+        for listen_addr in self.listen_addrs.lock().iter() {
+            for addr in listen_addr.reachable.iter() {
+                if !out.contains(addr) {
+                    out.push(addr.clone());
+                }
+            }
+        }
+        out
+    }
+
+    /// Expands the wildcard listen addresses again, and produces a `NewListenAddr` or an
+    /// `ExpiredListenAddr` event for each concrete address that appeared or disappeared since
+    /// the previous expansion.
+    ///
+    /// The network interfaces of the machine can change over time, but the swarm doesn't watch
+    /// them by itself. This method should therefore be called periodically, or whenever the
+    /// operating system reports a change.
+    pub fn refresh_listen_addrs(&self) {
+        for listen_addr in self.listen_addrs.lock().iter_mut() {
+            let reachable = self.transport.expand_listen_addr(&listen_addr.bound);
+
+            for addr in listen_addr.reachable.iter() {
+                if !reachable.contains(addr) {
+                    self.events.dispatch(SwarmEvent::ExpiredListenAddr { addr: addr.clone() });
+                }
+            }
+
+            for addr in reachable.iter() {
+                if !listen_addr.reachable.contains(addr) {
+                    self.events.dispatch(SwarmEvent::NewListenAddr { addr: addr.clone() });
+                }
+            }
+
+            listen_addr.reachable = reachable;
+        }
+    }
+
+    /// Translates an address of ours that has been observed by a remote (for example through the
+    /// identify protocol) into the addresses that we should advertise.
+    ///
+    /// When we are behind a NAT, the observed address usually has the right IP address but the
+    /// wrong port. Each listen address is combined with the observed address by the transport,
+    /// and the list of results is returned, without duplicates.
+    pub fn nat_traversal(&self, observed: &Multiaddr) -> Vec<Multiaddr> {
+        let mut out = Vec::new();
+        for listen_addr in self.listen_addrs.lock().iter() {
+            if let Some(addr) = self.transport.nat_traversal(&listen_addr.bound, observed) {
+                if !out.contains(&addr) {
+                    out.push(addr);
+                }
+            }
+        }
+        out
+    }
+
+    /// Adds an address through which remotes can reach us, for example one that was returned by
+    /// `nat_traversal` and then verified. Dialing this address is refused afterwards.
+    pub fn add_external_addr(&self, addr: Multiaddr) {
+        let mut external_addrs = self.external_addrs.lock();
+        if !external_addrs.contains(&addr) {
+            external_addrs.push(addr);
+        }
+    }
+
+    /// Returns the list of addresses that have been passed to `add_external_addr`.
+    #[inline]
+    pub fn external_addrs(&self) -> Vec<Multiaddr> {
+        self.external_addrs.lock().clone()
+    }
+
+    /// Sets the identity of the local node, as it appears in the `/p2p` component of a
+    /// multiaddress.
+    ///
+    /// The swarm itself doesn't know who we are, as the identity is only handled by upgrades such
+    /// as secio. Once it is set, dialing a multiaddress that contains our own identity is
+    /// refused, and only one connection to each remote is kept. See `update_connection()`.
+    #[inline]
+    pub fn set_local_peer_id(&self, peer_id: PeerId) {
+        self.connections.set_local_peer_id(peer_id.clone());
+        *self.local_peer_id.lock() = Some(peer_id);
+    }
+
+    /// Returns true if dialing `addr` would connect us to ourselves, either because it contains
+    /// our own identity or because it is one of our listen or external addresses.
+    ///
+    /// The `/p2p` components of `addr` are ignored when comparing it to our own addresses.
+    pub fn is_self_addr(&self, addr: &Multiaddr) -> bool {
+        let local_peer_id = self.local_peer_id.lock();
+        let mut without_peer_id = Vec::new();
+
+        for component in addr.iter() {
+            match component {
+                AddrComponent::P2P(ref id) | AddrComponent::IPFS(ref id) => {
+                    if local_peer_id.as_ref().map(|p| p.as_bytes()) == Some(&id[..]) {
+                        return true;
+                    }
+                },
+                other => without_peer_id.push(other),
+            }
+        }
+
+        let without_peer_id = without_peer_id.into_iter().collect::<Multiaddr>();
+        let is_listen_addr = self.listen_addrs.lock().iter().any(|listen_addr| {
+            listen_addr.bound == without_peer_id || listen_addr.reachable.contains(&without_peer_id)
+        });
+        is_listen_addr || self.external_addrs.lock().contains(&without_peer_id)
+    }
+
+    /// Sets the maximum number of incoming connections that can be upgraded at the same time.
+    ///
+    /// When the limit is reached, the swarm stops accepting connections until one of the upgrades
+    /// in progress finishes. In the meantime, the new connections wait in the backlog of the
+    /// listeners. This prevents a burst of incoming connections from spawning an unbounded number
+    /// of handshakes. The default value is `DEFAULT_MAX_LISTENER_UPGRADES`.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `max` is 0.
+    pub fn set_max_listener_upgrades(&self, max: usize) {
+        assert_ne!(max, 0, "the maximum number of listener upgrades must not be zero");
+
+        let mut limit = self.upgrades_limit.lock();
+        limit.slots.set_max(max);
+        if let Some(task) = limit.to_notify.take() {
+            task.notify();
+        }
+    }
+
+    /// Returns the maximum number of incoming connections that can be upgraded at the same time.
+    #[inline]
+    pub fn max_listener_upgrades(&self) -> usize {
+        self.upgrades_limit.lock().slots.max()
+    }
+
+    /// Sets whether the swarm sheds the incoming connections that are not protected when the
+    /// maximum number of listener upgrades is reached. Disabled by default.
+    ///
+    /// When enabled, the swarm keeps accepting connections while the limit is reached, instead of
+    /// leaving them in the backlog of the listeners. The connections for which
+    /// `ConnectionGater::is_protected()` returns true are upgraded regardless of the limit, and
+    /// the other ones are closed immediately and reported with a `SwarmEvent::IncomingShed`. This
+    /// prevents a flood of connections from unknown sources from delaying the important peers.
+    pub fn set_shed_under_pressure(&self, shed: bool) {
+        let mut limit = self.upgrades_limit.lock();
+        limit.shed_unprotected = shed;
+        if let Some(task) = limit.to_notify.take() {
+            task.notify();
+        }
+    }
+
+    /// Returns a stream of the events that happen in the swarm from now on.
+    ///
+    /// Each call to this method creates a new independent subscription. Dropping the returned
+    /// stream automatically unsubscribes.
+    #[inline]
+    pub fn events(&self) -> SwarmEvents {
+        self.events.subscribe()
+    }
+
+    /// Returns the list of banned and allowed multiaddresses and peers of the swarm.
+    ///
+    /// Banning an address refuses dialing it, drops incoming connections from it, and closes the
+    /// existing connections with it. Banning a peer closes the existing connections whose identity
+    /// has been recorded with `update_connection()` and refuses dialing the multiaddresses that
+    /// contain its identity.
+    #[inline]
+    pub fn ban_list(&self) -> &BanList {
+        &self.ban_list
+    }
+
+    /// Sets the `ConnectionGater` that decides which connections the swarm is allowed to open or
+    /// accept. Only applies to the dials and to the incoming connections that happen afterwards.
+    #[inline]
+    pub fn set_connection_gater<G>(&self, gater: G)
+        where G: ConnectionGater + 'static
+    {
+        *self.gater.lock() = Arc::new(gater);
+    }
+
+    /// Sets the `Executor` on which the connections are processed, instead of processing them
+    /// inside of the `SwarmFuture`.
+    ///
+    /// The connections are spawned on the executor once they have been upgraded and passed to
+    /// the handler, which is why the futures returned by the handler must be `Send`. Banning
+    /// their address or shutting down the swarm still closes them, and destroying the
+    /// `SwarmFuture` closes them as well.
+    #[inline]
+    pub fn set_executor<E>(&self, executor: E)
+        where E: Executor + 'static
+    {
+        *self.executor.lock() = Some(Arc::new(executor));
+    }
+
+    /// Returns the `ConnectionGater` of the swarm. By default, an `AllowAllGater`.
+    #[inline]
+    pub fn connection_gater(&self) -> Arc<ConnectionGater> {
+        self.gater.lock().clone()
+    }
+
+    /// Wraps `upgrade` so that the `ConnectionGater` of the swarm is consulted once the identity
+    /// of the remote is known. `peer_id` extracts this identity from the output of the upgrade.
+    ///
+    /// The gater is the one at the time of the call.
+    #[inline]
+    pub fn gate_upgrade<U, F>(&self, upgrade: U, peer_id: F) -> GatedUpgrade<U, F> {
+        GatedUpgrade::new(upgrade, self.connection_gater(), peer_id)
+    }
+
+    /// Makes the swarm fill the `ConnectionInfo` of the new connections with what `recorder` has
+    /// recorded about their remote address: the identity of the remote and the negotiated
+    /// security and muxer protocols. The upgrades of the transport must have been wrapped with
+    /// `UpgradeRecorder::record_upgrade()`.
+    ///
+    /// The connections whose recorded identity is banned are closed. Unlike with
+    /// `update_connection()`, the other connections to the same remote are kept, since they may
+    /// be substreams of the same muxed connection. Calling `update_connection()` registers the
+    /// connection as the one kept for the remote.
+    #[inline]
+    pub fn set_upgrade_recorder(&self, recorder: UpgradeRecorder) {
+        self.connections.set_recorder(recorder);
+    }
+
+    /// Returns the information about all the connections that are currently open, ordered by
+    /// identifier.
+    #[inline]
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        self.connections.list()
+    }
+
+    /// Returns the information about a connection, or `None` if it has been closed.
+    #[inline]
+    pub fn connection(&self, id: ConnectionId) -> Option<ConnectionInfo> {
+        self.connections.get(id)
+    }
+
+    /// Calls `update` with the information about a connection, so that the identity of the
+    /// remote and the negotiated protocols can be recorded. Returns false if the connection has
+    /// been closed.
+    ///
+    /// If the identity of the remote has been banned with `BanList::ban_peer()`, the connection
+    /// is closed and false is returned.
+    ///
+    /// The identifier of a connection is found in the `SwarmEvent::ConnectionEstablished` event.
+    ///
+    /// If the local identity has been set with `set_local_peer_id()` and another connection to
+    /// the same remote is open, for example because both nodes dialed each other at the same
+    /// time, one of the two connections is closed. Both nodes close the same one, see
+    /// `PeerConnections`. The connection that is kept is returned by `peer_connection()`.
+    pub fn update_connection<F>(&self, id: ConnectionId, update: F) -> bool
+        where F: FnOnce(&mut ConnectionInfo)
+    {
+        if !self.connections.update(id, update) {
+            return false;
+        }
+
+        // The connections to banned peers are closed as soon as their identity is known.
+        let banned = self.connections.remote_peer_id(id)
+            .map_or(false, |peer_id| self.ban_list.is_peer_banned(&peer_id));
+        if banned {
+            // Ignoring errors if the swarm future has been destroyed.
+            let _ = self.close_connections.unbounded_send(id);
+            return false;
+        }
+
+        if let Some(duplicate) = self.connections.register_peer(id) {
+            // Ignoring errors if the swarm future has been destroyed.
+            let _ = self.close_connections.unbounded_send(duplicate);
+        }
+        true
+    }
+
+    /// Returns the connection that is kept for the remote `peer_id`, on which the new substreams
+    /// to this remote should be opened. See `update_connection()`.
+    ///
+    /// Always `None` as long as the local identity hasn't been set with `set_local_peer_id()`.
+    #[inline]
+    pub fn peer_connection(&self, peer_id: &PeerId) -> Option<ConnectionId> {
+        self.connections.peer_connection(peer_id)
+    }
+
+    /// Returns the number of times remotes have requested each protocol that the `upgrade`
+    /// doesn't support.
+    #[inline]
+    pub fn unsupported_protocols(&self) -> &UnsupportedProtocols {
+        self.upgraded.unsupported_protocols()
+    }
+
+    /// Returns the names of the protocols supported by the `upgrade`, to report through
+    /// identify. See `UpgradedNode::supported_protocols()`.
+    #[inline]
+    pub fn supported_protocols(&self) -> Vec<String> {
+        self.upgraded.supported_protocols()
+    }
+
+    /// Asks the swarm to shut down.
+    ///
+    /// The swarm stops accepting incoming connections, aborts all pending dials and closes all
+    /// the open connections. Afterwards, the `SwarmFuture` resolves to `Ok(())`.
+    ///
+    /// Returns a future that resolves once everything has been torn down.
+    pub fn shutdown(&self) -> SwarmShutdown {
+        self.request_shutdown(None)
+    }
+
+    /// Asks the swarm to shut down gracefully.
+    ///
+    /// Like `shutdown()`, the swarm stops accepting incoming connections and aborts all pending
+    /// dials. The open connections are however given a chance to close themselves: the futures
+    /// returned by `closing()` resolve, which makes the `HandledNode`s built with `shutdown_on()`
+    /// shut their handlers down and close their muxer once the substreams have been flushed.
+    ///
+    /// The swarm waits until all the connections have finished, or until `deadline` resolves or
+    /// fails, after which the remaining connections are closed like with `shutdown()`. The
+    /// `deadline` is typically a timer.
+    ///
+    /// Returns a future that resolves once everything has been torn down.
+    pub fn shutdown_graceful<D>(&self, deadline: D) -> SwarmShutdown
+        where D: Future<Item = (), Error = IoError> + Send + 'static
+    {
+        self.request_shutdown(Some(Box::new(deadline)))
+    }
+
+    /// Returns a future that resolves when the swarm starts shutting down gracefully, or when
+    /// the swarm is destroyed. The connections can use it to close themselves, for example with
+    /// `HandledNode::shutdown_on()`.
+    #[inline]
+    pub fn closing(&self) -> SwarmClosing {
+        SwarmClosing { inner: self.closing.clone() }
+    }
+
+    fn request_shutdown(&self, deadline: Option<ShutdownDeadline>) -> SwarmShutdown {
+        let (tx, rx) = oneshot::channel();
+        // Ignoring errors if the receiver has been closed, because in that situation the swarm
+        // has already been destroyed and the returned future will resolve immediately.
+        let _ = self.shutdown.unbounded_send((tx, deadline));
+        SwarmShutdown { inner: rx }
+    }
+}
+
+/// Future that resolves when the swarm starts shutting down gracefully. Returned by
+/// `SwarmController::closing()`.
+#[derive(Clone)]
+pub struct SwarmClosing {
+    inner: future::Shared<oneshot::Receiver<()>>,
+}
+
+impl Future for SwarmClosing {
+    type Item = ();
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // An error means that the `SwarmFuture` has been destroyed, which also closes the
+            // connections.
+            Ok(Async::Ready(_)) | Err(_) => Ok(Async::Ready(())),
+        }
+    }
+}
+
+/// Future that resolves once the swarm has been shut down. Returned by
+/// `SwarmController::shutdown()`.
+pub struct SwarmShutdown {
+    inner: oneshot::Receiver<()>,
+}
+
+impl Future for SwarmShutdown {
+    type Item = ();
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // An error means that the `SwarmFuture` has been destroyed, which also counts as
+            // having been shut down.
+            Ok(Async::Ready(())) | Err(_) => Ok(Async::Ready(())),
+        }
+    }
+}
+
+/// Stream of events produced by the swarm. Returned by `SwarmController::events()`.
+pub type SwarmEvents = mpsc::UnboundedReceiver<SwarmEvent>;
+
+/// Event that happened in the swarm.
+///
+/// Errors are wrapped in an `Arc` because the same event is delivered to every subscriber. The
+/// `SwarmError` makes it possible to distinguish, for example, a failure to negotiate a protocol
+/// from an I/O error.
+#[derive(Debug, Clone)]
+pub enum SwarmEvent {
+    /// We started listening on a new multiaddress, or a new network interface appeared for a
+    /// wildcard listen address.
+    NewListenAddr {
+        /// The address we are now listening on.
+        addr: Multiaddr,
+    },
+
+    /// We are not listening on a multiaddress anymore, either because its listener has closed or
+    /// because the network interface went away.
+    ExpiredListenAddr {
+        /// The address we were listening on.
+        addr: Multiaddr,
+    },
+
+    /// A listener produced an error. The listener is then closed, and an `ExpiredListenAddr` for
+    /// each of its addresses and a `ListenerClosed` are produced.
+    ListenerError {
+        /// Identifier of the listener.
+        id: ListenerId,
+        /// The address that the listener was listening on.
+        addr: Multiaddr,
+        /// The error that happened, for example because the network interface went down.
+        error: Arc<SwarmError>,
+    },
+
+    /// A listener has stopped producing incoming connections, or has been removed with
+    /// `remove_listener`.
+    ListenerClosed {
+        /// Identifier of the listener.
+        id: ListenerId,
+        /// The address that the listener was listening on, as returned by `listen_on`.
+        addr: Multiaddr,
+        /// The error that closed the listener, or `None` if it closed gracefully.
+        error: Option<Arc<SwarmError>>,
+    },
+
+    /// A connection has been opened and successfully upgraded.
+    ConnectionEstablished {
+        /// Identifier of the connection. See `SwarmController::connection()`.
+        id: ConnectionId,
+        /// Address of the remote.
+        addr: Multiaddr,
+        /// Whether we dialed the remote or the remote dialed us.
+        endpoint: Endpoint,
+    },
+
+    /// The processing of a connection has finished.
+    ConnectionClosed {
+        /// Identifier of the connection.
+        id: ConnectionId,
+        /// Address of the remote.
+        addr: Multiaddr,
+        /// The error that closed the connection, or `None` if it closed gracefully.
+        error: Option<Arc<SwarmError>>,
+    },
+
+    /// A remote opened a substream on an existing connection, which has been upgraded and passed
+    /// to the handler, as produced by `MuxedTransport::next_incoming`. The substream isn't
+    /// registered as a connection of its own.
+    IncomingSubstream {
+        /// Address of the remote.
+        addr: Multiaddr,
+    },
+
+    /// The processing of a substream reported by `IncomingSubstream` has finished.
+    IncomingSubstreamClosed {
+        /// Address of the remote.
+        addr: Multiaddr,
+        /// The error that closed the substream, or `None` if it closed gracefully.
+        error: Option<Arc<SwarmError>>,
+    },
+
+    /// Failed to dial or to upgrade a connection that we dialed.
+    DialFailed {
+        /// Address that we tried to dial.
+        addr: Multiaddr,
+        /// The error that happened.
+        error: Arc<SwarmError>,
+    },
+
+    /// Failed to upgrade an incoming connection.
+    UpgradeFailed {
+        /// Address of the remote.
+        addr: Multiaddr,
+        /// The error that happened.
+        error: Arc<SwarmError>,
+    },
+
+    /// An incoming connection has been closed without being upgraded, because the maximum number
+    /// of listener upgrades was reached and the remote isn't protected. Only happens if
+    /// `SwarmController::set_shed_under_pressure()` has been enabled.
+    IncomingShed {
+        /// Address of the remote.
+        addr: Multiaddr,
+        /// Address of the listener that accepted the connection.
+        local_addr: Multiaddr,
+    },
+}
+
+/// Sends events to all the subscribers of the swarm. Shared between the controller and the future.
+#[derive(Clone)]
+struct EventsDispatcher {
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<SwarmEvent>>>>,
+}
+
+impl EventsDispatcher {
+    #[inline]
+    fn new() -> EventsDispatcher {
+        EventsDispatcher { subscribers: Arc::new(Mutex::new(Vec::new())) }
+    }
+
+    fn subscribe(&self) -> SwarmEvents {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().push(tx);
+        rx
+    }
+
+    fn dispatch(&self, event: SwarmEvent) {
+        // Subscribers whose receiver has been dropped are removed from the list.
+        self.subscribers.lock().retain(|sub| sub.unbounded_send(event.clone()).is_ok());
+    }
 }
 
 /// Future that must be driven to completion in order for the swarm to work.
@@ -158,14 +915,38 @@ pub struct SwarmFuture<T, C, H, F>
 {
     upgraded: UpgradedNode<T, C>,
     handler: H,
-    new_listeners: mpsc::UnboundedReceiver<Box<Stream<Item = (Box<Future<Item = C::Output, Error = IoError>>, Multiaddr), Error = IoError>>>,
-    next_incoming: Box<Future<Item = (C::Output, Multiaddr), Error = IoError>>,
-    listeners: Vec<Box<Stream<Item = (Box<Future<Item = C::Output, Error = IoError>>, Multiaddr), Error = IoError>>>,
-    listeners_upgrade: Vec<(Box<Future<Item = C::Output, Error = IoError>>, Multiaddr)>,
-    dialers: Vec<(Box<Future<Item = C::Output, Error = IoError>>, Multiaddr)>,
-    new_dialers: mpsc::UnboundedReceiver<(Box<Future<Item = C::Output, Error = IoError>>, Multiaddr)>,
-    to_process: Vec<future::Either<F, Box<Future<Item = (), Error = IoError>>>>,
-    new_toprocess: mpsc::UnboundedReceiver<Box<Future<Item = (), Error = IoError>>>,
+    new_listeners: mpsc::UnboundedReceiver<(BoxedListener<C::Output>, ListenerId, Multiaddr)>,
+    remove_listeners: mpsc::UnboundedReceiver<ListenerId>,
+    close_connections: mpsc::UnboundedReceiver<ConnectionId>,
+    next_incoming: Box<Future<Item = (C::Output, Multiaddr), Error = IoError> + Send>,
+    listeners: Vec<(BoxedListener<C::Output>, ListenerId, Multiaddr)>,
+    // Upgrades in progress, with the address of the remote and the address of the listener.
+    listeners_upgrade: Vec<(InboundLimitUpgrade<BoxedListenerUpgrade<C::Output>>, Multiaddr,
+                            Multiaddr)>,
+    dialers: Vec<(BoxedDial<C::Output>, Multiaddr)>,
+    new_dialers: mpsc::UnboundedReceiver<(BoxedDial<C::Output>, Multiaddr)>,
+    // Dials of `dial_custom_handler` in progress.
+    custom_dialers: Vec<(BoxedCustomDial, Multiaddr)>,
+    new_custom_dialers: mpsc::UnboundedReceiver<(BoxedCustomDial, Multiaddr)>,
+    // Connections and incoming substreams being processed.
+    to_process: Vec<(future::Either<F, BoxedProcess>, Multiaddr, Processing)>,
+    shutdown: mpsc::UnboundedReceiver<ShutdownRequest>,
+    // Shutdown requests waiting for the end of a graceful shutdown.
+    shutdown_requests: Vec<oneshot::Sender<()>>,
+    // If a graceful shutdown is in progress, the future after which it gives up.
+    deadline: Option<ShutdownDeadline>,
+    // Signals the start of a graceful shutdown to the `SwarmClosing`s.
+    closing: Option<oneshot::Sender<()>>,
+    events: EventsDispatcher,
+    ban_list: BanList,
+    connections: Connections,
+    gater: Arc<Mutex<Arc<ConnectionGater>>>,
+    executor: Arc<Mutex<Option<Arc<Executor>>>>,
+    // Connections that have been spawned on the executor, with the sender that aborts them.
+    background: Vec<(oneshot::Sender<()>, Multiaddr, Processing)>,
+    // Addresses of the listeners that are still alive.
+    listen_addrs: Arc<Mutex<Vec<ListenAddr>>>,
+    upgrades_limit: Arc<Mutex<UpgradesLimit>>,
 }
 
 impl<T, C, H, If, F> Future for SwarmFuture<T, C, H, F>
@@ -174,27 +955,80 @@ impl<T, C, H, If, F> Future for SwarmFuture<T, C, H, F>
           C::NamesIter: Clone,      // TODO: not elegant
           H: FnMut(C::Output, Multiaddr) -> If,
           If: IntoFuture<Future = F, Item = (), Error = IoError>,
-          F: Future<Item = (), Error = IoError>,
+          F: Future<Item = (), Error = IoError> + Send + 'static,      // TODO: 'static :-/
+          UpgradedNodeIncoming<T, C>: Send,
 {
     type Item = ();
-    type Error = IoError;
+    type Error = SwarmError;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        let handler = &mut self.handler;
+        let mut immediate = false;
+        while let Ok(Async::Ready(Some((request, deadline)))) = self.shutdown.poll() {
+            match deadline {
+                // The first deadline is kept if a graceful shutdown is already in progress.
+                Some(deadline) => if self.deadline.is_none() {
+                    self.stop_accepting();
+                    if let Some(closing) = self.closing.take() {
+                        let _ = closing.send(());
+                    }
+                    self.deadline = Some(deadline);
+                },
+                None => immediate = true,
+            }
+            self.shutdown_requests.push(request);
+        }
 
-        match self.next_incoming.poll() {
-            Ok(Async::Ready((connec, client_addr))) => {
-                self.next_incoming = self.upgraded.clone().next_incoming();
-                self.to_process.push(future::Either::A(handler(connec, client_addr).into_future()));
+        let deadline_reached = match self.deadline {
+            Some(ref mut deadline) => match deadline.poll() {
+                Ok(Async::NotReady) => false,
+                Ok(Async::Ready(())) | Err(_) => true,
             },
-            Ok(Async::NotReady) => {},
-            // TODO: may not be the best idea because we're killing the whole server
-            Err(err) => return Err(err),
+            None => false,
         };
+        if immediate || deadline_reached {
+            self.finish_shutdown();
+            return Ok(Async::Ready(()));
+        }
+
+        // Makes sure that we get woken up when a ban is added, so that we can close the existing
+        // connections.
+        self.ban_list.register_task();
+
+        let handler = &mut self.handler;
+        // No new connection or substream is accepted during a graceful shutdown.
+        let shutting_down = self.deadline.is_some();
+
+        if !shutting_down {
+            match self.next_incoming.poll() {
+                Ok(Async::Ready((_, ref client_addr)))
+                    if !self.ban_list.is_allowed(client_addr) =>
+                {
+                    // Dropping the substream immediately.
+                    self.next_incoming = Box::new(self.upgraded.clone().next_incoming());
+                },
+                Ok(Async::Ready((substream, client_addr))) => {
+                    // The substream belongs to a connection that has already been accepted or
+                    // dialed, therefore it isn't registered as a new connection.
+                    self.next_incoming = Box::new(self.upgraded.clone().next_incoming());
+                    self.events.dispatch(SwarmEvent::IncomingSubstream {
+                        addr: client_addr.clone(),
+                    });
+                    let future = handler(substream, client_addr.clone()).into_future();
+                    self.to_process.push((future::Either::A(future), client_addr,
+                                          Processing::IncomingSubstream));
+                },
+                Ok(Async::NotReady) => {},
+                // TODO: may not be the best idea because we're killing the whole server
+                Err(err) => return Err(err.into()),
+            };
+        }
 
         match self.new_listeners.poll() {
-            Ok(Async::Ready(Some(new_listener))) => {
-                self.listeners.push(new_listener);
+            Ok(Async::Ready(Some((new_listener, id, addr)))) => {
+                // The listener may have been removed before we received it.
+                if self.listen_addrs.lock().iter().any(|a| a.id == id) {
+                    self.listeners.push((new_listener, id, addr));
+                }
             },
             Ok(Async::Ready(None)) | Err(_) => {
                 // New listener sender has been closed.
@@ -202,6 +1036,16 @@ impl<T, C, H, If, F> Future for SwarmFuture<T, C, H, F>
             Ok(Async::NotReady) => {},
         };
 
+        while let Ok(Async::Ready(Some(id))) = self.remove_listeners.poll() {
+            // Dropping the listener closes the underlying socket. The events have already been
+            // produced by the controller.
+            self.listeners.retain(|&(_, listener_id, _)| listener_id != id);
+        }
+
+        while let Ok(Async::Ready(Some(id))) = self.close_connections.poll() {
+            self.close_connection(id);
+        }
+
         match self.new_dialers.poll() {
             Ok(Async::Ready(Some((new_dialer, multiaddr)))) => {
                 self.dialers.push((new_dialer, multiaddr));
@@ -212,68 +1056,432 @@ impl<T, C, H, If, F> Future for SwarmFuture<T, C, H, F>
             Ok(Async::NotReady) => {},
         };
 
-        match self.new_toprocess.poll() {
-            Ok(Async::Ready(Some(new_toprocess))) => {
-                self.to_process.push(future::Either::B(new_toprocess));
+        match self.new_custom_dialers.poll() {
+            Ok(Async::Ready(Some((new_dialer, multiaddr)))) => {
+                self.custom_dialers.push((new_dialer, multiaddr));
             },
             Ok(Async::Ready(None)) | Err(_) => {
-                // New to-process sender has been closed.
+                // New custom dialers sender has been closed.
             },
             Ok(Async::NotReady) => {},
         };
 
-        for n in (0 .. self.listeners.len()).rev() {
-            let mut listener = self.listeners.swap_remove(n);
-            match listener.poll() {
-                Ok(Async::Ready(Some((upgrade, client_addr)))) => {
-                    self.listeners.push(listener);
-                    self.listeners_upgrade.push((upgrade, client_addr));
-                },
-                Ok(Async::NotReady) => {
-                    self.listeners.push(listener);
-                },
-                Ok(Async::Ready(None)) => {},
-                Err(err) => return Err(err),
-            };
+        let (slots, shed_unprotected) = {
+            let mut limit = self.upgrades_limit.lock();
+            limit.to_notify = Some(task::current());
+            (limit.slots.clone(), limit.shed_unprotected)
+        };
+
+        let gater = self.gater.lock().clone();
+
+        // Incoming connections are accepted as long as there is a free slot for their upgrade.
+        // If the limit prevented us from accepting a connection and an upgrade finishes, we loop
+        // again so that the listeners get polled with the freed slot.
+        loop {
+            let mut listeners_blocked = false;
+
+            for n in (0 .. self.listeners.len()).rev() {
+                let (mut listener, listener_id, listen_addr) = self.listeners.swap_remove(n);
+
+                // `None` if the listener is still alive, otherwise contains the error that closed
+                // it, if any.
+                let mut closed = None;
+                loop {
+                    let under_pressure = slots.is_full();
+                    if under_pressure && !shed_unprotected {
+                        listeners_blocked = true;
+                        break;
+                    }
+
+                    match listener.poll() {
+                        Ok(Async::Ready(Some((upgrade, client_addr)))) => {
+                            // Incoming connections from banned or denied addresses are dropped
+                            // before the upgrade.
+                            if !self.ban_list.is_allowed(&client_addr) ||
+                                !gater.allow_accept(&listen_addr, &client_addr)
+                            {
+                                continue;
+                            }
+
+                            if under_pressure && !gater.is_protected(&client_addr) {
+                                self.events.dispatch(SwarmEvent::IncomingShed {
+                                    addr: client_addr,
+                                    local_addr: listen_addr.clone(),
+                                });
+                                continue;
+                            }
+
+                            // Under pressure, the protected connections go beyond the limit.
+                            let upgrade = InboundLimitUpgrade::new(upgrade, Slot::force(&slots));
+                            let local_addr = listen_addr.clone();
+                            self.listeners_upgrade.push((upgrade, client_addr, local_addr));
+                        },
+                        Ok(Async::NotReady) => break,
+                        Ok(Async::Ready(None)) => {
+                            closed = Some(None);
+                            break;
+                        },
+                        Err(err) => {
+                            closed = Some(Some(Arc::new(err.into())));
+                            break;
+                        },
+                    };
+                }
+
+                match closed {
+                    None => self.listeners.push((listener, listener_id, listen_addr)),
+                    Some(error) => {
+                        listener_closed(&self.listen_addrs, &self.events, listener_id, error);
+                    },
+                }
+            }
+
+            let num_upgrades = self.listeners_upgrade.len();
+            for n in (0 .. self.listeners_upgrade.len()).rev() {
+                let (mut upgrade, addr, local_addr) = self.listeners_upgrade.swap_remove(n);
+                if !self.ban_list.is_allowed(&addr) {
+                    continue;
+                }
+
+                match upgrade.poll() {
+                    Ok(Async::Ready(output)) => {
+                        let id = self.connections.insert(addr.clone(), Some(local_addr),
+                                                         Endpoint::Listener);
+                        self.events.dispatch(SwarmEvent::ConnectionEstablished {
+                            id: id,
+                            addr: addr.clone(),
+                            endpoint: Endpoint::Listener,
+                        });
+                        let future = future::Either::A(handler(output, addr.clone()).into_future());
+                        self.to_process.push((future, addr, Processing::Connection(id)));
+                    },
+                    Ok(Async::NotReady) => {
+                        self.listeners_upgrade.push((upgrade, addr, local_addr));
+                    },
+                    Err(err) => {
+                        self.events.dispatch(SwarmEvent::UpgradeFailed {
+                            addr: addr,
+                            error: Arc::new(err.into()),
+                        });
+                    },
+                }
+            }
+
+            if !listeners_blocked || self.listeners_upgrade.len() == num_upgrades {
+                break;
+            }
         }
 
-        for n in (0 .. self.listeners_upgrade.len()).rev() {
-            let (mut upgrade, addr) = self.listeners_upgrade.swap_remove(n);
-            match upgrade.poll() {
+        for n in (0 .. self.dialers.len()).rev() {
+            let (mut dialer, addr) = self.dialers.swap_remove(n);
+            if !self.ban_list.is_allowed(&addr) {
+                continue;
+            }
+
+            match dialer.poll() {
                 Ok(Async::Ready(output)) => {
-                    self.to_process.push(future::Either::A(handler(output, addr).into_future()));
+                    let id = self.connections.insert(addr.clone(), None, Endpoint::Dialer);
+                    self.events.dispatch(SwarmEvent::ConnectionEstablished {
+                        id: id,
+                        addr: addr.clone(),
+                        endpoint: Endpoint::Dialer,
+                    });
+                    let future = future::Either::A(handler(output, addr.clone()).into_future());
+                    self.to_process.push((future, addr, Processing::Connection(id)));
                 },
                 Ok(Async::NotReady) => {
-                    self.listeners_upgrade.push((upgrade, addr));
+                    self.dialers.push((dialer, addr));
+                },
+                Err(err) => {
+                    self.events.dispatch(SwarmEvent::DialFailed {
+                        addr: addr,
+                        error: Arc::new(err.into()),
+                    });
                 },
-                Err(err) => return Err(err),
             }
         }
 
-        for n in (0 .. self.dialers.len()).rev() {
-            let (mut dialer, addr) = self.dialers.swap_remove(n);
+        for n in (0 .. self.custom_dialers.len()).rev() {
+            let (mut dialer, addr) = self.custom_dialers.swap_remove(n);
+            if !self.ban_list.is_allowed(&addr) {
+                continue;
+            }
+
             match dialer.poll() {
-                Ok(Async::Ready(output)) => {
-                    self.to_process.push(future::Either::A(handler(output, addr).into_future()));
+                Ok(Async::Ready(process)) => {
+                    let id = self.connections.insert(addr.clone(), None, Endpoint::Dialer);
+                    self.events.dispatch(SwarmEvent::ConnectionEstablished {
+                        id: id,
+                        addr: addr.clone(),
+                        endpoint: Endpoint::Dialer,
+                    });
+                    self.to_process.push((future::Either::B(process), addr,
+                                          Processing::Connection(id)));
                 },
                 Ok(Async::NotReady) => {
-                    self.dialers.push((dialer, addr));
+                    self.custom_dialers.push((dialer, addr));
                 },
-                Err(err) => return Err(err),
+                Err(err) => {
+                    self.events.dispatch(SwarmEvent::DialFailed {
+                        addr: addr,
+                        error: Arc::new(err.into()),
+                    });
+                },
+            }
+        }
+
+        let executor = self.executor.lock().clone();
+        if let Some(executor) = executor {
+            for (to_process, addr, processing) in self.to_process.drain(..) {
+                let (abort_tx, abort_rx) = oneshot::channel();
+                executor.spawn(Box::new(BackgroundConnection {
+                    inner: to_process,
+                    abort: abort_rx,
+                    connections: self.connections.clone(),
+                    events: self.events.clone(),
+                    addr: addr.clone(),
+                    processing: processing,
+                }));
+                self.background.push((abort_tx, addr, processing));
+            }
+        }
+
+        // The connections whose task has finished have already been reported by the task.
+        // Polling registers the current task, so that we wake up when a task finishes.
+        for n in (0 .. self.background.len()).rev() {
+            if let Ok(Async::Ready(())) = self.background[n].0.poll_cancel() {
+                self.background.swap_remove(n);
+            }
+        }
+        for n in (0 .. self.background.len()).rev() {
+            let banned = {
+                let (_, ref addr, processing) = self.background[n];
+                is_banned(&self.ban_list, &self.connections, addr, processing)
+            };
+            if !banned {
+                continue;
+            }
+
+            let (abort, addr, processing) = self.background.swap_remove(n);
+            if abort.send(()).is_ok() {
+                connection_closed(&self.connections, &self.events, processing, addr, None);
             }
         }
 
         for n in (0 .. self.to_process.len()).rev() {
-            let mut to_process = self.to_process.swap_remove(n);
-            match to_process.poll() {
-                Ok(Async::Ready(())) => {},
-                Ok(Async::NotReady) => self.to_process.push(to_process),
-                Err(err) => return Err(err),
+            let (mut to_process, addr, processing) = self.to_process.swap_remove(n);
+            if is_banned(&self.ban_list, &self.connections, &addr, processing) {
+                // Dropping the future closes the connection.
+                connection_closed(&self.connections, &self.events, processing, addr, None);
+                continue;
             }
+
+            let error = match to_process.poll() {
+                Ok(Async::Ready(())) => None,
+                Ok(Async::NotReady) => {
+                    self.to_process.push((to_process, addr, processing));
+                    continue;
+                },
+                Err(err) => Some(Arc::new(err.into())),
+            };
+
+            connection_closed(&self.connections, &self.events, processing, addr, error);
         }
 
-        // TODO: we never return `Ok(Ready)` because there's no way to know whether
-        //       `next_incoming()` can produce anything more in the future
+        // A graceful shutdown is over once all the connections have finished.
+        if shutting_down && self.to_process.is_empty() && self.background.is_empty() {
+            self.finish_shutdown();
+            return Ok(Async::Ready(()));
+        }
+
+        // TODO: we never return `Ok(Ready)` unless we are shut down, because there's no way to
+        //       know whether `next_incoming()` can produce anything more in the future
         Ok(Async::NotReady)
     }
 }
+
+impl<T, C, H, F> SwarmFuture<T, C, H, F>
+    where T: MuxedTransport + 'static,      // TODO: 'static :-/
+          C: ConnectionUpgrade<T::RawConn> + 'static,      // TODO: 'static :-/
+{
+    // Destroys all the listeners, dialers and connections of the swarm, then answers the
+    // shutdown requests.
+    fn finish_shutdown(&mut self) {
+        self.close_all();
+        self.deadline = None;
+        for request in self.shutdown_requests.drain(..) {
+            // Ignoring errors if the user destroyed the `SwarmShutdown`.
+            let _ = request.send(());
+        }
+    }
+
+    // Closes a connection that lost against another connection to the same remote, or whose
+    // remote is banned. Does nothing if the connection has already finished.
+    fn close_connection(&mut self, id: ConnectionId) {
+        let processing = Processing::Connection(id);
+        if let Some(n) = self.to_process.iter().position(|&(_, _, p)| p == processing) {
+            // Dropping the future closes the connection.
+            let (_, addr, _) = self.to_process.swap_remove(n);
+            connection_closed(&self.connections, &self.events, processing, addr, None);
+        } else if let Some(n) = self.background.iter().position(|&(_, _, p)| p == processing) {
+            let (abort, addr, _) = self.background.swap_remove(n);
+            if abort.send(()).is_ok() {
+                connection_closed(&self.connections, &self.events, processing, addr, None);
+            }
+        }
+    }
+
+    // Destroys all the listeners, dialers and pending upgrades of the swarm, but keeps the open
+    // connections. Destroying the futures closes the underlying sockets.
+    fn stop_accepting(&mut self) {
+        // Includes the listeners that have been sent by the controller but not received yet.
+        let ids = self.listen_addrs.lock().iter().map(|a| a.id).collect::<Vec<_>>();
+        for id in ids {
+            listener_closed(&self.listen_addrs, &self.events, id, None);
+        }
+        self.listeners.clear();
+
+        self.listeners_upgrade.clear();
+        self.dialers.clear();
+        self.custom_dialers.clear();
+
+        // Listeners, dialers and connections that have been sent by the controller but not
+        // processed yet are destroyed as well.
+        self.new_listeners.close();
+        self.remove_listeners.close();
+        self.new_dialers.close();
+        self.new_custom_dialers.close();
+        while let Ok(Async::Ready(Some(_))) = self.new_listeners.poll() {}
+        while let Ok(Async::Ready(Some(_))) = self.new_dialers.poll() {}
+        while let Ok(Async::Ready(Some(_))) = self.new_custom_dialers.poll() {}
+    }
+
+    // Destroys all the listeners, dialers and connections of the swarm. Destroying the futures
+    // closes the underlying sockets.
+    fn close_all(&mut self) {
+        self.stop_accepting();
+
+        for (_, addr, processing) in self.to_process.drain(..) {
+            connection_closed(&self.connections, &self.events, processing, addr, None);
+        }
+        for (abort, addr, processing) in self.background.drain(..) {
+            if abort.send(()).is_ok() {
+                connection_closed(&self.connections, &self.events, processing, addr, None);
+            }
+        }
+        self.connections.clear();
+    }
+}
+
+// Unregisters a listener that has been closed, and reports it to the subscribers. Returns false
+// if the listener wasn't registered, in which case nothing is reported.
+fn listener_closed(listen_addrs: &Mutex<Vec<ListenAddr>>, events: &EventsDispatcher,
+                   id: ListenerId, error: Option<Arc<SwarmError>>) -> bool {
+    let listen_addr = {
+        let mut listen_addrs = listen_addrs.lock();
+        match listen_addrs.iter().position(|a| a.id == id) {
+            Some(pos) => listen_addrs.remove(pos),
+            None => return false,
+        }
+    };
+
+    if let Some(ref error) = error {
+        events.dispatch(SwarmEvent::ListenerError {
+            id: id,
+            addr: listen_addr.bound.clone(),
+            error: error.clone(),
+        });
+    }
+
+    for reachable in listen_addr.reachable {
+        events.dispatch(SwarmEvent::ExpiredListenAddr { addr: reachable });
+    }
+
+    events.dispatch(SwarmEvent::ListenerClosed {
+        id: id,
+        addr: listen_addr.bound,
+        error: error,
+    });
+    true
+}
+
+// Processes a connection on the executor of the swarm.
+struct BackgroundConnection<F> {
+    inner: F,
+    // Resolves when the swarm wants the connection to be closed, or produces an error if the
+    // swarm has been destroyed.
+    abort: oneshot::Receiver<()>,
+    connections: Connections,
+    events: EventsDispatcher,
+    addr: Multiaddr,
+    processing: Processing,
+}
+
+impl<F> Future for BackgroundConnection<F>
+    where F: Future<Item = (), Error = IoError>
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        match self.abort.poll() {
+            Ok(Async::NotReady) => (),
+            // The swarm has reported the closing, if necessary.
+            Ok(Async::Ready(())) | Err(_) => return Ok(Async::Ready(())),
+        }
+
+        let error = match self.inner.poll() {
+            Ok(Async::Ready(())) => None,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(err) => Some(Arc::new(err.into())),
+        };
+
+        // Closing the receiver tells the swarm that the connection is finished, and prevents it
+        // from reporting the closing a second time.
+        self.abort.close();
+        connection_closed(&self.connections, &self.events, self.processing, self.addr.clone(),
+                          error);
+        Ok(Async::Ready(()))
+    }
+}
+
+// What a future of `to_process` or of `background` processes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Processing {
+    // A connection registered in `connections`.
+    Connection(ConnectionId),
+    // A substream opened by a remote on an existing connection, as produced by `next_incoming`.
+    IncomingSubstream,
+}
+
+// Returns true if the connection or substream with the given address must be closed, because its
+// address or the identity of its remote is banned.
+fn is_banned(ban_list: &BanList, connections: &Connections, addr: &Multiaddr,
+             processing: Processing) -> bool {
+    if !ban_list.is_allowed(addr) {
+        return true;
+    }
+
+    match processing {
+        Processing::Connection(id) => connections.remote_peer_id(id)
+            .map_or(false, |peer_id| ban_list.is_peer_banned(&peer_id)),
+        Processing::IncomingSubstream => false,
+    }
+}
+
+// Unregisters a connection or a substream whose processing has finished, and reports it to the
+// subscribers.
+fn connection_closed(connections: &Connections, events: &EventsDispatcher,
+                     processing: Processing, addr: Multiaddr, error: Option<Arc<SwarmError>>) {
+    let event = match processing {
+        Processing::Connection(id) => {
+            connections.remove(id);
+            SwarmEvent::ConnectionClosed { id: id, addr: addr, error: error }
+        },
+        Processing::IncomingSubstream => {
+            SwarmEvent::IncomingSubstreamClosed { addr: addr, error: error }
+        },
+    };
+
+    events.dispatch(event);
+}
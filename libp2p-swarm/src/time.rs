@@ -0,0 +1,269 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Clock that also works in browsers.
+//!
+//! On `wasm32-unknown-unknown`, the standard library can't read the clock and `Instant::now()` or
+//! `SystemTime::now()` panic. This module provides an `Instant` that is the one of the standard
+//! library on the other platforms and that is backed by `performance.now()` in browsers, and a
+//! `system_now()` function that replaces `SystemTime::now()` and uses `Date.now()` in browsers.
+//!
+//! The crates of libp2p that need to read the clock use this module instead of `std::time`.
+//!
+//! The handlers that need timers take a `Clock`, which gives them the current time and their
+//! delays. The tokio-core `Handle` and `Remote` are clocks. Tests can use a clock whose time only
+//! moves when they decide, such as the `MockClock` of `libp2p-swarm-test`.
+
+use futures::Future;
+use std::io::Error as IoError;
+use std::time::{Duration, SystemTime};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use tokio_core::reactor::{Handle, Remote};
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+use std::time::UNIX_EPOCH;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use std::time::Instant;
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub use self::browser::Instant;
+
+/// Returns the current time of the system clock. Same as `SystemTime::now()`, except that it also
+/// works in browsers.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+#[inline]
+pub fn system_now() -> SystemTime {
+	SystemTime::now()
+}
+
+/// Returns the current time of the system clock. Same as `SystemTime::now()`, except that it also
+/// works in browsers.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub fn system_now() -> SystemTime {
+	use stdweb::unstable::TryInto;
+	let millis: f64 = js!(return Date.now();).try_into().expect("Date.now() returns a number");
+	UNIX_EPOCH + browser::from_millis(millis)
+}
+
+/// Source of the current time and of the delays of a handler.
+pub trait Clock {
+	/// Future that is ready once a delay has elapsed.
+	type Delay: Future<Item = (), Error = IoError>;
+
+	/// Returns the current time.
+	fn now(&self) -> Instant;
+
+	/// Returns a future that is ready once `duration` has elapsed.
+	fn delay(&self, duration: Duration) -> Self::Delay;
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl Clock for Handle {
+	type Delay = tokio::HandleDelay;
+
+	#[inline]
+	fn now(&self) -> Instant {
+		Instant::now()
+	}
+
+	#[inline]
+	fn delay(&self, duration: Duration) -> tokio::HandleDelay {
+		tokio::HandleDelay::new(duration, self)
+	}
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl Clock for Remote {
+	type Delay = tokio::RemoteDelay;
+
+	#[inline]
+	fn now(&self) -> Instant {
+		Instant::now()
+	}
+
+	#[inline]
+	fn delay(&self, duration: Duration) -> tokio::RemoteDelay {
+		tokio::RemoteDelay::new(duration, self)
+	}
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use self::tokio::{HandleDelay, RemoteDelay};
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod tokio {
+	use futures::{future, Async, Future, Poll};
+	use futures::sync::oneshot;
+	use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+	use std::time::Duration;
+	use tokio_core::reactor::{Handle, Remote, Timeout};
+
+	/// Delay of a tokio-core `Handle`. Fails if the timer can't be created.
+	pub struct HandleDelay {
+		// The error that happened when creating the timer is produced at the first poll.
+		inner: Result<Timeout, Option<IoError>>,
+	}
+
+	impl HandleDelay {
+		#[inline]
+		pub(crate) fn new(duration: Duration, handle: &Handle) -> HandleDelay {
+			HandleDelay { inner: Timeout::new(duration, handle).map_err(Some) }
+		}
+	}
+
+	impl Future for HandleDelay {
+		type Item = ();
+		type Error = IoError;
+
+		#[inline]
+		fn poll(&mut self) -> Poll<(), IoError> {
+			match self.inner {
+				Ok(ref mut timeout) => timeout.poll(),
+				Err(ref mut err) => Err(err.take().expect("delay polled after an error")),
+			}
+		}
+	}
+
+	/// Delay of a tokio-core `Remote`. Unlike the ones of a `Handle`, it can be sent to other
+	/// threads. Fails if the timer can't be created or if the event loop is gone.
+	pub struct RemoteDelay {
+		inner: oneshot::Receiver<Result<(), IoError>>,
+	}
+
+	impl RemoteDelay {
+		pub(crate) fn new(duration: Duration, remote: &Remote) -> RemoteDelay {
+			// The `Timeout` isn't `Send`, therefore it is created and polled on the thread of the
+			// event loop, and the result is sent through a channel.
+			let (tx, rx) = oneshot::channel();
+			remote.spawn(move |handle| {
+				future::result(Timeout::new(duration, handle))
+					.flatten()
+					.then(move |result| {
+						let _ = tx.send(result);
+						Ok(())
+					})
+			});
+			RemoteDelay { inner: rx }
+		}
+	}
+
+	impl Future for RemoteDelay {
+		type Item = ();
+		type Error = IoError;
+
+		fn poll(&mut self) -> Poll<(), IoError> {
+			match self.inner.poll() {
+				Ok(Async::Ready(result)) => result.map(Async::Ready),
+				Ok(Async::NotReady) => Ok(Async::NotReady),
+				Err(_) => Err(IoError::new(IoErrorKind::Other, "the event loop is gone")),
+			}
+		}
+	}
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod browser {
+	use std::ops::{Add, AddAssign, Sub, SubAssign};
+	use std::time::Duration;
+	use stdweb::unstable::TryInto;
+
+	/// Monotonic clock backed by `performance.now()`. Mirrors the API of `std::time::Instant`.
+	#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+	pub struct Instant {
+		// Time elapsed since the origin of `performance.now()`, which is when the page or the
+		// worker has been loaded.
+		since_origin: Duration,
+	}
+
+	impl Instant {
+		/// Returns the current moment.
+		pub fn now() -> Instant {
+			let millis: f64 = js!(return performance.now();)
+				.try_into()
+				.expect("performance.now() returns a number");
+			Instant { since_origin: from_millis(millis) }
+		}
+
+		/// Returns the time elapsed from `earlier` to `self`.
+		///
+		/// # Panic
+		///
+		/// Panics if `earlier` is later than `self`.
+		#[inline]
+		pub fn duration_since(&self, earlier: Instant) -> Duration {
+			self.since_origin - earlier.since_origin
+		}
+
+		/// Returns the time elapsed since `self`.
+		#[inline]
+		pub fn elapsed(&self) -> Duration {
+			Instant::now().duration_since(*self)
+		}
+	}
+
+	impl Add<Duration> for Instant {
+		type Output = Instant;
+
+		#[inline]
+		fn add(self, other: Duration) -> Instant {
+			Instant { since_origin: self.since_origin + other }
+		}
+	}
+
+	impl AddAssign<Duration> for Instant {
+		#[inline]
+		fn add_assign(&mut self, other: Duration) {
+			self.since_origin += other;
+		}
+	}
+
+	impl Sub<Duration> for Instant {
+		type Output = Instant;
+
+		#[inline]
+		fn sub(self, other: Duration) -> Instant {
+			Instant { since_origin: self.since_origin - other }
+		}
+	}
+
+	impl SubAssign<Duration> for Instant {
+		#[inline]
+		fn sub_assign(&mut self, other: Duration) {
+			self.since_origin -= other;
+		}
+	}
+
+	impl Sub<Instant> for Instant {
+		type Output = Duration;
+
+		#[inline]
+		fn sub(self, other: Instant) -> Duration {
+			self.duration_since(other)
+		}
+	}
+
+	// Converts a number of milliseconds returned by Javascript into a `Duration`.
+	pub fn from_millis(millis: f64) -> Duration {
+		let millis = millis.max(0.0);
+		let secs = (millis / 1000.0) as u64;
+		let nanos = ((millis % 1000.0) * 1_000_000.0) as u32;
+		Duration::new(secs, nanos)
+	}
+}
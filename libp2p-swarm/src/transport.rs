@@ -29,16 +29,22 @@
 //! `UpgradedNode::or_upgrade` methods, you can combine multiple transports and/or upgrades
 //! together in a complex chain of protocols negotiation.
 
+use boxed::Boxed;
 use bytes::Bytes;
 use connection_reuse::ConnectionReuse;
+use error::{ContextError, ErrorLayer, UpgradeError};
 use futures::{Async, Poll, stream, Stream};
 use futures::future::{self, FromErr, Future, FutureResult, IntoFuture};
 use multiaddr::Multiaddr;
-use multistream_select;
+use multistream_select::{self, UnsupportedResponse};
 use muxing::StreamMuxer;
-use std::io::{Cursor, Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::io::{Cursor, Error as IoError, Read, Write};
 use std::iter;
+use std::mem;
 use std::sync::Arc;
+use std::vec;
 use tokio_io::{AsyncRead, AsyncWrite};
 
 /// A transport is an object that can be used to produce connections by listening or dialing a
@@ -102,10 +108,29 @@ pub trait Transport {
 	/// doesn't recognize the protocols, or if `server` and `observed` are related.
 	fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr>;
 
+	/// Takes a multiaddress we're listening on, as returned by `listen_on`, and returns the
+	/// concrete addresses through which this listener can actually be reached.
+	///
+	/// For example, if we're listening on `/ip4/0.0.0.0/tcp/3000`, then the result could be
+	/// `/ip4/127.0.0.1/tcp/3000` and `/ip4/192.168.1.5/tcp/3000`. Since network interfaces can
+	/// appear or disappear, calling this method twice with the same address can produce different
+	/// results.
+	///
+	/// The default implementation returns the address unchanged, which is the right thing to do
+	/// for transports that don't have a notion of wildcard addresses.
+	#[inline]
+	fn expand_listen_addr(&self, addr: &Multiaddr) -> Vec<Multiaddr> {
+		vec![addr.clone()]
+	}
+
 	/// Builds a new struct that implements `Transport` that contains both `self` and `other`.
 	///
 	/// The returned object will redirect its calls to `self`, except that if `listen_on` or `dial`
 	/// return an error then `other` will be tried.
+	///
+	/// > **Note**: Only the format of the multiaddress is taken into account. If the first
+	/// >           transport supports the multiaddress but the connection then fails, the second
+	/// >           transport is not tried.
 	#[inline]
 	fn or_transport<T>(self, other: T) -> OrTransport<Self, T>
 	where
@@ -128,9 +153,25 @@ pub trait Transport {
 		UpgradedNode {
 			transports: self,
 			upgrade: upgrade,
+			unsupported_protocols: UnsupportedProtocols::new(),
 		}
 	}
 
+	/// Erases the type of this transport, so that it can be stored in the same field or returned
+	/// by the same function as any other transport that produces the same type of connection.
+	///
+	/// This is useful when the transport stack is only known at runtime.
+	#[inline]
+	fn boxed(self) -> Boxed<Self::RawConn>
+	where
+		Self: Sized + Clone + Send + Sync + 'static,
+		Self::Listener: Send + 'static,
+		Self::ListenerUpgrade: Send + 'static,
+		<Self::Dial as IntoFuture>::Future: Send + 'static,
+	{
+		Boxed::new(self)
+	}
+
 	/// Builds a dummy implementation of `MuxedTransport` that uses this transport.
 	/// 
 	/// The resulting object will not actually use muxing. This means that dialing the same node
@@ -174,9 +215,9 @@ pub struct DeniedTransport;
 impl Transport for DeniedTransport {
 	// TODO: could use `!` for associated types once stable
 	type RawConn = Cursor<Vec<u8>>;
-	type Listener = Box<Stream<Item = (Self::ListenerUpgrade, Multiaddr), Error = IoError>>;
-	type ListenerUpgrade = Box<Future<Item = Self::RawConn, Error = IoError>>;
-	type Dial = Box<Future<Item = Self::RawConn, Error = IoError>>;
+	type Listener = stream::Empty<(Self::ListenerUpgrade, Multiaddr), IoError>;
+	type ListenerUpgrade = future::Empty<Self::RawConn, IoError>;
+	type Dial = future::Empty<Self::RawConn, IoError>;
 
 	#[inline]
 	fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
@@ -251,6 +292,15 @@ where
 
 		self.1.nat_traversal(server, observed)
 	}
+
+	fn expand_listen_addr(&self, addr: &Multiaddr) -> Vec<Multiaddr> {
+		let first = self.0.expand_listen_addr(addr);
+		if first.len() != 1 || first[0] != *addr {
+			return first;
+		}
+
+		self.1.expand_listen_addr(addr)
+	}
 }
 
 /// Implementation of `ConnectionUpgrade`. Convenient to use with small protocols.
@@ -290,19 +340,43 @@ impl<A, B> MuxedTransport for OrTransport<A, B>
 where
 	A: MuxedTransport,
 	B: MuxedTransport,
-	A::Incoming: 'static,		// TODO: meh :-/
-	B::Incoming: 'static,		// TODO: meh :-/
 {
-	type Incoming = Box<Future<Item = (EitherSocket<A::RawConn, B::RawConn>, Multiaddr), Error = IoError>>;
+	type Incoming = EitherIncoming<A::Incoming, B::Incoming>;
 
 	#[inline]
 	fn next_incoming(self) -> Self::Incoming {
-		let first = self.0.next_incoming().map(|(out, addr)| (EitherSocket::First(out), addr));
-		let second = self.1.next_incoming().map(|(out, addr)| (EitherSocket::Second(out), addr));
-		let future = first.select(second)
-			.map(|(i, _)| i)
-			.map_err(|(e, _)| e);
-		Box::new(future) as Box<_>
+		EitherIncoming {
+			first: self.0.next_incoming(),
+			second: self.1.next_incoming(),
+		}
+	}
+}
+
+/// Future that produces the next incoming substream of either of two transports. Returned by
+/// the `next_incoming()` method of `OrTransport`.
+pub struct EitherIncoming<A, B> {
+	first: A,
+	second: B,
+}
+
+impl<A, B, Ca, Cb> Future for EitherIncoming<A, B>
+where
+	A: Future<Item = (Ca, Multiaddr), Error = IoError>,
+	B: Future<Item = (Cb, Multiaddr), Error = IoError>,
+{
+	type Item = (EitherSocket<Ca, Cb>, Multiaddr);
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		if let Async::Ready((out, addr)) = self.first.poll()? {
+			return Ok(Async::Ready((EitherSocket::First(out), addr)));
+		}
+
+		if let Async::Ready((out, addr)) = self.second.poll()? {
+			return Ok(Async::Ready((EitherSocket::Second(out), addr)));
+		}
+
+		Ok(Async::NotReady)
 	}
 }
 
@@ -515,6 +589,14 @@ where
 			EitherSocket::Second(b) => EitherTransportFuture::Second(b.outbound()),
 		}
 	}
+
+	#[inline]
+	fn close(&self) -> Poll<(), IoError> {
+		match self {
+			&EitherSocket::First(ref a) => a.close(),
+			&EitherSocket::Second(ref b) => b.close(),
+		}
+	}
 }
 
 /// Implemented on structs that describe a possible upgrade to a connection between two peers.
@@ -572,7 +654,7 @@ impl<C> ConnectionUpgrade<C> for DeniedConnectionUpgrade
 	type NamesIter = iter::Empty<(Bytes, ())>;
 	type UpgradeIdentifier = ();		// TODO: could use `!`
 	type Output = ();		// TODO: could use `!`
-	type Future = Box<Future<Item = (), Error = IoError>>;		// TODO: could use `!`
+	type Future = future::Empty<(), IoError>;		// TODO: could use `!`
 
 	#[inline]
 	fn protocol_names(&self) -> Self::NamesIter {
@@ -591,6 +673,24 @@ pub trait UpgradeExt {
 	/// the remote supports.
     fn or_upgrade<T>(self, other: T) -> OrUpgrade<Self, T>
 		where Self: Sized;
+
+	/// Builds a struct that advertises and tries the protocols of `self` in the order given by
+	/// `preference`. See `PreferenceOrder`.
+	fn with_preference<I>(self, preference: I) -> PreferenceOrder<Self>
+		where Self: Sized,
+			  I: IntoIterator,
+			  I::Item: Into<Bytes>;
+
+	/// Builds a struct that wraps the errors produced by the handshake of `self` in a
+	/// `ContextError` indicating the layer, the negotiated protocol and the remote. See
+	/// `ErrorContext`.
+	fn with_error_context(self, layer: ErrorLayer) -> ErrorContext<Self>
+		where Self: Sized;
+
+	/// Builds a struct that wraps the output of `self` in a `Negotiated` that contains the name
+	/// of the protocol that has been negotiated. See `NegotiatedName`.
+	fn with_negotiated_name(self) -> NegotiatedName<Self>
+		where Self: Sized;
 }
 
 impl<T> UpgradeExt for T {
@@ -598,6 +698,32 @@ impl<T> UpgradeExt for T {
     fn or_upgrade<U>(self, other: U) -> OrUpgrade<Self, U> {
         OrUpgrade(self, other)
     }
+
+	#[inline]
+	fn with_preference<I>(self, preference: I) -> PreferenceOrder<Self>
+		where I: IntoIterator,
+			  I::Item: Into<Bytes>
+	{
+		PreferenceOrder {
+			inner: self,
+			preference: Arc::new(preference.into_iter().map(Into::into).collect()),
+		}
+	}
+
+	#[inline]
+	fn with_error_context(self, layer: ErrorLayer) -> ErrorContext<Self> {
+		ErrorContext {
+			inner: self,
+			layer: layer,
+		}
+	}
+
+	#[inline]
+	fn with_negotiated_name(self) -> NegotiatedName<Self> {
+		NegotiatedName {
+			inner: self,
+		}
+	}
 }
 
 /// See `or_upgrade()`.
@@ -721,6 +847,284 @@ where
 	}
 }
 
+/// Wraps around a `ConnectionUpgrade` and reorders its protocol names. See `with_preference()`.
+///
+/// The protocols whose name is in the preference list come first, in the order of the list,
+/// followed with the other protocols in their original order. When dialing, the protocols are
+/// tried in this order, therefore the first one that the remote supports is chosen. When
+/// listening, this is the order in which the protocols are advertised to the remote. If both
+/// sides use the same preference list (eg. `/ipfs/id/2.0.0` before `/ipfs/id/1.0.0`), the best
+/// version that both of them support is always the one that gets negotiated.
+#[derive(Debug, Clone)]
+pub struct PreferenceOrder<U> {
+	inner: U,
+	preference: Arc<Vec<Bytes>>,
+}
+
+impl<C, U> ConnectionUpgrade<C> for PreferenceOrder<U>
+where
+	C: AsyncRead + AsyncWrite,
+	U: ConnectionUpgrade<C>,
+{
+	type NamesIter = vec::IntoIter<(Bytes, U::UpgradeIdentifier)>;
+	type UpgradeIdentifier = U::UpgradeIdentifier;
+
+	fn protocol_names(&self) -> Self::NamesIter {
+		let mut names = self.inner.protocol_names().collect::<Vec<_>>();
+		// The sort is stable, therefore the protocols that aren't in the list keep their order.
+		names.sort_by_key(|&(ref name, _)| {
+			self.preference.iter().position(|p| p == name).unwrap_or(self.preference.len())
+		});
+		names.into_iter()
+	}
+
+	type Output = U::Output;
+	type Future = U::Future;
+
+	#[inline]
+	fn upgrade(self, socket: C, id: Self::UpgradeIdentifier, ty: Endpoint,
+			   remote_addr: &Multiaddr) -> Self::Future
+	{
+		self.inner.upgrade(socket, id, ty, remote_addr)
+	}
+}
+
+/// Wraps around a `ConnectionUpgrade` and attaches a context to the errors of its handshake. See
+/// `with_error_context()`.
+///
+/// Without this, a failed handshake is usually reported as a bare I/O error such as "invalid
+/// data", which doesn't say which layer of the stack failed. The errors produced by the wrapped
+/// upgrade are turned into `ContextError`s that contain the layer, the name of the negotiated
+/// protocol, the endpoint and the address of the remote.
+#[derive(Debug, Clone)]
+pub struct ErrorContext<U> {
+	inner: U,
+	layer: ErrorLayer,
+}
+
+impl<C, U> ConnectionUpgrade<C> for ErrorContext<U>
+where
+	C: AsyncRead + AsyncWrite,
+	U: ConnectionUpgrade<C>,
+{
+	type NamesIter = vec::IntoIter<(Bytes, Self::UpgradeIdentifier)>;
+	// The name of the protocol is kept so that it can be put in the errors.
+	type UpgradeIdentifier = (Bytes, U::UpgradeIdentifier);
+
+	fn protocol_names(&self) -> Self::NamesIter {
+		self.inner.protocol_names()
+			.map(|(name, id)| (name.clone(), (name, id)))
+			.collect::<Vec<_>>()
+			.into_iter()
+	}
+
+	type Output = U::Output;
+	type Future = ErrorContextFuture<U::Future>;
+
+	#[inline]
+	fn upgrade(self, socket: C, id: Self::UpgradeIdentifier, ty: Endpoint,
+			   remote_addr: &Multiaddr) -> Self::Future
+	{
+		let (protocol, id) = id;
+		ErrorContextFuture {
+			inner: self.inner.upgrade(socket, id, ty, remote_addr),
+			layer: self.layer,
+			protocol: protocol,
+			endpoint: ty,
+			remote_addr: remote_addr.clone(),
+		}
+	}
+}
+
+/// Future returned by the `upgrade` method of `ErrorContext`.
+pub struct ErrorContextFuture<F> {
+	inner: F,
+	layer: ErrorLayer,
+	protocol: Bytes,
+	endpoint: Endpoint,
+	remote_addr: Multiaddr,
+}
+
+impl<F> Future for ErrorContextFuture<F>
+	where F: Future<Error = IoError>
+{
+	type Item = F::Item;
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		self.inner.poll().map_err(|err| {
+			ContextError::new(self.layer, err)
+				.with_protocol(self.protocol.clone())
+				.with_endpoint(self.endpoint)
+				.with_remote_addr(self.remote_addr.clone())
+				.into()
+		})
+	}
+}
+
+/// Wraps around a `ConnectionUpgrade` and reports which of its protocols has been negotiated.
+/// See `with_negotiated_name()`.
+///
+/// This is useful on top of a stack of security protocols built with `or_upgrade()` and
+/// `with_preference()`, for example Noise preferred over secio. Each connection negotiates one of
+/// them with multistream-select, and the name found in the `Negotiated` output can be recorded
+/// in the `ConnectionInfo` with `set_security_protocol()`.
+#[derive(Debug, Clone)]
+pub struct NegotiatedName<U> {
+	inner: U,
+}
+
+impl<C, U> ConnectionUpgrade<C> for NegotiatedName<U>
+where
+	C: AsyncRead + AsyncWrite,
+	U: ConnectionUpgrade<C>,
+{
+	type NamesIter = vec::IntoIter<(Bytes, Self::UpgradeIdentifier)>;
+	type UpgradeIdentifier = (Bytes, U::UpgradeIdentifier);
+
+	fn protocol_names(&self) -> Self::NamesIter {
+		self.inner.protocol_names()
+			.map(|(name, id)| (name.clone(), (name, id)))
+			.collect::<Vec<_>>()
+			.into_iter()
+	}
+
+	type Output = Negotiated<U::Output>;
+	type Future = NegotiatedNameFuture<U::Future>;
+
+	#[inline]
+	fn upgrade(self, socket: C, id: Self::UpgradeIdentifier, ty: Endpoint,
+			   remote_addr: &Multiaddr) -> Self::Future
+	{
+		let (protocol, id) = id;
+		NegotiatedNameFuture {
+			inner: self.inner.upgrade(socket, id, ty, remote_addr),
+			protocol: Some(protocol),
+		}
+	}
+}
+
+/// Future returned by the `upgrade` method of `NegotiatedName`.
+pub struct NegotiatedNameFuture<F> {
+	inner: F,
+	protocol: Option<Bytes>,
+}
+
+impl<F> Future for NegotiatedNameFuture<F>
+	where F: Future<Error = IoError>
+{
+	type Item = Negotiated<F::Item>;
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let inner = try_ready!(self.inner.poll());
+		Ok(Async::Ready(Negotiated {
+			protocol: self.protocol.take().expect("poll() called after the future has finished"),
+			inner: inner,
+		}))
+	}
+}
+
+/// Output of a `NegotiatedName` upgrade: the output of the wrapped upgrade, and the name of the
+/// protocol that has been negotiated.
+///
+/// Implements `AsyncRead`, `AsyncWrite` and `StreamMuxer` by forwarding to the inner object.
+#[derive(Debug, Clone)]
+pub struct Negotiated<O> {
+	protocol: Bytes,
+	inner: O,
+}
+
+impl<O> Negotiated<O> {
+	/// Returns the name of the protocol that has been negotiated, for example `/secio/1.0.0`.
+	#[inline]
+	pub fn protocol_name(&self) -> &[u8] {
+		&self.protocol
+	}
+
+	/// Returns a reference to the output of the wrapped upgrade.
+	#[inline]
+	pub fn get_ref(&self) -> &O {
+		&self.inner
+	}
+
+	/// Returns a mutable reference to the output of the wrapped upgrade.
+	#[inline]
+	pub fn get_mut(&mut self) -> &mut O {
+		&mut self.inner
+	}
+
+	/// Returns the output of the wrapped upgrade.
+	#[inline]
+	pub fn into_inner(self) -> O {
+		self.inner
+	}
+}
+
+impl<O> Read for Negotiated<O>
+	where O: Read
+{
+	#[inline]
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+		self.inner.read(buf)
+	}
+}
+
+impl<O> AsyncRead for Negotiated<O>
+	where O: AsyncRead
+{
+	#[inline]
+	unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+		self.inner.prepare_uninitialized_buffer(buf)
+	}
+}
+
+impl<O> Write for Negotiated<O>
+	where O: Write
+{
+	#[inline]
+	fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+		self.inner.write(buf)
+	}
+
+	#[inline]
+	fn flush(&mut self) -> Result<(), IoError> {
+		self.inner.flush()
+	}
+}
+
+impl<O> AsyncWrite for Negotiated<O>
+	where O: AsyncWrite
+{
+	#[inline]
+	fn shutdown(&mut self) -> Poll<(), IoError> {
+		self.inner.shutdown()
+	}
+}
+
+impl<O> StreamMuxer for Negotiated<O>
+	where O: StreamMuxer
+{
+	type Substream = O::Substream;
+	type InboundSubstream = O::InboundSubstream;
+	type OutboundSubstream = O::OutboundSubstream;
+
+	#[inline]
+	fn inbound(self) -> Self::InboundSubstream {
+		self.inner.inbound()
+	}
+
+	#[inline]
+	fn outbound(self) -> Self::OutboundSubstream {
+		self.inner.outbound()
+	}
+
+	#[inline]
+	fn close(&self) -> Poll<(), IoError> {
+		self.inner.close()
+	}
+}
+
 /// Implementation of the `ConnectionUpgrade` that negotiates the `/plaintext/1.0.0` protocol and
 /// simply passes communications through without doing anything more.
 ///
@@ -799,6 +1203,11 @@ impl<T> Transport for DummyMuxing<T>
 	fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
 		self.inner.nat_traversal(server, observed)
 	}
+
+	#[inline]
+	fn expand_listen_addr(&self, addr: &Multiaddr) -> Vec<Multiaddr> {
+		self.inner.expand_listen_addr(addr)
+	}
 }
 
 /// Implements the `Transport` trait. Dials or listens, then upgrades any dialed or received
@@ -809,12 +1218,113 @@ impl<T> Transport for DummyMuxing<T>
 pub struct UpgradedNode<T, C> {
 	transports: T,
 	upgrade: C,
+	unsupported_protocols: UnsupportedProtocols,
+}
+
+/// Maximum number of distinct protocols counted by an `UnsupportedProtocols`. The requests for
+/// the other protocols are counted together, so that remotes can't make us use an arbitrary
+/// amount of memory by requesting many different names.
+pub const MAX_UNSUPPORTED_PROTOCOLS: usize = 128;
+
+/// Keeps track of the number of times remotes have requested a protocol that we don't support on
+/// incoming connections.
+///
+/// This can help operators see what the other nodes of the network expect of them. By default,
+/// the remotes are answered with the `na` message of multistream-select, which all the
+/// implementations understand. They can then ask for the list of the protocols we support, or
+/// find it in the `protocols` that we report through identify, which
+/// `UpgradedNode::supported_protocols()` fills. With
+/// `set_response(UnsupportedResponse::NotAvailableWithHint)`, the `na` is followed with this
+/// list, so that the remotes that understand it don't need to ask.
+///
+/// At most `MAX_UNSUPPORTED_PROTOCOLS` protocols are counted individually. Once this limit is
+/// reached, the requests for the protocols that aren't counted yet are added to `other()`.
+///
+/// Cloning an `UnsupportedProtocols` is cheap and produces an object that shares the same
+/// counters.
+#[derive(Debug, Clone, Default)]
+pub struct UnsupportedProtocols {
+	counters: Arc<Mutex<UnsupportedCounters>>,
+}
+
+#[derive(Debug, Default)]
+struct UnsupportedCounters {
+	// Number of requests for each protocol, for at most `MAX_UNSUPPORTED_PROTOCOLS` protocols.
+	protocols: HashMap<Bytes, u64>,
+	// Number of requests for the protocols that didn't fit in `protocols`.
+	other: u64,
+	// How the remotes are answered.
+	response: UnsupportedResponse,
+}
+
+impl UnsupportedProtocols {
+	/// Builds a new `UnsupportedProtocols` where all the counters are at zero.
+	#[inline]
+	pub fn new() -> UnsupportedProtocols {
+		Default::default()
+	}
+
+	/// Returns the number of times the given protocol has been requested by a remote. Doesn't
+	/// include the requests counted in `other()`.
+	#[inline]
+	pub fn count(&self, protocol: &[u8]) -> u64 {
+		self.counters.lock().protocols.get(protocol).cloned().unwrap_or(0)
+	}
+
+	/// Returns the list of unsupported protocols that have been requested, and the number of
+	/// times each of them has been requested.
+	#[inline]
+	pub fn list(&self) -> Vec<(Bytes, u64)> {
+		self.counters.lock().protocols.iter().map(|(k, v)| (k.clone(), *v)).collect()
+	}
+
+	/// Returns the number of requests for protocols that aren't counted individually, because
+	/// `MAX_UNSUPPORTED_PROTOCOLS` other protocols were already counted.
+	#[inline]
+	pub fn other(&self) -> u64 {
+		self.counters.lock().other
+	}
+
+	/// Sets how the remotes that request a protocol we don't support are answered on the
+	/// negotiations that start afterwards. Defaults to `UnsupportedResponse::NotAvailable`.
+	#[inline]
+	pub fn set_response(&self, response: UnsupportedResponse) {
+		self.counters.lock().response = response;
+	}
+
+	// Increments the counter of the given protocol, or `other` if the limit is reached.
+	fn increment(&self, protocol: &Bytes) {
+		let mut counters = self.counters.lock();
+		if let Some(count) = counters.protocols.get_mut(protocol) {
+			*count += 1;
+			return;
+		}
+
+		if counters.protocols.len() < MAX_UNSUPPORTED_PROTOCOLS {
+			counters.protocols.insert(protocol.clone(), 1);
+		} else {
+			counters.other += 1;
+		}
+	}
+}
+
+// Counts the protocols requested by the remotes during the negotiations of the listeners.
+impl multistream_select::UnsupportedObserver for UnsupportedProtocols {
+	#[inline]
+	fn on_unsupported(&self, name: &Bytes) {
+		self.increment(name)
+	}
+
+	#[inline]
+	fn response(&self) -> UnsupportedResponse {
+		self.counters.lock().response
+	}
 }
 
-impl<'a, T, C> UpgradedNode<T, C>
+impl<T, C> UpgradedNode<T, C>
 where
-	T: Transport + 'a,
-	C: ConnectionUpgrade<T::RawConn> + 'a,
+	T: Transport,
+	C: ConnectionUpgrade<T::RawConn>,
 {
 	/// Turns this upgraded node into a `ConnectionReuse`. If the `Output` implements the
 	/// `StreamMuxer` trait, the returned object will implement `Transport` and `MuxedTransport`.
@@ -829,17 +1339,49 @@ where
 		&self.transports
 	}
 
+	/// Returns the counters of the protocols that remotes requested on incoming connections and
+	/// that the upgrade doesn't support.
+	#[inline]
+	pub fn unsupported_protocols(&self) -> &UnsupportedProtocols {
+		&self.unsupported_protocols
+	}
+
+	/// Returns the names of the protocols supported by the upgrade, in the format of the
+	/// `protocols` reported through identify. The names that aren't valid UTF-8 are skipped.
+	///
+	/// Reporting them lets the remotes that requested a protocol we don't support find out what
+	/// they can use instead.
+	pub fn supported_protocols(&self) -> Vec<String> {
+		self.upgrade.protocol_names()
+			.filter_map(|(name, _)| String::from_utf8(name.to_vec()).ok())
+			.collect()
+	}
+
+	/// Replaces the upgrade that is applied on the connections, while keeping the same transport
+	/// and the same counters of unsupported protocols.
+	///
+	/// This makes it possible to use a different upgrade for some of the listeners or dialers of
+	/// the same node.
+	#[inline]
+	pub fn replace_upgrade<U>(self, upgrade: U) -> UpgradedNode<T, U>
+		where U: ConnectionUpgrade<T::RawConn>
+	{
+		UpgradedNode {
+			transports: self.transports,
+			upgrade: upgrade,
+			unsupported_protocols: self.unsupported_protocols,
+		}
+	}
+
 	/// Tries to dial on the `Multiaddr` using the transport that was passed to `new`, then upgrade
 	/// the connection.
 	///
 	/// Note that this does the same as `Transport::dial`, but with less restrictions on the trait
 	/// requirements.
 	#[inline]
-	pub fn dial(
-		self,
-		addr: Multiaddr,
-	) -> Result<Box<Future<Item = C::Output, Error = IoError> + 'a>, (Self, Multiaddr)> {
+	pub fn dial(self, addr: Multiaddr) -> Result<UpgradedNodeDial<T, C>, (Self, Multiaddr)> {
 		let upgrade = self.upgrade;
+		let unsupported_protocols = self.unsupported_protocols;
 
 		let dialed_fut = match self.transports.dial(addr.clone()) {
 			Ok(f) => f.into_future(),
@@ -847,26 +1389,57 @@ where
 				let builder = UpgradedNode {
 					transports: trans,
 					upgrade: upgrade,
+					unsupported_protocols: unsupported_protocols,
 				};
 
 				return Err((builder, addr));
 			}
 		};
 
-		let future = dialed_fut
-            // Try to negotiate the protocol.
-            .and_then(move |connection| {
-                let iter = upgrade.protocol_names()
-                    .map(|(name, id)| (name, <Bytes as PartialEq>::eq, id));
-                let negotiated = multistream_select::dialer_select_proto(connection, iter)
-                    .map_err(|err| IoError::new(IoErrorKind::Other, err));
-                negotiated.map(|(upgrade_id, conn)| (upgrade_id, conn, upgrade))
-            })
-            .and_then(move |(upgrade_id, connection, upgrade)| {
-                upgrade.upgrade(connection, upgrade_id, Endpoint::Dialer, &addr)
-            });
+		Ok(UpgradedNodeDial {
+			inner: UpgradedNodeDialState::Dialing {
+				future: dialed_fut,
+				upgrade: upgrade,
+				addr: addr,
+			},
+		})
+	}
 
-		Ok(Box::new(future))
+	/// Same as `dial`, but the remote may be dialing us at the same time, for example as part of a
+	/// coordinated hole punching attempt.
+	///
+	/// If the transport merges both attempts into a single connection (like a TCP simultaneous
+	/// open does), both sides believe that they are the dialer. The roles are then decided during
+	/// the protocol negotiation, and the future produces the `Endpoint` that the upgrade was
+	/// applied with. The remote must use `dial_simultaneous` as well.
+	#[inline]
+	pub fn dial_simultaneous(self, addr: Multiaddr)
+							 -> Result<UpgradedNodeSimultaneousDial<T, C>, (Self, Multiaddr)>
+		where C::NamesIter: Clone, // TODO: not elegant
+	{
+		let upgrade = self.upgrade;
+		let unsupported_protocols = self.unsupported_protocols;
+
+		let dialed_fut = match self.transports.dial(addr.clone()) {
+			Ok(f) => f.into_future(),
+			Err((trans, addr)) => {
+				let builder = UpgradedNode {
+					transports: trans,
+					upgrade: upgrade,
+					unsupported_protocols: unsupported_protocols,
+				};
+
+				return Err((builder, addr));
+			}
+		};
+
+		Ok(UpgradedNodeSimultaneousDial {
+			inner: UpgradedNodeSimultaneousDialState::Dialing {
+				future: dialed_fut,
+				upgrade: upgrade,
+				addr: addr,
+			},
+		})
 	}
 
 	/// If the underlying transport is a `MuxedTransport`, then after calling `dial` we may receive
@@ -874,28 +1447,18 @@ where
 	/// 
 	/// This function returns the next incoming substream. You are strongly encouraged to call it
 	/// if you have a muxed transport.
-	pub fn next_incoming(self) -> Box<Future<Item = (C::Output, Multiaddr), Error = IoError> + 'a>
+	#[inline]
+	pub fn next_incoming(self) -> UpgradedNodeIncoming<T, C>
 		where T: MuxedTransport,
 			  C::NamesIter: Clone, // TODO: not elegant
-			  C: Clone,
 	{
-		let upgrade = self.upgrade;
-
-		let future = self.transports.next_incoming()
-            // Try to negotiate the protocol.
-            .and_then(move |(connection, addr)| {
-                let iter = upgrade.protocol_names()
-                    .map::<_, fn(_) -> _>(|(name, id)| (name, <Bytes as PartialEq>::eq, id));
-                let negotiated = multistream_select::listener_select_proto(connection, iter)
-                    .map_err(|err| IoError::new(IoErrorKind::Other, err));
-                negotiated.map(|(upgrade_id, conn)| (upgrade_id, conn, upgrade, addr))
-            })
-            .and_then(|(upgrade_id, connection, upgrade, addr)| {
-                upgrade.upgrade(connection, upgrade_id, Endpoint::Dialer, &addr)
-					.map(|u| (u, addr))
-            });
-
-		Box::new(future) as Box<_>
+		UpgradedNodeIncoming {
+			inner: UpgradedNodeIncomingState::Waiting {
+				future: self.transports.next_incoming(),
+				upgrade: self.upgrade,
+				unsupported_protocols: self.unsupported_protocols,
+			},
+		}
 	}
 
 	/// Start listening on the multiaddr using the transport that was passed to `new`.
@@ -904,18 +1467,13 @@ where
 	/// Note that this does the same as `Transport::listen_on`, but with less restrictions on the
 	/// trait requirements.
 	#[inline]
-	pub fn listen_on(
-		self,
-		addr: Multiaddr,
-	) -> Result<
-		(Box<Stream<Item = (Box<Future<Item = C::Output, Error = IoError> + 'a>, Multiaddr), Error = IoError> + 'a>, Multiaddr),
-		(Self, Multiaddr),
-	>
-	where
-		C::NamesIter: Clone, // TODO: not elegant
-		C: Clone,
+	pub fn listen_on(self, addr: Multiaddr)
+					 -> Result<(UpgradedNodeListener<T, C>, Multiaddr), (Self, Multiaddr)>
+		where C::NamesIter: Clone, // TODO: not elegant
+			  C: Clone,
 	{
 		let upgrade = self.upgrade;
+		let unsupported_protocols = self.unsupported_protocols;
 
 		let (listening_stream, new_addr) = match self.transports.listen_on(addr) {
 			Ok((l, new_addr)) => (l, new_addr),
@@ -923,53 +1481,35 @@ where
 				let builder = UpgradedNode {
 					transports: trans,
 					upgrade: upgrade,
+					unsupported_protocols: unsupported_protocols,
 				};
 
 				return Err((builder, addr));
 			}
 		};
 
-		// Try to negotiate the protocol.
-		// Note that failing to negotiate a protocol will never produce a future with an error.
-		// Instead the `stream` will produce `Ok(Err(...))`.
-		// `stream` can only produce an `Err` if `listening_stream` produces an `Err`.
-		let stream = listening_stream
-			.map(move |(connection, client_addr)| {
-				let upgrade = upgrade.clone();
-				let remote_addr = client_addr.clone();
-				let connection = connection
-					// Try to negotiate the protocol
-					.and_then(move |connection| {
-						let iter = upgrade.protocol_names()
-							.map::<_, fn(_) -> _>(|(n, t)| (n, <Bytes as PartialEq>::eq, t));
-						multistream_select::listener_select_proto(connection, iter)
-							.map_err(|err| IoError::new(IoErrorKind::Other, err))
-							.and_then(move |(upgrade_id, connection)| {
-								upgrade.upgrade(connection, upgrade_id, Endpoint::Listener,
-												&remote_addr)
-							})
-							.into_future()
-					});
-
-				(Box::new(connection) as Box<_>, client_addr)
-			});
-
-		Ok((Box::new(stream), new_addr))
+		let listener = UpgradedNodeListener {
+			inner: listening_stream,
+			upgrade: upgrade,
+			unsupported_protocols: unsupported_protocols,
+		};
+
+		Ok((listener, new_addr))
 	}
 }
 
 impl<T, C> Transport for UpgradedNode<T, C>
 where
-	T: Transport + 'static,
-	C: ConnectionUpgrade<T::RawConn> + 'static,
+	T: Transport,
+	C: ConnectionUpgrade<T::RawConn>,
 	C::Output: AsyncRead + AsyncWrite,
 	C::NamesIter: Clone, // TODO: not elegant
 	C: Clone,
 {
 	type RawConn = C::Output;
-	type Listener = Box<Stream<Item = (Self::ListenerUpgrade, Multiaddr), Error = IoError>>;
-	type ListenerUpgrade = Box<Future<Item = C::Output, Error = IoError>>;
-	type Dial = Box<Future<Item = C::Output, Error = IoError>>;
+	type Listener = UpgradedNodeListener<T, C>;
+	type ListenerUpgrade = UpgradedNodeListenerUpgrade<T, C>;
+	type Dial = UpgradedNodeDial<T, C>;
 
 	#[inline]
 	fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
@@ -985,20 +1525,649 @@ where
 	fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
 		self.transports.nat_traversal(server, observed)
 	}
+
+	#[inline]
+	fn expand_listen_addr(&self, addr: &Multiaddr) -> Vec<Multiaddr> {
+		self.transports.expand_listen_addr(addr)
+	}
 }
 
 impl<T, C> MuxedTransport for UpgradedNode<T, C>
 where
-	T: MuxedTransport + 'static,
-	C: ConnectionUpgrade<T::RawConn> + 'static,
+	T: MuxedTransport,
+	C: ConnectionUpgrade<T::RawConn>,
 	C::Output: AsyncRead + AsyncWrite,
 	C::NamesIter: Clone, // TODO: not elegant
 	C: Clone,
 {
-	type Incoming = Box<Future<Item = (C::Output, Multiaddr), Error = IoError>>;
+	type Incoming = UpgradedNodeIncoming<T, C>;
 
 	#[inline]
 	fn next_incoming(self) -> Self::Incoming {
 		self.next_incoming()
 	}
 }
+
+// Function that checks whether a protocol name sent by the remote matches one of ours.
+pub(crate) type MatchFn = fn(&Bytes, &Bytes) -> bool;
+
+// Iterator of the protocol names of an upgrade, in the format expected by `multistream_select`.
+pub(crate) type NamesWithMatch<C, Id> = iter::Map<C, fn((Bytes, Id)) -> (Bytes, MatchFn, Id)>;
+
+// Returns the protocol names of `upgrade`, in the format expected by `multistream_select`.
+pub(crate) fn names_with_match<C, U>(upgrade: &U)
+	-> NamesWithMatch<U::NamesIter, U::UpgradeIdentifier>
+	where C: AsyncRead + AsyncWrite,
+		  U: ConnectionUpgrade<C>
+{
+	fn with_match<Id>((name, id): (Bytes, Id)) -> (Bytes, MatchFn, Id) {
+		let matches: MatchFn = <Bytes as PartialEq>::eq;
+		(name, matches, id)
+	}
+
+	upgrade.protocol_names().map(with_match::<U::UpgradeIdentifier> as fn(_) -> _)
+}
+
+/// Future that dials a node and upgrades the connection. Returned by `UpgradedNode::dial`.
+///
+/// This future doesn't box anything, therefore it implements `Send` if the transport, the upgrade
+/// and their futures do.
+pub struct UpgradedNodeDial<T, C>
+where
+	T: Transport,
+	C: ConnectionUpgrade<T::RawConn>,
+{
+	inner: UpgradedNodeDialState<T, C>,
+}
+
+enum UpgradedNodeDialState<T, C>
+where
+	T: Transport,
+	C: ConnectionUpgrade<T::RawConn>,
+{
+	Dialing {
+		future: <T::Dial as IntoFuture>::Future,
+		upgrade: C,
+		addr: Multiaddr,
+	},
+	Negotiating {
+		future: multistream_select::DialerSelectFuture<
+			T::RawConn,
+			NamesWithMatch<C::NamesIter, C::UpgradeIdentifier>,
+			C::UpgradeIdentifier,
+		>,
+		upgrade: C,
+		addr: Multiaddr,
+	},
+	Upgrading {
+		future: C::Future,
+	},
+	// Temporary state while switching between the other states.
+	Undefined,
+}
+
+impl<T, C> Future for UpgradedNodeDial<T, C>
+where
+	T: Transport,
+	C: ConnectionUpgrade<T::RawConn>,
+{
+	type Item = C::Output;
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		loop {
+			match mem::replace(&mut self.inner, UpgradedNodeDialState::Undefined) {
+				UpgradedNodeDialState::Dialing { mut future, upgrade, addr } => {
+					let connection = match future.poll()? {
+						Async::Ready(connection) => connection,
+						Async::NotReady => {
+							self.inner = UpgradedNodeDialState::Dialing {
+								future: future,
+								upgrade: upgrade,
+								addr: addr,
+							};
+							return Ok(Async::NotReady);
+						},
+					};
+
+					// Try to negotiate the protocol.
+					let names = names_with_match::<T::RawConn, _>(&upgrade);
+					self.inner = UpgradedNodeDialState::Negotiating {
+						future: multistream_select::dialer_select_proto(connection, names),
+						upgrade: upgrade,
+						addr: addr,
+					};
+				},
+
+				UpgradedNodeDialState::Negotiating { mut future, upgrade, addr } => {
+					let (upgrade_id, connection) = match future.poll() {
+						Ok(Async::Ready(val)) => val,
+						Ok(Async::NotReady) => {
+							self.inner = UpgradedNodeDialState::Negotiating {
+								future: future,
+								upgrade: upgrade,
+								addr: addr,
+							};
+							return Ok(Async::NotReady);
+						},
+						Err(err) => return Err(UpgradeError::Negotiation(err).into()),
+					};
+
+					let future = upgrade.upgrade(connection, upgrade_id, Endpoint::Dialer, &addr);
+					self.inner = UpgradedNodeDialState::Upgrading { future: future };
+				},
+
+				UpgradedNodeDialState::Upgrading { mut future } => {
+					return match future.poll() {
+						Ok(Async::Ready(output)) => Ok(Async::Ready(output)),
+						Ok(Async::NotReady) => {
+							self.inner = UpgradedNodeDialState::Upgrading { future: future };
+							Ok(Async::NotReady)
+						},
+						Err(err) => Err(UpgradeError::from(err).into()),
+					};
+				},
+
+				UpgradedNodeDialState::Undefined => {
+					panic!("UpgradedNodeDial polled after completion")
+				},
+			}
+		}
+	}
+}
+
+/// Future that dials a node that may be dialing us at the same time, and upgrades the connection.
+/// Returned by `UpgradedNode::dial_simultaneous`.
+///
+/// Produces the upgraded connection and the `Endpoint` that the upgrade was applied with.
+pub struct UpgradedNodeSimultaneousDial<T, C>
+where
+	T: Transport,
+	C: ConnectionUpgrade<T::RawConn>,
+{
+	inner: UpgradedNodeSimultaneousDialState<T, C>,
+}
+
+enum UpgradedNodeSimultaneousDialState<T, C>
+where
+	T: Transport,
+	C: ConnectionUpgrade<T::RawConn>,
+{
+	Dialing {
+		future: <T::Dial as IntoFuture>::Future,
+		upgrade: C,
+		addr: Multiaddr,
+	},
+	Negotiating {
+		future: multistream_select::SimultaneousSelectFuture<
+			T::RawConn,
+			NamesWithMatch<C::NamesIter, C::UpgradeIdentifier>,
+			C::UpgradeIdentifier,
+		>,
+		upgrade: C,
+		addr: Multiaddr,
+	},
+	Upgrading {
+		future: C::Future,
+		endpoint: Endpoint,
+	},
+	// Temporary state while switching between the other states.
+	Undefined,
+}
+
+impl<T, C> Future for UpgradedNodeSimultaneousDial<T, C>
+where
+	T: Transport,
+	C: ConnectionUpgrade<T::RawConn>,
+	C::NamesIter: Clone, // TODO: not elegant
+{
+	type Item = (C::Output, Endpoint);
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		loop {
+			match mem::replace(&mut self.inner, UpgradedNodeSimultaneousDialState::Undefined) {
+				UpgradedNodeSimultaneousDialState::Dialing { mut future, upgrade, addr } => {
+					let connection = match future.poll()? {
+						Async::Ready(connection) => connection,
+						Async::NotReady => {
+							self.inner = UpgradedNodeSimultaneousDialState::Dialing {
+								future: future,
+								upgrade: upgrade,
+								addr: addr,
+							};
+							return Ok(Async::NotReady);
+						},
+					};
+
+					// Try to negotiate the protocol and the roles.
+					let names = names_with_match::<T::RawConn, _>(&upgrade);
+					self.inner = UpgradedNodeSimultaneousDialState::Negotiating {
+						future: multistream_select::simultaneous_select_proto(connection, names),
+						upgrade: upgrade,
+						addr: addr,
+					};
+				},
+
+				UpgradedNodeSimultaneousDialState::Negotiating { mut future, upgrade, addr } => {
+					let (upgrade_id, connection, role) = match future.poll() {
+						Ok(Async::Ready(val)) => val,
+						Ok(Async::NotReady) => {
+							self.inner = UpgradedNodeSimultaneousDialState::Negotiating {
+								future: future,
+								upgrade: upgrade,
+								addr: addr,
+							};
+							return Ok(Async::NotReady);
+						},
+						Err(err) => return Err(UpgradeError::Negotiation(err).into()),
+					};
+
+					let endpoint = match role {
+						multistream_select::Role::Initiator => Endpoint::Dialer,
+						multistream_select::Role::Responder => Endpoint::Listener,
+					};
+					let future = upgrade.upgrade(connection, upgrade_id, endpoint, &addr);
+					self.inner = UpgradedNodeSimultaneousDialState::Upgrading {
+						future: future,
+						endpoint: endpoint,
+					};
+				},
+
+				UpgradedNodeSimultaneousDialState::Upgrading { mut future, endpoint } => {
+					return match future.poll() {
+						Ok(Async::Ready(output)) => Ok(Async::Ready((output, endpoint))),
+						Ok(Async::NotReady) => {
+							self.inner = UpgradedNodeSimultaneousDialState::Upgrading {
+								future: future,
+								endpoint: endpoint,
+							};
+							Ok(Async::NotReady)
+						},
+						Err(err) => Err(UpgradeError::from(err).into()),
+					};
+				},
+
+				UpgradedNodeSimultaneousDialState::Undefined => {
+					panic!("UpgradedNodeSimultaneousDial polled after completion")
+				},
+			}
+		}
+	}
+}
+
+/// Stream of incoming connections that get upgraded. Returned by `UpgradedNode::listen_on`.
+///
+/// Failing to negotiate a protocol never produces an error on the stream. Instead, the error is
+/// produced by the `UpgradedNodeListenerUpgrade` of the connection. The stream only produces an
+/// error if the underlying listener does.
+pub struct UpgradedNodeListener<T, C>
+where
+	T: Transport,
+{
+	inner: T::Listener,
+	upgrade: C,
+	unsupported_protocols: UnsupportedProtocols,
+}
+
+impl<T, C> Stream for UpgradedNodeListener<T, C>
+where
+	T: Transport,
+	C: ConnectionUpgrade<T::RawConn> + Clone,
+	C::NamesIter: Clone, // TODO: not elegant
+{
+	type Item = (UpgradedNodeListenerUpgrade<T, C>, Multiaddr);
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+		let (connection, client_addr) = match self.inner.poll()? {
+			Async::Ready(Some(val)) => val,
+			Async::Ready(None) => return Ok(Async::Ready(None)),
+			Async::NotReady => return Ok(Async::NotReady),
+		};
+
+		let upgrade = UpgradedNodeListenerUpgrade {
+			inner: UpgradedNodeListenerUpgradeState::Accepting {
+				future: connection,
+				upgrade: self.upgrade.clone(),
+				unsupported_protocols: self.unsupported_protocols.clone(),
+				addr: client_addr.clone(),
+			},
+		};
+
+		Ok(Async::Ready(Some((upgrade, client_addr))))
+	}
+}
+
+/// Future that upgrades an incoming connection. Produced by `UpgradedNodeListener`.
+pub struct UpgradedNodeListenerUpgrade<T, C>
+where
+	T: Transport,
+	C: ConnectionUpgrade<T::RawConn>,
+{
+	inner: UpgradedNodeListenerUpgradeState<T, C>,
+}
+
+enum UpgradedNodeListenerUpgradeState<T, C>
+where
+	T: Transport,
+	C: ConnectionUpgrade<T::RawConn>,
+{
+	Accepting {
+		future: T::ListenerUpgrade,
+		upgrade: C,
+		unsupported_protocols: UnsupportedProtocols,
+		addr: Multiaddr,
+	},
+	Negotiating {
+		future: multistream_select::ListenerSelectFuture<
+			T::RawConn,
+			NamesWithMatch<C::NamesIter, C::UpgradeIdentifier>,
+			C::UpgradeIdentifier,
+			UnsupportedProtocols,
+		>,
+		upgrade: C,
+		addr: Multiaddr,
+	},
+	Upgrading {
+		future: C::Future,
+	},
+	// Temporary state while switching between the other states.
+	Undefined,
+}
+
+impl<T, C> Future for UpgradedNodeListenerUpgrade<T, C>
+where
+	T: Transport,
+	C: ConnectionUpgrade<T::RawConn>,
+	C::NamesIter: Clone, // TODO: not elegant
+{
+	type Item = C::Output;
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		loop {
+			match mem::replace(&mut self.inner, UpgradedNodeListenerUpgradeState::Undefined) {
+				UpgradedNodeListenerUpgradeState::Accepting {
+					mut future, upgrade, unsupported_protocols, addr
+				} => {
+					let connection = match future.poll()? {
+						Async::Ready(connection) => connection,
+						Async::NotReady => {
+							self.inner = UpgradedNodeListenerUpgradeState::Accepting {
+								future: future,
+								upgrade: upgrade,
+								unsupported_protocols: unsupported_protocols,
+								addr: addr,
+							};
+							return Ok(Async::NotReady);
+						},
+					};
+
+					// Try to negotiate the protocol.
+					let names = names_with_match::<T::RawConn, _>(&upgrade);
+					let future = multistream_select::listener_select_proto_with_observer(
+						connection, names, unsupported_protocols);
+					self.inner = UpgradedNodeListenerUpgradeState::Negotiating {
+						future: future,
+						upgrade: upgrade,
+						addr: addr,
+					};
+				},
+
+				UpgradedNodeListenerUpgradeState::Negotiating { mut future, upgrade, addr } => {
+					let (upgrade_id, connection) = match future.poll() {
+						Ok(Async::Ready(val)) => val,
+						Ok(Async::NotReady) => {
+							self.inner = UpgradedNodeListenerUpgradeState::Negotiating {
+								future: future,
+								upgrade: upgrade,
+								addr: addr,
+							};
+							return Ok(Async::NotReady);
+						},
+						Err(err) => return Err(UpgradeError::Negotiation(err).into()),
+					};
+
+					let future = upgrade.upgrade(connection, upgrade_id, Endpoint::Listener, &addr);
+					self.inner = UpgradedNodeListenerUpgradeState::Upgrading { future: future };
+				},
+
+				UpgradedNodeListenerUpgradeState::Upgrading { mut future } => {
+					return match future.poll() {
+						Ok(Async::Ready(output)) => Ok(Async::Ready(output)),
+						Ok(Async::NotReady) => {
+							self.inner = UpgradedNodeListenerUpgradeState::Upgrading {
+								future: future,
+							};
+							Ok(Async::NotReady)
+						},
+						Err(err) => Err(UpgradeError::from(err).into()),
+					};
+				},
+
+				UpgradedNodeListenerUpgradeState::Undefined => {
+					panic!("UpgradedNodeListenerUpgrade polled after completion")
+				},
+			}
+		}
+	}
+}
+
+/// Future that produces the next incoming substream, upgraded. Returned by
+/// `UpgradedNode::next_incoming`.
+pub struct UpgradedNodeIncoming<T, C>
+where
+	T: MuxedTransport,
+	C: ConnectionUpgrade<T::RawConn>,
+{
+	inner: UpgradedNodeIncomingState<T, C>,
+}
+
+enum UpgradedNodeIncomingState<T, C>
+where
+	T: MuxedTransport,
+	C: ConnectionUpgrade<T::RawConn>,
+{
+	Waiting {
+		future: T::Incoming,
+		upgrade: C,
+		unsupported_protocols: UnsupportedProtocols,
+	},
+	Negotiating {
+		future: multistream_select::ListenerSelectFuture<
+			T::RawConn,
+			NamesWithMatch<C::NamesIter, C::UpgradeIdentifier>,
+			C::UpgradeIdentifier,
+			UnsupportedProtocols,
+		>,
+		upgrade: C,
+		addr: Multiaddr,
+	},
+	Upgrading {
+		future: C::Future,
+		addr: Multiaddr,
+	},
+	// Temporary state while switching between the other states.
+	Undefined,
+}
+
+impl<T, C> Future for UpgradedNodeIncoming<T, C>
+where
+	T: MuxedTransport,
+	C: ConnectionUpgrade<T::RawConn>,
+	C::NamesIter: Clone, // TODO: not elegant
+{
+	type Item = (C::Output, Multiaddr);
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		loop {
+			match mem::replace(&mut self.inner, UpgradedNodeIncomingState::Undefined) {
+				UpgradedNodeIncomingState::Waiting {
+					mut future, upgrade, unsupported_protocols
+				} => {
+					let (connection, addr) = match future.poll()? {
+						Async::Ready(val) => val,
+						Async::NotReady => {
+							self.inner = UpgradedNodeIncomingState::Waiting {
+								future: future,
+								upgrade: upgrade,
+								unsupported_protocols: unsupported_protocols,
+							};
+							return Ok(Async::NotReady);
+						},
+					};
+
+					// Try to negotiate the protocol.
+					let names = names_with_match::<T::RawConn, _>(&upgrade);
+					let future = multistream_select::listener_select_proto_with_observer(
+						connection, names, unsupported_protocols);
+					self.inner = UpgradedNodeIncomingState::Negotiating {
+						future: future,
+						upgrade: upgrade,
+						addr: addr,
+					};
+				},
+
+				UpgradedNodeIncomingState::Negotiating { mut future, upgrade, addr } => {
+					let (upgrade_id, connection) = match future.poll() {
+						Ok(Async::Ready(val)) => val,
+						Ok(Async::NotReady) => {
+							self.inner = UpgradedNodeIncomingState::Negotiating {
+								future: future,
+								upgrade: upgrade,
+								addr: addr,
+							};
+							return Ok(Async::NotReady);
+						},
+						Err(err) => return Err(UpgradeError::Negotiation(err).into()),
+					};
+
+					let future = upgrade.upgrade(connection, upgrade_id, Endpoint::Dialer, &addr);
+					self.inner = UpgradedNodeIncomingState::Upgrading {
+						future: future,
+						addr: addr,
+					};
+				},
+
+				UpgradedNodeIncomingState::Upgrading { mut future, addr } => {
+					return match future.poll() {
+						Ok(Async::Ready(output)) => Ok(Async::Ready((output, addr))),
+						Ok(Async::NotReady) => {
+							self.inner = UpgradedNodeIncomingState::Upgrading {
+								future: future,
+								addr: addr,
+							};
+							Ok(Async::NotReady)
+						},
+						Err(err) => Err(UpgradeError::from(err).into()),
+					};
+				},
+
+				UpgradedNodeIncomingState::Undefined => {
+					panic!("UpgradedNodeIncoming polled after completion")
+				},
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ConnectionUpgrade, DeniedTransport, Endpoint, PlainTextConfig, SimpleProtocol};
+	use super::{Transport, UpgradeExt};
+	use super::{UpgradedNodeDial, UpgradedNodeIncoming, UpgradedNodeSimultaneousDial};
+	use super::{UpgradedNodeListener, UpgradedNodeListenerUpgrade};
+	use super::{UnsupportedProtocols, MAX_UNSUPPORTED_PROTOCOLS};
+	use multistream_select::{UnsupportedObserver, UnsupportedResponse};
+	use bytes::Bytes;
+	use futures::Future;
+	use std::io::{Cursor, Error as IoError};
+
+	fn assert_send<T: Send>() {}
+
+	#[test]
+	fn upgraded_node_futures_are_send() {
+		assert_send::<UpgradedNodeDial<DeniedTransport, PlainTextConfig>>();
+		assert_send::<UpgradedNodeIncoming<DeniedTransport, PlainTextConfig>>();
+		assert_send::<UpgradedNodeListener<DeniedTransport, PlainTextConfig>>();
+		assert_send::<UpgradedNodeListenerUpgrade<DeniedTransport, PlainTextConfig>>();
+		assert_send::<UpgradedNodeSimultaneousDial<DeniedTransport, PlainTextConfig>>();
+	}
+
+	#[test]
+	fn preference_order() {
+		let upgrade = SimpleProtocol::new("/ipfs/id/1.0.0", |s| Ok::<_, IoError>(s))
+			.or_upgrade(PlainTextConfig)
+			.or_upgrade(SimpleProtocol::new("/ipfs/id/2.0.0", |s| Ok::<_, IoError>(s)))
+			.with_preference(vec!["/ipfs/id/2.0.0", "/ipfs/id/1.0.0"]);
+
+		let names = ConnectionUpgrade::<Cursor<Vec<u8>>>::protocol_names(&upgrade)
+			.map(|(name, _)| name)
+			.collect::<Vec<_>>();
+		assert_eq!(names, vec![
+			Bytes::from("/ipfs/id/2.0.0"),
+			Bytes::from("/ipfs/id/1.0.0"),
+			Bytes::from("/plaintext/1.0.0"),
+		]);
+	}
+
+	#[test]
+	fn negotiated_name() {
+		let upgrade = SimpleProtocol::new("/noise", |s| Ok::<_, IoError>(s))
+			.or_upgrade(PlainTextConfig)
+			.with_preference(vec!["/noise", "/plaintext/1.0.0"])
+			.with_negotiated_name();
+
+		// Simulates the negotiation of the second protocol.
+		let (name, id) = ConnectionUpgrade::<Cursor<Vec<u8>>>::protocol_names(&upgrade)
+			.nth(1)
+			.unwrap();
+		assert_eq!(name, Bytes::from("/plaintext/1.0.0"));
+
+		let addr = "/ip4/1.2.3.4/tcp/5".parse().unwrap();
+		let output = upgrade.upgrade(Cursor::new(Vec::new()), id, Endpoint::Dialer, &addr)
+			.wait()
+			.unwrap();
+		assert_eq!(output.protocol_name(), b"/plaintext/1.0.0");
+	}
+
+	#[test]
+	fn replace_upgrade_shares_counters() {
+		let node = DeniedTransport.with_upgrade(PlainTextConfig);
+		let other = node.clone()
+			.replace_upgrade(SimpleProtocol::new("/ipfs/id/1.0.0", |s| Ok::<_, IoError>(s)));
+
+		node.unsupported_protocols().increment(&Bytes::from("/foo/1.0.0"));
+		assert_eq!(other.unsupported_protocols().count(b"/foo/1.0.0"), 1);
+	}
+
+	#[test]
+	fn unsupported_protocols_bounded() {
+		let unsupported = UnsupportedProtocols::new();
+		for n in 0 .. MAX_UNSUPPORTED_PROTOCOLS + 10 {
+			unsupported.increment(&Bytes::from(format!("/foo/{}", n)));
+		}
+		unsupported.increment(&Bytes::from("/foo/0"));
+
+		assert_eq!(unsupported.list().len(), MAX_UNSUPPORTED_PROTOCOLS);
+		assert_eq!(unsupported.count(b"/foo/0"), 2);
+		assert_eq!(unsupported.count(b"/foo/130"), 0);
+		assert_eq!(unsupported.other(), 10);
+	}
+
+	#[test]
+	fn unsupported_response_is_shared() {
+		let unsupported = UnsupportedProtocols::new();
+		assert_eq!(unsupported.response(), UnsupportedResponse::NotAvailable);
+		unsupported.clone().set_response(UnsupportedResponse::NotAvailableWithHint);
+		assert_eq!(unsupported.response(), UnsupportedResponse::NotAvailableWithHint);
+	}
+
+	#[test]
+	fn supported_protocols() {
+		let node = DeniedTransport.with_upgrade(PlainTextConfig);
+		assert_eq!(node.supported_protocols(), vec!["/plaintext/1.0.0".to_owned()]);
+	}
+}
@@ -48,21 +48,74 @@
 //!
 //! The `TcpConfig` structs implements the `Transport` trait of the `swarm` library. See the
 //! documentation of `swarm` and of libp2p in general to learn how to use the `Transport` trait.
+//!
+//! The `TcpConfig` and its futures implement `Send`, and can be used from any thread. The steps
+//! that require the tokio `Handle`, such as registering a socket, are run on the thread of the
+//! `Core`, which must therefore keep running.
+//!
+//! The `with_nodelay()`, `with_keepalive()` and similar methods of `TcpConfig` configure the
+//! options of the dialed and accepted sockets. For example, latency-sensitive protocols usually
+//! want to disable Nagle's algorithm with `with_nodelay(true)`.
+//!
+//! Calling `with_dial_timeout()` makes the dials to unreachable addresses fail quickly, instead of
+//! waiting for the operating system to give up.
+//!
+//! Calling `with_port_reuse(true)` makes the dials use the same port as the listeners, so that the
+//! remotes observe the address that we listen on. This is necessary for NAT hole punching.
+//!
+//! # IPv6
+//!
+//! The `/ip6/<ip>/tcp/<port>` addresses are supported for both listening and dialing. By default
+//! the IPv6 listeners only accept IPv6 connections (the `IPV6_V6ONLY` option), which makes it
+//! possible to listen on `/ip4/0.0.0.0/tcp/<port>` and `/ip6/::/tcp/<port>` at the same time.
+//!
+//! Calling `with_ipv6_only(false)` turns the IPv6 listeners into dual-stack listeners instead. A
+//! listener on `/ip6/::/tcp/<port>` then accepts the IPv4 connections as well, whose remote
+//! address is reported as an `/ip4` address, and `expand_listen_addr()` reports the addresses of
+//! both families.
+//!
+//! # SOCKS5 proxy
+//!
+//! Calling `with_socks5_proxy()` makes all the dials go through a SOCKS5 proxy, for example the
+//! one of Tor at `127.0.0.1:9050`. In that case the `/dns4/<name>/tcp/<port>` and
+//! `/dns6/<name>/tcp/<port>` addresses can be dialed as well, and the name is resolved by the
+//! proxy instead of locally.
+//!
+//! > **Note**: Don't put a DNS transport on top of a `TcpConfig` that uses a proxy, as it would
+//! >           resolve the names locally before they reach the proxy.
+//!
+//! > **Note**: The proxy is only used for dialing. Listening still happens on the local machine.
 
 extern crate libp2p_swarm as swarm;
 extern crate tokio_core;
 extern crate tokio_io;
 extern crate multiaddr;
 extern crate futures;
+extern crate get_if_addrs;
+extern crate net2;
+extern crate parking_lot;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+extern crate libc;
+
+mod options;
+mod port_reuse;
+mod socks5;
 
-use std::io::Error as IoError;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::iter;
-use std::net::SocketAddr;
-use tokio_core::reactor::Handle;
-use tokio_core::net::{TcpStream, TcpListener, TcpStreamNew};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use std::net::TcpListener as StdTcpListener;
+use tokio_core::reactor::{Handle, Remote, Timeout};
+use tokio_core::net::{TcpStream, TcpListener};
 use futures::future::{self, Future, FutureResult, IntoFuture};
+use futures::sync::oneshot;
 use futures::stream::Stream;
 use multiaddr::{Multiaddr, AddrComponent, ToMultiaddr};
+use net2::TcpBuilder;
+use options::SocketOptions;
+use port_reuse::PortReuse;
+use socks5::{Socks5Proxy, Socks5Target};
 use swarm::Transport;
 
 /// Represents the configuration for a TCP/IP transport capability for libp2p.
@@ -72,7 +125,19 @@ use swarm::Transport;
 /// through the tokio reactor.
 #[derive(Debug, Clone)]
 pub struct TcpConfig {
-    event_loop: Handle,
+    // The `Handle` itself isn't `Send`, therefore we only keep a `Remote` and use the `Handle`
+    // on the thread of the reactor. See `on_event_loop`.
+    event_loop: Remote,
+    // If `Some`, all the dials go through this proxy.
+    socks5_proxy: Option<Socks5Proxy>,
+    // Applied to the dialed and accepted sockets.
+    options: SocketOptions,
+    // If `Some`, the dials reuse the port of the listeners.
+    port_reuse: Option<PortReuse>,
+    // Value of the `IPV6_V6ONLY` option of the IPv6 listeners.
+    ipv6_only: bool,
+    // If `Some`, the dials that take longer than this fail.
+    dial_timeout: Option<Duration>,
 }
 
 impl TcpConfig {
@@ -80,21 +145,170 @@ impl TcpConfig {
     /// connections will be created with.
     #[inline]
     pub fn new(handle: Handle) -> TcpConfig {
-        TcpConfig { event_loop: handle }
+        TcpConfig {
+            event_loop: handle.remote().clone(),
+            socks5_proxy: None,
+            options: SocketOptions::default(),
+            port_reuse: None,
+            ipv6_only: true,
+            dial_timeout: None,
+        }
+    }
+
+    /// Makes the dials fail with an error of kind `TimedOut` if the connection isn't established
+    /// after `timeout`, including the handshake with the SOCKS5 proxy if any. Without a timeout,
+    /// dialing an unreachable address can take minutes before the operating system gives up.
+    ///
+    /// > **Note**: This only covers establishing the TCP connection. Use the
+    /// >           `libp2p-transport-timeout` crate in order to bound the upgrades as well.
+    #[inline]
+    pub fn with_dial_timeout(mut self, timeout: Duration) -> TcpConfig {
+        self.dial_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the `IPV6_V6ONLY` option of the IPv6 listeners. If `false`, the listeners on an IPv6
+    /// address also accept the IPv4 connections, and the listen addresses of the IPv4 interfaces
+    /// are reported by `expand_listen_addr()` as well. Defaults to `true`.
+    ///
+    /// > **Note**: A dual-stack listener on `/ip6/::/tcp/<port>` prevents listening on
+    /// >           `/ip4/0.0.0.0/tcp/<port>`, as the port is already taken.
+    #[inline]
+    pub fn with_ipv6_only(mut self, ipv6_only: bool) -> TcpConfig {
+        self.ipv6_only = ipv6_only;
+        self
+    }
+
+    /// If `true`, the dials are bound to the same port as a listener of this `TcpConfig` (or of
+    /// one of its clones) with the same IP version, so that the remotes observe the address that
+    /// we listen on. This is necessary for NAT hole punching.
+    ///
+    /// The listeners created after this call set the `SO_REUSEADDR` and `SO_REUSEPORT` options
+    /// on their socket, so that the dials can bind to the same port.
+    ///
+    /// > **Note**: If the port can't be reused, the dial uses a port chosen by the operating
+    /// >           system instead. The dials through a SOCKS5 proxy never reuse the port.
+    #[inline]
+    pub fn with_port_reuse(mut self, port_reuse: bool) -> TcpConfig {
+        self.port_reuse = if port_reuse {
+            Some(PortReuse::default())
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Sets the `TCP_NODELAY` option of the sockets. If `true`, Nagle's algorithm is disabled and
+    /// the small writes are sent immediately instead of being grouped together.
+    #[inline]
+    pub fn with_nodelay(mut self, nodelay: bool) -> TcpConfig {
+        self.options.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Enables the keepalive probes of the operating system after the connection has been idle
+    /// for `time`, or disables them if `None`.
+    #[inline]
+    pub fn with_keepalive(mut self, time: Option<Duration>) -> TcpConfig {
+        self.options.keepalive = Some(time);
+        self
+    }
+
+    /// Sets the time between two keepalive probes. Rounded to seconds.
+    ///
+    /// > **Note**: Only supported on Linux and Android. Ignored on the other platforms.
+    #[inline]
+    pub fn with_keepalive_interval(mut self, interval: Duration) -> TcpConfig {
+        self.options.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Sets the time-to-live of the packets sent on the sockets.
+    #[inline]
+    pub fn with_ttl(mut self, ttl: u32) -> TcpConfig {
+        self.options.ttl = Some(ttl);
+        self
+    }
+
+    /// Sets the size of the receive buffer of the sockets (`SO_RCVBUF`).
+    #[inline]
+    pub fn with_recv_buffer_size(mut self, size: usize) -> TcpConfig {
+        self.options.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets the size of the send buffer of the sockets (`SO_SNDBUF`).
+    #[inline]
+    pub fn with_send_buffer_size(mut self, size: usize) -> TcpConfig {
+        self.options.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Dials through the SOCKS5 proxy at `proxy` instead of connecting directly. Also allows
+    /// dialing `/dns4` and `/dns6` addresses, whose names are resolved by the proxy.
+    #[inline]
+    pub fn with_socks5_proxy(mut self, proxy: SocketAddr) -> TcpConfig {
+        self.socks5_proxy = Some(Socks5Proxy {
+            addr: proxy,
+            credentials: None,
+        });
+        self
+    }
+
+    /// Same as `with_socks5_proxy()`, but authenticates to the proxy with a username and a
+    /// password. Tor, for example, uses separate circuits for different credentials.
+    ///
+    /// The username and the password must be between 1 and 255 bytes long, otherwise the dials
+    /// fail.
+    #[inline]
+    pub fn with_socks5_proxy_auth(mut self, proxy: SocketAddr, username: String, password: String)
+                                  -> TcpConfig
+    {
+        self.socks5_proxy = Some(Socks5Proxy {
+            addr: proxy,
+            credentials: Some((username, password)),
+        });
+        self
+    }
+}
+
+impl TcpConfig {
+    // Creates a listener bound to `addr`, with the options of this config. It must then be
+    // registered to the reactor.
+    fn bind_listener(&self, addr: &SocketAddr) -> Result<StdTcpListener, IoError> {
+        let builder = if addr.is_ipv4() {
+            TcpBuilder::new_v4()?
+        } else {
+            let builder = TcpBuilder::new_v6()?;
+            builder.only_v6(self.ipv6_only)?;
+            builder
+        };
+
+        // Same as what `TcpListener::bind()` does.
+        if cfg!(unix) {
+            builder.reuse_address(true)?;
+        }
+        if self.port_reuse.is_some() {
+            port_reuse::set_reusable(&builder)?;
+        }
+
+        builder.bind(addr)?;
+        builder.listen(1024)
     }
 }
 
 impl Transport for TcpConfig {
     type RawConn = TcpStream;
-    type Listener = Box<Stream<Item = (Self::ListenerUpgrade, Multiaddr), Error = IoError>>;
+    type Listener = Box<Stream<Item = (Self::ListenerUpgrade, Multiaddr), Error = IoError> + Send>;
     type ListenerUpgrade = FutureResult<Self::RawConn, IoError>;
-    type Dial = TcpStreamNew;
+    type Dial = Box<Future<Item = TcpStream, Error = IoError> + Send>;
 
     /// Listen on the given multi-addr.
     /// Returns the address back if it isn't supported.
     fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
         if let Ok(socket_addr) = multiaddr_to_socketaddr(&addr) {
-            let listener = TcpListener::bind(&socket_addr, &self.event_loop);
+            let listener = self.bind_listener(&socket_addr);
+            let local_addr = listener.as_ref().ok().and_then(|l| l.local_addr().ok());
             // We need to build the `Multiaddr` to return from this function. If an error happened,
             // just return the original multiaddr.
             let new_addr = match listener {
@@ -107,16 +321,29 @@ impl Transport for TcpConfig {
                 Err(_) => addr,
             };
 
-            let future = future::result(listener).map(|listener| {
+            let options = self.options;
+            let event_loop = self.event_loop.clone();
+            let future = future::result(listener)
+                .and_then(move |listener| {
+                    on_event_loop(&event_loop, move |handle| {
+                        TcpListener::from_listener(listener, &socket_addr, handle)
+                    })
+                })
+                .map(move |listener| {
                     // Pull out a stream of sockets for incoming connections
-                    listener.incoming().map(|(sock, addr)| {
-                        let addr = addr.to_multiaddr()
-                            .expect("generating a multiaddr from a socket addr never fails");
-                        (Ok(sock).into_future(), addr)
+                    listener.incoming().map(move |(sock, addr)| {
+                        let addr = socketaddr_to_multiaddr(&addr);
+                        (options.apply(&sock).map(|()| sock).into_future(), addr)
                     })
                 })
-                    .flatten_stream();
-            Ok((Box::new(future), new_addr))
+                .flatten_stream();
+
+            match (self.port_reuse, local_addr) {
+                (Some(port_reuse), Some(local_addr)) => {
+                    Ok((Box::new(port_reuse.register(local_addr, future)), new_addr))
+                }
+                _ => Ok((Box::new(future), new_addr)),
+            }
         } else {
             Err((self, addr))
         }
@@ -126,11 +353,38 @@ impl Transport for TcpConfig {
     /// Returns either a future which may resolve to a connection,
     /// or gives back the multiaddress.
     fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
-        if let Ok(socket_addr) = multiaddr_to_socketaddr(&addr) {
-            Ok(TcpStream::connect(&socket_addr, &self.event_loop))
-        } else {
-            Err((self, addr))
-        }
+        // The address is checked immediately, but the connection is started on the thread of
+        // the reactor.
+        let target = match self.socks5_proxy.clone() {
+            None => match multiaddr_to_socketaddr(&addr) {
+                Ok(socket_addr) => DialTarget::Direct(socket_addr),
+                Err(()) => return Err((self, addr)),
+            },
+            Some(proxy) => match multiaddr_to_socks5_target(&addr) {
+                Ok(target) => DialTarget::Proxy(proxy, target),
+                Err(()) => return Err((self, addr)),
+            },
+        };
+
+        let port_reuse = self.port_reuse;
+        let dial_timeout = self.dial_timeout;
+        let options = self.options;
+        Ok(on_event_loop(&self.event_loop, move |handle| {
+            let dial = match target {
+                DialTarget::Direct(socket_addr) => match port_reuse {
+                    Some(ref port_reuse) => port_reuse.connect(&socket_addr, handle),
+                    None => Box::new(TcpStream::connect(&socket_addr, handle)) as Box<_>,
+                },
+                DialTarget::Proxy(proxy, target) => socks5::connect(&proxy, target, handle),
+            };
+
+            let dial = match dial_timeout {
+                Some(timeout) => with_timeout(dial, timeout, handle),
+                None => dial,
+            };
+
+            dial.and_then(move |sock| options.apply(&sock).map(|()| sock))
+        }))
     }
 
     fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
@@ -161,6 +415,106 @@ impl Transport for TcpConfig {
 
         Some(result)
     }
+
+    fn expand_listen_addr(&self, addr: &Multiaddr) -> Vec<Multiaddr> {
+        let socket_addr = match multiaddr_to_socketaddr(addr) {
+            Ok(a) => a,
+            Err(()) => return vec![addr.clone()],
+        };
+
+        if !socket_addr.ip().is_unspecified() {
+            return vec![addr.clone()];
+        }
+
+        // If we can't enumerate the interfaces, the wildcard address is the best we can do.
+        let interfaces = match get_if_addrs::get_if_addrs() {
+            Ok(i) => i,
+            Err(_) => return vec![addr.clone()],
+        };
+
+        interfaces
+            .into_iter()
+            .map(|interface| interface.ip())
+            .filter(|ip| {
+                ip.is_ipv4() == socket_addr.is_ipv4() || (socket_addr.is_ipv6() && !self.ipv6_only)
+            })
+            .map(|ip| ip_to_multiaddr(ip, socket_addr.port()))
+            .collect()
+    }
+}
+
+// Builds the `/ipX/<ip>/tcp/<port>` multiaddress of an interface.
+fn ip_to_multiaddr(ip: IpAddr, port: u16) -> Multiaddr {
+    SocketAddr::new(ip, port)
+        .to_multiaddr()
+        .expect("multiaddr generated from socket addr is always valid")
+}
+
+// Destination of a dial.
+enum DialTarget {
+    Direct(SocketAddr),
+    Proxy(Socks5Proxy, Socks5Target),
+}
+
+// Runs `f` on the thread of the reactor, where the `Handle` can be used, and produces the
+// result of the future it returns. The result is sent back through a channel, which makes the
+// returned future `Send` even though the future returned by `f` isn't.
+fn on_event_loop<F, R>(event_loop: &Remote, f: F)
+                       -> Box<Future<Item = R::Item, Error = IoError> + Send>
+    where F: FnOnce(&Handle) -> R + Send + 'static,
+          R: IntoFuture<Error = IoError> + 'static,
+          R::Future: 'static,
+          R::Item: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    event_loop.spawn(move |handle| {
+        f(handle).into_future().then(move |result| -> Result<(), ()> {
+            // Ignoring errors if the returned future has been dropped.
+            let _ = tx.send(result);
+            Ok(())
+        })
+    });
+
+    Box::new(rx.then(|result| match result {
+        Ok(result) => result,
+        Err(_) => Err(IoError::new(IoErrorKind::Other, "the tokio reactor has been destroyed")),
+    }))
+}
+
+// Makes `dial` fail with an error of kind `TimedOut` if it doesn't finish within `timeout`.
+fn with_timeout(dial: Box<Future<Item = TcpStream, Error = IoError>>, timeout: Duration,
+                handle: &Handle) -> Box<Future<Item = TcpStream, Error = IoError>>
+{
+    let timer = match Timeout::new(timeout, handle) {
+        Ok(timer) => timer,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    let timer = timer.and_then(|()| -> Result<TcpStream, IoError> {
+        Err(IoError::new(IoErrorKind::TimedOut, "dial timed out"))
+    });
+
+    Box::new(dial.select(timer).map(|(sock, _)| sock).map_err(|(err, _)| err))
+}
+
+// Builds the multiaddress of a socket address. The IPv4-mapped IPv6 addresses, which the
+// dual-stack listeners report for the IPv4 connections, are turned into IPv4 addresses.
+fn socketaddr_to_multiaddr(addr: &SocketAddr) -> Multiaddr {
+    let ip = match addr.ip() {
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            if segments[.. 6] == [0, 0, 0, 0, 0, 0xffff] {
+                let (high, low) = (segments[6], segments[7]);
+                IpAddr::V4(Ipv4Addr::new((high >> 8) as u8, high as u8, (low >> 8) as u8,
+                                         low as u8))
+            } else {
+                IpAddr::V6(ip)
+            }
+        }
+        ip => ip,
+    };
+
+    ip_to_multiaddr(ip, addr.port())
 }
 
 // This type of logic should probably be moved into the multiaddr package
@@ -182,6 +536,26 @@ fn multiaddr_to_socketaddr(addr: &Multiaddr) -> Result<SocketAddr, ()> {
     }
 }
 
+// Same as `multiaddr_to_socketaddr`, but also accepts DNS names.
+fn multiaddr_to_socks5_target(addr: &Multiaddr) -> Result<Socks5Target, ()> {
+    if let Ok(socket_addr) = multiaddr_to_socketaddr(addr) {
+        return Ok(Socks5Target::Ip(socket_addr));
+    }
+
+    let protocols: Vec<_> = addr.iter().collect();
+    if protocols.len() != 2 {
+        return Err(());
+    }
+
+    match (&protocols[0], &protocols[1]) {
+        (&AddrComponent::DNS4(ref name), &AddrComponent::TCP(port)) |
+        (&AddrComponent::DNS6(ref name), &AddrComponent::TCP(port)) => {
+            Ok(Socks5Target::Domain(name.clone(), port))
+        }
+        _ => Err(()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{TcpConfig, multiaddr_to_socketaddr};
@@ -329,4 +703,187 @@ mod tests {
         let out = tcp.nat_traversal(&server, &observed);
         assert_eq!(out.unwrap(), "/ip4/80.81.82.83/tcp/10000".parse::<Multiaddr>().unwrap());
     }
+
+    #[test]
+    fn expand_wildcard_listen_addr() {
+        let core = Core::new().unwrap();
+        let tcp = TcpConfig::new(core.handle());
+
+        let concrete = "/ip4/127.0.0.1/tcp/10000".parse::<Multiaddr>().unwrap();
+        assert_eq!(tcp.expand_listen_addr(&concrete), vec![concrete.clone()]);
+
+        let wildcard = "/ip4/0.0.0.0/tcp/10000".parse::<Multiaddr>().unwrap();
+        let expanded = tcp.expand_listen_addr(&wildcard);
+        assert!(expanded.contains(&concrete));
+        for addr in expanded {
+            assert!(addr.to_string().starts_with("/ip4/"));
+            assert!(addr.to_string().ends_with("/tcp/10000"));
+        }
+    }
+
+    #[test]
+    fn dial_through_socks5_proxy() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+
+        // Fake proxy that checks the handshake, then sends some data to the dialer.
+        let thread = std::thread::spawn(move || {
+            let (mut socket, _) = proxy.accept().unwrap();
+
+            let mut greeting = [0; 3];
+            socket.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting, [5, 1, 2]);
+            socket.write_all(&[5, 2]).unwrap();
+
+            let mut auth = [0; 11];
+            socket.read_exact(&mut auth).unwrap();
+            assert_eq!(&auth, b"\x01\x04user\x04pass");
+            socket.write_all(&[1, 0]).unwrap();
+
+            let mut request = [0; 18];
+            socket.read_exact(&mut request).unwrap();
+            assert_eq!(&request, b"\x05\x01\x00\x03\x0bexample.com\x00\x50");
+            socket.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0x12, 0x34]).unwrap();
+
+            socket.write_all(b"hello").unwrap();
+        });
+
+        let mut core = Core::new().unwrap();
+        let tcp = TcpConfig::new(core.handle())
+            .with_socks5_proxy_auth(proxy_addr, "user".to_owned(), "pass".to_owned());
+        let dial = tcp.dial("/dns4/example.com/tcp/80".parse().unwrap())
+            .unwrap()
+            .and_then(|socket| tokio_io::io::read_exact(socket, [0; 5]))
+            .map(|(_, buf)| assert_eq!(&buf, b"hello"));
+        core.run(dial).unwrap();
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn socket_options_applied() {
+        let mut core = Core::new().unwrap();
+        let tcp = TcpConfig::new(core.handle())
+            .with_nodelay(true)
+            .with_ttl(42);
+
+        let (listener, addr) = tcp.clone()
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .unwrap();
+        let listener = listener
+            .into_future()
+            .map_err(|(err, _)| err)
+            .and_then(|(upgrade, _)| upgrade.unwrap().0);
+        let dialer = tcp.dial(addr).unwrap();
+
+        let (accepted, dialed) = core.run(listener.join(dialer)).unwrap();
+        for socket in &[accepted, dialed] {
+            assert!(socket.nodelay().unwrap());
+            assert_eq!(socket.ttl().unwrap(), 42);
+        }
+    }
+
+    #[test]
+    fn dial_reuses_listen_port() {
+        use multiaddr::AddrComponent;
+
+        let mut core = Core::new().unwrap();
+        let tcp = TcpConfig::new(core.handle()).with_port_reuse(true);
+        let (_listener, listen_addr) = tcp.clone()
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .unwrap();
+
+        let remote = TcpConfig::new(core.handle());
+        let (remote_listener, remote_addr) = remote
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .unwrap();
+        let remote_listener = remote_listener
+            .into_future()
+            .map_err(|(err, _)| err)
+            .map(|(incoming, _)| incoming.unwrap().1);
+        let dialer = tcp.dial(remote_addr).unwrap();
+
+        let (observed_addr, _) = core.run(remote_listener.join(dialer)).unwrap();
+        let port = |addr: &Multiaddr| match addr.iter().last() {
+            Some(AddrComponent::TCP(port)) => port,
+            _ => panic!("not a TCP address: {}", addr),
+        };
+        assert_eq!(port(&observed_addr), port(&listen_addr));
+    }
+
+    #[test]
+    fn dns_addr_requires_socks5_proxy() {
+        let core = Core::new().unwrap();
+        let tcp = TcpConfig::new(core.handle());
+        assert!(tcp.dial("/dns4/example.com/tcp/80".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn listen_on_both_stacks() {
+        use multiaddr::AddrComponent;
+
+        let core = Core::new().unwrap();
+        let tcp = TcpConfig::new(core.handle());
+
+        let (_listener4, addr4) = tcp.clone()
+            .listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap())
+            .unwrap();
+        let port = match addr4.iter().last() {
+            Some(AddrComponent::TCP(port)) => port,
+            _ => panic!("not a TCP address: {}", addr4),
+        };
+
+        let addr6 = format!("/ip6/::/tcp/{}", port).parse::<Multiaddr>().unwrap();
+        let (_listener6, new_addr6) = tcp.listen_on(addr6.clone()).unwrap();
+        assert_eq!(new_addr6, addr6);
+    }
+
+    #[test]
+    fn dual_stack_listener() {
+        let mut core = Core::new().unwrap();
+        let tcp = TcpConfig::new(core.handle()).with_ipv6_only(false);
+
+        let (listener, listen_addr) = tcp.clone()
+            .listen_on("/ip6/::/tcp/0".parse().unwrap())
+            .unwrap();
+        let port = listen_addr.to_string().rsplit('/').next().unwrap().to_owned();
+
+        let listener = listener
+            .into_future()
+            .map_err(|(err, _)| err)
+            .map(|(incoming, _)| incoming.unwrap().1);
+        let dialer = tcp.clone()
+            .dial(format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap())
+            .unwrap();
+
+        let (remote_addr, _) = core.run(listener.join(dialer)).unwrap();
+        assert!(remote_addr.to_string().starts_with("/ip4/127.0.0.1/tcp/"));
+
+        let wildcard = format!("/ip6/::/tcp/{}", port).parse::<Multiaddr>().unwrap();
+        let concrete = format!("/ip4/127.0.0.1/tcp/{}", port).parse::<Multiaddr>().unwrap();
+        assert!(tcp.expand_listen_addr(&wildcard).contains(&concrete));
+    }
+
+    #[test]
+    fn dial_timeout() {
+        use std::io::ErrorKind;
+        use std::net::TcpListener;
+        use std::time::Duration;
+
+        // The proxy never answers, so the dial can't finish.
+        let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let mut core = Core::new().unwrap();
+        let tcp = TcpConfig::new(core.handle())
+            .with_socks5_proxy(proxy.local_addr().unwrap())
+            .with_dial_timeout(Duration::from_millis(100));
+        let dial = tcp.dial("/ip4/1.2.3.4/tcp/5".parse().unwrap()).unwrap();
+
+        match core.run(dial) {
+            Err(err) => assert_eq!(err.kind(), ErrorKind::TimedOut),
+            Ok(_) => panic!("the dial should have timed out"),
+        }
+    }
 }
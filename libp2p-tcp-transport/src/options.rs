@@ -0,0 +1,92 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+//! Options applied to the sockets of the dialed and accepted connections.
+
+use std::io::Error as IoError;
+use std::time::Duration;
+use tokio_core::net::TcpStream;
+
+/// Socket options of a `TcpConfig`. The options that are `None` keep the default value of the
+/// operating system.
+#[derive(Debug, Clone, Default)]
+pub struct SocketOptions {
+    pub nodelay: Option<bool>,
+    pub keepalive: Option<Option<Duration>>,
+    pub keepalive_interval: Option<Duration>,
+    pub ttl: Option<u32>,
+    pub recv_buffer_size: Option<usize>,
+    pub send_buffer_size: Option<usize>,
+}
+
+impl SocketOptions {
+    /// Applies the options to `socket`.
+    pub fn apply(&self, socket: &TcpStream) -> Result<(), IoError> {
+        if let Some(nodelay) = self.nodelay {
+            socket.set_nodelay(nodelay)?;
+        }
+        if let Some(keepalive) = self.keepalive {
+            socket.set_keepalive(keepalive)?;
+        }
+        if let Some(interval) = self.keepalive_interval {
+            set_keepalive_interval(socket, interval)?;
+        }
+        if let Some(ttl) = self.ttl {
+            socket.set_ttl(ttl)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        Ok(())
+    }
+}
+
+// The standard library and tokio only allow setting the time before the first keepalive probe,
+// so we set the time between the probes ourselves.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_keepalive_interval(socket: &TcpStream, interval: Duration) -> Result<(), IoError> {
+    use libc;
+    use std::{cmp, mem};
+    use std::os::unix::io::AsRawFd;
+
+    // The interval is in seconds, and must be at least one.
+    let secs = cmp::min(cmp::max(interval.as_secs(), 1), libc::c_int::max_value() as u64);
+    let secs = secs as libc::c_int;
+
+    let ret = unsafe {
+        libc::setsockopt(socket.as_raw_fd(), libc::IPPROTO_TCP, libc::TCP_KEEPINTVL,
+                         &secs as *const libc::c_int as *const libc::c_void,
+                         mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(IoError::last_os_error())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_keepalive_interval(_: &TcpStream, _: Duration) -> Result<(), IoError> {
+    // Not supported on this platform.
+    Ok(())
+}
@@ -0,0 +1,147 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+//! Reusing the port of the listeners for the outgoing connections.
+//!
+//! When the dials are bound to the same port as a listener, the remotes observe the address
+//! that we listen on, which is what NAT hole punching relies on. All the sockets involved need
+//! the `SO_REUSEADDR` and `SO_REUSEPORT` options.
+
+use futures::{Poll, Stream};
+use futures::future::{self, Future};
+use net2::TcpBuilder;
+use parking_lot::Mutex;
+use std::io::Error as IoError;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+
+/// Addresses of the listeners whose port the dials reuse. Shared between the clones of a
+/// `TcpConfig`.
+#[derive(Debug, Clone, Default)]
+pub struct PortReuse {
+    listen_addrs: Arc<Mutex<Vec<SocketAddr>>>,
+}
+
+impl PortReuse {
+    /// Registers `addr` as the address of a listener until the returned stream is dropped.
+    pub fn register<S>(self, addr: SocketAddr, listener: S) -> PortReuseListener<S> {
+        self.listen_addrs.lock().push(addr);
+        PortReuseListener {
+            inner: listener,
+            port_reuse: self,
+            addr: addr,
+        }
+    }
+
+    /// Connects to `remote` from the port of a listener of the same IP version, or from a port
+    /// chosen by the operating system if there is none.
+    pub fn connect(&self, remote: &SocketAddr, handle: &Handle)
+                   -> Box<Future<Item = TcpStream, Error = IoError>>
+    {
+        let local_port = self.listen_addrs
+            .lock()
+            .iter()
+            .find(|addr| addr.is_ipv4() == remote.is_ipv4())
+            .map(|addr| addr.port());
+
+        if let Some(port) = local_port {
+            // Bind to the unspecified address so that the dial can reach any interface, even if
+            // the listener is bound to a specific one.
+            let ip = if remote.is_ipv4() {
+                IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))
+            } else {
+                IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0))
+            };
+
+            let socket = reusable_socket(&SocketAddr::new(ip, port))
+                .and_then(|builder| builder.to_tcp_stream());
+            // If the port can't be reused, we still want the connection to happen.
+            if let Ok(socket) = socket {
+                return TcpStream::connect_stream(socket, remote, handle);
+            }
+        }
+
+        Box::new(TcpStream::connect(remote, handle))
+    }
+}
+
+/// Stream of the incoming connections of a listener registered with `PortReuse::register()`.
+/// Unregisters the listener when dropped.
+pub struct PortReuseListener<S> {
+    inner: S,
+    port_reuse: PortReuse,
+    addr: SocketAddr,
+}
+
+impl<S> Stream for PortReuseListener<S>
+    where S: Stream
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    #[inline]
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.inner.poll()
+    }
+}
+
+impl<S> Drop for PortReuseListener<S> {
+    fn drop(&mut self) {
+        let mut listen_addrs = self.port_reuse.listen_addrs.lock();
+        if let Some(pos) = listen_addrs.iter().position(|addr| *addr == self.addr) {
+            listen_addrs.remove(pos);
+        }
+    }
+}
+
+/// Sets the options that allow other sockets to bind to the same port as `builder`. Must be
+/// called before binding.
+pub fn set_reusable(builder: &TcpBuilder) -> Result<(), IoError> {
+    builder.reuse_address(true)?;
+    set_reuse_port(builder)
+}
+
+// Creates a socket bound to `addr`, with the options that allow other sockets to bind to the
+// same port.
+fn reusable_socket(addr: &SocketAddr) -> Result<TcpBuilder, IoError> {
+    let builder = if addr.is_ipv4() {
+        TcpBuilder::new_v4()?
+    } else {
+        TcpBuilder::new_v6()?
+    };
+
+    set_reusable(&builder)?;
+    builder.bind(addr)?;
+    Ok(builder)
+}
+
+#[cfg(unix)]
+fn set_reuse_port(builder: &TcpBuilder) -> Result<(), IoError> {
+    use net2::unix::UnixTcpBuilderExt;
+    builder.reuse_port(true)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_reuse_port(_: &TcpBuilder) -> Result<(), IoError> {
+    // `SO_REUSEADDR` is enough on Windows.
+    Ok(())
+}
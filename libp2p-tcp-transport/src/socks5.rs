@@ -0,0 +1,208 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+//! Client side of the SOCKS5 protocol ([RFC 1928](https://tools.ietf.org/html/rfc1928)), used
+//! for dialing through a proxy.
+
+use futures::future::{self, Future};
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::net::SocketAddr;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+use tokio_io::io;
+
+const VERSION: u8 = 5;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const COMMAND_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// A SOCKS5 proxy, and the credentials to use with it if any.
+#[derive(Clone)]
+pub struct Socks5Proxy {
+    pub addr: SocketAddr,
+    pub credentials: Option<(String, String)>,
+}
+
+impl fmt::Debug for Socks5Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        // Don't leak the credentials in the logs.
+        f.debug_struct("Socks5Proxy")
+            .field("addr", &self.addr)
+            .field("authenticated", &self.credentials.is_some())
+            .finish()
+    }
+}
+
+/// Destination that the proxy must connect to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Socks5Target {
+    Ip(SocketAddr),
+    /// The name is resolved by the proxy.
+    Domain(String, u16),
+}
+
+/// Connects to `proxy` and asks it to connect to `target`. Produces the socket once the proxy
+/// has connected, after which it relays the data in both directions.
+pub fn connect(proxy: &Socks5Proxy, target: Socks5Target, handle: &Handle)
+               -> Box<Future<Item = TcpStream, Error = IoError>>
+{
+    let request = match encode_connect_request(&target) {
+        Ok(request) => request,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    let credentials = proxy.credentials.clone();
+    let method = if credentials.is_some() {
+        METHOD_USERNAME_PASSWORD
+    } else {
+        METHOD_NO_AUTH
+    };
+
+    let future = TcpStream::connect(&proxy.addr, handle)
+        .and_then(move |socket| io::write_all(socket, [VERSION, 1, method]))
+        .and_then(|(socket, _)| io::read_exact(socket, [0; 2]))
+        .and_then(move |(socket, reply)| {
+            if reply[0] != VERSION {
+                return Err(invalid_reply());
+            }
+            if reply[1] != method {
+                return Err(IoError::new(IoErrorKind::PermissionDenied,
+                                        "the SOCKS5 proxy refused our authentication method"));
+            }
+            Ok(socket)
+        })
+        .and_then(move |socket| -> Box<Future<Item = TcpStream, Error = IoError>> {
+            let (username, password) = match credentials {
+                Some(credentials) => credentials,
+                None => return Box::new(future::ok(socket)),
+            };
+
+            let request = match encode_auth_request(&username, &password) {
+                Ok(request) => request,
+                Err(err) => return Box::new(future::err(err)),
+            };
+
+            let future = io::write_all(socket, request)
+                .and_then(|(socket, _)| io::read_exact(socket, [0; 2]))
+                .and_then(|(socket, reply)| {
+                    if reply[1] != 0 {
+                        return Err(IoError::new(IoErrorKind::PermissionDenied,
+                                                "the SOCKS5 proxy refused our credentials"));
+                    }
+                    Ok(socket)
+                });
+            Box::new(future)
+        })
+        .and_then(move |socket| io::write_all(socket, request))
+        // The reply ends with the address that the proxy bound, whose length depends on its type.
+        // We read the first byte of the address as well, which is the length of domain names.
+        .and_then(|(socket, _)| io::read_exact(socket, [0; 5]))
+        .and_then(|(socket, reply)| -> Box<Future<Item = TcpStream, Error = IoError>> {
+            if reply[0] != VERSION {
+                return Box::new(future::err(invalid_reply()));
+            }
+            if reply[1] != 0 {
+                return Box::new(future::err(reply_error(reply[1])));
+            }
+
+            let remaining = match reply[3] {
+                ATYP_IPV4 => 4 + 2 - 1,
+                ATYP_IPV6 => 16 + 2 - 1,
+                ATYP_DOMAIN => reply[4] as usize + 2,
+                _ => return Box::new(future::err(invalid_reply())),
+            };
+            Box::new(io::read_exact(socket, vec![0; remaining]).map(|(socket, _)| socket))
+        });
+
+    Box::new(future)
+}
+
+// Builds the request that asks the proxy to connect to `target`.
+fn encode_connect_request(target: &Socks5Target) -> Result<Vec<u8>, IoError> {
+    let mut out = vec![VERSION, COMMAND_CONNECT, 0];
+
+    let port = match *target {
+        Socks5Target::Ip(SocketAddr::V4(ref addr)) => {
+            out.push(ATYP_IPV4);
+            out.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Socks5Target::Ip(SocketAddr::V6(ref addr)) => {
+            out.push(ATYP_IPV6);
+            out.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Socks5Target::Domain(ref name, port) => {
+            if name.is_empty() || name.len() > 255 {
+                return Err(IoError::new(IoErrorKind::InvalidInput,
+                                        "domain name too long for SOCKS5"));
+            }
+            out.push(ATYP_DOMAIN);
+            out.push(name.len() as u8);
+            out.extend_from_slice(name.as_bytes());
+            port
+        }
+    };
+
+    out.push((port >> 8) as u8);
+    out.push(port as u8);
+    Ok(out)
+}
+
+// Builds the username/password authentication request of RFC 1929.
+fn encode_auth_request(username: &str, password: &str) -> Result<Vec<u8>, IoError> {
+    if username.is_empty() || username.len() > 255 || password.is_empty() ||
+        password.len() > 255
+    {
+        return Err(IoError::new(IoErrorKind::InvalidInput,
+                                "SOCKS5 username and password must be 1 to 255 bytes long"));
+    }
+
+    let mut out = Vec::with_capacity(3 + username.len() + password.len());
+    out.push(1);
+    out.push(username.len() as u8);
+    out.extend_from_slice(username.as_bytes());
+    out.push(password.len() as u8);
+    out.extend_from_slice(password.as_bytes());
+    Ok(out)
+}
+
+#[inline]
+fn invalid_reply() -> IoError {
+    IoError::new(IoErrorKind::InvalidData, "invalid reply from the SOCKS5 proxy")
+}
+
+// Turns the error code of the reply to a connection request into an error.
+fn reply_error(code: u8) -> IoError {
+    let (kind, msg) = match code {
+        0x02 => (IoErrorKind::PermissionDenied, "connection not allowed by ruleset"),
+        0x03 => (IoErrorKind::Other, "network unreachable"),
+        0x04 => (IoErrorKind::Other, "host unreachable"),
+        0x05 => (IoErrorKind::ConnectionRefused, "connection refused"),
+        0x06 => (IoErrorKind::TimedOut, "TTL expired"),
+        0x07 => (IoErrorKind::Other, "command not supported"),
+        0x08 => (IoErrorKind::Other, "address type not supported"),
+        _ => (IoErrorKind::Other, "general failure"),
+    };
+    IoError::new(kind, format!("SOCKS5 proxy failed to connect: {}", msg))
+}
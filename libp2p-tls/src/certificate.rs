@@ -0,0 +1,376 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Generation and verification of the self-signed certificates of the libp2p TLS handshake.
+//!
+//! The certificate of a node is signed with a key that is generated for this purpose only. Its
+//! link with the identity of the node is made by an X.509 extension, whose value is the DER
+//! encoding of:
+//!
+//! ```text
+//! SignedKey ::= SEQUENCE {
+//!     publicKey OCTET STRING,
+//!     signature OCTET STRING
+//! }
+//! ```
+//!
+//! `publicKey` is the `PublicKey` protobuf message of the identity key, and `signature` is the
+//! signature by the identity key of `libp2p-tls-handshake:` followed with the DER-encoded
+//! `SubjectPublicKeyInfo` of the certificate.
+
+use error::TlsError;
+use futures::{future, Future};
+use libp2p_keys::{PublicKey, Signer};
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::x509::{X509, X509Builder, X509Extension, X509NameBuilder, X509Ref};
+
+/// Object identifier of the libp2p extension.
+pub const EXTENSION_OID: &'static str = "1.3.6.1.4.1.53594.1.1";
+
+// DER encoding of the content of `EXTENSION_OID`.
+const EXTENSION_OID_DER: &'static [u8] = &[
+	0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xa2, 0x5a, 0x01, 0x01
+];
+
+// DER encodings of the object identifiers of the critical extensions that OpenSSL handles. Any
+// other critical extension makes OpenSSL refuse the certificate.
+const HANDLED_EXTENSIONS: &'static [&'static [u8]] = &[
+	&[0x55, 0x1d, 0x0f],  // Key usage.
+	&[0x55, 0x1d, 0x11],  // Subject alternative name.
+	&[0x55, 0x1d, 0x13],  // Basic constraints.
+	&[0x55, 0x1d, 0x1e],  // Name constraints.
+	&[0x55, 0x1d, 0x20],  // Certificate policies.
+	&[0x55, 0x1d, 0x21],  // Policy mappings.
+	&[0x55, 0x1d, 0x24],  // Policy constraints.
+	&[0x55, 0x1d, 0x25],  // Extended key usage.
+	&[0x55, 0x1d, 0x36],  // Inhibit any policy.
+];
+
+// Prefix of the data signed by the identity key.
+const SIGNATURE_PREFIX: &'static [u8] = b"libp2p-tls-handshake:";
+
+// DER tags of the types we need.
+const BOOLEAN: u8 = 0x01;
+const OCTET_STRING: u8 = 0x04;
+const OID: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const EXTENSIONS: u8 = 0xa3;
+
+/// Generates a certificate key and a self-signed certificate that contains the libp2p extension
+/// signed by the identity key of `signer`.
+pub fn generate(signer: &Signer)
+				-> Box<Future<Item = (X509, PKey<Private>), Error = TlsError>>
+{
+	let (private_key, signed_data) = match generate_key() {
+		Ok(key) => key,
+		Err(err) => return Box::new(future::err(err)),
+	};
+
+	let public_key = signer.public().into_protobuf_encoding();
+	let future = signer.sign(&signed_data)
+		.map_err(|_| TlsError::SigningFailure)
+		.and_then(move |signature| -> Result<_, TlsError> {
+			let signed_key = encode_signed_key(&public_key, &signature);
+			let certificate = build_certificate(&private_key, &signed_key)?;
+			Ok((certificate, private_key))
+		});
+	Box::new(future)
+}
+
+// Generates the key of the certificate, and returns it with the data that the identity key must
+// sign.
+fn generate_key() -> Result<(PKey<Private>, Vec<u8>), TlsError> {
+	let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+	let private_key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+
+	let mut signed_data = SIGNATURE_PREFIX.to_vec();
+	signed_data.extend_from_slice(&private_key.public_key_to_der()?);
+	Ok((private_key, signed_data))
+}
+
+// Builds the certificate of `private_key`, with `signed_key` as the libp2p extension.
+fn build_certificate(private_key: &PKey<Private>, signed_key: &[u8]) -> Result<X509, TlsError> {
+	let mut builder = X509Builder::new()?;
+	builder.set_version(2)?;
+	let mut serial = BigNum::new()?;
+	serial.rand(64, MsbOption::MAYBE_ZERO, false)?;
+	builder.set_serial_number(&*serial.to_asn1_integer()?)?;
+	let mut name = X509NameBuilder::new()?;
+	name.append_entry_by_text("CN", "libp2p")?;
+	let name = name.build();
+	builder.set_subject_name(&name)?;
+	builder.set_issuer_name(&name)?;
+	builder.set_not_before(&*Asn1Time::days_from_now(0)?)?;
+	builder.set_not_after(&*Asn1Time::days_from_now(365)?)?;
+	builder.set_pubkey(private_key)?;
+
+	let extension = {
+		let hex = signed_key.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":");
+		let context = builder.x509v3_context(None, None);
+		X509Extension::new(None, Some(&context), EXTENSION_OID, &format!("critical,DER:{}", hex))?
+	};
+	builder.append_extension(extension)?;
+	builder.sign(private_key, MessageDigest::sha256())?;
+
+	Ok(builder.build())
+}
+
+/// Checks the certificate of a remote, and returns its identity key.
+pub fn verify(certificate: &X509Ref) -> Result<PublicKey, TlsError> {
+	let certificate_key = certificate.public_key()?;
+	if !certificate.verify(&certificate_key)? {
+		return Err(TlsError::InvalidCertificate);
+	}
+
+	let der = certificate.to_der()?;
+	let signed_key = find_extension(&der)
+		.map_err(|()| TlsError::InvalidCertificate)?
+		.ok_or(TlsError::MissingExtension)?;
+	let (public_key, signature) = decode_signed_key(signed_key)
+		.map_err(|()| TlsError::InvalidCertificate)?;
+	let public_key = PublicKey::from_protobuf_encoding(public_key)
+		.map_err(|_| TlsError::InvalidCertificate)?;
+
+	let mut signed_data = SIGNATURE_PREFIX.to_vec();
+	signed_data.extend_from_slice(&certificate_key.public_key_to_der()?);
+	if !public_key.verify(&signed_data, signature) {
+		return Err(TlsError::SignatureVerificationFailed);
+	}
+
+	Ok(public_key)
+}
+
+/// Returns true if the libp2p extension is the only critical extension of the DER-encoded
+/// `certificate` that OpenSSL doesn't handle.
+///
+/// OpenSSL refuses the certificates that have a critical extension it doesn't know about, which
+/// is the case of the libp2p extension. This function tells whether this refusal can be ignored.
+pub fn only_libp2p_extension_unhandled(certificate: &[u8]) -> bool {
+	let extensions = match extensions(certificate) {
+		Ok(extensions) => extensions,
+		Err(()) => return false,
+	};
+
+	extensions.iter()
+		.filter(|ext| ext.critical && !HANDLED_EXTENSIONS.iter().any(|&oid| oid == ext.oid))
+		.all(|ext| ext.oid == EXTENSION_OID_DER)
+}
+
+fn encode_signed_key(public_key: &[u8], signature: &[u8]) -> Vec<u8> {
+	let mut content = Vec::new();
+	write_tlv(&mut content, OCTET_STRING, public_key);
+	write_tlv(&mut content, OCTET_STRING, signature);
+	let mut out = Vec::new();
+	write_tlv(&mut out, SEQUENCE, &content);
+	out
+}
+
+// Returns the public key and the signature of a `SignedKey`.
+fn decode_signed_key(mut data: &[u8]) -> Result<(&[u8], &[u8]), ()> {
+	let mut content = read_tlv(&mut data, SEQUENCE)?;
+	let public_key = read_tlv(&mut content, OCTET_STRING)?;
+	let signature = read_tlv(&mut content, OCTET_STRING)?;
+	if !data.is_empty() || !content.is_empty() {
+		return Err(());
+	}
+	Ok((public_key, signature))
+}
+
+// Returns the value of the libp2p extension in a DER-encoded certificate.
+fn find_extension(der: &[u8]) -> Result<Option<&[u8]>, ()> {
+	let extension = extensions(der)?
+		.into_iter()
+		.find(|ext| ext.oid == EXTENSION_OID_DER)
+		.map(|ext| ext.value);
+	Ok(extension)
+}
+
+// Extension of a certificate.
+struct Extension<'a> {
+	// DER encoding of the content of the object identifier.
+	oid: &'a [u8],
+	critical: bool,
+	value: &'a [u8],
+}
+
+// Returns the extensions of a DER-encoded certificate.
+fn extensions(mut der: &[u8]) -> Result<Vec<Extension>, ()> {
+	let mut certificate = read_tlv(&mut der, SEQUENCE)?;
+	let mut tbs_certificate = read_tlv(&mut certificate, SEQUENCE)?;
+	let mut out = Vec::new();
+
+	// The extensions are the last field of the `TBSCertificate`, and are optional.
+	while !tbs_certificate.is_empty() {
+		let (tag, content) = read_any_tlv(&mut tbs_certificate)?;
+		if tag != EXTENSIONS {
+			continue;
+		}
+
+		let mut content = content;
+		let mut extensions = read_tlv(&mut content, SEQUENCE)?;
+		while !extensions.is_empty() {
+			let mut extension = read_tlv(&mut extensions, SEQUENCE)?;
+			let oid = read_tlv(&mut extension, OID)?;
+			// The `critical` field is omitted when false.
+			let critical = if extension.first() == Some(&BOOLEAN) {
+				read_tlv(&mut extension, BOOLEAN)? == [0xff]
+			} else {
+				false
+			};
+			let value = read_tlv(&mut extension, OCTET_STRING)?;
+			out.push(Extension {
+				oid: oid,
+				critical: critical,
+				value: value,
+			});
+		}
+	}
+
+	Ok(out)
+}
+
+// Reads a DER value of type `tag` at the start of `data`, and returns its content.
+fn read_tlv<'a>(data: &mut &'a [u8], tag: u8) -> Result<&'a [u8], ()> {
+	match read_any_tlv(data)? {
+		(actual, content) if actual == tag => Ok(content),
+		_ => Err(()),
+	}
+}
+
+// Reads a DER value at the start of `data`, and returns its tag and its content.
+fn read_any_tlv<'a>(data: &mut &'a [u8]) -> Result<(u8, &'a [u8]), ()> {
+	let input: &'a [u8] = *data;
+	if input.len() < 2 {
+		return Err(());
+	}
+
+	let tag = input[0];
+	// Lengths of 128 bytes or more are encoded as a number of bytes, followed with the length in
+	// big endian.
+	let (len, header_len) = match input[1] {
+		len if len < 0x80 => (len as usize, 2),
+		num @ 0x81 ... 0x83 => {
+			let num = (num & 0x7f) as usize;
+			if input.len() < 2 + num {
+				return Err(());
+			}
+			let len = input[2 .. 2 + num].iter().fold(0, |len, &b| (len << 8) | b as usize);
+			(len, 2 + num)
+		},
+		_ => return Err(()),
+	};
+
+	if input.len() - header_len < len {
+		return Err(());
+	}
+	let (content, rest) = input[header_len ..].split_at(len);
+	*data = rest;
+	Ok((tag, content))
+}
+
+// Writes a DER value of type `tag` with the given content.
+fn write_tlv(out: &mut Vec<u8>, tag: u8, content: &[u8]) {
+	out.push(tag);
+	if content.len() < 0x80 {
+		out.push(content.len() as u8);
+	} else if content.len() < 0x100 {
+		out.push(0x81);
+		out.push(content.len() as u8);
+	} else {
+		assert!(content.len() < 0x10000, "public keys and signatures are never that large");
+		out.push(0x82);
+		out.push((content.len() >> 8) as u8);
+		out.push(content.len() as u8);
+	}
+	out.extend_from_slice(content);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{generate, only_libp2p_extension_unhandled, verify, EXTENSION_OID};
+	use error::TlsError;
+	use futures::Future;
+	use libp2p_keys::Keypair;
+	use openssl::hash::MessageDigest;
+	use openssl::x509::{X509Builder, X509Extension};
+
+	#[test]
+	fn generate_and_verify() {
+		for keypair in vec![Keypair::generate_ed25519().unwrap(),
+							Keypair::generate_secp256k1().unwrap()]
+		{
+			let (certificate, _) = generate(&keypair).wait().unwrap();
+			assert_eq!(verify(&certificate).unwrap(), keypair.public());
+		}
+	}
+
+	#[test]
+	fn missing_extension() {
+		let keypair = Keypair::generate_ed25519().unwrap();
+		let (certificate, private_key) = generate(&keypair).wait().unwrap();
+
+		// Builds a certificate with the same key and name, but without the extension.
+		let mut builder = X509Builder::new().unwrap();
+		builder.set_version(2).unwrap();
+		builder.set_subject_name(certificate.subject_name()).unwrap();
+		builder.set_issuer_name(certificate.subject_name()).unwrap();
+		builder.set_not_before(certificate.not_before()).unwrap();
+		builder.set_not_after(certificate.not_after()).unwrap();
+		builder.set_pubkey(&private_key).unwrap();
+		builder.sign(&private_key, MessageDigest::sha256()).unwrap();
+
+		match verify(&builder.build()) {
+			Err(TlsError::MissingExtension) => (),
+			other => panic!("unexpected result: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn unhandled_critical_extensions() {
+		let keypair = Keypair::generate_ed25519().unwrap();
+		let (certificate, private_key) = generate(&keypair).wait().unwrap();
+		assert!(only_libp2p_extension_unhandled(&certificate.to_der().unwrap()));
+
+		// Builds a certificate with the libp2p extension and another unknown critical extension.
+		let mut builder = X509Builder::new().unwrap();
+		builder.set_version(2).unwrap();
+		builder.set_subject_name(certificate.subject_name()).unwrap();
+		builder.set_issuer_name(certificate.subject_name()).unwrap();
+		builder.set_not_before(certificate.not_before()).unwrap();
+		builder.set_not_after(certificate.not_after()).unwrap();
+		builder.set_pubkey(&private_key).unwrap();
+		for &oid in &[EXTENSION_OID, "1.3.6.1.4.1.53594.1.2"] {
+			let extension = {
+				let context = builder.x509v3_context(None, None);
+				X509Extension::new(None, Some(&context), oid, "critical,DER:05:00").unwrap()
+			};
+			builder.append_extension(extension).unwrap();
+		}
+		builder.sign(&private_key, MessageDigest::sha256()).unwrap();
+
+		let der = builder.build().to_der().unwrap();
+		assert!(!only_libp2p_extension_unhandled(&der));
+	}
+}
@@ -0,0 +1,83 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Defines the `TlsError` enum that groups all possible errors of the TLS upgrade.
+
+use openssl::error::ErrorStack;
+use std::error;
+use std::fmt;
+
+/// Error at the TLS layer.
+#[derive(Debug)]
+pub enum TlsError {
+	/// Error in the OpenSSL library.
+	Openssl(ErrorStack),
+
+	/// Failed to sign the certificate key with the identity key.
+	SigningFailure,
+
+	/// The certificate of the remote is malformed or isn't properly self-signed.
+	InvalidCertificate,
+
+	/// The certificate of the remote doesn't contain the libp2p extension.
+	MissingExtension,
+
+	/// The signature of the certificate key by the identity key of the remote is invalid.
+	SignatureVerificationFailed,
+}
+
+impl error::Error for TlsError {
+	#[inline]
+	fn description(&self) -> &str {
+		match *self {
+			TlsError::Openssl(_) => "Error in the OpenSSL library",
+			TlsError::SigningFailure => "Failed to sign the certificate key with the identity key",
+			TlsError::InvalidCertificate => "The certificate of the remote is invalid",
+			TlsError::MissingExtension => {
+				"The certificate of the remote doesn't contain the libp2p extension"
+			},
+			TlsError::SignatureVerificationFailed => {
+				"The signature of the certificate key by the identity key of the remote is invalid"
+			},
+		}
+	}
+
+	#[inline]
+	fn cause(&self) -> Option<&error::Error> {
+		match *self {
+			TlsError::Openssl(ref err) => Some(err),
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for TlsError {
+	#[inline]
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		write!(fmt, "{}", error::Error::description(self))
+	}
+}
+
+impl From<ErrorStack> for TlsError {
+	#[inline]
+	fn from(err: ErrorStack) -> TlsError {
+		TlsError::Openssl(err)
+	}
+}
@@ -0,0 +1,352 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+// TODO: use this once stable ; for now we just copy-paste the content of the README.md
+//#![doc(include = "../README.md")]
+
+//! Implementation of the libp2p TLS handshake.
+//!
+//! The two nodes perform a TLS 1.3 handshake in which each of them presents a self-signed
+//! certificate. The certificate contains an extension that holds the `PublicKey` protobuf message
+//! of the identity key of the node, and the signature of the key of the certificate by this
+//! identity key. This proves that the remote owns its identity key, and lets us derive its peer
+//! ID. The rest of the communications are encrypted by TLS.
+//!
+//! > **Note**: The TLS handshake is performed by OpenSSL, which must be at least version 1.1.1
+//! >           to support TLS 1.3.
+//!
+//! # Usage
+//!
+//! The `TlsConfig` struct implements the `ConnectionUpgrade` trait. It is built from the `Signer`
+//! of the identity key of the local node, such as a `Keypair` of `libp2p-keys`, and generates the
+//! certificate when it is built. Since the signer may be a hardware security module, building a
+//! `TlsConfig` is asynchronous. The future is immediately ready for a `Keypair`. The output of
+//! the upgrade is a `TlsOutput`, which implements `AsyncRead` and `AsyncWrite` and gives access
+//! to the public key and the peer ID of the remote.
+//!
+//! ```
+//! extern crate futures;
+//! extern crate libp2p_keys;
+//! extern crate libp2p_swarm;
+//! extern crate libp2p_tcp_transport;
+//! extern crate libp2p_tls;
+//! extern crate tokio_core;
+//!
+//! use futures::Future;
+//! use libp2p_keys::Keypair;
+//! use libp2p_swarm::Transport;
+//! use libp2p_tcp_transport::TcpConfig;
+//! use libp2p_tls::TlsConfig;
+//! use std::sync::Arc;
+//! use tokio_core::reactor::Core;
+//!
+//! # fn main() {
+//! let core = Core::new().unwrap();
+//! let keypair = Keypair::generate_ed25519().unwrap();
+//! let transport = TcpConfig::new(core.handle())
+//! 	.with_upgrade(TlsConfig::new(Arc::new(keypair)).wait().unwrap());
+//! # }
+//! ```
+
+extern crate bytes;
+extern crate futures;
+extern crate libp2p_keys;
+extern crate libp2p_swarm;
+extern crate openssl;
+extern crate tokio_io;
+extern crate tokio_openssl;
+
+pub use self::error::TlsError;
+
+use bytes::Bytes;
+use futures::{future, Future, Poll};
+use libp2p_keys::{PublicKey, Signer};
+use libp2p_swarm::{ConnectionUpgrade, Endpoint, Multiaddr, PeerId};
+use openssl::ssl::{self, AlpnError, HandshakeError, SslAcceptor, SslConnector, SslContextBuilder};
+use openssl::ssl::{SslMethod, SslVerifyMode, SslVersion};
+use openssl::pkey::{PKey, Private};
+use openssl::x509::{X509, X509StoreContextRef};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::iter;
+use std::sync::Arc;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_openssl::{ConnectConfigurationExt, SslAcceptorExt, SslStream};
+
+pub mod certificate;
+mod error;
+
+/// Protocol negotiated with ALPN during the TLS handshake.
+const ALPN_PROTOCOL: &'static [u8] = b"libp2p";
+
+// Error reported by OpenSSL for the self-signed certificates, which are expected.
+const X509_V_ERR_DEPTH_ZERO_SELF_SIGNED_CERT: i32 = 18;
+// Error reported by OpenSSL for the certificates that have an unknown critical extension, which
+// is the case of the libp2p extension. Only tolerated if no other critical extension is unknown.
+const X509_V_ERR_UNHANDLED_CRITICAL_EXTENSION: i32 = 34;
+
+/// Implementation of the `ConnectionUpgrade` trait of `libp2p_swarm` for the libp2p TLS
+/// handshake.
+#[derive(Clone)]
+pub struct TlsConfig {
+	connector: SslConnector,
+	acceptor: SslAcceptor,
+}
+
+impl TlsConfig {
+	/// Builds a `TlsConfig` from the signer of the identity key of the local node. Generates
+	/// the certificate and its key.
+	pub fn new(signer: Arc<Signer>) -> Box<Future<Item = TlsConfig, Error = TlsError>> {
+		let future = certificate::generate(&*signer).and_then(|(certificate, private_key)| {
+			TlsConfig::from_certificate(&certificate, &private_key)
+		});
+		Box::new(future)
+	}
+
+	// Builds a `TlsConfig` that presents `certificate`.
+	fn from_certificate(certificate: &X509, private_key: &PKey<Private>)
+						-> Result<TlsConfig, TlsError>
+	{
+
+		let mut connector = SslConnector::builder(SslMethod::tls())?;
+		configure(&mut connector)?;
+		connector.set_certificate(certificate)?;
+		connector.set_private_key(private_key)?;
+		connector.set_alpn_protos(&alpn_wire_format())?;
+
+		let mut acceptor = SslAcceptor::mozilla_modern(SslMethod::tls())?;
+		configure(&mut acceptor)?;
+		acceptor.set_certificate(certificate)?;
+		acceptor.set_private_key(private_key)?;
+		acceptor.check_private_key()?;
+		acceptor.set_alpn_select_callback(|_, client| {
+			ssl::select_next_proto(&alpn_wire_format(), client).ok_or(AlpnError::NOACK)
+		});
+
+		Ok(TlsConfig {
+			connector: connector.build(),
+			acceptor: acceptor.build(),
+		})
+	}
+}
+
+impl<C> ConnectionUpgrade<C> for TlsConfig
+	where C: AsyncRead + AsyncWrite + Send + 'static
+{
+	type Output = TlsOutput<C>;
+	type Future = Box<Future<Item = Self::Output, Error = IoError> + Send>;
+	type NamesIter = iter::Once<(Bytes, ())>;
+	type UpgradeIdentifier = ();
+
+	#[inline]
+	fn protocol_names(&self) -> Self::NamesIter {
+		iter::once((Bytes::from("/tls/1.0.0"), ()))
+	}
+
+	fn upgrade(self, socket: C, _: (), endpoint: Endpoint, _: &Multiaddr) -> Self::Future {
+		let stream: Box<Future<Item = SslStream<C>, Error = IoError> + Send> = match endpoint {
+			Endpoint::Dialer => {
+				// The certificate of the remote is checked after the handshake, and isn't
+				// related to any domain name.
+				let configuration = match self.connector.configure() {
+					Ok(configuration) => configuration,
+					Err(err) => {
+						let err = IoError::new(IoErrorKind::Other, err);
+						return Box::new(future::err(err));
+					},
+				};
+				let future = configuration
+					.use_server_name_indication(false)
+					.verify_hostname(false)
+					.connect_async("libp2p", socket)
+					.map_err(handshake_error);
+				Box::new(future)
+			},
+			Endpoint::Listener => {
+				Box::new(self.acceptor.accept_async(socket).map_err(handshake_error))
+			},
+		};
+
+		let future = stream.and_then(|stream| -> Result<_, IoError> {
+			let remote_public_key = {
+				let certificate = stream.get_ref().ssl().peer_certificate()
+					.ok_or_else(|| IoError::new(IoErrorKind::InvalidData, "no certificate"))?;
+				certificate::verify(&certificate)
+					.map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?
+			};
+
+			let peer_id = PeerId::from_public_key(&remote_public_key.into_protobuf_encoding());
+			Ok(TlsOutput {
+				stream: stream,
+				remote_public_key: remote_public_key,
+				remote_peer_id: peer_id,
+			})
+		});
+
+		Box::new(future)
+	}
+}
+
+/// Connection encrypted with TLS, whose remote has proven its identity.
+///
+/// Implements `AsyncRead` and `AsyncWrite`.
+#[derive(Debug)]
+pub struct TlsOutput<S> {
+	stream: SslStream<S>,
+	remote_public_key: PublicKey,
+	remote_peer_id: PeerId,
+}
+
+impl<S> TlsOutput<S> {
+	/// Returns the identity key of the remote.
+	#[inline]
+	pub fn remote_public_key(&self) -> &PublicKey {
+		&self.remote_public_key
+	}
+
+	/// Returns the peer ID of the remote, which is derived from its identity key.
+	#[inline]
+	pub fn remote_peer_id(&self) -> &PeerId {
+		&self.remote_peer_id
+	}
+}
+
+impl<S> Read for TlsOutput<S>
+	where S: Read + Write
+{
+	#[inline]
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+		self.stream.read(buf)
+	}
+}
+
+impl<S> AsyncRead for TlsOutput<S>
+	where S: AsyncRead + AsyncWrite
+{
+}
+
+impl<S> Write for TlsOutput<S>
+	where S: Read + Write
+{
+	#[inline]
+	fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+		self.stream.write(buf)
+	}
+
+	#[inline]
+	fn flush(&mut self) -> Result<(), IoError> {
+		self.stream.flush()
+	}
+}
+
+impl<S> AsyncWrite for TlsOutput<S>
+	where S: AsyncRead + AsyncWrite
+{
+	#[inline]
+	fn shutdown(&mut self) -> Poll<(), IoError> {
+		self.stream.shutdown()
+	}
+}
+
+// Applies the settings that are common to the dialer and the listener.
+fn configure(builder: &mut SslContextBuilder) -> Result<(), TlsError> {
+	builder.set_min_proto_version(Some(SslVersion::TLS1_3))?;
+	// The certificates are self-signed, and are checked by `certificate::verify` once the
+	// handshake is finished. We still refuse the other errors, such as an expired certificate.
+	let mode = SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT;
+	builder.set_verify_callback(mode, |ok, context: &mut X509StoreContextRef| {
+		ok || match context.error().as_raw() {
+			X509_V_ERR_DEPTH_ZERO_SELF_SIGNED_CERT => true,
+			X509_V_ERR_UNHANDLED_CRITICAL_EXTENSION => {
+				context.current_cert()
+					.and_then(|cert| cert.to_der().ok())
+					.map_or(false, |der| certificate::only_libp2p_extension_unhandled(&der))
+			},
+			_ => false,
+		}
+	});
+	Ok(())
+}
+
+// Returns `ALPN_PROTOCOL` prefixed with its length, which is the format expected by OpenSSL.
+fn alpn_wire_format() -> Vec<u8> {
+	let mut out = Vec::with_capacity(ALPN_PROTOCOL.len() + 1);
+	out.push(ALPN_PROTOCOL.len() as u8);
+	out.extend_from_slice(ALPN_PROTOCOL);
+	out
+}
+
+// Turns an error of the handshake into an `IoError`.
+fn handshake_error<S>(err: HandshakeError<S>) -> IoError {
+	match err {
+		HandshakeError::SetupFailure(err) => IoError::new(IoErrorKind::Other, err),
+		HandshakeError::Failure(stream) | HandshakeError::WouldBlock(stream) => {
+			IoError::new(IoErrorKind::Other, stream.into_error())
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	extern crate tokio_core;
+	use self::tokio_core::net::{TcpListener, TcpStream};
+	use self::tokio_core::reactor::Core;
+	use super::TlsConfig;
+	use futures::{Future, Stream};
+	use libp2p_keys::Keypair;
+	use libp2p_swarm::{ConnectionUpgrade, Endpoint, Multiaddr};
+	use std::sync::Arc;
+	use tokio_io::io::{read_exact, write_all};
+
+	#[test]
+	fn handshake_and_communicate() {
+		let mut core = Core::new().unwrap();
+		let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+		let listener_addr = listener.local_addr().unwrap();
+		let addr: Multiaddr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+
+		let server_keypair = Keypair::generate_ed25519().unwrap();
+		let client_keypair = Keypair::generate_secp256k1().unwrap();
+
+		let server_config = TlsConfig::new(Arc::new(server_keypair.clone())).wait().unwrap();
+		let client_config = TlsConfig::new(Arc::new(client_keypair.clone())).wait().unwrap();
+		let server_addr = addr.clone();
+		let server = listener.incoming()
+			.into_future()
+			.map_err(|(err, _)| err)
+			.and_then(move |(connec, _)| {
+				server_config.upgrade(connec.unwrap().0, (), Endpoint::Listener, &server_addr)
+			})
+			.and_then(|output| {
+				assert_eq!(output.remote_public_key(), &client_keypair.public());
+				read_exact(output, [0; 5])
+			})
+			.map(|(_, data)| data);
+
+		let client = TcpStream::connect(&listener_addr, &core.handle())
+			.and_then(move |stream| client_config.upgrade(stream, (), Endpoint::Dialer, &addr))
+			.and_then(move |output| {
+				assert_eq!(output.remote_public_key(), &server_keypair.public());
+				write_all(output, b"hello")
+			});
+
+		let (data, _) = core.run(server.join(client)).unwrap();
+		assert_eq!(&data, b"hello");
+	}
+}
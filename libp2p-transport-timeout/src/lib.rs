@@ -0,0 +1,338 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+// TODO: use this once stable ; for now we just copy-paste the content of the README.md
+//#![doc(include = "../README.md")]
+
+//! Wrapper around a `Transport` that adds a timeout to the dials and to the incoming connections.
+//!
+//! When the wrapped transport is an upgraded transport (as returned by `with_upgrade()`), the
+//! timeout bounds the whole process of opening a connection: connecting, negotiating the
+//! protocols, and performing the handshakes of the upgrades. This prevents remotes that never
+//! finish their handshake from holding resources forever.
+//!
+//! # Usage
+//!
+//! ```
+//! extern crate libp2p_tcp_transport;
+//! extern crate libp2p_transport_timeout;
+//! extern crate tokio_core;
+//!
+//! use libp2p_tcp_transport::TcpConfig;
+//! use libp2p_transport_timeout::TransportTimeout;
+//! use std::time::Duration;
+//! use tokio_core::reactor::Core;
+//!
+//! # fn main() {
+//! let core = Core::new().unwrap();
+//! let tcp = TcpConfig::new(core.handle());
+//! let transport = TransportTimeout::new(tcp, Duration::from_secs(20), core.handle());
+//! # }
+//! ```
+//!
+//! If the timeout expires, the connection is dropped and an error of kind `TimedOut` is produced.
+//!
+//! > **Note**: The timeout doesn't apply to the substreams that are opened on top of an existing
+//! >           connection, which are produced by `next_incoming()`.
+
+#[macro_use]
+extern crate futures;
+extern crate libp2p_swarm as swarm;
+extern crate multiaddr;
+extern crate tokio_core;
+
+#[cfg(test)]
+extern crate libp2p_tcp_transport as tcp;
+
+use futures::{Async, Future, IntoFuture, Poll, Stream};
+use multiaddr::Multiaddr;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::time::Duration;
+use swarm::{MuxedTransport, Transport};
+use tokio_core::reactor::{Handle, Timeout};
+
+// Timeout used when there shouldn't be any timeout.
+const NO_TIMEOUT_SECS: u64 = 100 * 365 * 24 * 3600;
+
+/// Wraps around a `Transport` and adds a timeout to the dials and to the incoming connections.
+#[derive(Debug, Clone)]
+pub struct TransportTimeout<T> {
+	inner: T,
+	outgoing_timeout: Duration,
+	incoming_timeout: Duration,
+	handle: Handle,
+}
+
+impl<T> TransportTimeout<T> {
+	/// Wraps around `trans`, with the same timeout for the dials and the incoming connections.
+	#[inline]
+	pub fn new(trans: T, timeout: Duration, handle: Handle) -> TransportTimeout<T> {
+		TransportTimeout {
+			inner: trans,
+			outgoing_timeout: timeout,
+			incoming_timeout: timeout,
+			handle: handle,
+		}
+	}
+
+	/// Wraps around `trans`, with a timeout for the dials only.
+	#[inline]
+	pub fn with_outgoing_timeout(trans: T, timeout: Duration, handle: Handle)
+								 -> TransportTimeout<T>
+	{
+		TransportTimeout {
+			inner: trans,
+			outgoing_timeout: timeout,
+			incoming_timeout: Duration::from_secs(NO_TIMEOUT_SECS),
+			handle: handle,
+		}
+	}
+
+	/// Wraps around `trans`, with a timeout for the incoming connections only.
+	#[inline]
+	pub fn with_incoming_timeout(trans: T, timeout: Duration, handle: Handle)
+								 -> TransportTimeout<T>
+	{
+		TransportTimeout {
+			inner: trans,
+			outgoing_timeout: Duration::from_secs(NO_TIMEOUT_SECS),
+			incoming_timeout: timeout,
+			handle: handle,
+		}
+	}
+}
+
+impl<T> Transport for TransportTimeout<T>
+	where T: Transport
+{
+	type RawConn = T::RawConn;
+	type Listener = TimeoutListener<T::Listener>;
+	type ListenerUpgrade = TimeoutFuture<T::ListenerUpgrade>;
+	type Dial = TimeoutFuture<<T::Dial as IntoFuture>::Future>;
+
+	fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+		match self.inner.listen_on(addr) {
+			Ok((listener, addr)) => {
+				let listener = TimeoutListener {
+					inner: listener,
+					timeout: self.incoming_timeout,
+					handle: self.handle,
+				};
+				Ok((listener, addr))
+			}
+			Err((inner, addr)) => {
+				let transport = TransportTimeout {
+					inner: inner,
+					outgoing_timeout: self.outgoing_timeout,
+					incoming_timeout: self.incoming_timeout,
+					handle: self.handle,
+				};
+				Err((transport, addr))
+			}
+		}
+	}
+
+	fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+		match self.inner.dial(addr) {
+			Ok(dial) => Ok(TimeoutFuture::new(dial.into_future(), self.outgoing_timeout,
+											  &self.handle)),
+			Err((inner, addr)) => {
+				let transport = TransportTimeout {
+					inner: inner,
+					outgoing_timeout: self.outgoing_timeout,
+					incoming_timeout: self.incoming_timeout,
+					handle: self.handle,
+				};
+				Err((transport, addr))
+			}
+		}
+	}
+
+	#[inline]
+	fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+		self.inner.nat_traversal(server, observed)
+	}
+
+	#[inline]
+	fn expand_listen_addr(&self, addr: &Multiaddr) -> Vec<Multiaddr> {
+		self.inner.expand_listen_addr(addr)
+	}
+}
+
+impl<T> MuxedTransport for TransportTimeout<T>
+	where T: MuxedTransport
+{
+	type Incoming = T::Incoming;
+
+	#[inline]
+	fn next_incoming(self) -> Self::Incoming {
+		self.inner.next_incoming()
+	}
+}
+
+/// Stream of the incoming connections of a `TransportTimeout`.
+#[must_use = "streams do nothing unless polled"]
+pub struct TimeoutListener<S> {
+	inner: S,
+	timeout: Duration,
+	handle: Handle,
+}
+
+impl<S, F> Stream for TimeoutListener<S>
+	where S: Stream<Item = (F, Multiaddr)>
+{
+	type Item = (TimeoutFuture<F>, Multiaddr);
+	type Error = S::Error;
+
+	fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+		match try_ready!(self.inner.poll()) {
+			Some((upgrade, addr)) => {
+				let upgrade = TimeoutFuture::new(upgrade, self.timeout, &self.handle);
+				Ok(Async::Ready(Some((upgrade, addr))))
+			}
+			None => Ok(Async::Ready(None)),
+		}
+	}
+}
+
+/// Future that produces a connection of a `TransportTimeout`, or an error if the timeout expires
+/// first.
+#[must_use = "futures do nothing unless polled"]
+pub struct TimeoutFuture<F> {
+	inner: F,
+	// `Err` if the timer couldn't be created. The error is produced by the next call to `poll()`.
+	timer: Result<Timeout, Option<IoError>>,
+}
+
+impl<F> TimeoutFuture<F> {
+	fn new(inner: F, timeout: Duration, handle: &Handle) -> TimeoutFuture<F> {
+		TimeoutFuture {
+			inner: inner,
+			timer: Timeout::new(timeout, handle).map_err(Some),
+		}
+	}
+}
+
+impl<F> Future for TimeoutFuture<F>
+	where F: Future<Error = IoError>
+{
+	type Item = F::Item;
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		if let Async::Ready(item) = self.inner.poll()? {
+			return Ok(Async::Ready(item));
+		}
+
+		let timer = match self.timer {
+			Ok(ref mut timer) => timer,
+			Err(ref mut err) => {
+				return Err(err.take().expect("future polled after it finished"));
+			}
+		};
+
+		match timer.poll()? {
+			Async::Ready(()) => Err(IoError::new(IoErrorKind::TimedOut, "connection timed out")),
+			Async::NotReady => Ok(Async::NotReady),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::TransportTimeout;
+	use futures::{future, stream, Future, Stream};
+	use multiaddr::Multiaddr;
+	use std::io::{Cursor, Error as IoError, ErrorKind as IoErrorKind};
+	use std::time::Duration;
+	use swarm::Transport;
+	use tcp::TcpConfig;
+	use tokio_core::reactor::Core;
+
+	// Transport whose connections never finish opening.
+	#[derive(Debug, Clone)]
+	struct StuckTransport;
+
+	impl Transport for StuckTransport {
+		type RawConn = Cursor<Vec<u8>>;
+		type Listener = stream::Once<(Self::ListenerUpgrade, Multiaddr), IoError>;
+		type ListenerUpgrade = future::Empty<Self::RawConn, IoError>;
+		type Dial = future::Empty<Self::RawConn, IoError>;
+
+		fn listen_on(self, addr: Multiaddr)
+					 -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)>
+		{
+			Ok((stream::once(Ok((future::empty(), addr.clone()))), addr))
+		}
+
+		fn dial(self, _: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+			Ok(future::empty())
+		}
+
+		fn nat_traversal(&self, _: &Multiaddr, _: &Multiaddr) -> Option<Multiaddr> {
+			None
+		}
+	}
+
+	#[test]
+	fn dial_timeout() {
+		let mut core = Core::new().unwrap();
+		let transport = TransportTimeout::new(StuckTransport, Duration::from_millis(50),
+											  core.handle());
+		let dial = transport.dial("/memory/1".parse().unwrap()).unwrap();
+		match core.run(dial) {
+			Err(err) => assert_eq!(err.kind(), IoErrorKind::TimedOut),
+			Ok(_) => panic!("dial should have timed out"),
+		}
+	}
+
+	#[test]
+	fn incoming_timeout() {
+		let mut core = Core::new().unwrap();
+		let transport = TransportTimeout::with_incoming_timeout(StuckTransport,
+																Duration::from_millis(50),
+																core.handle());
+		let (listener, _) = transport.listen_on("/memory/1".parse().unwrap()).unwrap();
+		let incoming = listener
+			.into_future()
+			.map_err(|(err, _)| err)
+			.and_then(|(upgrade, _)| upgrade.unwrap().0);
+		match core.run(incoming) {
+			Err(err) => assert_eq!(err.kind(), IoErrorKind::TimedOut),
+			Ok(_) => panic!("incoming connection should have timed out"),
+		}
+	}
+
+	#[test]
+	fn dial_before_timeout() {
+		let mut core = Core::new().unwrap();
+		let tcp = TcpConfig::new(core.handle());
+		let transport = TransportTimeout::new(tcp, Duration::from_secs(5), core.handle());
+
+		let (listener, addr) = transport.clone()
+			.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+			.unwrap();
+		let listener = listener
+			.into_future()
+			.map_err(|(err, _)| err)
+			.and_then(|(upgrade, _)| upgrade.unwrap().0);
+		let dial = transport.dial(addr).unwrap();
+		core.run(listener.join(dial)).unwrap();
+	}
+}
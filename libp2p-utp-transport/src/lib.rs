@@ -0,0 +1,356 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+// TODO: use this once stable ; for now we just copy-paste the content of the README.md
+//#![doc(include = "../README.md")]
+
+//! Implementation of the libp2p `Transport` trait for uTP, a reliable protocol on top of UDP.
+//!
+//! uTP is mostly useful behind NATs where UDP hole punching succeeds but TCP hole punching
+//! doesn't. The addresses have the form `/ip4/1.2.3.4/udp/5678/utp`.
+//!
+//! # Usage
+//!
+//! Create [a tokio `Core`](https://docs.rs/tokio-core/0.1/tokio_core/reactor/struct.Core.html),
+//! then grab a handle by calling the `handle()` method on it, then create a `UtpConfig` and pass
+//! the handle.
+//!
+//! ```
+//! extern crate libp2p_utp_transport;
+//! extern crate tokio_core;
+//!
+//! use libp2p_utp_transport::UtpConfig;
+//! use tokio_core::reactor::Core;
+//!
+//! # fn main() {
+//! let mut core = Core::new().unwrap();
+//! let utp = UtpConfig::new(core.handle());
+//! # }
+//! ```
+//!
+//! The `UtpConfig` struct implements the `Transport` trait of the `swarm` library. See the
+//! documentation of `swarm` and of libp2p in general to learn how to use the `Transport` trait.
+//!
+//! Each UDP socket is driven by a background task spawned on the tokio reactor, which
+//! acknowledges and retransmits the packets. The task finishes once all the connections, dials
+//! and listeners using the socket are closed. Calling `with_executor()` spawns these tasks on an
+//! `Executor` of the `swarm` library instead, for example a `ThreadPoolExecutor`. The sockets
+//! are still registered on the tokio reactor, which has to keep running.
+//!
+//! The dials go through the UDP socket of a listener of the same IP version, so that the remotes
+//! observe the address that we listen on, which helps with NAT hole punching. If there is no
+//! listener, each dial uses its own UDP socket with a port chosen by the operating system.
+//!
+//! > **Note**: The packets follow the format of [BEP 29](http://bittorrent.org/beps/bep_0029.html),
+//! >           but the congestion control is limited to a fixed window and the lost packets are
+//! >           recovered with go-back-N retransmissions. Selective acknowledgements are ignored.
+
+extern crate futures;
+extern crate libp2p_swarm as swarm;
+extern crate multiaddr;
+extern crate parking_lot;
+extern crate rand;
+extern crate tokio_core;
+extern crate tokio_io;
+
+mod packet;
+mod port_reuse;
+mod socket;
+
+pub use self::socket::UtpStream;
+
+use futures::future::{self, Future, FutureResult};
+use futures::stream::Stream;
+use multiaddr::{AddrComponent, Multiaddr};
+use port_reuse::PortReuse;
+use socket::UtpListener;
+use std::io::Error as IoError;
+use std::fmt;
+use std::iter;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use swarm::{Executor, Transport};
+use tokio_core::reactor::Handle;
+
+/// Represents the configuration for a uTP transport capability for libp2p.
+///
+/// Each connection created by this config is tied to a tokio reactor, on which the background
+/// tasks of the UDP sockets are spawned unless an executor has been set.
+#[derive(Clone)]
+pub struct UtpConfig {
+	event_loop: Handle,
+	// Executor that the background tasks of the UDP sockets are spawned on, if any.
+	executor: Option<Arc<Executor>>,
+	// Sockets of the listeners, which the dials go through.
+	port_reuse: PortReuse,
+}
+
+impl UtpConfig {
+	/// Creates a new configuration object for uTP. The `Handle` is a tokio reactor the
+	/// connections will be created with.
+	#[inline]
+	pub fn new(handle: Handle) -> UtpConfig {
+		UtpConfig {
+			event_loop: handle,
+			executor: None,
+			port_reuse: PortReuse::default(),
+		}
+	}
+
+	/// Spawns the background tasks of the UDP sockets on `executor` instead of the tokio
+	/// reactor.
+	#[inline]
+	pub fn with_executor<E>(mut self, executor: E) -> UtpConfig
+		where E: Executor + 'static
+	{
+		self.executor = Some(Arc::new(executor));
+		self
+	}
+}
+
+impl fmt::Debug for UtpConfig {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("UtpConfig")
+			.field("event_loop", &self.event_loop)
+			.field("executor", &self.executor.is_some())
+			.field("port_reuse", &self.port_reuse)
+			.finish()
+	}
+}
+
+impl Transport for UtpConfig {
+	type RawConn = UtpStream;
+	type Listener = Box<Stream<Item = (Self::ListenerUpgrade, Multiaddr), Error = IoError> + Send>;
+	type ListenerUpgrade = FutureResult<Self::RawConn, IoError>;
+	type Dial = Box<Future<Item = Self::RawConn, Error = IoError> + Send>;
+
+	fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+		let socket_addr = match multiaddr_to_socketaddr(&addr) {
+			Ok(socket_addr) => socket_addr,
+			Err(()) => return Err((self, addr)),
+		};
+
+		match socket::bind(&socket_addr, true, &self.event_loop, self.executor.as_ref()) {
+			Ok((shared, local_addr)) => {
+				let listener = UtpListener::new(shared.clone()).map(|(stream, remote_addr)| {
+					(future::ok::<_, IoError>(stream), socketaddr_to_multiaddr(remote_addr))
+				});
+				let listener = self.port_reuse.register(local_addr, shared, listener);
+				Ok((Box::new(listener), socketaddr_to_multiaddr(local_addr)))
+			},
+			// Like for TCP, the error is reported by the listener.
+			Err(err) => {
+				let listener = future::err::<(Self::ListenerUpgrade, Multiaddr), _>(err);
+				Ok((Box::new(listener.into_stream()), addr))
+			},
+		}
+	}
+
+	fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+		let remote_addr = match multiaddr_to_socketaddr(&addr) {
+			Ok(socket_addr) => socket_addr,
+			Err(()) => return Err((self, addr)),
+		};
+
+		if let Some(shared) = self.port_reuse.socket_for(&remote_addr) {
+			return Ok(Box::new(socket::connect(shared, remote_addr)));
+		}
+
+		let local_ip = if remote_addr.is_ipv4() {
+			IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))
+		} else {
+			IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0))
+		};
+
+		let local_addr = SocketAddr::new(local_ip, 0);
+		match socket::bind(&local_addr, false, &self.event_loop, self.executor.as_ref()) {
+			Ok((shared, _)) => Ok(Box::new(socket::connect(shared, remote_addr))),
+			Err(err) => Ok(Box::new(future::err::<UtpStream, _>(err))),
+		}
+	}
+
+	fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+		// Check that both addresses are valid uTP addresses.
+		if multiaddr_to_socketaddr(server).is_err() || multiaddr_to_socketaddr(observed).is_err() {
+			return None;
+		}
+
+		// The IP address as observed by the remote, with the port of our listener.
+		let server_protocols: Vec<_> = server.iter().collect();
+		let observed_protocols: Vec<_> = observed.iter().collect();
+		let result = iter::once(observed_protocols[0].clone())
+			.chain(server_protocols[1..].iter().cloned())
+			.collect();
+
+		Some(result)
+	}
+}
+
+// Builds the `/ipX/<ip>/udp/<port>/utp` multiaddress of a socket address.
+fn socketaddr_to_multiaddr(addr: SocketAddr) -> Multiaddr {
+	let ip = match addr.ip() {
+		IpAddr::V4(ip) => AddrComponent::IP4(ip),
+		IpAddr::V6(ip) => AddrComponent::IP6(ip),
+	};
+
+	iter::once(ip)
+		.chain(iter::once(AddrComponent::UDP(addr.port())))
+		.chain(iter::once(AddrComponent::UTP))
+		.collect()
+}
+
+// Parses a `/ipX/<ip>/udp/<port>/utp` multiaddress.
+fn multiaddr_to_socketaddr(addr: &Multiaddr) -> Result<SocketAddr, ()> {
+	let protocols: Vec<_> = addr.iter().collect();
+
+	if protocols.len() != 3 {
+		return Err(());
+	}
+
+	match (&protocols[0], &protocols[1], &protocols[2]) {
+		(&AddrComponent::IP4(ref ip), &AddrComponent::UDP(port), &AddrComponent::UTP) => {
+			Ok(SocketAddr::new(ip.clone().into(), port))
+		},
+		(&AddrComponent::IP6(ref ip), &AddrComponent::UDP(port), &AddrComponent::UTP) => {
+			Ok(SocketAddr::new(ip.clone().into(), port))
+		},
+		_ => Err(()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{multiaddr_to_socketaddr, socketaddr_to_multiaddr, UtpConfig};
+	use futures::{Future, Stream};
+	use multiaddr::Multiaddr;
+	use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+	use swarm::{ThreadPoolExecutor, Transport};
+	use tokio_core::reactor::Core;
+	use tokio_io;
+
+	#[test]
+	fn multiaddr_conversion() {
+		let addr = "/ip4/127.0.0.1/udp/1234/utp".parse::<Multiaddr>().unwrap();
+		let socket_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 1234);
+		assert_eq!(multiaddr_to_socketaddr(&addr), Ok(socket_addr));
+		assert_eq!(socketaddr_to_multiaddr(socket_addr), addr);
+
+		let addr = "/ip6/::1/udp/1234/utp".parse::<Multiaddr>().unwrap();
+		assert_eq!(socketaddr_to_multiaddr(multiaddr_to_socketaddr(&addr).unwrap()), addr);
+
+		assert!(multiaddr_to_socketaddr(&"/ip4/127.0.0.1/udp/1234".parse().unwrap()).is_err());
+		assert!(multiaddr_to_socketaddr(&"/ip4/127.0.0.1/tcp/1234".parse().unwrap()).is_err());
+	}
+
+	#[test]
+	fn nat_traversal() {
+		let core = Core::new().unwrap();
+		let utp = UtpConfig::new(core.handle());
+
+		let server = "/ip4/127.0.0.1/udp/10000/utp".parse::<Multiaddr>().unwrap();
+		let observed = "/ip4/80.81.82.83/udp/25000/utp".parse::<Multiaddr>().unwrap();
+		let expected = "/ip4/80.81.82.83/udp/10000/utp".parse::<Multiaddr>().unwrap();
+		assert_eq!(utp.nat_traversal(&server, &observed), Some(expected));
+
+		let observed = "/ip4/80.81.82.83/tcp/25000".parse::<Multiaddr>().unwrap();
+		assert_eq!(utp.nat_traversal(&server, &observed), None);
+	}
+
+	#[test]
+	fn communicate() {
+		let mut core = Core::new().unwrap();
+		let utp = UtpConfig::new(core.handle());
+
+		let (listener, addr) = utp.clone()
+			.listen_on("/ip4/127.0.0.1/udp/0/utp".parse().unwrap())
+			.unwrap();
+		assert!(!addr.to_string().contains("/udp/0/"));
+
+		// More than what fits in the window, in order to exercise the acknowledgements.
+		let data = (0 .. 1024 * 1024).map(|n| n as u8).collect::<Vec<_>>();
+
+		let server = listener
+			.into_future()
+			.map_err(|(err, _)| err)
+			.and_then(|(conn, _)| conn.unwrap().0)
+			.and_then(|conn| tokio_io::io::read_to_end(conn, Vec::new()))
+			.map(|(_, received)| received);
+
+		let expected = data.clone();
+		let client = utp.dial(addr)
+			.unwrap()
+			.and_then(move |conn| tokio_io::io::write_all(conn, data))
+			.and_then(|(conn, _)| tokio_io::io::shutdown(conn));
+
+		let (received, _) = core.run(server.join(client)).unwrap();
+		assert_eq!(received, expected);
+	}
+
+	#[test]
+	fn communicate_with_executor() {
+		let mut core = Core::new().unwrap();
+		let utp = UtpConfig::new(core.handle()).with_executor(ThreadPoolExecutor::new(2));
+
+		let (listener, addr) = utp.clone()
+			.listen_on("/ip4/127.0.0.1/udp/0/utp".parse().unwrap())
+			.unwrap();
+
+		let data = (0 .. 256 * 1024).map(|n| n as u8).collect::<Vec<_>>();
+
+		let server = listener
+			.into_future()
+			.map_err(|(err, _)| err)
+			.and_then(|(conn, _)| conn.unwrap().0)
+			.and_then(|conn| tokio_io::io::read_to_end(conn, Vec::new()))
+			.map(|(_, received)| received);
+
+		let expected = data.clone();
+		let client = utp.dial(addr)
+			.unwrap()
+			.and_then(move |conn| tokio_io::io::write_all(conn, data))
+			.and_then(|(conn, _)| tokio_io::io::shutdown(conn));
+
+		let (received, _) = core.run(server.join(client)).unwrap();
+		assert_eq!(received, expected);
+	}
+
+	#[test]
+	fn dial_reuses_listen_socket() {
+		let mut core = Core::new().unwrap();
+		let utp = UtpConfig::new(core.handle());
+
+		let (_listener, local_addr) = utp.clone()
+			.listen_on("/ip4/127.0.0.1/udp/0/utp".parse().unwrap())
+			.unwrap();
+		let (remote_listener, remote_addr) = UtpConfig::new(core.handle())
+			.listen_on("/ip4/127.0.0.1/udp/0/utp".parse().unwrap())
+			.unwrap();
+
+		let server = remote_listener
+			.into_future()
+			.map_err(|(err, _)| err)
+			.map(|(conn, _)| conn.unwrap().1);
+		let client = utp.dial(remote_addr).unwrap();
+
+		// The remote sees the address of our listener.
+		let (observed_addr, _) = core.run(server.join(client)).unwrap();
+		assert_eq!(observed_addr, local_addr);
+	}
+}
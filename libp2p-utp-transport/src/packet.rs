@@ -0,0 +1,212 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Encoding and decoding of the uTP packets, as described in BEP 29.
+
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+
+/// Size of the header of every packet.
+pub const HEADER_LEN: usize = 20;
+
+/// Version of the protocol, stored in the low four bits of the first byte of the header.
+const VERSION: u8 = 1;
+
+/// Type of a packet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PacketType {
+	/// Contains data.
+	Data,
+	/// Finishes the connection. No data will be sent after this packet.
+	Fin,
+	/// Only acknowledges packets, without consuming a sequence number.
+	State,
+	/// Terminates the connection abruptly.
+	Reset,
+	/// Opens a new connection.
+	Syn,
+}
+
+impl PacketType {
+	#[inline]
+	fn to_u8(self) -> u8 {
+		match self {
+			PacketType::Data => 0,
+			PacketType::Fin => 1,
+			PacketType::State => 2,
+			PacketType::Reset => 3,
+			PacketType::Syn => 4,
+		}
+	}
+
+	#[inline]
+	fn from_u8(value: u8) -> Option<PacketType> {
+		match value {
+			0 => Some(PacketType::Data),
+			1 => Some(PacketType::Fin),
+			2 => Some(PacketType::State),
+			3 => Some(PacketType::Reset),
+			4 => Some(PacketType::Syn),
+			_ => None,
+		}
+	}
+}
+
+/// A decoded packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+	pub ty: PacketType,
+	pub connection_id: u16,
+	pub timestamp: u32,
+	pub timestamp_diff: u32,
+	pub wnd_size: u32,
+	pub seq_nr: u16,
+	pub ack_nr: u16,
+	pub payload: Vec<u8>,
+}
+
+impl Packet {
+	/// Decodes a packet received from the network. Extensions are skipped.
+	pub fn decode(bytes: &[u8]) -> Result<Packet, IoError> {
+		if bytes.len() < HEADER_LEN {
+			return Err(IoError::new(IoErrorKind::InvalidData, "uTP packet too short"));
+		}
+
+		if bytes[0] & 0xf != VERSION {
+			return Err(IoError::new(IoErrorKind::InvalidData, "unsupported uTP version"));
+		}
+
+		let ty = PacketType::from_u8(bytes[0] >> 4)
+			.ok_or_else(|| IoError::new(IoErrorKind::InvalidData, "unknown uTP packet type"))?;
+
+		// Each extension starts with the type of the next one and its length.
+		let mut offset = HEADER_LEN;
+		let mut extension = bytes[1];
+		while extension != 0 {
+			if bytes.len() < offset + 2 || bytes.len() < offset + 2 + bytes[offset + 1] as usize {
+				return Err(IoError::new(IoErrorKind::InvalidData, "truncated uTP extension"));
+			}
+			extension = bytes[offset];
+			offset += 2 + bytes[offset + 1] as usize;
+		}
+
+		Ok(Packet {
+			ty: ty,
+			connection_id: read_u16(&bytes[2..]),
+			timestamp: read_u32(&bytes[4..]),
+			timestamp_diff: read_u32(&bytes[8..]),
+			wnd_size: read_u32(&bytes[12..]),
+			seq_nr: read_u16(&bytes[16..]),
+			ack_nr: read_u16(&bytes[18..]),
+			payload: bytes[offset..].to_vec(),
+		})
+	}
+
+	/// Encodes the packet, without any extension.
+	pub fn encode(&self) -> Vec<u8> {
+		let mut out = Vec::with_capacity(HEADER_LEN + self.payload.len());
+		out.push((self.ty.to_u8() << 4) | VERSION);
+		out.push(0);
+		write_u16(&mut out, self.connection_id);
+		write_u32(&mut out, self.timestamp);
+		write_u32(&mut out, self.timestamp_diff);
+		write_u32(&mut out, self.wnd_size);
+		write_u16(&mut out, self.seq_nr);
+		write_u16(&mut out, self.ack_nr);
+		out.extend_from_slice(&self.payload);
+		out
+	}
+}
+
+#[inline]
+fn read_u16(bytes: &[u8]) -> u16 {
+	(bytes[0] as u16) << 8 | bytes[1] as u16
+}
+
+#[inline]
+fn read_u32(bytes: &[u8]) -> u32 {
+	(read_u16(bytes) as u32) << 16 | read_u16(&bytes[2..]) as u32
+}
+
+#[inline]
+fn write_u16(out: &mut Vec<u8>, value: u16) {
+	out.push((value >> 8) as u8);
+	out.push(value as u8);
+}
+
+#[inline]
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+	write_u16(out, (value >> 16) as u16);
+	write_u16(out, value as u16);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Packet, PacketType};
+
+	#[test]
+	fn encode_decode() {
+		let packet = Packet {
+			ty: PacketType::Data,
+			connection_id: 0x1234,
+			timestamp: 0xdeadbeef,
+			timestamp_diff: 5,
+			wnd_size: 65536,
+			seq_nr: 0xffff,
+			ack_nr: 7,
+			payload: b"hello world".to_vec(),
+		};
+
+		let bytes = packet.encode();
+		assert_eq!(&bytes[..4], &[0x01, 0x00, 0x12, 0x34]);
+		assert_eq!(Packet::decode(&bytes).unwrap(), packet);
+	}
+
+	#[test]
+	fn skips_extensions() {
+		let mut bytes = Packet {
+			ty: PacketType::State,
+			connection_id: 1,
+			timestamp: 2,
+			timestamp_diff: 3,
+			wnd_size: 4,
+			seq_nr: 5,
+			ack_nr: 6,
+			payload: Vec::new(),
+		}.encode();
+
+		// A selective ack extension of four bytes.
+		bytes[1] = 1;
+		bytes.extend_from_slice(&[0, 4, 0xff, 0xff, 0xff, 0xff]);
+		let packet = Packet::decode(&bytes).unwrap();
+		assert_eq!(packet.ty, PacketType::State);
+		assert!(packet.payload.is_empty());
+
+		// Truncated extension.
+		bytes.truncate(bytes.len() - 1);
+		assert!(Packet::decode(&bytes).is_err());
+	}
+
+	#[test]
+	fn rejects_garbage() {
+		assert!(Packet::decode(&[0x01, 0, 0]).is_err());
+		assert!(Packet::decode(&[0x02; 20]).is_err());
+		assert!(Packet::decode(&[0x91; 20]).is_err());
+	}
+}
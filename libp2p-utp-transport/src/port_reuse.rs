@@ -0,0 +1,99 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Reusing the sockets of the listeners for the outgoing connections.
+//!
+//! A uTP connection is identified by the address of the remote and a connection id, so a single
+//! UDP socket can carry the connections of a listener and the dials at the same time. When the
+//! dials go through the socket of a listener, the remotes observe the address that we listen on,
+//! which is what NAT hole punching relies on.
+
+use futures::{Poll, Stream};
+use parking_lot::Mutex;
+use socket::Shared;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Sockets of the listeners that the dials reuse. Shared between the clones of a `UtpConfig`.
+#[derive(Clone, Default)]
+pub struct PortReuse {
+	listeners: Arc<Mutex<Vec<(SocketAddr, Arc<Mutex<Shared>>)>>>,
+}
+
+impl PortReuse {
+	/// Registers `shared` as the socket of a listener bound to `addr`, until the returned stream
+	/// is dropped.
+	pub fn register<S>(self, addr: SocketAddr, shared: Arc<Mutex<Shared>>, listener: S)
+					   -> PortReuseListener<S>
+	{
+		self.listeners.lock().push((addr, shared));
+		PortReuseListener {
+			inner: listener,
+			port_reuse: self,
+			addr: addr,
+		}
+	}
+
+	/// Returns the socket of a listener of the same IP version as `remote`, if there is one.
+	pub fn socket_for(&self, remote: &SocketAddr) -> Option<Arc<Mutex<Shared>>> {
+		self.listeners
+			.lock()
+			.iter()
+			.find(|&&(ref addr, _)| addr.is_ipv4() == remote.is_ipv4())
+			.map(|&(_, ref shared)| shared.clone())
+	}
+}
+
+impl fmt::Debug for PortReuse {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let addrs: Vec<_> = self.listeners.lock().iter().map(|&(addr, _)| addr).collect();
+		f.debug_tuple("PortReuse").field(&addrs).finish()
+	}
+}
+
+/// Stream of the incoming connections of a listener registered with `PortReuse::register()`.
+/// Unregisters the listener when dropped.
+pub struct PortReuseListener<S> {
+	inner: S,
+	port_reuse: PortReuse,
+	addr: SocketAddr,
+}
+
+impl<S> Stream for PortReuseListener<S>
+	where S: Stream
+{
+	type Item = S::Item;
+	type Error = S::Error;
+
+	#[inline]
+	fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+		self.inner.poll()
+	}
+}
+
+impl<S> Drop for PortReuseListener<S> {
+	fn drop(&mut self) {
+		let mut listeners = self.port_reuse.listeners.lock();
+		if let Some(pos) = listeners.iter().position(|&(ref addr, _)| *addr == self.addr) {
+			listeners.remove(pos);
+		}
+	}
+}
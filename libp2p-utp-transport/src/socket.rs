@@ -0,0 +1,750 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the state of a UDP socket, shared between the connections that use it, and the
+//! background task that sends and receives its packets.
+//!
+//! The background task is spawned when the socket is opened, on the tokio reactor or on the
+//! executor of the transport if there is one. It finishes once the socket isn't used by any
+//! connection, listener or dial anymore, and all the connections have been closed.
+
+use futures::{Async, Future, Poll, Stream};
+use futures::task::{self, Task};
+use packet::{Packet, PacketType};
+use parking_lot::Mutex;
+use rand;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use swarm::Executor;
+use tokio_core::net::UdpSocket;
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Maximum size of the payload of a packet, so that the datagrams stay below the usual MTU.
+const MAX_PAYLOAD: usize = 1200;
+/// Maximum number of packets that have been sent and not acknowledged yet, per connection.
+const MAX_IN_FLIGHT: usize = 64;
+/// Maximum number of bytes that a connection buffers in each direction.
+const MAX_BUFFER: usize = 256 * 1024;
+/// Maximum number of incoming connections that haven't been accepted by the listener yet.
+const MAX_PENDING_INCOMING: usize = 128;
+/// Number of retransmissions of the same packets after which the connection is considered dead.
+const MAX_RETRIES: u32 = 6;
+
+// Delay before the first retransmission. Doubled after each retransmission.
+#[inline]
+fn initial_rto() -> Duration {
+	Duration::from_millis(1000)
+}
+
+/// Identifies a connection within a socket: the address of the remote, and the connection id
+/// of the packets that we receive.
+pub type ConnectionKey = (SocketAddr, u16);
+
+/// State shared between the background task, the listener and the connections of a socket.
+pub struct Shared {
+	connections: HashMap<ConnectionKey, Connection>,
+	// Connections opened by remotes that the listener hasn't produced yet. `None` if the socket
+	// doesn't accept incoming connections.
+	incoming: Option<VecDeque<ConnectionKey>>,
+	listener_task: Option<Task>,
+	driver_task: Option<Task>,
+	// Set if the socket produced an error. The background task has stopped.
+	socket_error: Option<IoErrorKind>,
+}
+
+impl Shared {
+	#[inline]
+	fn notify_driver(&self) {
+		if let Some(ref task) = self.driver_task {
+			task.notify();
+		}
+	}
+}
+
+// A packet that has been queued, and that is retransmitted until it is acknowledged.
+struct InFlight {
+	ty: PacketType,
+	seq_nr: u16,
+	payload: Vec<u8>,
+	sent: bool,
+}
+
+// State of a single connection.
+struct Connection {
+	// Connection id of the packets that we send.
+	send_id: u16,
+	// False until the remote has acknowledged our SYN. Always true for incoming connections.
+	connected: bool,
+	// Sequence number of the next packet that we send.
+	seq_nr: u16,
+	// Sequence number of the first packet we sent, which is reported when acknowledging a SYN.
+	initial_seq_nr: u16,
+	// Sequence number of the last packet that we received in order.
+	ack_nr: u16,
+	in_flight: VecDeque<InFlight>,
+	// Number of bytes that the remote accepts to buffer.
+	remote_wnd: usize,
+	// Data written by the user and not split into packets yet.
+	to_send: VecDeque<u8>,
+	// Data received in order and not read by the user yet.
+	received: VecDeque<u8>,
+	need_ack: bool,
+	need_syn_ack: bool,
+	// When to retransmit the packets in flight.
+	deadline: Option<Instant>,
+	rto: Duration,
+	retries: u32,
+	// True if the user has shut down the writing side. A FIN is sent after the remaining data.
+	close_requested: bool,
+	fin_sent: bool,
+	// True if we have received the FIN of the remote.
+	remote_closed: bool,
+	// True if the `UtpStream` has been destroyed.
+	dropped: bool,
+	error: Option<IoErrorKind>,
+	read_task: Option<Task>,
+	write_task: Option<Task>,
+}
+
+impl Connection {
+	fn new(send_id: u16, seq_nr: u16, connected: bool) -> Connection {
+		Connection {
+			send_id: send_id,
+			connected: connected,
+			seq_nr: seq_nr,
+			initial_seq_nr: seq_nr,
+			ack_nr: 0,
+			in_flight: VecDeque::new(),
+			remote_wnd: MAX_BUFFER,
+			to_send: VecDeque::new(),
+			received: VecDeque::new(),
+			need_ack: false,
+			need_syn_ack: false,
+			deadline: None,
+			rto: initial_rto(),
+			retries: 0,
+			close_requested: false,
+			fin_sent: false,
+			remote_closed: false,
+			dropped: false,
+			error: None,
+			read_task: None,
+			write_task: None,
+		}
+	}
+
+	// Wakes up the tasks that use the connection.
+	fn notify(&mut self) {
+		if let Some(task) = self.read_task.take() {
+			task.notify();
+		}
+		if let Some(task) = self.write_task.take() {
+			task.notify();
+		}
+	}
+
+	fn fail(&mut self, kind: IoErrorKind) {
+		if self.error.is_none() {
+			self.error = Some(kind);
+		}
+		self.in_flight.clear();
+		self.deadline = None;
+		self.notify();
+	}
+
+	// Queues a packet that must be acknowledged by the remote.
+	fn push_in_flight(&mut self, ty: PacketType, payload: Vec<u8>) {
+		self.in_flight.push_back(InFlight {
+			ty: ty,
+			seq_nr: self.seq_nr,
+			payload: payload,
+			sent: false,
+		});
+		self.seq_nr = self.seq_nr.wrapping_add(1);
+	}
+
+	fn on_packet(&mut self, packet: Packet) {
+		if packet.ty == PacketType::Reset {
+			self.fail(IoErrorKind::ConnectionReset);
+			return;
+		}
+
+		self.remote_wnd = packet.wnd_size as usize;
+
+		if !self.connected {
+			// Until the remote acknowledges our SYN, we don't know its first sequence number and
+			// can't process anything else. The remote will retransmit its packets.
+			if packet.ty != PacketType::State {
+				return;
+			}
+			self.connected = true;
+			self.ack_nr = packet.seq_nr.wrapping_sub(1);
+			self.notify();
+		}
+
+		let mut acked = false;
+		while self.in_flight.front().map_or(false, |p| p.sent && seq_le(p.seq_nr, packet.ack_nr)) {
+			self.in_flight.pop_front();
+			acked = true;
+		}
+		if acked {
+			self.retries = 0;
+			self.rto = initial_rto();
+			self.deadline = None;
+			self.notify();
+		}
+
+		match packet.ty {
+			PacketType::Data | PacketType::Fin
+				if packet.seq_nr == self.ack_nr.wrapping_add(1) && !self.remote_closed =>
+			{
+				if packet.ty == PacketType::Fin {
+					self.remote_closed = true;
+				} else if self.received.len() + packet.payload.len() <= MAX_BUFFER {
+					self.received.extend(packet.payload);
+				} else {
+					// No room for the data. The remote will retransmit it.
+					self.need_ack = true;
+					return;
+				}
+				self.ack_nr = packet.seq_nr;
+				self.need_ack = true;
+				self.notify();
+			},
+			// Duplicate or out of order. Acknowledging what we have makes the remote retransmit.
+			PacketType::Data | PacketType::Fin => self.need_ack = true,
+			_ => (),
+		}
+	}
+
+	// True if the connection can be removed from the socket.
+	#[inline]
+	fn is_finished(&self) -> bool {
+		self.dropped && (self.error.is_some() || (self.fin_sent && self.in_flight.is_empty()))
+	}
+}
+
+// Returns true if `a` is before or equal to `b`, taking into account the wrapping of the
+// sequence numbers.
+#[inline]
+fn seq_le(a: u16, b: u16) -> bool {
+	b.wrapping_sub(a) < 0x8000
+}
+
+// Returns the current time in microseconds, truncated to 32 bits, as put in the packets.
+fn timestamp() -> u32 {
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::new(0, 0));
+	(now.as_secs().wrapping_mul(1_000_000) + (now.subsec_nanos() / 1000) as u64) as u32
+}
+
+/// Binds a UDP socket and spawns its background task on `executor`, or on `handle` if there is
+/// no executor. If `listen` is true, the connections opened by remotes are accepted and can be
+/// retrieved with a `UtpListener`.
+pub fn bind(addr: &SocketAddr, listen: bool, handle: &Handle, executor: Option<&Arc<Executor>>)
+			-> Result<(Arc<Mutex<Shared>>, SocketAddr), IoError>
+{
+	let socket = UdpSocket::bind(addr, handle)?;
+	let local_addr = socket.local_addr()?;
+	let timer = Timeout::new(Duration::from_secs(3600), handle)?;
+
+	let shared = Arc::new(Mutex::new(Shared {
+		connections: HashMap::new(),
+		incoming: if listen { Some(VecDeque::new()) } else { None },
+		listener_task: None,
+		driver_task: None,
+		socket_error: None,
+	}));
+
+	let driver = Driver {
+		socket: socket,
+		shared: shared.clone(),
+		timer: timer,
+		buffer: vec![0; 64 * 1024],
+		outgoing: VecDeque::new(),
+	};
+
+	match executor {
+		Some(executor) => executor.spawn(Box::new(driver)),
+		None => handle.spawn(driver),
+	}
+
+	Ok((shared, local_addr))
+}
+
+/// Opens a connection to `remote` on the socket.
+pub fn connect(shared: Arc<Mutex<Shared>>, remote: SocketAddr) -> UtpDial {
+	let key = {
+		let mut inner = shared.lock();
+		let recv_id = loop {
+			let id = rand::random::<u16>();
+			if !inner.connections.contains_key(&(remote, id)) {
+				break id;
+			}
+		};
+
+		// The SYN has the sequence number 1 and our receiving connection id; all the other
+		// packets use the sending connection id.
+		let mut connection = Connection::new(recv_id.wrapping_add(1), 1, false);
+		connection.push_in_flight(PacketType::Syn, Vec::new());
+		inner.connections.insert((remote, recv_id), connection);
+		inner.notify_driver();
+		(remote, recv_id)
+	};
+
+	UtpDial {
+		stream: Some(UtpStream {
+			shared: shared,
+			key: key,
+		}),
+	}
+}
+
+// Background task of a socket.
+struct Driver {
+	socket: UdpSocket,
+	shared: Arc<Mutex<Shared>>,
+	// Fires when the earliest retransmission is due.
+	timer: Timeout,
+	buffer: Vec<u8>,
+	// Datagrams waiting for the socket to be writable.
+	outgoing: VecDeque<(Vec<u8>, SocketAddr)>,
+}
+
+impl Driver {
+	fn on_packet(&mut self, packet: Packet, from: SocketAddr) {
+		let mut shared = self.shared.lock();
+		let shared = &mut *shared;
+
+		if packet.ty != PacketType::Syn {
+			if let Some(connection) = shared.connections.get_mut(&(from, packet.connection_id)) {
+				connection.on_packet(packet);
+			}
+			return;
+		}
+
+		let key = (from, packet.connection_id.wrapping_add(1));
+		if !shared.connections.contains_key(&key) {
+			let accepted = match shared.incoming {
+				Some(ref mut incoming) => if incoming.len() < MAX_PENDING_INCOMING {
+					incoming.push_back(key);
+					true
+				} else {
+					false
+				},
+				None => false,
+			};
+			// If we're not listening or there are too many pending connections, the remote will
+			// time out.
+			if !accepted {
+				return;
+			}
+
+			let mut connection = Connection::new(packet.connection_id, rand::random(), true);
+			connection.ack_nr = packet.seq_nr;
+			connection.remote_wnd = packet.wnd_size as usize;
+			shared.connections.insert(key, connection);
+			if let Some(task) = shared.listener_task.take() {
+				task.notify();
+			}
+		}
+
+		// Acknowledge the SYN, again if it has been retransmitted.
+		let connection = shared.connections.get_mut(&key).expect("inserted above if missing");
+		connection.need_ack = true;
+		connection.need_syn_ack = true;
+	}
+
+	// Processes the timeouts and queues the packets to send. Returns the earliest deadline.
+	fn process(&mut self) -> Option<Instant> {
+		let now = Instant::now();
+		let mut next_deadline: Option<Instant> = None;
+		let mut shared = self.shared.lock();
+		let shared = &mut *shared;
+
+		for (&(remote, recv_id), connection) in shared.connections.iter_mut() {
+			if connection.deadline.map_or(false, |deadline| deadline <= now) {
+				connection.deadline = None;
+				connection.retries += 1;
+				if connection.retries > MAX_RETRIES {
+					connection.fail(IoErrorKind::TimedOut);
+				} else {
+					// Go-back-N: everything that hasn't been acknowledged is sent again.
+					connection.rto = connection.rto * 2;
+					for packet in connection.in_flight.iter_mut() {
+						packet.sent = false;
+					}
+				}
+			}
+
+			if connection.error.is_some() {
+				continue;
+			}
+
+			if connection.connected {
+				let mut made_room = false;
+				let mut in_flight_bytes: usize = connection.in_flight.iter()
+					.map(|packet| packet.payload.len())
+					.sum();
+				while connection.in_flight.len() < MAX_IN_FLIGHT && !connection.to_send.is_empty() {
+					let len = cmp::min(MAX_PAYLOAD, connection.to_send.len());
+					// Respect the window of the remote, but always allow one packet in flight in
+					// order to probe it.
+					if !connection.in_flight.is_empty() &&
+						in_flight_bytes + len > connection.remote_wnd
+					{
+						break;
+					}
+
+					let payload = connection.to_send.drain(.. len).collect();
+					connection.push_in_flight(PacketType::Data, payload);
+					in_flight_bytes += len;
+					made_room = true;
+				}
+				if made_room {
+					if let Some(task) = connection.write_task.take() {
+						task.notify();
+					}
+				}
+
+				if connection.close_requested && connection.to_send.is_empty() &&
+					!connection.fin_sent && connection.in_flight.len() < MAX_IN_FLIGHT
+				{
+					connection.push_in_flight(PacketType::Fin, Vec::new());
+					connection.fin_sent = true;
+				}
+			}
+
+			let wnd_size = (MAX_BUFFER - connection.received.len()) as u32;
+			let ack_nr = connection.ack_nr;
+			let send_id = connection.send_id;
+			for packet in connection.in_flight.iter_mut().filter(|p| !p.sent) {
+				let encoded = Packet {
+					ty: packet.ty,
+					connection_id: if packet.ty == PacketType::Syn { recv_id } else { send_id },
+					timestamp: timestamp(),
+					timestamp_diff: 0,
+					wnd_size: wnd_size,
+					seq_nr: packet.seq_nr,
+					ack_nr: ack_nr,
+					payload: packet.payload.clone(),
+				}.encode();
+				self.outgoing.push_back((encoded, remote));
+				packet.sent = true;
+				// The packets that we send carry the acknowledgement.
+				connection.need_ack = false;
+			}
+
+			if connection.need_ack || connection.need_syn_ack {
+				let seq_nr = if connection.need_syn_ack {
+					connection.initial_seq_nr
+				} else {
+					connection.seq_nr
+				};
+				let encoded = Packet {
+					ty: PacketType::State,
+					connection_id: send_id,
+					timestamp: timestamp(),
+					timestamp_diff: 0,
+					wnd_size: wnd_size,
+					seq_nr: seq_nr,
+					ack_nr: ack_nr,
+					payload: Vec::new(),
+				}.encode();
+				self.outgoing.push_back((encoded, remote));
+				connection.need_ack = false;
+				connection.need_syn_ack = false;
+			}
+
+			if !connection.in_flight.is_empty() && connection.deadline.is_none() {
+				connection.deadline = Some(now + connection.rto);
+			}
+			if let Some(deadline) = connection.deadline {
+				next_deadline = Some(next_deadline.map_or(deadline, |d| cmp::min(d, deadline)));
+			}
+		}
+
+		shared.connections.retain(|_, connection| !connection.is_finished());
+		next_deadline
+	}
+
+	// Reports an error of the socket to all its users.
+	fn fail(&mut self, error: IoError) {
+		let mut shared = self.shared.lock();
+		shared.socket_error = Some(error.kind());
+		for connection in shared.connections.values_mut() {
+			connection.fail(error.kind());
+		}
+		if let Some(task) = shared.listener_task.take() {
+			task.notify();
+		}
+	}
+}
+
+impl Future for Driver {
+	type Item = ();
+	type Error = ();
+
+	fn poll(&mut self) -> Poll<(), ()> {
+		loop {
+			loop {
+				match self.socket.recv_from(&mut self.buffer) {
+					Ok((len, from)) => {
+						if let Ok(packet) = Packet::decode(&self.buffer[.. len]) {
+							self.on_packet(packet, from);
+						}
+					},
+					Err(ref err) if err.kind() == IoErrorKind::WouldBlock => break,
+					// Reported by some platforms when a previous datagram couldn't be delivered.
+					Err(ref err) if err.kind() == IoErrorKind::ConnectionRefused ||
+						err.kind() == IoErrorKind::ConnectionReset => (),
+					Err(err) => {
+						self.fail(err);
+						return Ok(Async::Ready(()));
+					},
+				}
+			}
+
+			let next_deadline = self.process();
+
+			while let Some((datagram, remote)) = self.outgoing.pop_front() {
+				match self.socket.send_to(&datagram, &remote) {
+					Ok(_) => (),
+					Err(ref err) if err.kind() == IoErrorKind::WouldBlock => {
+						self.outgoing.push_front((datagram, remote));
+						break;
+					},
+					// Losing a datagram is not a problem, as it will be retransmitted if needed.
+					Err(_) => (),
+				}
+			}
+
+			{
+				let mut shared = self.shared.lock();
+				if Arc::strong_count(&self.shared) == 1 && shared.connections.is_empty() {
+					return Ok(Async::Ready(()));
+				}
+				shared.driver_task = Some(task::current());
+			}
+
+			let next_deadline = next_deadline
+				.unwrap_or_else(|| Instant::now() + Duration::from_secs(3600));
+			self.timer.reset(next_deadline);
+			match self.timer.poll() {
+				Ok(Async::Ready(())) => continue,
+				Ok(Async::NotReady) => return Ok(Async::NotReady),
+				Err(err) => {
+					self.fail(err);
+					return Ok(Async::Ready(()));
+				},
+			}
+		}
+	}
+}
+
+/// Produces the connections opened by remotes on a socket.
+pub struct UtpListener {
+	shared: Arc<Mutex<Shared>>,
+}
+
+impl UtpListener {
+	/// Builds a listener for a socket bound with `listen` set to true.
+	#[inline]
+	pub fn new(shared: Arc<Mutex<Shared>>) -> UtpListener {
+		UtpListener { shared: shared }
+	}
+}
+
+impl Stream for UtpListener {
+	type Item = (UtpStream, SocketAddr);
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Option<Self::Item>, IoError> {
+		let mut shared = self.shared.lock();
+		if let Some(kind) = shared.socket_error {
+			return Err(IoError::new(kind, "uTP socket failed"));
+		}
+
+		let key = shared.incoming.as_mut().and_then(|incoming| incoming.pop_front());
+		if let Some(key) = key {
+			let stream = UtpStream {
+				shared: self.shared.clone(),
+				key: key,
+			};
+			return Ok(Async::Ready(Some((stream, key.0))));
+		}
+
+		shared.listener_task = Some(task::current());
+		Ok(Async::NotReady)
+	}
+}
+
+impl Drop for UtpListener {
+	fn drop(&mut self) {
+		let mut shared = self.shared.lock();
+		let shared = &mut *shared;
+		// The connections that haven't been produced are closed.
+		if let Some(incoming) = shared.incoming.take() {
+			for key in incoming {
+				if let Some(connection) = shared.connections.get_mut(&key) {
+					connection.dropped = true;
+					connection.close_requested = true;
+				}
+			}
+		}
+		shared.notify_driver();
+	}
+}
+
+/// Future that resolves to a `UtpStream` once the remote has accepted the connection.
+pub struct UtpDial {
+	stream: Option<UtpStream>,
+}
+
+impl Future for UtpDial {
+	type Item = UtpStream;
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<UtpStream, IoError> {
+		{
+			let stream = self.stream.as_ref().expect("polled a finished UtpDial");
+			let mut shared = stream.shared.lock();
+			let connection = shared.connections.get_mut(&stream.key)
+				.expect("a connection is only removed once its stream is dropped");
+			if let Some(kind) = connection.error {
+				return Err(IoError::new(kind, "failed to open uTP connection"));
+			}
+			if !connection.connected {
+				connection.read_task = Some(task::current());
+				return Ok(Async::NotReady);
+			}
+		}
+
+		Ok(Async::Ready(self.stream.take().expect("checked above")))
+	}
+}
+
+/// A uTP connection.
+pub struct UtpStream {
+	shared: Arc<Mutex<Shared>>,
+	key: ConnectionKey,
+}
+
+impl Read for UtpStream {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+		let mut shared = self.shared.lock();
+		let shared = &mut *shared;
+		let (len, window_update) = {
+			let connection = shared.connections.get_mut(&self.key)
+				.expect("a connection is only removed once its stream is dropped");
+
+			if connection.received.is_empty() {
+				if let Some(kind) = connection.error {
+					return Err(IoError::new(kind, "uTP connection failed"));
+				}
+				if connection.remote_closed {
+					return Ok(0);
+				}
+
+				connection.read_task = Some(task::current());
+				return Err(IoErrorKind::WouldBlock.into());
+			}
+
+			// If the buffer was getting full, the remote may be waiting for a bigger window.
+			let window_update = connection.received.len() >= MAX_BUFFER / 2;
+			let len = cmp::min(buf.len(), connection.received.len());
+			for (dest, byte) in buf.iter_mut().zip(connection.received.drain(.. len)) {
+				*dest = byte;
+			}
+			if window_update {
+				connection.need_ack = true;
+			}
+			(len, window_update)
+		};
+
+		if window_update {
+			shared.notify_driver();
+		}
+		Ok(len)
+	}
+}
+
+impl AsyncRead for UtpStream {}
+
+impl Write for UtpStream {
+	fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+		let mut shared = self.shared.lock();
+		let shared = &mut *shared;
+		let len = {
+			let connection = shared.connections.get_mut(&self.key)
+				.expect("a connection is only removed once its stream is dropped");
+
+			if let Some(kind) = connection.error {
+				return Err(IoError::new(kind, "uTP connection failed"));
+			}
+			if connection.close_requested {
+				return Err(IoErrorKind::BrokenPipe.into());
+			}
+
+			let len = cmp::min(buf.len(), MAX_BUFFER - connection.to_send.len());
+			if len == 0 && !buf.is_empty() {
+				connection.write_task = Some(task::current());
+				return Err(IoErrorKind::WouldBlock.into());
+			}
+			connection.to_send.extend(&buf[.. len]);
+			len
+		};
+
+		shared.notify_driver();
+		Ok(len)
+	}
+
+	#[inline]
+	fn flush(&mut self) -> Result<(), IoError> {
+		// The data is sent by the background task as soon as possible.
+		Ok(())
+	}
+}
+
+impl AsyncWrite for UtpStream {
+	fn shutdown(&mut self) -> Poll<(), IoError> {
+		let mut shared = self.shared.lock();
+		if let Some(connection) = shared.connections.get_mut(&self.key) {
+			connection.close_requested = true;
+		}
+		shared.notify_driver();
+		Ok(Async::Ready(()))
+	}
+}
+
+impl Drop for UtpStream {
+	fn drop(&mut self) {
+		let mut shared = self.shared.lock();
+		if let Some(connection) = shared.connections.get_mut(&self.key) {
+			connection.dropped = true;
+			connection.close_requested = true;
+		}
+		shared.notify_driver();
+	}
+}
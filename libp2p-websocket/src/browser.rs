@@ -36,6 +36,8 @@ use tokio_io::{AsyncRead, AsyncWrite};
 ///
 /// This implementation of `Transport` accepts any address that looks like
 /// `/ip4/.../tcp/.../ws` or `/ip6/.../tcp/.../ws`, and connect to the corresponding IP and port.
+/// The IP address can also be replaced with `/dns4/...` or `/dns6/...`, in which case the name is
+/// resolved by the browser. The same addresses ending with `/wss` use a secure websocket.
 #[derive(Debug, Clone)]
 pub struct BrowserWsConfig;
 
@@ -49,8 +51,10 @@ impl BrowserWsConfig {
 
 impl Transport for BrowserWsConfig {
 	type RawConn = BrowserWsConn;
-	type Listener = Box<Stream<Item = (Self::ListenerUpgrade, Multiaddr), Error = IoError>>; // TODO: use `!`
-	type ListenerUpgrade = Box<Future<Item = Self::RawConn, Error = IoError>>; // TODO: use `!`
+	// TODO: use `!`
+	type Listener = Box<Stream<Item = (Self::ListenerUpgrade, Multiaddr), Error = IoError> + Send>;
+	// TODO: use `!`
+	type ListenerUpgrade = Box<Future<Item = Self::RawConn, Error = IoError> + Send>;
 	type Dial = FutureThen<
 		oneshot::Receiver<Result<BrowserWsConn, IoError>>,
 		Result<BrowserWsConn, IoError>,
@@ -98,7 +102,8 @@ impl Transport for BrowserWsConfig {
 		// by `open_cb`.
 		let (message_tx, message_rx) = mpsc::unbounded::<Result<Vec<u8>, IoError>>();
 		let message_tx = Arc::new(message_tx);
-		let mut message_rx = Some(message_rx);
+		// Wrapped in a `Mutex` so that `open_cb` is a `Fn`, which is what stdweb expects.
+		let message_rx = Mutex::new(Some(message_rx));
 		let message_cb = {
 			let message_tx = message_tx.clone();
 			move |message_data: Reference| {
@@ -132,7 +137,11 @@ impl Transport for BrowserWsConfig {
 					.expect("the websocket can only open once");
 				// `message_rx` can be empty if the `open` event is triggered twice, which again
 				// is not supposed to happen.
-				let message_rx = message_rx.take().expect("the websocket can only open once");
+				let message_rx = message_rx
+					.lock()
+					.unwrap()
+					.take()
+					.expect("the websocket can only open once");
 
 				// Send a `BrowserWsConn` to the future that was returned by `dial`. Ignoring errors that
 				// would happen the future has been dropped by the user.
@@ -330,6 +339,14 @@ fn multiaddr_to_target(addr: &Multiaddr) -> Result<String, ()> {
 	}
 
 	match (&protocols[0], &protocols[1], &protocols[2]) {
+		(&AddrComponent::DNS4(ref name), &AddrComponent::TCP(port), &AddrComponent::WS) |
+		(&AddrComponent::DNS6(ref name), &AddrComponent::TCP(port), &AddrComponent::WS) => {
+			Ok(format!("ws://{}:{}/", name, port))
+		}
+		(&AddrComponent::DNS4(ref name), &AddrComponent::TCP(port), &AddrComponent::WSS) |
+		(&AddrComponent::DNS6(ref name), &AddrComponent::TCP(port), &AddrComponent::WSS) => {
+			Ok(format!("wss://{}:{}/", name, port))
+		}
 		(&AddrComponent::IP4(ref ip), &AddrComponent::TCP(port), &AddrComponent::WS) => {
 			Ok(format!("ws://{}:{}/", ip, port))
 		}
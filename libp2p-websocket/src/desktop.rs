@@ -18,11 +18,15 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use futures::{stream, Future, IntoFuture, Sink, Stream};
+use futures::{future, Future, IntoFuture, Sink, Stream};
 use multiaddr::{AddrComponent, Multiaddr};
+use native_tls::{TlsAcceptor, TlsConnector};
 use rw_stream_sink::RwStreamSink;
+use std::fmt;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::sync::Arc;
 use swarm::Transport;
+use tokio_tls::{TlsAcceptorExt, TlsConnectorExt};
 use websocket::client::builder::ClientBuilder;
 use websocket::message::OwnedMessage;
 use websocket::server::upgrade::async::IntoWs;
@@ -34,10 +38,18 @@ use websocket::stream::async::Stream as AsyncStream;
 /// This implementation of `Transport` accepts any address that ends with `/ws` or `/wss`, and will
 /// try to pass the underlying multiaddress to the underlying `Transport`.
 ///
-/// > **Note**: `/wss` is only supported for dialing and not listening.
-#[derive(Debug, Clone)]
+/// With `/wss`, a TLS session is established on top of the underlying connection before the
+/// websocket handshake. When dialing, the name of the `dns4` or `dns6` component of the address
+/// (or its IP address) is used for the server name indication and for verifying the certificate.
+///
+/// > **Note**: Listening on `/wss` requires passing a `TlsAcceptor` to `with_tls_acceptor()`.
+#[derive(Clone)]
 pub struct WsConfig<T> {
 	transport: T,
+	// Used when dialing `/wss` addresses. If `None`, uses a connector with the default settings.
+	tls_connector: Option<Arc<TlsConnector>>,
+	// Used when listening on `/wss` addresses. If `None`, listening on `/wss` isn't supported.
+	tls_acceptor: Option<Arc<TlsAcceptor>>,
 }
 
 impl<T> WsConfig<T> {
@@ -46,7 +58,50 @@ impl<T> WsConfig<T> {
 	/// The websockets will run on top of the `Transport` you pass as parameter.
 	#[inline]
 	pub fn new(inner: T) -> WsConfig<T> {
-		WsConfig { transport: inner }
+		WsConfig {
+			transport: inner,
+			tls_connector: None,
+			tls_acceptor: None,
+		}
+	}
+
+	/// Sets the `TlsConnector` to use when dialing `/wss` addresses, for example in order to
+	/// trust custom root certificates.
+	///
+	/// ```ignore
+	/// let mut builder = TlsConnector::builder()?;
+	/// builder.add_root_certificate(Certificate::from_der(&root_der)?)?;
+	/// let ws_config = ws_config.with_tls_connector(builder.build()?);
+	/// ```
+	#[inline]
+	pub fn with_tls_connector(mut self, connector: TlsConnector) -> WsConfig<T> {
+		self.tls_connector = Some(Arc::new(connector));
+		self
+	}
+
+	/// Sets the `TlsAcceptor` to use when listening on `/wss` addresses, which contains the
+	/// certificate and the private key of the server.
+	///
+	/// ```ignore
+	/// let identity = Pkcs12::from_der(&pkcs12_der, "password")?;
+	/// let ws_config = ws_config.with_tls_acceptor(TlsAcceptor::builder(identity)?.build()?);
+	/// ```
+	#[inline]
+	pub fn with_tls_acceptor(mut self, acceptor: TlsAcceptor) -> WsConfig<T> {
+		self.tls_acceptor = Some(Arc::new(acceptor));
+		self
+	}
+}
+
+impl<T> fmt::Debug for WsConfig<T>
+	where T: fmt::Debug
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		f.debug_struct("WsConfig")
+			.field("transport", &self.transport)
+			.field("custom_tls_connector", &self.tls_connector.is_some())
+			.field("tls_acceptor", &self.tls_acceptor.is_some())
+			.finish()
 	}
 }
 
@@ -54,44 +109,65 @@ impl<T> Transport for WsConfig<T>
 where
 	T: Transport + 'static, // TODO: this 'static is pretty arbitrary and is necessary because of the websocket library
 	T::RawConn: Send, // TODO: this Send is pretty arbitrary and is necessary because of the websocket library
+	T::Listener: Send,
+	T::ListenerUpgrade: Send,
+	<T::Dial as IntoFuture>::Future: Send,
 {
-	type RawConn = Box<AsyncStream>;
-	type Listener = stream::Map<
-		T::Listener,
-		fn((<T as Transport>::ListenerUpgrade, Multiaddr)) -> (Self::ListenerUpgrade, Multiaddr),
-	>;
-	type ListenerUpgrade = Box<Future<Item = Self::RawConn, Error = IoError>>;
-	type Dial = Box<Future<Item = Self::RawConn, Error = IoError>>;
+	type RawConn = Box<AsyncStream + Send>;
+	type Listener = Box<Stream<Item = (Self::ListenerUpgrade, Multiaddr), Error = IoError> + Send>;
+	type ListenerUpgrade = Box<Future<Item = Self::RawConn, Error = IoError> + Send>;
+	type Dial = Box<Future<Item = Self::RawConn, Error = IoError> + Send>;
 
 	fn listen_on(
 		self,
 		original_addr: Multiaddr,
 	) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
 		let mut inner_addr = original_addr.clone();
-		match inner_addr.pop() {
-			Some(AddrComponent::WS) => {}
+		let (last_proto, tls_acceptor) = match inner_addr.pop() {
+			Some(AddrComponent::WS) => (AddrComponent::WS, None),
+			Some(AddrComponent::WSS) if self.tls_acceptor.is_some() => {
+				(AddrComponent::WSS, self.tls_acceptor.clone())
+			}
 			_ => return Err((self, original_addr)),
 		};
 
-		let (inner_listen, new_addr) = match self.transport.listen_on(inner_addr) {
+		let WsConfig { transport, tls_connector, tls_acceptor: acceptor } = self;
+		let (inner_listen, new_addr) = match transport.listen_on(inner_addr) {
 			Ok((listen, mut new_addr)) => {
-				// Need to suffix `/ws` to the listening address.
-				new_addr.append(AddrComponent::WS);
+				// Need to suffix `/ws` or `/wss` to the listening address.
+				new_addr.append(last_proto.clone());
 				(listen, new_addr)
 			}
 			Err((transport, _)) => {
 				return Err((
 					WsConfig {
 						transport: transport,
+						tls_connector: tls_connector,
+						tls_acceptor: acceptor,
 					},
 					original_addr,
 				));
 			}
 		};
 
-		let listen = inner_listen.map::<_, fn(_) -> _>(|(stream, mut client_addr)| {
-			// Need to suffix `/ws` to each client address.
-			client_addr.append(AddrComponent::WS);
+		let listen = inner_listen.map(move |(stream, mut client_addr)| {
+			// Need to suffix `/ws` or `/wss` to each client address.
+			client_addr.append(last_proto.clone());
+
+			// Establish the TLS session, if necessary.
+			let tls_acceptor = tls_acceptor.clone();
+			let stream = stream.and_then(move |stream| {
+				if let Some(acceptor) = tls_acceptor {
+					let future = acceptor
+						.accept_async(stream)
+						.map_err(|err| IoError::new(IoErrorKind::Other, err))
+						.map(|stream| Box::new(stream) as Box<AsyncStream + Send>);
+					Box::new(future) as Box<Future<Item = _, Error = _> + Send>
+				} else {
+					let stream = Box::new(stream) as Box<AsyncStream + Send>;
+					Box::new(future::ok(stream)) as Box<Future<Item = _, Error = _> + Send>
+				}
+			});
 
 			// Upgrade the listener to websockets like the websockets library requires us to do.
 			let upgraded = stream.and_then(|stream| {
@@ -124,44 +200,82 @@ where
 									.map(|v| v.expect("we only take while this is Some"));
 
 								let read_write = RwStreamSink::new(framed_data);
-								Box::new(read_write) as Box<AsyncStream>
+								Box::new(read_write) as Box<AsyncStream + Send>
 							})
 					})
-					.map(|s| Box::new(Ok(s).into_future()) as Box<Future<Item = _, Error = _>>)
+					.map(|s| {
+						Box::new(Ok(s).into_future()) as Box<Future<Item = _, Error = _> + Send>
+					})
 					.into_future()
 					.flatten()
 			});
 
 			(
-				Box::new(upgraded) as Box<Future<Item = _, Error = _>>,
+				Box::new(upgraded) as Box<Future<Item = _, Error = _> + Send>,
 				client_addr,
 			)
 		});
 
-		Ok((listen, new_addr))
+		Ok((Box::new(listen) as Box<_>, new_addr))
 	}
 
 	fn dial(self, original_addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
 		let mut inner_addr = original_addr.clone();
-		let is_wss = match inner_addr.pop() {
-			Some(AddrComponent::WS) => false,
-			Some(AddrComponent::WSS) => true,
+		let tls_domain = match inner_addr.pop() {
+			Some(AddrComponent::WS) => None,
+			Some(AddrComponent::WSS) => match tls_domain(&inner_addr) {
+				Some(domain) => Some(domain),
+				None => return Err((self, original_addr)),
+			},
 			_ => return Err((self, original_addr)),
 		};
+		let is_wss = tls_domain.is_some();
 
-		let inner_dial = match self.transport.dial(inner_addr) {
+		let WsConfig { transport, tls_connector, tls_acceptor } = self;
+		let inner_dial = match transport.dial(inner_addr) {
 			Ok(d) => d,
 			Err((transport, _)) => {
 				return Err((
 					WsConfig {
 						transport: transport,
+						tls_connector: tls_connector,
+						tls_acceptor: tls_acceptor,
 					},
 					original_addr,
 				));
 			}
 		};
 
-		let dial = inner_dial.into_future().and_then(move |connec| {
+		// Establish the TLS session, if necessary.
+		let connec = inner_dial.into_future().and_then(move |connec| {
+			let domain = match tls_domain {
+				Some(domain) => domain,
+				None => {
+					let connec = Box::new(connec) as Box<AsyncStream + Send>;
+					return Box::new(future::ok(connec)) as Box<Future<Item = _, Error = _> + Send>;
+				}
+			};
+
+			let connector = match tls_connector {
+				Some(connector) => connector,
+				None => match TlsConnector::builder().and_then(|builder| builder.build()) {
+					Ok(connector) => Arc::new(connector),
+					Err(err) => {
+						let err = IoError::new(IoErrorKind::Other, err);
+						let future = future::err(err);
+						return Box::new(future) as Box<Future<Item = _, Error = _> + Send>;
+					}
+				},
+			};
+
+			let future = connector
+				.connect_async(&domain, connec)
+				.map_err(|err| IoError::new(IoErrorKind::Other, err))
+				.map(|connec| Box::new(connec) as Box<AsyncStream + Send>);
+			Box::new(future) as Box<Future<Item = _, Error = _> + Send>
+		});
+
+		let dial = connec.and_then(move |connec| {
 			// We pass a dummy address to `ClientBuilder` because it is never used anywhere
 			// in the negotiation anyway, and we use `async_connect_on` to pass a stream.
 			ClientBuilder::new(if is_wss { "wss://127.0.0.1" } else { "ws://127.0.0.1" })
@@ -185,7 +299,7 @@ where
 							}
 						});
 					let read_write = RwStreamSink::new(framed_data);
-					Box::new(read_write) as Box<AsyncStream>
+					Box::new(read_write) as Box<AsyncStream + Send>
 				})
 		});
 
@@ -209,6 +323,30 @@ where
 		self.transport.nat_traversal(&server, &observed)
 			.map(move |mut result| { result.append(last_proto); result })
 	}
+
+	fn expand_listen_addr(&self, addr: &Multiaddr) -> Vec<Multiaddr> {
+		let mut inner_addr = addr.clone();
+		let last_proto = match inner_addr.pop() {
+			Some(v @ AddrComponent::WS) | Some(v @ AddrComponent::WSS) => v,
+			_ => return vec![addr.clone()],
+		};
+
+		self.transport.expand_listen_addr(&inner_addr)
+			.into_iter()
+			.map(|mut result| { result.append(last_proto.clone()); result })
+			.collect()
+	}
+}
+
+// Returns the name to use for the server name indication and for verifying the certificate of
+// the remote when dialing `addr` with TLS, or `None` if `addr` doesn't start with a host.
+fn tls_domain(addr: &Multiaddr) -> Option<String> {
+	match addr.iter().next() {
+		Some(AddrComponent::DNS4(name)) | Some(AddrComponent::DNS6(name)) => Some(name),
+		Some(AddrComponent::IP4(ip)) => Some(ip.to_string()),
+		Some(AddrComponent::IP6(ip)) => Some(ip.to_string()),
+		_ => None,
+	}
 }
 
 #[cfg(test)]
@@ -216,6 +354,7 @@ mod tests {
 	extern crate libp2p_tcp_transport as tcp;
 	extern crate tokio_core;
 	use self::tokio_core::reactor::Core;
+	use super::tls_domain;
 	use WsConfig;
 	use futures::{Future, Stream};
 	use multiaddr::Multiaddr;
@@ -302,4 +441,25 @@ mod tests {
 					"/ip4/80.81.82.83/tcp/10000/wss".parse::<Multiaddr>().unwrap());
 		}
 	}
+
+	#[test]
+	fn wss_listen_requires_acceptor() {
+		let core = Core::new().unwrap();
+		let ws_config = WsConfig::new(tcp::TcpConfig::new(core.handle()));
+		assert!(ws_config.listen_on("/ip4/127.0.0.1/tcp/0/wss".parse().unwrap()).is_err());
+	}
+
+	#[test]
+	fn tls_domain_from_addr() {
+		let addr = "/dns4/example.com/tcp/443".parse::<Multiaddr>().unwrap();
+		assert_eq!(tls_domain(&addr), Some("example.com".to_owned()));
+		let addr = "/dns6/example.com/tcp/443".parse::<Multiaddr>().unwrap();
+		assert_eq!(tls_domain(&addr), Some("example.com".to_owned()));
+		let addr = "/ip4/1.2.3.4/tcp/443".parse::<Multiaddr>().unwrap();
+		assert_eq!(tls_domain(&addr), Some("1.2.3.4".to_owned()));
+		let addr = "/ip6/::1/tcp/443".parse::<Multiaddr>().unwrap();
+		assert_eq!(tls_domain(&addr), Some("::1".to_owned()));
+		let addr = "/unix/foo".parse::<Multiaddr>().unwrap();
+		assert_eq!(tls_domain(&addr), None);
+	}
 }
@@ -28,16 +28,21 @@
 //! See the documentation of `swarm` and of libp2p in general to learn how to use the `Transport`
 //! trait.
 //!
-//! This library is used in a different way depending on whether you are compiling for emscripten
-//! or for a different operating system.
+//! This library is used in a different way depending on whether you are compiling for a browser
+//! (emscripten or `wasm32-unknown-unknown`) or for a different operating system.
 //!
-//! # Emscripten
+//! # Browsers
 //!
-//! On emscripten, you can create a `BrowserWsConfig` object with `BrowserWsConfig::new()`. It can
-//! then be used as a transport.
+//! When compiling for emscripten or for `wasm32-unknown-unknown`, you can create a
+//! `BrowserWsConfig` object with `BrowserWsConfig::new()`. It can then be used as a transport.
 //!
-//! Listening on a websockets multiaddress isn't supported on emscripten. Dialing a multiaddress
-//! which uses `ws` on top of TCP/IP will automatically use the `XMLHttpRequest` Javascript object.
+//! Listening on a websockets multiaddress isn't supported in browsers. Dialing a multiaddress that
+//! uses `ws` or `wss` on top of TCP/IP or on top of a `dns4` or `dns6` name will automatically use
+//! the `WebSocket` Javascript object. Note that browsers only accept `wss` connections to a server
+//! whose certificate is valid for the name that is dialed.
+//!
+//! The browser version relies on [stdweb](https://github.com/koute/stdweb). For
+//! `wasm32-unknown-unknown`, build the application with `cargo-web`.
 //!
 //! ```ignore
 //! use libp2p_websocket::BrowserWsConfig;
@@ -73,6 +78,13 @@
 //! # }
 //! ```
 //!
+//! Addresses that end with `/wss` use TLS on top of the underlying transport. When dialing, the
+//! name of the `dns4` or `dns6` component of the address is used for the server name indication and
+//! for verifying the certificate of the remote, and the root certificates that are trusted can be
+//! customized by passing a `TlsConnector` to `with_tls_connector()`. Listening on `/wss` requires
+//! passing a `TlsAcceptor` containing the certificate and private key of the server to
+//! `with_tls_acceptor()`. The `native_tls` crate is re-exported for this purpose.
+//!
 
 extern crate futures;
 extern crate libp2p_swarm as swarm;
@@ -80,18 +92,22 @@ extern crate multiaddr;
 extern crate rw_stream_sink;
 extern crate tokio_io;
 
-#[cfg(target_os = "emscripten")]
+#[cfg(any(target_os = "emscripten", target_arch = "wasm32"))]
 #[macro_use]
 extern crate stdweb;
-#[cfg(not(target_os = "emscripten"))]
+#[cfg(not(any(target_os = "emscripten", target_arch = "wasm32")))]
+pub extern crate native_tls;
+#[cfg(not(any(target_os = "emscripten", target_arch = "wasm32")))]
+extern crate tokio_tls;
+#[cfg(not(any(target_os = "emscripten", target_arch = "wasm32")))]
 extern crate websocket;
 
-#[cfg(not(target_os = "emscripten"))]
+#[cfg(not(any(target_os = "emscripten", target_arch = "wasm32")))]
 mod desktop;
-#[cfg(target_os = "emscripten")]
+#[cfg(any(target_os = "emscripten", target_arch = "wasm32"))]
 mod browser;
 
-#[cfg(target_os = "emscripten")]
+#[cfg(any(target_os = "emscripten", target_arch = "wasm32"))]
 pub use self::browser::{BrowserWsConfig, BrowserWsConn};
-#[cfg(not(target_os = "emscripten"))]
+#[cfg(not(any(target_os = "emscripten", target_arch = "wasm32")))]
 pub use self::desktop::WsConfig;
@@ -0,0 +1,262 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+// TODO: use this once stable ; for now we just copy-paste the content of the README.md
+//#![doc(include = "../README.md")]
+
+//! Implementation of the `/yamux/1.0.0` stream multiplexer.
+//!
+//! Contrary to mplex, yamux has a flow control mechanism. Each substream has a receive window,
+//! which is the number of bytes that the remote is allowed to send before it has to wait. The
+//! window is given back to the remote with a window update frame as the data is read. A substream
+//! that isn't read therefore stops the remote from sending more data on it, instead of filling the
+//! memory of the local node. This makes yamux the preferred muxer for connections that transfer
+//! large amounts of data.
+//!
+//! # Usage
+//!
+//! The `YamuxConfig` struct implements the `ConnectionUpgrade` trait. The output of the upgrade is
+//! a `Yamux`, which implements the `StreamMuxer` trait of `libp2p-swarm`.
+//!
+//! ```
+//! extern crate libp2p_swarm;
+//! extern crate libp2p_tcp_transport;
+//! extern crate libp2p_yamux;
+//! extern crate tokio_core;
+//!
+//! use libp2p_swarm::Transport;
+//! use libp2p_tcp_transport::TcpConfig;
+//! use libp2p_yamux::YamuxConfig;
+//! use tokio_core::reactor::Core;
+//!
+//! # fn main() {
+//! let core = Core::new().unwrap();
+//! let transport = TcpConfig::new(core.handle())
+//! 	.with_upgrade(YamuxConfig::default().with_receive_window(1024 * 1024));
+//! # }
+//! ```
+//!
+//! In order to fall back to mplex with the peers that don't support yamux, combine both upgrades
+//! with `or_upgrade()` and put `/yamux/1.0.0` first with `with_preference()`.
+
+extern crate bytes;
+extern crate futures;
+extern crate libp2p_swarm;
+extern crate tokio_io;
+extern crate yamux;
+
+use bytes::Bytes;
+use futures::{future, Async, Future, Poll, Stream};
+use libp2p_swarm::{ConnectionUpgrade, Endpoint, Multiaddr, StreamMuxer};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::iter;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Implementation of `ConnectionUpgrade` for the `/yamux/1.0.0` protocol.
+#[derive(Clone)]
+pub struct YamuxConfig {
+	inner: yamux::Config,
+}
+
+impl YamuxConfig {
+	/// Builds a `YamuxConfig` from the configuration of the `yamux` library.
+	#[inline]
+	pub fn new(config: yamux::Config) -> YamuxConfig {
+		YamuxConfig {
+			inner: config,
+		}
+	}
+
+	/// Sets the initial receive window of each substream, in bytes. This is the amount of data that
+	/// the remote can send on a substream before we read it.
+	#[inline]
+	pub fn with_receive_window(mut self, num_bytes: u32) -> Self {
+		self.inner.set_receive_window(num_bytes);
+		self
+	}
+
+	/// Sets the maximum number of bytes that are buffered for each substream.
+	#[inline]
+	pub fn with_max_buffer_size(mut self, num_bytes: usize) -> Self {
+		self.inner.set_max_buffer_size(num_bytes);
+		self
+	}
+}
+
+impl Default for YamuxConfig {
+	#[inline]
+	fn default() -> YamuxConfig {
+		YamuxConfig::new(yamux::Config::default())
+	}
+}
+
+impl<C> ConnectionUpgrade<C> for YamuxConfig
+where
+	C: AsyncRead + AsyncWrite + 'static,
+{
+	type NamesIter = iter::Once<(Bytes, ())>;
+	type UpgradeIdentifier = ();
+
+	#[inline]
+	fn protocol_names(&self) -> Self::NamesIter {
+		iter::once((Bytes::from("/yamux/1.0.0"), ()))
+	}
+
+	type Output = Yamux<C>;
+	type Future = future::FutureResult<Yamux<C>, IoError>;
+
+	#[inline]
+	fn upgrade(self, socket: C, _: (), endpoint: Endpoint, _: &Multiaddr) -> Self::Future {
+		let mode = match endpoint {
+			Endpoint::Dialer => yamux::Mode::Client,
+			Endpoint::Listener => yamux::Mode::Server,
+		};
+
+		future::ok(Yamux {
+			inner: yamux::Connection::new(socket, self.inner, mode),
+		})
+	}
+}
+
+/// Connection multiplexed with yamux. Clone it in order to open or accept several substreams.
+pub struct Yamux<C> {
+	inner: yamux::Connection<C>,
+}
+
+impl<C> Clone for Yamux<C> {
+	#[inline]
+	fn clone(&self) -> Self {
+		Yamux {
+			inner: self.inner.clone(),
+		}
+	}
+}
+
+impl<C> StreamMuxer for Yamux<C>
+where
+	C: AsyncRead + AsyncWrite + 'static,
+{
+	type Substream = yamux::StreamRef<C>;
+	type InboundSubstream = InboundFuture<C>;
+	type OutboundSubstream = future::FutureResult<yamux::StreamRef<C>, IoError>;
+
+	#[inline]
+	fn inbound(self) -> Self::InboundSubstream {
+		InboundFuture {
+			inner: self.inner,
+		}
+	}
+
+	fn outbound(self) -> Self::OutboundSubstream {
+		let substream = match self.inner.open_stream() {
+			Ok(Some(substream)) => Ok(substream),
+			Ok(None) => Err(connection_closed()),
+			Err(err) => Err(IoError::new(IoErrorKind::Other, err)),
+		};
+
+		future::result(substream)
+	}
+}
+
+/// Future that produces the next substream opened by the remote.
+pub struct InboundFuture<C> {
+	inner: yamux::Connection<C>,
+}
+
+impl<C> Future for InboundFuture<C>
+where
+	C: AsyncRead + AsyncWrite + 'static,
+{
+	type Item = yamux::StreamRef<C>;
+	type Error = IoError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		match self.inner.poll() {
+			Ok(Async::Ready(Some(substream))) => Ok(Async::Ready(substream)),
+			Ok(Async::Ready(None)) => Err(connection_closed()),
+			Ok(Async::NotReady) => Ok(Async::NotReady),
+			Err(err) => Err(IoError::new(IoErrorKind::Other, err)),
+		}
+	}
+}
+
+#[inline]
+fn connection_closed() -> IoError {
+	IoError::new(IoErrorKind::BrokenPipe, "the yamux connection has been closed")
+}
+
+#[cfg(test)]
+mod tests {
+	extern crate libp2p_tcp_transport;
+	extern crate tokio_core;
+
+	use self::libp2p_tcp_transport::TcpConfig;
+	use self::tokio_core::reactor::Core;
+	use futures::{Future, Stream};
+	use libp2p_swarm::{StreamMuxer, Transport};
+	use std::sync::mpsc;
+	use std::thread;
+	use tokio_io::io::{flush, read_exact, write_all};
+	use YamuxConfig;
+
+	#[test]
+	fn substream_roundtrip() {
+		let (tx, rx) = mpsc::channel();
+
+		let bg_thread = thread::spawn(move || {
+			let mut core = Core::new().unwrap();
+			let transport = TcpConfig::new(core.handle()).with_upgrade(YamuxConfig::default());
+
+			let (listener, addr) = transport
+				.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+				.unwrap();
+			tx.send(addr).unwrap();
+
+			let future = listener
+				.into_future()
+				.map_err(|(err, _)| err)
+				.and_then(|(client, _)| client.unwrap().0)
+				.and_then(|muxer| muxer.inbound())
+				.and_then(|substream| read_exact(substream, [0; 11]))
+				.and_then(|(substream, msg)| {
+					assert_eq!(&msg, b"hello world");
+					write_all(substream, b"hello back")
+				})
+				.and_then(|(substream, _)| flush(substream));
+
+			core.run(future).unwrap();
+		});
+
+		let mut core = Core::new().unwrap();
+		let transport = TcpConfig::new(core.handle()).with_upgrade(YamuxConfig::default());
+
+		let future = transport
+			.dial(rx.recv().unwrap())
+			.unwrap_or_else(|_| panic!())
+			.and_then(|muxer| muxer.outbound())
+			.and_then(|substream| write_all(substream, b"hello world"))
+			.and_then(|(substream, _)| flush(substream))
+			.and_then(|substream| read_exact(substream, [0; 10]))
+			.map(|(_, msg)| assert_eq!(&msg, b"hello back"));
+
+		core.run(future).unwrap();
+		bg_thread.join().unwrap();
+	}
+}
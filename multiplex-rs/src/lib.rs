@@ -22,6 +22,7 @@ extern crate arrayvec;
 extern crate bytes;
 #[macro_use]
 extern crate error_chain;
+#[macro_use]
 extern crate futures;
 extern crate futures_mutex;
 extern crate libp2p_swarm as swarm;
@@ -40,12 +41,12 @@ mod header;
 use bytes::Bytes;
 use futures::{Async, Future, Poll};
 use futures::future::{self, FutureResult};
-use header::MultiplexHeader;
+use header::{MultiplexHeader, PacketType};
 use swarm::muxing::StreamMuxer;
 use swarm::{ConnectionUpgrade, Endpoint, Multiaddr};
 use futures_mutex::Mutex;
 use read::{read_stream, MultiplexReadState};
-use shared::{buf_from_slice, ByteBuf, MultiplexShared};
+use shared::{buf_from_slice, ByteBuf, MultiplexShared, SubstreamMetadata};
 use std::iter;
 use std::io::{self, Read, Write};
 use std::sync::Arc;
@@ -67,17 +68,64 @@ use write::write_stream;
 
 pub struct Substream<T> {
     id: u32,
+    // `Dialer` if we opened this substream, `Listener` if the remote did. This determines the flag
+    // of the message packets, regardless of which side dialed the underlying connection.
     end: Endpoint,
     name: Option<Bytes>,
     state: Arc<Mutex<MultiplexShared<T>>>,
     buffer: Option<io::Cursor<ByteBuf>>,
+    // True if we have closed our writing side with `shutdown()`.
+    write_closed: bool,
 }
 
 impl<T> Drop for Substream<T> {
     fn drop(&mut self) {
         let mut lock = self.state.lock().wait().expect("This should never fail");
 
-        lock.close_stream(self.id);
+        // Unless both sides have been closed, the substream is reset so that the remote doesn't
+        // wait for data that will never come. The packet can't be written from here, as we may
+        // not be in a task, so it is queued.
+        let reset = match lock.open_streams.remove(&self.id) {
+            Some(SubstreamMetadata::Reset) => false,
+            Some(SubstreamMetadata::Closed) => !self.write_closed,
+            Some(SubstreamMetadata::Open { .. }) | None => true,
+        };
+
+        if reset {
+            lock.resets.push_back(MultiplexHeader::reset(self.id, self.end));
+            for task in lock.meta_write_tasks.drain(..) {
+                task.notify();
+            }
+        }
+    }
+}
+
+// Writes the `Reset` packets of the substreams that have been dropped.
+fn flush_resets<T: AsyncWrite>(lock: &mut MultiplexShared<T>) -> io::Result<()> {
+    if lock.resets.is_empty() {
+        return Ok(());
+    }
+
+    while let Some(header) = lock.resets.front().cloned() {
+        // Another packet is being written. We don't interleave with it, and try again later.
+        let current = lock.write_state.as_ref().and_then(|state| state.current_header());
+        if current.is_some() && current != Some(header) {
+            return Ok(());
+        }
+
+        let mut empty = io::Cursor::new(ByteBuf::new());
+        match write_stream(lock, write::WriteRequest::meta(header), &mut empty) {
+            Ok(_) => {
+                lock.resets.pop_front();
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(err) => return Err(err),
+        }
+    }
+
+    match lock.stream.flush() {
+        Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Ok(()),
+        result => result,
     }
 }
 
@@ -96,6 +144,7 @@ impl<T> Substream<T> {
             name,
             state,
             buffer: None,
+            write_closed: false,
         }
     }
 
@@ -108,6 +157,51 @@ impl<T> Substream<T> {
     }
 }
 
+impl<T: AsyncWrite> Substream<T> {
+    /// Aborts the substream in both directions. The remote gets an error when it reads or writes
+    /// on it, and so do we.
+    ///
+    /// Contrary to `shutdown()`, which only closes our writing side, the data that hasn't been
+    /// read or written yet is discarded.
+    pub fn reset(&mut self) -> Poll<(), io::Error> {
+        let header = MultiplexHeader::reset(self.id, self.end);
+        self.write_meta(header)
+    }
+
+    // Writes a packet with an empty body about this substream, such as `Close` or `Reset`.
+    fn write_meta(&mut self, header: MultiplexHeader) -> Poll<(), io::Error> {
+        let mut lock = match self.state.poll_lock() {
+            Async::Ready(lock) => lock,
+            Async::NotReady => return Ok(Async::NotReady),
+        };
+
+        if let PacketType::Close(_) = header.packet_type {
+            if lock.is_reset(self.id) {
+                return Err(io::ErrorKind::ConnectionReset.into());
+            }
+        }
+
+        let mut empty = io::Cursor::new(ByteBuf::new());
+        match write_stream(&mut *lock, write::WriteRequest::meta(header), &mut empty) {
+            Ok(_) => (),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                return Ok(Async::NotReady)
+            }
+            Err(err) => return Err(err),
+        }
+
+        if let PacketType::Reset(_) = header.packet_type {
+            lock.reset_stream(self.id);
+        }
+
+        match lock.stream.flush() {
+            Ok(()) => Ok(Async::Ready(())),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(err) => Err(err),
+        }
+    }
+}
+
 // TODO: We always zero the buffer, we should delegate to the inner stream.
 impl<T: AsyncRead> Read for Substream<T> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -116,6 +210,10 @@ impl<T: AsyncRead> Read for Substream<T> {
             Async::NotReady => return Err(io::ErrorKind::WouldBlock.into()),
         };
 
+        if lock.is_reset(self.id) {
+            return Err(io::ErrorKind::ConnectionReset.into());
+        }
+
         read_stream(&mut lock, (self.id, buf))
     }
 }
@@ -124,11 +222,21 @@ impl<T: AsyncRead> AsyncRead for Substream<T> {}
 
 impl<T: AsyncWrite> Write for Substream<T> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.write_closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
         let mut lock = match self.state.poll_lock() {
             Async::Ready(lock) => lock,
             Async::NotReady => return Err(io::ErrorKind::WouldBlock.into()),
         };
 
+        if lock.is_reset(self.id) {
+            return Err(io::ErrorKind::ConnectionReset.into());
+        }
+
+        flush_resets(&mut lock)?;
+
         let mut buffer = self.buffer
             .take()
             .unwrap_or_else(|| io::Cursor::new(buf_from_slice(buf)));
@@ -157,17 +265,25 @@ impl<T: AsyncWrite> Write for Substream<T> {
 }
 
 impl<T: AsyncWrite> AsyncWrite for Substream<T> {
+    /// Closes our writing side of the substream by sending a `Close` packet. The substream can
+    /// still be read until the remote closes its own side.
     fn shutdown(&mut self) -> Poll<(), io::Error> {
+        if self.write_closed {
+            return Ok(Async::Ready(()));
+        }
+
+        let header = MultiplexHeader::close(self.id, self.end);
+        try_ready!(self.write_meta(header));
+        self.write_closed = true;
         Ok(Async::Ready(()))
     }
 }
 
 pub struct InboundFuture<T> {
-    end: Endpoint,
     state: Arc<Mutex<MultiplexShared<T>>>,
 }
 
-impl<T: AsyncRead> Future for InboundFuture<T> {
+impl<T: AsyncRead + AsyncWrite> Future for InboundFuture<T> {
     type Item = Substream<T>;
     type Error = io::Error;
 
@@ -177,6 +293,8 @@ impl<T: AsyncRead> Future for InboundFuture<T> {
             Async::NotReady => return Ok(Async::NotReady),
         };
 
+        flush_resets(&mut lock)?;
+
         // Attempt to make progress, but don't block if we can't
         match read_stream(&mut lock, None) {
             Ok(_) => {}
@@ -198,7 +316,7 @@ impl<T: AsyncRead> Future for InboundFuture<T> {
 
         Ok(Async::Ready(Substream::new(
             id,
-            self.end,
+            Endpoint::Listener,
             name,
             Arc::clone(&self.state),
         )))
@@ -235,6 +353,8 @@ impl<T: AsyncWrite> Future for OutboundFuture<T> {
             Async::NotReady => return Ok(Async::NotReady),
         };
 
+        flush_resets(&mut lock)?;
+
         loop {
             let (mut id_str, id) = self.current_id.take().unwrap_or_else(|| {
                 let next = nonce_to_id(
@@ -258,7 +378,7 @@ impl<T: AsyncWrite> Future for OutboundFuture<T> {
                         if lock.open_stream(id) {
                             return Ok(Async::Ready(Substream::new(
                                 id,
-                                self.meta.end,
+                                Endpoint::Dialer,
                                 Bytes::from(&id_str.get_ref()[..]),
                                 Arc::clone(&self.state),
                             )));
@@ -300,12 +420,16 @@ impl<T> Clone for Multiplex<T> {
 
 impl<T> Multiplex<T> {
     pub fn new(stream: T, end: Endpoint) -> Self {
+        Self::with_config(stream, end, MultiplexConfig::new())
+    }
+
+    pub fn with_config(stream: T, end: Endpoint, config: MultiplexConfig) -> Self {
         Multiplex {
             meta: Arc::new(MultiplexMetadata {
                 nonce: AtomicUsize::new(0),
                 end,
             }),
-            state: Arc::new(Mutex::new(MultiplexShared::new(stream))),
+            state: Arc::new(Mutex::new(MultiplexShared::new(stream, config))),
         }
     }
 
@@ -326,17 +450,65 @@ impl<T: AsyncRead + AsyncWrite> StreamMuxer for Multiplex<T> {
     fn inbound(self) -> Self::InboundSubstream {
         InboundFuture {
             state: Arc::clone(&self.state),
-            end: self.meta.end,
         }
     }
 
     fn outbound(self) -> Self::OutboundSubstream {
         OutboundFuture::new(self)
     }
+
+    fn close(&self) -> Poll<(), io::Error> {
+        let mut lock = match self.state.poll_lock() {
+            Async::Ready(lock) => lock,
+            Async::NotReady => return Ok(Async::NotReady),
+        };
+
+        lock.stream.shutdown()
+    }
 }
 
+/// Implementation of `ConnectionUpgrade` for the `/mplex/6.7.0` protocol. Upgrading a connection
+/// produces a `Multiplex` on which substreams can be opened and accepted.
 #[derive(Debug, Copy, Clone)]
-pub struct MultiplexConfig;
+pub struct MultiplexConfig {
+    max_frame_size: usize,
+    max_pending_substreams: usize,
+}
+
+impl MultiplexConfig {
+    /// Builds the default configuration: frames of at most 1 MiB, and at most 128 substreams
+    /// opened by the remote and not accepted yet.
+    #[inline]
+    pub fn new() -> MultiplexConfig {
+        MultiplexConfig {
+            max_frame_size: 1024 * 1024,
+            max_pending_substreams: 128,
+        }
+    }
+
+    /// Sets the maximum size of the body of a frame. Receiving a larger frame is an error that
+    /// closes the connection.
+    #[inline]
+    pub fn with_max_frame_size(mut self, max: usize) -> Self {
+        self.max_frame_size = max;
+        self
+    }
+
+    /// Sets the maximum number of substreams opened by the remote that haven't been accepted
+    /// with `inbound()` yet. The substreams opened past this limit are ignored.
+    #[inline]
+    pub fn with_max_pending_substreams(mut self, max: usize) -> Self {
+        self.max_pending_substreams = max;
+        self
+    }
+}
+
+impl Default for MultiplexConfig {
+    #[inline]
+    fn default() -> MultiplexConfig {
+        MultiplexConfig::new()
+    }
+}
 
 impl<C> ConnectionUpgrade<C> for MultiplexConfig
 where
@@ -349,7 +521,7 @@ where
 
     #[inline]
     fn upgrade(self, i: C, _: (), end: Endpoint, _: &Multiaddr) -> Self::Future {
-        future::ok(Multiplex::new(i, end))
+        future::ok(Multiplex::with_config(i, end, self))
     }
 
     #[inline]
@@ -454,6 +626,193 @@ mod tests {
         }
     }
 
+    #[test]
+    fn message_flags_follow_substream_initiator() {
+        use std::iter;
+
+        // The listener of the connection opens a substream. Its messages must use the flag of the
+        // initiator of the substream, not the one of the listener of the connection.
+        let mplex = Multiplex::listen(io::Cursor::new(Vec::new()));
+
+        let mut substream = mplex.clone().outbound().wait().unwrap();
+        assert!(tokio::write_all(&mut substream, b"hello").wait().is_ok());
+
+        let id = substream.id();
+        let data = mplex.state.lock().wait().unwrap().stream.get_ref().clone();
+
+        let expected = iter::empty()
+            .chain(varint::encode(MultiplexHeader::open(id).to_u64()))
+            .chain(varint::encode(id.to_string().len()))
+            .chain(id.to_string().into_bytes())
+            .chain(varint::encode(MultiplexHeader::message(id, Endpoint::Dialer).to_u64()))
+            .chain(varint::encode(5usize))
+            .chain(b"hello".iter().cloned())
+            .collect::<Vec<_>>();
+        assert_eq!(data, expected);
+
+        // On the other side, the substream is an inbound one and answers with the flag of the
+        // receiver.
+        let mplex = Multiplex::dial(io::Cursor::new(data));
+        let mut substream = mplex.clone().inbound().wait().unwrap();
+        assert_eq!(substream.id(), id);
+
+        let mut buf = [0; 5];
+        assert!(tokio::read_exact(&mut substream, &mut buf).wait().is_ok());
+        assert_eq!(&buf, b"hello");
+
+        assert!(tokio::write_all(&mut substream, b"world").wait().is_ok());
+        let data = mplex.state.lock().wait().unwrap().stream.get_ref().clone();
+        let header = varint::encode(MultiplexHeader::message(id, Endpoint::Listener).to_u64());
+        assert!(data.ends_with(&header.into_iter()
+            .chain(varint::encode(5usize))
+            .chain(b"world".iter().cloned())
+            .collect::<Vec<_>>()));
+    }
+
+    #[test]
+    fn frames_over_the_limit_are_refused() {
+        use std::iter;
+
+        let input = iter::empty()
+            // Open a stream with a 64 bytes name
+            .chain(varint::encode(MultiplexHeader::open(0).to_u64()))
+            .chain(varint::encode(64usize))
+            .chain(iter::repeat(b'a').take(64))
+            .collect::<Vec<_>>();
+
+        let config = MultiplexConfig::new().with_max_frame_size(32);
+        let mplex = Multiplex::with_config(io::Cursor::new(input), Endpoint::Listener, config);
+
+        let err = mplex.inbound().wait().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn pending_substreams_over_the_limit_are_ignored() {
+        use std::iter;
+
+        let input = iter::empty()
+            .chain(varint::encode(MultiplexHeader::open(0).to_u64()))
+            .chain(varint::encode(0usize))
+            .chain(varint::encode(MultiplexHeader::open(1).to_u64()))
+            .chain(varint::encode(0usize))
+            .collect::<Vec<_>>();
+
+        let config = MultiplexConfig::new().with_max_pending_substreams(1);
+        let mplex = Multiplex::with_config(io::Cursor::new(input), Endpoint::Listener, config);
+
+        assert_eq!(mplex.clone().inbound().wait().unwrap().id(), 0);
+
+        let lock = mplex.state.lock().wait().unwrap();
+        assert!(lock.to_open.is_empty());
+        assert!(!lock.open_streams.contains_key(&1));
+    }
+
+    #[test]
+    fn half_close() {
+        let mplex = Multiplex::dial(io::Cursor::new(Vec::new()));
+        let mut substream = mplex.clone().outbound().wait().unwrap();
+        let id = substream.id();
+
+        assert!(tokio::write_all(&mut substream, b"hello").wait().is_ok());
+        assert!(tokio::shutdown(&mut substream).wait().is_ok());
+        let err = tokio::write_all(&mut substream, b"world").wait().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+
+        let data = mplex.state.lock().wait().unwrap().stream.get_ref().clone();
+        let mplex = Multiplex::listen(io::Cursor::new(data));
+        let mut substream = mplex.clone().inbound().wait().unwrap();
+        assert_eq!(substream.id(), id);
+
+        // The data is received, then the end of the substream.
+        let mut buf = [0; 5];
+        assert!(tokio::read_exact(&mut substream, &mut buf).wait().is_ok());
+        assert_eq!(&buf, b"hello");
+        assert_eq!(tokio::read(&mut substream, [0; 5]).wait().unwrap().2, 0);
+
+        // We can still answer.
+        assert!(tokio::write_all(&mut substream, b"world").wait().is_ok());
+    }
+
+    #[test]
+    fn reset() {
+        use std::iter;
+
+        let mplex = Multiplex::dial(io::Cursor::new(Vec::new()));
+        let mut substream = mplex.clone().outbound().wait().unwrap();
+        let id = substream.id();
+
+        assert!(substream.reset().unwrap().is_ready());
+        let err = tokio::write_all(&mut substream, b"hello").wait().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+
+        let data = mplex.state.lock().wait().unwrap().stream.get_ref().clone();
+        let reset = iter::empty()
+            .chain(varint::encode(MultiplexHeader::reset(id, Endpoint::Dialer).to_u64()))
+            .chain(varint::encode(0usize))
+            .collect::<Vec<_>>();
+        assert!(data.ends_with(&reset));
+
+        // The remote gets an error when reading.
+        let mplex = Multiplex::listen(io::Cursor::new(data));
+        let mut substream = mplex.inbound().wait().unwrap();
+        let err = tokio::read(&mut substream, [0; 5]).wait().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    }
+
+    #[test]
+    fn dropping_a_substream_resets_it() {
+        extern crate tokio_core;
+        use self::tokio_core::net::{TcpListener, TcpStream};
+        use self::tokio_core::reactor::Core;
+        use futures::Stream;
+        use futures::future::Either;
+
+        let mut core = Core::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+
+        // The listener drops the substream without closing it, then waits for other substreams.
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(err, _)| err)
+            .and_then(|(conn, _)| {
+                let mplex = Multiplex::listen(conn.unwrap().0);
+                mplex.clone().inbound().map(move |substream| (mplex, substream))
+            })
+            .and_then(|(mplex, substream)| {
+                tokio::read_exact(substream, [0; 5]).and_then(move |(substream, buf)| {
+                    assert_eq!(&buf, b"hello");
+                    drop(substream);
+                    mplex.inbound()
+                })
+            });
+
+        let client = TcpStream::connect(&listener_addr, &core.handle())
+            .and_then(|conn| Multiplex::dial(conn).outbound())
+            .and_then(|substream| tokio::write_all(substream, b"hello"))
+            .and_then(|(substream, _)| tokio::read_to_end(substream, Vec::new()));
+
+        let err = match core.run(server.select2(client)) {
+            Err(Either::B((err, _))) => err,
+            _ => panic!("the dialer should have observed the reset"),
+        };
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    }
+
+    #[test]
+    fn dropped_substreams_are_forgotten() {
+        let mplex = Multiplex::dial(io::Cursor::new(Vec::new()));
+        let substream = mplex.clone().outbound().wait().unwrap();
+        let id = substream.id();
+        drop(substream);
+
+        let lock = mplex.state.lock().wait().unwrap();
+        assert!(!lock.open_streams.contains_key(&id));
+        assert_eq!(lock.resets.front(), Some(&MultiplexHeader::reset(id, Endpoint::Dialer)));
+    }
+
     #[test]
     fn packets_to_unopened_streams_are_dropped() {
         use std::iter;
@@ -131,9 +131,19 @@ pub fn read_stream<'a, O: Into<Option<(u32, &'a mut [u8])>>, T: AsyncRead>(
 
                         match packet_type {
                             PacketType::Open => {
+                                // Substreams that haven't been accepted yet are kept in memory,
+                                // therefore we ignore the new ones past the limit.
+                                let next = if lock.to_open.len()
+                                    >= lock.config.max_pending_substreams
+                                {
+                                    NextMultiplexState::Ignore
+                                } else {
+                                    NextMultiplexState::NewStream(substream_id)
+                                };
+
                                 lock.read_state = Some(BodyLength {
                                     state: Default::default(),
-                                    next: NextMultiplexState::NewStream(substream_id),
+                                    next,
                                 })
                             }
                             PacketType::Message(_) => {
@@ -142,14 +152,37 @@ pub fn read_stream<'a, O: Into<Option<(u32, &'a mut [u8])>>, T: AsyncRead>(
                                     next: NextMultiplexState::ParsingMessageBody(substream_id),
                                 })
                             }
-                            // NOTE: What's the difference between close and reset?
-                            PacketType::Close(_) | PacketType::Reset(_) => {
+                            // The remote won't write on the substream anymore, but we can still
+                            // write to it.
+                            PacketType::Close(_) => {
+                                lock.read_state = Some(BodyLength {
+                                    state: Default::default(),
+                                    next: NextMultiplexState::Ignore,
+                                });
+
+                                if lock.is_known(substream_id) {
+                                    lock.close_stream(substream_id);
+                                }
+                            }
+                            // The substream is aborted in both directions.
+                            PacketType::Reset(_) => {
                                 lock.read_state = Some(BodyLength {
                                     state: Default::default(),
                                     next: NextMultiplexState::Ignore,
                                 });
 
-                                lock.close_stream(substream_id);
+                                if lock.is_known(substream_id) {
+                                    lock.reset_stream(substream_id);
+                                }
+
+                                // The data read before the reset is returned first.
+                                let reading = stream_data.as_ref().map(|&(id, _)| id);
+                                if reading == Some(substream_id) {
+                                    return match on_block {
+                                        Ok(read) if read > 0 => Ok(read),
+                                        _ => Err(io::ErrorKind::ConnectionReset.into()),
+                                    };
+                                }
                             }
                         }
                     }
@@ -179,13 +212,22 @@ pub fn read_stream<'a, O: Into<Option<(u32, &'a mut [u8])>>, T: AsyncRead>(
                     .map_err(|_| io::Error::new(io::ErrorKind::Other, "Error reading varint"))?
                 {
                     Async::Ready(length) => {
-                        // TODO: Limit `length` to prevent resource-exhaustion DOS
                         let length = if let Some(length) = length {
                             length
                         } else {
                             return Ok(on_block.unwrap_or(0));
                         };
 
+                        if length > lock.config.max_frame_size {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!(
+                                    "Frame of {} bytes exceeds the maximum of {} bytes",
+                                    length, lock.config.max_frame_size
+                                ),
+                            ));
+                        }
+
                         lock.read_state = match next {
                             Ignore => Some(MultiplexReadState::Ignore {
                                 remaining_bytes: length,
@@ -18,13 +18,15 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+use MultiplexConfig;
 use read::MultiplexReadState;
 use write::MultiplexWriteState;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use bytes::Bytes;
 use arrayvec::ArrayVec;
 use futures::task::Task;
+use header::MultiplexHeader;
 
 const BUF_SIZE: usize = 1024;
 
@@ -32,6 +34,8 @@ pub type ByteBuf = ArrayVec<[u8; BUF_SIZE]>;
 
 pub enum SubstreamMetadata {
     Closed,
+    // The substream has been aborted by one of the two sides.
+    Reset,
     Open { read: Vec<Task>, write: Vec<Task> },
 }
 
@@ -45,21 +49,21 @@ impl SubstreamMetadata {
 
     pub fn open(&self) -> bool {
         match *self {
-            SubstreamMetadata::Closed => false,
+            SubstreamMetadata::Closed | SubstreamMetadata::Reset => false,
             SubstreamMetadata::Open { .. } => true,
         }
     }
 
     pub fn read_tasks_mut(&mut self) -> Option<&mut Vec<Task>> {
         match *self {
-            SubstreamMetadata::Closed => None,
+            SubstreamMetadata::Closed | SubstreamMetadata::Reset => None,
             SubstreamMetadata::Open { ref mut read, .. } => Some(read),
         }
     }
 
     pub fn write_tasks_mut(&mut self) -> Option<&mut Vec<Task>> {
         match *self {
-            SubstreamMetadata::Closed => None,
+            SubstreamMetadata::Closed | SubstreamMetadata::Reset => None,
             SubstreamMetadata::Open { ref mut write, .. } => Some(write),
         }
     }
@@ -81,17 +85,23 @@ pub struct MultiplexShared<T> {
     //       `WouldBlock` if it's full? Even if we ignore or size-cap names you can still open 2^32
     //       streams.
     pub to_open: HashMap<u32, Option<Bytes>>,
+    // `Reset` packets of the substreams that have been dropped, which are written the next time
+    // the connection is polled.
+    pub resets: VecDeque<MultiplexHeader>,
+    pub config: MultiplexConfig,
 }
 
 impl<T> MultiplexShared<T> {
-    pub fn new(stream: T) -> Self {
+    pub fn new(stream: T, config: MultiplexConfig) -> Self {
         MultiplexShared {
             read_state: Default::default(),
             write_state: Default::default(),
             open_streams: Default::default(),
             meta_write_tasks: Default::default(),
             to_open: Default::default(),
+            resets: Default::default(),
             stream: stream,
+            config: config,
         }
     }
 
@@ -105,8 +115,34 @@ impl<T> MultiplexShared<T> {
             .open()
     }
 
+    // Returns true if the substream is open or waiting to be accepted. The substreams that we
+    // have dropped are unknown.
+    pub fn is_known(&self, id: u32) -> bool {
+        self.open_streams.contains_key(&id) || self.to_open.contains_key(&id)
+    }
+
     pub fn close_stream(&mut self, id: u32) {
-        self.open_streams.insert(id, SubstreamMetadata::Closed);
+        // A reset substream stays reset.
+        if !self.is_reset(id) {
+            self.open_streams.insert(id, SubstreamMetadata::Closed);
+        }
+    }
+
+    pub fn reset_stream(&mut self, id: u32) {
+        // The tasks blocked on the substream get the error.
+        let previous = self.open_streams.insert(id, SubstreamMetadata::Reset);
+        if let Some(SubstreamMetadata::Open { read, write }) = previous {
+            for task in read.into_iter().chain(write) {
+                task.notify();
+            }
+        }
+    }
+
+    pub fn is_reset(&self, id: u32) -> bool {
+        match self.open_streams.get(&id) {
+            Some(&SubstreamMetadata::Reset) => true,
+            _ => false,
+        }
     }
 }
 
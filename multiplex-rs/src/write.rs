@@ -62,6 +62,13 @@ pub struct MultiplexWriteState {
     to_close: Vec<u32>,
 }
 
+impl MultiplexWriteState {
+    // Returns the header of the packet that is partially written, if any.
+    pub fn current_header(&self) -> Option<MultiplexHeader> {
+        self.current.as_ref().map(|&(ref request, _)| request.header)
+    }
+}
+
 #[derive(Debug)]
 pub enum MultiplexWriteStateInner {
     WriteHeader { state: varint::EncoderState<u64> },
@@ -92,7 +99,10 @@ pub fn write_stream<T: AsyncWrite>(
 
     let id = write_request.header.substream_id;
 
-    if buf.get_ref().len() as u64 - buf.position() == 0 {
+    // Only the meta packets, such as `Close` and `Reset`, can have an empty body.
+    if write_request.request_type == RequestType::Substream
+        && buf.get_ref().len() as u64 - buf.position() == 0
+    {
         return Ok(0);
     }
 
@@ -139,6 +149,13 @@ pub fn write_stream<T: AsyncWrite>(
 
             return on_block;
         }
+        (RequestType::Meta, RequestType::Meta) if request.header != write_request.header => {
+            // Another meta packet is being written, and its writer will wake us up.
+            lock.write_state = Some(write_state);
+            lock.meta_write_tasks.push(task::current());
+
+            return on_block;
+        }
         (RequestType::Meta, RequestType::Substream) => {
             use std::mem;
 
@@ -191,6 +208,8 @@ pub fn write_stream<T: AsyncWrite>(
             },
             Body { size } => {
                 if buf.position() == buf.get_ref().len() as u64 {
+                    // The packet has been entirely written.
+                    on_block = Ok(on_block.unwrap_or(0));
                     Err(None)
                 } else {
                     match lock.stream.write(&buf.get_ref()[buf.position() as usize..]) {
@@ -44,7 +44,7 @@ fn client_to_server_outbound() {
     let bg_thread = thread::spawn(move || {
         let mut core = Core::new().unwrap();
         let transport = TcpConfig::new(core.handle())
-            .with_upgrade(multiplex::MultiplexConfig);
+            .with_upgrade(multiplex::MultiplexConfig::new());
 
         let (listener, addr) = transport
             .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
@@ -72,7 +72,7 @@ fn client_to_server_outbound() {
 
     let mut core = Core::new().unwrap();
     let transport = TcpConfig::new(core.handle())
-        .with_upgrade(multiplex::MultiplexConfig);
+        .with_upgrade(multiplex::MultiplexConfig::new());
 
     let future = transport.dial(rx.recv().unwrap()).unwrap()
         .and_then(|client| client.inbound())
@@ -93,7 +93,7 @@ fn client_to_server_inbound() {
     let bg_thread = thread::spawn(move || {
         let mut core = Core::new().unwrap();
         let transport = TcpConfig::new(core.handle())
-            .with_upgrade(multiplex::MultiplexConfig);
+            .with_upgrade(multiplex::MultiplexConfig::new());
 
         let (listener, addr) = transport
             .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
@@ -121,7 +121,7 @@ fn client_to_server_inbound() {
 
     let mut core = Core::new().unwrap();
     let transport = TcpConfig::new(core.handle())
-        .with_upgrade(multiplex::MultiplexConfig);
+        .with_upgrade(multiplex::MultiplexConfig::new());
 
     let future = transport.dial(rx.recv().unwrap()).unwrap()
         .and_then(|client| client.outbound())
@@ -23,14 +23,22 @@
 
 use ProtocolChoiceError;
 use bytes::Bytes;
-use futures::{Future, Sink, Stream};
-use futures::future::{result, loop_fn, Loop};
+use futures::{Async, Future, Poll, Sink, Stream};
+use futures::future::Either;
+use futures::sink::Send as SinkSend;
+use futures::stream::StreamFuture;
+use std::mem;
 
-use protocol::Dialer;
+use protocol::{Dialer, DialerFuture};
 use protocol::DialerToListenerMessage;
 use protocol::ListenerToDialerMessage;
 use tokio_io::{AsyncRead, AsyncWrite};
 
+/// Future returned by `dialer_select_proto`, which uses either the "serial" or the "parallel"
+/// strategy.
+pub type DialerSelectFuture<R, I, P> = Either<DialerSelectSeq<R, IgnoreMatchFn<I>, P>,
+											  DialerSelectPar<R, I, P>>;
+
 /// Helps selecting a protocol amongst the ones supported.
 ///
 /// This function expects a socket and a list of protocols. It uses the `multistream-select`
@@ -42,22 +50,47 @@ use tokio_io::{AsyncRead, AsyncWrite};
 /// remote, and the protocol name that we passed (so that you don't have to clone the name). On
 /// success, the function returns the identifier (of type `P`), plus the socket which now uses that
 /// chosen protocol.
-// TODO: remove the Box once -> impl Trait lands
 #[inline]
-pub fn dialer_select_proto<'a, R, I, M, P>(
-	inner: R,
-	protocols: I,
-) -> Box<Future<Item = (P, R), Error = ProtocolChoiceError> + 'a>
-	where R: AsyncRead + AsyncWrite + 'a,
-	      I: Iterator<Item = (Bytes, M, P)> + 'a,
-	      M: FnMut(&Bytes, &Bytes) -> bool + 'a,
-	      P: 'a
+pub fn dialer_select_proto<R, I, M, P>(inner: R, protocols: I) -> DialerSelectFuture<R, I, P>
+	where R: AsyncRead + AsyncWrite,
+	      I: Iterator<Item = (Bytes, M, P)>,
+	      M: FnMut(&Bytes, &Bytes) -> bool
 {
 	// We choose between the "serial" and "parallel" strategies based on the number of protocols.
 	if protocols.size_hint().1.map(|n| n <= 3).unwrap_or(false) {
-		dialer_select_proto_serial(inner, protocols.map(|(n, _, id)| (n, id)))
+		Either::A(dialer_select_proto_serial(inner, IgnoreMatchFn { inner: protocols }))
 	} else {
-		dialer_select_proto_parallel(inner, protocols)
+		Either::B(dialer_select_proto_parallel(inner, protocols))
+	}
+}
+
+/// Iterator that turns the elements of an iterator of `(name, match function, identifier)` into
+/// `(name, identifier)`.
+#[derive(Debug, Clone)]
+pub struct IgnoreMatchFn<I> {
+	inner: I,
+}
+
+impl<I> IgnoreMatchFn<I> {
+	#[inline]
+	pub(crate) fn new(inner: I) -> IgnoreMatchFn<I> {
+		IgnoreMatchFn { inner: inner }
+	}
+}
+
+impl<I, M, P> Iterator for IgnoreMatchFn<I>
+	where I: Iterator<Item = (Bytes, M, P)>
+{
+	type Item = (Bytes, P);
+
+	#[inline]
+	fn next(&mut self) -> Option<Self::Item> {
+		self.inner.next().map(|(name, _, id)| (name, id))
+	}
+
+	#[inline]
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.inner.size_hint()
 	}
 }
 
@@ -65,118 +98,372 @@ pub fn dialer_select_proto<'a, R, I, M, P>(
 ///
 /// Same as `dialer_select_proto`. Tries protocols one by one. The iterator doesn't need to produce
 /// match functions, because it's not needed.
-// TODO: remove the Box once -> impl Trait lands
-pub fn dialer_select_proto_serial<'a, R, I, P>(
-	inner: R,
-	mut protocols: I,
-) -> Box<Future<Item = (P, R), Error = ProtocolChoiceError> + 'a>
-	where R: AsyncRead + AsyncWrite + 'a,
-	      I: Iterator<Item = (Bytes, P)> + 'a,
-	      P: 'a
+#[inline]
+pub fn dialer_select_proto_serial<R, I, P>(inner: R, protocols: I) -> DialerSelectSeq<R, I, P>
+	where R: AsyncRead + AsyncWrite,
+	      I: Iterator<Item = (Bytes, P)>
 {
-	let future = Dialer::new(inner)
-        .from_err()
-        .and_then(move |dialer| {
-            // Similar to a `loop` keyword.
-            loop_fn(dialer, move |dialer| {
-                result(protocols.next().ok_or(ProtocolChoiceError::NoProtocolFound))
-                    // If the `protocols` iterator produced an element, send it to the dialer
-                    .and_then(|(proto_name, proto_value)| {
-                        dialer.send(DialerToListenerMessage::ProtocolRequest { name: proto_name.clone() })
-                            .map(|d| (d, proto_name, proto_value))
-                            .from_err()
-                    })
-                    // Once sent, read one element from `dialer`.
-                    .and_then(|(dialer, proto_name, proto_value)| {
-                        dialer
-                            .into_future()
-                            .map(|(msg, rest)| (msg, rest, proto_name, proto_value))
-                            .map_err(|(e, _)| e.into())
-                    })
-                    // Once read, analyze the response.
-                    .and_then(|(message, rest, proto_name, proto_value)| {
-                        let message = message.ok_or(ProtocolChoiceError::UnexpectedMessage)?;
-
-                        match message {
-                            ListenerToDialerMessage::ProtocolAck { ref name }
-                                                                    if name == &proto_name =>
-                            {
-                                // Satisfactory response, break the loop.
-                                Ok(Loop::Break((proto_value, rest.into_inner())))
-                            },
-                            ListenerToDialerMessage::NotAvailable => {
-                                Ok(Loop::Continue(rest))
-                            },
-                            _ => Err(ProtocolChoiceError::UnexpectedMessage),
-                        }
-                    })
-            })
-        });
-
-	// The "Rust doesn't have impl Trait yet" tax.
-	Box::new(future)
+	DialerSelectSeq {
+		inner: DialerSelectSeqState::AwaitDialer {
+			dialer_fut: Dialer::new(inner),
+			protocols: protocols,
+		},
+	}
+}
+
+// Same as `dialer_select_proto_serial`, but continues with a `Dialer` whose handshake has already
+// been performed.
+#[inline]
+pub(crate) fn dialer_select_proto_serial_with_dialer<R, I, P>(dialer: Dialer<R>, protocols: I)
+	-> DialerSelectSeq<R, I, P>
+	where R: AsyncRead + AsyncWrite,
+	      I: Iterator<Item = (Bytes, P)>
+{
+	DialerSelectSeq {
+		inner: DialerSelectSeqState::NextProtocol {
+			dialer: dialer,
+			protocols: protocols,
+		},
+	}
+}
+
+/// Future returned by `dialer_select_proto_serial`.
+pub struct DialerSelectSeq<R, I, P> {
+	inner: DialerSelectSeqState<R, I, P>,
+}
+
+enum DialerSelectSeqState<R, I, P> {
+	AwaitDialer {
+		dialer_fut: DialerFuture<R>,
+		protocols: I,
+	},
+	NextProtocol {
+		dialer: Dialer<R>,
+		protocols: I,
+	},
+	SendProtocol {
+		sender: SinkSend<Dialer<R>>,
+		proto_name: Bytes,
+		proto_value: P,
+		protocols: I,
+	},
+	AwaitProtocol {
+		stream: StreamFuture<Dialer<R>>,
+		proto_name: Bytes,
+		proto_value: P,
+		protocols: I,
+	},
+	// Temporary state while switching between the other states.
+	Undefined,
+}
+
+impl<R, I, P> Future for DialerSelectSeq<R, I, P>
+	where R: AsyncRead + AsyncWrite,
+	      I: Iterator<Item = (Bytes, P)>
+{
+	type Item = (P, R);
+	type Error = ProtocolChoiceError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		loop {
+			match mem::replace(&mut self.inner, DialerSelectSeqState::Undefined) {
+				DialerSelectSeqState::AwaitDialer { mut dialer_fut, protocols } => {
+					let dialer = match dialer_fut.poll()? {
+						Async::Ready(dialer) => dialer,
+						Async::NotReady => {
+							self.inner = DialerSelectSeqState::AwaitDialer {
+								dialer_fut: dialer_fut,
+								protocols: protocols,
+							};
+							return Ok(Async::NotReady);
+						},
+					};
+
+					self.inner = DialerSelectSeqState::NextProtocol {
+						dialer: dialer,
+						protocols: protocols,
+					};
+				},
+
+				DialerSelectSeqState::NextProtocol { dialer, mut protocols } => {
+					let (proto_name, proto_value) = protocols.next()
+						.ok_or(ProtocolChoiceError::NoProtocolFound)?;
+					let request = DialerToListenerMessage::ProtocolRequest {
+						name: proto_name.clone(),
+					};
+					self.inner = DialerSelectSeqState::SendProtocol {
+						sender: dialer.send(request),
+						proto_name: proto_name,
+						proto_value: proto_value,
+						protocols: protocols,
+					};
+				},
+
+				DialerSelectSeqState::SendProtocol {
+					mut sender, proto_name, proto_value, protocols
+				} => {
+					let dialer = match sender.poll()? {
+						Async::Ready(dialer) => dialer,
+						Async::NotReady => {
+							self.inner = DialerSelectSeqState::SendProtocol {
+								sender: sender,
+								proto_name: proto_name,
+								proto_value: proto_value,
+								protocols: protocols,
+							};
+							return Ok(Async::NotReady);
+						},
+					};
+
+					self.inner = DialerSelectSeqState::AwaitProtocol {
+						stream: dialer.into_future(),
+						proto_name: proto_name,
+						proto_value: proto_value,
+						protocols: protocols,
+					};
+				},
+
+				DialerSelectSeqState::AwaitProtocol {
+					mut stream, proto_name, proto_value, protocols
+				} => {
+					let (message, dialer) = match stream.poll() {
+						Ok(Async::Ready(val)) => val,
+						Ok(Async::NotReady) => {
+							self.inner = DialerSelectSeqState::AwaitProtocol {
+								stream: stream,
+								proto_name: proto_name,
+								proto_value: proto_value,
+								protocols: protocols,
+							};
+							return Ok(Async::NotReady);
+						},
+						Err((err, _)) => return Err(err.into()),
+					};
+
+					match message {
+						Some(ListenerToDialerMessage::ProtocolAck { ref name })
+							if name == &proto_name =>
+						{
+							// Satisfactory response.
+							return Ok(Async::Ready((proto_value, dialer.into_inner())));
+						},
+						Some(ListenerToDialerMessage::NotAvailable) => {
+							self.inner = DialerSelectSeqState::NextProtocol {
+								dialer: dialer,
+								protocols: protocols,
+							};
+						},
+						// Hint that a listener may send after "not available", which isn't the
+						// answer to our request. See `UnsupportedResponse::NotAvailableWithHint`.
+						Some(ListenerToDialerMessage::ProtocolsListResponse { .. }) => {
+							self.inner = DialerSelectSeqState::AwaitProtocol {
+								stream: dialer.into_future(),
+								proto_name: proto_name,
+								proto_value: proto_value,
+								protocols: protocols,
+							};
+						},
+						_ => return Err(ProtocolChoiceError::UnexpectedMessage),
+					}
+				},
+
+				DialerSelectSeqState::Undefined => {
+					panic!("DialerSelectSeq polled after completion")
+				},
+			}
+		}
+	}
 }
 
 /// Helps selecting a protocol amongst the ones supported.
 ///
 /// Same as `dialer_select_proto`. Queries the list of supported protocols from the remote, then
 /// chooses the most appropriate one.
-// TODO: remove the Box once -> impl Trait lands
-pub fn dialer_select_proto_parallel<'a, R, I, M, P>(
-	inner: R,
-	protocols: I,
-) -> Box<Future<Item = (P, R), Error = ProtocolChoiceError> + 'a>
-	where R: AsyncRead + AsyncWrite + 'a,
-	      I: Iterator<Item = (Bytes, M, P)> + 'a,
-	      M: FnMut(&Bytes, &Bytes) -> bool + 'a,
-	      P: 'a
+#[inline]
+pub fn dialer_select_proto_parallel<R, I, M, P>(inner: R, protocols: I) -> DialerSelectPar<R, I, P>
+	where R: AsyncRead + AsyncWrite,
+	      I: Iterator<Item = (Bytes, M, P)>,
+	      M: FnMut(&Bytes, &Bytes) -> bool
+{
+	DialerSelectPar {
+		inner: DialerSelectParState::AwaitDialer {
+			dialer_fut: Dialer::new(inner),
+			protocols: protocols,
+		},
+	}
+}
+
+/// Future returned by `dialer_select_proto_parallel`.
+pub struct DialerSelectPar<R, I, P> {
+	inner: DialerSelectParState<R, I, P>,
+}
+
+enum DialerSelectParState<R, I, P> {
+	AwaitDialer {
+		dialer_fut: DialerFuture<R>,
+		protocols: I,
+	},
+	SendListRequest {
+		sender: SinkSend<Dialer<R>>,
+		protocols: I,
+	},
+	AwaitListResponse {
+		stream: StreamFuture<Dialer<R>>,
+		protocols: I,
+	},
+	SendProtocol {
+		sender: SinkSend<Dialer<R>>,
+		proto_name: Bytes,
+		proto_value: P,
+	},
+	AwaitProtocol {
+		stream: StreamFuture<Dialer<R>>,
+		proto_name: Bytes,
+		proto_value: P,
+	},
+	// Temporary state while switching between the other states.
+	Undefined,
+}
+
+impl<R, I, M, P> Future for DialerSelectPar<R, I, P>
+	where R: AsyncRead + AsyncWrite,
+	      I: Iterator<Item = (Bytes, M, P)>,
+	      M: FnMut(&Bytes, &Bytes) -> bool
 {
-	let future = Dialer::new(inner)
-		.from_err()
-		.and_then(
-			move |dialer| dialer.send(DialerToListenerMessage::ProtocolsListRequest).from_err(),
-		)
-		.and_then(move |dialer| dialer.into_future().map_err(|(e, _)| e.into()))
-		.and_then(move |(msg, dialer)| {
-			let list = match msg {
-				Some(ListenerToDialerMessage::ProtocolsListResponse { list }) => list,
-				_ => return Err(ProtocolChoiceError::UnexpectedMessage),
-			};
-
-			let mut found = None;
-			for (local_name, mut match_fn, ident) in protocols {
-				for remote_name in &list {
-					if match_fn(remote_name, &local_name) {
-						found = Some((remote_name.clone(), ident));
-						break;
+	type Item = (P, R);
+	type Error = ProtocolChoiceError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		loop {
+			match mem::replace(&mut self.inner, DialerSelectParState::Undefined) {
+				DialerSelectParState::AwaitDialer { mut dialer_fut, protocols } => {
+					let dialer = match dialer_fut.poll()? {
+						Async::Ready(dialer) => dialer,
+						Async::NotReady => {
+							self.inner = DialerSelectParState::AwaitDialer {
+								dialer_fut: dialer_fut,
+								protocols: protocols,
+							};
+							return Ok(Async::NotReady);
+						},
+					};
+
+					self.inner = DialerSelectParState::SendListRequest {
+						sender: dialer.send(DialerToListenerMessage::ProtocolsListRequest),
+						protocols: protocols,
+					};
+				},
+
+				DialerSelectParState::SendListRequest { mut sender, protocols } => {
+					let dialer = match sender.poll()? {
+						Async::Ready(dialer) => dialer,
+						Async::NotReady => {
+							self.inner = DialerSelectParState::SendListRequest {
+								sender: sender,
+								protocols: protocols,
+							};
+							return Ok(Async::NotReady);
+						},
+					};
+
+					self.inner = DialerSelectParState::AwaitListResponse {
+						stream: dialer.into_future(),
+						protocols: protocols,
+					};
+				},
+
+				DialerSelectParState::AwaitListResponse { mut stream, protocols } => {
+					let (message, dialer) = match stream.poll() {
+						Ok(Async::Ready(val)) => val,
+						Ok(Async::NotReady) => {
+							self.inner = DialerSelectParState::AwaitListResponse {
+								stream: stream,
+								protocols: protocols,
+							};
+							return Ok(Async::NotReady);
+						},
+						Err((err, _)) => return Err(err.into()),
+					};
+
+					let list = match message {
+						Some(ListenerToDialerMessage::ProtocolsListResponse { list }) => list,
+						_ => return Err(ProtocolChoiceError::UnexpectedMessage),
+					};
+
+					let mut found = None;
+					for (local_name, mut match_fn, ident) in protocols {
+						for remote_name in &list {
+							if match_fn(remote_name, &local_name) {
+								found = Some((remote_name.clone(), ident));
+								break;
+							}
+						}
+
+						if found.is_some() {
+							break;
+						}
 					}
-				}
 
-				if found.is_some() {
-					break;
-				}
-			}
+					let (proto_name, proto_value) = found
+						.ok_or(ProtocolChoiceError::NoProtocolFound)?;
+					let request = DialerToListenerMessage::ProtocolRequest {
+						name: proto_name.clone(),
+					};
+					self.inner = DialerSelectParState::SendProtocol {
+						sender: dialer.send(request),
+						proto_name: proto_name,
+						proto_value: proto_value,
+					};
+				},
 
-			let (proto_name, proto_val) = found.ok_or(ProtocolChoiceError::NoProtocolFound)?;
-			Ok((proto_name, proto_val, dialer))
-		})
-		.and_then(|(proto_name, proto_val, dialer)| {
-			dialer.send(DialerToListenerMessage::ProtocolRequest { name: proto_name.clone() })
-			      .from_err()
-			      .map(|dialer| (proto_name, proto_val, dialer))
-		})
-		.and_then(|(proto_name, proto_val, dialer)| {
-			dialer.into_future()
-			      .map(|(msg, rest)| (proto_name, proto_val, msg, rest))
-			      .map_err(|(err, _)| err.into())
-		})
-		.and_then(|(proto_name, proto_val, msg, dialer)| match msg {
-			Some(ListenerToDialerMessage::ProtocolAck { ref name }) if name == &proto_name => {
-				Ok((proto_val, dialer.into_inner()))
-			}
-			_ => Err(ProtocolChoiceError::UnexpectedMessage),
-		});
+				DialerSelectParState::SendProtocol { mut sender, proto_name, proto_value } => {
+					let dialer = match sender.poll()? {
+						Async::Ready(dialer) => dialer,
+						Async::NotReady => {
+							self.inner = DialerSelectParState::SendProtocol {
+								sender: sender,
+								proto_name: proto_name,
+								proto_value: proto_value,
+							};
+							return Ok(Async::NotReady);
+						},
+					};
+
+					self.inner = DialerSelectParState::AwaitProtocol {
+						stream: dialer.into_future(),
+						proto_name: proto_name,
+						proto_value: proto_value,
+					};
+				},
+
+				DialerSelectParState::AwaitProtocol { mut stream, proto_name, proto_value } => {
+					let (message, dialer) = match stream.poll() {
+						Ok(Async::Ready(val)) => val,
+						Ok(Async::NotReady) => {
+							self.inner = DialerSelectParState::AwaitProtocol {
+								stream: stream,
+								proto_name: proto_name,
+								proto_value: proto_value,
+							};
+							return Ok(Async::NotReady);
+						},
+						Err((err, _)) => return Err(err.into()),
+					};
 
-	// The "Rust doesn't have impl Trait yet" tax.
-	Box::new(future)
+					return match message {
+						Some(ListenerToDialerMessage::ProtocolAck { ref name })
+							if name == &proto_name =>
+						{
+							Ok(Async::Ready((proto_value, dialer.into_inner())))
+						},
+						_ => Err(ProtocolChoiceError::UnexpectedMessage),
+					};
+				},
+
+				DialerSelectParState::Undefined => {
+					panic!("DialerSelectPar polled after completion")
+				},
+			}
+		}
+	}
 }
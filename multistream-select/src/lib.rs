@@ -39,6 +39,16 @@
 //! The dialer has two options available: either request the list of protocols that the listener
 //! supports, or suggest a protocol. If a protocol is suggested, the listener can either accept (by
 //! answering with the same protocol name) or refuse the choice (by answering "not available").
+//!
+//! A listener can also follow "not available" with the list of the protocols it supports, as a
+//! hint of what the dialer can use instead. This isn't understood by all the implementations, and
+//! is only done if the `UnsupportedObserver` passed to `listener_select_proto_with_observer` asks
+//! for it with `UnsupportedResponse::NotAvailableWithHint`. The dialers of this crate skip it.
+//!
+//! When two nodes dial each other at the same time, a TCP simultaneous open can produce a single
+//! connection on which both sides act as the dialer. The `simultaneous_select_proto` function
+//! detects this situation by first proposing `/libp2p/simultaneous-connect`, then decides the roles
+//! by exchanging random nonces with the remote.
 //! 
 //! ## Examples
 //! 
@@ -120,6 +130,7 @@
 
 extern crate bytes;
 extern crate futures;
+extern crate rand;
 extern crate smallvec;
 extern crate tokio_io;
 extern crate varint;
@@ -128,10 +139,15 @@ mod dialer_select;
 mod error;
 mod length_delimited;
 mod listener_select;
+mod simultaneous_open;
 mod tests;
 
 pub mod protocol;
 
-pub use self::dialer_select::dialer_select_proto;
+pub use self::dialer_select::{dialer_select_proto, DialerSelectFuture, DialerSelectPar};
+pub use self::dialer_select::{DialerSelectSeq, IgnoreMatchFn};
 pub use self::error::ProtocolChoiceError;
-pub use self::listener_select::listener_select_proto;
+pub use self::listener_select::{listener_select_proto, listener_select_proto_with_observer};
+pub use self::listener_select::{ListenerSelectFuture, UnsupportedObserver, UnsupportedResponse};
+pub use self::simultaneous_open::{simultaneous_select_proto, Role, SimultaneousSelectFuture};
+pub use self::simultaneous_open::SIMULTANEOUS_CONNECT_PROTOCOL;
@@ -23,11 +23,13 @@
 
 use ProtocolChoiceError;
 use bytes::Bytes;
-use futures::{Future, Sink, Stream};
-use futures::future::{err, loop_fn, Loop};
+use futures::{Async, Future, Poll, Sink, Stream};
+use futures::sink::Send as SinkSend;
+use futures::stream::StreamFuture;
+use std::mem;
 
 use protocol::DialerToListenerMessage;
-use protocol::Listener;
+use protocol::{Listener, ListenerFuture};
 use protocol::ListenerToDialerMessage;
 use tokio_io::{AsyncRead, AsyncWrite};
 
@@ -45,58 +47,243 @@ use tokio_io::{AsyncRead, AsyncWrite};
 ///
 /// On success, returns the socket and the identifier of the chosen protocol (of type `P`). The
 /// socket now uses this protocol.
-// TODO: remove the Box once -> impl Trait lands
-pub fn listener_select_proto<'a, R, I, M, P>(
-	inner: R,
-	protocols: I,
-) -> Box<Future<Item = (P, R), Error = ProtocolChoiceError> + 'a>
-	where R: AsyncRead + AsyncWrite + 'a,
-	      I: Iterator<Item = (Bytes, M, P)> + Clone + 'a,
-	      M: FnMut(&Bytes, &Bytes) -> bool + 'a,
-	      P: 'a
+#[inline]
+pub fn listener_select_proto<R, I, M, P>(inner: R, protocols: I)
+										 -> ListenerSelectFuture<R, I, P, fn(&Bytes)>
+	where R: AsyncRead + AsyncWrite,
+	      I: Iterator<Item = (Bytes, M, P)> + Clone,
+	      M: FnMut(&Bytes, &Bytes) -> bool
 {
-	let future = Listener::new(inner).from_err().and_then(move |listener| {
+	fn ignore(_: &Bytes) {}
+	listener_select_proto_with_observer(inner, protocols, ignore as fn(&Bytes))
+}
+
+/// Same as `listener_select_proto`, but additionally calls `on_unsupported` with the name of the
+/// protocol every time the remote requests a protocol that we don't support. `on_unsupported` can
+/// be a closure or any other implementation of `UnsupportedObserver`.
+///
+/// The remote is answered according to `UnsupportedObserver::response()`. By default, it is
+/// answered with "not available" as usual, and it can request the list of protocols if it wants
+/// to know which protocols we support.
+#[inline]
+pub fn listener_select_proto_with_observer<R, I, M, P, F>(inner: R, protocols: I, on_unsupported: F)
+														  -> ListenerSelectFuture<R, I, P, F>
+	where R: AsyncRead + AsyncWrite,
+	      I: Iterator<Item = (Bytes, M, P)> + Clone,
+	      M: FnMut(&Bytes, &Bytes) -> bool,
+	      F: UnsupportedObserver
+{
+	ListenerSelectFuture {
+		inner: ListenerSelectState::AwaitListener {
+			listener_fut: Listener::new(inner),
+			protocols: protocols,
+		},
+		on_unsupported: on_unsupported,
+	}
+}
+
+/// Receives the names of the protocols that the remote requested and that we don't support.
+///
+/// Implemented on closures that take a `&Bytes`, so that a closure can be passed to
+/// `listener_select_proto_with_observer`. Implementing it on a struct instead avoids having to
+/// box the closure in order to name the type of the `ListenerSelectFuture`.
+pub trait UnsupportedObserver {
+	/// Called with the name of a protocol that the remote requested and that we don't support.
+	fn on_unsupported(&self, name: &Bytes);
+
+	/// Returns how the remote is answered, after `on_unsupported` has been called. Defaults to
+	/// `UnsupportedResponse::NotAvailable`.
+	#[inline]
+	fn response(&self) -> UnsupportedResponse {
+		UnsupportedResponse::NotAvailable
+	}
+}
+
+/// How the listener answers a request for a protocol that it doesn't support.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UnsupportedResponse {
+	/// Answers with "not available", which all the implementations of multistream-select
+	/// understand.
+	NotAvailable,
+	/// Answers with "not available", then sends the list of the protocols we support as if the
+	/// remote had requested it, as a hint of what it can use instead.
+	///
+	/// The dialers of this crate skip the hint. Other implementations may consider it as an
+	/// unexpected message and abort the negotiation, therefore this should only be used in
+	/// networks whose nodes are known to accept it.
+	NotAvailableWithHint,
+}
+
+impl Default for UnsupportedResponse {
+	#[inline]
+	fn default() -> UnsupportedResponse {
+		UnsupportedResponse::NotAvailable
+	}
+}
+
+impl<F> UnsupportedObserver for F
+	where F: Fn(&Bytes)
+{
+	#[inline]
+	fn on_unsupported(&self, name: &Bytes) {
+		self(name)
+	}
+}
+
+/// Future returned by `listener_select_proto` and `listener_select_proto_with_observer`.
+pub struct ListenerSelectFuture<R, I, P, F> {
+	inner: ListenerSelectState<R, I, P>,
+	on_unsupported: F,
+}
 
-		loop_fn(listener, move |listener| {
-			let protocols = protocols.clone();
+enum ListenerSelectState<R, I, P> {
+	AwaitListener {
+		listener_fut: ListenerFuture<R>,
+		protocols: I,
+	},
+	Incoming {
+		stream: StreamFuture<Listener<R>>,
+		protocols: I,
+	},
+	Outgoing {
+		sender: SinkSend<Listener<R>>,
+		protocols: I,
+		// If `Some`, the protocol has been chosen and we finish once the message has been sent.
+		outcome: Option<P>,
+		// If `Some`, list of the protocols we support, sent after the "not available" message.
+		hint: Option<Vec<Bytes>>,
+	},
+	// Temporary state while switching between the other states.
+	Undefined,
+}
+
+impl<R, I, M, P, F> Future for ListenerSelectFuture<R, I, P, F>
+	where R: AsyncRead + AsyncWrite,
+	      I: Iterator<Item = (Bytes, M, P)> + Clone,
+	      M: FnMut(&Bytes, &Bytes) -> bool,
+	      F: UnsupportedObserver
+{
+	type Item = (P, R);
+	type Error = ProtocolChoiceError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		loop {
+			match mem::replace(&mut self.inner, ListenerSelectState::Undefined) {
+				ListenerSelectState::AwaitListener { mut listener_fut, protocols } => {
+					let listener = match listener_fut.poll()? {
+						Async::Ready(listener) => listener,
+						Async::NotReady => {
+							self.inner = ListenerSelectState::AwaitListener {
+								listener_fut: listener_fut,
+								protocols: protocols,
+							};
+							return Ok(Async::NotReady);
+						},
+					};
+
+					self.inner = ListenerSelectState::Incoming {
+						stream: listener.into_future(),
+						protocols: protocols,
+					};
+				},
+
+				ListenerSelectState::Incoming { mut stream, protocols } => {
+					let (message, listener) = match stream.poll() {
+						Ok(Async::Ready(val)) => val,
+						Ok(Async::NotReady) => {
+							self.inner = ListenerSelectState::Incoming {
+								stream: stream,
+								protocols: protocols,
+							};
+							return Ok(Async::NotReady);
+						},
+						Err((err, _)) => return Err(err.into()),
+					};
 
-			listener.into_future()
-			        .map_err(|(e, _)| e.into())
-			        .and_then(move |(message, listener)| match message {
-				Some(DialerToListenerMessage::ProtocolsListRequest) => {
-					let msg = ListenerToDialerMessage::ProtocolsListResponse {
-						list: protocols.map(|(p, _, _)| p).collect(),
+					match message {
+						Some(DialerToListenerMessage::ProtocolsListRequest) => {
+							let msg = ListenerToDialerMessage::ProtocolsListResponse {
+								list: protocols.clone().map(|(p, _, _)| p).collect(),
+							};
+							self.inner = ListenerSelectState::Outgoing {
+								sender: listener.send(msg),
+								protocols: protocols,
+								outcome: None,
+								hint: None,
+							};
+						},
+						Some(DialerToListenerMessage::ProtocolRequest { name }) => {
+							let mut outcome = None;
+							let mut send_back = ListenerToDialerMessage::NotAvailable;
+							for (supported, mut matches, value) in protocols.clone() {
+								if matches(&name, &supported) {
+									send_back = ListenerToDialerMessage::ProtocolAck {
+										name: name.clone(),
+									};
+									outcome = Some(value);
+									break;
+								}
+							}
+
+							let mut hint = None;
+							if outcome.is_none() {
+								self.on_unsupported.on_unsupported(&name);
+								let response = self.on_unsupported.response();
+								if response == UnsupportedResponse::NotAvailableWithHint {
+									hint = Some(protocols.clone().map(|(p, _, _)| p).collect());
+								}
+							}
+
+							self.inner = ListenerSelectState::Outgoing {
+								sender: listener.send(send_back),
+								protocols: protocols,
+								outcome: outcome,
+								hint: hint,
+							};
+						},
+						None => return Err(ProtocolChoiceError::NoProtocolFound),
+					}
+				},
+
+				ListenerSelectState::Outgoing { mut sender, protocols, outcome, hint } => {
+					let listener = match sender.poll()? {
+						Async::Ready(listener) => listener,
+						Async::NotReady => {
+							self.inner = ListenerSelectState::Outgoing {
+								sender: sender,
+								protocols: protocols,
+								outcome: outcome,
+								hint: hint,
+							};
+							return Ok(Async::NotReady);
+						},
 					};
-					let fut = listener.send(msg).from_err().map(move |listener| (None, listener));
-					Box::new(fut) as Box<Future<Item = _, Error = ProtocolChoiceError>>
-				}
-				Some(DialerToListenerMessage::ProtocolRequest { name }) => {
-					let mut outcome = None;
-					let mut send_back = ListenerToDialerMessage::NotAvailable;
-					for (supported, mut matches, value) in protocols {
-						if matches(&name, &supported) {
-							send_back = ListenerToDialerMessage::ProtocolAck { name: name.clone() };
-							outcome = Some(value);
-							break;
-						}
+
+					if let Some(outcome) = outcome {
+						return Ok(Async::Ready((outcome, listener.into_inner())));
 					}
 
-					let fut = listener.send(send_back)
-					                  .from_err()
-					                  .map(move |listener| (outcome, listener));
-					Box::new(fut) as Box<Future<Item = _, Error = ProtocolChoiceError>>
-				}
-				None => {
-					Box::new(err(ProtocolChoiceError::NoProtocolFound)) as Box<_>
-				}
-			})
-			        .map(|(outcome, listener): (_, Listener<R>)| match outcome {
-				Some(outcome) => Loop::Break((outcome, listener.into_inner())),
-				None => Loop::Continue(listener),
-			})
-		})
-	});
-
-	// The "Rust doesn't have impl Trait yet" tax.
-	Box::new(future)
+					if let Some(list) = hint {
+						let msg = ListenerToDialerMessage::ProtocolsListResponse { list: list };
+						self.inner = ListenerSelectState::Outgoing {
+							sender: listener.send(msg),
+							protocols: protocols,
+							outcome: None,
+							hint: None,
+						};
+						continue;
+					}
+
+					self.inner = ListenerSelectState::Incoming {
+						stream: listener.into_future(),
+						protocols: protocols,
+					};
+				},
+
+				ListenerSelectState::Undefined => {
+					panic!("ListenerSelectFuture polled after completion")
+				},
+			}
+		}
+	}
 }
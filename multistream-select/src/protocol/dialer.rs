@@ -22,6 +22,7 @@
 
 use bytes::{Bytes, BytesMut};
 use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use futures::sink::Send as SinkSend;
 use length_delimited::LengthDelimitedFramedRead;
 use protocol::DialerToListenerMessage;
 use protocol::ListenerToDialerMessage;
@@ -45,20 +46,13 @@ impl<R> Dialer<R>
 {
 	/// Takes ownership of a socket and starts the handshake. If the handshake succeeds, the
 	/// future returns a `Dialer`.
-	pub fn new<'a>(inner: R) -> Box<Future<Item = Dialer<R>, Error = MultistreamSelectError> + 'a>
-		where R: 'a
-	{
+	pub fn new(inner: R) -> DialerFuture<R> {
 		let write = LengthDelimitedBuilder::new().length_field_length(1).new_write(inner);
 		let inner = LengthDelimitedFramedRead::new(write);
 
-		let future =
-			inner.send(BytesMut::from(MULTISTREAM_PROTOCOL_WITH_LF)).from_err().map(|inner| {
-				Dialer {
-					inner: inner,
-					handshake_finished: false,
-				}
-			});
-		Box::new(future)
+		DialerFuture {
+			inner: inner.send(BytesMut::from(MULTISTREAM_PROTOCOL_WITH_LF)),
+		}
 	}
 
 	/// Grants back the socket. Typically used after a `ProtocolAck` has been received.
@@ -68,6 +62,31 @@ impl<R> Dialer<R>
 	}
 }
 
+/// Future that sends the handshake and then produces a `Dialer`. Returned by `Dialer::new`.
+pub struct DialerFuture<R> {
+	inner: SinkSend<LengthDelimitedFramedRead<Bytes, LengthDelimitedFramedWrite<R, BytesMut>>>,
+}
+
+impl<R> Future for DialerFuture<R>
+    where R: AsyncRead + AsyncWrite
+{
+	type Item = Dialer<R>;
+	type Error = MultistreamSelectError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let inner = match self.inner.poll() {
+			Ok(Async::Ready(inner)) => inner,
+			Ok(Async::NotReady) => return Ok(Async::NotReady),
+			Err(err) => return Err(err.into()),
+		};
+
+		Ok(Async::Ready(Dialer {
+			inner: inner,
+			handshake_finished: false,
+		}))
+	}
+}
+
 impl<R> Sink for Dialer<R>
     where R: AsyncRead + AsyncWrite
 {
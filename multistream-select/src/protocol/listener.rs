@@ -22,11 +22,14 @@
 
 use bytes::{Bytes, BytesMut};
 use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use futures::sink::Send as SinkSend;
+use futures::stream::StreamFuture;
 use length_delimited::LengthDelimitedFramedRead;
 use protocol::DialerToListenerMessage;
 use protocol::ListenerToDialerMessage;
 use protocol::MULTISTREAM_PROTOCOL_WITH_LF;
 use protocol::MultistreamSelectError;
+use std::mem;
 use tokio_io::{AsyncRead, AsyncWrite};
 use tokio_io::codec::length_delimited::Builder as LengthDelimitedBuilder;
 use tokio_io::codec::length_delimited::FramedWrite as LengthDelimitedFramedWrite;
@@ -43,26 +46,13 @@ impl<R> Listener<R>
 {
 	/// Takes ownership of a socket and starts the handshake. If the handshake succeeds, the
 	/// future returns a `Listener`.
-	pub fn new<'a>(inner: R) -> Box<Future<Item = Listener<R>, Error = MultistreamSelectError> + 'a>
-		where R: 'a
-	{
+	pub fn new(inner: R) -> ListenerFuture<R> {
 		let write = LengthDelimitedBuilder::new().length_field_length(1).new_write(inner);
 		let inner = LengthDelimitedFramedRead::<Bytes, _>::new(write);
 
-		let future = inner.into_future()
-		                  .map_err(|(e, _)| e.into())
-		                  .and_then(|(msg, rest)| {
-			if msg.as_ref().map(|b| &b[..]) != Some(MULTISTREAM_PROTOCOL_WITH_LF) {
-				return Err(MultistreamSelectError::FailedHandshake);
-			}
-			Ok(rest)
-		})
-		                  .and_then(|socket| {
-			socket.send(BytesMut::from(MULTISTREAM_PROTOCOL_WITH_LF)).from_err()
-		})
-		                  .map(|inner| Listener { inner: inner });
-
-		Box::new(future)
+		ListenerFuture {
+			inner: ListenerFutureState::Await { inner: inner.into_future() },
+		}
 	}
 
 	/// Grants back the socket. Typically used after a `ProtocolRequest` has been received and a
@@ -73,6 +63,72 @@ impl<R> Listener<R>
 	}
 }
 
+/// Future that performs the handshake and then produces a `Listener`. Returned by
+/// `Listener::new`.
+pub struct ListenerFuture<R> {
+	inner: ListenerFutureState<R>,
+}
+
+enum ListenerFutureState<R> {
+	// Waiting for the handshake of the remote.
+	Await {
+		inner: StreamFuture<LengthDelimitedFramedRead<Bytes,
+														LengthDelimitedFramedWrite<R, BytesMut>>>,
+	},
+	// Sending back our handshake.
+	Reply {
+		inner: SinkSend<LengthDelimitedFramedRead<Bytes, LengthDelimitedFramedWrite<R, BytesMut>>>,
+	},
+	// Temporary state while switching between the other states.
+	Undefined,
+}
+
+impl<R> Future for ListenerFuture<R>
+    where R: AsyncRead + AsyncWrite
+{
+	type Item = Listener<R>;
+	type Error = MultistreamSelectError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		loop {
+			match mem::replace(&mut self.inner, ListenerFutureState::Undefined) {
+				ListenerFutureState::Await { mut inner } => {
+					let (msg, socket) = match inner.poll() {
+						Ok(Async::Ready(val)) => val,
+						Ok(Async::NotReady) => {
+							self.inner = ListenerFutureState::Await { inner: inner };
+							return Ok(Async::NotReady);
+						},
+						Err((err, _)) => return Err(err.into()),
+					};
+
+					if msg.as_ref().map(|b| &b[..]) != Some(MULTISTREAM_PROTOCOL_WITH_LF) {
+						return Err(MultistreamSelectError::FailedHandshake);
+					}
+
+					let inner = socket.send(BytesMut::from(MULTISTREAM_PROTOCOL_WITH_LF));
+					self.inner = ListenerFutureState::Reply { inner: inner };
+				},
+				ListenerFutureState::Reply { mut inner } => {
+					match inner.poll() {
+						Ok(Async::Ready(inner)) => {
+							return Ok(Async::Ready(Listener { inner: inner }));
+						},
+						Ok(Async::NotReady) => {
+							self.inner = ListenerFutureState::Reply { inner: inner };
+							return Ok(Async::NotReady);
+						},
+						Err(err) => return Err(err.into()),
+					}
+				},
+				ListenerFutureState::Undefined => {
+					panic!("ListenerFuture polled after completion")
+				},
+			}
+		}
+	}
+}
+
 impl<R> Sink for Listener<R>
     where R: AsyncRead + AsyncWrite
 {
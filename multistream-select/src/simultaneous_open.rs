@@ -0,0 +1,312 @@
+// Copyright 2018 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Contains the `simultaneous_select_proto` code, which allows selecting a protocol when both
+//! sides of the connection may believe that they are the dialer.
+//!
+//! This happens with a TCP simultaneous open, when two nodes dial each other at the same time
+//! (for example during a hole punching attempt) and the operating systems merge both attempts
+//! into a single connection.
+//!
+//! Before proposing any other protocol, the dialer proposes `/libp2p/simultaneous-connect`. A
+//! regular listener never supports this protocol and answers "not available", after which the
+//! negotiation continues as usual. If the remote is a dialer as well, it proposes the same
+//! protocol, which we receive as if it was an acknowledgement. Both sides then send a random
+//! nonce, and the side with the higher nonce becomes the initiator. The negotiation then starts
+//! again from the beginning, with the initiator acting as the dialer and the other side acting as
+//! the listener.
+
+use ProtocolChoiceError;
+use bytes::Bytes;
+use dialer_select::{self, DialerSelectSeq, IgnoreMatchFn};
+use futures::{Async, Future, Poll, Sink, Stream};
+use futures::sink::Send as SinkSend;
+use futures::stream::StreamFuture;
+use listener_select::{listener_select_proto, ListenerSelectFuture};
+use protocol::{Dialer, DialerFuture};
+use protocol::DialerToListenerMessage;
+use protocol::ListenerToDialerMessage;
+use rand;
+use std::mem;
+use std::str;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Name of the protocol that the dialer proposes in order to detect a simultaneous open.
+pub const SIMULTANEOUS_CONNECT_PROTOCOL: &'static [u8] = b"/libp2p/simultaneous-connect";
+
+// Prefix of the message that contains the nonce, followed with the nonce in decimal.
+const NONCE_PREFIX: &'static str = "/libp2p/simultaneous-connect/select/";
+
+/// Role of the local node in the negotiation performed by `simultaneous_select_proto`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Role {
+	/// We acted as the dialer.
+	Initiator,
+	/// We acted as the listener, because the remote won the simultaneous open.
+	Responder,
+}
+
+/// Helps selecting a protocol amongst the ones supported, when the remote may be a dialer as well.
+///
+/// Same as `dialer_select_proto`, except that a simultaneous open is detected and resolved with
+/// the remote. On success, also returns the `Role` that we ended up having in the negotiation.
+/// The iterator must be clonable, because we may act as the listener.
+///
+/// > **Note**: Both sides must use this function for a simultaneous open to succeed. The
+/// >           listeners don't need to do anything special.
+#[inline]
+pub fn simultaneous_select_proto<R, I, M, P>(inner: R, protocols: I)
+											 -> SimultaneousSelectFuture<R, I, P>
+	where R: AsyncRead + AsyncWrite,
+	      I: Iterator<Item = (Bytes, M, P)> + Clone,
+	      M: FnMut(&Bytes, &Bytes) -> bool
+{
+	SimultaneousSelectFuture {
+		inner: SimultaneousSelectState::AwaitDialer {
+			dialer_fut: Dialer::new(inner),
+			protocols: protocols,
+		},
+		nonce: rand::random::<u64>(),
+	}
+}
+
+/// Future returned by `simultaneous_select_proto`.
+pub struct SimultaneousSelectFuture<R, I, P> {
+	inner: SimultaneousSelectState<R, I, P>,
+	nonce: u64,
+}
+
+enum SimultaneousSelectState<R, I, P> {
+	AwaitDialer {
+		dialer_fut: DialerFuture<R>,
+		protocols: I,
+	},
+	SendProposal {
+		sender: SinkSend<Dialer<R>>,
+		protocols: I,
+	},
+	AwaitProposal {
+		stream: StreamFuture<Dialer<R>>,
+		protocols: I,
+	},
+	SendNonce {
+		sender: SinkSend<Dialer<R>>,
+		protocols: I,
+	},
+	AwaitNonce {
+		stream: StreamFuture<Dialer<R>>,
+		protocols: I,
+	},
+	Initiator {
+		future: DialerSelectSeq<R, IgnoreMatchFn<I>, P>,
+	},
+	Responder {
+		future: ListenerSelectFuture<R, I, P, fn(&Bytes)>,
+	},
+	// Temporary state while switching between the other states.
+	Undefined,
+}
+
+impl<R, I, M, P> Future for SimultaneousSelectFuture<R, I, P>
+	where R: AsyncRead + AsyncWrite,
+	      I: Iterator<Item = (Bytes, M, P)> + Clone,
+	      M: FnMut(&Bytes, &Bytes) -> bool
+{
+	type Item = (P, R, Role);
+	type Error = ProtocolChoiceError;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		loop {
+			match mem::replace(&mut self.inner, SimultaneousSelectState::Undefined) {
+				SimultaneousSelectState::AwaitDialer { mut dialer_fut, protocols } => {
+					let dialer = match dialer_fut.poll()? {
+						Async::Ready(dialer) => dialer,
+						Async::NotReady => {
+							self.inner = SimultaneousSelectState::AwaitDialer {
+								dialer_fut: dialer_fut,
+								protocols: protocols,
+							};
+							return Ok(Async::NotReady);
+						},
+					};
+
+					let request = DialerToListenerMessage::ProtocolRequest {
+						name: Bytes::from(SIMULTANEOUS_CONNECT_PROTOCOL),
+					};
+					self.inner = SimultaneousSelectState::SendProposal {
+						sender: dialer.send(request),
+						protocols: protocols,
+					};
+				},
+
+				SimultaneousSelectState::SendProposal { mut sender, protocols } => {
+					let dialer = match sender.poll()? {
+						Async::Ready(dialer) => dialer,
+						Async::NotReady => {
+							self.inner = SimultaneousSelectState::SendProposal {
+								sender: sender,
+								protocols: protocols,
+							};
+							return Ok(Async::NotReady);
+						},
+					};
+
+					self.inner = SimultaneousSelectState::AwaitProposal {
+						stream: dialer.into_future(),
+						protocols: protocols,
+					};
+				},
+
+				SimultaneousSelectState::AwaitProposal { mut stream, protocols } => {
+					let (message, dialer) = match stream.poll() {
+						Ok(Async::Ready(val)) => val,
+						Ok(Async::NotReady) => {
+							self.inner = SimultaneousSelectState::AwaitProposal {
+								stream: stream,
+								protocols: protocols,
+							};
+							return Ok(Async::NotReady);
+						},
+						Err((err, _)) => return Err(err.into()),
+					};
+
+					match message {
+						Some(ListenerToDialerMessage::NotAvailable) => {
+							// The remote is a regular listener.
+							let protocols = IgnoreMatchFn::new(protocols);
+							self.inner = SimultaneousSelectState::Initiator {
+								future: dialer_select::dialer_select_proto_serial_with_dialer(
+									dialer, protocols),
+							};
+						},
+						Some(ListenerToDialerMessage::ProtocolAck { ref name })
+							if &name[..] == SIMULTANEOUS_CONNECT_PROTOCOL =>
+						{
+							// The remote is a dialer as well.
+							let request = DialerToListenerMessage::ProtocolRequest {
+								name: Bytes::from(format!("{}{}", NONCE_PREFIX, self.nonce)),
+							};
+							self.inner = SimultaneousSelectState::SendNonce {
+								sender: dialer.send(request),
+								protocols: protocols,
+							};
+						},
+						_ => return Err(ProtocolChoiceError::UnexpectedMessage),
+					}
+				},
+
+				SimultaneousSelectState::SendNonce { mut sender, protocols } => {
+					let dialer = match sender.poll()? {
+						Async::Ready(dialer) => dialer,
+						Async::NotReady => {
+							self.inner = SimultaneousSelectState::SendNonce {
+								sender: sender,
+								protocols: protocols,
+							};
+							return Ok(Async::NotReady);
+						},
+					};
+
+					self.inner = SimultaneousSelectState::AwaitNonce {
+						stream: dialer.into_future(),
+						protocols: protocols,
+					};
+				},
+
+				SimultaneousSelectState::AwaitNonce { mut stream, protocols } => {
+					let (message, dialer) = match stream.poll() {
+						Ok(Async::Ready(val)) => val,
+						Ok(Async::NotReady) => {
+							self.inner = SimultaneousSelectState::AwaitNonce {
+								stream: stream,
+								protocols: protocols,
+							};
+							return Ok(Async::NotReady);
+						},
+						Err((err, _)) => return Err(err.into()),
+					};
+
+					let remote_nonce = match message {
+						Some(ListenerToDialerMessage::ProtocolAck { ref name }) => {
+							parse_nonce(name).ok_or(ProtocolChoiceError::UnexpectedMessage)?
+						},
+						_ => return Err(ProtocolChoiceError::UnexpectedMessage),
+					};
+
+					// Both sides compare the same two numbers, and therefore agree on the roles.
+					// Equal nonces are astronomically unlikely, and simply make the negotiation
+					// fail.
+					if remote_nonce == self.nonce {
+						return Err(ProtocolChoiceError::UnexpectedMessage);
+					}
+
+					let socket = dialer.into_inner();
+					self.inner = if self.nonce > remote_nonce {
+						let protocols = IgnoreMatchFn::new(protocols);
+						SimultaneousSelectState::Initiator {
+							future: dialer_select::dialer_select_proto_serial(socket, protocols),
+						}
+					} else {
+						SimultaneousSelectState::Responder {
+							future: listener_select_proto(socket, protocols),
+						}
+					};
+				},
+
+				SimultaneousSelectState::Initiator { mut future } => {
+					return match future.poll()? {
+						Async::Ready((proto, socket)) => {
+							Ok(Async::Ready((proto, socket, Role::Initiator)))
+						},
+						Async::NotReady => {
+							self.inner = SimultaneousSelectState::Initiator { future: future };
+							Ok(Async::NotReady)
+						},
+					};
+				},
+
+				SimultaneousSelectState::Responder { mut future } => {
+					return match future.poll()? {
+						Async::Ready((proto, socket)) => {
+							Ok(Async::Ready((proto, socket, Role::Responder)))
+						},
+						Async::NotReady => {
+							self.inner = SimultaneousSelectState::Responder { future: future };
+							Ok(Async::NotReady)
+						},
+					};
+				},
+
+				SimultaneousSelectState::Undefined => {
+					panic!("SimultaneousSelectFuture polled after completion")
+				},
+			}
+		}
+	}
+}
+
+// Extracts the nonce from the message sent by the remote.
+fn parse_nonce(message: &Bytes) -> Option<u64> {
+	if !message.starts_with(NONCE_PREFIX.as_bytes()) {
+		return None;
+	}
+
+	str::from_utf8(&message[NONCE_PREFIX.len() ..]).ok().and_then(|nonce| nonce.parse().ok())
+}
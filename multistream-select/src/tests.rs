@@ -24,7 +24,9 @@
 
 extern crate tokio_core;
 
-use {listener_select_proto, dialer_select_proto};
+use {listener_select_proto, listener_select_proto_with_observer, dialer_select_proto};
+use {simultaneous_select_proto, Role};
+use {UnsupportedObserver, UnsupportedResponse};
 use ProtocolChoiceError;
 use bytes::Bytes;
 use dialer_select::{dialer_select_proto_parallel, dialer_select_proto_serial};
@@ -34,6 +36,8 @@ use protocol::{Dialer, Listener, DialerToListenerMessage, ListenerToDialerMessag
 use self::tokio_core::net::TcpListener;
 use self::tokio_core::net::TcpStream;
 use self::tokio_core::reactor::Core;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 #[test]
 fn negotiate_with_self_succeeds() {
@@ -146,6 +150,142 @@ fn no_protocol_found() {
 	}
 }
 
+#[test]
+fn unsupported_protocols_are_observed() {
+	let mut core = Core::new().unwrap();
+
+	let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+	let listener_addr = listener.local_addr().unwrap();
+
+	let unsupported = Rc::new(RefCell::new(Vec::new()));
+	let unsupported2 = unsupported.clone();
+
+	let server = listener.incoming()
+	                     .into_future()
+	                     .map(|s| s.0.unwrap().0)
+	                     .map_err(|(e, _)| e.into())
+	                     .and_then(move |connec| {
+		let protos = vec![
+			(Bytes::from("/proto1"), <Bytes as PartialEq>::eq, 1),
+			(Bytes::from("/proto2"), <Bytes as PartialEq>::eq, 2),
+		]
+		             .into_iter();
+		listener_select_proto_with_observer(connec, protos, move |name: &Bytes| {
+			unsupported2.borrow_mut().push(name.clone())
+		}).map(|r| r.0)
+	});
+
+	let client =
+		TcpStream::connect(&listener_addr, &core.handle()).from_err().and_then(move |connec| {
+			let protos = vec![
+				(Bytes::from("/proto3"), <Bytes as PartialEq>::eq, 3),
+				(Bytes::from("/proto2"), <Bytes as PartialEq>::eq, 2),
+			]
+			             .into_iter();
+			dialer_select_proto(connec, protos).map(|r| r.0)
+		});
+
+	let (dialer_chosen, listener_chosen) = core.run(client.join(server)).unwrap();
+	assert_eq!(dialer_chosen, 2);
+	assert_eq!(listener_chosen, 2);
+	assert_eq!(*unsupported.borrow(), vec![Bytes::from("/proto3")]);
+}
+
+// Observer that asks for the hint to be sent.
+struct WithHint;
+
+impl UnsupportedObserver for WithHint {
+	fn on_unsupported(&self, _: &Bytes) {}
+
+	fn response(&self) -> UnsupportedResponse {
+		UnsupportedResponse::NotAvailableWithHint
+	}
+}
+
+#[test]
+fn unsupported_protocol_hint() {
+	let mut core = Core::new().unwrap();
+
+	let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+	let listener_addr = listener.local_addr().unwrap();
+
+	let server = listener.incoming()
+	                     .into_future()
+	                     .map(|s| s.0.unwrap().0)
+	                     .map_err(|(e, _)| e.into())
+	                     .and_then(move |connec| {
+		let protos = vec![
+			(Bytes::from("/proto1"), <Bytes as PartialEq>::eq, 1),
+			(Bytes::from("/proto2"), <Bytes as PartialEq>::eq, 2),
+		]
+		             .into_iter();
+		listener_select_proto_with_observer(connec, protos, WithHint).map(|r| r.0)
+	});
+
+	// The hint comes after "not available", and before the answer to the next request.
+	let client = TcpStream::connect(&listener_addr, &core.handle())
+		.from_err()
+		.and_then(move |connec| Dialer::new(connec))
+		.and_then(move |dialer| {
+			let name = Bytes::from("/proto3");
+			dialer.send(DialerToListenerMessage::ProtocolRequest { name: name })
+		})
+		.and_then(|dialer| dialer.into_future().map_err(|(e, _)| e))
+		.and_then(|(msg, dialer)| {
+			assert_eq!(msg, Some(ListenerToDialerMessage::NotAvailable));
+			dialer.into_future().map_err(|(e, _)| e)
+		})
+		.and_then(|(msg, dialer)| {
+			let list = vec![Bytes::from("/proto1"), Bytes::from("/proto2")];
+			assert_eq!(msg, Some(ListenerToDialerMessage::ProtocolsListResponse { list: list }));
+			let name = Bytes::from("/proto2");
+			dialer.send(DialerToListenerMessage::ProtocolRequest { name: name })
+		})
+		.and_then(|dialer| dialer.into_future().map_err(|(e, _)| e))
+		.map(|(msg, _)| {
+			assert_eq!(msg, Some(ListenerToDialerMessage::ProtocolAck { name: "/proto2".into() }));
+		})
+		.from_err();
+
+	let ((), listener_chosen) = core.run(client.join(server)).unwrap();
+	assert_eq!(listener_chosen, 2);
+}
+
+#[test]
+fn dialer_skips_unsupported_protocol_hint() {
+	let mut core = Core::new().unwrap();
+
+	let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+	let listener_addr = listener.local_addr().unwrap();
+
+	let server = listener.incoming()
+	                     .into_future()
+	                     .map(|s| s.0.unwrap().0)
+	                     .map_err(|(e, _)| e.into())
+	                     .and_then(move |connec| {
+		let protos = vec![
+			(Bytes::from("/proto1"), <Bytes as PartialEq>::eq, 1),
+			(Bytes::from("/proto2"), <Bytes as PartialEq>::eq, 2),
+		]
+		             .into_iter();
+		listener_select_proto_with_observer(connec, protos, WithHint).map(|r| r.0)
+	});
+
+	let client =
+		TcpStream::connect(&listener_addr, &core.handle()).from_err().and_then(move |connec| {
+			let protos = vec![
+				(Bytes::from("/proto3"), <Bytes as PartialEq>::eq, 3),
+				(Bytes::from("/proto2"), <Bytes as PartialEq>::eq, 2),
+			]
+			             .into_iter();
+			dialer_select_proto(connec, protos).map(|r| r.0)
+		});
+
+	let (dialer_chosen, listener_chosen) = core.run(client.join(server)).unwrap();
+	assert_eq!(dialer_chosen, 2);
+	assert_eq!(listener_chosen, 2);
+}
+
 #[test]
 fn select_proto_parallel() {
 	let mut core = Core::new().unwrap();
@@ -211,3 +351,77 @@ fn select_proto_serial() {
 	assert_eq!(dialer_chosen, 3);
 	assert_eq!(listener_chosen, 1);
 }
+
+#[test]
+fn simultaneous_open_decides_roles() {
+	let mut core = Core::new().unwrap();
+
+	let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+	let listener_addr = listener.local_addr().unwrap();
+
+	// Both sides act as if they dialed the connection.
+	let server = listener.incoming()
+	                     .into_future()
+	                     .map(|s| s.0.unwrap().0)
+	                     .map_err(|(e, _)| e.into())
+	                     .and_then(move |connec| {
+		let protos = vec![
+			(Bytes::from("/proto1"), <Bytes as PartialEq>::eq, 0),
+			(Bytes::from("/proto2"), <Bytes as PartialEq>::eq, 1),
+		]
+		             .into_iter();
+		simultaneous_select_proto(connec, protos).map(|(proto, _, role)| (proto, role))
+	});
+
+	let client =
+		TcpStream::connect(&listener_addr, &core.handle()).from_err().and_then(move |connec| {
+			let protos = vec![
+				(Bytes::from("/proto3"), <Bytes as PartialEq>::eq, 2),
+				(Bytes::from("/proto2"), <Bytes as PartialEq>::eq, 3),
+			]
+			             .into_iter();
+			simultaneous_select_proto(connec, protos).map(|(proto, _, role)| (proto, role))
+		});
+
+	let ((client_chosen, client_role), (server_chosen, server_role)) =
+		core.run(client.join(server)).unwrap();
+	assert_eq!(client_chosen, 3);
+	assert_eq!(server_chosen, 1);
+	assert_ne!(client_role, server_role);
+}
+
+#[test]
+fn simultaneous_open_with_regular_listener() {
+	let mut core = Core::new().unwrap();
+
+	let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+	let listener_addr = listener.local_addr().unwrap();
+
+	let server = listener.incoming()
+	                     .into_future()
+	                     .map(|s| s.0.unwrap().0)
+	                     .map_err(|(e, _)| e.into())
+	                     .and_then(move |connec| {
+		let protos = vec![
+			(Bytes::from("/proto1"), <Bytes as PartialEq>::eq, 0),
+			(Bytes::from("/proto2"), <Bytes as PartialEq>::eq, 1),
+		]
+		             .into_iter();
+		listener_select_proto(connec, protos).map(|r| r.0)
+	});
+
+	let client =
+		TcpStream::connect(&listener_addr, &core.handle()).from_err().and_then(move |connec| {
+			let protos = vec![
+				(Bytes::from("/proto3"), <Bytes as PartialEq>::eq, 2),
+				(Bytes::from("/proto2"), <Bytes as PartialEq>::eq, 3),
+			]
+			             .into_iter();
+			simultaneous_select_proto(connec, protos).map(|(proto, _, role)| (proto, role))
+		});
+
+	let ((dialer_chosen, dialer_role), listener_chosen) = core.run(client.join(server)).unwrap();
+	assert_eq!(dialer_chosen, 3);
+	assert_eq!(dialer_role, Role::Initiator);
+	assert_eq!(listener_chosen, 1);
+}
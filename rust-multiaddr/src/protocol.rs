@@ -24,6 +24,7 @@ pub enum ProtocolId {
     IP6 = 41,
     DNS4 = 54,
     DNS6 = 55,
+    DNSADDR = 56,
     SCTP = 132,
     UDT = 301,
     UTP = 302,
@@ -40,6 +41,7 @@ pub enum ProtocolId {
     Libp2pWebrtcStar = 275,
     Libp2pWebrtcDirect = 276,
     P2pCircuit = 290,
+    Memory = 777,
 }
 
 impl From<ProtocolId> for u32 {
@@ -64,6 +66,7 @@ impl ToString for ProtocolId {
             ProtocolId::IP6 => "ip6",
             ProtocolId::DNS4 => "dns4",
             ProtocolId::DNS6 => "dns6",
+            ProtocolId::DNSADDR => "dnsaddr",
             ProtocolId::SCTP => "sctp",
             ProtocolId::UDT => "udt",
             ProtocolId::UTP => "utp",
@@ -80,6 +83,7 @@ impl ToString for ProtocolId {
             ProtocolId::Libp2pWebrtcStar => "p2p-webrtc-star",
             ProtocolId::Libp2pWebrtcDirect => "p2p-webrtc-direct",
             ProtocolId::P2pCircuit => "p2p-circuit",
+            ProtocolId::Memory => "memory",
         }.to_owned()
     }
 }
@@ -96,6 +100,7 @@ impl FromStr for ProtocolId {
             "ip6" => Ok(ProtocolId::IP6),
             "dns4" => Ok(ProtocolId::DNS4),
             "dns6" => Ok(ProtocolId::DNS6),
+            "dnsaddr" => Ok(ProtocolId::DNSADDR),
             "sctp" => Ok(ProtocolId::SCTP),
             "udt" => Ok(ProtocolId::UDT),
             "utp" => Ok(ProtocolId::UTP),
@@ -112,6 +117,7 @@ impl FromStr for ProtocolId {
             "p2p-webrtc-star" => Ok(ProtocolId::Libp2pWebrtcStar),
             "p2p-webrtc-direct" => Ok(ProtocolId::Libp2pWebrtcDirect),
             "p2p-circuit" => Ok(ProtocolId::P2pCircuit),
+            "memory" => Ok(ProtocolId::Memory),
             _ => Err(Error::UnknownProtocolString),
         }
     }
@@ -138,6 +144,7 @@ impl ProtocolId {
             41 => Ok(ProtocolId::IP6),
             54 => Ok(ProtocolId::DNS4),
             55 => Ok(ProtocolId::DNS6),
+            56 => Ok(ProtocolId::DNSADDR),
             132 => Ok(ProtocolId::SCTP),
             301 => Ok(ProtocolId::UDT),
             302 => Ok(ProtocolId::UTP),
@@ -154,6 +161,7 @@ impl ProtocolId {
             275 => Ok(ProtocolId::Libp2pWebrtcStar),
             276 => Ok(ProtocolId::Libp2pWebrtcDirect),
             290 => Ok(ProtocolId::P2pCircuit),
+            777 => Ok(ProtocolId::Memory),
             _ => Err(Error::UnknownProtocol),
         }
     }
@@ -178,6 +186,7 @@ impl ProtocolId {
             ProtocolId::IP6 => ProtocolArgSize::Fixed { bytes: 16 },
             ProtocolId::DNS4 => ProtocolArgSize::Variable,
             ProtocolId::DNS6 => ProtocolArgSize::Variable,
+            ProtocolId::DNSADDR => ProtocolArgSize::Variable,
             ProtocolId::SCTP => ProtocolArgSize::Fixed { bytes: 2 },
             ProtocolId::UDT => ProtocolArgSize::Fixed { bytes: 0 },
             ProtocolId::UTP => ProtocolArgSize::Fixed { bytes: 0 },
@@ -194,6 +203,7 @@ impl ProtocolId {
             ProtocolId::Libp2pWebrtcStar => ProtocolArgSize::Fixed { bytes: 0 },
             ProtocolId::Libp2pWebrtcDirect => ProtocolArgSize::Fixed { bytes: 0 },
             ProtocolId::P2pCircuit => ProtocolArgSize::Fixed { bytes: 0 },
+            ProtocolId::Memory => ProtocolArgSize::Fixed { bytes: 8 },
         }
     }
 }
@@ -238,6 +248,9 @@ impl ProtocolId {
             ProtocolId::DNS6 => {
                 Ok(AddrComponent::DNS6(a.to_owned()))
             }
+            ProtocolId::DNSADDR => {
+                Ok(AddrComponent::DNSADDR(a.to_owned()))
+            }
             ProtocolId::TCP => {
                 let parsed: u16 = a.parse()?;
                 Ok(AddrComponent::TCP(parsed))
@@ -277,6 +290,10 @@ impl ProtocolId {
             ProtocolId::Libp2pWebrtcStar => Ok(AddrComponent::Libp2pWebrtcStar),
             ProtocolId::Libp2pWebrtcDirect => Ok(AddrComponent::Libp2pWebrtcDirect),
             ProtocolId::P2pCircuit => Ok(AddrComponent::P2pCircuit),
+            ProtocolId::Memory => {
+                let parsed: u64 = a.parse()?;
+                Ok(AddrComponent::Memory(parsed))
+            }
         }
     }
 }
@@ -290,6 +307,7 @@ pub enum AddrComponent {
     IP6(Ipv6Addr),
     DNS4(String),
     DNS6(String),
+    DNSADDR(String),
     SCTP(u16),
     UDT,
     UTP,
@@ -306,6 +324,7 @@ pub enum AddrComponent {
     Libp2pWebrtcStar,
     Libp2pWebrtcDirect,
     P2pCircuit,
+    Memory(u64),
 }
 
 impl AddrComponent {
@@ -320,6 +339,7 @@ impl AddrComponent {
             AddrComponent::IP6(_) => ProtocolId::IP6,
             AddrComponent::DNS4(_) => ProtocolId::DNS4,
             AddrComponent::DNS6(_) => ProtocolId::DNS6,
+            AddrComponent::DNSADDR(_) => ProtocolId::DNSADDR,
             AddrComponent::SCTP(_) => ProtocolId::SCTP,
             AddrComponent::UDT => ProtocolId::UDT,
             AddrComponent::UTP => ProtocolId::UTP,
@@ -336,6 +356,7 @@ impl AddrComponent {
             AddrComponent::Libp2pWebrtcStar => ProtocolId::Libp2pWebrtcStar,
             AddrComponent::Libp2pWebrtcDirect => ProtocolId::Libp2pWebrtcDirect,
             AddrComponent::P2pCircuit => ProtocolId::P2pCircuit,
+            AddrComponent::Memory(_) => ProtocolId::Memory,
         }
     }
 
@@ -383,6 +404,9 @@ impl AddrComponent {
             ProtocolId::DNS6 => {
                 AddrComponent::DNS6(String::from_utf8(data.to_owned())?)
             }
+            ProtocolId::DNSADDR => {
+                AddrComponent::DNSADDR(String::from_utf8(data.to_owned())?)
+            }
             ProtocolId::TCP => {
                 let mut rdr = Cursor::new(data);
                 let num = rdr.read_u16::<BigEndian>()?;
@@ -426,6 +450,11 @@ impl AddrComponent {
             ProtocolId::Libp2pWebrtcStar => AddrComponent::Libp2pWebrtcStar,
             ProtocolId::Libp2pWebrtcDirect => AddrComponent::Libp2pWebrtcDirect,
             ProtocolId::P2pCircuit => AddrComponent::P2pCircuit,
+            ProtocolId::Memory => {
+                let mut rdr = Cursor::new(data);
+                let num = rdr.read_u64::<BigEndian>()?;
+                AddrComponent::Memory(num)
+            }
         };
 
         Ok((addr_component, rest))
@@ -448,7 +477,11 @@ impl AddrComponent {
             AddrComponent::SCTP(port) => {
                 out.write_u16::<BigEndian>(port)?;
             }
-            AddrComponent::DNS4(s) | AddrComponent::DNS6(s) | AddrComponent::UNIX(s) => {
+            AddrComponent::Memory(id) => {
+                out.write_u64::<BigEndian>(id)?;
+            }
+            AddrComponent::DNS4(s) | AddrComponent::DNS6(s) | AddrComponent::DNSADDR(s) |
+            AddrComponent::UNIX(s) => {
                 let bytes = s.as_bytes();
                 out.write_varint(bytes.len())?;
                 out.write_all(&bytes)?;
@@ -487,6 +520,7 @@ impl ToString for AddrComponent {
             AddrComponent::IP6(ref addr) => format!("/ip6/{}", addr),
             AddrComponent::DNS4(ref s) => format!("/dns4/{}", s.clone()),
             AddrComponent::DNS6(ref s) => format!("/dns6/{}", s.clone()),
+            AddrComponent::DNSADDR(ref s) => format!("/dnsaddr/{}", s.clone()),
             AddrComponent::SCTP(port) => format!("/sctp/{}", port),
             AddrComponent::UDT => format!("/udt"),
             AddrComponent::UTP => format!("/utp"),
@@ -511,6 +545,7 @@ impl ToString for AddrComponent {
             AddrComponent::Libp2pWebrtcStar => format!("/p2p-webrtc-star"),
             AddrComponent::Libp2pWebrtcDirect => format!("/p2p-webrtc-direct"),
             AddrComponent::P2pCircuit => format!("/p2p-circuit"),
+            AddrComponent::Memory(id) => format!("/memory/{}", id),
         }
     }
 }
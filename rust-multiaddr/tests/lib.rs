@@ -62,6 +62,10 @@ fn construct_success() {
     ma_valid("/udp/1234/sctp/1234", "1104D2840104D2", vec![UDP, SCTP]);
     ma_valid("/udp/1234/udt", "1104D2AD02", vec![UDP, UDT]);
     ma_valid("/udp/1234/utp", "1104D2AE02", vec![UDP, UTP]);
+    ma_valid("/memory/1234", "890600000000000004D2", vec![Memory]);
+    ma_valid("/dnsaddr/bootstrap.libp2p.io",
+             "3813626F6F7473747261702E6C69627032702E696F",
+             vec![DNSADDR]);
     ma_valid("/tcp/1234/http", "0604D2E003", vec![TCP, HTTP]);
     ma_valid("/tcp/1234/https", "0604D2BB03", vec![TCP, HTTPS]);
     ma_valid("/ipfs/QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC/tcp/1234",
@@ -132,7 +136,9 @@ fn construct_fail() {
                      "/ip4/127.0.0.1/tcp",
                      "/ip4/127.0.0.1/ipfs",
                      "/ip4/127.0.0.1/ipfs/tcp",
-                     "/p2p-circuit/50"];
+                     "/p2p-circuit/50",
+                     "/memory",
+                     "/memory/foo"];
 
     for address in &addresses {
         assert!(address.parse::<Multiaddr>().is_err(), address.to_string());